@@ -0,0 +1,365 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cleanup::storage_disk_status,
+    clock::{rfc3339, unix_ms},
+    config,
+    error::AppError,
+    storage::Storage,
+};
+
+const DEFAULT_MIN_FREE_BYTES: u64 = 5 * 1024 * 1024 * 1024; // 5 GiB
+const DEFAULT_FFMPEG_FAILURES: u32 = 3;
+const DEFAULT_YTDLP_ERROR_RATE: f32 = 0.5;
+const DEFAULT_YTDLP_WINDOW: usize = 20;
+/// The yt-dlp error rate is not judged over fewer downloads than this.
+const MIN_YTDLP_SAMPLES: usize = 5;
+/// Raised and resolved events kept for `GET /admin/alerts`.
+const HISTORY_LEN: usize = 50;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// When to warn operators, and where besides the log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertConfig {
+    /// Receives every raised and resolved [`AlertEvent`] as a JSON `POST`.
+    pub webhook_url: Option<String>,
+    pub min_free_bytes: u64,
+    pub min_free_ratio: f32,
+    /// Consecutive failed encodes that raise `ffmpeg_failing`; 0 disables it.
+    pub ffmpeg_failures: u32,
+    /// Share of failed yt-dlp downloads that raises `ytdlp_failing`; 0
+    /// disables it.
+    pub ytdlp_error_rate: f32,
+    /// Number of recent yt-dlp downloads the error rate is taken over.
+    pub ytdlp_window: usize,
+}
+
+impl AlertConfig {
+    pub fn from_env() -> Self {
+        Self {
+            webhook_url: config::var("VIDEO_ALERT_WEBHOOK_URL")
+                .filter(|url| !url.trim().is_empty()),
+            min_free_bytes: config::parse_var("VIDEO_ALERT_MIN_FREE_BYTES")
+                .unwrap_or(DEFAULT_MIN_FREE_BYTES),
+            min_free_ratio: config::parse_var::<f32>("VIDEO_ALERT_MIN_FREE_RATIO")
+                .map(|ratio| ratio.clamp(0.0, 0.9))
+                .unwrap_or(0.0),
+            ffmpeg_failures: config::parse_var("VIDEO_ALERT_FFMPEG_FAILURES")
+                .unwrap_or(DEFAULT_FFMPEG_FAILURES),
+            ytdlp_error_rate: config::parse_var::<f32>("VIDEO_ALERT_YTDLP_ERROR_RATE")
+                .map(|rate| rate.clamp(0.0, 1.0))
+                .unwrap_or(DEFAULT_YTDLP_ERROR_RATE),
+            ytdlp_window: config::parse_var::<usize>("VIDEO_ALERT_YTDLP_WINDOW")
+                .filter(|&window| window > 0)
+                .unwrap_or(DEFAULT_YTDLP_WINDOW)
+                .max(MIN_YTDLP_SAMPLES),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    /// Free space on the storage volume is below the alert threshold.
+    LowDiskSpace,
+    /// Encodes keep failing, e.g. after a broken ffmpeg upgrade.
+    FfmpegFailing,
+    /// A high share of recent yt-dlp downloads failed, usually because the
+    /// installed yt-dlp is outdated.
+    #[serde(rename = "ytdlp_failing")]
+    YtDlpFailing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertStatus {
+    Raised,
+    Resolved,
+}
+
+/// A condition that is currently raised.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Alert {
+    pub kind: AlertKind,
+    pub message: String,
+    /// When the alert was raised, RFC 3339.
+    pub since: String,
+    pub since_unix_ms: u128,
+}
+
+/// An alert being raised or resolved, as posted to `VIDEO_ALERT_WEBHOOK_URL`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertEvent {
+    pub kind: AlertKind,
+    pub status: AlertStatus,
+    pub message: String,
+    pub at: String,
+    pub at_unix_ms: u128,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertReport {
+    pub active: Vec<Alert>,
+    /// The latest events, newest first.
+    pub recent: Vec<AlertEvent>,
+}
+
+/// Watches disk space, encode failures and the yt-dlp error rate, and warns
+/// through the log and the alert webhook when one of them crosses its
+/// threshold, and again once it recovers.
+#[derive(Clone)]
+pub struct AlertCenter {
+    inner: Arc<AlertInner>,
+}
+
+struct AlertInner {
+    client: Client,
+    /// Fixed thresholds; otherwise they are re-read from config on each check.
+    config: Option<AlertConfig>,
+    tracker: Mutex<Tracker>,
+}
+
+#[derive(Default)]
+struct Tracker {
+    active: BTreeMap<AlertKind, Alert>,
+    history: VecDeque<AlertEvent>,
+    ffmpeg_failures: u32,
+    /// Outcomes of the latest yt-dlp downloads, `true` for a failure.
+    ytdlp_outcomes: VecDeque<bool>,
+}
+
+impl AlertCenter {
+    pub fn new(client: Client) -> Self {
+        Self::build(client, None)
+    }
+
+    /// Uses `config` instead of reading thresholds from the environment.
+    pub fn with_config(client: Client, config: AlertConfig) -> Self {
+        Self::build(client, Some(config))
+    }
+
+    fn build(client: Client, config: Option<AlertConfig>) -> Self {
+        Self {
+            inner: Arc::new(AlertInner {
+                client,
+                config,
+                tracker: Mutex::default(),
+            }),
+        }
+    }
+
+    fn config(&self) -> AlertConfig {
+        self.inner
+            .config
+            .clone()
+            .unwrap_or_else(AlertConfig::from_env)
+    }
+
+    /// Records how an encode ended. Only ffmpeg errors and missing tools
+    /// count; a cancelled job or a full disk does not.
+    pub fn record_ffmpeg(&self, result: Result<(), &AppError>) {
+        let config = self.config();
+        let event = {
+            let mut tracker = self.tracker();
+            match result {
+                Ok(()) => {
+                    tracker.ffmpeg_failures = 0;
+                    tracker.resolve(AlertKind::FfmpegFailing, "encodes are succeeding again")
+                }
+                Err(err)
+                    if matches!(err.root(), AppError::Transcode(_) | AppError::Dependency(_)) =>
+                {
+                    tracker.ffmpeg_failures += 1;
+                    let failures = tracker.ffmpeg_failures;
+                    if config.ffmpeg_failures > 0 && failures >= config.ffmpeg_failures {
+                        tracker.raise(
+                            AlertKind::FfmpegFailing,
+                            format!("{failures} encodes in a row failed; last error: {err}"),
+                        )
+                    } else {
+                        None
+                    }
+                }
+                Err(_) => None,
+            }
+        };
+        self.emit(event, &config);
+    }
+
+    /// Records how a yt-dlp download ended. Cancelled downloads are not
+    /// counted.
+    pub fn record_ytdlp(&self, result: Result<(), &AppError>) {
+        if result.is_err_and(|err| matches!(err.root(), AppError::Cancelled(_))) {
+            return;
+        }
+        let config = self.config();
+        let event = {
+            let mut tracker = self.tracker();
+            tracker.ytdlp_outcomes.push_back(result.is_err());
+            while tracker.ytdlp_outcomes.len() > config.ytdlp_window {
+                tracker.ytdlp_outcomes.pop_front();
+            }
+            let samples = tracker.ytdlp_outcomes.len();
+            let failed = tracker
+                .ytdlp_outcomes
+                .iter()
+                .filter(|&&failed| failed)
+                .count();
+            let rate = failed as f32 / samples as f32;
+            if config.ytdlp_error_rate > 0.0
+                && samples >= MIN_YTDLP_SAMPLES
+                && rate >= config.ytdlp_error_rate
+            {
+                let message = match result {
+                    Err(err) => format!(
+                        "{failed} of the last {samples} yt-dlp downloads failed; last error: {err}"
+                    ),
+                    Ok(()) => format!("{failed} of the last {samples} yt-dlp downloads failed"),
+                };
+                tracker.raise(AlertKind::YtDlpFailing, message)
+            } else {
+                tracker.resolve(
+                    AlertKind::YtDlpFailing,
+                    format!("{failed} of the last {samples} yt-dlp downloads failed"),
+                )
+            }
+        };
+        self.emit(event, &config);
+    }
+
+    /// Samples free space on the storage volume.
+    pub async fn check_disk(&self, storage: &Storage) {
+        let config = self.config();
+        let disk = match storage_disk_status(storage).await {
+            Ok(disk) => disk,
+            Err(err) => {
+                tracing::debug!(error = %err, "disk status unavailable for alerts");
+                return;
+            }
+        };
+        let low = if disk.free_bytes < config.min_free_bytes {
+            Some(format!(
+                "{} bytes free on the storage volume, below {}",
+                disk.free_bytes, config.min_free_bytes
+            ))
+        } else if disk.free_ratio() < config.min_free_ratio {
+            Some(format!(
+                "{:.1}% free on the storage volume, below {:.1}%",
+                disk.free_ratio() * 100.0,
+                config.min_free_ratio * 100.0
+            ))
+        } else {
+            None
+        };
+        let event = {
+            let mut tracker = self.tracker();
+            match low {
+                Some(message) => tracker.raise(AlertKind::LowDiskSpace, message),
+                None => tracker.resolve(
+                    AlertKind::LowDiskSpace,
+                    format!("{} bytes free on the storage volume", disk.free_bytes),
+                ),
+            }
+        };
+        self.emit(event, &config);
+    }
+
+    pub fn report(&self) -> AlertReport {
+        let tracker = self.tracker();
+        AlertReport {
+            active: tracker.active.values().cloned().collect(),
+            recent: tracker.history.iter().rev().cloned().collect(),
+        }
+    }
+
+    fn tracker(&self) -> std::sync::MutexGuard<'_, Tracker> {
+        self.inner.tracker.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    /// Logs `event` and posts it to the webhook in the background.
+    fn emit(&self, event: Option<AlertEvent>, config: &AlertConfig) {
+        let Some(event) = event else {
+            return;
+        };
+        match event.status {
+            AlertStatus::Raised => {
+                tracing::warn!(kind = ?event.kind, message = %event.message, "alert raised");
+            }
+            AlertStatus::Resolved => {
+                tracing::info!(kind = ?event.kind, message = %event.message, "alert resolved");
+            }
+        }
+        let Some(url) = config.webhook_url.clone() else {
+            return;
+        };
+        let client = self.inner.client.clone();
+        tokio::spawn(async move {
+            let delivered = client
+                .post(&url)
+                .timeout(WEBHOOK_TIMEOUT)
+                .json(&event)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(err) = delivered {
+                tracing::warn!(url, kind = ?event.kind, error = %err, "alert webhook failed");
+            }
+        });
+    }
+}
+
+impl Tracker {
+    /// Raises `kind`, or refreshes its message while it is already raised.
+    /// Returns the event when the alert is new.
+    fn raise(&mut self, kind: AlertKind, message: String) -> Option<AlertEvent> {
+        if let Some(alert) = self.active.get_mut(&kind) {
+            alert.message = message;
+            return None;
+        }
+        let now = unix_ms(SystemTime::now());
+        self.active.insert(
+            kind,
+            Alert {
+                kind,
+                message: message.clone(),
+                since: rfc3339(now),
+                since_unix_ms: now,
+            },
+        );
+        Some(self.push(kind, AlertStatus::Raised, message, now))
+    }
+
+    fn resolve(&mut self, kind: AlertKind, message: impl Into<String>) -> Option<AlertEvent> {
+        self.active.remove(&kind)?;
+        let now = unix_ms(SystemTime::now());
+        Some(self.push(kind, AlertStatus::Resolved, message.into(), now))
+    }
+
+    fn push(
+        &mut self,
+        kind: AlertKind,
+        status: AlertStatus,
+        message: String,
+        now: u128,
+    ) -> AlertEvent {
+        let event = AlertEvent {
+            kind,
+            status,
+            message,
+            at: rfc3339(now),
+            at_unix_ms: now,
+        };
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(event.clone());
+        event
+    }
+}
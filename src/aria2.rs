@@ -0,0 +1,321 @@
+use std::time::Duration;
+
+use reqwest::Client;
+use serde::{Deserialize, Deserializer, Serialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+use crate::error::AppError;
+
+mod session;
+pub(crate) use session::download;
+
+const RPC_TIMEOUT: Duration = Duration::from_secs(5);
+/// Extensions of the files in a torrent taken to be videos.
+const VIDEO_EXTENSIONS: &[&str] = &[
+    "mp4", "m4v", "mkv", "webm", "mov", "avi", "wmv", "flv", "ts", "m2ts", "mpg", "mpeg", "ogv",
+    "3gp",
+];
+/// Fields of `aria2.tellStatus` that [`Aria2Status`] reads.
+const STATUS_KEYS: &[&str] = &[
+    "status",
+    "totalLength",
+    "completedLength",
+    "downloadSpeed",
+    "followedBy",
+    "errorCode",
+    "errorMessage",
+];
+
+/// JSON-RPC connection to an aria2c started with `--enable-rpc` on a local
+/// port, authenticated with its `--rpc-secret`.
+#[derive(Clone)]
+pub struct Aria2Rpc {
+    client: Client,
+    url: String,
+    token: String,
+}
+
+impl Aria2Rpc {
+    pub fn new(port: u16, secret: &str) -> Result<Self, AppError> {
+        // A configured proxy must not see loopback calls or the secret.
+        let client = Client::builder().no_proxy().timeout(RPC_TIMEOUT).build()?;
+        Ok(Self {
+            client,
+            url: format!("http://127.0.0.1:{port}/jsonrpc"),
+            token: format!("token:{secret}"),
+        })
+    }
+
+    pub async fn tell_status(&self, gid: &str) -> Result<Aria2Status, AppError> {
+        self.call("aria2.tellStatus", json!([gid, STATUS_KEYS]))
+            .await
+    }
+
+    /// The files of a download; for a torrent, known once its metadata is.
+    pub async fn get_files(&self, gid: &str) -> Result<Vec<Aria2File>, AppError> {
+        self.call("aria2.getFiles", json!([gid])).await
+    }
+
+    /// Limits the torrent download `gid` to the file at `index`.
+    pub async fn select_file(&self, gid: &str, index: u32) -> Result<(), AppError> {
+        self.call::<Value>(
+            "aria2.changeOption",
+            json!([gid, { "select-file": index.to_string() }]),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Resumes `gid`, such as a torrent paused by `--pause-metadata`.
+    pub async fn unpause(&self, gid: &str) -> Result<(), AppError> {
+        self.call::<Value>("aria2.unpause", json!([gid])).await?;
+        Ok(())
+    }
+
+    /// Stops the download of `gid`, disconnecting from its peers.
+    pub async fn remove(&self, gid: &str) -> Result<(), AppError> {
+        self.call::<Value>("aria2.remove", json!([gid])).await?;
+        Ok(())
+    }
+
+    /// Makes aria2c exit, which it does not do by itself in RPC mode.
+    pub async fn shutdown(&self) -> Result<(), AppError> {
+        self.call::<Value>("aria2.forceShutdown", json!([])).await?;
+        Ok(())
+    }
+
+    async fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T, AppError> {
+        let mut params = params.as_array().cloned().unwrap_or_default();
+        params.insert(0, Value::String(self.token.clone()));
+        let response: RpcResponse<T> = self
+            .client
+            .post(&self.url)
+            .json(&json!({
+                "jsonrpc": "2.0",
+                "id": "vrs",
+                "method": method,
+                "params": params,
+            }))
+            .send()
+            .await?
+            .json()
+            .await?;
+        match response {
+            RpcResponse {
+                result: Some(result),
+                ..
+            } => Ok(result),
+            RpcResponse { error, .. } => Err(AppError::dependency(format!(
+                "aria2 {method} failed: {}",
+                error.map_or_else(|| "no result".to_string(), |error| error.message)
+            ))),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    error: Option<RpcError>,
+}
+
+#[derive(Deserialize)]
+struct RpcError {
+    message: String,
+}
+
+/// What `aria2.tellStatus` reports about one download.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Aria2Status {
+    /// `active`, `waiting`, `paused`, `error`, `complete` or `removed`.
+    pub status: String,
+    #[serde(default, deserialize_with = "number")]
+    pub total_length: u64,
+    #[serde(default, deserialize_with = "number")]
+    pub completed_length: u64,
+    /// Bytes per second.
+    #[serde(default, deserialize_with = "number")]
+    pub download_speed: u64,
+    /// Downloads started by this one, such as the torrent a magnet's
+    /// metadata describes.
+    #[serde(default)]
+    pub followed_by: Vec<String>,
+    #[serde(default)]
+    pub error_code: Option<String>,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+impl Aria2Status {
+    /// Share of the bytes received, once the length is known.
+    pub fn progress(&self) -> Option<f32> {
+        (self.total_length > 0)
+            .then(|| (self.completed_length as f64 / self.total_length as f64).min(1.0) as f32)
+    }
+
+    /// Time left at the current speed.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.total_length == 0 || self.download_speed == 0 {
+            return None;
+        }
+        let remaining = self.total_length.saturating_sub(self.completed_length);
+        Some(Duration::from_secs_f64(
+            remaining as f64 / self.download_speed as f64,
+        ))
+    }
+}
+
+/// One file of a download, as `aria2.getFiles` reports it.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Aria2File {
+    /// 1-based position in the torrent, as `--select-file` takes it.
+    #[serde(deserialize_with = "number")]
+    pub index: u32,
+    /// Where the file is written: `--dir` joined with its path in the torrent.
+    pub path: String,
+    #[serde(default, deserialize_with = "number")]
+    pub length: u64,
+}
+
+/// Which file of a multi-file torrent becomes the video. By default the
+/// largest file with a video extension, or the only file of a single-file
+/// torrent.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileSelection {
+    /// 1-based index of the file in the torrent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_index: Option<u32>,
+    /// Pattern the file's path in the torrent must match, e.g.
+    /// `*/Season 1/*E01*.mkv`. `*` matches any run of characters, `?` a
+    /// single one, regardless of case.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_glob: Option<String>,
+}
+
+impl FileSelection {
+    pub fn is_empty(&self) -> bool {
+        self.file_index.is_none() && self.file_glob.is_none()
+    }
+
+    pub fn validate(&self) -> Result<(), AppError> {
+        let invalid = |message: &str| {
+            AppError::validation(message.to_string()).with_code("file_selection_invalid")
+        };
+        if self.file_index.is_some() && self.file_glob.is_some() {
+            return Err(invalid("give either file_index or file_glob, not both"));
+        }
+        if self.file_index == Some(0) {
+            return Err(invalid("file_index counts from 1"));
+        }
+        if self
+            .file_glob
+            .as_deref()
+            .is_some_and(|glob| glob.trim().is_empty())
+        {
+            return Err(invalid("file_glob must not be empty"));
+        }
+        Ok(())
+    }
+
+    /// Picks the file to download from `files`, whose paths lie under `dir`.
+    pub fn choose<'a>(&self, files: &'a [Aria2File], dir: &str) -> Result<&'a Aria2File, AppError> {
+        let not_found =
+            |message: String| AppError::validation(message).with_code("torrent_file_not_found");
+        if let Some(index) = self.file_index {
+            return files
+                .iter()
+                .find(|file| file.index == index)
+                .ok_or_else(|| {
+                    not_found(format!(
+                        "torrent has {} files, no file_index {index}",
+                        files.len()
+                    ))
+                    .with_param("file_index", index)
+                });
+        }
+        if let Some(glob) = &self.file_glob {
+            return files
+                .iter()
+                .filter(|file| glob_match(glob, relative_path(&file.path, dir)))
+                .max_by_key(|file| file.length)
+                .ok_or_else(|| {
+                    not_found(format!("no file in the torrent matches {glob:?}"))
+                        .with_param("file_glob", glob.clone())
+                });
+        }
+        if let [file] = files {
+            return Ok(file);
+        }
+        files
+            .iter()
+            .filter(|file| is_video_file(&file.path))
+            .max_by_key(|file| file.length)
+            .ok_or_else(|| not_found("torrent holds no video file".to_string()))
+    }
+}
+
+/// `path` without its `dir` prefix, as the file is named inside the torrent.
+pub fn relative_path<'a>(path: &'a str, dir: &str) -> &'a str {
+    path.strip_prefix(dir)
+        .map(|rest| rest.trim_start_matches('/'))
+        .unwrap_or(path)
+}
+
+/// Whether `source` is fetched with BitTorrent.
+pub fn is_torrent(source: &str) -> bool {
+    source.starts_with("magnet:") || source.to_ascii_lowercase().ends_with(".torrent")
+}
+
+fn is_video_file(path: &str) -> bool {
+    path.rsplit_once('.').is_some_and(|(_, extension)| {
+        VIDEO_EXTENSIONS
+            .iter()
+            .any(|video| extension.eq_ignore_ascii_case(video))
+    })
+}
+
+/// Matches `text` against `pattern`, where `*` stands for any run of
+/// characters and `?` for any one, ignoring case.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was, and the text position it currently covers up to.
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// aria2 sends every number as a string.
+fn number<'de, D: Deserializer<'de>, T: std::str::FromStr<Err: std::fmt::Display>>(
+    deserializer: D,
+) -> Result<T, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    value.parse().map_err(serde::de::Error::custom)
+}
+
+/// A GID for `--gid`: 16 hex digits.
+pub fn new_gid() -> String {
+    Uuid::new_v4().simple().to_string()[..16].to_string()
+}
@@ -0,0 +1,324 @@
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use reqwest::Url;
+use uuid::Uuid;
+
+use super::{Aria2Rpc, FileSelection, is_torrent, new_gid, relative_path};
+use crate::{
+    cookies,
+    error::AppError,
+    jobs::DynJobStore,
+    process::{DynProcessRunner, RunningProcess, map_spawn_error},
+};
+
+const ARIA2_BIN: &str = "aria2c";
+/// How often a running aria2c is asked for its progress.
+const ARIA2_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const ARIA2_SHUTDOWN_WAIT: Duration = Duration::from_secs(5);
+
+/// Downloads `source` with aria2c, reporting its progress and ETA from
+/// aria2's JSON-RPC interface while it runs. Of a torrent, only the file
+/// `files` selects is downloaded.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn download(
+    runner: &DynProcessRunner,
+    jobs: &DynJobStore,
+    id: Uuid,
+    source: &str,
+    destination: &Path,
+    files: &FileSelection,
+    max_rate: Option<u64>,
+    proxy: Option<&Url>,
+) -> Result<(), AppError> {
+    let parent = destination
+        .parent()
+        .ok_or_else(|| AppError::transcode("temporary destination missing parent directory"))?;
+
+    let file_name = destination
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| AppError::transcode("temporary destination missing file name"))?;
+    let port = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?
+        .local_addr()?
+        .port();
+    let secret = Uuid::new_v4().simple().to_string();
+    let mut conf = format!("rpc-secret={secret}\n");
+    if let Some(proxy) = proxy {
+        conf.push_str(&format!("all-proxy={proxy}\n"));
+    }
+    // Written before the snapshot, so it is not taken for the download.
+    let conf = Aria2Conf::write(destination, &conf).await?;
+    let before = dir_snapshot(parent).await?;
+
+    let is_magnet = source.starts_with("magnet:");
+    let is_torrent = is_torrent(source);
+
+    let mut args: Vec<OsString> = vec![
+        "--allow-overwrite=true".into(),
+        "--auto-file-renaming=false".into(),
+        "--summary-interval=0".into(),
+        "--seed-time=0".into(),
+        "--bt-seed-until=0".into(),
+        "--bt-stop-timeout=0".into(),
+        "--bt-remove-unselected-file=true".into(),
+        "--bt-save-metadata=false".into(),
+        "--dir".into(),
+        parent.as_os_str().to_os_string(),
+    ];
+
+    if is_torrent {
+        // The torrent waits until its file is selected, and a fetched
+        // `.torrent` is not written next to the download.
+        args.extend([
+            "--pause-metadata=true".into(),
+            "--follow-torrent=mem".into(),
+        ]);
+    } else {
+        args.extend(["--out".into(), file_name.into()]);
+    }
+    if let Some(rate) = max_rate {
+        args.push(format!("--max-overall-download-limit={rate}").into());
+    }
+    let mut conf_path = OsString::from("--conf-path=");
+    conf_path.push(&conf.path);
+    let gid = new_gid();
+    args.extend([
+        conf_path,
+        "--enable-rpc=true".into(),
+        "--rpc-listen-all=false".into(),
+        format!("--rpc-listen-port={port}").into(),
+        format!("--gid={gid}").into(),
+    ]);
+
+    args.push(source.into());
+
+    let process = runner
+        .spawn(ARIA2_BIN, &args)
+        .await
+        .map_err(|err| map_spawn_error(err, ARIA2_BIN))?;
+    let mut session = Aria2Session {
+        process: Some(process),
+        rpc: Aria2Rpc::new(port, &secret)?,
+        gid,
+    };
+    let selection = is_torrent.then(|| (files, parent.to_string_lossy().into_owned()));
+    // A magnet first fetches the torrent's metadata, whose progress says
+    // nothing about the video.
+    let chosen = session.run(jobs, id, is_magnet, selection).await?;
+
+    if let Some(chosen) = chosen {
+        tokio::fs::rename(&chosen, destination).await?;
+        // Unselected files are removed by aria2, but not the torrent's folder.
+        if let Some(root) = chosen
+            .strip_prefix(parent)
+            .ok()
+            .and_then(|relative| relative.components().next())
+            .map(|root| parent.join(root))
+            .filter(|root| root.is_dir())
+        {
+            tokio::fs::remove_dir_all(&root).await.ok();
+        }
+        tracing::debug!(source, temp = %chosen.display(), dest = %destination.display(), "aria2 torrent file moved into place");
+        return Ok(());
+    }
+
+    if destination.exists() {
+        tracing::debug!(source, dest = %destination.display(), "aria2 produced target file directly");
+        return Ok(());
+    }
+
+    let after = dir_snapshot(parent).await?;
+    let mut new_entries: Vec<PathBuf> = after.difference(&before).cloned().collect();
+
+    if new_entries.len() == 1 {
+        let candidate = new_entries.remove(0);
+        if tokio::fs::metadata(&candidate)
+            .await
+            .map_err(AppError::from)?
+            .is_file()
+        {
+            tokio::fs::rename(&candidate, destination).await?;
+            tracing::debug!(source, temp = %candidate.display(), dest = %destination.display(), "aria2 download moved into place");
+            return Ok(());
+        }
+    }
+
+    Err(AppError::transcode(
+        "aria2c produced unexpected output (expected a single file)",
+    ))
+}
+
+/// Options handed to aria2c in a file only the server can read, next to the
+/// download, so the RPC secret and a proxy login stay out of the process
+/// list. The file is
+/// removed when this is dropped, including when the job is cancelled.
+struct Aria2Conf {
+    path: PathBuf,
+}
+
+impl Aria2Conf {
+    async fn write(destination: &Path, options: &str) -> Result<Self, AppError> {
+        let conf = Self {
+            path: destination.with_extension("aria2.conf"),
+        };
+        cookies::write_private(&conf.path, options.as_bytes()).await?;
+        Ok(conf)
+    }
+}
+
+impl Drop for Aria2Conf {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
+/// A running aria2c. Dropping it before the download finished, e.g. when
+/// the job is cancelled, removes the download through RPC and shuts aria2c
+/// down in the background.
+struct Aria2Session {
+    process: Option<Box<dyn RunningProcess>>,
+    rpc: Aria2Rpc,
+    gid: String,
+}
+
+impl Aria2Session {
+    /// Waits for the download to complete, or aria2c to exit by itself.
+    /// With a `selection` of files and the directory the torrent is saved
+    /// to, the torrent aria2 follows on to is limited to the selected file,
+    /// whose path is returned.
+    async fn run(
+        &mut self,
+        jobs: &DynJobStore,
+        id: Uuid,
+        mut metadata: bool,
+        selection: Option<(&FileSelection, String)>,
+    ) -> Result<Option<PathBuf>, AppError> {
+        let mut ticker = tokio::time::interval(ARIA2_POLL_INTERVAL);
+        let mut chosen = None;
+        loop {
+            let Some(process) = self.process.as_mut() else {
+                return Ok(chosen);
+            };
+            let exited = tokio::select! {
+                status = process.wait() => Some(status),
+                _ = ticker.tick() => None,
+            };
+            if let Some(status) = exited {
+                let status = status?;
+                self.process = None;
+                if !status.success() {
+                    return Err(AppError::dependency(format!(
+                        "aria2c exited with status {status}"
+                    )));
+                }
+                return Ok(chosen);
+            }
+            // Until aria2c listens, calls fail; it reports its own startup errors by exiting.
+            let Ok(status) = self.rpc.tell_status(&self.gid).await else {
+                continue;
+            };
+            match status.status.as_str() {
+                "complete" => match status.followed_by.first() {
+                    Some(next) => {
+                        self.gid = next.clone();
+                        metadata = false;
+                        if let Some((files, dir)) = &selection {
+                            chosen = Some(self.select(jobs, id, files, dir).await?);
+                        }
+                    }
+                    None => break,
+                },
+                "error" | "removed" => {
+                    return Err(AppError::dependency(format!(
+                        "aria2c download failed: {}",
+                        status
+                            .error_message
+                            .or(status.error_code)
+                            .unwrap_or(status.status)
+                    )));
+                }
+                _ if metadata => {}
+                _ => {
+                    if let Some(progress) = status.progress() {
+                        jobs.update_progress(id, progress).await?;
+                    }
+                    jobs.update_stage_eta(id, status.eta().map(|eta| eta.as_secs_f64()))
+                        .await?;
+                }
+            }
+        }
+
+        let mut process = self.process.take().expect("aria2c still running");
+        if let Err(err) = self.rpc.shutdown().await {
+            tracing::debug!(error = %err, "aria2 shutdown call failed");
+        }
+        stop_aria2(process.as_mut()).await;
+        Ok(chosen)
+    }
+
+    /// Limits the paused torrent to the file `files` picks, names it in the
+    /// job's status and starts the download.
+    async fn select(
+        &mut self,
+        jobs: &DynJobStore,
+        id: Uuid,
+        files: &FileSelection,
+        dir: &str,
+    ) -> Result<PathBuf, AppError> {
+        let listed = self.rpc.get_files(&self.gid).await?;
+        let file = files.choose(&listed, dir)?;
+        let name = relative_path(&file.path, dir);
+        tracing::debug!(%id, file = name, index = file.index, files = listed.len(), "selected torrent file");
+        jobs.set_source_file(id, name).await?;
+        self.rpc.select_file(&self.gid, file.index).await?;
+        self.rpc.unpause(&self.gid).await?;
+        Ok(PathBuf::from(&file.path))
+    }
+}
+
+impl Drop for Aria2Session {
+    fn drop(&mut self) {
+        let Some(mut process) = self.process.take() else {
+            return;
+        };
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let rpc = self.rpc.clone();
+        let gid = self.gid.clone();
+        runtime.spawn(async move {
+            if let Err(err) = rpc.remove(&gid).await {
+                tracing::debug!(gid, error = %err, "aria2 remove call failed");
+            }
+            if let Err(err) = rpc.shutdown().await {
+                tracing::debug!(error = %err, "aria2 shutdown call failed");
+            }
+            stop_aria2(process.as_mut()).await;
+        });
+    }
+}
+
+/// Gives aria2c a moment to exit after a shutdown call, then kills it.
+async fn stop_aria2(process: &mut dyn RunningProcess) {
+    if tokio::time::timeout(ARIA2_SHUTDOWN_WAIT, process.wait())
+        .await
+        .is_err()
+    {
+        process.kill().await.ok();
+    }
+}
+
+async fn dir_snapshot(dir: &Path) -> Result<HashSet<PathBuf>, AppError> {
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    let mut set = HashSet::new();
+    while let Some(entry) = entries.next_entry().await? {
+        set.insert(entry.path());
+    }
+    Ok(set)
+}
@@ -0,0 +1,301 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::http::{HeaderMap, Method, header};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, jwk::JwkSet};
+use reqwest::Client;
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+use crate::{config, error::AppError};
+
+const DEFAULT_JWKS_TTL_SECS: u64 = 300;
+/// A token with an unknown `kid` refetches the key set at most this often,
+/// so rotated keys are picked up without letting bad tokens hammer the IdP.
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(30);
+
+/// Permission a token must carry to use a group of routes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// Ingest, edit videos and collections, cancel and retry jobs, and read
+    /// `/usage`.
+    Upload,
+    /// Delete videos and collections.
+    Delete,
+    /// `/admin`, `/capabilities` and `/metrics`. Grants the other scopes too.
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Upload => "upload",
+            Self::Delete => "delete",
+            Self::Admin => "admin",
+        }
+    }
+
+    /// The scope guarding a route. Reads outside the admin and upload routes
+    /// need none, so playback stays governed by signed URLs, passwords and
+    /// shares.
+    pub fn for_route(method: &Method, path: &str) -> Option<Self> {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        if matches!(segments[0], "admin" | "capabilities" | "metrics") {
+            return Some(Self::Admin);
+        }
+        // Failure reports and the live ffmpeg log name paths and show
+        // frames of private sources. Listed share ids open the video to
+        // anyone holding them.
+        if segments == ["usage"]
+            || segments[0] == "upload"
+            || matches!(segments.as_slice(), ["jobs", _, "diagnostics" | "ws", ..])
+            || matches!(segments.as_slice(), ["videos", _, "share", ..])
+        {
+            return Some(Self::Upload);
+        }
+        // Exchanging a video password for an access token is part of playback.
+        if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+            || matches!(segments.as_slice(), ["videos", _, "access"])
+        {
+            return None;
+        }
+        match segments.as_slice() {
+            ["videos" | "collections", _] if *method == Method::DELETE => Some(Self::Delete),
+            _ => Some(Self::Upload),
+        }
+    }
+}
+
+/// Where tokens come from and what they must say. Auth is off unless a
+/// secret or a JWKS URL is set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JwtConfig {
+    /// Shared secret for HS256 tokens.
+    pub secret: Option<String>,
+    /// Key set for RS256 tokens, matched by `kid`.
+    pub jwks_url: Option<String>,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    /// Prefix the identity provider puts in front of scope names, e.g. `vrs:`.
+    pub scope_prefix: String,
+    pub jwks_ttl: Duration,
+}
+
+impl JwtConfig {
+    pub fn from_env() -> Self {
+        let non_empty = |key: &str| config::var(key).filter(|value| !value.trim().is_empty());
+        Self {
+            secret: non_empty("VIDEO_JWT_SECRET"),
+            jwks_url: non_empty("VIDEO_JWT_JWKS_URL"),
+            issuer: non_empty("VIDEO_JWT_ISSUER"),
+            audience: non_empty("VIDEO_JWT_AUDIENCE"),
+            scope_prefix: config::var("VIDEO_JWT_SCOPE_PREFIX").unwrap_or_default(),
+            jwks_ttl: Duration::from_secs(
+                config::parse_var("VIDEO_JWT_JWKS_TTL_SECS").unwrap_or(DEFAULT_JWKS_TTL_SECS),
+            ),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.secret.is_some() || self.jwks_url.is_some()
+    }
+}
+
+/// The claims of a verified token that the server looks at.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Claims {
+    #[serde(default)]
+    pub sub: Option<String>,
+    /// Space-separated scopes, as in OAuth 2.0.
+    #[serde(default)]
+    scope: Option<String>,
+    /// Scopes as issued by Azure AD and Okta, a list or a space-separated string.
+    #[serde(default)]
+    scp: Option<ScopeClaim>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum ScopeClaim {
+    Joined(String),
+    List(Vec<String>),
+}
+
+impl Claims {
+    /// Whether the token carries `scope` or `admin`, after stripping `prefix`.
+    pub fn grants(&self, scope: Scope, prefix: &str) -> bool {
+        let listed = match &self.scp {
+            Some(ScopeClaim::List(scopes)) => scopes.iter().map(String::as_str).collect(),
+            Some(ScopeClaim::Joined(scopes)) => scopes.split_whitespace().collect(),
+            None => Vec::new(),
+        };
+        self.scope
+            .iter()
+            .flat_map(|scopes| scopes.split_whitespace())
+            .chain(listed)
+            .filter_map(|granted| granted.strip_prefix(prefix))
+            .any(|granted| granted == scope.as_str() || granted == Scope::Admin.as_str())
+    }
+}
+
+/// Verifies bearer tokens against the configured secret or key set.
+#[derive(Clone, Default)]
+pub struct JwtAuth {
+    inner: Arc<AuthInner>,
+}
+
+#[derive(Default)]
+struct AuthInner {
+    /// Fixed settings; otherwise they are re-read from config on each request.
+    config: Option<JwtConfig>,
+    jwks: Mutex<Option<CachedJwks>>,
+}
+
+struct CachedJwks {
+    url: String,
+    keys: JwkSet,
+    fetched_at: Instant,
+}
+
+impl JwtAuth {
+    /// Uses `config` instead of reading settings from the environment.
+    pub fn with_config(config: JwtConfig) -> Self {
+        Self {
+            inner: Arc::new(AuthInner {
+                config: Some(config),
+                ..AuthInner::default()
+            }),
+        }
+    }
+
+    fn config(&self) -> JwtConfig {
+        self.inner
+            .config
+            .clone()
+            .unwrap_or_else(JwtConfig::from_env)
+    }
+
+    /// Checks the `Authorization: Bearer` token for `scope`. Fails with `401`
+    /// for a missing or invalid token and `403` when the scope is missing.
+    /// Returns `None` without looking at the request while auth is off.
+    pub async fn authorize(
+        &self,
+        client: &Client,
+        headers: &HeaderMap,
+        scope: Scope,
+    ) -> Result<Option<Claims>, AppError> {
+        let config = self.config();
+        if !config.is_enabled() {
+            return Ok(None);
+        }
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .ok_or_else(|| {
+                AppError::unauthorized("a bearer token is required").with_code("token_missing")
+            })?;
+        let token_header = jsonwebtoken::decode_header(token)
+            .map_err(|err| invalid_token(format!("malformed token: {err}")))?;
+
+        let key = match token_header.alg {
+            Algorithm::HS256 => {
+                let secret = config
+                    .secret
+                    .as_deref()
+                    .ok_or_else(|| invalid_token("HS256 tokens are not accepted"))?;
+                DecodingKey::from_secret(secret.as_bytes())
+            }
+            Algorithm::RS256 => {
+                let url = config
+                    .jwks_url
+                    .as_deref()
+                    .ok_or_else(|| invalid_token("RS256 tokens are not accepted"))?;
+                self.jwks_key(client, url, config.jwks_ttl, token_header.kid.as_deref())
+                    .await?
+            }
+            other => return Err(invalid_token(format!("unsupported algorithm {other:?}"))),
+        };
+
+        let mut validation = Validation::new(token_header.alg);
+        match &config.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        if let Some(issuer) = &config.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        let claims = jsonwebtoken::decode::<Claims>(token, &key, &validation)
+            .map_err(|err| invalid_token(format!("invalid token: {err}")))?
+            .claims;
+
+        if !claims.grants(scope, &config.scope_prefix) {
+            return Err(
+                AppError::forbidden(format!("token lacks the {} scope", scope.as_str()))
+                    .with_code("scope_missing")
+                    .with_param("scope", scope.as_str()),
+            );
+        }
+        Ok(Some(claims))
+    }
+
+    /// The RS256 key named `kid`, or the only key when the token names none.
+    async fn jwks_key(
+        &self,
+        client: &Client,
+        url: &str,
+        ttl: Duration,
+        kid: Option<&str>,
+    ) -> Result<DecodingKey, AppError> {
+        let mut cached = self.inner.jwks.lock().await;
+        let fresh = |entry: &CachedJwks, max_age: Duration| {
+            entry.url == url && entry.fetched_at.elapsed() < max_age
+        };
+        if !cached.as_ref().is_some_and(|entry| fresh(entry, ttl)) {
+            *cached = Some(fetch_jwks(client, url).await?);
+        }
+        let find = |keys: &JwkSet| match kid {
+            Some(kid) => keys.find(kid).cloned(),
+            None if keys.keys.len() == 1 => keys.keys.first().cloned(),
+            None => None,
+        };
+        let mut jwk = cached.as_ref().and_then(|entry| find(&entry.keys));
+        if jwk.is_none()
+            && kid.is_some()
+            && !cached
+                .as_ref()
+                .is_some_and(|entry| fresh(entry, JWKS_MIN_REFRESH))
+        {
+            let refetched = fetch_jwks(client, url).await?;
+            jwk = find(&refetched.keys);
+            *cached = Some(refetched);
+        }
+        let jwk = jwk.ok_or_else(|| invalid_token("token signed with an unknown key"))?;
+        DecodingKey::from_jwk(&jwk)
+            .map_err(|err| invalid_token(format!("unusable signing key: {err}")))
+    }
+}
+
+async fn fetch_jwks(client: &Client, url: &str) -> Result<CachedJwks, AppError> {
+    let keys = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| AppError::dependency(format!("failed to fetch JWKS: {err}")))?
+        .json::<JwkSet>()
+        .await
+        .map_err(|err| AppError::dependency(format!("invalid JWKS: {err}")))?;
+    Ok(CachedJwks {
+        url: url.to_string(),
+        keys,
+        fetched_at: Instant::now(),
+    })
+}
+
+fn invalid_token(message: impl std::fmt::Display) -> AppError {
+    AppError::unauthorized(message).with_code("token_invalid")
+}
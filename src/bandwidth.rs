@@ -0,0 +1,314 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use axum::{body::Body, http::HeaderMap, response::Response};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    clock, config,
+    error::AppError,
+    metrics,
+    storage::{Storage, ensure_dir},
+};
+
+const DEFAULT_KEY_HEADER: &str = "x-tenant-id";
+/// Caller key of requests that do not name one.
+pub const ANONYMOUS_KEY: &str = "anonymous";
+const MAX_KEY_LEN: usize = 128;
+
+/// How a video's bytes were delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryKind {
+    /// The mezzanine file, including partial-encode previews.
+    Download,
+    Hls,
+    Dash,
+}
+
+impl DeliveryKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Download => "download",
+            Self::Hls => "hls",
+            Self::Dash => "dash",
+        }
+    }
+}
+
+/// Bytes served, split by delivery kind.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BandwidthUsage {
+    pub download_bytes: u64,
+    pub hls_bytes: u64,
+    pub dash_bytes: u64,
+}
+
+impl BandwidthUsage {
+    pub fn add(&mut self, kind: DeliveryKind, bytes: u64) {
+        let counter = match kind {
+            DeliveryKind::Download => &mut self.download_bytes,
+            DeliveryKind::Hls => &mut self.hls_bytes,
+            DeliveryKind::Dash => &mut self.dash_bytes,
+        };
+        *counter = counter.saturating_add(bytes);
+    }
+
+    pub fn total(&self) -> u64 {
+        self.download_bytes
+            .saturating_add(self.hls_bytes)
+            .saturating_add(self.dash_bytes)
+    }
+
+    fn absorb(&mut self, other: &Self) {
+        self.add(DeliveryKind::Download, other.download_bytes);
+        self.add(DeliveryKind::Hls, other.hls_bytes);
+        self.add(DeliveryKind::Dash, other.dash_bytes);
+    }
+}
+
+/// One calendar month (UTC) of delivery, as stored under
+/// `<storage>/analytics/bandwidth/<YYYY-MM>.json`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MonthlyBandwidth {
+    pub total: BandwidthUsage,
+    pub videos: BTreeMap<Uuid, BandwidthUsage>,
+    /// Keyed by the value of `VIDEO_BANDWIDTH_KEY_HEADER`.
+    pub keys: BTreeMap<String, BandwidthUsage>,
+}
+
+impl MonthlyBandwidth {
+    fn record(&mut self, video_id: Uuid, key: &str, kind: DeliveryKind, bytes: u64) {
+        self.total.add(kind, bytes);
+        self.videos.entry(video_id).or_default().add(kind, bytes);
+        self.keys
+            .entry(key.to_string())
+            .or_default()
+            .add(kind, bytes);
+    }
+
+    fn absorb(&mut self, other: &Self) {
+        self.total.absorb(&other.total);
+        for (video_id, usage) in &other.videos {
+            self.videos.entry(*video_id).or_default().absorb(usage);
+        }
+        for (key, usage) in &other.keys {
+            self.keys.entry(key.clone()).or_default().absorb(usage);
+        }
+    }
+}
+
+/// Accumulates bytes served per video and per caller key.
+///
+/// Counts are kept in memory and merged into the month's file on
+/// [`flush`](Self::flush), which runs every `VIDEO_BANDWIDTH_FLUSH_SECS` and
+/// before every rollup. The merge holds the `bandwidth.<YYYY-MM>` lock, so
+/// instances sharing a storage root add up rather than overwrite each other.
+#[derive(Clone)]
+pub struct BandwidthLedger {
+    storage: Storage,
+    pending: Arc<Mutex<BTreeMap<String, MonthlyBandwidth>>>,
+}
+
+impl BandwidthLedger {
+    pub fn new(storage: Storage) -> Self {
+        Self {
+            storage,
+            pending: Arc::default(),
+        }
+    }
+
+    pub fn record(&self, video_id: Uuid, key: &str, kind: DeliveryKind, bytes: u64) {
+        if bytes == 0 {
+            return;
+        }
+        metrics::add(
+            "vrs_delivery_bytes_total",
+            &[("kind", kind.as_str())],
+            bytes,
+        );
+        let month = current_month();
+        let mut pending = self.pending.lock().unwrap_or_else(|p| p.into_inner());
+        pending
+            .entry(month)
+            .or_default()
+            .record(video_id, key, kind, bytes);
+    }
+
+    /// Wraps `response` so the bytes of its body are recorded as they are
+    /// sent. A client that disconnects early is only charged for what it
+    /// received.
+    pub fn meter(
+        &self,
+        response: Response,
+        video_id: Uuid,
+        headers: &HeaderMap,
+        kind: DeliveryKind,
+    ) -> Response {
+        self.meter_parts(response, vec![(video_id, u64::MAX)], headers, kind)
+    }
+
+    /// Like [`meter`](Self::meter), for a body made of consecutive parts
+    /// belonging to different videos, such as a collection playlist. Each
+    /// `(video_id, len)` is charged for the bytes of its part in order; the
+    /// last one takes whatever is left over.
+    pub fn meter_parts(
+        &self,
+        response: Response,
+        parts: Vec<(Uuid, u64)>,
+        headers: &HeaderMap,
+        kind: DeliveryKind,
+    ) -> Response {
+        let (head, body) = response.into_parts();
+        let mut meter = Meter {
+            ledger: self.clone(),
+            parts,
+            key: request_key(headers),
+            kind,
+            bytes: 0,
+        };
+        let stream = body.into_data_stream().map(move |chunk| {
+            // Name the whole meter so the closure owns it, not just the
+            // counter field; it must be dropped with the stream.
+            let meter = &mut meter;
+            if let Ok(bytes) = &chunk {
+                meter.bytes += bytes.len() as u64;
+            }
+            chunk
+        });
+        Response::from_parts(head, Body::from_stream(stream))
+    }
+
+    /// Merges pending counts into their month files. Counts that could not
+    /// be written are kept for the next attempt.
+    pub async fn flush(&self) -> Result<(), AppError> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap_or_else(|p| p.into_inner()));
+        let mut result = Ok(());
+        for (month, usage) in pending {
+            if result.is_ok() {
+                result = self.merge(&month, &usage).await;
+                if result.is_ok() {
+                    continue;
+                }
+            }
+            self.pending
+                .lock()
+                .unwrap_or_else(|p| p.into_inner())
+                .entry(month)
+                .or_default()
+                .absorb(&usage);
+        }
+        result
+    }
+
+    /// Everything recorded for `month` (`YYYY-MM`), including counts not yet
+    /// flushed.
+    pub async fn month(&self, month: &str) -> Result<MonthlyBandwidth, AppError> {
+        validate_month(month)?;
+        self.flush().await?;
+        read_month(&self.path(month)).await
+    }
+
+    async fn merge(&self, month: &str, usage: &MonthlyBandwidth) -> Result<(), AppError> {
+        let _guard = self
+            .storage
+            .locks()
+            .acquire(&format!("bandwidth.{month}"))
+            .await?;
+        let path = self.path(month);
+        let mut stored = read_month(&path).await?;
+        stored.absorb(usage);
+        ensure_dir(&self.dir()).await?;
+        let bytes = serde_json::to_vec_pretty(&stored).map_err(std::io::Error::from)?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, bytes).await?;
+        fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    fn dir(&self) -> PathBuf {
+        self.storage.root_dir().join("analytics").join("bandwidth")
+    }
+
+    fn path(&self, month: &str) -> PathBuf {
+        self.dir().join(format!("{month}.json"))
+    }
+}
+
+/// Charges the bytes it saw to the ledger once the body is finished or dropped.
+struct Meter {
+    ledger: BandwidthLedger,
+    parts: Vec<(Uuid, u64)>,
+    key: String,
+    kind: DeliveryKind,
+    bytes: u64,
+}
+
+impl Drop for Meter {
+    fn drop(&mut self) {
+        let mut remaining = self.bytes;
+        let count = self.parts.len();
+        for (index, (video_id, len)) in self.parts.iter().enumerate() {
+            let bytes = if index + 1 == count {
+                remaining
+            } else {
+                remaining.min(*len)
+            };
+            self.ledger.record(*video_id, &self.key, self.kind, bytes);
+            remaining -= bytes;
+        }
+    }
+}
+
+async fn read_month(path: &Path) -> Result<MonthlyBandwidth, AppError> {
+    match fs::read(path).await {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes).map_err(std::io::Error::from)?),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(MonthlyBandwidth::default()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The caller key for a request: the value of `VIDEO_BANDWIDTH_KEY_HEADER`
+/// (default `X-Tenant-Id`), or `anonymous` when it is absent.
+pub fn request_key(headers: &HeaderMap) -> String {
+    let header = config::var("VIDEO_BANDWIDTH_KEY_HEADER")
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| DEFAULT_KEY_HEADER.to_string());
+    headers
+        .get(header.as_str())
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| value.chars().take(MAX_KEY_LEN).collect())
+        .unwrap_or_else(|| ANONYMOUS_KEY.to_string())
+}
+
+/// The current UTC month as `YYYY-MM`.
+pub fn current_month() -> String {
+    clock::rfc3339(clock::unix_ms(SystemTime::now()))[..7].to_string()
+}
+
+fn validate_month(month: &str) -> Result<(), AppError> {
+    let valid = month.len() == 7
+        && month.as_bytes()[4] == b'-'
+        && month
+            .bytes()
+            .enumerate()
+            .all(|(index, byte)| index == 4 || byte.is_ascii_digit())
+        && matches!(month[5..].parse::<u8>(), Ok(1..=12));
+    if valid {
+        Ok(())
+    } else {
+        Err(
+            AppError::validation(format!("month must be YYYY-MM, got {month:?}"))
+                .with_code("month_invalid"),
+        )
+    }
+}
@@ -0,0 +1,104 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    clock,
+    error::AppError,
+    jobs::{DynJobStore, JobGroupMember, JobGroupStatus},
+    storage::{Storage, ensure_parent},
+};
+
+/// The jobs created from one playlist or channel, persisted as
+/// `<storage>/batches/<id>.json`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Batch {
+    pub id: Uuid,
+    /// The playlist URL that was expanded.
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub created_at_unix_ms: u128,
+    /// In playlist order.
+    pub entries: Vec<BatchEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchEntry {
+    /// The job, and the video it publishes.
+    pub id: Uuid,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+/// A batch with the progress of its jobs, aggregated as for a job group.
+/// Members are named after their entry URLs; jobs the store has forgotten
+/// are left out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchStatus {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub created_at: String,
+    pub entries: Vec<BatchEntry>,
+    #[serde(flatten)]
+    pub progress: JobGroupStatus,
+}
+
+fn path(storage: &Storage, id: &Uuid) -> PathBuf {
+    storage
+        .root_dir()
+        .join("batches")
+        .join(format!("{}.json", id.hyphenated()))
+}
+
+pub async fn save(storage: &Storage, batch: &Batch) -> Result<(), AppError> {
+    let path = path(storage, &batch.id);
+    ensure_parent(&path).await?;
+    let bytes = serde_json::to_vec_pretty(batch).map_err(std::io::Error::from)?;
+    let temp = path.with_extension("json.tmp");
+    fs::write(&temp, bytes).await?;
+    fs::rename(&temp, &path).await?;
+    Ok(())
+}
+
+pub async fn load(storage: &Storage, id: &Uuid) -> Result<Batch, AppError> {
+    match fs::read(path(storage, id)).await {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes).map_err(std::io::Error::from)?),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            Err(AppError::not_found(format!("batch {id}"))
+                .with_code("batch_not_found")
+                .with_param("id", id.to_string()))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The batch `id` with the current status of each of its jobs.
+pub async fn status(
+    storage: &Storage,
+    jobs: &DynJobStore,
+    id: &Uuid,
+) -> Result<BatchStatus, AppError> {
+    let batch = load(storage, id).await?;
+    let mut members = Vec::with_capacity(batch.entries.len());
+    for entry in &batch.entries {
+        if let Some(status) = jobs.status(&entry.id).await? {
+            members.push(JobGroupMember {
+                name: entry.url.clone(),
+                weight: 1.0,
+                status,
+            });
+        }
+    }
+    Ok(BatchStatus {
+        url: batch.url,
+        title: batch.title,
+        created_at: clock::rfc3339(batch.created_at_unix_ms),
+        entries: batch.entries,
+        progress: JobGroupStatus::aggregate(batch.id, members),
+    })
+}
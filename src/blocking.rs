@@ -0,0 +1,122 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+use crate::config;
+
+const DEFAULT_THREADS: usize = 4;
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// Dedicated threads for the blocking work of ingest and maintenance: disk
+/// usage checks, large file copies, archive extraction, password hashing and
+/// policy evaluation.
+///
+/// Tokio's own blocking pool backs `tokio::fs`, which delivery uses to read
+/// segments. Keeping heavy ingest work on a separate, fixed set of
+/// `VIDEO_BLOCKING_THREADS` threads means a burst of uploads queues here
+/// instead of delaying segment reads.
+struct BlockingPool {
+    sender: mpsc::Sender<Task>,
+    threads: usize,
+    queued: Arc<AtomicUsize>,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("blocking task panicked")]
+pub struct TaskPanicked;
+
+static POOL: OnceLock<BlockingPool> = OnceLock::new();
+
+fn pool() -> &'static BlockingPool {
+    POOL.get_or_init(|| {
+        let threads = config::parse_var::<usize>("VIDEO_BLOCKING_THREADS")
+            .filter(|threads| *threads > 0)
+            .unwrap_or(DEFAULT_THREADS);
+        BlockingPool::start(threads)
+    })
+}
+
+impl BlockingPool {
+    fn start(threads: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Task>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for index in 0..threads {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("vrs-blocking-{index}"))
+                .spawn(move || {
+                    loop {
+                        let task = receiver.lock().unwrap_or_else(|p| p.into_inner()).recv();
+                        match task {
+                            Ok(task) => task(),
+                            Err(_) => break,
+                        }
+                    }
+                })
+                .expect("failed to start blocking pool thread");
+        }
+        tracing::debug!(threads, "blocking pool started");
+        Self {
+            sender,
+            threads,
+            queued: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+/// Runs `task` on the blocking pool and waits for its result.
+pub async fn run<T, F>(task: F) -> Result<T, TaskPanicked>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let pool = pool();
+    let (sender, receiver) = oneshot::channel();
+    let queued = pool.queued.clone();
+    queued.fetch_add(1, Ordering::Relaxed);
+    let job: Task = Box::new(move || {
+        queued.fetch_sub(1, Ordering::Relaxed);
+        let _ = sender.send(panic::catch_unwind(AssertUnwindSafe(task)));
+    });
+    if pool.sender.send(job).is_err() {
+        return Err(TaskPanicked);
+    }
+    match receiver.await {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(_)) | Err(_) => Err(TaskPanicked),
+    }
+}
+
+/// Copies `from` to `to` on the blocking pool, like [`tokio::fs::copy`].
+pub async fn copy(from: &Path, to: &Path) -> std::io::Result<u64> {
+    let (from, to) = (from.to_path_buf(), to.to_path_buf());
+    run(move || std::fs::copy(from, to))
+        .await
+        .map_err(std::io::Error::other)?
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct BlockingStats {
+    pub threads: usize,
+    /// Tasks waiting for a free thread.
+    pub queued: usize,
+}
+
+pub fn stats() -> BlockingStats {
+    let pool = pool();
+    BlockingStats {
+        threads: pool.threads,
+        queued: pool.queued.load(Ordering::Relaxed),
+    }
+}
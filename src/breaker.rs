@@ -0,0 +1,186 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use reqwest::Url;
+use serde::Serialize;
+
+use crate::{
+    config,
+    error::{AppError, ErrorClass},
+};
+
+const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+const DEFAULT_COOLDOWN_SECS: u64 = 300;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BreakerConfig {
+    /// Consecutive download failures that open a host's breaker; 0 disables it.
+    pub failure_threshold: u32,
+    pub cooldown: Duration,
+}
+
+impl BreakerConfig {
+    pub fn from_env() -> Self {
+        Self {
+            failure_threshold: config::parse_var("VIDEO_BREAKER_FAILURES")
+                .unwrap_or(DEFAULT_FAILURE_THRESHOLD),
+            cooldown: Duration::from_secs(
+                config::parse_var("VIDEO_BREAKER_COOLDOWN_SECS").unwrap_or(DEFAULT_COOLDOWN_SECS),
+            ),
+        }
+    }
+}
+
+/// A host with recent download failures.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HostReport {
+    pub host: String,
+    pub consecutive_failures: u32,
+    pub open: bool,
+    /// Seconds until new jobs are accepted again while open.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after_secs: Option<u64>,
+    pub trips: u64,
+}
+
+/// Per-host circuit breaker for remote downloads.
+///
+/// After `VIDEO_BREAKER_FAILURES` consecutive failed downloads from one host,
+/// new jobs for it are refused for `VIDEO_BREAKER_COOLDOWN_SECS`. Once the
+/// cooldown passes, jobs are admitted again; the next failure reopens the
+/// breaker straight away and a success closes it.
+#[derive(Clone, Default)]
+pub struct HostBreaker {
+    inner: Arc<BreakerInner>,
+}
+
+#[derive(Default)]
+struct BreakerInner {
+    /// Fixed thresholds; otherwise they are re-read from config on each check.
+    config: Option<BreakerConfig>,
+    hosts: Mutex<BTreeMap<String, HostState>>,
+}
+
+#[derive(Default)]
+struct HostState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    trips: u64,
+}
+
+impl HostBreaker {
+    /// Uses `config` instead of reading thresholds from the environment.
+    pub fn with_config(config: BreakerConfig) -> Self {
+        Self {
+            inner: Arc::new(BreakerInner {
+                config: Some(config),
+                ..BreakerInner::default()
+            }),
+        }
+    }
+
+    fn config(&self) -> BreakerConfig {
+        self.inner
+            .config
+            .clone()
+            .unwrap_or_else(BreakerConfig::from_env)
+    }
+
+    /// Fails with `503` and `Retry-After` while the breaker for `url`'s host
+    /// is open. Sources without a host, such as magnet links, always pass.
+    pub fn admit(&self, url: &str) -> Result<(), AppError> {
+        let Some(host) = host_of(url) else {
+            return Ok(());
+        };
+        let hosts = self.inner.hosts.lock().unwrap_or_else(|p| p.into_inner());
+        let Some(state) = hosts.get(&host) else {
+            return Ok(());
+        };
+        match state
+            .open_until
+            .map(|until| until.saturating_duration_since(Instant::now()))
+        {
+            Some(remaining) if !remaining.is_zero() => Err(AppError::overloaded(
+                format!(
+                    "downloads from {host} are paused after {} consecutive failures",
+                    state.consecutive_failures
+                ),
+                remaining.as_secs().max(1),
+            )
+            .with_code("source_host_paused")
+            .with_param("host", host.as_str())
+            .with_param("failures", state.consecutive_failures)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records how a download from `url` ended. Only failures the host is
+    /// responsible for count; local problems such as a full disk or a
+    /// cancelled job leave the breaker untouched.
+    pub fn record(&self, url: &str, result: Result<(), &AppError>) {
+        let Some(host) = host_of(url) else {
+            return;
+        };
+        let mut hosts = self.inner.hosts.lock().unwrap_or_else(|p| p.into_inner());
+        match result {
+            Ok(()) => {
+                if let Some(state) = hosts.remove(&host)
+                    && state.open_until.is_some()
+                {
+                    tracing::info!(%host, "download succeeded; closing circuit breaker");
+                }
+            }
+            Err(err) if matches!(err.class(), ErrorClass::Network | ErrorClass::SourceInvalid) => {
+                let config = self.config();
+                if config.failure_threshold == 0 {
+                    return;
+                }
+                let state = hosts.entry(host.clone()).or_default();
+                state.consecutive_failures += 1;
+                if state.consecutive_failures >= config.failure_threshold {
+                    state.open_until = Some(Instant::now() + config.cooldown);
+                    state.trips += 1;
+                    tracing::warn!(
+                        %host,
+                        failures = state.consecutive_failures,
+                        cooldown_secs = config.cooldown.as_secs(),
+                        "opening circuit breaker for source host"
+                    );
+                }
+            }
+            Err(_) => {}
+        }
+    }
+
+    /// Hosts with at least one failure since their last success.
+    pub fn report(&self) -> Vec<HostReport> {
+        let now = Instant::now();
+        let hosts = self.inner.hosts.lock().unwrap_or_else(|p| p.into_inner());
+        hosts
+            .iter()
+            .map(|(host, state)| {
+                let remaining = state
+                    .open_until
+                    .map(|until| until.saturating_duration_since(now))
+                    .filter(|remaining| !remaining.is_zero());
+                HostReport {
+                    host: host.clone(),
+                    consecutive_failures: state.consecutive_failures,
+                    open: remaining.is_some(),
+                    retry_after_secs: remaining.map(|remaining| remaining.as_secs().max(1)),
+                    trips: state.trips,
+                }
+            })
+            .collect()
+    }
+}
+
+fn host_of(url: &str) -> Option<String> {
+    Url::parse(url)
+        .ok()?
+        .host_str()
+        .map(|host| host.to_ascii_lowercase())
+}
@@ -0,0 +1,160 @@
+use std::time::{Duration, Instant};
+
+use reqwest::{Client, Url};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::{
+    config,
+    error::AppError,
+    jobs::{DynJobStore, JobStage, JobStatusResponse},
+};
+
+const ATTEMPTS: u32 = 3;
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const PROGRESS_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// Progress deliveries slower than this count against the receiver.
+const SLOW_DELIVERY: Duration = Duration::from_secs(1);
+const MAX_PROGRESS_PAUSE: Duration = Duration::from_secs(60);
+
+/// Whether jobs with a callback URL also report their progress there.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProgressCallbacks {
+    /// Percent of overall progress between two reports; 0 turns them off.
+    pub step_percent: u32,
+}
+
+impl ProgressCallbacks {
+    /// From `VIDEO_CALLBACK_PROGRESS_STEP`, in percent.
+    pub fn from_env() -> Self {
+        Self {
+            step_percent: config::parse_var::<u32>("VIDEO_CALLBACK_PROGRESS_STEP")
+                .unwrap_or(0)
+                .min(100),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.step_percent > 0
+    }
+
+    /// How many steps of overall `progress`, from 0 to 1, a job has made.
+    pub fn steps(&self, progress: f32) -> u32 {
+        let percent = (progress.clamp(0.0, 1.0) * 100.0).floor() as u32;
+        percent / self.step_percent.max(1)
+    }
+
+    /// The pause before the next report after one that took `took` and
+    /// failed or not, given the pause before it. Slow or failing receivers
+    /// are given exponentially longer breaks, a prompt one none at all.
+    pub fn next_pause(&self, pause: Duration, delivered: bool, took: Duration) -> Duration {
+        if delivered && took <= SLOW_DELIVERY {
+            return Duration::ZERO;
+        }
+        pause
+            .saturating_mul(2)
+            .clamp(FIRST_BACKOFF, MAX_PROGRESS_PAUSE)
+    }
+}
+
+/// Reports a running job's progress until it is dropped.
+pub struct ProgressReporter(JoinHandle<()>);
+
+impl Drop for ProgressReporter {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Checks a callback URL given at ingest; only HTTP(S) is accepted.
+pub fn validate_url(url: &str) -> Result<String, AppError> {
+    match Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => Ok(parsed.into()),
+        _ => Err(
+            AppError::validation(format!("callback_url must be an http(s) URL, got {url:?}"))
+                .with_code("callback_url_invalid"),
+        ),
+    }
+}
+
+/// Posts the final status of a job to its callback URL. Failed deliveries
+/// are retried with backoff, then given up on with a warning; the job's
+/// outcome does not depend on them.
+pub async fn notify(client: &Client, url: &str, status: &JobStatusResponse) {
+    let mut backoff = FIRST_BACKOFF;
+    for attempt in 1..=ATTEMPTS {
+        match post(client, url, status).await {
+            Ok(_) => {
+                tracing::debug!(job_id = %status.id, url, "job callback delivered");
+                return;
+            }
+            Err(err) if attempt == ATTEMPTS => {
+                tracing::warn!(job_id = %status.id, url, error = %err, "giving up on job callback");
+            }
+            Err(err) => {
+                tracing::debug!(job_id = %status.id, url, attempt, error = %err, "job callback failed");
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+    }
+}
+
+/// Posts the status of job `id` to `url` whenever its stage changes or its
+/// progress passes another step of `config`, until the returned reporter is
+/// dropped or the job ends. The final status is left to [`notify`].
+/// Reports are not retried: a missed one is superseded by the next, which
+/// carries the latest status once the receiver has had its pause.
+pub fn report_progress(
+    client: Client,
+    url: String,
+    jobs: DynJobStore,
+    id: Uuid,
+    config: ProgressCallbacks,
+) -> ProgressReporter {
+    ProgressReporter(tokio::spawn(async move {
+        let mut sent: Option<(JobStage, u32)> = None;
+        let mut pause = Duration::ZERO;
+        let mut poll = tokio::time::interval(PROGRESS_POLL_INTERVAL);
+        loop {
+            poll.tick().await;
+            let status = match jobs.status(&id).await {
+                Ok(Some(status)) if !status.stage.is_terminal() => status,
+                Ok(_) => return,
+                Err(err) => {
+                    tracing::debug!(job_id = %id, error = %err, "cannot read status for progress callback");
+                    continue;
+                }
+            };
+            let mark = (status.stage, config.steps(status.progress));
+            if sent.is_some_and(|(stage, steps)| stage == mark.0 && steps >= mark.1) {
+                continue;
+            }
+            let started = Instant::now();
+            let result = post(&client, &url, &status).await;
+            if let Err(err) = &result {
+                tracing::debug!(job_id = %id, url, error = %err, "progress callback failed");
+            }
+            sent = Some(mark);
+            pause = config.next_pause(pause, result.is_ok(), started.elapsed());
+            if !pause.is_zero() {
+                tokio::time::sleep(pause).await;
+            }
+        }
+    }))
+}
+
+async fn post(
+    client: &Client,
+    url: &str,
+    status: &JobStatusResponse,
+) -> Result<reqwest::Response, reqwest::Error> {
+    client
+        .post(url)
+        .timeout(REQUEST_TIMEOUT)
+        .json(status)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+}
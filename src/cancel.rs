@@ -0,0 +1,115 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::error::AppError;
+
+/// Pipelines running in this process, by job id, so they can be cancelled.
+#[derive(Clone, Default)]
+pub struct RunningJobs {
+    tokens: Arc<Mutex<HashMap<Uuid, (u64, CancellationToken)>>>,
+    registrations: Arc<AtomicU64>,
+}
+
+impl RunningJobs {
+    /// Registers the pipeline of job `id` until the returned handle is dropped.
+    pub fn register(&self, id: Uuid) -> RunningJob {
+        let mut tokens = self.tokens.lock().unwrap_or_else(|p| p.into_inner());
+        self.insert(&mut tokens, id)
+    }
+
+    /// Like [`register`](Self::register), but returns `None` when a pipeline
+    /// for `id` is already registered.
+    pub fn register_idle(&self, id: Uuid) -> Option<RunningJob> {
+        let mut tokens = self.tokens.lock().unwrap_or_else(|p| p.into_inner());
+        if tokens.contains_key(&id) {
+            return None;
+        }
+        Some(self.insert(&mut tokens, id))
+    }
+
+    /// Stops the pipeline of job `id`. Returns `false` when no pipeline for
+    /// it runs in this process.
+    pub fn cancel(&self, id: &Uuid) -> bool {
+        match self
+            .tokens
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .get(id)
+        {
+            Some((_, token)) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_running(&self, id: &Uuid) -> bool {
+        self.tokens
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .contains_key(id)
+    }
+
+    fn insert(&self, tokens: &mut HashMap<Uuid, (u64, CancellationToken)>, id: Uuid) -> RunningJob {
+        let token = CancellationToken::new();
+        let registration = self.registrations.fetch_add(1, Ordering::Relaxed);
+        tokens.insert(id, (registration, token.clone()));
+        RunningJob {
+            jobs: self.clone(),
+            id,
+            registration,
+            token,
+        }
+    }
+}
+
+/// Registration of one running pipeline; removed on drop.
+pub struct RunningJob {
+    jobs: RunningJobs,
+    id: Uuid,
+    registration: u64,
+    token: CancellationToken,
+}
+
+impl RunningJob {
+    /// Drives `pipeline` until it finishes or the job is cancelled. On
+    /// cancellation the pipeline future is dropped, which kills any external
+    /// tool it is waiting on.
+    pub async fn run<T>(
+        &self,
+        pipeline: impl Future<Output = Result<T, AppError>>,
+    ) -> Result<T, AppError> {
+        tokio::select! {
+            result = pipeline => result,
+            _ = self.token.cancelled() => Err(AppError::cancelled(format!("job {} was cancelled", self.id))),
+        }
+    }
+
+    /// Whether the job was cancelled, e.g. before its pipeline started.
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+}
+
+impl Drop for RunningJob {
+    fn drop(&mut self) {
+        let mut tokens = self.jobs.tokens.lock().unwrap_or_else(|p| p.into_inner());
+        // A newer registration for the same id, e.g. from a retry, stays.
+        if tokens
+            .get(&self.id)
+            .is_some_and(|(registration, _)| *registration == self.registration)
+        {
+            tokens.remove(&self.id);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    blocking,
+    error::AppError,
+    storage::{Storage, ensure_dir},
+};
+
+const MAX_LANGUAGES: usize = 20;
+const MAX_LANGUAGE_LEN: usize = 32;
+
+/// Subtitles to fetch with yt-dlp, converted to WebVTT and added to the
+/// video's caption set.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubtitleRequest {
+    /// yt-dlp `--sub-langs` entries such as `en`, `de`, `en.*` or `all`.
+    #[serde(default)]
+    pub languages: Vec<String>,
+    /// Fall back to the platform's automatic captions.
+    #[serde(default)]
+    pub auto_captions: bool,
+}
+
+impl SubtitleRequest {
+    pub fn is_empty(&self) -> bool {
+        self.languages.is_empty()
+    }
+
+    pub fn validate(&self) -> Result<(), AppError> {
+        if self.languages.len() > MAX_LANGUAGES {
+            return Err(AppError::validation(format!(
+                "at most {MAX_LANGUAGES} subtitle languages can be requested"
+            ))
+            .with_code("subtitle_languages_invalid")
+            .with_param("max", MAX_LANGUAGES));
+        }
+        for language in &self.languages {
+            let pattern = |c: char| c.is_ascii_alphanumeric() || "_-.*+".contains(c);
+            if language.is_empty()
+                || language.len() > MAX_LANGUAGE_LEN
+                || !language.chars().all(pattern)
+            {
+                return Err(AppError::validation(format!(
+                    "invalid subtitle language {language:?}"
+                ))
+                .with_code("subtitle_languages_invalid")
+                .with_param("language", language.as_str()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A WebVTT track of the caption set.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaptionTrack {
+    pub language: String,
+    pub url: String,
+}
+
+/// Whether `language` can name a track file, e.g. `en` or `pt-BR`.
+pub fn is_track_language(language: &str) -> bool {
+    !language.is_empty()
+        && language.len() <= MAX_LANGUAGE_LEN
+        && language
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The tracks in `captions/` of the video directory, sorted by language.
+pub async fn tracks(storage: &Storage, id: &Uuid) -> Result<Vec<CaptionTrack>, AppError> {
+    let mut entries = match fs::read_dir(storage.captions_dir(id)).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut tracks = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let Some(language) = name.to_str().and_then(|name| name.strip_suffix(".vtt")) else {
+            continue;
+        };
+        if is_track_language(language) {
+            tracks.push(CaptionTrack {
+                language: language.to_string(),
+                url: format!("/videos/{id}/captions/{language}.vtt"),
+            });
+        }
+    }
+    tracks.sort_by(|a, b| a.language.cmp(&b.language));
+    Ok(tracks)
+}
+
+/// Moves downloaded `(language, file)` pairs into the caption set,
+/// replacing tracks of the same language.
+pub async fn attach(
+    storage: &Storage,
+    id: &Uuid,
+    files: Vec<(String, PathBuf)>,
+) -> Result<(), AppError> {
+    if files.is_empty() {
+        return Ok(());
+    }
+    let dir = storage.captions_dir(id);
+    ensure_dir(&dir).await?;
+    for (language, file) in files {
+        move_file(&file, &dir.join(format!("{language}.vtt"))).await?;
+    }
+    Ok(())
+}
+
+async fn move_file(from: &Path, to: &Path) -> Result<(), AppError> {
+    if fs::rename(from, to).await.is_err() {
+        // The incoming area may be on another filesystem.
+        blocking::copy(from, to).await?;
+        fs::remove_file(from).await.ok();
+    }
+    Ok(())
+}
@@ -0,0 +1,145 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+};
+
+use serde::Serialize;
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    clock,
+    error::AppError,
+    metadata::{self, VideoMetadata},
+    storage::{Storage, dir_size},
+};
+
+/// A video in the storage root with the assets that exist for it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StoredVideo {
+    pub id: Uuid,
+    /// Total size of the files in the video's directory.
+    pub size_bytes: u64,
+    pub created_at: String,
+    pub created_at_unix_ms: u64,
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+    /// See [`listed_attributes`].
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, serde_json::Value>,
+    pub password_protected: bool,
+    pub assets: VideoAssets,
+}
+
+/// Which derived assets of a video are on disk right now. HLS and DASH are
+/// packaged on demand, so `false` there only means they are not cached.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct VideoAssets {
+    pub download: bool,
+    pub hls: bool,
+    pub dash: bool,
+    pub thumbnail: bool,
+    pub sprites: bool,
+    pub preview: bool,
+    pub captions: bool,
+    pub fallback: bool,
+    pub source: bool,
+    pub proxy: bool,
+}
+
+impl VideoAssets {
+    pub fn of(storage: &Storage, id: &Uuid) -> Self {
+        Self {
+            download: storage.download_path(id).exists(),
+            hls: storage.hls_dir(id).join("master.m3u8").exists(),
+            dash: storage.dash_dir(id).join("manifest.mpd").exists(),
+            thumbnail: storage.thumbnail_path(id).exists(),
+            sprites: storage.sprite_path(id).exists(),
+            preview: storage.preview_path(id).exists(),
+            captions: storage.captions_path(id).exists() || storage.captions_dir(id).exists(),
+            fallback: storage.fallback_path(id).exists(),
+            source: storage.source_path(id).exists(),
+            proxy: storage.proxy_path(id).exists(),
+        }
+    }
+}
+
+/// A video directory found by [`scan`].
+#[derive(Debug, Clone)]
+pub struct CatalogEntry {
+    pub id: Uuid,
+    pub dir: PathBuf,
+    /// When the directory was created, or last modified where the
+    /// filesystem does not record creation.
+    pub created_at_unix_ms: u64,
+    pub meta: VideoMetadata,
+}
+
+/// Every video directory under the storage root, in no particular order,
+/// keeping those tagged `tag` if given. Both `GET /videos` and the tag
+/// listings are built from this scan.
+pub async fn scan(storage: &Storage, tag: Option<&str>) -> Result<Vec<CatalogEntry>, AppError> {
+    let mut entries = match fs::read_dir(storage.root_dir()).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut videos = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let Some(id) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| Uuid::parse_str(name).ok())
+        else {
+            continue;
+        };
+        let Ok(dir) = entry.metadata().await else {
+            continue;
+        };
+        if !dir.is_dir() {
+            continue;
+        }
+        let meta = metadata::load(storage, &id).await?;
+        if tag.is_some_and(|tag| !meta.tags.contains(tag)) {
+            continue;
+        }
+        let created = dir.created().or_else(|_| dir.modified()).ok();
+        videos.push(CatalogEntry {
+            id,
+            dir: entry.path(),
+            created_at_unix_ms: created.map(clock::unix_ms).unwrap_or(0) as u64,
+            meta,
+        });
+    }
+    Ok(videos)
+}
+
+/// The attributes a listing may show: none for a password-protected video,
+/// whose attributes need the password.
+pub fn listed_attributes(meta: &VideoMetadata) -> BTreeMap<String, serde_json::Value> {
+    if meta.password_hash.is_some() {
+        BTreeMap::new()
+    } else {
+        meta.attributes.clone()
+    }
+}
+
+/// Every video directory under the storage root, oldest first, keeping
+/// those tagged `tag` if given.
+pub async fn list(storage: &Storage, tag: Option<&str>) -> Result<Vec<StoredVideo>, AppError> {
+    let mut videos = Vec::new();
+    for entry in scan(storage, tag).await? {
+        videos.push(StoredVideo {
+            id: entry.id,
+            size_bytes: dir_size(&entry.dir).await,
+            created_at: clock::rfc3339(entry.created_at_unix_ms as u128),
+            created_at_unix_ms: entry.created_at_unix_ms,
+            attributes: listed_attributes(&entry.meta),
+            tags: entry.meta.tags,
+            password_protected: entry.meta.password_hash.is_some(),
+            assets: VideoAssets::of(storage, &entry.id),
+        });
+    }
+    videos.sort_by_key(|video| (video.created_at_unix_ms, video.id));
+    Ok(videos)
+}
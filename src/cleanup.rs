@@ -1,11 +1,12 @@
-use std::{collections::HashSet, env, path::Path};
+use std::{collections::HashSet, path::Path};
 
 use fs2::{available_space, total_space};
-use tokio::task;
+use serde::Serialize;
 use tracing::{info, warn};
 use uuid::Uuid;
 
 use crate::{
+    blocking, config,
     error::AppError,
     jobs::{DynJobStore, JobStage},
     storage::{Storage, ensure_dir},
@@ -20,20 +21,14 @@ pub struct CleanupConfig {
 
 impl CleanupConfig {
     pub fn from_env() -> Self {
-        let minimum_free_bytes = env::var("VIDEO_STORAGE_MIN_FREE_BYTES")
-            .ok()
-            .and_then(|val| val.parse::<u64>().ok())
+        let minimum_free_bytes = config::parse_var::<u64>("VIDEO_STORAGE_MIN_FREE_BYTES")
             .unwrap_or(5 * 1024 * 1024 * 1024); // 5 GiB
 
-        let minimum_free_ratio = env::var("VIDEO_STORAGE_MIN_FREE_RATIO")
-            .ok()
-            .and_then(|val| val.parse::<f32>().ok())
+        let minimum_free_ratio = config::parse_var::<f32>("VIDEO_STORAGE_MIN_FREE_RATIO")
             .map(|ratio| ratio.clamp(0.0, 0.9))
             .unwrap_or(0.1); // 10%
 
-        let max_cleanup_batch = env::var("VIDEO_STORAGE_CLEANUP_BATCH")
-            .ok()
-            .and_then(|val| val.parse::<usize>().ok())
+        let max_cleanup_batch = config::parse_var::<usize>("VIDEO_STORAGE_CLEANUP_BATCH")
             .filter(|&value| value > 0)
             .unwrap_or(5);
 
@@ -100,13 +95,7 @@ pub async fn ensure_capacity(
 
 async fn needs_cleanup(storage: &Storage, config: &CleanupConfig) -> Result<bool, AppError> {
     let root = storage.root_dir();
-    let status = match task::spawn_blocking({
-        let path = root.clone();
-        move || disk_status(&path)
-    })
-    .await
-    .map_err(|err| AppError::dependency(format!("cleanup blocking task failed: {err}")))?
-    {
+    let status = match storage_disk_status(storage).await {
         Ok(status) => status,
         Err(AppError::Io(io_err)) if io_err.kind() == std::io::ErrorKind::NotFound => {
             ensure_dir(root.as_path()).await?;
@@ -115,18 +104,35 @@ async fn needs_cleanup(storage: &Storage, config: &CleanupConfig) -> Result<bool
         Err(other) => return Err(other),
     };
 
-    let free_ratio = if status.total_bytes > 0 {
-        status.free_bytes as f32 / status.total_bytes as f32
-    } else {
-        1.0
-    };
+    Ok(status.below_threshold(config))
+}
+
+/// Reports free and total space for the volume backing the storage root.
+pub async fn storage_disk_status(storage: &Storage) -> Result<DiskStatus, AppError> {
+    let root = storage.root_dir();
+    blocking::run(move || disk_status(&root))
+        .await
+        .map_err(|err| AppError::dependency(format!("cleanup blocking task failed: {err}")))?
+}
 
-    Ok(status.free_bytes < config.minimum_free_bytes || free_ratio < config.minimum_free_ratio)
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct DiskStatus {
+    pub total_bytes: u64,
+    pub free_bytes: u64,
 }
 
-struct DiskStatus {
-    total_bytes: u64,
-    free_bytes: u64,
+impl DiskStatus {
+    pub fn free_ratio(&self) -> f32 {
+        if self.total_bytes > 0 {
+            self.free_bytes as f32 / self.total_bytes as f32
+        } else {
+            1.0
+        }
+    }
+
+    pub fn below_threshold(&self, config: &CleanupConfig) -> bool {
+        self.free_bytes < config.minimum_free_bytes || self.free_ratio() < config.minimum_free_ratio
+    }
 }
 
 fn disk_status(path: &Path) -> Result<DiskStatus, AppError> {
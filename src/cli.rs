@@ -0,0 +1,160 @@
+use crate::error::AppError;
+
+pub const USAGE: &str = "\
+usage: vrs [--output text|json] [command]
+
+commands:
+  (none)                   serve the HTTP API
+  --check                  list pending storage migrations, exit 1 if any
+  transcode <file|->       run one source through the pipeline, - reads stdin
+  completions <shell>      print completions for bash, zsh or fish
+  help                     show this message
+";
+
+/// How commands print their result on stdout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    /// One JSON document, errors included, for scripts and CI.
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Serve,
+    Check,
+    Transcode { input: String },
+    Completions(Shell),
+    Help,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invocation {
+    pub command: Command,
+    pub output: OutputFormat,
+}
+
+/// Parses the arguments after the program name. `--output` may appear
+/// anywhere, as `--output json` or `--output=json`.
+pub fn parse<I>(args: I) -> Result<Invocation, AppError>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut output = OutputFormat::Text;
+    let mut words = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let format = match arg.strip_prefix("--output") {
+            Some("") => Some(args.next().unwrap_or_default()),
+            Some(value) => value.strip_prefix('=').map(str::to_string),
+            _ => None,
+        };
+        match format.as_deref() {
+            Some("text") => output = OutputFormat::Text,
+            Some("json") => output = OutputFormat::Json,
+            Some(other) => {
+                return Err(usage(format!("unknown output format {other:?}"))
+                    .with_param("formats", "text,json"));
+            }
+            None => words.push(arg),
+        }
+    }
+
+    let command = match words.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => Command::Serve,
+        ["--check"] => Command::Check,
+        ["help" | "--help" | "-h"] => Command::Help,
+        ["transcode", input] => Command::Transcode {
+            input: input.to_string(),
+        },
+        ["transcode"] => return Err(usage("transcode needs a file, or - for stdin")),
+        ["completions", shell] => Command::Completions(match shell {
+            "bash" => Shell::Bash,
+            "zsh" => Shell::Zsh,
+            "fish" => Shell::Fish,
+            other => {
+                return Err(
+                    usage(format!("unknown shell {other:?}")).with_param("shells", "bash,zsh,fish")
+                );
+            }
+        }),
+        _ => return Err(usage(format!("unexpected arguments {words:?}"))),
+    };
+    Ok(Invocation { command, output })
+}
+
+fn usage(message: impl std::fmt::Display) -> AppError {
+    AppError::validation(message).with_code("cli_usage")
+}
+
+/// The completion script for `shell`, to be sourced or saved where the
+/// shell looks for completions.
+pub fn completions(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash => BASH_COMPLETIONS,
+        Shell::Zsh => ZSH_COMPLETIONS,
+        Shell::Fish => FISH_COMPLETIONS,
+    }
+}
+
+const BASH_COMPLETIONS: &str = r#"_vrs() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    local prev="${COMP_WORDS[COMP_CWORD-1]}"
+    case "$prev" in
+        --output)
+            COMPREPLY=($(compgen -W "text json" -- "$cur"))
+            return
+            ;;
+        completions)
+            COMPREPLY=($(compgen -W "bash zsh fish" -- "$cur"))
+            return
+            ;;
+        transcode)
+            COMPREPLY=($(compgen -f -- "$cur") $(compgen -W "-" -- "$cur"))
+            return
+            ;;
+    esac
+    COMPREPLY=($(compgen -W "transcode completions help --check --output" -- "$cur"))
+}
+complete -o filenames -F _vrs vrs
+"#;
+
+const ZSH_COMPLETIONS: &str = r#"#compdef vrs
+
+_vrs() {
+    local state
+    _arguments \
+        '--output[output format]:format:(text json)' \
+        '--check[list pending storage migrations]' \
+        '1:command:((transcode\:"run one source through the pipeline" completions\:"print shell completions" help\:"show usage"))' \
+        '*::argument:->argument'
+    case $state in
+        argument)
+            case $words[1] in
+                transcode) _alternative 'files:input:_files' 'stdin:stdin:(-)' ;;
+                completions) _values shell bash zsh fish ;;
+            esac
+            ;;
+    esac
+}
+
+_vrs "$@"
+"#;
+
+const FISH_COMPLETIONS: &str = r#"complete -c vrs -f
+complete -c vrs -l output -x -a 'text json' -d 'Output format'
+complete -c vrs -l check -d 'List pending storage migrations'
+complete -c vrs -n __fish_use_subcommand -a transcode -d 'Run one source through the pipeline'
+complete -c vrs -n __fish_use_subcommand -a completions -d 'Print shell completions'
+complete -c vrs -n __fish_use_subcommand -a help -d 'Show usage'
+complete -c vrs -n '__fish_seen_subcommand_from transcode' -F -a '-'
+complete -c vrs -n '__fish_seen_subcommand_from completions' -a 'bash zsh fish'
+"#;
@@ -0,0 +1,301 @@
+use std::{path::Path, time::Duration};
+
+use reqwest::{
+    Response, StatusCode, Url,
+    header::RANGE,
+    multipart::{Form, Part},
+};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use thiserror::Error;
+use tokio::fs::File;
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+use crate::{
+    batches::BatchStatus,
+    handlers::{
+        BatchResponse, RemoteUploadRequest, UploadOptions, UploadResponse, YtDlpDownloadRequest,
+        YtDlpPlaylistRequest,
+    },
+    jobs::{JobGroupStatus, JobStatusResponse},
+};
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("invalid url: {0}")]
+    Url(#[from] url::ParseError),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("server responded with {status}: {message}")]
+    Api {
+        status: StatusCode,
+        /// Machine-readable error code, e.g. `job_not_found`, when the
+        /// server sent one.
+        code: Option<String>,
+        message: String,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    error: String,
+    #[serde(default)]
+    code: Option<String>,
+}
+
+/// Async client for the VRS HTTP API built on the server's own request and
+/// response types.
+#[derive(Debug, Clone)]
+pub struct VrsClient {
+    base_url: Url,
+    http: reqwest::Client,
+}
+
+impl VrsClient {
+    pub fn new(base_url: &str) -> Result<Self, ClientError> {
+        Self::with_http_client(base_url, reqwest::Client::new())
+    }
+
+    pub fn with_http_client(base_url: &str, http: reqwest::Client) -> Result<Self, ClientError> {
+        let mut base_url = Url::parse(base_url)?;
+        if !base_url.path().ends_with('/') {
+            let path = format!("{}/", base_url.path());
+            base_url.set_path(&path);
+        }
+        Ok(Self { base_url, http })
+    }
+
+    /// Streams a local file to `POST /upload/multipart`.
+    pub async fn upload_file(&self, path: impl AsRef<Path>) -> Result<UploadResponse, ClientError> {
+        self.upload(path.as_ref(), None).await
+    }
+
+    /// Like [`upload_file`](Self::upload_file), with transcode options, tags,
+    /// attributes or a callback URL sent ahead of the file.
+    pub async fn upload_file_with_options(
+        &self,
+        path: impl AsRef<Path>,
+        options: &UploadOptions,
+    ) -> Result<UploadResponse, ClientError> {
+        self.upload(path.as_ref(), Some(options)).await
+    }
+
+    async fn upload(
+        &self,
+        path: &Path,
+        options: Option<&UploadOptions>,
+    ) -> Result<UploadResponse, ClientError> {
+        let file = File::open(path).await?;
+        let length = file.metadata().await?.len();
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("upload")
+            .to_string();
+
+        let part =
+            Part::stream_with_length(reqwest::Body::wrap_stream(ReaderStream::new(file)), length)
+                .file_name(file_name);
+        let mut form = Form::new();
+        if let Some(options) = options {
+            let options = serde_json::to_string(options).map_err(std::io::Error::from)?;
+            form = form.text("options", options);
+        }
+        let form = form.part("file", part);
+
+        let response = self
+            .http
+            .post(self.endpoint("upload/multipart")?)
+            .multipart(form)
+            .send()
+            .await?;
+        parse_json(response).await
+    }
+
+    pub async fn upload_url(
+        &self,
+        request: &RemoteUploadRequest,
+    ) -> Result<UploadResponse, ClientError> {
+        self.post_json("upload/remote", request).await
+    }
+
+    pub async fn download_via_ytdlp(
+        &self,
+        request: &YtDlpDownloadRequest,
+    ) -> Result<UploadResponse, ClientError> {
+        self.post_json("download/yt-dlp", request).await
+    }
+
+    pub async fn download_playlist_via_ytdlp(
+        &self,
+        request: &YtDlpPlaylistRequest,
+    ) -> Result<BatchResponse, ClientError> {
+        self.post_json("download/yt-dlp/playlist", request).await
+    }
+
+    pub async fn batch_status(&self, id: Uuid) -> Result<BatchStatus, ClientError> {
+        let response = self
+            .http
+            .get(self.endpoint(&format!("batches/{id}"))?)
+            .send()
+            .await?;
+        parse_json(response).await
+    }
+
+    pub async fn job_status(&self, id: Uuid) -> Result<JobStatusResponse, ClientError> {
+        let response = self
+            .http
+            .get(self.endpoint(&format!("jobs/{id}"))?)
+            .send()
+            .await?;
+        parse_json(response).await
+    }
+
+    pub async fn job_group(&self, id: Uuid) -> Result<JobGroupStatus, ClientError> {
+        let response = self
+            .http
+            .get(self.endpoint(&format!("jobs/{id}/group"))?)
+            .send()
+            .await?;
+        parse_json(response).await
+    }
+
+    /// Polls a job until it completes, fails, or waits for approval with its
+    /// review proxy ready.
+    pub async fn wait_for_job(&self, id: Uuid) -> Result<JobStatusResponse, ClientError> {
+        let mut watcher = self.watch_job(id, DEFAULT_POLL_INTERVAL);
+        let mut last = None;
+        while let Some(status) = watcher.next().await {
+            last = Some(status?);
+        }
+        last.ok_or_else(|| ClientError::Api {
+            status: StatusCode::NOT_FOUND,
+            code: None,
+            message: format!("job {id} produced no status"),
+        })
+    }
+
+    /// Returns a watcher that yields each distinct job snapshot until the job
+    /// finishes or waits for approval.
+    pub fn watch_job(&self, id: Uuid, interval: Duration) -> JobWatcher {
+        JobWatcher {
+            client: self.clone(),
+            id,
+            interval,
+            last_update: None,
+            finished: false,
+        }
+    }
+
+    /// Fetches `/videos/{id}/download`, optionally restricted to a byte range.
+    pub async fn download_video(
+        &self,
+        id: Uuid,
+        range: Option<(u64, Option<u64>)>,
+    ) -> Result<Response, ClientError> {
+        let mut request = self
+            .http
+            .get(self.endpoint(&format!("videos/{id}/download"))?);
+        if let Some((start, end)) = range {
+            let end = end.map(|value| value.to_string()).unwrap_or_default();
+            request = request.header(RANGE, format!("bytes={start}-{end}"));
+        }
+        ensure_success(request.send().await?).await
+    }
+
+    pub async fn fetch_hls_asset(&self, id: Uuid, asset: &str) -> Result<Response, ClientError> {
+        self.fetch(&format!("videos/{id}/hls/{asset}")).await
+    }
+
+    pub async fn fetch_dash_asset(&self, id: Uuid, asset: &str) -> Result<Response, ClientError> {
+        self.fetch(&format!("videos/{id}/dash/{asset}")).await
+    }
+
+    async fn fetch(&self, path: &str) -> Result<Response, ClientError> {
+        ensure_success(self.http.get(self.endpoint(path)?).send().await?).await
+    }
+
+    async fn post_json<B: Serialize, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<T, ClientError> {
+        let response = self
+            .http
+            .post(self.endpoint(path)?)
+            .json(body)
+            .send()
+            .await?;
+        parse_json(response).await
+    }
+
+    fn endpoint(&self, path: &str) -> Result<Url, ClientError> {
+        Ok(self.base_url.join(path.trim_start_matches('/'))?)
+    }
+}
+
+pub struct JobWatcher {
+    client: VrsClient,
+    id: Uuid,
+    interval: Duration,
+    last_update: Option<u128>,
+    finished: bool,
+}
+
+impl JobWatcher {
+    /// Waits for the next changed snapshot; returns `None` once a terminal
+    /// stage, or a job awaiting approval with nothing running, was yielded.
+    pub async fn next(&mut self) -> Option<Result<JobStatusResponse, ClientError>> {
+        if self.finished {
+            return None;
+        }
+
+        loop {
+            let status = match self.client.job_status(self.id).await {
+                Ok(status) => status,
+                Err(err) => {
+                    self.finished = true;
+                    return Some(Err(err));
+                }
+            };
+
+            if status.stage.is_terminal() || status.review_ready {
+                self.finished = true;
+                return Some(Ok(status));
+            }
+
+            if self.last_update != Some(status.last_update_unix_ms) {
+                self.last_update = Some(status.last_update_unix_ms);
+                return Some(Ok(status));
+            }
+
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+}
+
+async fn ensure_success(response: Response) -> Result<Response, ClientError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let text = response.text().await.unwrap_or_default();
+    let (code, message) = match serde_json::from_str::<ErrorBody>(&text) {
+        Ok(body) => (body.code, body.error),
+        Err(_) => (None, text),
+    };
+    Err(ClientError::Api {
+        status,
+        code,
+        message,
+    })
+}
+
+async fn parse_json<T: DeserializeOwned>(response: Response) -> Result<T, ClientError> {
+    Ok(ensure_success(response).await?.json::<T>().await?)
+}
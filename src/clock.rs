@@ -0,0 +1,91 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+/// Server clock as reported by `/healthz`, for clients to detect skew and
+/// correlate timestamps across services.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ClockInfo {
+    /// Current time in UTC, RFC 3339.
+    pub time: String,
+    pub unix_ms: u128,
+    /// IANA zone the host is configured for, e.g. `Europe/Berlin`. All
+    /// timestamps in responses are UTC regardless.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+impl ClockInfo {
+    pub fn now() -> Self {
+        let unix_ms = unix_ms(SystemTime::now());
+        Self {
+            time: rfc3339(unix_ms),
+            unix_ms,
+            timezone: server_timezone(),
+        }
+    }
+}
+
+pub fn unix_ms(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+/// Formats milliseconds since the Unix epoch as an RFC 3339 UTC timestamp
+/// with millisecond precision, e.g. `2024-05-01T12:30:00.250Z`.
+pub fn rfc3339(unix_ms: u128) -> String {
+    let secs = (unix_ms / 1000) as i64;
+    let millis = unix_ms % 1000;
+    let days = secs.div_euclid(86_400);
+    let second_of_day = secs.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{millis:03}Z",
+        second_of_day / 3600,
+        second_of_day / 60 % 60,
+        second_of_day % 60
+    )
+}
+
+/// Proleptic Gregorian date for a day count since 1970-01-01, after Howard
+/// Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Reads the host's zone from `TZ`, `/etc/timezone`, or the `/etc/localtime`
+/// symlink, in that order.
+fn server_timezone() -> Option<String> {
+    if let Ok(tz) = std::env::var("TZ") {
+        let tz = tz.trim().trim_start_matches(':');
+        if !tz.is_empty() {
+            return Some(tz.to_string());
+        }
+    }
+    if let Ok(zone) = std::fs::read_to_string("/etc/timezone") {
+        let zone = zone.trim();
+        if !zone.is_empty() {
+            return Some(zone.to_string());
+        }
+    }
+    let target = std::fs::read_link("/etc/localtime").ok()?;
+    let target = target.to_str()?;
+    target
+        .split_once("zoneinfo/")
+        .map(|(_, zone)| zone.to_string())
+}
@@ -0,0 +1,180 @@
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{fs, sync::Mutex};
+use uuid::Uuid;
+
+use crate::{
+    error::AppError,
+    storage::{Storage, ensure_dir},
+};
+
+const MAX_NAME_LEN: usize = 200;
+const MAX_VIDEOS: usize = 1000;
+
+/// A named, ordered list of videos, e.g. the episodes of a series.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Collection {
+    pub id: Uuid,
+    pub name: String,
+    /// Playback order.
+    pub videos: Vec<Uuid>,
+    pub created_at_unix_ms: u64,
+    pub updated_at_unix_ms: u64,
+    /// Account of the caller that created the collection; only its videos
+    /// can be added.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+/// Collections persisted as one JSON file each under `<storage>/collections/`.
+#[derive(Clone)]
+pub struct CollectionStore {
+    storage: Storage,
+    // Serialises read-modify-write of a collection.
+    lock: Arc<Mutex<()>>,
+}
+
+impl CollectionStore {
+    pub fn new(storage: Storage) -> Self {
+        Self {
+            storage,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    fn dir(&self) -> PathBuf {
+        self.storage.root_dir().join("collections")
+    }
+
+    fn path(&self, id: &Uuid) -> PathBuf {
+        self.dir().join(format!("{}.json", id.hyphenated()))
+    }
+
+    pub async fn create(
+        &self,
+        name: String,
+        videos: Vec<Uuid>,
+        owner: String,
+    ) -> Result<Collection, AppError> {
+        let now = unix_ms();
+        let collection = Collection {
+            id: Uuid::new_v4(),
+            name: validate_name(name)?,
+            videos: validate_videos(videos)?,
+            created_at_unix_ms: now,
+            updated_at_unix_ms: now,
+            owner: Some(owner),
+        };
+        let _guard = self.lock.lock().await;
+        ensure_dir(&self.dir()).await?;
+        self.write(&collection).await?;
+        Ok(collection)
+    }
+
+    pub async fn get(&self, id: &Uuid) -> Result<Collection, AppError> {
+        match fs::read(self.path(id)).await {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes).map_err(std::io::Error::from)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                Err(AppError::not_found(format!("collection {id}")))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// All collections, oldest first.
+    pub async fn list(&self) -> Result<Vec<Collection>, AppError> {
+        let mut entries = match fs::read_dir(self.dir()).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut collections = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.path().extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let Ok(bytes) = fs::read(entry.path()).await else {
+                continue;
+            };
+            match serde_json::from_slice::<Collection>(&bytes) {
+                Ok(collection) => collections.push(collection),
+                Err(err) => {
+                    tracing::warn!(path = %entry.path().display(), %err, "skipping unreadable collection");
+                }
+            }
+        }
+        collections.sort_by_key(|collection| collection.created_at_unix_ms);
+        Ok(collections)
+    }
+
+    /// Replaces the name and/or the video order of a collection.
+    pub async fn update(
+        &self,
+        id: &Uuid,
+        name: Option<String>,
+        videos: Option<Vec<Uuid>>,
+    ) -> Result<Collection, AppError> {
+        let _guard = self.lock.lock().await;
+        let mut collection = self.get(id).await?;
+        if let Some(name) = name {
+            collection.name = validate_name(name)?;
+        }
+        if let Some(videos) = videos {
+            collection.videos = validate_videos(videos)?;
+        }
+        collection.updated_at_unix_ms = unix_ms();
+        self.write(&collection).await?;
+        Ok(collection)
+    }
+
+    pub async fn delete(&self, id: &Uuid) -> Result<(), AppError> {
+        let _guard = self.lock.lock().await;
+        match fs::remove_file(self.path(id)).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                Err(AppError::not_found(format!("collection {id}")))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn write(&self, collection: &Collection) -> Result<(), AppError> {
+        let path = self.path(&collection.id);
+        let bytes = serde_json::to_vec_pretty(collection).map_err(std::io::Error::from)?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, bytes).await?;
+        fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+}
+
+fn validate_name(name: String) -> Result<String, AppError> {
+    let name = name.trim().to_string();
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(AppError::validation(format!(
+            "collection name must be 1 to {MAX_NAME_LEN} bytes"
+        )));
+    }
+    Ok(name)
+}
+
+fn validate_videos(videos: Vec<Uuid>) -> Result<Vec<Uuid>, AppError> {
+    if videos.len() > MAX_VIDEOS {
+        return Err(AppError::validation(format!(
+            "a collection holds at most {MAX_VIDEOS} videos"
+        )));
+    }
+    Ok(videos)
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
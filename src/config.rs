@@ -0,0 +1,161 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    env,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use serde::Serialize;
+
+use crate::error::AppError;
+
+/// Points to an optional `KEY=VALUE` file whose entries override the process
+/// environment. The file is re-read on `SIGHUP` and `POST /admin/reload`.
+pub const CONFIG_FILE_ENV: &str = "VIDEO_CONFIG_FILE";
+
+/// Settings captured once at startup; changing them only takes effect after a restart.
+const RESTART_REQUIRED: &[&str] = &[
+    "VIDEO_SERVER_ADDR",
+    "VIDEO_STORAGE_DIR",
+    "VIDEO_HTTP_POOL_MAX_IDLE_PER_HOST",
+    "VIDEO_HTTP_POOL_IDLE_TIMEOUT_SECS",
+    "VIDEO_HTTP_CONNECT_TIMEOUT_SECS",
+    "VIDEO_HTTP_READ_TIMEOUT_SECS",
+    "VIDEO_HTTP_TCP_KEEPALIVE_SECS",
+    "VIDEO_HTTP_VERSION",
+    "VIDEO_HTTP_DNS_CACHE_SECS",
+    "VIDEO_HTTP_IP_FAMILY",
+    "VIDEO_HTTP_RESOLVE",
+    "VIDEO_REDIS_URL",
+    "VIDEO_REDIS_KEY_PREFIX",
+    "VIDEO_REDIS_JOB_TTL_SECS",
+    "VIDEO_BLOCKING_THREADS",
+    "VIDEO_UPLOAD_BODY_LIMIT_BYTES",
+    "VIDEO_JSON_BODY_LIMIT_BYTES",
+    "VIDEO_MAX_UPLOAD_BYTES",
+    "VIDEO_POLICY_WASM",
+    "VIDEO_FAKE_TRANSCODE",
+    "VIDEO_FAKE_TRANSCODE_SECONDS",
+    "VIDEO_SHARDING",
+    "VIDEO_INSTANCE_ID",
+    "VIDEO_SHARD_HEARTBEAT_SECS",
+    "VIDEO_SHARD_NODE_TTL_SECS",
+    "VIDEO_TAG_RETENTION_INTERVAL_SECS",
+    "VIDEO_BANDWIDTH_FLUSH_SECS",
+    "VIDEO_ALERT_CHECK_SECS",
+];
+
+static OVERLAY: RwLock<Option<HashMap<String, String>>> = RwLock::new(None);
+
+/// Looks up a setting, preferring the config file over the process environment.
+pub fn var(key: &str) -> Option<String> {
+    let overlay = OVERLAY.read().unwrap_or_else(|poison| poison.into_inner());
+    overlay
+        .as_ref()
+        .and_then(|values| values.get(key).cloned())
+        .or_else(|| env::var(key).ok())
+}
+
+/// Parses a setting with `FromStr`, ignoring missing or malformed values.
+pub fn parse_var<T: std::str::FromStr>(key: &str) -> Option<T> {
+    var(key).and_then(|value| value.trim().parse::<T>().ok())
+}
+
+/// Re-reads the config file and returns the keys whose effective value changed.
+pub fn reload() -> Result<Vec<String>, AppError> {
+    let fresh = match env::var(CONFIG_FILE_ENV) {
+        Ok(path) => read_config_file(Path::new(&path))?,
+        Err(_) => HashMap::new(),
+    };
+
+    let mut overlay = OVERLAY.write().unwrap_or_else(|poison| poison.into_inner());
+    let previous = overlay.take().unwrap_or_default();
+
+    let keys: BTreeSet<&String> = previous.keys().chain(fresh.keys()).collect();
+    let changed = keys
+        .into_iter()
+        .filter(|key| {
+            let env_value = env::var(key.as_str()).ok();
+            let before = previous.get(*key).cloned().or_else(|| env_value.clone());
+            let after = fresh.get(*key).cloned().or(env_value);
+            before != after
+        })
+        .cloned()
+        .collect();
+
+    *overlay = Some(fresh);
+    Ok(changed)
+}
+
+fn read_config_file(path: &Path) -> Result<HashMap<String, String>, AppError> {
+    let text = std::fs::read_to_string(path).map_err(|err| {
+        AppError::validation(format!(
+            "failed to read config file {}: {err}",
+            path.display()
+        ))
+    })?;
+    Ok(parse_config(&text))
+}
+
+pub fn parse_config(text: &str) -> HashMap<String, String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|inner| inner.strip_suffix('"'))
+                .unwrap_or(value);
+            Some((key.trim().to_string(), value.to_string()))
+        })
+        .filter(|(key, _)| !key.is_empty())
+        .collect()
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ReloadReport {
+    pub applied: Vec<String>,
+    pub requires_restart: Vec<String>,
+}
+
+impl ReloadReport {
+    pub fn from_changed(changed: Vec<String>) -> Self {
+        let (requires_restart, applied) = changed
+            .into_iter()
+            .partition(|key| RESTART_REQUIRED.contains(&key.as_str()));
+        Self {
+            applied,
+            requires_restart,
+        }
+    }
+}
+
+/// Shared handle for settings that are swapped in place on reload.
+#[derive(Debug, Clone)]
+pub struct Reloadable<T> {
+    inner: Arc<RwLock<T>>,
+}
+
+impl<T: Clone> Reloadable<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(value)),
+        }
+    }
+
+    pub fn get(&self) -> T {
+        self.inner
+            .read()
+            .unwrap_or_else(|poison| poison.into_inner())
+            .clone()
+    }
+
+    pub fn set(&self, value: T) {
+        *self
+            .inner
+            .write()
+            .unwrap_or_else(|poison| poison.into_inner()) = value;
+    }
+}
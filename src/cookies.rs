@@ -0,0 +1,167 @@
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use tokio::fs;
+
+use crate::{
+    clock,
+    error::AppError,
+    storage::{Storage, ensure_parent},
+};
+
+const MAX_NAME_LEN: usize = 64;
+const MAX_JAR_BYTES: usize = 1024 * 1024;
+
+/// A stored cookies file, in the Netscape format yt-dlp reads.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CookieJar {
+    pub name: String,
+    /// Cookie lines in the file.
+    pub cookies: usize,
+    pub size_bytes: u64,
+    pub updated_at: String,
+}
+
+fn dir(storage: &Storage) -> PathBuf {
+    storage.root_dir().join("libs").join("cookies")
+}
+
+/// `<storage>/libs/cookies/<name>.txt`.
+pub fn path(storage: &Storage, name: &str) -> PathBuf {
+    dir(storage).join(format!("{name}.txt"))
+}
+
+pub fn validate_name(name: &str) -> Result<(), AppError> {
+    let allowed = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if name.is_empty() || name.len() > MAX_NAME_LEN || !name.chars().all(allowed) {
+        return Err(
+            AppError::validation(format!("invalid cookies file name {name:?}"))
+                .with_code("cookies_name_invalid")
+                .with_param("max_length", MAX_NAME_LEN),
+        );
+    }
+    Ok(())
+}
+
+/// Stores `text` as the cookies file `name`, replacing any earlier one.
+pub async fn save(storage: &Storage, name: &str, text: &str) -> Result<CookieJar, AppError> {
+    validate_name(name)?;
+    if text.len() > MAX_JAR_BYTES {
+        return Err(AppError::too_large("cookies file")
+            .with_code("cookies_too_large")
+            .with_param("max_bytes", MAX_JAR_BYTES));
+    }
+    let cookies = count_cookies(text)?;
+    let path = path(storage, name);
+    ensure_parent(&path).await?;
+    let temp = path.with_extension("txt.tmp");
+    write_private(&temp, text.as_bytes()).await?;
+    fs::rename(&temp, &path).await?;
+    describe(name, &path, cookies).await
+}
+
+/// The stored cookies files, by name.
+pub async fn list(storage: &Storage) -> Result<Vec<CookieJar>, AppError> {
+    let mut entries = match fs::read_dir(dir(storage)).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut jars = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let file_name = entry.file_name();
+        let Some(name) = file_name
+            .to_str()
+            .and_then(|name| name.strip_suffix(".txt"))
+        else {
+            continue;
+        };
+        if validate_name(name).is_err() {
+            continue;
+        }
+        let text = fs::read_to_string(entry.path()).await?;
+        let cookies = count_cookies(&text).unwrap_or(0);
+        jars.push(describe(name, &entry.path(), cookies).await?);
+    }
+    jars.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(jars)
+}
+
+/// Fails with `cookies_not_found` unless the cookies file `name` is stored.
+pub async fn ensure_exists(storage: &Storage, name: &str) -> Result<PathBuf, AppError> {
+    validate_name(name)?;
+    let path = path(storage, name);
+    if fs::try_exists(&path).await? {
+        Ok(path)
+    } else {
+        Err(not_found(name))
+    }
+}
+
+pub async fn remove(storage: &Storage, name: &str) -> Result<(), AppError> {
+    validate_name(name)?;
+    match fs::remove_file(path(storage, name)).await {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Err(not_found(name)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Writes a file only the server's user can read, for credentials.
+pub(crate) async fn write_private(path: &Path, bytes: &[u8]) -> Result<(), AppError> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).await?;
+    file.write_all(bytes).await?;
+    file.flush().await?;
+    Ok(())
+}
+
+fn not_found(name: &str) -> AppError {
+    AppError::not_found(format!("cookies file {name}"))
+        .with_code("cookies_not_found")
+        .with_param("name", name)
+}
+
+/// Counts the cookie lines of a Netscape cookies file: seven tab-separated
+/// fields, with `#HttpOnly_` lines counting as cookies, not comments.
+fn count_cookies(text: &str) -> Result<usize, AppError> {
+    let mut cookies = 0;
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        let cookie = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+        if cookie.trim().is_empty() || cookie.starts_with('#') {
+            continue;
+        }
+        if cookie.split('\t').count() != 7 {
+            return Err(AppError::validation(format!(
+                "line {} is not a Netscape cookie line",
+                index + 1
+            ))
+            .with_code("cookies_invalid")
+            .with_param("line", index + 1));
+        }
+        cookies += 1;
+    }
+    if cookies == 0 {
+        return Err(
+            AppError::validation("the cookies file holds no cookies").with_code("cookies_invalid")
+        );
+    }
+    Ok(cookies)
+}
+
+async fn describe(name: &str, path: &Path, cookies: usize) -> Result<CookieJar, AppError> {
+    let metadata = fs::metadata(path).await?;
+    let modified = metadata.modified().map(clock::unix_ms).unwrap_or_default();
+    Ok(CookieJar {
+        name: name.to_string(),
+        cookies,
+        size_bytes: metadata.len(),
+        updated_at: clock::rfc3339(modified),
+    })
+}
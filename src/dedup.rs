@@ -0,0 +1,60 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    error::AppError,
+    storage::{Storage, ensure_parent},
+};
+
+/// Index of the videos published from sources with this SHA-256, mapping
+/// each caller key to its video. Matches are kept per key, so an upload never
+/// reveals another tenant's video.
+fn index_path(storage: &Storage, sha256: &str) -> PathBuf {
+    storage
+        .root_dir()
+        .join("hashes")
+        .join(format!("{sha256}.json"))
+}
+
+async fn load(storage: &Storage, sha256: &str) -> Result<BTreeMap<String, Uuid>, AppError> {
+    match fs::read(index_path(storage, sha256)).await {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes).map_err(std::io::Error::from)?),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The video `account` published from a source with this hash, if its
+/// download still exists.
+pub async fn find(
+    storage: &Storage,
+    account: &str,
+    sha256: &str,
+) -> Result<Option<Uuid>, AppError> {
+    Ok(load(storage, sha256)
+        .await?
+        .get(account)
+        .copied()
+        .filter(|id| storage.download_path(id).is_file()))
+}
+
+/// Records `id` as the video `account` published from this source.
+pub async fn record(
+    storage: &Storage,
+    account: &str,
+    sha256: &str,
+    id: Uuid,
+) -> Result<(), AppError> {
+    let _guard = storage.locks().acquire(&format!("hash.{sha256}")).await?;
+    let mut index = load(storage, sha256).await?;
+    index.insert(account.to_string(), id);
+    let path = index_path(storage, sha256);
+    ensure_parent(&path).await?;
+    let bytes = serde_json::to_vec_pretty(&index).map_err(std::io::Error::from)?;
+    let temp = path.with_extension("json.tmp");
+    fs::write(&temp, bytes).await?;
+    fs::rename(&temp, &path).await?;
+    Ok(())
+}
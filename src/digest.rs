@@ -0,0 +1,262 @@
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWrite;
+
+use crate::blocking;
+
+/// Bytes kept from the start of a source for container sniffing.
+const HEAD_LEN: usize = 512;
+
+/// Size, checksum and container of an ingested source, computed while it is
+/// written to the incoming area so later checks need not read it again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceDigest {
+    pub bytes: u64,
+    /// Lowercase hex SHA-256 of the whole file.
+    pub sha256: String,
+    /// Container recognised from the leading bytes; `None` when unknown.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<Container>,
+}
+
+/// File formats recognised by their leading bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Container {
+    /// Matroska or WebM.
+    Matroska,
+    /// MP4, MOV and other ISO base media files.
+    Mp4,
+    Avi,
+    MpegTs,
+    MpegPs,
+    Ogg,
+    Flv,
+    Wav,
+    Mp3,
+    Flac,
+    Zip,
+    Jpeg,
+    Png,
+    Gif,
+    Webp,
+}
+
+impl Container {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Matroska => "matroska",
+            Self::Mp4 => "mp4",
+            Self::Avi => "avi",
+            Self::MpegTs => "mpeg_ts",
+            Self::MpegPs => "mpeg_ps",
+            Self::Ogg => "ogg",
+            Self::Flv => "flv",
+            Self::Wav => "wav",
+            Self::Mp3 => "mp3",
+            Self::Flac => "flac",
+            Self::Zip => "zip",
+            Self::Jpeg => "jpeg",
+            Self::Png => "png",
+            Self::Gif => "gif",
+            Self::Webp => "webp",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Matroska => "video/x-matroska",
+            Self::Mp4 => "video/mp4",
+            Self::Avi => "video/x-msvideo",
+            Self::MpegTs => "video/mp2t",
+            Self::MpegPs => "video/mpeg",
+            Self::Ogg => "application/ogg",
+            Self::Flv => "video/x-flv",
+            Self::Wav => "audio/wav",
+            Self::Mp3 => "audio/mpeg",
+            Self::Flac => "audio/flac",
+            Self::Zip => "application/zip",
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+            Self::Gif => "image/gif",
+            Self::Webp => "image/webp",
+        }
+    }
+
+    /// The usual file extension, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Matroska => "mkv",
+            Self::Mp4 => "mp4",
+            Self::Avi => "avi",
+            Self::MpegTs => "ts",
+            Self::MpegPs => "mpg",
+            Self::Ogg => "ogg",
+            Self::Flv => "flv",
+            Self::Wav => "wav",
+            Self::Mp3 => "mp3",
+            Self::Flac => "flac",
+            Self::Zip => "zip",
+            Self::Jpeg => "jpg",
+            Self::Png => "png",
+            Self::Gif => "gif",
+            Self::Webp => "webp",
+        }
+    }
+
+    /// Recognises a container from the first bytes of a file.
+    pub fn sniff(head: &[u8]) -> Option<Self> {
+        let at =
+            |offset: usize, magic: &[u8]| head.get(offset..offset + magic.len()) == Some(magic);
+        if at(0, b"RIFF") {
+            return match head.get(8..12)? {
+                b"AVI " => Some(Self::Avi),
+                b"WAVE" => Some(Self::Wav),
+                b"WEBP" => Some(Self::Webp),
+                _ => None,
+            };
+        }
+        if at(0, &[0x1a, 0x45, 0xdf, 0xa3]) {
+            Some(Self::Matroska)
+        } else if at(4, b"ftyp") || at(4, b"moov") || at(4, b"mdat") || at(4, b"wide") {
+            Some(Self::Mp4)
+        } else if head.first() == Some(&0x47) && head.get(188) == Some(&0x47) {
+            Some(Self::MpegTs)
+        } else if at(0, &[0x00, 0x00, 0x01, 0xba]) {
+            Some(Self::MpegPs)
+        } else if at(0, b"OggS") {
+            Some(Self::Ogg)
+        } else if at(0, b"FLV") {
+            Some(Self::Flv)
+        } else if at(0, b"fLaC") {
+            Some(Self::Flac)
+        } else if at(0, b"ID3") || (head.len() >= 2 && head[0] == 0xff && head[1] & 0xe0 == 0xe0) {
+            Some(Self::Mp3)
+        } else if at(0, b"PK\x03\x04") {
+            Some(Self::Zip)
+        } else if at(0, &[0xff, 0xd8, 0xff]) {
+            Some(Self::Jpeg)
+        } else if at(0, b"\x89PNG") {
+            Some(Self::Png)
+        } else if at(0, b"GIF8") {
+            Some(Self::Gif)
+        } else {
+            None
+        }
+    }
+}
+
+/// Passes writes through to `inner` while counting, hashing and keeping the
+/// head of everything written. Works as both a blocking and an async writer.
+pub struct DigestWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    head: Vec<u8>,
+    bytes: u64,
+}
+
+impl<W> DigestWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            head: Vec::with_capacity(HEAD_LEN),
+            bytes: 0,
+        }
+    }
+
+    fn observe(&mut self, written: &[u8]) {
+        self.hasher.update(written);
+        self.bytes += written.len() as u64;
+        if self.head.len() < HEAD_LEN {
+            let take = written.len().min(HEAD_LEN - self.head.len());
+            self.head.extend_from_slice(&written[..take]);
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// The digest of everything written so far.
+    pub fn digest(&self) -> SourceDigest {
+        SourceDigest {
+            bytes: self.bytes,
+            sha256: hex::encode(self.hasher.clone().finalize()),
+            container: Container::sniff(&self.head),
+        }
+    }
+
+    pub fn into_inner(self) -> (W, SourceDigest) {
+        let digest = self.digest();
+        (self.inner, digest)
+    }
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.observe(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for DigestWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            this.observe(&buf[..written]);
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Reads `path` once on the blocking pool, for files written by external
+/// tools or in parallel ranges.
+pub async fn digest_file(path: &Path) -> io::Result<SourceDigest> {
+    let path = path.to_path_buf();
+    blocking::run(move || {
+        let mut writer = DigestWriter::new(io::sink());
+        io::copy(&mut std::fs::File::open(path)?, &mut writer)?;
+        Ok(writer.digest())
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+/// Copies `from` to `to` on the blocking pool, digesting it on the way.
+pub async fn copy_file(from: &Path, to: &Path) -> io::Result<SourceDigest> {
+    let (from, to): (PathBuf, PathBuf) = (from.to_path_buf(), to.to_path_buf());
+    blocking::run(move || {
+        let mut writer = DigestWriter::new(std::fs::File::create(to)?);
+        io::copy(&mut std::fs::File::open(from)?, &mut writer)?;
+        writer.flush()?;
+        Ok(writer.digest())
+    })
+    .await
+    .map_err(io::Error::other)?
+}
@@ -1,7 +1,11 @@
-use std::fmt::Display;
+use std::{collections::BTreeMap, fmt::Display};
 
-use axum::{Json, http::StatusCode, response::IntoResponse};
-use serde::Serialize;
+use axum::{
+    Json,
+    http::{HeaderValue, StatusCode, header},
+    response::IntoResponse,
+};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -10,44 +14,147 @@ pub enum AppError {
     Validation(String),
     #[error("resource not found: {0}")]
     NotFound(String),
+    #[error("authentication required: {0}")]
+    Unauthorized(String),
+    #[error("access denied: {0}")]
+    Forbidden(String),
+    #[error("rate limited: {0}")]
+    RateLimited(String),
+    #[error("service overloaded: {message}")]
+    Overloaded {
+        message: String,
+        retry_after_secs: u64,
+    },
     #[error("transcoding failed: {0}")]
     Transcode(String),
     #[error("external dependency missing: {0}")]
     Dependency(String),
+    #[error("cancelled: {0}")]
+    Cancelled(String),
+    #[error("conflict: {0}")]
+    Conflict(String),
+    #[error("too large: {0}")]
+    TooLarge(String),
     #[error(transparent)]
     Multipart(#[from] axum::extract::multipart::MultipartError),
     #[error(transparent)]
     Io(#[from] std::io::Error),
     #[error(transparent)]
     Http(#[from] reqwest::Error),
+    /// Another error tagged with a specific code and parameters, see
+    /// [`AppError::with_code`].
+    #[error("{inner}")]
+    Coded {
+        inner: Box<AppError>,
+        code: &'static str,
+        params: BTreeMap<&'static str, serde_json::Value>,
+    },
 }
 
+/// Why a job failed, in terms a client can act on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    /// A remote source or service could not be reached or timed out.
+    Network,
+    /// The source or request is unusable: unreadable media, bad input, a
+    /// 4xx from the source host.
+    SourceInvalid,
+    /// ffmpeg, aria2, yt-dlp or another required tool is missing.
+    EncoderMissing,
+    /// The storage volume ran out of space.
+    DiskFull,
+    /// The host shed load or a limit was hit.
+    Overloaded,
+    Cancelled,
+    Internal,
+}
+
+impl ErrorClass {
+    /// Whether running the job again unchanged may succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Network | Self::DiskFull | Self::Overloaded)
+    }
+}
+
+/// Error response body. `error` is a human-readable English message; `code`
+/// and `params` let clients render their own, localized one.
 #[derive(Debug, Serialize)]
 struct ErrorBody {
     error: String,
+    code: &'static str,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    params: BTreeMap<&'static str, serde_json::Value>,
+}
+
+impl AppError {
+    /// The body an HTTP client would receive for this error, for reporting
+    /// it elsewhere, such as the CLI's JSON output.
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!(ErrorBody {
+            error: self.to_string(),
+            code: self.code(),
+            params: self.params(),
+        })
+    }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
-        let status = match &self {
+        let status = match self.root() {
             AppError::Validation(_) => StatusCode::BAD_REQUEST,
             AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Overloaded { .. } => StatusCode::SERVICE_UNAVAILABLE,
             AppError::Transcode(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::Dependency(_) => StatusCode::SERVICE_UNAVAILABLE,
-            AppError::Multipart(_) | AppError::Io(_) | AppError::Http(_) => {
-                StatusCode::INTERNAL_SERVER_ERROR
-            }
+            AppError::Cancelled(_) | AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::Multipart(err) => err.status(),
+            AppError::Io(_) | AppError::Http(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Coded { .. } => unreachable!("root() unwraps coded errors"),
         };
 
         tracing::error!(?status, error = %self);
 
-        (
+        let mut response = (
             status,
             Json(ErrorBody {
                 error: self.to_string(),
+                code: self.code(),
+                params: self.params(),
             }),
         )
-            .into_response()
+            .into_response();
+        match self.root() {
+            AppError::Overloaded {
+                retry_after_secs, ..
+            } => {
+                response
+                    .headers_mut()
+                    .insert(header::RETRY_AFTER, HeaderValue::from(*retry_after_secs));
+            }
+            AppError::RateLimited(_) => {
+                if let Some(secs) = self
+                    .params()
+                    .get("retry_after_secs")
+                    .and_then(|v| v.as_u64())
+                {
+                    response
+                        .headers_mut()
+                        .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+                }
+            }
+            AppError::Unauthorized(_) => {
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+            _ => {}
+        }
+        response
     }
 }
 
@@ -56,6 +163,25 @@ impl AppError {
         Self::NotFound(resource.to_string())
     }
 
+    pub fn unauthorized(message: impl Display) -> Self {
+        Self::Unauthorized(message.to_string())
+    }
+
+    pub fn forbidden(message: impl Display) -> Self {
+        Self::Forbidden(message.to_string())
+    }
+
+    pub fn rate_limited(message: impl Display) -> Self {
+        Self::RateLimited(message.to_string())
+    }
+
+    pub fn overloaded(message: impl Display, retry_after_secs: u64) -> Self {
+        Self::Overloaded {
+            message: message.to_string(),
+            retry_after_secs,
+        }
+    }
+
     pub fn validation(message: impl Display) -> Self {
         Self::Validation(message.to_string())
     }
@@ -67,4 +193,160 @@ impl AppError {
     pub fn transcode(message: impl Display) -> Self {
         Self::Transcode(message.to_string())
     }
+
+    pub fn cancelled(message: impl Display) -> Self {
+        Self::Cancelled(message.to_string())
+    }
+
+    /// The request does not fit the current state of the resource.
+    pub fn conflict(message: impl Display) -> Self {
+        Self::Conflict(message.to_string())
+    }
+
+    /// The request body or the file it carries exceeds a configured size.
+    pub fn too_large(message: impl Display) -> Self {
+        Self::TooLarge(message.to_string())
+    }
+
+    /// Tags the error with a stable, machine-readable `code`, replacing the
+    /// default one for its kind. Status and classification are unchanged.
+    pub fn with_code(self, code: &'static str) -> Self {
+        match self {
+            AppError::Coded { inner, params, .. } => AppError::Coded {
+                inner,
+                code,
+                params,
+            },
+            other => AppError::Coded {
+                inner: Box::new(other),
+                code,
+                params: BTreeMap::new(),
+            },
+        }
+    }
+
+    /// Adds a parameter clients can interpolate into a localized message.
+    pub fn with_param(self, key: &'static str, value: impl Into<serde_json::Value>) -> Self {
+        match self {
+            AppError::Coded {
+                inner,
+                code,
+                mut params,
+            } => {
+                params.insert(key, value.into());
+                AppError::Coded {
+                    inner,
+                    code,
+                    params,
+                }
+            }
+            other => {
+                let code = other.code();
+                other.with_code(code).with_param(key, value)
+            }
+        }
+    }
+
+    /// Stable identifier for the error, e.g. `not_found` or `range_invalid`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Validation(_) => "validation_failed",
+            AppError::NotFound(_) => "not_found",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::RateLimited(_) => "rate_limited",
+            AppError::Overloaded { .. } => "overloaded",
+            AppError::Transcode(_) => "transcode_failed",
+            AppError::Dependency(_) => "dependency_unavailable",
+            AppError::Cancelled(_) => "cancelled",
+            AppError::Conflict(_) => "conflict",
+            AppError::TooLarge(_) => "too_large",
+            AppError::Multipart(err) if err.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                "body_too_large"
+            }
+            AppError::Multipart(_) => "multipart_invalid",
+            AppError::Io(_) => "io_error",
+            AppError::Http(_) => "upstream_http_error",
+            AppError::Coded { code, .. } => code,
+        }
+    }
+
+    /// Values for the message behind `code`. `overloaded` always carries
+    /// `retry_after_secs`; `rate_limited` does when a retry time is known,
+    /// and the response then carries a matching `Retry-After`.
+    pub fn params(&self) -> BTreeMap<&'static str, serde_json::Value> {
+        let mut params = match self {
+            AppError::Coded { params, .. } => params.clone(),
+            _ => BTreeMap::new(),
+        };
+        match self.root() {
+            AppError::Overloaded {
+                retry_after_secs, ..
+            } => {
+                params.insert("retry_after_secs", (*retry_after_secs).into());
+            }
+            AppError::Http(err) => {
+                if let Some(status) = err.status() {
+                    params.insert("status", status.as_u16().into());
+                }
+            }
+            _ => {}
+        }
+        params
+    }
+
+    /// The error with any code and parameters removed.
+    pub fn root(&self) -> &AppError {
+        match self {
+            AppError::Coded { inner, .. } => inner.root(),
+            other => other,
+        }
+    }
+
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            AppError::Validation(_)
+            | AppError::NotFound(_)
+            | AppError::Unauthorized(_)
+            | AppError::Forbidden(_)
+            | AppError::Conflict(_)
+            | AppError::TooLarge(_)
+            | AppError::Multipart(_)
+            | AppError::Transcode(_) => ErrorClass::SourceInvalid,
+            AppError::RateLimited(_) | AppError::Overloaded { .. } => ErrorClass::Overloaded,
+            AppError::Dependency(_) => ErrorClass::EncoderMissing,
+            AppError::Cancelled(_) => ErrorClass::Cancelled,
+            AppError::Io(err) => io_error_class(err),
+            AppError::Http(err) => match err.status() {
+                Some(status)
+                    if status.is_client_error()
+                        && status != StatusCode::REQUEST_TIMEOUT
+                        && status != StatusCode::TOO_MANY_REQUESTS =>
+                {
+                    ErrorClass::SourceInvalid
+                }
+                _ => ErrorClass::Network,
+            },
+            AppError::Coded { inner, .. } => inner.class(),
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.class().is_retryable()
+    }
+}
+
+fn io_error_class(err: &std::io::Error) -> ErrorClass {
+    use std::io::ErrorKind;
+    match err.kind() {
+        ErrorKind::StorageFull | ErrorKind::QuotaExceeded => ErrorClass::DiskFull,
+        ErrorKind::ConnectionRefused
+        | ErrorKind::ConnectionReset
+        | ErrorKind::ConnectionAborted
+        | ErrorKind::NotConnected
+        | ErrorKind::BrokenPipe
+        | ErrorKind::TimedOut
+        | ErrorKind::UnexpectedEof => ErrorClass::Network,
+        _ => ErrorClass::Internal,
+    }
 }
@@ -0,0 +1,362 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures_util::StreamExt;
+use reqwest::{Client, StatusCode, Url};
+use tokio::{fs::File, io::AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::{
+    config,
+    error::AppError,
+    http_client,
+    locks::LockManager,
+    rate_limit::{Bucket, RateLimit},
+    replication::{self, parse_peer},
+    storage::{Storage, ensure_parent},
+};
+
+/// Marks requests between federated instances, which never consult their
+/// own peers, so two instances pointing at each other cannot loop. With JWT
+/// auth on, it only counts on requests carrying an `admin` token.
+pub const FEDERATED_HEADER: &str = "x-vrs-federated";
+
+const DEFAULT_LOOKUP_TTL_SECS: u64 = 60;
+const DEFAULT_PROBE_LIMIT: RateLimit = RateLimit {
+    burst: 10,
+    period: Duration::from_secs(1),
+};
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What a miss for a video stored on a peer leads to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FederationMode {
+    /// Redirect the client to the peer.
+    #[default]
+    Redirect,
+    /// Redirect the client, and copy the video from the peer so later
+    /// requests are served locally.
+    Cache,
+}
+
+/// Upstream instances asked for videos this one does not have.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FederationConfig {
+    /// Base URLs, asked in order.
+    pub peers: Vec<Url>,
+    pub mode: FederationMode,
+    /// Bearer token sent with probes, and to the peers'
+    /// `GET /admin/export/{id}` in cache mode.
+    pub token: Option<String>,
+    /// How long the peer found for a video, or that none has it, is
+    /// remembered.
+    pub lookup_ttl: Duration,
+    /// Lookups that may probe the peers; past it, misses are answered
+    /// without asking them.
+    pub probe_limit: RateLimit,
+}
+
+impl Default for FederationConfig {
+    fn default() -> Self {
+        Self {
+            peers: Vec::new(),
+            mode: FederationMode::default(),
+            token: None,
+            lookup_ttl: Duration::from_secs(DEFAULT_LOOKUP_TTL_SECS),
+            probe_limit: DEFAULT_PROBE_LIMIT,
+        }
+    }
+}
+
+impl FederationConfig {
+    /// From `VIDEO_FEDERATION_PEERS` (comma-separated),
+    /// `VIDEO_FEDERATION_MODE` (`redirect` or `cache`),
+    /// `VIDEO_FEDERATION_TOKEN`, `VIDEO_FEDERATION_LOOKUP_TTL_SECS` and
+    /// `VIDEO_FEDERATION_PROBE_LIMIT`.
+    pub fn from_env() -> Self {
+        let peers = config::var("VIDEO_FEDERATION_PEERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|peer| !peer.is_empty())
+            .filter_map(|peer| {
+                parse_peer(peer)
+                    .inspect_err(
+                        |err| tracing::warn!(peer, error = %err, "ignoring federation peer"),
+                    )
+                    .ok()
+            })
+            .collect();
+        let mode = match config::var("VIDEO_FEDERATION_MODE")
+            .as_deref()
+            .map(str::trim)
+        {
+            Some("cache") => FederationMode::Cache,
+            None | Some("" | "redirect") => FederationMode::Redirect,
+            Some(other) => {
+                tracing::warn!(mode = other, "unknown VIDEO_FEDERATION_MODE, redirecting");
+                FederationMode::Redirect
+            }
+        };
+        let probe_limit = match config::var("VIDEO_FEDERATION_PROBE_LIMIT")
+            .filter(|value| !value.trim().is_empty())
+        {
+            Some(value) => RateLimit::parse(&value).unwrap_or_else(|| {
+                tracing::warn!(
+                    value,
+                    "ignoring invalid VIDEO_FEDERATION_PROBE_LIMIT; use e.g. 10/s"
+                );
+                DEFAULT_PROBE_LIMIT
+            }),
+            None => DEFAULT_PROBE_LIMIT,
+        };
+        Self {
+            peers,
+            mode,
+            token: config::var("VIDEO_FEDERATION_TOKEN").filter(|token| !token.trim().is_empty()),
+            lookup_ttl: Duration::from_secs(
+                config::parse_var("VIDEO_FEDERATION_LOOKUP_TTL_SECS")
+                    .unwrap_or(DEFAULT_LOOKUP_TTL_SECS),
+            ),
+            probe_limit,
+        }
+    }
+}
+
+/// Finds videos missing here on upstream peers, and in cache mode copies
+/// them over, one copy per video at a time.
+#[derive(Clone)]
+pub struct Federation {
+    inner: Arc<FederationInner>,
+}
+
+struct FederationInner {
+    storage: Storage,
+    client: Client,
+    /// Fixed peers; otherwise they are re-read from config on each miss.
+    config: Option<FederationConfig>,
+    /// The peer holding each recently requested video, or `None`.
+    lookups: Mutex<HashMap<Uuid, (Option<Url>, Instant)>>,
+    /// Probes left under `probe_limit`, shared by all videos.
+    probes: Mutex<Option<Bucket>>,
+    copying: Mutex<HashSet<Uuid>>,
+}
+
+impl Federation {
+    pub fn new(storage: Storage, client: Client) -> Self {
+        Self::build(storage, client, None)
+    }
+
+    /// Uses `config` instead of reading peers from the environment.
+    pub fn with_config(storage: Storage, client: Client, config: FederationConfig) -> Self {
+        Self::build(storage, client, Some(config))
+    }
+
+    fn build(storage: Storage, client: Client, config: Option<FederationConfig>) -> Self {
+        Self {
+            inner: Arc::new(FederationInner {
+                storage,
+                client,
+                config,
+                lookups: Mutex::default(),
+                probes: Mutex::default(),
+                copying: Mutex::default(),
+            }),
+        }
+    }
+
+    pub fn config(&self) -> FederationConfig {
+        self.inner
+            .config
+            .clone()
+            .unwrap_or_else(FederationConfig::from_env)
+    }
+
+    /// Where the client should fetch `path_and_query` of a video this
+    /// instance does not have: the same path on the first peer that has
+    /// the video. A peer has it when a `HEAD` of the path is answered with
+    /// anything but `404` or a server error. In cache mode, finding the
+    /// peer also starts copying the video from it. Lookups past the probe
+    /// limit find nothing and are not remembered, so a flood of unknown ids
+    /// cannot be turned into a flood of probes.
+    pub async fn locate(&self, video_id: Uuid, path_and_query: &str) -> Option<Url> {
+        let config = self.config();
+        if config.peers.is_empty() {
+            return None;
+        }
+        let remembered = self
+            .lookups()
+            .get(&video_id)
+            .filter(|(_, at)| at.elapsed() < config.lookup_ttl)
+            .map(|(peer, _)| peer.clone());
+        let peer = match remembered {
+            Some(peer) => peer,
+            None => {
+                if !self.admit_probe(config.probe_limit) {
+                    tracing::debug!(%video_id, "federation probe limit reached");
+                    return None;
+                }
+                let peer = self
+                    .probe(&config.peers, config.token.as_deref(), path_and_query)
+                    .await;
+                let mut lookups = self.lookups();
+                lookups.retain(|_, (_, at)| at.elapsed() < config.lookup_ttl);
+                lookups.insert(video_id, (peer.clone(), Instant::now()));
+                drop(lookups);
+                if let (Some(peer), FederationMode::Cache) = (&peer, config.mode) {
+                    self.copy(video_id, peer.clone(), config.token);
+                }
+                peer
+            }
+        }?;
+        peer.join(path_and_query.trim_start_matches('/')).ok()
+    }
+
+    fn admit_probe(&self, limit: RateLimit) -> bool {
+        let now = Instant::now();
+        let mut probes = self
+            .inner
+            .probes
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner());
+        let mut bucket = probes
+            .unwrap_or(Bucket::full(limit, now))
+            .refilled(limit, now);
+        let admitted = bucket.take();
+        *probes = Some(bucket);
+        admitted
+    }
+
+    async fn probe(&self, peers: &[Url], token: Option<&str>, path_and_query: &str) -> Option<Url> {
+        for peer in peers {
+            let Ok(url) = peer.join(path_and_query.trim_start_matches('/')) else {
+                continue;
+            };
+            let mut request = self
+                .inner
+                .client
+                .head(url)
+                .header(FEDERATED_HEADER, "1")
+                .timeout(PROBE_TIMEOUT);
+            if let Some(token) = token {
+                request = request.bearer_auth(token);
+            }
+            let response = request.send().await;
+            match response {
+                Ok(response)
+                    if response.status() != StatusCode::NOT_FOUND
+                        && !response.status().is_server_error() =>
+                {
+                    return Some(peer.clone());
+                }
+                Ok(_) => {}
+                Err(err) => tracing::debug!(peer = %peer, error = %err, "federation probe failed"),
+            }
+        }
+        None
+    }
+
+    /// Copies the video from `peer` in the background unless a copy is
+    /// already running.
+    fn copy(&self, video_id: Uuid, peer: Url, token: Option<String>) {
+        if !self.copying().insert(video_id) {
+            return;
+        }
+        let federation = self.clone();
+        tokio::spawn(async move {
+            let storage = &federation.inner.storage;
+            match fetch(
+                storage,
+                &federation.inner.client,
+                token.as_deref(),
+                video_id,
+                &peer,
+            )
+            .await
+            {
+                Ok(()) => {
+                    tracing::info!(%video_id, peer = %peer, "cached video from federation peer");
+                }
+                Err(err) => {
+                    tracing::warn!(%video_id, peer = %peer, error = %err, "failed to cache video from federation peer");
+                }
+            }
+            federation.copying().remove(&video_id);
+        });
+    }
+
+    fn lookups(&self) -> std::sync::MutexGuard<'_, HashMap<Uuid, (Option<Url>, Instant)>> {
+        self.inner
+            .lookups
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+    }
+
+    fn copying(&self) -> std::sync::MutexGuard<'_, HashSet<Uuid>> {
+        self.inner
+            .copying
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+    }
+}
+
+/// Downloads the peer's export of the video and imports it as a replica.
+async fn fetch(
+    storage: &Storage,
+    client: &Client,
+    token: Option<&str>,
+    video_id: Uuid,
+    peer: &Url,
+) -> Result<(), AppError> {
+    let _guard = storage
+        .locks()
+        .acquire(&LockManager::video_key(&video_id, "import"))
+        .await?;
+    if storage.video_dir(&video_id).exists() {
+        return Ok(());
+    }
+    let url = peer
+        .join(&format!("admin/export/{video_id}"))
+        .map_err(|err| AppError::validation(format!("invalid peer URL {peer}: {err}")))?;
+    let mut request = client
+        .get(url)
+        .header(FEDERATED_HEADER, "1")
+        .timeout(http_client::download_timeout());
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    let response = request.send().await?.error_for_status()?;
+
+    let archive = storage.import_dir(&video_id).with_extension("zip");
+    ensure_parent(&archive).await?;
+    let max_bytes = replication::max_import_bytes();
+    let received = async {
+        let mut file = File::create(&archive).await?;
+        let mut chunks = response.bytes_stream();
+        let mut received = 0u64;
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk?;
+            received += chunk.len() as u64;
+            if received > max_bytes {
+                return Err(replication::replica_too_large(max_bytes));
+            }
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        Ok(())
+    }
+    .await;
+    let result = match received {
+        Ok(()) => replication::import_archive(storage, video_id, &archive, max_bytes).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = tokio::fs::remove_file(&archive).await
+        && err.kind() != std::io::ErrorKind::NotFound
+    {
+        tracing::warn!(path = %archive.display(), error = %err, "failed to remove fetched replica");
+    }
+    result
+}
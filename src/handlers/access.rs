@@ -0,0 +1,116 @@
+use std::net::IpAddr;
+
+use axum::{
+    Json,
+    extract::{Path as AxumPath, State},
+    http::{HeaderMap, StatusCode},
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    blocking,
+    error::AppError,
+    metadata::{self, VideoMetadata},
+    password::{PASSWORD_HEADER, hash_password},
+    rate_limit::ClientAddr,
+    state::AppState,
+    video_access::AccessSigner,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct SetPasswordRequest {
+    pub password: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccessTokenResponse {
+    pub access_token: String,
+    /// Unix timestamp the token expires at.
+    pub expires_at: u64,
+}
+
+/// Sets or replaces the password of a video. Replacing an existing password
+/// requires the current one in `X-Video-Password`.
+pub async fn set_video_password(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+    Json(request): Json<SetPasswordRequest>,
+) -> Result<StatusCode, AppError> {
+    let (video_id, mut meta) = authorize_owner(&state, &id, &headers, client).await?;
+    let password = request.password;
+    let hash = blocking::run(move || hash_password(&password))
+        .await
+        .map_err(|err| AppError::validation(format!("failed to hash password: {err}")))??;
+    meta.password_hash = Some(hash);
+    metadata::save(&state.storage, &video_id, &meta).await?;
+    tracing::info!(%video_id, "video password set");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Exchanges the password in `X-Video-Password` for an access token that
+/// players pass as `?access=` instead, so the password never ends up in a URL.
+pub async fn create_access_token(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+) -> Result<Json<AccessTokenResponse>, AppError> {
+    let (video_id, meta) = authorize_owner(&state, &id, &headers, client).await?;
+    let hash = meta.password_hash.ok_or_else(|| {
+        AppError::validation(format!("video {video_id} has no password"))
+            .with_code("video_not_protected")
+            .with_param("id", video_id.to_string())
+    })?;
+    let signer = AccessSigner::from_env();
+    let (access_token, expires_at) = signer.issue_for(&video_id, &hash, signer.ttl());
+    Ok(Json(AccessTokenResponse {
+        access_token,
+        expires_at,
+    }))
+}
+
+/// Removes the password of a video, given the current one in `X-Video-Password`.
+pub async fn remove_video_password(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+) -> Result<StatusCode, AppError> {
+    let (video_id, mut meta) = authorize_owner(&state, &id, &headers, client).await?;
+    if meta.password_hash.take().is_some() {
+        metadata::save(&state.storage, &video_id, &meta).await?;
+        tracing::info!(%video_id, "video password removed");
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Resolves a video that exists, checking its current password if it has one.
+pub(super) async fn authorize_owner(
+    state: &AppState,
+    id: &str,
+    headers: &HeaderMap,
+    client: Option<IpAddr>,
+) -> Result<(Uuid, VideoMetadata), AppError> {
+    let video_id =
+        Uuid::parse_str(id).map_err(|_| AppError::validation("invalid video identifier"))?;
+    if !tokio::fs::try_exists(state.storage.video_dir(&video_id)).await?
+        && state.jobs.status(&video_id).await?.is_none()
+    {
+        return Err(AppError::not_found(format!("video {video_id} not found")));
+    }
+
+    let meta = metadata::load(&state.storage, &video_id).await?;
+    if let Some(hash) = &meta.password_hash {
+        let current = headers
+            .get(PASSWORD_HEADER)
+            .and_then(|value| value.to_str().ok());
+        state
+            .password_attempts
+            .verify(&video_id, client, hash, current)
+            .await?;
+    }
+    Ok((video_id, meta))
+}
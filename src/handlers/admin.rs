@@ -0,0 +1,296 @@
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    alerts::AlertReport,
+    bandwidth::{self, BandwidthUsage},
+    blocking::{self, BlockingStats},
+    breaker::HostReport,
+    cleanup::{self, DiskStatus},
+    config::ReloadReport,
+    cookies::{self, CookieJar},
+    error::AppError,
+    jobs::JobStage,
+    metrics,
+    shaping::TenantIngest,
+    shedding::LoadReport,
+    state::AppState,
+    transcode::{EncoderCapabilities, clear_encoder_failures, encoder_capabilities},
+    workspace::{self, TmpClearReport, TmpEntry, TmpListing},
+};
+
+const STAGE_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Serialize)]
+pub struct AdminOverview {
+    pub version: VersionInfo,
+    pub queue_depth: usize,
+    pub active_jobs: BTreeMap<&'static str, usize>,
+    pub stage_durations: BTreeMap<&'static str, StageDurationSummary>,
+    pub disk: Option<DiskOverview>,
+    pub load: LoadReport,
+    pub blocking: BlockingStats,
+    pub source_hosts: Vec<HostReport>,
+    /// Upload and download traffic per caller key against its budget.
+    pub ingest: Vec<TenantIngest>,
+    pub encoders: EncoderCapabilities,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    pub name: &'static str,
+    pub version: &'static str,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct StageDurationSummary {
+    pub samples: usize,
+    pub average_seconds: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiskOverview {
+    #[serde(flatten)]
+    pub status: DiskStatus,
+    pub free_ratio: f32,
+    pub below_threshold: bool,
+}
+
+pub async fn admin_overview(
+    State(state): State<AppState>,
+) -> Result<Json<AdminOverview>, AppError> {
+    let statuses = state.jobs.list().await?;
+
+    let queue_depth = statuses
+        .iter()
+        .filter(|status| status.stage == JobStage::Queued)
+        .count();
+
+    let mut active_jobs = BTreeMap::new();
+    for status in statuses.iter().filter(|status| !status.stage.is_terminal()) {
+        *active_jobs.entry(status.stage.as_str()).or_insert(0) += 1;
+    }
+
+    let since = SystemTime::now()
+        .checked_sub(STAGE_WINDOW)
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    let mut stage_durations: BTreeMap<&'static str, StageDurationSummary> = BTreeMap::new();
+    for timing in state.jobs.stage_timings(since).await? {
+        let summary = stage_durations.entry(timing.stage.as_str()).or_default();
+        summary.samples += 1;
+        summary.average_seconds +=
+            (timing.duration_seconds - summary.average_seconds) / summary.samples as f64;
+    }
+
+    let disk = match cleanup::storage_disk_status(&state.storage).await {
+        Ok(status) => Some(DiskOverview {
+            status,
+            free_ratio: status.free_ratio(),
+            below_threshold: status.below_threshold(&state.cleanup.get()),
+        }),
+        Err(err) => {
+            tracing::warn!(error = %err, "failed to read disk status for overview");
+            None
+        }
+    };
+
+    Ok(Json(AdminOverview {
+        version: VersionInfo {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+        },
+        queue_depth,
+        active_jobs,
+        stage_durations,
+        disk,
+        load: state.load.report(&state.storage).await,
+        blocking: blocking::stats(),
+        source_hosts: state.breaker.report(),
+        ingest: state.shaper.report(),
+        encoders: encoder_capabilities(&state.process_runner).await,
+    }))
+}
+
+/// Raised alerts and the latest raised and resolved events. Disk space is
+/// sampled again for the answer.
+pub async fn admin_alerts(State(state): State<AppState>) -> Json<AlertReport> {
+    state.alerts.check_disk(&state.storage).await;
+    Json(state.alerts.report())
+}
+
+/// Encoders compiled into ffmpeg, plus those blacklisted after failing at
+/// runtime while a fallback succeeded.
+pub async fn capabilities(State(state): State<AppState>) -> Json<EncoderCapabilities> {
+    Json(encoder_capabilities(&state.process_runner).await)
+}
+
+/// Lets blacklisted encoders be tried again, e.g. after a driver update.
+pub async fn clear_capability_failures() -> StatusCode {
+    let cleared = clear_encoder_failures();
+    tracing::info!(cleared, "encoder failures cleared");
+    StatusCode::NO_CONTENT
+}
+
+pub async fn reload_config(State(state): State<AppState>) -> Result<Json<ReloadReport>, AppError> {
+    let report = state.reload_config()?;
+    tracing::info!(
+        applied = ?report.applied,
+        requires_restart = ?report.requires_restart,
+        "configuration reloaded"
+    );
+    Ok(Json(report))
+}
+
+/// Process metrics in the Prometheus text format.
+pub async fn metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::render(),
+    )
+}
+
+/// `?month=YYYY-MM` (default: the current UTC month) and `?limit=N` to keep
+/// only the heaviest videos and keys.
+#[derive(Debug, Default, Deserialize)]
+pub struct BandwidthQuery {
+    pub month: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Monthly delivery rollup, heaviest first.
+#[derive(Debug, Serialize)]
+pub struct BandwidthRollup {
+    pub month: String,
+    #[serde(flatten)]
+    pub total: BandwidthUsage,
+    pub total_bytes: u64,
+    pub videos: Vec<VideoBandwidth>,
+    pub keys: Vec<KeyBandwidth>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VideoBandwidth {
+    pub video_id: Uuid,
+    #[serde(flatten)]
+    pub usage: BandwidthUsage,
+    pub total_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KeyBandwidth {
+    pub key: String,
+    #[serde(flatten)]
+    pub usage: BandwidthUsage,
+    pub total_bytes: u64,
+}
+
+/// Bytes served in one month per video and per caller key, for chargeback.
+pub async fn bandwidth_rollup(
+    State(state): State<AppState>,
+    Query(query): Query<BandwidthQuery>,
+) -> Result<Json<BandwidthRollup>, AppError> {
+    let month = query.month.unwrap_or_else(bandwidth::current_month);
+    let usage = state.bandwidth.month(&month).await?;
+    let limit = query.limit.unwrap_or(usize::MAX);
+
+    let mut videos: Vec<VideoBandwidth> = usage
+        .videos
+        .into_iter()
+        .map(|(video_id, usage)| VideoBandwidth {
+            video_id,
+            total_bytes: usage.total(),
+            usage,
+        })
+        .collect();
+    videos.sort_by_key(|video| Reverse(video.total_bytes));
+    videos.truncate(limit);
+
+    let mut keys: Vec<KeyBandwidth> = usage
+        .keys
+        .into_iter()
+        .map(|(key, usage)| KeyBandwidth {
+            key,
+            total_bytes: usage.total(),
+            usage,
+        })
+        .collect();
+    keys.sort_by_key(|key| Reverse(key.total_bytes));
+    keys.truncate(limit);
+
+    Ok(Json(BandwidthRollup {
+        month,
+        total_bytes: usage.total.total(),
+        total: usage.total,
+        videos,
+        keys,
+    }))
+}
+
+/// Lists the tmp workspace: pending uploads and downloads, generated
+/// renditions and intermediate encode output, with their owning jobs.
+pub async fn tmp_workspace(State(state): State<AppState>) -> Result<Json<TmpListing>, AppError> {
+    Ok(Json(workspace::list(&state.storage, &state.jobs).await?))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DeleteTmpQuery {
+    /// Also delete items of jobs that are still running.
+    #[serde(default)]
+    pub force: bool,
+}
+
+pub async fn delete_tmp_item(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<DeleteTmpQuery>,
+) -> Result<Json<TmpEntry>, AppError> {
+    Ok(Json(
+        workspace::remove(&state.storage, &state.jobs, &name, query.force).await?,
+    ))
+}
+
+/// Deletes every orphaned tmp workspace item.
+pub async fn clear_tmp_orphans(
+    State(state): State<AppState>,
+) -> Result<Json<TmpClearReport>, AppError> {
+    Ok(Json(
+        workspace::clear_orphans(&state.storage, &state.jobs).await?,
+    ))
+}
+
+/// Stored cookies files yt-dlp jobs can log in with.
+pub async fn list_cookies(State(state): State<AppState>) -> Result<Json<Vec<CookieJar>>, AppError> {
+    Ok(Json(cookies::list(&state.storage).await?))
+}
+
+/// Stores a Netscape cookies file, e.g. exported from a logged-in browser,
+/// under `name`.
+pub async fn put_cookies(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    body: String,
+) -> Result<Json<CookieJar>, AppError> {
+    let jar = cookies::save(&state.storage, &name, &body).await?;
+    tracing::info!(name, cookies = jar.cookies, "cookies file stored");
+    Ok(Json(jar))
+}
+
+pub async fn delete_cookies(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, AppError> {
+    cookies::remove(&state.storage, &name).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
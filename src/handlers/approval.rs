@@ -0,0 +1,173 @@
+use std::path::Path;
+
+use tokio::fs;
+use uuid::Uuid;
+
+use super::encode::{keep_original, render_source, transcode_and_publish, transcode_slot};
+use super::pipeline::{conclude_pipeline, fail_pipeline, job_not_found, progress_reporter};
+use crate::{
+    cancel::RunningJob,
+    digest::SourceDigest,
+    error::AppError,
+    jobs::{JobOrigin, JobSource, JobStage, JobStatusResponse},
+    metadata,
+    state::AppState,
+    transcode::{EncodeParams, render_proxy},
+};
+
+/// Starts the full encode of a job waiting in `AwaitingApproval` once its
+/// review proxy is ready.
+pub(crate) async fn approve_awaiting_job(
+    state: &AppState,
+    id: Uuid,
+) -> Result<JobStatusResponse, AppError> {
+    let Some(status) = state.jobs.status(&id).await? else {
+        return Err(job_not_found(id));
+    };
+    if status.stage != JobStage::AwaitingApproval {
+        return Err(AppError::validation(format!(
+            "job {id} is {}; only jobs awaiting approval can be approved",
+            status.stage.as_str()
+        ))
+        .with_code("job_not_awaiting_approval")
+        .with_param("id", id.to_string()));
+    }
+    let mut meta = metadata::load(&state.storage, &id).await?;
+    let (Some(source), Some(digest), true) = (
+        state.jobs.source(&id).await?,
+        meta.source.clone(),
+        state.storage.incoming_path(&id).is_file(),
+    ) else {
+        return Err(AppError::validation(format!(
+            "the source of job {id} is no longer in the incoming area; ingest it again"
+        ))
+        .with_code("job_source_missing")
+        .with_param("id", id.to_string()));
+    };
+    let Some(job) = state.running.register_idle(id) else {
+        return Err(AppError::validation(format!(
+            "the review proxy of job {id} is still rendering"
+        ))
+        .with_code("job_not_awaiting_approval")
+        .with_param("id", id.to_string()));
+    };
+    state.dispatcher.take(&state.jobs, id).await?;
+    meta.approved = true;
+    metadata::save(&state.storage, &id, &meta).await?;
+    tracing::info!(%id, "job approved");
+    spawn_approved_pipeline(state.clone(), job, id, source, digest);
+    state
+        .jobs
+        .status(&id)
+        .await?
+        .ok_or_else(|| job_not_found(id))
+}
+
+/// Starts the full encode of an approved job from the source its review
+/// proxy was rendered from.
+fn spawn_approved_pipeline(
+    state: AppState,
+    job: RunningJob,
+    id: Uuid,
+    source: JobSource,
+    digest: SourceDigest,
+) {
+    tokio::spawn(async move {
+        let url = match &source.origin {
+            JobOrigin::Local => None,
+            JobOrigin::Remote { url, .. } | JobOrigin::YtDlp { url, .. } => Some(url.as_str()),
+        };
+        let temp_path = state.storage.incoming_path(&id);
+        let progress = progress_reporter(&state, id, &source);
+        let result = job
+            .run(transcode_and_publish(
+                &state,
+                id,
+                url,
+                &temp_path,
+                &digest,
+                source.encode,
+            ))
+            .await;
+        drop(progress);
+        conclude_pipeline(&state, job, id, &source, result).await;
+    });
+}
+
+/// Fails a job waiting for approval as cancelled and drops its source and
+/// review proxy.
+pub(super) async fn reject_job(state: &AppState, id: Uuid) -> Result<JobStatusResponse, AppError> {
+    tracing::info!(%id, "rejecting job awaiting approval");
+    fail_pipeline(
+        state,
+        id,
+        &AppError::cancelled(format!("job {id} was rejected")),
+    )
+    .await;
+    for path in [
+        state.storage.incoming_path(&id),
+        state.storage.proxy_path(&id),
+    ] {
+        match fs::remove_file(&path).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                tracing::warn!(path = %path.display(), ?err, "cleanup failed");
+            }
+            _ => {}
+        }
+    }
+    state
+        .jobs
+        .status(&id)
+        .await?
+        .ok_or_else(|| job_not_found(id))
+}
+
+/// Renders the review proxy of a job ingested with `approval` and leaves it
+/// in `AwaitingApproval`. Returns false when the job goes straight on to the
+/// full encode, because it needs no approval or already has it.
+pub(super) async fn await_approval(
+    state: &AppState,
+    id: Uuid,
+    path: &Path,
+    encode: Option<&EncodeParams>,
+) -> Result<bool, AppError> {
+    if !encode.is_some_and(|params| params.approval)
+        || metadata::load(&state.storage, &id).await?.approved
+    {
+        return Ok(false);
+    }
+    let _slot = transcode_slot(state, id).await?;
+    state
+        .jobs
+        .update_stage(id, JobStage::AwaitingApproval)
+        .await?;
+    keep_original(state, id, path, encode).await?;
+    render_source(state, id, path, encode).await?;
+    render_proxy(
+        &state.storage,
+        Some(&state.jobs),
+        &state.process_runner,
+        &id,
+        path,
+    )
+    .await?;
+    state.jobs.update_progress(id, 1.0).await?;
+    tracing::info!(%id, "review proxy ready; waiting for approval");
+    Ok(true)
+}
+
+/// Renders the `proxy` copy while the encode runs, unless approval already
+/// did. Like the preview, a proxy that fails to render leaves the job be.
+pub(super) async fn quick_proxy(
+    state: &AppState,
+    id: Uuid,
+    path: &Path,
+    encode: Option<&EncodeParams>,
+) {
+    if !encode.is_some_and(|encode| encode.proxy) || state.storage.proxy_path(&id).is_file() {
+        return;
+    }
+    if let Err(err) = render_proxy(&state.storage, None, &state.process_runner, &id, path).await {
+        tracing::warn!(%id, error = %err, "failed to render proxy");
+    }
+}
@@ -0,0 +1,26 @@
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{auth::Scope, error::AppError, state::AppState};
+
+/// Route layer requiring a bearer token with the route's scope while JWT
+/// auth is configured. Routes without a scope pass untouched. The verified
+/// [`Claims`](crate::auth::Claims) are added to the request's extensions.
+pub async fn require_scope(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if let Some(scope) = Scope::for_route(request.method(), request.uri().path())
+        && let Some(claims) = state
+            .auth
+            .authorize(&state.http_client, request.headers(), scope)
+            .await?
+    {
+        request.extensions_mut().insert(claims);
+    }
+    Ok(next.run(request).await)
+}
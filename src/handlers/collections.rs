@@ -0,0 +1,339 @@
+use axum::{
+    Extension, Json,
+    extract::{Path as AxumPath, Query, State},
+    http::{self, HeaderMap, HeaderValue, StatusCode},
+    response::Response,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    auth::Claims,
+    bandwidth::{ANONYMOUS_KEY, DeliveryKind},
+    collections::Collection,
+    error::AppError,
+    metadata,
+    playlist::{append_query_to_playlist, concat_media_playlists, select_variant},
+    signing::PlaybackSigner,
+    state::AppState,
+    transcode::ensure_hls_ready,
+    usage,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateCollectionRequest {
+    pub name: String,
+    #[serde(default)]
+    pub videos: Vec<Uuid>,
+}
+
+/// Omitted fields are left unchanged; `videos` replaces the whole order.
+#[derive(Debug, Default, Deserialize)]
+pub struct UpdateCollectionRequest {
+    pub name: Option<String>,
+    pub videos: Option<Vec<Uuid>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CollectionPlaylistQuery {
+    /// Picks the tallest rendition of each video that fits.
+    pub max_height: Option<u32>,
+}
+
+pub async fn create_collection(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    claims: Option<Extension<Claims>>,
+    Json(request): Json<CreateCollectionRequest>,
+) -> Result<(StatusCode, Json<Collection>), AppError> {
+    let owner = usage::account_key(
+        &headers,
+        claims.as_deref(),
+        state.rate_limits.trusts_forwarded_headers(),
+    );
+    ensure_videos_owned(&state, &request.videos, &owner).await?;
+    let collection = state
+        .collections
+        .create(request.name, request.videos, owner)
+        .await?;
+    tracing::info!(collection_id = %collection.id, videos = collection.videos.len(), "collection created");
+    Ok((StatusCode::CREATED, Json(collection)))
+}
+
+pub async fn list_collections(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<Collection>>, AppError> {
+    Ok(Json(state.collections.list().await?))
+}
+
+pub async fn get_collection(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+) -> Result<Json<Collection>, AppError> {
+    let id = parse_collection_id(&id)?;
+    Ok(Json(state.collections.get(&id).await?))
+}
+
+pub async fn update_collection(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+    claims: Option<Extension<Claims>>,
+    Json(request): Json<UpdateCollectionRequest>,
+) -> Result<Json<Collection>, AppError> {
+    let id = parse_collection_id(&id)?;
+    if let Some(videos) = &request.videos {
+        let caller = usage::account_key(
+            &headers,
+            claims.as_deref(),
+            state.rate_limits.trusts_forwarded_headers(),
+        );
+        let owner = collection_owner(&state.collections.get(&id).await?).to_string();
+        if caller != owner {
+            return Err(
+                AppError::forbidden(format!("collection {id} belongs to another account"))
+                    .with_code("collection_not_owned"),
+            );
+        }
+        ensure_videos_owned(&state, videos, &owner).await?;
+    }
+    let collection = state
+        .collections
+        .update(&id, request.name, request.videos)
+        .await?;
+    Ok(Json(collection))
+}
+
+pub async fn delete_collection(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+) -> Result<StatusCode, AppError> {
+    let id = parse_collection_id(&id)?;
+    state.collections.delete(&id).await?;
+    tracing::info!(collection_id = %id, "collection deleted");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// One HLS media playlist that plays every video of the collection in order,
+/// using a single rendition per video.
+pub async fn collection_playlist(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+    Query(query): Query<CollectionPlaylistQuery>,
+) -> Result<Response, AppError> {
+    let id = parse_collection_id(&id)?;
+    let collection = state.collections.get(&id).await?;
+    let signer = PlaybackSigner::from_env();
+
+    let mut parts = Vec::new();
+    for video_id in playable_videos(&state, &collection, signer.is_some()).await? {
+        if let Err(err) = ensure_hls_ready(&state.storage, &state.process_runner, &video_id).await {
+            tracing::warn!(collection_id = %id, %video_id, error = %err, "skipping video without HLS output");
+            continue;
+        }
+        let hls_dir = state.storage.hls_dir(&video_id);
+        let Ok(master) = tokio::fs::read_to_string(hls_dir.join("index.m3u8")).await else {
+            continue;
+        };
+        let Some(variant) = select_variant(&master, query.max_height) else {
+            continue;
+        };
+        let Ok(mut media) = tokio::fs::read_to_string(hls_dir.join(variant)).await else {
+            continue;
+        };
+        if let Some(signer) = &signer {
+            media = append_query_to_playlist(&media, &format!("token={}", signer.issue(&video_id)));
+        }
+        // Relative to /collections/{id}/playlist.m3u8 so path prefixes survive.
+        parts.push((
+            video_id,
+            format!("../../videos/{}/hls/", video_id.hyphenated()),
+            media,
+        ));
+    }
+    if parts.is_empty() {
+        return Err(AppError::not_found(format!(
+            "collection {id} has no playable videos"
+        )));
+    }
+
+    let playlist = concat_media_playlists(
+        parts
+            .iter()
+            .map(|(_, base, media)| (base.as_str(), media.as_str())),
+    );
+    let mut response = Response::new(playlist.into());
+    response.headers_mut().insert(
+        http::header::CONTENT_TYPE,
+        HeaderValue::from_static("application/vnd.apple.mpegurl"),
+    );
+    let metered = parts
+        .iter()
+        .map(|(video_id, _, media)| (*video_id, media.len() as u64))
+        .collect();
+    Ok(state
+        .bandwidth
+        .meter_parts(response, metered, &headers, DeliveryKind::Hls))
+}
+
+/// Player page that works through the collection as a queue.
+pub async fn collection_embed(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let id = parse_collection_id(&id)?;
+    let collection = state.collections.get(&id).await?;
+    let signer = PlaybackSigner::from_env();
+    let videos = playable_videos(&state, &collection, signer.is_some()).await?;
+    let sources = videos
+        .iter()
+        .map(|video_id| {
+            let token = signer
+                .as_ref()
+                .map(|signer| format!("?token={}", signer.issue(video_id)))
+                .unwrap_or_default();
+            let base = format!("../../videos/{}", video_id.hyphenated());
+            (
+                format!("{base}/hls/master.m3u8{token}"),
+                format!("{base}/download{token}"),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let page = embed_page(&collection.name, &sources);
+    // The page is charged to its videos in equal shares.
+    let share = (page.len() / videos.len().max(1)) as u64;
+    let metered = videos.iter().map(|video_id| (*video_id, share)).collect();
+    let mut response = Response::new(page.into());
+    response.headers_mut().insert(
+        http::header::CONTENT_TYPE,
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    Ok(state
+        .bandwidth
+        .meter_parts(response, metered, &headers, DeliveryKind::Hls))
+}
+
+fn parse_collection_id(id: &str) -> Result<Uuid, AppError> {
+    Uuid::parse_str(id).map_err(|_| AppError::validation("invalid collection identifier"))
+}
+
+/// Collections created before owners were recorded belong to `anonymous`.
+fn collection_owner(collection: &Collection) -> &str {
+    collection.owner.as_deref().unwrap_or(ANONYMOUS_KEY)
+}
+
+/// Rejects videos that do not exist or whose account is not `owner`.
+async fn ensure_videos_owned(
+    state: &AppState,
+    videos: &[Uuid],
+    owner: &str,
+) -> Result<(), AppError> {
+    for video_id in videos {
+        if !tokio::fs::try_exists(state.storage.video_dir(video_id)).await?
+            && state.jobs.status(video_id).await?.is_none()
+        {
+            return Err(AppError::validation(format!("unknown video {video_id}")));
+        }
+        let meta = metadata::load(&state.storage, video_id).await?;
+        if meta.account.as_deref().unwrap_or(ANONYMOUS_KEY) != owner {
+            return Err(AppError::forbidden(format!(
+                "video {video_id} belongs to another account"
+            ))
+            .with_code("video_not_owned"));
+        }
+    }
+    Ok(())
+}
+
+/// Collection members that can be played without a password, in order.
+/// With signed URLs on, only videos of the collection's owner are admitted,
+/// since playing the collection hands out tokens for them.
+async fn playable_videos(
+    state: &AppState,
+    collection: &Collection,
+    signed: bool,
+) -> Result<Vec<Uuid>, AppError> {
+    let owner = collection_owner(collection);
+    let mut videos = Vec::with_capacity(collection.videos.len());
+    for video_id in &collection.videos {
+        let meta = metadata::load(&state.storage, video_id).await?;
+        if meta.password_hash.is_some() {
+            tracing::debug!(collection_id = %collection.id, %video_id, "omitting password-protected video");
+            continue;
+        }
+        if signed && meta.account.as_deref().unwrap_or(ANONYMOUS_KEY) != owner {
+            tracing::debug!(collection_id = %collection.id, %video_id, "omitting video of another account");
+            continue;
+        }
+        videos.push(*video_id);
+    }
+    Ok(videos)
+}
+
+/// `sources` holds the HLS and download URL of each video, relative to
+/// `/collections/{id}/embed`.
+fn embed_page(name: &str, sources: &[(String, String)]) -> String {
+    let name = escape_html(name);
+    let videos = sources
+        .iter()
+        .map(|(hls, download)| format!("{{hls:\"{hls}\",download:\"{download}\"}}"))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!(
+        r#"<!doctype html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{name}</title>
+<style>body{{margin:0;background:#000;color:#ddd;font-family:sans-serif}}video{{display:block;width:100%;max-height:80vh}}ol{{margin:0;padding:.5em 2em}}li{{cursor:pointer;padding:.2em 0}}li.current{{color:#fff;font-weight:bold}}</style>
+</head>
+<body>
+<video id="player" controls playsinline></video>
+<ol id="queue"></ol>
+<script>
+const videos = [{videos}];
+const player = document.getElementById("player");
+const queue = document.getElementById("queue");
+const hls = player.canPlayType("application/vnd.apple.mpegurl") !== "";
+let current = 0;
+function play(index, autoplay) {{
+  current = index;
+  player.src = hls ? videos[index].hls : videos[index].download;
+  queue.querySelectorAll("li").forEach((item, i) => item.classList.toggle("current", i === index));
+  if (autoplay) player.play().catch(() => {{}});
+}}
+videos.forEach((video, index) => {{
+  const item = document.createElement("li");
+  item.textContent = "Part " + (index + 1);
+  item.addEventListener("click", () => play(index, true));
+  queue.appendChild(item);
+}});
+player.addEventListener("ended", () => {{
+  if (current + 1 < videos.length) play(current + 1, true);
+}});
+if (videos.length > 0) play(0, false);
+</script>
+</body>
+</html>
+"#
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            other => escaped.push(other),
+        }
+    }
+    escaped
+}
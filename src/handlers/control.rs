@@ -0,0 +1,189 @@
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use super::approval::reject_job;
+use super::pipeline::{conclude_pipeline, fail_pipeline, job_not_found, spawn_pipeline};
+use crate::{
+    cookies,
+    error::AppError,
+    jobs::{JobCredentials, JobOrigin, JobStage, JobStatusResponse},
+    state::AppState,
+    transcode::clear_failure,
+};
+
+const CANCEL_WAIT: Duration = Duration::from_secs(10);
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Re-runs the pipeline of a failed job under the same id: the download is
+/// fetched again, or for uploads, the file left in the incoming area is
+/// transcoded again. Per-request credentials are not kept with the job, so
+/// a job submitted with some needs `credentials` given again.
+pub(crate) async fn retry_failed_job(
+    state: &AppState,
+    id: Uuid,
+    credentials: JobCredentials,
+) -> Result<JobStatusResponse, AppError> {
+    let Some(group) = state.jobs.group_status(&id).await? else {
+        return Err(job_not_found(id));
+    };
+    let status = &group.members[0].status;
+    if status.parent_id.is_some() {
+        return Err(AppError::validation(format!(
+            "job {id} is an optional stage; retry its parent job instead"
+        ))
+        .with_code("job_not_retryable")
+        .with_param("id", id.to_string()));
+    }
+    if status.stage != JobStage::Failed {
+        return Err(AppError::validation(format!(
+            "job {id} is {}; only failed jobs can be retried",
+            status.stage.as_str()
+        ))
+        .with_code("job_not_failed")
+        .with_param("id", id.to_string()));
+    }
+    let Some(mut source) = state.jobs.source(&id).await? else {
+        return Err(
+            AppError::validation(format!("job {id} has no recorded source to retry from"))
+                .with_code("job_not_retryable")
+                .with_param("id", id.to_string()),
+        );
+    };
+    source
+        .restore_credentials(credentials)
+        .map_err(|err| err.with_param("id", id.to_string()))?;
+    if let JobOrigin::YtDlp { options, .. } = &source.origin
+        && let Some(name) = &options.auth.cookies
+    {
+        cookies::ensure_exists(&state.storage, name).await?;
+    }
+    match &source.origin {
+        JobOrigin::Local if !state.storage.incoming_path(&id).is_file() => {
+            return Err(AppError::validation(format!(
+                "the upload of job {id} is no longer in the incoming area; upload it again"
+            ))
+            .with_code("job_source_missing")
+            .with_param("id", id.to_string()));
+        }
+        JobOrigin::Local => {}
+        JobOrigin::Remote { url, .. } | JobOrigin::YtDlp { url, .. } => state.breaker.admit(url)?,
+    }
+    state.load.admit(&state.storage).await?;
+
+    let Some(job) = state.running.register_idle(id) else {
+        return Err(AppError::validation(format!("job {id} is already running"))
+            .with_code("job_not_failed")
+            .with_param("id", id.to_string()));
+    };
+    for member in &group.members {
+        if member.status.stage.is_terminal() {
+            state.jobs.reset(member.status.id).await?;
+        }
+    }
+    clear_failure(&state.storage, &id).await?;
+    tracing::info!(%id, origin = ?source.redacted().origin, "retrying job");
+    spawn_pipeline(state.clone(), job, id, source);
+    state
+        .jobs
+        .status(&id)
+        .await?
+        .ok_or_else(|| job_not_found(id))
+}
+
+/// Stops the pipeline of job `id` and waits briefly for it to wind down, so
+/// the returned status already shows the job as cancelled. A job waiting for
+/// approval is rejected instead: it fails as cancelled, and its source and
+/// review proxy are dropped. Under sharded dispatch, a job not running here
+/// is cancelled through the job store, see [`cancel_sharded_job`].
+pub(crate) async fn cancel_running_job(
+    state: &AppState,
+    id: Uuid,
+) -> Result<JobStatusResponse, AppError> {
+    let Some(group) = state.jobs.group_status(&id).await? else {
+        return Err(job_not_found(id));
+    };
+    if group.members[0].status.stage == JobStage::AwaitingApproval && !state.running.is_running(&id)
+    {
+        return reject_job(state, id).await;
+    }
+    if !state.running.cancel(&id) {
+        let finished = group
+            .members
+            .iter()
+            .all(|member| member.status.stage.is_terminal());
+        if finished {
+            return Err(
+                AppError::validation(format!("job {id} has already finished"))
+                    .with_code("job_finished")
+                    .with_param("id", id.to_string()),
+            );
+        }
+        return cancel_sharded_job(state, id).await;
+    }
+    tracing::info!(%id, "cancelling job");
+    let stopped = async {
+        while state.running.is_running(&id) {
+            tokio::time::sleep(CANCEL_POLL_INTERVAL).await;
+        }
+    };
+    if tokio::time::timeout(CANCEL_WAIT, stopped).await.is_err() {
+        tracing::warn!(%id, "cancelled pipeline has not stopped yet");
+    }
+    state
+        .jobs
+        .status(&id)
+        .await?
+        .ok_or_else(|| job_not_found(id))
+}
+
+/// Cancels job `id`, which does not run here, under sharded dispatch. A
+/// job no live instance holds, such as one queued for its owner, is claimed
+/// so it cannot start meanwhile, then fails as cancelled. For one running
+/// on another instance, the cancel is recorded in the job store, and that
+/// instance stops the job on its next heartbeat; the status is returned
+/// once it did, or when waiting took too long.
+async fn cancel_sharded_job(state: &AppState, id: Uuid) -> Result<JobStatusResponse, AppError> {
+    let Some(config) = state.dispatcher.config() else {
+        return Err(
+            AppError::validation(format!("job {id} is not running on this instance"))
+                .with_code("job_not_running")
+                .with_param("id", id.to_string()),
+        );
+    };
+    let live = state.jobs.live_instances().await?;
+    let job = state.running.register_idle(id);
+    if let Some(job) = job
+        && state.jobs.claim(id, &config.instance_id, &live).await?
+    {
+        tracing::info!(%id, "cancelling job no instance runs");
+        let err = AppError::cancelled(format!("job {id} was cancelled"));
+        match state.jobs.source(&id).await? {
+            Some(source) => conclude_pipeline(state, job, id, &source, Err(err)).await,
+            None => {
+                fail_pipeline(state, id, &err).await;
+                state.dispatcher.release(&state.jobs, id).await;
+            }
+        }
+    } else {
+        tracing::info!(%id, "asking the instance running the job to cancel it");
+        state.jobs.request_cancel(id).await?;
+        let stopped = async {
+            loop {
+                match state.jobs.status(&id).await {
+                    Ok(Some(status)) if !status.stage.is_terminal() => {}
+                    _ => break,
+                }
+                tokio::time::sleep(CANCEL_POLL_INTERVAL).await;
+            }
+        };
+        if tokio::time::timeout(CANCEL_WAIT, stopped).await.is_err() {
+            tracing::warn!(%id, "cancelled job has not stopped yet");
+        }
+    }
+    state
+        .jobs
+        .status(&id)
+        .await?
+        .ok_or_else(|| job_not_found(id))
+}
@@ -1,251 +1,565 @@
-use std::{future::Future, path::PathBuf};
-
 use axum::{
-    body::Body,
-    extract::{FromRequestParts, Path as AxumPath, State},
-    http::{self, HeaderValue, StatusCode},
-    response::Response,
+    Json,
+    extract::{Path as AxumPath, Query, State},
+    http::{self, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
 };
-use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncSeekExt, BufReader};
-use tokio_util::io::ReaderStream;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
+    bandwidth::DeliveryKind,
+    captions::{self, CaptionTrack},
+    config,
     error::AppError,
+    metadata::{self, VideoMetadata},
+    rate_limit::ClientAddr,
     state::AppState,
-    transcode::{ensure_dash_ready, ensure_hls_ready},
+    transcode::{
+        FrameFormat, FrameRequest, HlsArchive, SPRITE_FILE, STORYBOARD_FILE,
+        append_query_to_storyboard, ensure_frame,
+    },
+    video_access::{forwarded_query, unlock, verify_playback},
+};
+
+use super::{
+    files::{RangeHeader, read_text_asset, serve_static_file, serve_video_file, text_response},
+    meta::SkipSegmentList,
 };
 
+/// Playback session token, required when `VIDEO_SIGNING_SECRET` is set, and
+/// an access token standing in for the password of a protected video.
+#[derive(Debug, Default, Deserialize)]
+pub struct PlaybackQuery {
+    pub token: Option<String>,
+    pub access: Option<String>,
+}
+
+/// `GET /videos/{id}/thumbnail` parameters: `t` in seconds, an output
+/// `width`, and `format` (`jpeg` or `webp`).
+#[derive(Debug, Default, Deserialize)]
+pub struct ThumbnailQuery {
+    pub t: Option<f64>,
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub format: FrameFormat,
+    pub token: Option<String>,
+    pub access: Option<String>,
+}
+
+const IMAGE_MAX_AGE_SECS: u32 = 24 * 60 * 60;
+/// How long clients wait before asking for an `hls.zip` being built again.
+const ARCHIVE_RETRY_AFTER_SECS: u64 = 5;
+
+/// Body of the `202` sent while an `hls.zip` is being built.
+#[derive(Debug, Serialize)]
+pub struct ArchivePendingResponse {
+    pub status: &'static str,
+    pub retry_after_secs: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CaptionTracksResponse {
+    pub tracks: Vec<CaptionTrack>,
+}
+
 pub async fn download_video(
     State(state): State<AppState>,
     AxumPath(id): AxumPath<String>,
     RangeHeader(range_header): RangeHeader,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+    Query(query): Query<PlaybackQuery>,
 ) -> Result<Response, AppError> {
     let video_id =
         Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid video identifier"))?;
+    verify_playback(&video_id, query.token.as_deref())?;
+    let meta = metadata::load(&state.storage, &video_id).await?;
+    unlock(
+        &state,
+        &video_id,
+        &meta,
+        &headers,
+        client,
+        query.access.as_deref(),
+    )
+    .await?;
     let path = state.storage.download_path(&video_id);
-    serve_video_file(path, range_header.as_deref()).await
+    let response = serve_video_file(path, range_header.as_deref(), meta.mezzanine).await?;
+    Ok(state.bandwidth.meter(
+        with_custom_headers(response, &meta),
+        video_id,
+        &headers,
+        DeliveryKind::Download,
+    ))
 }
 
-pub async fn get_hls_asset(
+/// Streams the in-progress encode of a video when `VIDEO_SERVE_PARTIAL_ENCODES`
+/// is enabled. Responses carry `X-VRS-Partial: true` and are never cached.
+pub async fn download_partial_video(
     State(state): State<AppState>,
-    AxumPath((id, asset)): AxumPath<(String, String)>,
+    AxumPath(id): AxumPath<String>,
+    RangeHeader(range_header): RangeHeader,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+    Query(query): Query<PlaybackQuery>,
 ) -> Result<Response, AppError> {
     let video_id =
         Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid video identifier"))?;
-    validate_relative_path(&asset)?;
-    ensure_hls_ready(&state.storage, &video_id).await?;
-    let path = state.storage.hls_dir(&video_id).join(asset);
-    serve_static_file(path).await
+    if !partial_encodes_enabled() {
+        return Err(AppError::not_found("partial encode previews are disabled"));
+    }
+    verify_playback(&video_id, query.token.as_deref())?;
+    let meta = metadata::load(&state.storage, &video_id).await?;
+    unlock(
+        &state,
+        &video_id,
+        &meta,
+        &headers,
+        client,
+        query.access.as_deref(),
+    )
+    .await?;
+
+    let path = state.storage.partial_encode_path(&video_id);
+    if !path.exists() {
+        return Err(AppError::not_found(format!(
+            "no encode in progress for {video_id}; use /videos/{video_id}/download once finished"
+        )));
+    }
+
+    let mut response = state.bandwidth.meter(
+        with_custom_headers(
+            serve_video_file(path, range_header.as_deref(), meta.mezzanine).await?,
+            &meta,
+        ),
+        video_id,
+        &headers,
+        DeliveryKind::Download,
+    );
+    let headers = response.headers_mut();
+    headers.insert("x-vrs-partial", HeaderValue::from_static("true"));
+    headers.insert(
+        http::header::CACHE_CONTROL,
+        HeaderValue::from_static("no-store"),
+    );
+    if let Ok(value) = HeaderValue::from_str(&format!(
+        "inline; filename=\"{}.partial.{}\"",
+        video_id.simple(),
+        meta.mezzanine.extension()
+    )) {
+        headers.insert(http::header::CONTENT_DISPOSITION, value);
+    }
+    if let Some(status) = state.jobs.status(&video_id).await?
+        && let Ok(value) = HeaderValue::from_str(&format!("{:.3}", status.progress))
+    {
+        headers.insert("x-vrs-progress", value);
+    }
+    Ok(response)
 }
 
-pub async fn get_dash_asset(
+/// Serves a frame of the download, extracting it with ffmpeg on first request.
+pub async fn get_thumbnail(
     State(state): State<AppState>,
-    AxumPath((id, asset)): AxumPath<(String, String)>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+    Query(query): Query<ThumbnailQuery>,
 ) -> Result<Response, AppError> {
     let video_id =
         Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid video identifier"))?;
-    validate_relative_path(&asset)?;
-    ensure_dash_ready(&state.storage, &video_id).await?;
-    let path = state.storage.dash_dir(&video_id).join(asset);
-    serve_static_file(path).await
+    let signer = verify_playback(&video_id, query.token.as_deref())?;
+    let meta = metadata::load(&state.storage, &video_id).await?;
+    let access = unlock(
+        &state,
+        &video_id,
+        &meta,
+        &headers,
+        client,
+        query.access.as_deref(),
+    )
+    .await?;
+
+    let request = FrameRequest {
+        at_seconds: query.t,
+        width: query.width,
+        format: query.format,
+    };
+    let path = ensure_frame(&state.storage, &state.process_runner, &video_id, request).await?;
+    let response = with_custom_headers(serve_static_file(path).await?, &meta);
+    Ok(with_image_cache(
+        response,
+        signer.is_some() || access.is_some(),
+    ))
 }
 
-fn validate_relative_path(path: &str) -> Result<(), AppError> {
-    if path.starts_with('/') || path.contains("..") {
-        return Err(AppError::validation("invalid asset path"));
+/// Serves the animated `preview.webp` rendered when the upload asked for one.
+pub async fn get_preview(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+    Query(query): Query<PlaybackQuery>,
+) -> Result<Response, AppError> {
+    let video_id =
+        Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid video identifier"))?;
+    let signer = verify_playback(&video_id, query.token.as_deref())?;
+    let meta = metadata::load(&state.storage, &video_id).await?;
+    let access = unlock(
+        &state,
+        &video_id,
+        &meta,
+        &headers,
+        client,
+        query.access.as_deref(),
+    )
+    .await?;
+
+    let path = state.storage.preview_path(&video_id);
+    if !path.exists() {
+        return Err(
+            AppError::not_found(format!("video {video_id} has no animated preview"))
+                .with_code("preview_missing")
+                .with_param("id", video_id.to_string()),
+        );
     }
-    Ok(())
+    let response = with_custom_headers(serve_static_file(path).await?, &meta);
+    Ok(with_image_cache(
+        response,
+        signer.is_some() || access.is_some(),
+    ))
 }
 
-async fn serve_video_file(path: PathBuf, range_header: Option<&str>) -> Result<Response, AppError> {
+/// Serves the review proxy of a video, a 480p H.264 MP4 that plays in any
+/// browser.
+pub async fn get_proxy(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    RangeHeader(range_header): RangeHeader,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+    Query(query): Query<PlaybackQuery>,
+) -> Result<Response, AppError> {
+    let video_id =
+        Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid video identifier"))?;
+    verify_playback(&video_id, query.token.as_deref())?;
+    let meta = metadata::load(&state.storage, &video_id).await?;
+    unlock(
+        &state,
+        &video_id,
+        &meta,
+        &headers,
+        client,
+        query.access.as_deref(),
+    )
+    .await?;
+
+    let path = state.storage.proxy_path(&video_id);
     if !path.exists() {
-        return Err(AppError::not_found(format!(
-            "video not found under {}",
-            path.display()
-        )));
+        return Err(
+            AppError::not_found(format!("video {video_id} has no review proxy"))
+                .with_code("proxy_missing")
+                .with_param("id", video_id.to_string()),
+        );
     }
+    let response = serve_video_file(path, range_header.as_deref(), meta.mezzanine).await?;
+    let response = with_file_type(
+        response,
+        "video/mp4",
+        &format!("{}.proxy.mp4", video_id.simple()),
+    );
+    Ok(state.bandwidth.meter(
+        with_custom_headers(response, &meta),
+        video_id,
+        &headers,
+        DeliveryKind::Download,
+    ))
+}
 
-    let mut file = File::open(&path).await?;
-    let metadata = file.metadata().await?;
-    let file_size = metadata.len();
-
-    let range = if let Some(range) = range_header {
-        Some(parse_range(range, file_size)?)
-    } else {
-        None
-    };
+/// Serves the whole HLS directory of a video as one zip. The archive is
+/// built in the background; until it is ready, and whenever the renditions
+/// have changed since, the response is `202` with a `Retry-After`.
+pub async fn get_hls_archive(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    RangeHeader(range_header): RangeHeader,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+    Query(query): Query<PlaybackQuery>,
+) -> Result<Response, AppError> {
+    let video_id =
+        Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid video identifier"))?;
+    verify_playback(&video_id, query.token.as_deref())?;
+    let meta = metadata::load(&state.storage, &video_id).await?;
+    unlock(
+        &state,
+        &video_id,
+        &meta,
+        &headers,
+        client,
+        query.access.as_deref(),
+    )
+    .await?;
 
-    let (status, body, content_length, content_range) = if let Some(range) = range {
-        file.seek(std::io::SeekFrom::Start(range.start)).await?;
-        let reader = BufReader::new(file).take(range.length);
-        let body = Body::from_stream(ReaderStream::new(reader));
-        let content_range = format!("bytes {}-{}/{}", range.start, range.end, file_size);
-        (
-            StatusCode::PARTIAL_CONTENT,
-            body,
-            range.length,
-            Some(content_range),
-        )
-    } else {
-        let body = Body::from_stream(ReaderStream::new(file));
-        (StatusCode::OK, body, file_size, None)
+    let path = match state
+        .hls_archives
+        .request(&state.storage, &state.process_runner, video_id)
+        .await?
+    {
+        HlsArchive::Ready(path) => path,
+        HlsArchive::Building => {
+            let mut response = (
+                StatusCode::ACCEPTED,
+                Json(ArchivePendingResponse {
+                    status: "building",
+                    retry_after_secs: ARCHIVE_RETRY_AFTER_SECS,
+                }),
+            )
+                .into_response();
+            response.headers_mut().insert(
+                http::header::RETRY_AFTER,
+                HeaderValue::from(ARCHIVE_RETRY_AFTER_SECS),
+            );
+            return Ok(response);
+        }
     };
+    let response = serve_video_file(path, range_header.as_deref(), meta.mezzanine).await?;
+    let response = with_file_type(
+        response,
+        "application/zip",
+        &format!("{}.hls.zip", video_id.simple()),
+    );
+    Ok(state.bandwidth.meter(
+        with_custom_headers(response, &meta),
+        video_id,
+        &headers,
+        DeliveryKind::Hls,
+    ))
+}
 
-    let mut response = Response::builder().status(status).body(body).unwrap();
+/// Serves the original upload of a video ingested with `keep_source`, with
+/// the content type of its container.
+pub async fn get_source(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    RangeHeader(range_header): RangeHeader,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+    Query(query): Query<PlaybackQuery>,
+) -> Result<Response, AppError> {
+    let video_id =
+        Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid video identifier"))?;
+    verify_playback(&video_id, query.token.as_deref())?;
+    let meta = metadata::load(&state.storage, &video_id).await?;
+    unlock(
+        &state,
+        &video_id,
+        &meta,
+        &headers,
+        client,
+        query.access.as_deref(),
+    )
+    .await?;
 
-    response.headers_mut().insert(
-        http::header::CONTENT_TYPE,
-        HeaderValue::from_static("video/webm"),
-    );
-    response.headers_mut().insert(
-        http::header::ACCEPT_RANGES,
-        HeaderValue::from_static("bytes"),
-    );
-    response.headers_mut().insert(
-        http::header::CONTENT_LENGTH,
-        HeaderValue::from_str(&content_length.to_string()).unwrap_or(HeaderValue::from_static("0")),
-    );
-    if let Some(content_range) = content_range {
-        response.headers_mut().insert(
-            http::header::CONTENT_RANGE,
-            HeaderValue::from_str(&content_range).unwrap_or(HeaderValue::from_static("bytes */0")),
+    let path = state.storage.source_path(&video_id);
+    if !path.exists() {
+        return Err(
+            AppError::not_found(format!("video {video_id} has no kept source"))
+                .with_code("source_missing")
+                .with_param("id", video_id.to_string()),
         );
     }
-    response.headers_mut().insert(
-        http::header::CONTENT_DISPOSITION,
-        HeaderValue::from_str(&format!(
-            "inline; filename=\"{}.webm\"",
-            path.file_stem()
-                .and_then(|stem| stem.to_str())
-                .unwrap_or("video")
-        ))
-        .unwrap_or(HeaderValue::from_static("inline")),
+    let container = meta.source.as_ref().and_then(|digest| digest.container);
+    let (content_type, extension) = container.map_or(("application/octet-stream", "bin"), |c| {
+        (c.content_type(), c.extension())
+    });
+    let response = serve_video_file(path, range_header.as_deref(), meta.mezzanine).await?;
+    let response = with_file_type(
+        response,
+        content_type,
+        &format!("{}.source.{extension}", video_id.simple()),
     );
-
-    Ok(response)
+    Ok(state.bandwidth.meter(
+        with_custom_headers(response, &meta),
+        video_id,
+        &headers,
+        DeliveryKind::Download,
+    ))
 }
 
-async fn serve_static_file(path: PathBuf) -> Result<Response, AppError> {
-    if !path.exists() {
-        return Err(AppError::not_found(format!(
-            "asset not found: {}",
-            path.display()
-        )));
+/// Replaces the mezzanine content type and file name that
+/// [`serve_video_file`] sets, for files that are not the download.
+fn with_file_type(mut response: Response, content_type: &'static str, file_name: &str) -> Response {
+    let headers = response.headers_mut();
+    headers.insert(
+        http::header::CONTENT_TYPE,
+        HeaderValue::from_static(content_type),
+    );
+    if let Ok(value) = HeaderValue::from_str(&format!("inline; filename=\"{file_name}\"")) {
+        headers.insert(http::header::CONTENT_DISPOSITION, value);
     }
+    response
+}
 
-    let file = File::open(&path).await?;
-    let body = Body::from_stream(ReaderStream::new(file));
-    let mut response = Response::builder()
-        .status(StatusCode::OK)
-        .body(body)
-        .unwrap();
-
-    if let Some(mime) = mime_guess::from_path(&path).first() {
-        if let Ok(value) = HeaderValue::from_str(mime.as_ref()) {
-            response
-                .headers_mut()
-                .insert(http::header::CONTENT_TYPE, value);
-        }
-    } else if path
-        .extension()
-        .and_then(|ext| ext.to_str())
-        .map(|ext| ext.eq_ignore_ascii_case("m3u8"))
-        .unwrap_or(false)
-    {
-        response.headers_mut().insert(
-            http::header::CONTENT_TYPE,
-            HeaderValue::from_static("application/vnd.apple.mpegurl"),
-        );
-    } else if path
-        .extension()
-        .and_then(|ext| ext.to_str())
-        .map(|ext| ext.eq_ignore_ascii_case("mpd"))
-        .unwrap_or(false)
+/// Lets clients keep images for a day. Shared caches must not hand out
+/// images that needed a token or password.
+fn with_image_cache(mut response: Response, private: bool) -> Response {
+    let visibility = if private { "private" } else { "public" };
+    if let Ok(value) = HeaderValue::from_str(&format!("{visibility}, max-age={IMAGE_MAX_AGE_SECS}"))
     {
-        response.headers_mut().insert(
-            http::header::CONTENT_TYPE,
-            HeaderValue::from_static("application/dash+xml"),
-        );
+        response
+            .headers_mut()
+            .insert(http::header::CACHE_CONTROL, value);
     }
-
-    Ok(response)
+    response
 }
 
-#[derive(Debug, Clone, Copy)]
-struct ByteRange {
-    start: u64,
-    end: u64,
-    length: u64,
-}
+/// Serves the storyboard written by the `sprites` stage: `thumbnails.vtt`
+/// and the `sprites.jpg` its cues point into.
+pub async fn get_storyboard_asset(
+    State(state): State<AppState>,
+    AxumPath((id, asset)): AxumPath<(String, String)>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+    Query(query): Query<PlaybackQuery>,
+) -> Result<Response, AppError> {
+    let video_id =
+        Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid video identifier"))?;
+    let signer = verify_playback(&video_id, query.token.as_deref())?;
+    let meta = metadata::load(&state.storage, &video_id).await?;
+    let access = unlock(
+        &state,
+        &video_id,
+        &meta,
+        &headers,
+        client,
+        query.access.as_deref(),
+    )
+    .await?;
 
-fn parse_range(raw: &str, file_size: u64) -> Result<ByteRange, AppError> {
-    let raw = raw.trim();
-    if !raw.starts_with("bytes=") {
-        return Err(AppError::validation("unsupported range unit"));
-    }
-    let range = &raw[6..];
-    let mut parts = range.splitn(2, '-');
-    let start_str = parts
-        .next()
-        .ok_or_else(|| AppError::validation("invalid range format"))?;
-    let end_str = parts
-        .next()
-        .ok_or_else(|| AppError::validation("invalid range format"))?;
-
-    let start = start_str
-        .parse::<u64>()
-        .map_err(|_| AppError::validation("range start must be numeric"))?;
-
-    let end = if end_str.is_empty() {
-        file_size.saturating_sub(1)
-    } else {
-        end_str
-            .parse::<u64>()
-            .map_err(|_| AppError::validation("range end must be numeric"))?
+    let response = match asset.as_str() {
+        STORYBOARD_FILE => {
+            let path = state.storage.storyboard_path(&video_id);
+            match forwarded_query(&signer, query.token.as_deref(), access.as_deref()) {
+                Some(forward) => {
+                    let vtt = read_text_asset(&path).await?;
+                    text_response(append_query_to_storyboard(&vtt, &forward), "text/vtt")
+                }
+                None => serve_static_file(path).await?,
+            }
+        }
+        SPRITE_FILE => serve_static_file(state.storage.sprite_path(&video_id)).await?,
+        other => {
+            return Err(AppError::not_found(format!(
+                "no storyboard asset named {other}"
+            )));
+        }
     };
+    Ok(with_custom_headers(response, &meta))
+}
 
-    if start > end || end >= file_size {
-        return Err(AppError::validation("invalid range bounds"));
-    }
-
-    let length = end - start + 1;
-    Ok(ByteRange { start, end, length })
+/// Lists the WebVTT tracks of the video's caption set.
+pub async fn list_caption_tracks(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+    Query(query): Query<PlaybackQuery>,
+) -> Result<Json<CaptionTracksResponse>, AppError> {
+    let video_id =
+        Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid video identifier"))?;
+    verify_playback(&video_id, query.token.as_deref())?;
+    let meta = metadata::load(&state.storage, &video_id).await?;
+    unlock(
+        &state,
+        &video_id,
+        &meta,
+        &headers,
+        client,
+        query.access.as_deref(),
+    )
+    .await?;
+    let tracks = captions::tracks(&state.storage, &video_id).await?;
+    Ok(Json(CaptionTracksResponse { tracks }))
 }
 
-#[derive(Debug, Clone)]
-pub struct RangeHeader(Option<String>);
+/// The video's skip segments, for players that skip them without reading
+/// the HLS date ranges.
+pub async fn get_skip_segments(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+    Query(query): Query<PlaybackQuery>,
+) -> Result<Json<SkipSegmentList>, AppError> {
+    let video_id =
+        Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid video identifier"))?;
+    verify_playback(&video_id, query.token.as_deref())?;
+    let meta = metadata::load(&state.storage, &video_id).await?;
+    unlock(
+        &state,
+        &video_id,
+        &meta,
+        &headers,
+        client,
+        query.access.as_deref(),
+    )
+    .await?;
+    Ok(Json(SkipSegmentList {
+        segments: meta.skip_segments,
+    }))
+}
 
-impl RangeHeader {
-    pub fn new(value: Option<String>) -> Self {
-        Self(value)
-    }
+/// Serves one track of the caption set, named `<language>.vtt`.
+pub async fn get_caption_track(
+    State(state): State<AppState>,
+    AxumPath((id, track)): AxumPath<(String, String)>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+    Query(query): Query<PlaybackQuery>,
+) -> Result<Response, AppError> {
+    let video_id =
+        Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid video identifier"))?;
+    verify_playback(&video_id, query.token.as_deref())?;
+    let meta = metadata::load(&state.storage, &video_id).await?;
+    unlock(
+        &state,
+        &video_id,
+        &meta,
+        &headers,
+        client,
+        query.access.as_deref(),
+    )
+    .await?;
+    let language = track
+        .strip_suffix(".vtt")
+        .filter(|language| captions::is_track_language(language))
+        .ok_or_else(|| AppError::not_found(format!("no caption track named {track}")))?;
+    let path = state
+        .storage
+        .captions_dir(&video_id)
+        .join(format!("{language}.vtt"));
+    Ok(with_custom_headers(serve_static_file(path).await?, &meta))
+}
 
-    pub fn as_deref(&self) -> Option<&str> {
-        self.0.as_deref()
-    }
+fn partial_encodes_enabled() -> bool {
+    config::var("VIDEO_SERVE_PARTIAL_ENCODES")
+        .map(|value| matches!(value.trim(), "1" | "true" | "yes" | "on"))
+        .unwrap_or(false)
 }
 
-impl<S> FromRequestParts<S> for RangeHeader
-where
-    S: Send + Sync,
-{
-    type Rejection = AppError;
-
-    fn from_request_parts(
-        parts: &mut http::request::Parts,
-        _state: &S,
-    ) -> impl Future<Output = Result<Self, Self::Rejection>> + Send {
-        let range = parts
-            .headers
-            .get(http::header::RANGE)
-            .map(|value| value.to_str().map(|s| s.to_owned()))
-            .transpose()
-            .map_err(|_| AppError::validation("invalid Range header"));
-
-        async move { range.map(RangeHeader) }
+/// Adds the video's custom response headers. Names and values were validated
+/// when they were stored; anything that no longer parses is skipped.
+pub(crate) fn with_custom_headers(mut response: Response, meta: &VideoMetadata) -> Response {
+    for (name, value) in &meta.response_headers {
+        if let (Ok(name), Ok(value)) = (
+            http::HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            response.headers_mut().insert(name, value);
+        }
     }
+    response
 }
 
 // Tests for this module live under `tests/` to keep source files focused.
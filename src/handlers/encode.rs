@@ -0,0 +1,256 @@
+use std::path::Path;
+
+use tokio::fs;
+use uuid::Uuid;
+
+use super::approval::quick_proxy;
+use super::pipeline::{charge_usage, run_hooks};
+use crate::{
+    blocking, dedup,
+    digest::SourceDigest,
+    error::AppError,
+    hooks::HookPoint,
+    jobs::{EncodeSummary, JobStage},
+    metadata,
+    policy::PolicyRequest,
+    shedding::TranscodePermit,
+    state::AppState,
+    storage::ensure_parent,
+    transcode::{
+        EncodeParams, probe_source, process_video, render_audio_visual, render_stills,
+        run_optional_stages, salvage_point, truncate_source,
+    },
+};
+
+/// Asks the configured ingest policy about the downloaded source. Returns the
+/// encode settings to use, or a validation error when the policy rejects it.
+async fn apply_policy(
+    state: &AppState,
+    id: Uuid,
+    source: Option<&str>,
+    input: &Path,
+    encode: Option<EncodeParams>,
+) -> Result<Option<EncodeParams>, AppError> {
+    let Some(policy) = state.policy.clone() else {
+        return Ok(encode);
+    };
+
+    let params = encode.unwrap_or_default().sanitized();
+    let request = PolicyRequest {
+        video_id: id,
+        source: source.map(str::to_string),
+        probe: probe_source(&state.process_runner, input).await?,
+        transcode: params.into(),
+    };
+    let decision = blocking::run(move || policy.evaluate(&request))
+        .await
+        .map_err(|err| AppError::dependency(format!("ingest policy panicked: {err}")))??;
+    tracing::debug!(%id, verdict = ?decision.decision, "ingest policy evaluated");
+    decision.apply(params).map(Some)
+}
+
+/// Runs the post-encode and pre-publish hooks, marks the primary job complete
+/// and indexes its source for deduplication, then works through its optional
+/// stages once they get a transcode slot.
+async fn finish_pipeline(
+    state: &AppState,
+    id: Uuid,
+    source: Option<&str>,
+    digest: &SourceDigest,
+    encode: Option<&EncodeParams>,
+    summary: EncodeSummary,
+) -> Result<(), AppError> {
+    let download = state.storage.download_path(&id);
+    for point in [HookPoint::PostEncode, HookPoint::PrePublish] {
+        run_hooks(
+            state,
+            id,
+            point,
+            source,
+            Some(&download),
+            Some(digest),
+            encode,
+            Some(&summary),
+        )
+        .await?;
+    }
+    tracing::info!(
+        %id,
+        encoder = %summary.encoder,
+        download_bytes = summary.download_bytes,
+        compression_ratio = ?summary.compression_ratio,
+        encode_wall_seconds = summary.encode_wall_seconds,
+        "encode finished"
+    );
+    let account = metadata::load(&state.storage, &id).await?.account;
+    if let Some(account) = &account {
+        let minutes = summary.encode_wall_seconds / 60.0;
+        charge_usage(state, id, account, 0, minutes).await;
+    }
+    state.jobs.set_summary(id, summary).await?;
+    state.jobs.complete(id).await?;
+    if let Some(account) = &account
+        && let Err(err) = dedup::record(&state.storage, account, &digest.sha256, id).await
+    {
+        tracing::warn!(%id, error = %err, "failed to index source for deduplication");
+    }
+    let optional = async {
+        let group = state.jobs.group_status(&id).await?;
+        if group.is_some_and(|group| group.members.len() > 1) {
+            let _slot = transcode_slot(state, id).await?;
+            run_optional_stages(&state.storage, &state.jobs, &state.process_runner, &id).await?;
+        }
+        Ok::<_, AppError>(())
+    };
+    if let Err(err) = optional.await {
+        tracing::error!(%id, error = %err, "optional stages aborted");
+    }
+    Ok(())
+}
+
+/// Waits for a transcode slot, ranked by how far the job's transcode got: a
+/// fresh encode ranks at 0, the optional stages of a finished one at 1 and
+/// go ahead of new encodes.
+pub(super) async fn transcode_slot(
+    state: &AppState,
+    id: Uuid,
+) -> Result<TranscodePermit, AppError> {
+    let progress = match state.jobs.status(&id).await? {
+        Some(status) if status.stage == JobStage::Complete => 1.0,
+        Some(status) if status.stage == JobStage::Transcoding => status.stage_progress,
+        _ => 0.0,
+    };
+    Ok(state.load.transcode_slot(&state.storage, progress).await)
+}
+
+/// Encodes the source, counting the outcome towards the `ffmpeg_failing`
+/// alert.
+async fn encode_source(
+    state: &AppState,
+    id: Uuid,
+    path: &Path,
+    encode: Option<EncodeParams>,
+) -> Result<EncodeSummary, AppError> {
+    let summary = process_video(
+        &state.storage,
+        &state.jobs,
+        &state.process_runner,
+        &id,
+        path,
+        encode,
+    )
+    .await;
+    let summary = match summary {
+        Err(err) if encode.is_some_and(|encode| encode.salvage) => {
+            salvage_encode(state, id, path, encode, err).await
+        }
+        summary => summary,
+    };
+    state.alerts.record_ffmpeg(summary.as_ref().map(|_| ()));
+    summary
+}
+
+/// Encodes again the part of the source before the point where `err` made
+/// the encode fail, and notes the cut on the video. The upload kept by
+/// `keep_source` stays whole.
+async fn salvage_encode(
+    state: &AppState,
+    id: Uuid,
+    path: &Path,
+    encode: Option<EncodeParams>,
+    err: AppError,
+) -> Result<EncodeSummary, AppError> {
+    let Some(seconds) = salvage_point(&err) else {
+        return Err(err);
+    };
+    tracing::warn!(%id, seconds, error = %err, "encode failed; salvaging the decodable part");
+    let truncation = match truncate_source(&state.process_runner, path, seconds, &err).await {
+        Ok(truncation) => truncation,
+        Err(cut_err) => {
+            tracing::warn!(%id, error = %cut_err, "failed to cut the source for salvage");
+            return Err(err);
+        }
+    };
+    let summary = process_video(
+        &state.storage,
+        &state.jobs,
+        &state.process_runner,
+        &id,
+        path,
+        encode,
+    )
+    .await?;
+    let mut meta = metadata::load(&state.storage, &id).await?;
+    meta.truncated = Some(truncation);
+    metadata::save(&state.storage, &id, &meta).await?;
+    Ok(summary)
+}
+
+/// Transcodes the ingested source and publishes the video, once ingest
+/// finished or the job was approved.
+pub(super) async fn transcode_and_publish(
+    state: &AppState,
+    id: Uuid,
+    url: Option<&str>,
+    temp_path: &Path,
+    digest: &SourceDigest,
+    encode: Option<EncodeParams>,
+) -> Result<(), AppError> {
+    let (summary, encode) = {
+        let _slot = transcode_slot(state, id).await?;
+        state.jobs.update_stage(id, JobStage::Transcoding).await?;
+        keep_original(state, id, temp_path, encode.as_ref()).await?;
+        render_source(state, id, temp_path, encode.as_ref()).await?;
+        let encode = apply_policy(state, id, url, temp_path, encode).await?;
+        let (summary, ()) = tokio::join!(
+            encode_source(state, id, temp_path, encode),
+            quick_proxy(state, id, temp_path, encode.as_ref()),
+        );
+        (summary?, encode)
+    };
+    // The hooks are network calls and the optional stages queue for a slot
+    // of their own, so neither holds the encode's.
+    finish_pipeline(state, id, url, digest, encode.as_ref(), summary).await
+}
+
+/// Keeps the upload as the video's original when `keep_source` asks for it,
+/// before anything is rendered from it in place. Hard-linked when the
+/// incoming area shares the storage volume, copied otherwise.
+pub(super) async fn keep_original(
+    state: &AppState,
+    id: Uuid,
+    path: &Path,
+    encode: Option<&EncodeParams>,
+) -> Result<(), AppError> {
+    let kept = state.storage.source_path(&id);
+    // An approved or retried job kept it on its first run.
+    if !encode.copied().unwrap_or_default().keeps_source() || kept.exists() {
+        return Ok(());
+    }
+    ensure_parent(&kept).await?;
+    if fs::hard_link(path, &kept).await.is_err() {
+        let partial = kept.with_extension("part");
+        if let Err(err) = blocking::copy(path, &partial).await {
+            fs::remove_file(&partial).await.ok();
+            return Err(err.into());
+        }
+        fs::rename(&partial, &kept).await?;
+    }
+    Ok(())
+}
+
+/// Turns stills and, when requested, audio-only sources into video in place
+/// before the policy and encoder see them.
+pub(super) async fn render_source(
+    state: &AppState,
+    id: Uuid,
+    path: &Path,
+    encode: Option<&EncodeParams>,
+) -> Result<(), AppError> {
+    let storage = &state.storage;
+    let runner = &state.process_runner;
+    if !render_stills(storage, &state.jobs, runner, &id, path, encode).await? {
+        render_audio_visual(storage, &state.jobs, runner, &id, path, encode).await?;
+    }
+    Ok(())
+}
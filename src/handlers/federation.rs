@@ -0,0 +1,94 @@
+use axum::{
+    body::Body,
+    extract::{Path, Request, State},
+    http::{HeaderValue, Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tokio::fs::File;
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+use crate::{
+    auth::Scope, error::AppError, federation::FEDERATED_HEADER, replication, state::AppState,
+};
+
+/// Route layer answering `GET` and `HEAD` requests for a video that does
+/// not exist here with a `302` to the federation peer that has it.
+/// Requests from another federated instance are never redirected; with
+/// JWT auth on, a request only counts as one with an `admin` token.
+pub async fn federate(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let from_peer = request.headers().contains_key(FEDERATED_HEADER)
+        && state
+            .auth
+            .authorize(&state.http_client, request.headers(), Scope::Admin)
+            .await
+            .is_ok();
+    let video_id = (matches!(*request.method(), Method::GET | Method::HEAD) && !from_peer)
+        .then(|| federated_video(request.uri().path()))
+        .flatten();
+    let path_and_query = request
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str().to_string());
+    let response = next.run(request).await;
+    let (Some(video_id), Some(path_and_query)) = (video_id, path_and_query) else {
+        return response;
+    };
+    if response.status() != StatusCode::NOT_FOUND || state.storage.video_dir(&video_id).exists() {
+        return response;
+    }
+    let Some(location) = state.federation.locate(video_id, &path_and_query).await else {
+        return response;
+    };
+    let Ok(location) = HeaderValue::from_str(location.as_str()) else {
+        return response;
+    };
+    tracing::debug!(%video_id, location = ?location, "redirecting to federation peer");
+    (StatusCode::FOUND, [(header::LOCATION, location)]).into_response()
+}
+
+/// The video of a `/videos/{id}/...` path.
+fn federated_video(path: &str) -> Option<Uuid> {
+    let id = path.strip_prefix("/videos/")?.split('/').next()?;
+    Uuid::parse_str(id).ok()
+}
+
+/// Streams a video zipped the way replication pushes it, for peers that
+/// cache it on demand.
+pub async fn export_video(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Response, AppError> {
+    let video_id =
+        Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid video identifier"))?;
+    let archive = state.storage.tmp_dir().join(format!(
+        "{}.{}.export.zip",
+        video_id.simple(),
+        Uuid::new_v4().simple()
+    ));
+    let written = replication::write_replica(&state.storage, video_id, &archive).await;
+    let opened = match written {
+        Ok(()) => File::open(&archive).await.map_err(AppError::from),
+        Err(err) => Err(err),
+    };
+    // The open handle keeps the zip readable until the response is sent.
+    if let Err(err) = tokio::fs::remove_file(&archive).await
+        && err.kind() != std::io::ErrorKind::NotFound
+    {
+        tracing::warn!(path = %archive.display(), error = %err, "failed to remove export archive");
+    }
+    let file = opened?;
+    let size = file.metadata().await?.len();
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/zip"),
+            ),
+            (header::CONTENT_LENGTH, HeaderValue::from(size)),
+        ],
+        Body::from_stream(ReaderStream::new(file)),
+    )
+        .into_response())
+}
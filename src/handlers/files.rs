@@ -0,0 +1,248 @@
+use std::{future::Future, path::PathBuf};
+
+use axum::{
+    body::Body,
+    extract::FromRequestParts,
+    http::{self, HeaderValue, StatusCode},
+    response::Response,
+};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, BufReader};
+use tokio_util::io::ReaderStream;
+
+use crate::{error::AppError, transcode::MezzanineCodec};
+
+pub(super) async fn read_text_asset(path: &std::path::Path) -> Result<String, AppError> {
+    tokio::fs::read_to_string(path)
+        .await
+        .map_err(|_| AppError::not_found(format!("asset not found: {}", path.display())))
+}
+
+pub(crate) fn validate_relative_path(path: &str) -> Result<(), AppError> {
+    if path.starts_with('/') || path.contains("..") {
+        return Err(AppError::validation("invalid asset path"));
+    }
+    Ok(())
+}
+
+pub(crate) async fn serve_video_file(
+    path: PathBuf,
+    range_header: Option<&str>,
+    format: MezzanineCodec,
+) -> Result<Response, AppError> {
+    if !path.exists() {
+        return Err(AppError::not_found(format!(
+            "video not found under {}",
+            path.display()
+        )));
+    }
+
+    let mut file = File::open(&path).await?;
+    let metadata = file.metadata().await?;
+    let file_size = metadata.len();
+
+    let range = if let Some(range) = range_header {
+        Some(parse_range(range, file_size)?)
+    } else {
+        None
+    };
+
+    let (status, body, content_length, content_range) = if let Some(range) = range {
+        file.seek(std::io::SeekFrom::Start(range.start)).await?;
+        let reader = BufReader::new(file).take(range.length);
+        let body = Body::from_stream(ReaderStream::new(reader));
+        let content_range = format!("bytes {}-{}/{}", range.start, range.end, file_size);
+        (
+            StatusCode::PARTIAL_CONTENT,
+            body,
+            range.length,
+            Some(content_range),
+        )
+    } else {
+        // Bounded by the size seen at open time in case the file is still growing.
+        let body = Body::from_stream(ReaderStream::new(file.take(file_size)));
+        (StatusCode::OK, body, file_size, None)
+    };
+
+    let mut response = Response::builder().status(status).body(body).unwrap();
+
+    response.headers_mut().insert(
+        http::header::CONTENT_TYPE,
+        HeaderValue::from_static(format.content_type()),
+    );
+    response.headers_mut().insert(
+        http::header::ACCEPT_RANGES,
+        HeaderValue::from_static("bytes"),
+    );
+    response.headers_mut().insert(
+        http::header::CONTENT_LENGTH,
+        HeaderValue::from_str(&content_length.to_string()).unwrap_or(HeaderValue::from_static("0")),
+    );
+    if let Some(content_range) = content_range {
+        response.headers_mut().insert(
+            http::header::CONTENT_RANGE,
+            HeaderValue::from_str(&content_range).unwrap_or(HeaderValue::from_static("bytes */0")),
+        );
+    }
+    response.headers_mut().insert(
+        http::header::CONTENT_DISPOSITION,
+        HeaderValue::from_str(&format!(
+            "inline; filename=\"{}.{}\"",
+            path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or("video"),
+            format.extension()
+        ))
+        .unwrap_or(HeaderValue::from_static("inline")),
+    );
+
+    Ok(response)
+}
+
+pub(super) async fn serve_static_file(path: PathBuf) -> Result<Response, AppError> {
+    if !path.exists() {
+        return Err(AppError::not_found(format!(
+            "asset not found: {}",
+            path.display()
+        )));
+    }
+
+    let file = File::open(&path).await?;
+    let body = Body::from_stream(ReaderStream::new(file));
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .body(body)
+        .unwrap();
+
+    if path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.eq_ignore_ascii_case("ts"))
+        .unwrap_or(false)
+    {
+        // mime_guess maps `.ts` to TypeScript.
+        response.headers_mut().insert(
+            http::header::CONTENT_TYPE,
+            HeaderValue::from_static("video/mp2t"),
+        );
+    } else if let Some(mime) = mime_guess::from_path(&path).first() {
+        if let Ok(value) = HeaderValue::from_str(mime.as_ref()) {
+            response
+                .headers_mut()
+                .insert(http::header::CONTENT_TYPE, value);
+        }
+    } else if path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.eq_ignore_ascii_case("m3u8"))
+        .unwrap_or(false)
+    {
+        response.headers_mut().insert(
+            http::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/vnd.apple.mpegurl"),
+        );
+    } else if path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.eq_ignore_ascii_case("mpd"))
+        .unwrap_or(false)
+    {
+        response.headers_mut().insert(
+            http::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/dash+xml"),
+        );
+    }
+
+    Ok(response)
+}
+
+pub(super) fn text_response(body: String, content_type: &'static str) -> Response {
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(body))
+        .unwrap();
+    response.headers_mut().insert(
+        http::header::CONTENT_TYPE,
+        HeaderValue::from_static(content_type),
+    );
+    response
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ByteRange {
+    start: u64,
+    end: u64,
+    length: u64,
+}
+
+fn parse_range(raw: &str, file_size: u64) -> Result<ByteRange, AppError> {
+    let raw = raw.trim();
+    if !raw.starts_with("bytes=") {
+        return Err(
+            AppError::validation("unsupported range unit").with_code("range_unit_unsupported")
+        );
+    }
+    let range = &raw[6..];
+    let mut parts = range.splitn(2, '-');
+    let start_str = parts
+        .next()
+        .ok_or_else(|| AppError::validation("invalid range format"))?;
+    let end_str = parts
+        .next()
+        .ok_or_else(|| AppError::validation("invalid range format"))?;
+
+    let start = start_str
+        .parse::<u64>()
+        .map_err(|_| AppError::validation("range start must be numeric"))?;
+
+    let end = if end_str.is_empty() {
+        file_size.saturating_sub(1)
+    } else {
+        end_str
+            .parse::<u64>()
+            .map_err(|_| AppError::validation("range end must be numeric"))?
+    };
+
+    if start > end || end >= file_size {
+        return Err(AppError::validation("invalid range bounds")
+            .with_code("range_invalid")
+            .with_param("max", file_size.saturating_sub(1)));
+    }
+
+    let length = end - start + 1;
+    Ok(ByteRange { start, end, length })
+}
+
+#[derive(Debug, Clone)]
+pub struct RangeHeader(pub(super) Option<String>);
+
+impl RangeHeader {
+    pub fn new(value: Option<String>) -> Self {
+        Self(value)
+    }
+
+    pub fn as_deref(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+}
+
+impl<S> FromRequestParts<S> for RangeHeader
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    fn from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> impl Future<Output = Result<Self, Self::Rejection>> + Send {
+        let range = parts
+            .headers
+            .get(http::header::RANGE)
+            .map(|value| value.to_str().map(|s| s.to_owned()))
+            .transpose()
+            .map_err(|_| AppError::validation("invalid Range header"));
+
+        async move { range.map(RangeHeader) }
+    }
+}
@@ -0,0 +1,171 @@
+use axum::{Extension, Json, extract::State, http::HeaderMap};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    aria2::FileSelection,
+    auth::Claims,
+    callbacks,
+    captions::SubtitleRequest,
+    cookies,
+    error::AppError,
+    jobs::{JobStage, PreferredCodec, YtDlpAuth, YtDlpOptions},
+    s3::S3Credentials,
+    state::AppState,
+    storage::Storage,
+    transcode::EncodeParams,
+    usage,
+};
+
+use super::pipeline::create_pipeline_job;
+use super::remote::submit_remote_job;
+use super::transcode_options::ClientTranscodeOptions;
+use super::upload::{UploadResponse, build_upload_response, validate_client_data};
+use super::ytdlp::spawn_ytdlp_pipeline;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteUploadRequest {
+    pub url: String,
+    #[serde(default)]
+    pub transcode: Option<ClientTranscodeOptions>,
+    #[serde(default)]
+    pub callback_url: Option<String>,
+    /// Any JSON, echoed in the job's status and callbacks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_data: Option<Value>,
+    /// HTTP(S) proxy URL for this download, overriding `VIDEO_HTTP_PROXY`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Keys for an `s3://` URL, overriding the `AWS_*` environment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s3_credentials: Option<S3Credentials>,
+    /// Which file of a multi-file torrent to ingest.
+    #[serde(flatten)]
+    pub files: FileSelection,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct YtDlpDownloadRequest {
+    pub url: String,
+    #[serde(default)]
+    pub transcode: Option<ClientTranscodeOptions>,
+    #[serde(default)]
+    pub callback_url: Option<String>,
+    /// Subtitle tracks to add to the video's caption set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subtitles: Option<SubtitleRequest>,
+    /// Import SponsorBlock segments as the video's skip segments.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub skip_segments: bool,
+    /// Any JSON, echoed in the job's status and callbacks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_data: Option<Value>,
+    /// Tallest format to fetch, e.g. `1080` to leave 4K sources alone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_height: Option<u32>,
+    /// Video codec to prefer among formats of the same height.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefer_codec: Option<PreferredCodec>,
+    /// Raw yt-dlp format selector, e.g. `bv*[vcodec^=avc1]+ba`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    /// Cookies or login for sites that need one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<YtDlpAuth>,
+    /// Proxy URL for this download, overriding `VIDEO_HTTP_PROXY`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+}
+
+impl YtDlpDownloadRequest {
+    /// The request's yt-dlp options, validated, with its cookies file
+    /// checked to exist.
+    pub(super) async fn options(&self, storage: &Storage) -> Result<YtDlpOptions, AppError> {
+        let options = YtDlpOptions {
+            subtitles: self.subtitles.clone().unwrap_or_default(),
+            skip_segments: self.skip_segments,
+            max_height: self.max_height,
+            prefer_codec: self.prefer_codec,
+            format: self.format.clone(),
+            auth: self.auth.clone().unwrap_or_default(),
+            proxy: self.proxy.clone(),
+        };
+        options.validate()?;
+        if let Some(name) = &options.auth.cookies {
+            cookies::ensure_exists(storage, name).await?;
+        }
+        Ok(options)
+    }
+}
+
+pub async fn upload_remote(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    claims: Option<Extension<Claims>>,
+    Json(payload): Json<RemoteUploadRequest>,
+) -> Result<Json<UploadResponse>, AppError> {
+    let encode = payload.transcode.map(EncodeParams::from);
+    let callback_url = payload
+        .callback_url
+        .as_deref()
+        .map(callbacks::validate_url)
+        .transpose()?;
+    let client_data = validate_client_data(payload.client_data)?;
+    let account = usage::account_key(
+        &headers,
+        claims.as_deref(),
+        state.rate_limits.trusts_forwarded_headers(),
+    );
+    let id = submit_remote_job(
+        &state,
+        payload.url,
+        payload.proxy,
+        payload.s3_credentials,
+        payload.files,
+        encode,
+        callback_url,
+        Some(&account),
+        client_data,
+    )
+    .await?;
+
+    Ok(Json(build_upload_response(id)))
+}
+
+pub async fn download_via_ytdlp(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    claims: Option<Extension<Claims>>,
+    Json(payload): Json<YtDlpDownloadRequest>,
+) -> Result<Json<UploadResponse>, AppError> {
+    let url = Url::parse(&payload.url)
+        .map_err(|err| AppError::validation(format!("invalid url: {err}")))?;
+    let encode = payload.transcode.map(EncodeParams::from);
+    let callback_url = payload
+        .callback_url
+        .as_deref()
+        .map(callbacks::validate_url)
+        .transpose()?;
+    let options = payload.options(&state.storage).await?;
+    let client_data = validate_client_data(payload.client_data)?;
+    let account = usage::account_key(
+        &headers,
+        claims.as_deref(),
+        state.rate_limits.trusts_forwarded_headers(),
+    );
+    let id = create_pipeline_job(
+        &state,
+        Some(JobStage::Downloading),
+        Some(&payload.url),
+        encode.as_ref(),
+        Some(&account),
+        client_data,
+    )
+    .await?;
+
+    let url_string: String = url.into();
+    spawn_ytdlp_pipeline(state.clone(), id, url_string, options, encode, callback_url);
+
+    Ok(Json(build_upload_response(id)))
+}
@@ -0,0 +1,63 @@
+use axum::{
+    extract::{Path as AxumPath, State},
+    http::{HeaderMap, StatusCode},
+};
+use uuid::Uuid;
+
+use super::access::authorize_owner;
+use crate::{
+    error::AppError, locks::LockManager, rate_limit::ClientAddr, replication::ReplicationEvent,
+    state::AppState,
+};
+
+/// Deletes a video with its renditions, pending upload and share links.
+/// Refused while a job for it is still running.
+pub async fn delete_video(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+) -> Result<StatusCode, AppError> {
+    let (video_id, _) = authorize_owner(&state, &id, &headers, client).await?;
+    if !remove_video(&state, video_id).await? {
+        return Err(AppError::not_found(format!("video {video_id} not found")));
+    }
+    state
+        .replication
+        .emit(ReplicationEvent::Deleted { video_id });
+    tracing::info!(%video_id, "video deleted");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Deletes a video and revokes its share links, refused with `video_in_use`
+/// while a job for it is still running. Holds the video's import lock, so
+/// a replica cannot be swapped in halfway. Returns whether anything existed.
+pub(crate) async fn remove_video(state: &AppState, video_id: Uuid) -> Result<bool, AppError> {
+    let _guard = state
+        .storage
+        .locks()
+        .acquire(&LockManager::video_key(&video_id, "import"))
+        .await?;
+    let active = match state.jobs.group_status(&video_id).await? {
+        Some(group) => group
+            .members
+            .iter()
+            .find(|member| !member.status.stage.is_terminal())
+            .map(|member| member.status.stage),
+        None => None,
+    };
+    if let Some(stage) = active {
+        return Err(AppError::conflict(format!(
+            "video {video_id} cannot be deleted while its job is {}; cancel it first",
+            stage.as_str()
+        ))
+        .with_code("video_in_use")
+        .with_param("id", video_id.to_string()));
+    }
+
+    let removed = state.storage.delete_video(&video_id).await?;
+    for link in state.shares.list(&video_id).await? {
+        state.shares.revoke(&video_id, &link.share_id).await?;
+    }
+    Ok(removed)
+}
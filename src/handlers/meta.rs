@@ -0,0 +1,367 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use axum::{
+    Json,
+    body::{Body, to_bytes},
+    extract::{Path as AxumPath, Query, State},
+    http::{HeaderMap, HeaderName, HeaderValue, header},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    catalog::{self, StoredVideo},
+    digest::SourceDigest,
+    error::AppError,
+    metadata::{self, ImportedDetails, Truncation, VideoMetadata},
+    password::PASSWORD_HEADER,
+    rate_limit::ClientAddr,
+    skip_segments::{self, SkipSegment},
+    state::AppState,
+    storage::ensure_dir,
+    tags,
+    transcode::{MediaInfo, PosterChoice, poster_from_frame, poster_from_image, probe_media_info},
+};
+
+use super::access::authorize_owner;
+
+const MAX_ATTRIBUTES_BYTES: usize = 16 * 1024;
+const MAX_RESPONSE_HEADERS: usize = 20;
+const MAX_HEADER_VALUE_LEN: usize = 1024;
+const DEFAULT_VIDEO_PAGE: usize = 50;
+const MAX_VIDEO_PAGE: usize = 500;
+const MAX_POSTER_REQUEST_BYTES: usize = 4 * 1024;
+const MAX_POSTER_IMAGE_BYTES: usize = 16 * 1024 * 1024;
+
+/// `GET /videos` parameters. Videos are ordered by creation time; `order` is
+/// `desc` (default) or `asc`.
+#[derive(Debug, Default, Deserialize)]
+pub struct VideoListQuery {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    pub order: Option<String>,
+    /// Only videos carrying this tag.
+    pub tag: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VideoListResponse {
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub videos: Vec<StoredVideo>,
+}
+
+/// Merge patch for a video's attributes and custom headers: keys set to
+/// `null` are removed, others are added or replaced.
+#[derive(Debug, Default, Deserialize)]
+pub struct PatchMetaRequest {
+    #[serde(default)]
+    pub attributes: BTreeMap<String, Value>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, Option<String>>,
+}
+
+/// Body of `PUT /videos/{id}/segments` and of both segment routes' responses.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SkipSegmentList {
+    #[serde(default)]
+    pub segments: Vec<SkipSegment>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VideoMetaResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poster: Option<PosterChoice>,
+    pub id: Uuid,
+    pub tags: BTreeSet<String>,
+    pub attributes: BTreeMap<String, Value>,
+    pub headers: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<SourceDigest>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub imported: Option<ImportedDetails>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated: Option<Truncation>,
+}
+
+impl VideoMetaResponse {
+    fn new(id: Uuid, meta: VideoMetadata) -> Self {
+        Self {
+            id,
+            tags: meta.tags,
+            attributes: meta.attributes,
+            headers: meta.response_headers,
+            source: meta.source,
+            imported: meta.imported,
+            truncated: meta.truncated,
+            poster: meta.poster,
+        }
+    }
+}
+
+/// Every stored video with its size, creation time and existing assets.
+pub async fn list_videos(
+    State(state): State<AppState>,
+    Query(query): Query<VideoListQuery>,
+) -> Result<Json<VideoListResponse>, AppError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_VIDEO_PAGE)
+        .clamp(1, MAX_VIDEO_PAGE);
+    let offset = query.offset.unwrap_or(0);
+    let tag = query.tag.as_deref().map(tags::normalize_tag).transpose()?;
+    let mut videos = catalog::list(&state.storage, tag.as_deref()).await?;
+    match query.order.as_deref() {
+        None | Some("desc") => videos.reverse(),
+        Some("asc") => {}
+        Some(other) => {
+            return Err(
+                AppError::validation(format!("order must be asc or desc, got {other:?}"))
+                    .with_code("order_invalid"),
+            );
+        }
+    }
+    let total = videos.len();
+    let videos = videos.into_iter().skip(offset).take(limit).collect();
+    Ok(Json(VideoListResponse {
+        total,
+        offset,
+        limit,
+        videos,
+    }))
+}
+
+pub async fn get_video_meta(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+) -> Result<Json<VideoMetaResponse>, AppError> {
+    let (video_id, meta) = authorize_owner(&state, &id, &headers, client).await?;
+    Ok(Json(VideoMetaResponse::new(video_id, meta)))
+}
+
+/// Container and stream details of the download. The ffprobe report is
+/// cached in `info.json` next to it and refreshed when the download is
+/// rewritten.
+pub async fn get_video_info(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+) -> Result<Json<MediaInfo>, AppError> {
+    let (video_id, _) = authorize_owner(&state, &id, &headers, client).await?;
+    let download = state.storage.download_path(&video_id);
+    let written = match fs::metadata(&download).await {
+        Ok(meta) => meta.modified()?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(
+                AppError::not_found(format!("video {video_id} has no download yet"))
+                    .with_code("download_missing")
+                    .with_param("id", video_id.to_string()),
+            );
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    let cache = state.storage.info_path(&video_id);
+    if let Ok(cached) = fs::metadata(&cache).await
+        && cached.modified().is_ok_and(|probed| probed >= written)
+        && let Ok(bytes) = fs::read(&cache).await
+        && let Ok(info) = serde_json::from_slice::<MediaInfo>(&bytes)
+    {
+        return Ok(Json(info));
+    }
+
+    let info = probe_media_info(&state.process_runner, &download).await?;
+    let tmp = cache.with_extension("json.tmp");
+    let stored = match serde_json::to_vec_pretty(&info) {
+        Ok(bytes) => match fs::write(&tmp, bytes).await {
+            Ok(()) => fs::rename(&tmp, &cache).await,
+            Err(err) => Err(err),
+        },
+        Err(err) => Err(err.into()),
+    };
+    if let Err(err) = stored {
+        tracing::warn!(%video_id, error = %err, "failed to cache media info");
+    }
+    Ok(Json(info))
+}
+
+pub async fn patch_video_meta(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+    Json(request): Json<PatchMetaRequest>,
+) -> Result<Json<VideoMetaResponse>, AppError> {
+    let (video_id, mut meta) = authorize_owner(&state, &id, &headers, client).await?;
+    merge_attributes(&mut meta, request.attributes)?;
+
+    for (name, value) in request.headers {
+        let name = validate_header_name(&name)?;
+        match value {
+            Some(value) => {
+                validate_header_value(&name, &value)?;
+                meta.response_headers.insert(name, value);
+            }
+            None => {
+                meta.response_headers.remove(&name);
+            }
+        }
+    }
+    if meta.response_headers.len() > MAX_RESPONSE_HEADERS {
+        return Err(AppError::validation(format!(
+            "a video carries at most {MAX_RESPONSE_HEADERS} custom headers"
+        )));
+    }
+
+    metadata::save(&state.storage, &video_id, &meta).await?;
+    tracing::info!(%video_id, "video attributes updated");
+    Ok(Json(VideoMetaResponse::new(video_id, meta)))
+}
+
+/// Replaces the video's skip segments; an empty list removes them.
+pub async fn put_skip_segments(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+    Json(request): Json<SkipSegmentList>,
+) -> Result<Json<SkipSegmentList>, AppError> {
+    let (video_id, mut meta) = authorize_owner(&state, &id, &headers, client).await?;
+    let mut segments = request.segments;
+    skip_segments::validate(&mut segments)?;
+    meta.skip_segments = segments;
+    metadata::save(&state.storage, &video_id, &meta).await?;
+    tracing::info!(%video_id, count = meta.skip_segments.len(), "skip segments updated");
+    Ok(Json(SkipSegmentList {
+        segments: meta.skip_segments,
+    }))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PosterFrameRequest {
+    /// Seconds into the video.
+    pub t: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PosterResponse {
+    pub poster: PosterChoice,
+    pub thumbnail_url: String,
+}
+
+/// Replaces the poster `thumbnail.jpg`. A JSON body `{"t": <seconds>}`
+/// picks a frame of the download; a JPEG, PNG or WebP body is used as the
+/// image. Later transcodes keep the poster, and frames requested without a
+/// time are taken from it.
+pub async fn put_thumbnail(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+    body: Body,
+) -> Result<Json<PosterResponse>, AppError> {
+    let (video_id, mut meta) = authorize_owner(&state, &id, &headers, client).await?;
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+
+    let poster = match content_type.as_str() {
+        "application/json" => {
+            let bytes = to_bytes(body, MAX_POSTER_REQUEST_BYTES)
+                .await
+                .map_err(|err| AppError::validation(format!("invalid request body: {err}")))?;
+            let request: PosterFrameRequest = serde_json::from_slice(&bytes)
+                .map_err(|err| AppError::validation(format!("invalid poster request: {err}")))?;
+            poster_from_frame(&state.storage, &state.process_runner, &video_id, request.t).await?;
+            PosterChoice::Frame { t: request.t }
+        }
+        "image/jpeg" | "image/png" | "image/webp" => {
+            let bytes = to_bytes(body, MAX_POSTER_IMAGE_BYTES).await.map_err(|_| {
+                AppError::validation(format!(
+                    "poster images must be at most {MAX_POSTER_IMAGE_BYTES} bytes"
+                ))
+                .with_code("poster_too_large")
+                .with_param("max_bytes", MAX_POSTER_IMAGE_BYTES)
+            })?;
+            let tmp_dir = state.storage.tmp_dir();
+            ensure_dir(&tmp_dir).await?;
+            let upload = tmp_dir.join(format!("{video_id}.poster.{}", Uuid::new_v4().simple()));
+            fs::write(&upload, &bytes).await?;
+            let written =
+                poster_from_image(&state.storage, &state.process_runner, &video_id, &upload).await;
+            fs::remove_file(&upload).await.ok();
+            written?;
+            PosterChoice::Image
+        }
+        other => {
+            return Err(AppError::validation(format!(
+                "posters are sent as application/json or a JPEG, PNG or WebP image, not {other:?}"
+            ))
+            .with_code("poster_content_type_invalid"));
+        }
+    };
+
+    meta.poster = Some(poster);
+    metadata::save(&state.storage, &video_id, &meta).await?;
+    tracing::info!(%video_id, ?poster, "poster replaced");
+    Ok(Json(PosterResponse {
+        poster,
+        thumbnail_url: format!("/videos/{video_id}/thumbnail"),
+    }))
+}
+
+/// Applies an attributes merge patch, keeping the result within
+/// `MAX_ATTRIBUTES_BYTES`.
+pub(super) fn merge_attributes(
+    meta: &mut VideoMetadata,
+    attributes: BTreeMap<String, Value>,
+) -> Result<(), AppError> {
+    for (key, value) in attributes {
+        if value.is_null() {
+            meta.attributes.remove(&key);
+        } else {
+            meta.attributes.insert(key, value);
+        }
+    }
+    let size = serde_json::to_vec(&meta.attributes)
+        .map_err(std::io::Error::from)?
+        .len();
+    if size > MAX_ATTRIBUTES_BYTES {
+        return Err(AppError::validation(format!(
+            "attributes must serialize to at most {MAX_ATTRIBUTES_BYTES} bytes"
+        )));
+    }
+    Ok(())
+}
+
+/// Custom headers must be `x-` headers that do not shadow ones the server
+/// sets or reads itself.
+fn validate_header_name(name: &str) -> Result<String, AppError> {
+    let name = name.trim().to_ascii_lowercase();
+    let parsed = HeaderName::from_bytes(name.as_bytes())
+        .map_err(|_| AppError::validation(format!("invalid header name {name:?}")))?;
+    if !name.starts_with("x-") || name.starts_with("x-vrs-") || parsed == PASSWORD_HEADER {
+        return Err(AppError::validation(format!(
+            "custom header {name:?} must start with x- and must not start with x-vrs-"
+        )));
+    }
+    Ok(name)
+}
+
+fn validate_header_value(name: &str, value: &str) -> Result<(), AppError> {
+    if value.len() > MAX_HEADER_VALUE_LEN || HeaderValue::from_str(value).is_err() {
+        return Err(AppError::validation(format!(
+            "value of header {name} must be at most {MAX_HEADER_VALUE_LEN} bytes of visible ASCII"
+        )));
+    }
+    Ok(())
+}
@@ -1,11 +1,93 @@
+mod access;
+mod admin;
+mod approval;
+mod auth;
+mod collections;
+mod control;
 mod delivery;
+mod encode;
+mod federation;
+mod files;
+mod ingest;
+mod lifecycle;
+mod meta;
+mod multipart;
 mod pipeline;
+mod playlist;
+mod remote;
+mod replication;
+mod sessions;
+mod shares;
 mod status;
+mod streaming;
+mod tags;
+mod transcode_options;
+mod tus;
 mod upload;
+mod usage;
+mod ytdlp;
 
-pub use delivery::{RangeHeader, download_video, get_dash_asset, get_hls_asset};
-pub use status::job_status;
-pub use upload::{
-    ClientTranscodeOptions, RemoteUploadRequest, UploadResponse, YtDlpDownloadRequest,
-    download_via_ytdlp, upload_multipart, upload_remote,
+pub use access::{
+    AccessTokenResponse, SetPasswordRequest, create_access_token, remove_video_password,
+    set_video_password,
 };
+pub use admin::{
+    AdminOverview, BandwidthQuery, BandwidthRollup, DeleteTmpQuery, admin_alerts, admin_overview,
+    bandwidth_rollup, capabilities, clear_capability_failures, clear_tmp_orphans, delete_cookies,
+    delete_tmp_item, list_cookies, metrics, put_cookies, reload_config, tmp_workspace,
+};
+pub(crate) use approval::approve_awaiting_job;
+pub use auth::require_scope;
+pub use collections::{
+    CollectionPlaylistQuery, CreateCollectionRequest, UpdateCollectionRequest, collection_embed,
+    collection_playlist, create_collection, delete_collection, get_collection, list_collections,
+    update_collection,
+};
+pub(crate) use control::{cancel_running_job, retry_failed_job};
+pub use delivery::{
+    ArchivePendingResponse, CaptionTracksResponse, PlaybackQuery, ThumbnailQuery,
+    download_partial_video, download_video, get_caption_track, get_hls_archive, get_preview,
+    get_proxy, get_skip_segments, get_source, get_storyboard_asset, get_thumbnail,
+    list_caption_tracks,
+};
+pub use federation::{export_video, federate};
+pub use files::RangeHeader;
+pub use ingest::{RemoteUploadRequest, YtDlpDownloadRequest, download_via_ytdlp, upload_remote};
+pub use lifecycle::delete_video;
+pub(crate) use lifecycle::remove_video;
+pub use meta::{
+    PatchMetaRequest, PosterFrameRequest, PosterResponse, SkipSegmentList, VideoListQuery,
+    VideoListResponse, VideoMetaResponse, get_video_info, get_video_meta, list_videos,
+    patch_video_meta, put_skip_segments, put_thumbnail,
+};
+pub use multipart::upload_multipart;
+pub(crate) use pipeline::{
+    create_pipeline_job, record_source_digest, spawn_local_pipeline, take_over_job,
+};
+pub use playlist::{BatchResponse, YtDlpPlaylistRequest, download_playlist_via_ytdlp};
+pub(crate) use remote::submit_remote_job;
+pub use replication::{
+    ReplicateRequest, ReplicationReport, delete_import, import_video, replicate_video,
+    replication_status,
+};
+pub use sessions::{
+    CompleteUploadSessionRequest, CreateUploadSessionRequest, UploadPartResponse,
+    UploadSessionResponse, complete_upload_session, create_upload_session, put_upload_part,
+};
+pub use shares::{
+    CreateShareRequest, ShareResponse, create_share, list_shares, revoke_share, share_dash_asset,
+    share_download, share_hls_asset, share_page,
+};
+pub use status::{
+    HealthResponse, JobListQuery, JobListResponse, JobSocketQuery, approve_job, batch_status,
+    cancel_job, health, job_diagnostics, job_diagnostics_frame, job_group_status, job_socket,
+    job_status, list_jobs, retry_job,
+};
+pub use streaming::{HlsQuery, get_dash_asset, get_hls_asset};
+pub use tags::{
+    AddTagsRequest, add_video_tags, get_video_tags, list_tagged_videos, list_tags, remove_video_tag,
+};
+pub use transcode_options::ClientTranscodeOptions;
+pub use tus::{tus_append, tus_create, tus_offset, tus_protocol};
+pub use upload::{BodyLimits, UploadOptions, UploadResponse};
+pub use usage::get_usage;
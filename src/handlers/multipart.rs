@@ -0,0 +1,128 @@
+use axum::{
+    Extension, Json,
+    extract::{Multipart, RawQuery, State},
+    http::HeaderMap,
+};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::{
+    auth::Claims, dedup, digest::DigestWriter, error::AppError, jobs::JobStage, metadata,
+    state::AppState, storage::ensure_parent, usage,
+};
+
+use super::pipeline::{create_pipeline_job, record_source_digest, spawn_local_pipeline};
+use super::transcode_options::ClientTranscodeOptions;
+use super::upload::{
+    MULTIPART_OVERHEAD_BYTES, PreparedUpload, UploadOptions, UploadResponse, abandon_upload,
+    build_upload_response, check_content_length, upload_too_large,
+};
+
+/// Takes the file and an optional `options` part. Transcode options may also
+/// come from the query string or `X-VRS-Transcode` headers; fields set in the
+/// `options` part win over the query, which wins over the headers. A file the
+/// caller already published is not transcoded again; the existing video is
+/// returned instead.
+pub async fn upload_multipart(
+    State(state): State<AppState>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+    claims: Option<Extension<Claims>>,
+    mut multipart: Multipart,
+) -> Result<Json<UploadResponse>, AppError> {
+    check_content_length(&headers, state.max_upload_bytes, MULTIPART_OVERHEAD_BYTES)?;
+    let account = usage::account_key(
+        &headers,
+        claims.as_deref(),
+        state.rate_limits.trusts_forwarded_headers(),
+    );
+    let requested = ClientTranscodeOptions::from_request(query.as_deref(), &headers)?;
+    let mut options = UploadOptions::default();
+    while let Some(mut field) = multipart.next_field().await? {
+        let Some(file_name) = field.file_name().map(str::to_string) else {
+            if field.name() == Some("options") {
+                options = serde_json::from_slice(&field.bytes().await?).map_err(|err| {
+                    AppError::validation(format!("invalid upload options: {err}"))
+                        .with_code("upload_options_invalid")
+                })?;
+            }
+            continue;
+        };
+
+        let PreparedUpload {
+            meta,
+            encode,
+            callback_url,
+            client_data,
+        } = std::mem::take(&mut options).prepare(requested, &account)?;
+
+        let id = create_pipeline_job(
+            &state,
+            Some(JobStage::Uploading),
+            Some(&file_name),
+            encode.as_ref(),
+            Some(&account),
+            client_data,
+        )
+        .await?;
+        let temp_path = state.storage.incoming_path(&id);
+        // Any failure from here on, such as a full disk, fails the job.
+        let received = async {
+            metadata::save(&state.storage, &id, &meta).await?;
+            state.jobs.update_stage(id, JobStage::Uploading).await?;
+            ensure_parent(&temp_path).await?;
+
+            let mut file = DigestWriter::new(File::create(&temp_path).await?);
+            let mut size_bytes = 0;
+            let transfer = state.shaper.begin(&account);
+            while let Some(chunk) = field.chunk().await? {
+                size_bytes += chunk.len() as u64;
+                if let Some(max) = state.max_upload_bytes.filter(|&max| size_bytes > max) {
+                    return Err(upload_too_large(max));
+                }
+                file.write_all(&chunk).await?;
+                transfer.consume(chunk.len() as u64).await;
+            }
+            file.flush().await?;
+            let digest = file.digest();
+            drop(file);
+            let existing = dedup::find(&state.storage, &account, &digest.sha256).await?;
+            Ok((digest, existing))
+        };
+        let (digest, existing) = match received.await {
+            Ok(received) => received,
+            Err(err) => return Err(abandon_upload(&state, id, err).await),
+        };
+        if let Some(existing) = existing {
+            discard_duplicate(&state, id).await;
+            tracing::info!(%id, %existing, "upload matches an existing video");
+            return Ok(Json(UploadResponse {
+                deduplicated: true,
+                ..build_upload_response(existing)
+            }));
+        }
+        let recorded = async {
+            record_source_digest(&state, id, &temp_path, Some(digest)).await?;
+            state.jobs.update_progress(id, 1.0).await
+        };
+        if let Err(err) = recorded.await {
+            return Err(abandon_upload(&state, id, err).await);
+        }
+
+        spawn_local_pipeline(state.clone(), id, encode, callback_url);
+        return Ok(Json(build_upload_response(id)));
+    }
+
+    Err(AppError::validation("multipart payload missing file field"))
+}
+
+/// Drops the job and files of an upload that duplicates an existing video.
+async fn discard_duplicate(state: &AppState, id: Uuid) {
+    if let Err(err) = state.jobs.remove(id).await {
+        tracing::warn!(%id, error = %err, "failed to remove duplicate upload job");
+    }
+    if let Err(err) = state.storage.delete_video(&id).await {
+        tracing::warn!(%id, error = %err, "failed to remove duplicate upload");
+    }
+}
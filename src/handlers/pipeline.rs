@@ -1,353 +1,543 @@
-use std::{
-    collections::HashSet,
-    path::{Path, PathBuf},
-    time::Duration,
-};
+use std::path::{Path, PathBuf};
 
-use reqwest::Url;
-use tokio::fs::{self, File};
-use tokio::io::AsyncWriteExt;
-use tokio::process::Command as TokioCommand;
-use url::ParseError;
+use serde_json::Value;
 use uuid::Uuid;
 
+use super::approval::await_approval;
+use super::encode::transcode_and_publish;
+use super::remote::run_remote_pipeline;
+use super::ytdlp::run_ytdlp_pipeline;
 use crate::{
+    bandwidth::ANONYMOUS_KEY,
+    callbacks,
+    cancel::RunningJob,
     cleanup,
-    error::AppError,
-    jobs::JobStage,
+    digest::{self, SourceDigest},
+    error::{AppError, ErrorClass},
+    hooks::{HookContext, HookPoint},
+    jobs::{EncodeSummary, JobOrigin, JobSource, JobStage},
+    metadata::{self, VideoMetadata},
+    replication::ReplicationEvent,
+    shaping::IngestTransfer,
     state::AppState,
-    storage::ensure_parent,
-    transcode::{EncodeParams, process_video},
+    transcode::{EncodeParams, capture_failure, encoder_capabilities, ensure_media},
 };
 
-const ARIA2_BIN: &str = "aria2c";
-
-pub(super) fn spawn_local_pipeline(state: AppState, id: Uuid, temp_path: PathBuf) {
-    tokio::spawn(async move {
-        if let Err(err) = run_local_pipeline(state.clone(), id, temp_path.clone()).await {
-            tracing::error!(%id, error = %err, "local processing failed");
-            if let Err(store_err) = state.jobs.fail(id, err.to_string()).await {
-                tracing::error!(%id, error = %store_err, "failed to mark job as failed");
-            }
-            match tokio::fs::remove_file(&temp_path).await {
-                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
-                    tracing::warn!(path = %temp_path.display(), ?e, "cleanup failed");
-                }
-                _ => {}
-            }
-        }
-    });
-}
-
-pub(super) fn spawn_remote_pipeline(
+/// Starts transcoding the file already copied to the incoming path of `id`.
+pub(crate) fn spawn_local_pipeline(
     state: AppState,
     id: Uuid,
-    url: String,
     encode: Option<EncodeParams>,
+    callback_url: Option<String>,
 ) {
-    tokio::spawn(async move {
-        if let Err(err) = run_remote_pipeline(state.clone(), id, url.clone(), encode).await {
-            tracing::error!(%id, url, error = %err, "remote processing failed");
-            if let Err(store_err) = state.jobs.fail(id, err.to_string()).await {
-                tracing::error!(%id, url, error = %store_err, "failed to mark remote job failure");
-            }
-        }
-    });
+    let job = state.running.register(id);
+    let source = JobSource {
+        origin: JobOrigin::Local,
+        encode,
+        callback_url,
+        credentials_redacted: false,
+    };
+    spawn_pipeline(state, job, id, source);
 }
 
-pub(super) fn spawn_ytdlp_pipeline(
-    state: AppState,
+/// Runs the `PreIngest` hooks, then registers a job whose plan is the ingest
+/// stage, approval when requested, and transcoding, plus one child job per
+/// optional stage declared by the requested profile. Usage of the video is
+/// charged to `account`, which must be within its quotas, and `client_data`
+/// is attached to the job.
+pub(crate) async fn create_pipeline_job(
+    state: &AppState,
+    ingest: Option<JobStage>,
+    source: Option<&str>,
+    encode: Option<&EncodeParams>,
+    account: Option<&str>,
+    client_data: Option<Value>,
+) -> Result<Uuid, AppError> {
+    if let Some(encoder) = encode.and_then(|params| params.encoder) {
+        encoder_capabilities(&state.process_runner)
+            .await
+            .ensure_usable(encoder)?;
+    }
+    if let Some(account) = account {
+        state.usage.admit(account).await?;
+    }
+    state.load.admit(&state.storage).await?;
+    if ingest == Some(JobStage::Downloading)
+        && let Some(source) = source
+    {
+        state.breaker.admit(source)?;
+    }
+    let id = Uuid::new_v4();
+    run_hooks(
+        state,
+        id,
+        HookPoint::PreIngest,
+        source,
+        None,
+        None,
+        encode,
+        None,
+    )
+    .await?;
+    state.jobs.create_job(id).await?;
+    if let Some(data) = client_data {
+        state.jobs.set_client_data(id, data).await?;
+    }
+    if let Some(account) = account {
+        let meta = VideoMetadata {
+            account: Some(account.to_string()),
+            ..VideoMetadata::default()
+        };
+        metadata::save(&state.storage, &id, &meta).await?;
+    }
+
+    let approval = encode
+        .is_some_and(|params| params.approval)
+        .then_some(JobStage::AwaitingApproval);
+    let plan: Vec<JobStage> = ingest
+        .into_iter()
+        .chain(approval)
+        .chain(std::iter::once(JobStage::Transcoding))
+        .collect();
+    state.jobs.set_plan(id, plan).await?;
+
+    let profile = encode.map(|params| params.profile).unwrap_or_default();
+    for stage in profile.optional_stages() {
+        state
+            .jobs
+            .add_child(id, Uuid::new_v4(), stage.name())
+            .await?;
+    }
+
+    Ok(id)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn run_hooks(
+    state: &AppState,
     id: Uuid,
-    url: String,
-    encode: Option<EncodeParams>,
+    point: HookPoint,
+    source: Option<&str>,
+    media_path: Option<&Path>,
+    digest: Option<&SourceDigest>,
+    encode: Option<&EncodeParams>,
+    summary: Option<&EncodeSummary>,
+) -> Result<(), AppError> {
+    if state.hooks.is_empty() {
+        return Ok(());
+    }
+    state
+        .hooks
+        .run(&HookContext {
+            video_id: id,
+            point,
+            source: source.map(str::to_string),
+            media_path: media_path.map(Path::to_path_buf),
+            digest: digest.cloned(),
+            encode: encode.copied(),
+            summary: summary.cloned(),
+            storage: state.storage.clone(),
+        })
+        .await
+}
+
+/// Records usage without failing the job; a lost charge only loosens quotas.
+pub(super) async fn charge_usage(
+    state: &AppState,
+    id: Uuid,
+    account: &str,
+    bytes: u64,
+    minutes: f64,
 ) {
+    if let Err(err) = state.usage.record(account, bytes, minutes).await {
+        tracing::warn!(%id, account, error = %err, "failed to record usage");
+    }
+}
+
+/// Records `source` on the job and runs its pipeline in the background,
+/// unless sharded dispatch leaves the job queued for the instance owning
+/// it. A job with per-request credentials, which the store does not keep,
+/// always runs here. A job waiting for approval reports to its callback
+/// when its review proxy is ready, and again once the approved encode ends.
+pub(super) fn spawn_pipeline(state: AppState, job: RunningJob, id: Uuid, source: JobSource) {
     tokio::spawn(async move {
-        if let Err(err) = run_ytdlp_pipeline(state.clone(), id, url.clone(), encode).await {
-            tracing::error!(%id, url, error = %err, "yt-dlp processing failed");
-            if let Err(store_err) = state.jobs.fail(id, err.to_string()).await {
-                tracing::error!(%id, url, error = %store_err, "failed to mark yt-dlp job failure");
+        if let Err(err) = state.jobs.set_source(id, source.redacted()).await {
+            tracing::warn!(%id, error = %err, "failed to record job source; it cannot be retried");
+        }
+        if source.has_credentials() {
+            if let Err(err) = state.dispatcher.take(&state.jobs, id).await {
+                tracing::warn!(%id, error = %err, "failed to claim job; running it here");
+            }
+        } else if !state.dispatcher.admit(&state.jobs, id).await {
+            if !job.is_cancelled() {
+                tracing::debug!(%id, "job queued for the instance owning it");
+                return;
             }
+            // Cancelled while it was handed over, so its owner must not start it.
+            if let Err(err) = state.dispatcher.take(&state.jobs, id).await {
+                tracing::warn!(%id, error = %err, "failed to claim cancelled job");
+            }
+            let err = AppError::cancelled(format!("job {id} was cancelled"));
+            conclude_pipeline(&state, job, id, &source, Err(err)).await;
+            return;
         }
+        run_pipeline(&state, job, id, &source).await;
     });
 }
 
-async fn run_local_pipeline(state: AppState, id: Uuid, temp_path: PathBuf) -> Result<(), AppError> {
-    tracing::debug!(%id, path = %temp_path.display(), "starting local pipeline");
-    cleanup::ensure_capacity(&state.storage, &state.jobs, &state.cleanup).await?;
-    state.jobs.update_stage(id, JobStage::Transcoding).await?;
-    process_video(&state.storage, &state.jobs, &id, temp_path.as_path(), None).await?;
-    state.jobs.complete(id).await?;
-
-    tracing::debug!(%id, "local pipeline finished");
-
-    Ok(())
-}
-
-async fn run_remote_pipeline(
-    state: AppState,
+/// Starts the pipeline of a job this instance owns under sharded dispatch
+/// but no live instance runs, claiming it first. A job its gone instance
+/// had started runs again from its source, like a retry. A job whose
+/// credentials were only known to that instance fails with
+/// `job_credentials_required` instead, to be retried with them, and one
+/// `cancelled` through the job store fails as cancelled. Returns whether
+/// the pipeline started.
+pub(crate) async fn take_over_job(
+    state: &AppState,
     id: Uuid,
-    url: String,
-    encode: Option<EncodeParams>,
-) -> Result<(), AppError> {
-    cleanup::ensure_capacity(&state.storage, &state.jobs, &state.cleanup).await?;
-    state.jobs.update_stage(id, JobStage::Downloading).await?;
-
-    let temp_path = state.storage.incoming_path(&id);
-    ensure_parent(&temp_path).await?;
-    tracing::debug!(%id, %url, path = %temp_path.display(), "remote download starting");
-
-    let parsed_url = Url::parse(&url);
-    if should_use_aria2(&url, &parsed_url) {
-        state.jobs.update_progress(id, 0.0).await?;
-        download_with_aria2(&url, &temp_path).await?;
-        state.jobs.update_progress(id, 1.0).await?;
-        tracing::debug!(%id, %url, path = %temp_path.display(), "remote download completed via aria2");
+    live: &[String],
+    cancelled: bool,
+) -> Result<bool, AppError> {
+    let Some(config) = state.dispatcher.config() else {
+        return Ok(false);
+    };
+    let Some(job) = state.running.register_idle(id) else {
+        return Ok(false);
+    };
+    let (Some(group), Some(source)) = (
+        state.jobs.group_status(&id).await?,
+        state.jobs.source(&id).await?,
+    ) else {
+        return Ok(false);
+    };
+    if !state.jobs.claim(id, &config.instance_id, live).await? {
+        return Ok(false);
+    }
+    let stopped = if cancelled {
+        Some(AppError::cancelled(format!("job {id} was cancelled")))
+    } else if source.credentials_redacted {
+        Some(
+            AppError::validation(format!(
+                "job {id} lost its credentials with the instance running it; retry it with them"
+            ))
+            .with_code("job_credentials_required")
+            .with_param("id", id.to_string()),
+        )
     } else {
-        let http_url = parsed_url.map_err(|err| AppError::validation(err.to_string()))?;
-        let mut response = state
-            .http_client
-            .get(http_url)
-            .timeout(Duration::from_secs(60 * 10))
-            .send()
-            .await?
-            .error_for_status()?;
-
-        let mut file = File::create(&temp_path).await?;
-        let content_length = response.content_length();
-        let mut downloaded: u64 = 0;
-
-        while let Some(chunk) = response.chunk().await? {
-            file.write_all(&chunk).await?;
-            downloaded += chunk.len() as u64;
-            if let Some(total) = content_length {
-                let ratio = (downloaded as f32 / total as f32).clamp(0.0, 1.0);
-                state.jobs.update_progress(id, ratio).await?;
-            }
+        None
+    };
+    if let Some(err) = stopped {
+        conclude_pipeline(state, job, id, &source, Err(err)).await;
+        return Ok(false);
+    }
+    let status = &group.members[0].status;
+    if let Some(gone) = status
+        .instance
+        .as_deref()
+        .filter(|&holder| holder != config.instance_id)
+    {
+        for member in &group.members {
+            state.jobs.reset(member.status.id).await?;
         }
-        file.flush().await?;
+        tracing::info!(%id, instance = gone, stage = status.stage.as_str(), "taking over job from a gone instance");
+    }
+    let state = state.clone();
+    tokio::spawn(async move { run_pipeline(&state, job, id, &source).await });
+    Ok(true)
+}
 
-        state.jobs.update_progress(id, 1.0).await?;
-        tracing::debug!(
+/// Runs the pipeline for `source`, retrying it as the retry policy allows.
+async fn run_pipeline(state: &AppState, job: RunningJob, id: Uuid, source: &JobSource) {
+    let progress = progress_reporter(state, id, source);
+    let mut attempt = 1;
+    let result = loop {
+        let result = run_source(state, &job, id, source).await;
+        let Err(err) = &result else {
+            break result;
+        };
+        let Some(delay) = state.retry.get().next_delay(err, attempt) else {
+            break result;
+        };
+        let url = match &source.origin {
+            JobOrigin::Local => None,
+            JobOrigin::Remote { url, .. } | JobOrigin::YtDlp { url, .. } => Some(url),
+        };
+        // An open breaker means the host is down for longer than a retry waits.
+        if url.is_some_and(|url| state.breaker.admit(url).is_err()) {
+            break result;
+        }
+        attempt += 1;
+        tracing::warn!(
             %id,
-            %url,
-            path = %temp_path.display(),
-            bytes = downloaded,
-            "remote download completed"
+            attempt,
+            delay_secs = delay.as_secs_f64(),
+            error = %err,
+            "pipeline failed; retrying"
         );
-    }
-
-    state.jobs.update_stage(id, JobStage::Transcoding).await?;
-    tracing::debug!(%id, %url, path = %temp_path.display(), "starting transcode for remote job");
-
-    process_video(
-        &state.storage,
-        &state.jobs,
-        &id,
-        temp_path.as_path(),
-        encode,
-    )
-    .await?;
-    state.jobs.complete(id).await?;
-    tracing::debug!(%id, %url, "remote pipeline finished");
-
-    Ok(())
+        if let Err(store_err) = state.jobs.record_retry(id, attempt, err).await {
+            tracing::warn!(%id, error = %store_err, "failed to record retry");
+        }
+        // Cancelling the job also ends the wait.
+        let wait = job.run(async {
+            tokio::time::sleep(delay).await;
+            Ok(())
+        });
+        if let Err(cancelled) = wait.await {
+            break Err(cancelled);
+        }
+    };
+    drop(progress);
+    conclude_pipeline(state, job, id, source, result).await;
 }
 
-async fn run_ytdlp_pipeline(
-    state: AppState,
+/// Runs the pipeline for `source` once.
+async fn run_source(
+    state: &AppState,
+    job: &RunningJob,
     id: Uuid,
-    url: String,
-    encode: Option<EncodeParams>,
+    source: &JobSource,
 ) -> Result<(), AppError> {
-    cleanup::ensure_capacity(&state.storage, &state.jobs, &state.cleanup).await?;
-    state.jobs.update_stage(id, JobStage::Downloading).await?;
-
-    let temp_path = state.storage.incoming_path(&id);
-    ensure_parent(&temp_path).await?;
-    tracing::debug!(%id, %url, path = %temp_path.display(), "yt-dlp download starting");
-
-    let downloaded_path = download_with_ytdlp_cli(&url, &temp_path).await?;
-
-    if downloaded_path != temp_path {
-        fs::rename(&downloaded_path, &temp_path).await?;
+    let encode = source.encode;
+    match &source.origin {
+        JobOrigin::Local => {
+            let temp_path = state.storage.incoming_path(&id);
+            job.run(run_local_pipeline(state.clone(), id, temp_path, encode))
+                .await
+        }
+        JobOrigin::Remote {
+            url,
+            proxy,
+            s3_credentials,
+            files,
+        } => {
+            job.run(run_remote_pipeline(
+                state.clone(),
+                id,
+                url.clone(),
+                proxy.as_deref(),
+                s3_credentials.as_ref(),
+                files,
+                encode,
+            ))
+            .await
+        }
+        JobOrigin::YtDlp { url, options } => {
+            job.run(run_ytdlp_pipeline(
+                state.clone(),
+                id,
+                url.clone(),
+                options,
+                encode,
+            ))
+            .await
+        }
     }
-    tracing::debug!(%id, %url, path = %temp_path.display(), "yt-dlp download finished");
-
-    state.jobs.update_stage(id, JobStage::Transcoding).await?;
-    tracing::debug!(%id, %url, path = %temp_path.display(), "starting transcode for yt-dlp job");
-
-    process_video(
-        &state.storage,
-        &state.jobs,
-        &id,
-        temp_path.as_path(),
-        encode,
-    )
-    .await?;
-    state.jobs.complete(id).await?;
-    tracing::debug!(%id, %url, "yt-dlp pipeline finished");
-
-    Ok(())
 }
 
-async fn download_with_ytdlp_cli(url: &str, destination: &Path) -> Result<PathBuf, AppError> {
-    let parent = destination
-        .parent()
-        .ok_or_else(|| AppError::transcode("temporary destination missing parent directory"))?;
-
-    let template_path = destination.with_extension("%(ext)s");
-
-    let output = TokioCommand::new("yt-dlp")
-        .arg("--ignore-config")
-        .arg("--no-warnings")
-        .arg("--quiet")
-        .arg("--no-progress")
-        .arg("--no-playlist")
-        .arg("--no-part")
-        .arg("--no-write-comments")
-        .arg("--no-write-subs")
-        .arg("--no-write-description")
-        .arg("--no-write-info-json")
-        .arg("--output")
-        .arg(&template_path)
-        .arg("--print")
-        .arg("after_move:filepath")
-        .arg("-f")
-        .arg("bv*+ba/b")
-        .arg(url)
-        .output()
-        .await
-        .map_err(|err| map_spawn_error(err, "yt-dlp"))?;
+/// Starts reporting the job's progress to its callback URL, when it has one
+/// and `VIDEO_CALLBACK_PROGRESS_STEP` is set.
+pub(super) fn progress_reporter(
+    state: &AppState,
+    id: Uuid,
+    source: &JobSource,
+) -> Option<callbacks::ProgressReporter> {
+    let config = state.progress_callbacks.get();
+    let url = source.callback_url.clone().filter(|_| config.enabled())?;
+    Some(callbacks::report_progress(
+        state.http_client.clone(),
+        url,
+        state.jobs.clone(),
+        id,
+        config,
+    ))
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(AppError::dependency(format!(
-            "yt-dlp exited with status {}: {}",
-            output.status,
-            stderr.trim()
-        )));
+/// Fails the job when its pipeline stopped with an error, announces a
+/// completed video to replication, then reports its status to the callback
+/// URL.
+pub(super) async fn conclude_pipeline(
+    state: &AppState,
+    job: RunningJob,
+    id: Uuid,
+    source: &JobSource,
+    result: Result<(), AppError>,
+) {
+    let succeeded = result.is_ok();
+    if let Err(err) = result {
+        fail_after_error(state, id, source, err).await;
     }
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let reported_path = stdout
-        .lines()
-        .rev()
-        .find(|line| !line.trim().is_empty())
-        .ok_or_else(|| AppError::dependency("yt-dlp did not report an output file"))?
-        .trim()
-        .to_string();
-
-    let mut resolved = PathBuf::from(&reported_path);
-    if resolved.is_relative() {
-        resolved = parent.join(resolved);
+    // Released while still registered, so a retry's claim is not dropped.
+    state.dispatcher.release(&state.jobs, id).await;
+    // A slow callback receiver must not hold up a retry.
+    drop(job);
+    if !succeeded && source.callback_url.is_none() {
+        return;
     }
-
-    if !resolved.exists() {
-        return Err(AppError::dependency(format!(
-            "yt-dlp reported output {}, but file is missing",
-            resolved.display()
-        )));
+    let status = match state.jobs.status(&id).await {
+        Ok(status) => status,
+        Err(err) => {
+            tracing::warn!(%id, error = %err, "failed to load job status after its pipeline");
+            return;
+        }
+    };
+    // A job waiting for approval is not published yet.
+    if succeeded
+        && status
+            .as_ref()
+            .is_some_and(|status| status.stage == JobStage::Complete)
+    {
+        state
+            .replication
+            .emit(ReplicationEvent::Published { video_id: id });
+    }
+    if let (Some(url), Some(status)) = (&source.callback_url, &status) {
+        callbacks::notify(&state.http_client, url, status).await;
     }
-
-    Ok(resolved)
 }
 
-async fn download_with_aria2(source: &str, destination: &Path) -> Result<(), AppError> {
-    let parent = destination
-        .parent()
-        .ok_or_else(|| AppError::transcode("temporary destination missing parent directory"))?;
-
-    let before = dir_snapshot(parent).await?;
-    let file_name = destination
-        .file_name()
-        .and_then(|name| name.to_str())
-        .ok_or_else(|| AppError::transcode("temporary destination missing file name"))?;
-
-    let is_magnet = source.starts_with("magnet:");
-    let is_torrent = source.to_ascii_lowercase().ends_with(".torrent");
-
-    let mut command = TokioCommand::new(ARIA2_BIN);
-    command
-        .arg("--allow-overwrite=true")
-        .arg("--auto-file-renaming=false")
-        .arg("--summary-interval=0")
-        .arg("--seed-time=0")
-        .arg("--bt-seed-until=0")
-        .arg("--bt-stop-timeout=0")
-        .arg("--bt-remove-unselected-file=true")
-        .arg("--bt-save-metadata=false")
-        .arg("--dir")
-        .arg(parent);
-
-    if !is_magnet && !is_torrent {
-        command.arg("--out").arg(file_name);
+/// Logs why a pipeline failed, marks its jobs failed and drops an upload
+/// that cannot be transcoded.
+async fn fail_after_error(state: &AppState, id: Uuid, source: &JobSource, err: AppError) {
+    match &source.origin {
+        JobOrigin::Local => {
+            tracing::error!(%id, error = %err, class = ?err.class(), "local processing failed");
+        }
+        JobOrigin::Remote { url, .. } => {
+            tracing::error!(%id, url, error = %err, class = ?err.class(), "remote processing failed");
+        }
+        JobOrigin::YtDlp { url, .. } => {
+            tracing::error!(%id, url, error = %err, class = ?err.class(), "yt-dlp processing failed");
+        }
     }
-
-    command.arg(source);
-
-    let status = command
-        .status()
-        .await
-        .map_err(|err| map_spawn_error(err, ARIA2_BIN))?;
-
-    if !status.success() {
-        return Err(AppError::dependency(format!(
-            "aria2c exited with status {status}"
-        )));
+    fail_pipeline(state, id, &err).await;
+    if matches!(err.root(), AppError::Transcode(_)) {
+        let input = state.storage.incoming_path(&id);
+        if input.is_file()
+            && let Err(capture_err) =
+                capture_failure(&state.storage, &state.process_runner, &id, &input, &err).await
+        {
+            tracing::warn!(%id, error = %capture_err, "failed to capture failure diagnostics");
+        }
     }
-
-    if destination.exists() {
-        tracing::debug!(source, dest = %destination.display(), "aria2 produced target file directly");
-        return Ok(());
+    // Keep uploads for a retry unless the file itself is the problem.
+    if source.origin == JobOrigin::Local && err.class() == ErrorClass::SourceInvalid {
+        let temp_path = state.storage.incoming_path(&id);
+        match tokio::fs::remove_file(&temp_path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                tracing::warn!(path = %temp_path.display(), ?e, "cleanup failed");
+            }
+            _ => {}
+        }
     }
+}
 
-    let after = dir_snapshot(parent).await?;
-    let mut new_entries: Vec<PathBuf> = after.difference(&before).cloned().collect();
-
-    if new_entries.len() == 1 {
-        let candidate = new_entries.remove(0);
-        if tokio::fs::metadata(&candidate)
-            .await
-            .map_err(AppError::from)?
-            .is_file()
-        {
-            tokio::fs::rename(&candidate, destination).await?;
-            tracing::debug!(source, temp = %candidate.display(), dest = %destination.display(), "aria2 download moved into place");
-            return Ok(());
+/// Marks a job failed after its pipeline stopped with `err`. A cancelled
+/// pipeline also fails the child jobs it had not finished.
+pub(super) async fn fail_pipeline(state: &AppState, id: Uuid, err: &AppError) {
+    let mut failed = vec![id];
+    if matches!(err.root(), AppError::Cancelled(_)) {
+        match state.jobs.group_status(&id).await {
+            Ok(Some(group)) => {
+                failed = group
+                    .members
+                    .iter()
+                    .filter(|member| !member.status.stage.is_terminal())
+                    .map(|member| member.status.id)
+                    .collect();
+            }
+            Ok(None) => {}
+            Err(store_err) => {
+                tracing::error!(%id, error = %store_err, "failed to look up cancelled job group");
+            }
+        }
+    }
+    for job in failed {
+        if let Err(store_err) = state.jobs.fail(job, err).await {
+            tracing::error!(%job, error = %store_err, "failed to mark job as failed");
         }
     }
+}
 
-    Err(AppError::transcode(
-        "aria2c produced unexpected output (expected a single file)",
-    ))
+pub(super) fn job_not_found(id: Uuid) -> AppError {
+    AppError::not_found(format!("job {id} not found"))
+        .with_code("job_not_found")
+        .with_param("id", id.to_string())
 }
 
-fn should_use_aria2(url_str: &str, parsed: &Result<Url, ParseError>) -> bool {
-    let lower = url_str.to_ascii_lowercase();
-    if url_str.starts_with("magnet:") || lower.ends_with(".torrent") {
-        return true;
+async fn run_local_pipeline(
+    state: AppState,
+    id: Uuid,
+    temp_path: PathBuf,
+    encode: Option<EncodeParams>,
+) -> Result<(), AppError> {
+    tracing::debug!(%id, path = %temp_path.display(), "starting local pipeline");
+    cleanup::ensure_capacity(&state.storage, &state.jobs, &state.cleanup.get()).await?;
+    let digest = match metadata::load(&state.storage, &id).await?.source {
+        Some(digest) => digest,
+        None => record_source_digest(&state, id, &temp_path, None).await?,
+    };
+    run_hooks(
+        &state,
+        id,
+        HookPoint::PostDownload,
+        None,
+        Some(&temp_path),
+        Some(&digest),
+        encode.as_ref(),
+        None,
+    )
+    .await?;
+    if await_approval(&state, id, &temp_path, encode.as_ref()).await? {
+        return Ok(());
     }
+    transcode_and_publish(&state, id, None, &temp_path, &digest, encode).await?;
 
-    if let Ok(url) = parsed {
-        matches!(url.scheme(), "ftp" | "ftps" | "p2p")
-    } else {
-        false
-    }
+    tracing::debug!(%id, "local pipeline finished");
+
+    Ok(())
 }
 
-async fn dir_snapshot(dir: &Path) -> Result<HashSet<PathBuf>, AppError> {
-    let mut entries = fs::read_dir(dir).await?;
-    let mut set = HashSet::new();
-    while let Some(entry) = entries.next_entry().await? {
-        set.insert(entry.path());
+/// Stores the digest of a freshly written source in the video's metadata,
+/// reading the file once when it was not `streamed` through a
+/// [`DigestWriter`]. Fails with `source_not_media` when the file is not
+/// audio, video or an image, before the source is charged or hooks see it.
+pub(crate) async fn record_source_digest(
+    state: &AppState,
+    id: Uuid,
+    path: &Path,
+    streamed: Option<SourceDigest>,
+) -> Result<SourceDigest, AppError> {
+    let digest = match streamed {
+        Some(digest) => digest,
+        None => digest::digest_file(path).await?,
+    };
+    ensure_media(&state.process_runner, path, digest.container).await?;
+    let mut meta = metadata::load(&state.storage, &id).await?;
+    meta.source = Some(digest.clone());
+    metadata::save(&state.storage, &id, &meta).await?;
+    if let Some(account) = &meta.account {
+        charge_usage(state, id, account, digest.bytes, 0.0).await;
     }
-    Ok(set)
+    tracing::debug!(
+        %id,
+        bytes = digest.bytes,
+        sha256 = %digest.sha256,
+        container = digest.container.map(|container| container.as_str()),
+        "source digested"
+    );
+    Ok(digest)
 }
 
-fn map_spawn_error(err: std::io::Error, tool: &str) -> AppError {
-    match err.kind() {
-        std::io::ErrorKind::NotFound => AppError::dependency(format!("{tool} not found on PATH")),
-        _ => AppError::dependency(format!("failed to spawn {tool}: {err}")),
-    }
+/// Starts counting a transfer for the job's caller key against its ingest
+/// budget.
+pub(crate) async fn ingest_transfer(
+    state: &AppState,
+    id: Uuid,
+) -> Result<IngestTransfer, AppError> {
+    let account = metadata::load(&state.storage, &id).await?.account;
+    Ok(state
+        .shaper
+        .begin(account.as_deref().unwrap_or(ANONYMOUS_KEY)))
 }
+
 // Tests for this module live under `tests/` to keep source files focused.
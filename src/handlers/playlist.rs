@@ -0,0 +1,145 @@
+use std::time::SystemTime;
+
+use axum::{Extension, Json, extract::State, http::HeaderMap};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    auth::Claims,
+    batches::{self, Batch, BatchEntry},
+    callbacks, clock, config,
+    error::AppError,
+    jobs::JobStage,
+    state::AppState,
+    transcode::EncodeParams,
+    usage,
+};
+
+use super::ingest::YtDlpDownloadRequest;
+use super::pipeline::create_pipeline_job;
+use super::upload::{UploadResponse, build_upload_response, validate_client_data};
+use super::ytdlp::{expand_playlist, spawn_ytdlp_pipeline};
+
+/// Entries taken from a playlist unless `VIDEO_PLAYLIST_MAX_ENTRIES` says
+/// otherwise.
+const DEFAULT_PLAYLIST_MAX_ENTRIES: usize = 100;
+
+/// `POST /download/yt-dlp/playlist` body: a yt-dlp download whose URL is a
+/// playlist or channel.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct YtDlpPlaylistRequest {
+    #[serde(flatten)]
+    pub download: YtDlpDownloadRequest,
+    /// Entries to take from the start of the playlist, at most
+    /// `VIDEO_PLAYLIST_MAX_ENTRIES`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_entries: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchResponse {
+    pub batch_id: String,
+    pub status_url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// One job per entry, in playlist order.
+    pub jobs: Vec<UploadResponse>,
+    /// Entries left out because their job was refused, e.g. over quota.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<String>,
+}
+
+/// Expands a playlist or channel with yt-dlp and starts one yt-dlp job per
+/// entry, with the options of the request. The jobs are grouped in a batch
+/// whose progress is reported at `/batches/{id}`. Once the first job is
+/// created, entries refused by quotas or hooks are skipped instead of failing
+/// the request.
+pub async fn download_playlist_via_ytdlp(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    claims: Option<Extension<Claims>>,
+    Json(payload): Json<YtDlpPlaylistRequest>,
+) -> Result<Json<BatchResponse>, AppError> {
+    let YtDlpPlaylistRequest {
+        download: payload,
+        max_entries,
+    } = payload;
+    Url::parse(&payload.url).map_err(|err| AppError::validation(format!("invalid url: {err}")))?;
+    let encode = payload.transcode.map(EncodeParams::from);
+    let callback_url = payload
+        .callback_url
+        .as_deref()
+        .map(callbacks::validate_url)
+        .transpose()?;
+    let options = payload.options(&state.storage).await?;
+    let client_data = validate_client_data(payload.client_data)?;
+    let account = usage::account_key(
+        &headers,
+        claims.as_deref(),
+        state.rate_limits.trusts_forwarded_headers(),
+    );
+    let limit = config::parse_var("VIDEO_PLAYLIST_MAX_ENTRIES")
+        .filter(|&limit| limit > 0)
+        .unwrap_or(DEFAULT_PLAYLIST_MAX_ENTRIES);
+    let max_entries = max_entries.unwrap_or(limit).clamp(1, limit);
+
+    let playlist = expand_playlist(&state, &payload.url, max_entries, &options).await?;
+    if playlist.entries.is_empty() {
+        return Err(AppError::validation("the playlist has no entries")
+            .with_code("playlist_empty")
+            .with_param("url", payload.url));
+    }
+
+    let mut entries = Vec::with_capacity(playlist.entries.len());
+    let mut skipped = Vec::new();
+    for (url, title) in playlist.entries {
+        let created = create_pipeline_job(
+            &state,
+            Some(JobStage::Downloading),
+            Some(&url),
+            encode.as_ref(),
+            Some(&account),
+            client_data.clone(),
+        )
+        .await;
+        let id = match created {
+            Ok(id) => id,
+            Err(err) if entries.is_empty() => return Err(err),
+            Err(err) => {
+                tracing::warn!(%url, error = %err, "skipping playlist entry");
+                skipped.push(url);
+                continue;
+            }
+        };
+        spawn_ytdlp_pipeline(
+            state.clone(),
+            id,
+            url.clone(),
+            options.clone(),
+            encode,
+            callback_url.clone(),
+        );
+        entries.push(BatchEntry { id, url, title });
+    }
+
+    let batch = Batch {
+        id: Uuid::new_v4(),
+        url: payload.url,
+        title: playlist.title,
+        created_at_unix_ms: clock::unix_ms(SystemTime::now()),
+        entries,
+    };
+    batches::save(&state.storage, &batch).await?;
+    Ok(Json(BatchResponse {
+        batch_id: batch.id.to_string(),
+        status_url: format!("/batches/{}", batch.id),
+        title: batch.title,
+        jobs: batch
+            .entries
+            .iter()
+            .map(|entry| build_upload_response(entry.id))
+            .collect(),
+        skipped,
+    }))
+}
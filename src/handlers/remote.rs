@@ -0,0 +1,422 @@
+use std::{
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use reqwest::{Client, Url};
+use serde_json::Value;
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+use url::ParseError;
+use uuid::Uuid;
+
+use super::approval::await_approval;
+use super::encode::transcode_and_publish;
+use super::pipeline::{
+    create_pipeline_job, ingest_transfer, record_source_digest, run_hooks, spawn_pipeline,
+};
+use crate::{
+    aria2::{self, FileSelection},
+    cleanup,
+    digest::{DigestWriter, SourceDigest},
+    error::{AppError, ErrorClass},
+    hooks::HookPoint,
+    http_client::{self, HttpClientConfig, ResumePolicy},
+    jobs::{JobOrigin, JobSource, JobStage},
+    s3::{self, S3Config, S3Credentials, S3Location},
+    shaping::IngestTransfer,
+    state::AppState,
+    storage::ensure_parent,
+    transcode::EncodeParams,
+};
+
+const SEGMENTED_PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Validates a remote source, registers its job, and starts the download pipeline.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn submit_remote_job(
+    state: &AppState,
+    url: String,
+    proxy: Option<String>,
+    s3_credentials: Option<S3Credentials>,
+    files: FileSelection,
+    encode: Option<EncodeParams>,
+    callback_url: Option<String>,
+    account: Option<&str>,
+    client_data: Option<Value>,
+) -> Result<Uuid, AppError> {
+    if s3::is_s3_url(&url) {
+        S3Location::parse(&url)?;
+    } else if s3_credentials.is_some() {
+        return Err(
+            AppError::validation("s3_credentials only apply to s3:// URLs")
+                .with_code("s3_credentials_unexpected"),
+        );
+    } else if !url.starts_with("magnet:") {
+        Url::parse(&url).map_err(|err| AppError::validation(format!("invalid url: {err}")))?;
+    }
+    if !files.is_empty() && !aria2::is_torrent(&url) {
+        return Err(AppError::validation(
+            "file_index and file_glob only apply to magnet links and .torrent URLs",
+        )
+        .with_code("file_selection_unexpected"));
+    }
+    files.validate()?;
+    remote_proxy(proxy.as_deref())?;
+
+    let id = create_pipeline_job(
+        state,
+        Some(JobStage::Downloading),
+        Some(&url),
+        encode.as_ref(),
+        account,
+        client_data,
+    )
+    .await?;
+    let origin = JobOrigin::Remote {
+        url,
+        proxy,
+        s3_credentials,
+        files,
+    };
+    spawn_remote_pipeline(state.clone(), id, origin, encode, callback_url);
+    Ok(id)
+}
+
+/// The proxy a remote download goes through: the job's own, else
+/// `VIDEO_HTTP_PROXY`. Neither reqwest nor aria2 speak SOCKS, so a SOCKS
+/// proxy asked for by the job fails with `proxy_unsupported`, and one from
+/// the environment is left to yt-dlp.
+fn remote_proxy(requested: Option<&str>) -> Result<Option<Url>, AppError> {
+    let Some(requested) = requested else {
+        return Ok(http_client::configured_proxy().filter(|proxy| !http_client::is_socks(proxy)));
+    };
+    let proxy = http_client::parse_proxy(requested)?;
+    if http_client::is_socks(&proxy) {
+        return Err(AppError::validation(
+            "remote downloads only go through HTTP(S) proxies; SOCKS is supported for yt-dlp",
+        )
+        .with_code("proxy_unsupported")
+        .with_param("scheme", proxy.scheme()));
+    }
+    Ok(Some(proxy))
+}
+
+fn spawn_remote_pipeline(
+    state: AppState,
+    id: Uuid,
+    origin: JobOrigin,
+    encode: Option<EncodeParams>,
+    callback_url: Option<String>,
+) {
+    let job = state.running.register(id);
+    let source = JobSource {
+        origin,
+        encode,
+        callback_url,
+        credentials_redacted: false,
+    };
+    spawn_pipeline(state, job, id, source);
+}
+
+pub(super) async fn run_remote_pipeline(
+    state: AppState,
+    id: Uuid,
+    url: String,
+    proxy: Option<&str>,
+    s3_credentials: Option<&S3Credentials>,
+    files: &FileSelection,
+    encode: Option<EncodeParams>,
+) -> Result<(), AppError> {
+    cleanup::ensure_capacity(&state.storage, &state.jobs, &state.cleanup.get()).await?;
+    state.jobs.update_stage(id, JobStage::Downloading).await?;
+
+    let temp_path = state.storage.incoming_path(&id);
+    ensure_parent(&temp_path).await?;
+    tracing::debug!(%id, %url, path = %temp_path.display(), "remote download starting");
+
+    let downloaded =
+        download_remote(&state, id, &url, proxy, s3_credentials, files, &temp_path).await;
+    state.breaker.record(&url, downloaded.as_ref().map(|_| ()));
+    let digest = record_source_digest(&state, id, &temp_path, downloaded?).await?;
+
+    run_hooks(
+        &state,
+        id,
+        HookPoint::PostDownload,
+        Some(&url),
+        Some(&temp_path),
+        Some(&digest),
+        encode.as_ref(),
+        None,
+    )
+    .await?;
+    if await_approval(&state, id, &temp_path, encode.as_ref()).await? {
+        return Ok(());
+    }
+    tracing::debug!(%id, %url, path = %temp_path.display(), "starting transcode for remote job");
+    transcode_and_publish(&state, id, Some(&url), &temp_path, &digest, encode).await?;
+    tracing::debug!(%id, %url, "remote pipeline finished");
+
+    Ok(())
+}
+
+/// Fetches `url` into `temp_path` over HTTP, or through aria2 for torrents
+/// and when configured, through the job's `proxy` if it has one. Of a
+/// multi-file torrent, only the file `files` selects is fetched. `s3://`
+/// URLs are fetched from a presigned URL, signed with `s3_credentials` or
+/// the keys in the environment.
+/// Single-stream HTTP downloads are digested as they are written.
+async fn download_remote(
+    state: &AppState,
+    id: Uuid,
+    url: &str,
+    proxy: Option<&str>,
+    s3_credentials: Option<&S3Credentials>,
+    files: &FileSelection,
+    temp_path: &Path,
+) -> Result<Option<SourceDigest>, AppError> {
+    if !s3::is_s3_url(url) {
+        return download_url(state, id, url, url, proxy, files, temp_path).await;
+    }
+    let credentials = s3_credentials.cloned().or_else(S3Credentials::from_env);
+    let presigned = S3Config::from_env().presign_get(
+        &S3Location::parse(url)?,
+        credentials.as_ref(),
+        http_client::download_timeout(),
+        SystemTime::now(),
+    )?;
+    // reqwest errors name their URL, and this one carries a signature.
+    download_url(state, id, url, presigned.as_str(), proxy, files, temp_path)
+        .await
+        .map_err(without_url)
+}
+
+/// Drops the URL from HTTP errors.
+fn without_url(err: AppError) -> AppError {
+    match err {
+        AppError::Http(err) => AppError::Http(err.without_url()),
+        AppError::Coded {
+            inner,
+            code,
+            params,
+        } => AppError::Coded {
+            inner: Box::new(without_url(*inner)),
+            code,
+            params,
+        },
+        err => err,
+    }
+}
+
+/// Downloads `fetch_url`, the location of the job's source `url`, which
+/// is what logs name.
+async fn download_url(
+    state: &AppState,
+    id: Uuid,
+    url: &str,
+    fetch_url: &str,
+    proxy: Option<&str>,
+    files: &FileSelection,
+    temp_path: &Path,
+) -> Result<Option<SourceDigest>, AppError> {
+    let parsed_url = Url::parse(fetch_url);
+    let transfer = ingest_transfer(state, id).await?;
+    if should_use_aria2(fetch_url, &parsed_url) {
+        let proxy = remote_proxy(proxy)?;
+        state.jobs.update_progress(id, 0.0).await?;
+        aria2::download(
+            &state.process_runner,
+            &state.jobs,
+            id,
+            fetch_url,
+            temp_path,
+            files,
+            transfer.share(),
+            proxy.as_ref(),
+        )
+        .await?;
+        transfer.record(fs::metadata(temp_path).await?.len());
+        state.jobs.update_progress(id, 1.0).await?;
+        tracing::debug!(%id, %url, path = %temp_path.display(), "remote download completed via aria2");
+    } else {
+        let http_url = parsed_url.map_err(|err| AppError::validation(err.to_string()))?;
+        // The shared client already goes through `VIDEO_HTTP_PROXY`.
+        let client = match proxy {
+            Some(requested) => HttpClientConfig {
+                proxy: remote_proxy(Some(requested))?,
+                ..HttpClientConfig::from_env()
+            }
+            .build()?,
+            None => state.http_client.clone(),
+        };
+        let mut response = client
+            .get(http_url.clone())
+            .timeout(http_client::download_timeout())
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let content_length = response.content_length();
+        let connections = http_client::download_connections(response.headers(), content_length);
+        if let (Some(total), true) = (content_length, connections > 1) {
+            drop(response);
+            download_segmented(
+                state,
+                &client,
+                id,
+                &http_url,
+                temp_path,
+                total,
+                connections,
+                transfer,
+            )
+            .await?;
+            return Ok(None);
+        }
+
+        let policy = ResumePolicy::from_env();
+        let deadline = Instant::now() + http_client::download_timeout();
+        let validator = http_client::range_validator(response.headers());
+        let mut file = DigestWriter::new(File::create(temp_path).await?);
+        let mut downloaded: u64 = 0;
+        // Resume attempts since the download last got any further.
+        let mut attempts = 0;
+
+        loop {
+            let mut err = match response.chunk().await {
+                Ok(Some(chunk)) => {
+                    attempts = 0;
+                    file.write_all(&chunk).await?;
+                    downloaded += chunk.len() as u64;
+                    transfer.consume(chunk.len() as u64).await;
+                    if let Some(total) = content_length {
+                        let ratio = (downloaded as f32 / total as f32).clamp(0.0, 1.0);
+                        state.jobs.update_progress(id, ratio).await?;
+                    }
+                    continue;
+                }
+                Ok(None) => break,
+                Err(err) => AppError::from(err),
+            };
+            file.flush().await?;
+            // Pick up from the last written byte instead of failing the job.
+            loop {
+                attempts += 1;
+                if err.class() != ErrorClass::Network || attempts > policy.retries {
+                    return Err(err);
+                }
+                tracing::warn!(
+                    %id,
+                    %url,
+                    downloaded,
+                    attempt = attempts,
+                    error = %err,
+                    "remote download interrupted; resuming"
+                );
+                tokio::time::sleep(policy.delay(attempts)).await;
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(err);
+                }
+                match http_client::resume_download(
+                    &client,
+                    &http_url,
+                    downloaded,
+                    validator.as_ref(),
+                    remaining,
+                )
+                .await
+                {
+                    Ok((resumed, offset)) => {
+                        response = resumed;
+                        if offset < downloaded {
+                            tracing::warn!(%id, %url, "source cannot resume; downloading it again");
+                            file = DigestWriter::new(File::create(temp_path).await?);
+                            downloaded = 0;
+                        }
+                        break;
+                    }
+                    Err(resume_err) => err = resume_err,
+                }
+            }
+        }
+        file.flush().await?;
+
+        state.jobs.update_progress(id, 1.0).await?;
+        tracing::debug!(
+            %id,
+            %url,
+            path = %temp_path.display(),
+            bytes = downloaded,
+            "remote download completed"
+        );
+        return Ok(Some(file.digest()));
+    }
+    Ok(None)
+}
+
+/// Runs a multi-connection download, reporting the combined progress.
+#[allow(clippy::too_many_arguments)]
+async fn download_segmented(
+    state: &AppState,
+    client: &Client,
+    id: Uuid,
+    url: &Url,
+    temp_path: &Path,
+    total: u64,
+    connections: usize,
+    transfer: IngestTransfer,
+) -> Result<(), AppError> {
+    let received = Arc::new(AtomicU64::new(0));
+    let download = http_client::download_ranges(
+        client,
+        url,
+        temp_path,
+        total,
+        connections,
+        received.clone(),
+        Some(transfer),
+    );
+    tokio::pin!(download);
+    let mut progress = tokio::time::interval(SEGMENTED_PROGRESS_INTERVAL);
+    loop {
+        tokio::select! {
+            result = &mut download => {
+                result?;
+                break;
+            }
+            _ = progress.tick() => {
+                let ratio = received.load(Ordering::Relaxed) as f32 / total as f32;
+                state.jobs.update_progress(id, ratio.clamp(0.0, 1.0)).await?;
+            }
+        }
+    }
+    state.jobs.update_progress(id, 1.0).await?;
+    tracing::debug!(
+        %id,
+        %url,
+        path = %temp_path.display(),
+        bytes = total,
+        connections,
+        "segmented remote download completed"
+    );
+    Ok(())
+}
+
+fn should_use_aria2(url_str: &str, parsed: &Result<Url, ParseError>) -> bool {
+    let lower = url_str.to_ascii_lowercase();
+    if url_str.starts_with("magnet:") || lower.ends_with(".torrent") {
+        return true;
+    }
+
+    if let Ok(url) = parsed {
+        matches!(url.scheme(), "ftp" | "ftps" | "p2p")
+    } else {
+        false
+    }
+}
@@ -0,0 +1,160 @@
+use axum::{
+    Json,
+    body::Body,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::{fs::File, io::AsyncWriteExt};
+use uuid::Uuid;
+
+use super::lifecycle::remove_video;
+use crate::{
+    dedup,
+    error::AppError,
+    locks::LockManager,
+    metadata,
+    replication::{self, PeerReplication},
+    state::AppState,
+    storage::ensure_parent,
+};
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ReplicateRequest {
+    /// Base URLs to push to instead of `VIDEO_REPLICATION_PEERS`. Only peers
+    /// on that list are sent `VIDEO_REPLICATION_TOKEN`.
+    #[serde(default)]
+    pub peers: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReplicationReport {
+    pub video_id: Uuid,
+    /// The latest push to each peer.
+    pub peers: Vec<PeerReplication>,
+}
+
+/// Pushes a video to peer instances in the background.
+pub async fn replicate_video(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    request: Option<Json<ReplicateRequest>>,
+) -> Result<(StatusCode, Json<ReplicationReport>), AppError> {
+    let video_id = parse_video_id(&id)?;
+    let Json(request) = request.unwrap_or_default();
+    let peers = request
+        .peers
+        .map(|peers| {
+            peers
+                .iter()
+                .map(|peer| replication::parse_peer(peer))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()?;
+    let peers = state.replication.replicate(video_id, peers)?;
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ReplicationReport { video_id, peers }),
+    ))
+}
+
+pub async fn replication_status(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<ReplicationReport>, AppError> {
+    let video_id = parse_video_id(&id)?;
+    Ok(Json(ReplicationReport {
+        video_id,
+        peers: state.replication.status(video_id),
+    }))
+}
+
+/// Receives a replica zipped by a peer's replication and swaps it in for
+/// the local copy of the video.
+pub async fn import_video(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    body: Body,
+) -> Result<StatusCode, AppError> {
+    let video_id = parse_video_id(&id)?;
+    if let Some(status) = state.jobs.status(&video_id).await?
+        && !status.stage.is_terminal()
+    {
+        return Err(AppError::conflict(format!(
+            "video {video_id} cannot be replaced while its job is {}",
+            status.stage.as_str()
+        ))
+        .with_code("video_in_use")
+        .with_param("id", video_id.to_string()));
+    }
+    state.load.admit(&state.storage).await?;
+    let max_bytes = state.replication.config().max_import_bytes;
+    let _guard = state
+        .storage
+        .locks()
+        .acquire(&LockManager::video_key(&video_id, "import"))
+        .await?;
+
+    let archive = state.storage.import_dir(&video_id).with_extension("zip");
+    ensure_parent(&archive).await?;
+    let received = receive(body, &archive, max_bytes).await;
+    let result = match received {
+        Ok(()) => replication::import_archive(&state.storage, video_id, &archive, max_bytes).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = tokio::fs::remove_file(&archive).await
+        && err.kind() != std::io::ErrorKind::NotFound
+    {
+        tracing::warn!(path = %archive.display(), error = %err, "failed to remove received replica");
+    }
+    result?;
+    // Uploads of the same source on this instance find the replica too.
+    let meta = metadata::load(&state.storage, &video_id).await?;
+    if let (Some(account), Some(source)) = (&meta.account, &meta.source)
+        && let Err(err) = dedup::record(&state.storage, account, &source.sha256, video_id).await
+    {
+        tracing::warn!(%video_id, error = %err, "failed to index replica for deduplication");
+    }
+    tracing::info!(%video_id, "imported replica");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Removes a replica after its origin deleted the video, like a local
+/// delete but without raising a replication event. Missing replicas count
+/// as removed.
+pub async fn delete_import(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let video_id = parse_video_id(&id)?;
+    if remove_video(&state, video_id).await? {
+        tracing::info!(%video_id, "deleted replica");
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Writes the replica in `body` to `path`, failing with
+/// `replica_too_large` once it grows past `max_bytes`.
+async fn receive(body: Body, path: &std::path::Path, max_bytes: u64) -> Result<(), AppError> {
+    let mut file = File::create(path).await?;
+    let mut chunks = body.into_data_stream();
+    let mut received = 0u64;
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.map_err(|err| {
+            AppError::validation(format!("replica interrupted at byte {received}: {err}"))
+                .with_code("replica_interrupted")
+        })?;
+        received += chunk.len() as u64;
+        if received > max_bytes {
+            return Err(replication::replica_too_large(max_bytes));
+        }
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+fn parse_video_id(id: &str) -> Result<Uuid, AppError> {
+    Uuid::parse_str(id).map_err(|_| AppError::validation("invalid video identifier"))
+}
@@ -0,0 +1,328 @@
+use axum::{
+    Extension, Json,
+    body::Body,
+    extract::{Path as AxumPath, State},
+    http::{HeaderMap, StatusCode},
+};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::{
+    auth::Claims, digest::DigestWriter, error::AppError, jobs::JobStage, locks::LockManager,
+    metadata, state::AppState, storage::ensure_dir, transcode::EncodeParams, usage,
+};
+
+use super::pipeline::{
+    create_pipeline_job, ingest_transfer, record_source_digest, spawn_local_pipeline,
+};
+use super::transcode_options::ClientTranscodeOptions;
+use super::upload::{
+    PreparedUpload, UploadOptions, UploadResponse, abandon_upload, build_upload_response,
+    check_content_length, still_uploading, upload_too_large,
+};
+
+/// Highest part number a session accepts, as in S3 multipart uploads.
+const MAX_PARTS: u32 = 10_000;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CreateUploadSessionRequest {
+    /// Name of the file, shown as the job's source.
+    #[serde(default)]
+    pub filename: Option<String>,
+    #[serde(flatten)]
+    pub options: UploadOptions,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadSessionResponse {
+    #[serde(flatten)]
+    pub upload: UploadResponse,
+    /// Parts go to `<parts_url>/<n>`, numbered from 1.
+    pub parts_url: String,
+    pub complete_url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadPartResponse {
+    pub part: u32,
+    pub size_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CompleteUploadSessionRequest {
+    /// Number of parts the client sent; completion fails unless exactly
+    /// parts 1 to `parts` arrived.
+    #[serde(default)]
+    pub parts: Option<u32>,
+}
+
+/// What the session needs once it is completed.
+#[derive(Debug, Serialize, Deserialize)]
+struct UploadSession {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encode: Option<EncodeParams>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    callback_url: Option<String>,
+}
+
+/// Opens a session and its job. The body is optional and takes the fields
+/// of a multipart `options` part plus `filename`.
+pub async fn create_upload_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    claims: Option<Extension<Claims>>,
+    request: Option<Json<CreateUploadSessionRequest>>,
+) -> Result<(StatusCode, Json<UploadSessionResponse>), AppError> {
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+    let account = usage::account_key(
+        &headers,
+        claims.as_deref(),
+        state.rate_limits.trusts_forwarded_headers(),
+    );
+    let PreparedUpload {
+        meta,
+        encode,
+        callback_url,
+        client_data,
+    } = request
+        .options
+        .prepare(ClientTranscodeOptions::default(), &account)?;
+
+    let id = create_pipeline_job(
+        &state,
+        Some(JobStage::Uploading),
+        request.filename.as_deref(),
+        encode.as_ref(),
+        Some(&account),
+        client_data,
+    )
+    .await?;
+    metadata::save(&state.storage, &id, &meta).await?;
+    state.jobs.update_stage(id, JobStage::Uploading).await?;
+    ensure_dir(&state.storage.upload_parts_dir(&id)).await?;
+    let session = UploadSession {
+        encode,
+        callback_url,
+    };
+    fs::write(
+        state.storage.upload_session_path(&id),
+        serde_json::to_vec(&session).map_err(std::io::Error::other)?,
+    )
+    .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(UploadSessionResponse {
+            upload: build_upload_response(id),
+            parts_url: format!("/upload/sessions/{id}/parts"),
+            complete_url: format!("/upload/sessions/{id}/complete"),
+        }),
+    ))
+}
+
+/// Stores the body as part `n`, replacing an earlier upload of the same
+/// part. Parts may arrive in any order and in parallel. A part that takes
+/// the session past `VIDEO_MAX_UPLOAD_BYTES` fails its job.
+pub async fn put_upload_part(
+    State(state): State<AppState>,
+    AxumPath((id, part)): AxumPath<(String, String)>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Json<UploadPartResponse>, AppError> {
+    let id = parse_session_id(&id)?;
+    let part = part
+        .parse::<u32>()
+        .ok()
+        .filter(|part| (1..=MAX_PARTS).contains(part))
+        .ok_or_else(|| {
+            AppError::validation(format!("part numbers run from 1 to {MAX_PARTS}"))
+                .with_code("upload_part_invalid")
+                .with_param("max", MAX_PARTS)
+        })?;
+    load_session(&state, &id).await?;
+    let parts_dir = state.storage.upload_parts_dir(&id);
+    let others = match state.max_upload_bytes {
+        Some(max) => {
+            let others = parts_size(&parts_dir, Some(part)).await?;
+            if check_content_length(&headers, Some(max.saturating_sub(others)), 0).is_err() {
+                return Err(abandon_session(&state, id, upload_too_large(max)).await);
+            }
+            others
+        }
+        None => 0,
+    };
+
+    let partial = parts_dir.join(format!("{part}.part.{}", Uuid::new_v4().simple()));
+    let mut file = File::create(&partial).await?;
+    let mut size_bytes = 0;
+    let transfer = ingest_transfer(&state, id).await?;
+    let mut chunks = body.into_data_stream();
+    while let Some(chunk) = chunks.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                drop(file);
+                let _ = fs::remove_file(&partial).await;
+                return Err(
+                    AppError::validation(format!("part {part} interrupted: {err}"))
+                        .with_code("upload_part_interrupted"),
+                );
+            }
+        };
+        size_bytes += chunk.len() as u64;
+        if let Some(max) = state
+            .max_upload_bytes
+            .filter(|&max| others + size_bytes > max)
+        {
+            drop(file);
+            let _ = fs::remove_file(&partial).await;
+            return Err(abandon_session(&state, id, upload_too_large(max)).await);
+        }
+        file.write_all(&chunk).await?;
+        transfer.consume(chunk.len() as u64).await;
+    }
+    file.flush().await?;
+    drop(file);
+
+    // Completion holds the lock while it reads the parts.
+    let _guard = state
+        .storage
+        .locks()
+        .acquire(&LockManager::video_key(&id, "session"))
+        .await?;
+    if let Err(err) = load_session(&state, &id).await {
+        let _ = fs::remove_file(&partial).await;
+        return Err(err);
+    }
+    fs::rename(&partial, parts_dir.join(part_name(part))).await?;
+    Ok(Json(UploadPartResponse { part, size_bytes }))
+}
+
+/// Joins the parts in order into the upload and starts transcoding it.
+pub async fn complete_upload_session(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    request: Option<Json<CompleteUploadSessionRequest>>,
+) -> Result<Json<UploadResponse>, AppError> {
+    let id = parse_session_id(&id)?;
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+    let _guard = state
+        .storage
+        .locks()
+        .acquire(&LockManager::video_key(&id, "session"))
+        .await?;
+    let session = load_session(&state, &id).await?;
+
+    let parts_dir = state.storage.upload_parts_dir(&id);
+    let count = received_parts(&parts_dir, request.parts).await?;
+    // Parts sent in parallel are only checked against each other here.
+    if let Some(max) = state.max_upload_bytes
+        && parts_size(&parts_dir, None).await? > max
+    {
+        return Err(abandon_session(&state, id, upload_too_large(max)).await);
+    }
+    let incoming = state.storage.incoming_path(&id);
+    let mut file = DigestWriter::new(File::create(&incoming).await?);
+    for part in 1..=count {
+        let mut source = File::open(parts_dir.join(part_name(part))).await?;
+        tokio::io::copy(&mut source, &mut file).await?;
+    }
+    file.flush().await?;
+    let (_, digest) = file.into_inner();
+    fs::remove_dir_all(&parts_dir).await?;
+    fs::remove_file(state.storage.upload_session_path(&id)).await?;
+
+    if let Err(err) = record_source_digest(&state, id, &incoming, Some(digest)).await {
+        return Err(abandon_upload(&state, id, err).await);
+    }
+    state.jobs.update_progress(id, 1.0).await?;
+    spawn_local_pipeline(state.clone(), id, session.encode, session.callback_url);
+    Ok(Json(build_upload_response(id)))
+}
+
+fn parse_session_id(id: &str) -> Result<Uuid, AppError> {
+    Uuid::parse_str(id).map_err(|_| AppError::validation("invalid upload session identifier"))
+}
+
+/// Fails the session's job and drops its parts.
+async fn abandon_session(state: &AppState, id: Uuid, err: AppError) -> AppError {
+    let err = abandon_upload(state, id, err).await;
+    let _ = fs::remove_dir_all(state.storage.upload_parts_dir(&id)).await;
+    let _ = fs::remove_file(state.storage.upload_session_path(&id)).await;
+    err
+}
+
+/// Bytes in the stored parts, leaving out part `except`.
+async fn parts_size(parts_dir: &std::path::Path, except: Option<u32>) -> Result<u64, AppError> {
+    let mut total = 0;
+    let mut entries = fs::read_dir(parts_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let part = part_number(&entry.file_name());
+        if part.is_some() && part != except {
+            total += entry.metadata().await?.len();
+        }
+    }
+    Ok(total)
+}
+
+fn part_name(part: u32) -> String {
+    format!("{part}.part")
+}
+
+fn part_number(file_name: &std::ffi::OsStr) -> Option<u32> {
+    file_name.to_str()?.strip_suffix(".part")?.parse().ok()
+}
+
+/// The session of `id` while its job is still waiting for the upload.
+async fn load_session(state: &AppState, id: &Uuid) -> Result<UploadSession, AppError> {
+    let not_found = || {
+        AppError::not_found(format!("upload session {id}")).with_code("upload_session_not_found")
+    };
+    if !still_uploading(state, id).await? {
+        return Err(not_found());
+    }
+    let raw = match fs::read(state.storage.upload_session_path(id)).await {
+        Ok(raw) => raw,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Err(not_found()),
+        Err(err) => return Err(err.into()),
+    };
+    serde_json::from_slice(&raw).map_err(|err| std::io::Error::other(err).into())
+}
+
+/// The number of parts received, which must run from 1 without gaps and
+/// match `expected` when given.
+async fn received_parts(
+    parts_dir: &std::path::Path,
+    expected: Option<u32>,
+) -> Result<u32, AppError> {
+    let mut parts = Vec::new();
+    let mut entries = fs::read_dir(parts_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(part) = part_number(&entry.file_name()) {
+            parts.push(part);
+        }
+    }
+    if parts.is_empty() {
+        return Err(AppError::validation("no parts were uploaded")
+            .with_code("upload_parts_missing")
+            .with_param("received", 0));
+    }
+    parts.sort_unstable();
+    let count = expected.unwrap_or(parts[parts.len() - 1]);
+    let missing: Vec<u32> = (1..=count)
+        .filter(|part| parts.binary_search(part).is_err())
+        .collect();
+    if !missing.is_empty() || parts.len() as u32 != count {
+        return Err(AppError::validation(format!(
+            "expected parts 1 to {count}, received {}",
+            parts.len()
+        ))
+        .with_code("upload_parts_missing")
+        .with_param("received", parts.len())
+        .with_param("missing", missing));
+    }
+    Ok(count)
+}
@@ -0,0 +1,250 @@
+use std::time::Duration;
+
+use axum::{
+    Json,
+    extract::{Path as AxumPath, Query, State},
+    http::{self, HeaderMap, HeaderValue, StatusCode},
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bandwidth::DeliveryKind, config, error::AppError, metadata, rate_limit::ClientAddr,
+    shares::ShareLink, state::AppState, transcode::MezzanineCodec,
+};
+
+use super::{
+    access::authorize_owner,
+    delivery::with_custom_headers,
+    files::{RangeHeader, serve_video_file, validate_relative_path},
+    streaming::{HlsQuery, serve_dash_asset, serve_hls_asset},
+};
+
+const DEFAULT_SHARE_HOURS: u32 = 24;
+const DEFAULT_MAX_SHARE_HOURS: u32 = 24 * 30;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateShareRequest {
+    /// Lifetime of the link; defaults to 24 hours.
+    pub hours: Option<u32>,
+    /// Number of playbacks allowed; unlimited if unset.
+    pub max_uses: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShareResponse {
+    #[serde(flatten)]
+    pub link: ShareLink,
+    /// Path of the embed page, relative to the server root.
+    pub url: String,
+}
+
+/// `?play=` carries the grant of a playback the link already counted.
+#[derive(Debug, Default, Deserialize)]
+pub struct ShareQuery {
+    pub play: Option<String>,
+}
+
+impl From<ShareLink> for ShareResponse {
+    fn from(link: ShareLink) -> Self {
+        let url = format!("/s/{}", link.share_id);
+        Self { link, url }
+    }
+}
+
+/// Creates a share link. Protected videos require their password in
+/// `X-Video-Password`.
+pub async fn create_share(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+    request: Option<Json<CreateShareRequest>>,
+) -> Result<(StatusCode, Json<ShareResponse>), AppError> {
+    let (video_id, _) = authorize_owner(&state, &id, &headers, client).await?;
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+
+    let max_hours =
+        config::parse_var::<u32>("VIDEO_SHARE_MAX_HOURS").unwrap_or(DEFAULT_MAX_SHARE_HOURS);
+    let hours = request.hours.unwrap_or(DEFAULT_SHARE_HOURS);
+    if hours == 0 || hours > max_hours {
+        return Err(AppError::validation(format!(
+            "hours must be between 1 and {max_hours}"
+        )));
+    }
+    if request.max_uses == Some(0) {
+        return Err(AppError::validation("max_uses must be at least 1"));
+    }
+
+    let link = state
+        .shares
+        .create(
+            video_id,
+            Duration::from_secs(u64::from(hours) * 3600),
+            request.max_uses,
+        )
+        .await?;
+    tracing::info!(%video_id, share_id = %link.share_id, hours, "share link created");
+    Ok((StatusCode::CREATED, Json(link.into())))
+}
+
+pub async fn list_shares(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+) -> Result<Json<Vec<ShareResponse>>, AppError> {
+    let (video_id, _) = authorize_owner(&state, &id, &headers, client).await?;
+    let links = state.shares.list(&video_id).await?;
+    Ok(Json(links.into_iter().map(ShareResponse::from).collect()))
+}
+
+pub async fn revoke_share(
+    State(state): State<AppState>,
+    AxumPath((id, share_id)): AxumPath<(String, String)>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+) -> Result<StatusCode, AppError> {
+    let (video_id, _) = authorize_owner(&state, &id, &headers, client).await?;
+    if !state.shares.revoke(&video_id, &share_id).await? {
+        return Err(AppError::not_found(format!("share link {share_id}")));
+    }
+    tracing::info!(%video_id, %share_id, "share link revoked");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Embed page for a share link. Each view counts as one use, and the
+/// player's requests carry its grant.
+pub async fn share_page(
+    State(state): State<AppState>,
+    AxumPath(share_id): AxumPath<String>,
+    Query(query): Query<ShareQuery>,
+) -> Result<Response, AppError> {
+    let (link, play) = state
+        .shares
+        .resolve(&share_id, query.play.as_deref())
+        .await?;
+    let meta = metadata::load(&state.storage, &link.video_id).await?;
+    let mut response = Response::new(embed_page(&link.share_id, &play, meta.mezzanine).into());
+    let headers = response.headers_mut();
+    headers.insert(
+        http::header::CONTENT_TYPE,
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    headers.insert(
+        http::header::CACHE_CONTROL,
+        HeaderValue::from_static("no-store"),
+    );
+    Ok(response)
+}
+
+/// Download through a share link. Counts as a use unless it carries the
+/// grant of a playback already counted, whatever range it asks for.
+pub async fn share_download(
+    State(state): State<AppState>,
+    AxumPath(share_id): AxumPath<String>,
+    Query(query): Query<ShareQuery>,
+    range_header: RangeHeader,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let (link, _) = state
+        .shares
+        .resolve(&share_id, query.play.as_deref())
+        .await?;
+    let meta = metadata::load(&state.storage, &link.video_id).await?;
+    let path = state.storage.download_path(&link.video_id);
+    let response = serve_video_file(path, range_header.as_deref(), meta.mezzanine).await?;
+    Ok(state.bandwidth.meter(
+        with_custom_headers(response, &meta),
+        link.video_id,
+        &headers,
+        DeliveryKind::Download,
+    ))
+}
+
+/// HLS through a share link. Playlists pass the playback's grant on to
+/// every URI, so only the first request of a playback counts as a use.
+pub async fn share_hls_asset(
+    State(state): State<AppState>,
+    AxumPath((share_id, asset)): AxumPath<(String, String)>,
+    headers: HeaderMap,
+    Query(query): Query<HlsQuery>,
+    Query(share): Query<ShareQuery>,
+) -> Result<Response, AppError> {
+    validate_relative_path(&asset)?;
+    let (link, play) = state
+        .shares
+        .resolve(&share_id, share.play.as_deref())
+        .await?;
+    let meta = metadata::load(&state.storage, &link.video_id).await?;
+    let response = serve_hls_asset(
+        &state,
+        &link.video_id,
+        &asset,
+        &headers,
+        &query,
+        Some(play_query(&play)),
+        None,
+    )
+    .await?;
+    Ok(state.bandwidth.meter(
+        with_custom_headers(response, &meta),
+        link.video_id,
+        &headers,
+        DeliveryKind::Hls,
+    ))
+}
+
+/// DASH through a share link, counted like HLS.
+pub async fn share_dash_asset(
+    State(state): State<AppState>,
+    AxumPath((share_id, asset)): AxumPath<(String, String)>,
+    headers: HeaderMap,
+    Query(share): Query<ShareQuery>,
+) -> Result<Response, AppError> {
+    validate_relative_path(&asset)?;
+    let (link, play) = state
+        .shares
+        .resolve(&share_id, share.play.as_deref())
+        .await?;
+    let meta = metadata::load(&state.storage, &link.video_id).await?;
+    let response =
+        serve_dash_asset(&state, &link.video_id, &asset, Some(play_query(&play))).await?;
+    Ok(state.bandwidth.meter(
+        with_custom_headers(response, &meta),
+        link.video_id,
+        &headers,
+        DeliveryKind::Dash,
+    ))
+}
+
+fn play_query(play: &str) -> String {
+    url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("play", play)
+        .finish()
+}
+
+/// Minimal player page. Sources are relative so the page also works behind a
+/// path prefix; browsers without native HLS fall back to the download.
+fn embed_page(share_id: &str, play: &str, download: MezzanineCodec) -> String {
+    let download_type = download.content_type();
+    let play = play_query(play);
+    format!(
+        r#"<!doctype html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Shared video</title>
+<style>html,body{{margin:0;height:100%;background:#000}}video{{display:block;width:100%;height:100%}}</style>
+</head>
+<body>
+<video controls playsinline preload="metadata">
+<source src="{share_id}/hls/master.m3u8?{play}" type="application/vnd.apple.mpegurl">
+<source src="{share_id}/download?{play}" type="{download_type}">
+</video>
+</body>
+</html>
+"#
+    )
+}
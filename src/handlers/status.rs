@@ -1,10 +1,154 @@
+use std::time::Duration;
+
 use axum::{
     Json,
-    extract::{Path as AxumPath, State},
+    extract::{
+        Path as AxumPath, Query, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::{HeaderMap, header},
+    response::{IntoResponse, Response},
 };
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
 use uuid::Uuid;
 
-use crate::{error::AppError, jobs::JobStatusResponse, state::AppState};
+use crate::{
+    batches::{self, BatchStatus},
+    clock::ClockInfo,
+    error::AppError,
+    jobs::{JobCredentials, JobGroupStatus, JobStage, JobStatusResponse},
+    state::AppState,
+    transcode::{FailureReport, FfmpegLogLine, load_failure, subscribe_ffmpeg_log},
+};
+
+const SOCKET_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Serialize)]
+pub struct HealthResponse {
+    pub status: &'static str,
+    pub clock: ClockInfo,
+}
+
+/// Readiness probe. Answers `ok` as plain text, or the server clock as JSON
+/// when the client accepts `application/json`.
+pub async fn health(headers: HeaderMap) -> Response {
+    let wants_json = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"));
+    if !wants_json {
+        return "ok".into_response();
+    }
+    Json(HealthResponse {
+        status: "ok",
+        clock: ClockInfo::now(),
+    })
+    .into_response()
+}
+
+const DEFAULT_JOB_PAGE: usize = 50;
+const MAX_JOB_PAGE: usize = 500;
+
+/// `GET /jobs` parameters. `stage` takes one stage or a comma-separated
+/// list; `sort` is `started_at` (default), `last_update` or `progress`, and
+/// `order` is `desc` (default) or `asc`.
+#[derive(Debug, Default, Deserialize)]
+pub struct JobListQuery {
+    pub stage: Option<String>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    pub sort: Option<String>,
+    pub order: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobListResponse {
+    /// Jobs matching the filter, before pagination.
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub jobs: Vec<JobStatusResponse>,
+}
+
+/// Jobs known to the job store, filtered by stage and paginated.
+pub async fn list_jobs(
+    State(state): State<AppState>,
+    Query(query): Query<JobListQuery>,
+) -> Result<Json<JobListResponse>, AppError> {
+    let stages = match query.stage.as_deref() {
+        Some(stage) => Some(parse_stages(stage)?),
+        None => None,
+    };
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_JOB_PAGE)
+        .clamp(1, MAX_JOB_PAGE);
+    let offset = query.offset.unwrap_or(0);
+    let descending = match query.order.as_deref() {
+        None | Some("desc") => true,
+        Some("asc") => false,
+        Some(other) => {
+            return Err(
+                AppError::validation(format!("order must be asc or desc, got {other:?}"))
+                    .with_code("order_invalid"),
+            );
+        }
+    };
+
+    let mut jobs: Vec<JobStatusResponse> = state
+        .jobs
+        .list()
+        .await?
+        .into_iter()
+        .filter(|job| {
+            stages
+                .as_ref()
+                .is_none_or(|stages| stages.contains(&job.stage))
+        })
+        .collect();
+    match query.sort.as_deref() {
+        None | Some("started_at") => jobs.sort_by_key(|job| job.started_at_unix_ms),
+        Some("last_update") => jobs.sort_by_key(|job| job.last_update_unix_ms),
+        Some("progress") => jobs.sort_by(|a, b| a.progress.total_cmp(&b.progress)),
+        Some(other) => {
+            return Err(AppError::validation(format!(
+                "sort must be started_at, last_update or progress, got {other:?}"
+            ))
+            .with_code("sort_invalid"));
+        }
+    }
+    if descending {
+        jobs.reverse();
+    }
+
+    let total = jobs.len();
+    let jobs = jobs.into_iter().skip(offset).take(limit).collect();
+    Ok(Json(JobListResponse {
+        total,
+        offset,
+        limit,
+        jobs,
+    }))
+}
+
+fn parse_stages(value: &str) -> Result<Vec<JobStage>, AppError> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            JobStage::ALL
+                .into_iter()
+                .find(|stage| stage.as_str() == name)
+                .ok_or_else(|| {
+                    AppError::validation(format!("unknown job stage {name:?}"))
+                        .with_code("stage_invalid")
+                        .with_param("stage", name)
+                })
+        })
+        .collect()
+}
 
 pub async fn job_status(
     State(state): State<AppState>,
@@ -13,7 +157,222 @@ pub async fn job_status(
     let job_id =
         Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid job identifier"))?;
     match state.jobs.status(&job_id).await? {
+        Some(mut status) => {
+            // Under sharded dispatch, the proxy renders wherever the claim is.
+            status.review_ready = status.stage == JobStage::AwaitingApproval
+                && status.instance.is_none()
+                && !state.running.is_running(&job_id);
+            Ok(Json(status))
+        }
+        None => Err(AppError::not_found(format!("job {job_id} not found"))
+            .with_code("job_not_found")
+            .with_param("id", job_id.to_string())),
+    }
+}
+
+/// Aggregate status of a job and its child jobs (e.g. optional post-processing).
+pub async fn job_group_status(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+) -> Result<Json<JobGroupStatus>, AppError> {
+    let job_id =
+        Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid job identifier"))?;
+    match state.jobs.group_status(&job_id).await? {
         Some(status) => Ok(Json(status)),
-        None => Err(AppError::not_found(format!("job {job_id} not found"))),
+        None => Err(AppError::not_found(format!("job {job_id} not found"))
+            .with_code("job_not_found")
+            .with_param("id", job_id.to_string())),
+    }
+}
+
+/// Aggregate status of the jobs created from a playlist.
+pub async fn batch_status(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+) -> Result<Json<BatchStatus>, AppError> {
+    let batch_id =
+        Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid batch identifier"))?;
+    Ok(Json(
+        batches::status(&state.storage, &state.jobs, &batch_id).await?,
+    ))
+}
+
+/// What was captured when the job's encode failed: the error, where in the
+/// source it happened, and the source's streams.
+pub async fn job_diagnostics(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+) -> Result<Json<FailureReport>, AppError> {
+    let job_id =
+        Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid job identifier"))?;
+    match load_failure(&state.storage, &job_id).await? {
+        Some(report) => Ok(Json(report)),
+        None => Err(diagnostics_missing(job_id)),
     }
 }
+
+/// The source frame at which the job's encode failed.
+pub async fn job_diagnostics_frame(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+) -> Result<Response, AppError> {
+    let job_id =
+        Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid job identifier"))?;
+    let path = state.storage.diagnostics_dir(&job_id).join("frame.jpg");
+    match tokio::fs::read(&path).await {
+        Ok(bytes) => Ok(([(header::CONTENT_TYPE, "image/jpeg")], bytes).into_response()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Err(diagnostics_missing(job_id)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn diagnostics_missing(job_id: Uuid) -> AppError {
+    AppError::not_found(format!("no failure diagnostics for job {job_id}"))
+        .with_code("diagnostics_missing")
+        .with_param("id", job_id.to_string())
+}
+
+/// `GET /jobs/{id}/ws` parameters; `logs=true` adds ffmpeg's stderr.
+#[derive(Debug, Default, Deserialize)]
+pub struct JobSocketQuery {
+    #[serde(default)]
+    pub logs: bool,
+}
+
+/// A text frame sent on a job's WebSocket.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum JobSocketMessage<'a> {
+    Status {
+        status: &'a JobStatusResponse,
+    },
+    Log {
+        #[serde(flatten)]
+        line: &'a FfmpegLogLine,
+    },
+    /// Log lines dropped because the client read too slowly.
+    LogsSkipped {
+        count: u64,
+    },
+}
+
+/// Upgrades to a WebSocket that sends the job's status whenever it changes
+/// and closes once the job has completed or failed.
+pub async fn job_socket(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    Query(query): Query<JobSocketQuery>,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, AppError> {
+    let job_id =
+        Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid job identifier"))?;
+    let Some(status) = state.jobs.status(&job_id).await? else {
+        return Err(AppError::not_found(format!("job {job_id} not found"))
+            .with_code("job_not_found")
+            .with_param("id", job_id.to_string()));
+    };
+    let logs = query.logs.then(|| subscribe_ffmpeg_log(job_id));
+    Ok(upgrade.on_upgrade(move |socket| stream_job(state, status, logs, socket)))
+}
+
+async fn stream_job(
+    state: AppState,
+    mut status: JobStatusResponse,
+    mut logs: Option<broadcast::Receiver<FfmpegLogLine>>,
+    mut socket: WebSocket,
+) {
+    if send_message(&mut socket, JobSocketMessage::Status { status: &status })
+        .await
+        .is_err()
+    {
+        return;
+    }
+    let mut poll = tokio::time::interval(SOCKET_POLL_INTERVAL);
+    while !status.stage.is_terminal() {
+        let message = tokio::select! {
+            _ = poll.tick() => match state.jobs.status(&status.id).await {
+                Ok(Some(current)) if current.last_update_unix_ms != status.last_update_unix_ms => {
+                    status = current;
+                    send_message(&mut socket, JobSocketMessage::Status { status: &status }).await
+                }
+                Ok(Some(_)) => Ok(()),
+                Ok(None) => break,
+                Err(err) => {
+                    tracing::warn!(job_id = %status.id, error = %err, "job socket cannot read status");
+                    break;
+                }
+            },
+            line = next_log(&mut logs) => match line {
+                Ok(line) => send_message(&mut socket, JobSocketMessage::Log { line: &line }).await,
+                Err(RecvError::Lagged(count)) => {
+                    send_message(&mut socket, JobSocketMessage::LogsSkipped { count }).await
+                }
+                Err(RecvError::Closed) => {
+                    logs = None;
+                    Ok(())
+                }
+            },
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => return,
+                Some(Ok(_)) => Ok(()),
+            },
+        };
+        if message.is_err() {
+            return;
+        }
+    }
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+async fn next_log(
+    logs: &mut Option<broadcast::Receiver<FfmpegLogLine>>,
+) -> Result<FfmpegLogLine, RecvError> {
+    match logs {
+        Some(logs) => logs.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Sends `message` as a JSON text frame; fails once the client has gone.
+async fn send_message(
+    socket: &mut WebSocket,
+    message: JobSocketMessage<'_>,
+) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(&message).map_err(axum::Error::new)?;
+    socket.send(Message::Text(text.into())).await
+}
+
+/// Cancels a running job, stopping whatever external tool it is running.
+pub async fn cancel_job(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+) -> Result<Json<JobStatusResponse>, AppError> {
+    let job_id =
+        Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid job identifier"))?;
+    Ok(Json(super::cancel_running_job(&state, job_id).await?))
+}
+
+/// Starts the full encode of a job waiting for approval.
+pub async fn approve_job(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+) -> Result<Json<JobStatusResponse>, AppError> {
+    let job_id =
+        Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid job identifier"))?;
+    Ok(Json(super::approve_awaiting_job(&state, job_id).await?))
+}
+
+/// Re-runs a failed job under the same id, with the per-request
+/// credentials it was submitted with in the body.
+pub async fn retry_job(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    credentials: Option<Json<JobCredentials>>,
+) -> Result<Json<JobStatusResponse>, AppError> {
+    let job_id =
+        Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid job identifier"))?;
+    let credentials = credentials.map(|Json(credentials)| credentials);
+    Ok(Json(
+        super::retry_failed_job(&state, job_id, credentials.unwrap_or_default()).await?,
+    ))
+}
@@ -0,0 +1,388 @@
+use std::time::Duration;
+
+use axum::{
+    extract::{Path as AxumPath, Query, State},
+    http::{self, HeaderMap, HeaderValue},
+    response::Response,
+};
+use serde::Deserialize;
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+    bandwidth::DeliveryKind,
+    config,
+    error::AppError,
+    metadata,
+    playlist::{
+        VariantFilter, absolutize_playlist, add_session_data, append_query_to_mpd,
+        append_query_to_playlist, filter_master_playlist, negotiate_codecs,
+    },
+    rate_limit::ClientAddr,
+    signing::{PlaybackSigner, token_expiry, unix_now},
+    skip_segments::annotate_media_playlist,
+    state::AppState,
+    transcode::{ensure_dash_ready, ensure_hls_ready},
+    video_access::{AccessSigner, forwarded_query, unlock, verify_playback},
+};
+
+use super::{
+    delivery::{PlaybackQuery, with_custom_headers},
+    files::{read_text_asset, serve_static_file, text_response, validate_relative_path},
+};
+
+const MASTER_PLAYLISTS: [&str; 2] = ["master.m3u8", "index.m3u8"];
+const DEFAULT_OFFLINE_MAX_TTL_SECS: u64 = 30 * 24 * 60 * 60;
+/// `EXT-X-SESSION-DATA` id under which offline master playlists announce
+/// when their URLs expire.
+const OFFLINE_EXPIRES_DATA_ID: &str = "com.vrs.expires";
+
+/// Optional master playlist filters, e.g. `?max_height=720&codecs=h264`.
+#[derive(Debug, Default, Deserialize)]
+pub struct HlsQuery {
+    pub max_height: Option<u32>,
+    pub codecs: Option<String>,
+    pub token: Option<String>,
+    pub access: Option<String>,
+    /// Rewrite the playlist's URIs as absolute, signed URLs for players that
+    /// save it and play later.
+    #[serde(default, deserialize_with = "query_flag")]
+    pub offline: bool,
+    /// Seconds an offline master playlist's URLs stay valid.
+    pub expires_in: Option<u64>,
+}
+
+/// How an offline playlist is rewritten.
+pub(crate) struct OfflinePlaylist {
+    /// The playlist's own absolute URL, which its URIs are resolved against.
+    pub url: Url,
+    /// When the URLs of a master playlist expire, as a unix timestamp.
+    pub expires_unix: Option<u64>,
+}
+
+/// Reads `1`, `true`, `yes` or `on` as set; anything else, or no value, as unset.
+fn query_flag<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    let value = Option::<String>::deserialize(deserializer)?;
+    Ok(value.is_some_and(|value| matches!(value.trim(), "1" | "true" | "yes" | "on")))
+}
+
+pub async fn get_hls_asset(
+    State(state): State<AppState>,
+    AxumPath((id, asset)): AxumPath<(String, String)>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+    Query(query): Query<HlsQuery>,
+) -> Result<Response, AppError> {
+    let video_id =
+        Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid video identifier"))?;
+    validate_relative_path(&asset)?;
+    let signer = verify_playback(&video_id, query.token.as_deref())?;
+    let meta = metadata::load(&state.storage, &video_id).await?;
+    let access = unlock(
+        &state,
+        &video_id,
+        &meta,
+        &headers,
+        client,
+        query.access.as_deref(),
+    )
+    .await?;
+    let (forward, offline) = if query.offline && asset.ends_with(".m3u8") {
+        let (forward, offline) = offline_playlist(
+            &video_id,
+            &asset,
+            &headers,
+            state.rate_limits.trusts_forwarded_headers(),
+            &query,
+            &signer,
+            access.as_deref(),
+            meta.password_hash.as_deref(),
+        )?;
+        (forward, Some(offline))
+    } else {
+        (
+            forwarded_query(&signer, query.token.as_deref(), access.as_deref()),
+            None,
+        )
+    };
+    let response = serve_hls_asset(
+        &state, &video_id, &asset, &headers, &query, forward, offline,
+    )
+    .await?;
+    Ok(state.bandwidth.meter(
+        with_custom_headers(response, &meta),
+        video_id,
+        &headers,
+        DeliveryKind::Hls,
+    ))
+}
+
+/// Works out how an `?offline=1` playlist is rewritten. A master playlist
+/// gets a fresh session token and, for a protected video, a fresh access
+/// token, both valid for `expires_in`, which its variant URLs carry along
+/// with `offline=1`; media playlists pass on the tokens they were requested
+/// with, so every URL of the snapshot expires together. The fresh tokens
+/// never outlive the ones the master playlist was requested with.
+#[allow(clippy::too_many_arguments)]
+fn offline_playlist(
+    video_id: &Uuid,
+    asset: &str,
+    headers: &HeaderMap,
+    trust_forwarded: bool,
+    query: &HlsQuery,
+    signer: &Option<PlaybackSigner>,
+    access: Option<&str>,
+    password_hash: Option<&str>,
+) -> Result<(Option<String>, OfflinePlaylist), AppError> {
+    let url = public_base_url(headers, trust_forwarded)?
+        .join(&format!("videos/{video_id}/hls/{asset}"))
+        .map_err(|err| AppError::validation(format!("invalid asset path: {err}")))?;
+    if !MASTER_PLAYLISTS.contains(&asset) {
+        let forward = forwarded_query(signer, query.token.as_deref(), access);
+        let offline = OfflinePlaylist {
+            url,
+            expires_unix: None,
+        };
+        return Ok((forward, offline));
+    }
+
+    let max_ttl = config::parse_var::<u64>("VIDEO_OFFLINE_MAX_TTL_SECS")
+        .unwrap_or(DEFAULT_OFFLINE_MAX_TTL_SECS);
+    let access_signer = password_hash.map(|hash| (AccessSigner::from_env(), hash));
+    let default_ttl = match (signer, &access_signer) {
+        (Some(signer), _) => Some(signer.ttl()),
+        (None, Some((access_signer, _))) => Some(access_signer.ttl()),
+        (None, None) => None,
+    };
+    let ttl = match default_ttl {
+        Some(default_ttl) => {
+            let ttl = query.expires_in.unwrap_or(default_ttl.as_secs());
+            if ttl == 0 || ttl > max_ttl {
+                return Err(AppError::validation(format!(
+                    "expires_in must be between 1 and {max_ttl} seconds"
+                ))
+                .with_code("offline_expiry_invalid")
+                .with_param("max_secs", max_ttl));
+            }
+            // A presented token caps the snapshot, so a short-lived token
+            // cannot be traded for a long one. An access token freshly
+            // issued for the password is not a cap.
+            let presented = [
+                signer.as_ref().and(query.token.as_deref()),
+                access.filter(|access| query.access.as_deref() == Some(*access)),
+            ];
+            let remaining = presented
+                .into_iter()
+                .flatten()
+                .filter_map(token_expiry)
+                .map(|expires| expires.saturating_sub(unix_now()))
+                .min();
+            Some(Duration::from_secs(
+                remaining.map_or(ttl, |remaining| ttl.min(remaining)),
+            ))
+        }
+        None => None,
+    };
+    let token = signer
+        .as_ref()
+        .zip(ttl)
+        .map(|(signer, ttl)| signer.issue_for(video_id, ttl));
+    let access = access_signer
+        .zip(ttl)
+        .map(|((access_signer, hash), ttl)| access_signer.issue_for(video_id, hash, ttl));
+    let expires_unix = token
+        .as_ref()
+        .or(access.as_ref())
+        .map(|(_, expires)| *expires);
+    let mut forward = url::form_urlencoded::Serializer::new(String::new());
+    forward.append_pair("offline", "1");
+    if let Some(query) = forwarded_query(
+        signer,
+        token.as_ref().map(|(token, _)| token.as_str()),
+        access.as_ref().map(|(access, _)| access.as_str()),
+    ) {
+        forward.extend_pairs(url::form_urlencoded::parse(query.as_bytes()));
+    }
+    Ok((
+        Some(forward.finish()),
+        OfflinePlaylist { url, expires_unix },
+    ))
+}
+
+/// The URL clients reach this server at: `VIDEO_PUBLIC_BASE_URL`, else,
+/// behind a trusted proxy (`VIDEO_RATE_LIMIT_TRUST_FORWARDED_FOR`), the
+/// request's `Host` over the scheme in `X-Forwarded-Proto` or plain HTTP.
+fn public_base_url(headers: &HeaderMap, trust_forwarded: bool) -> Result<Url, AppError> {
+    let base = match config::var("VIDEO_PUBLIC_BASE_URL").filter(|base| !base.is_empty()) {
+        Some(base) => base,
+        None => {
+            let host = header_str(headers, "host")
+                .filter(|_| trust_forwarded)
+                .ok_or_else(|| {
+                    AppError::validation(
+                        "offline playlists need VIDEO_PUBLIC_BASE_URL, or a Host header from a trusted proxy",
+                    )
+                    .with_code("public_base_url_unknown")
+                })?;
+            let scheme = header_str(headers, "x-forwarded-proto").unwrap_or("http");
+            format!("{scheme}://{host}")
+        }
+    };
+    let base = if base.ends_with('/') {
+        base
+    } else {
+        format!("{base}/")
+    };
+    Url::parse(&base).map_err(|err| {
+        AppError::validation(format!("invalid public base URL {base:?}: {err}"))
+            .with_code("public_base_url_unknown")
+    })
+}
+
+/// Serves an HLS asset once access has been checked, filtering master
+/// playlists, marking skip segments in media playlists and appending
+/// `forward` to every URI of rewritten playlists. `offline` playlists have
+/// their URIs made absolute as well.
+pub(crate) async fn serve_hls_asset(
+    state: &AppState,
+    video_id: &Uuid,
+    asset: &str,
+    headers: &HeaderMap,
+    query: &HlsQuery,
+    forward: Option<String>,
+    offline: Option<OfflinePlaylist>,
+) -> Result<Response, AppError> {
+    ensure_hls_ready(&state.storage, &state.process_runner, video_id).await?;
+    let path = state.storage.hls_dir(video_id).join(asset);
+
+    if !asset.ends_with(".m3u8") {
+        return serve_static_file(path).await;
+    }
+    let is_master = MASTER_PLAYLISTS.contains(&asset);
+    let meta = metadata::load(&state.storage, video_id).await?;
+    if !is_master && forward.is_none() && offline.is_none() && meta.skip_segments.is_empty() {
+        return serve_static_file(path).await;
+    }
+
+    let mut playlist = read_text_asset(&path).await?;
+    if !is_master {
+        playlist = annotate_media_playlist(&playlist, &meta.skip_segments);
+    }
+    // Audio-only ladders have no video variants to filter by height or codec.
+    if is_master && !meta.audio_only {
+        let fallback = meta.profile.hls_packaging().codec.family();
+        let explicit = VariantFilter::from_query(query.max_height, query.codecs.as_deref())?;
+        let negotiated = query
+            .codecs
+            .is_none()
+            .then(|| {
+                negotiate_codecs(
+                    header_str(headers, "user-agent"),
+                    header_str(headers, "accept"),
+                )
+            })
+            .flatten();
+
+        playlist = match negotiated {
+            // Negotiation only narrows the ladder; if nothing the client is known to
+            // decode exists, fall back to the full set rather than failing playback.
+            Some(codecs) => {
+                let negotiated = VariantFilter {
+                    codecs,
+                    ..explicit.clone()
+                };
+                match filter_master_playlist(&playlist, &negotiated, Some(fallback)) {
+                    Ok(filtered) => filtered,
+                    Err(_) => filter_master_playlist(&playlist, &explicit, Some(fallback))?,
+                }
+            }
+            None if explicit.is_empty() => playlist,
+            None => filter_master_playlist(&playlist, &explicit, Some(fallback))?,
+        };
+    }
+    match &offline {
+        Some(offline) => {
+            playlist = absolutize_playlist(&playlist, &offline.url, forward.as_deref());
+            if let Some(expires) = offline.expires_unix {
+                playlist =
+                    add_session_data(&playlist, OFFLINE_EXPIRES_DATA_ID, &expires.to_string());
+            }
+        }
+        None => {
+            if let Some(forward) = &forward {
+                playlist = append_query_to_playlist(&playlist, forward);
+            }
+        }
+    }
+
+    let mut response = text_response(playlist, "application/vnd.apple.mpegurl");
+    // Offline URLs may be built from the Host header.
+    let vary = match (is_master, offline.is_some()) {
+        (true, true) => Some("User-Agent, Accept, Host"),
+        (true, false) => Some("User-Agent, Accept"),
+        (false, true) => Some("Host"),
+        (false, false) => None,
+    };
+    if let Some(vary) = vary {
+        response
+            .headers_mut()
+            .insert(http::header::VARY, HeaderValue::from_static(vary));
+    }
+    Ok(response)
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+pub async fn get_dash_asset(
+    State(state): State<AppState>,
+    AxumPath((id, asset)): AxumPath<(String, String)>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+    Query(query): Query<PlaybackQuery>,
+) -> Result<Response, AppError> {
+    let video_id =
+        Uuid::parse_str(&id).map_err(|_| AppError::validation("invalid video identifier"))?;
+    validate_relative_path(&asset)?;
+    let signer = verify_playback(&video_id, query.token.as_deref())?;
+    let meta = metadata::load(&state.storage, &video_id).await?;
+    let access = unlock(
+        &state,
+        &video_id,
+        &meta,
+        &headers,
+        client,
+        query.access.as_deref(),
+    )
+    .await?;
+    let forward = forwarded_query(&signer, query.token.as_deref(), access.as_deref());
+    let response = serve_dash_asset(&state, &video_id, &asset, forward).await?;
+    Ok(state.bandwidth.meter(
+        with_custom_headers(response, &meta),
+        video_id,
+        &headers,
+        DeliveryKind::Dash,
+    ))
+}
+
+/// Serves a DASH asset once access has been checked, appending `forward` to
+/// the URIs of the MPD.
+pub(crate) async fn serve_dash_asset(
+    state: &AppState,
+    video_id: &Uuid,
+    asset: &str,
+    forward: Option<String>,
+) -> Result<Response, AppError> {
+    ensure_dash_ready(&state.storage, &state.process_runner, video_id).await?;
+    let path = state.storage.dash_dir(video_id).join(asset);
+
+    match forward {
+        Some(forward) if asset.ends_with(".mpd") => {
+            let manifest = read_text_asset(&path).await?;
+            let manifest = append_query_to_mpd(&manifest, &forward);
+            Ok(text_response(manifest, "application/dash+xml"))
+        }
+        _ => serve_static_file(path).await,
+    }
+}
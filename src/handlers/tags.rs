@@ -0,0 +1,71 @@
+use std::collections::BTreeSet;
+
+use axum::{
+    Json,
+    extract::{Path as AxumPath, State},
+    http::HeaderMap,
+};
+use serde::Deserialize;
+
+use crate::{
+    error::AppError,
+    rate_limit::ClientAddr,
+    state::AppState,
+    tags::{self, TagSummary, TaggedVideo},
+};
+
+use super::access::authorize_owner;
+
+#[derive(Debug, Deserialize)]
+pub struct AddTagsRequest {
+    pub tags: Vec<String>,
+}
+
+pub async fn get_video_tags(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+) -> Result<Json<BTreeSet<String>>, AppError> {
+    let (_, meta) = authorize_owner(&state, &id, &headers, client).await?;
+    Ok(Json(meta.tags))
+}
+
+/// Adds tags to a video and returns its full tag set.
+pub async fn add_video_tags(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+    Json(request): Json<AddTagsRequest>,
+) -> Result<Json<BTreeSet<String>>, AppError> {
+    let (video_id, _) = authorize_owner(&state, &id, &headers, client).await?;
+    let tags = tags::add_tags(&state.storage, &video_id, &request.tags).await?;
+    tracing::info!(%video_id, ?tags, "video tags updated");
+    Ok(Json(tags))
+}
+
+/// Removes a tag from a video and returns the remaining tags.
+pub async fn remove_video_tag(
+    State(state): State<AppState>,
+    AxumPath((id, tag)): AxumPath<(String, String)>,
+    headers: HeaderMap,
+    ClientAddr(client): ClientAddr,
+) -> Result<Json<BTreeSet<String>>, AppError> {
+    let (video_id, _) = authorize_owner(&state, &id, &headers, client).await?;
+    Ok(Json(
+        tags::remove_tag(&state.storage, &video_id, &tag).await?,
+    ))
+}
+
+pub async fn list_tags(State(state): State<AppState>) -> Result<Json<Vec<TagSummary>>, AppError> {
+    Ok(Json(tags::summarize(&state.storage).await?))
+}
+
+pub async fn list_tagged_videos(
+    State(state): State<AppState>,
+    AxumPath(tag): AxumPath<String>,
+) -> Result<Json<Vec<TaggedVideo>>, AppError> {
+    let tag = tags::normalize_tag(&tag)?;
+    Ok(Json(tags::list_videos(&state.storage, Some(&tag)).await?))
+}
@@ -0,0 +1,169 @@
+use axum::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::AppError,
+    transcode::{AudioPresentation, EncodeParams, EncoderKind, LadderLimits, TranscodeProfile},
+};
+
+/// Transcode options for a multipart upload in query-string form, e.g.
+/// `X-VRS-Transcode: crf=28&cpu_used=6`. May be repeated.
+const TRANSCODE_HEADER: &str = "x-vrs-transcode";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct ClientTranscodeOptions {
+    pub crf: Option<u8>,
+    #[serde(default, rename = "cpu_used")]
+    pub cpu_used: Option<u8>,
+    #[serde(default)]
+    pub profile: Option<TranscodeProfile>,
+    /// Seconds per image when the upload is a still image or a zip of images.
+    #[serde(default)]
+    pub image_seconds: Option<f32>,
+    /// Frame rate of videos rendered from still images.
+    #[serde(default)]
+    pub fps: Option<u32>,
+    /// What to publish when the upload has audio but no video.
+    #[serde(default)]
+    pub audio_presentation: Option<AudioPresentation>,
+    /// Also render an animated `preview.webp`.
+    #[serde(default)]
+    pub preview: Option<bool>,
+    /// Also render `proxy.mp4`, a 480p H.264 copy that is ready long before
+    /// the encode.
+    #[serde(default)]
+    pub proxy: Option<bool>,
+    /// Render a review proxy and wait for `POST /jobs/{id}/approve` before
+    /// the full encode.
+    #[serde(default)]
+    pub approval: Option<bool>,
+    /// Publish what decoded before a corrupt tail instead of failing.
+    #[serde(default)]
+    pub salvage: Option<bool>,
+    /// Keep the original file, served at `/videos/{id}/source`.
+    #[serde(default)]
+    pub keep_source: Option<bool>,
+    /// Leave ladder rungs taller than this out of HLS and DASH.
+    #[serde(default)]
+    pub max_height: Option<u32>,
+    /// Leave ladder rungs with a higher average bitrate out of HLS and DASH.
+    #[serde(default)]
+    pub max_bitrate_kbps: Option<u32>,
+    /// Add 240p and 144p rungs for slow mobile networks, overriding the
+    /// profile's default.
+    #[serde(default)]
+    pub low_rungs: Option<bool>,
+    /// Encoder to try first, e.g. `software` for batch jobs. It must be
+    /// usable according to `GET /capabilities`.
+    #[serde(default)]
+    pub encoder: Option<EncoderKind>,
+}
+
+impl From<ClientTranscodeOptions> for EncodeParams {
+    fn from(options: ClientTranscodeOptions) -> Self {
+        let mut params = EncodeParams::default();
+        if let Some(crf) = options.crf {
+            params.crf = crf;
+        }
+        if let Some(cpu) = options.cpu_used {
+            params.cpu_used = cpu;
+        }
+        if let Some(profile) = options.profile {
+            params.profile = profile;
+        }
+        if let Some(seconds) = options.image_seconds.filter(|value| value.is_finite()) {
+            params.slideshow.image_seconds = seconds;
+        }
+        if let Some(fps) = options.fps {
+            params.slideshow.fps = fps;
+        }
+        if let Some(presentation) = options.audio_presentation {
+            params.audio_presentation = presentation;
+        }
+        if let Some(preview) = options.preview {
+            params.preview = preview;
+        }
+        if let Some(proxy) = options.proxy {
+            params.proxy = proxy;
+        }
+        if let Some(approval) = options.approval {
+            params.approval = approval;
+        }
+        if let Some(salvage) = options.salvage {
+            params.salvage = salvage;
+        }
+        params.keep_source = options.keep_source;
+        params.ladder = LadderLimits {
+            max_height: options.max_height,
+            max_bitrate_kbps: options.max_bitrate_kbps,
+            low_rungs: options.low_rungs,
+        };
+        params.encoder = options.encoder;
+        params.sanitized()
+    }
+}
+
+impl ClientTranscodeOptions {
+    /// Fills the fields unset in `self` from `fallback`.
+    pub fn or(self, fallback: Self) -> Self {
+        Self {
+            crf: self.crf.or(fallback.crf),
+            cpu_used: self.cpu_used.or(fallback.cpu_used),
+            profile: self.profile.or(fallback.profile),
+            image_seconds: self.image_seconds.or(fallback.image_seconds),
+            fps: self.fps.or(fallback.fps),
+            audio_presentation: self.audio_presentation.or(fallback.audio_presentation),
+            preview: self.preview.or(fallback.preview),
+            proxy: self.proxy.or(fallback.proxy),
+            approval: self.approval.or(fallback.approval),
+            salvage: self.salvage.or(fallback.salvage),
+            keep_source: self.keep_source.or(fallback.keep_source),
+            max_height: self.max_height.or(fallback.max_height),
+            max_bitrate_kbps: self.max_bitrate_kbps.or(fallback.max_bitrate_kbps),
+            low_rungs: self.low_rungs.or(fallback.low_rungs),
+            encoder: self.encoder.or(fallback.encoder),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.crf.is_none()
+            && self.cpu_used.is_none()
+            && self.profile.is_none()
+            && self.image_seconds.is_none()
+            && self.fps.is_none()
+            && self.audio_presentation.is_none()
+            && self.preview.is_none()
+            && self.proxy.is_none()
+            && self.approval.is_none()
+            && self.salvage.is_none()
+            && self.keep_source.is_none()
+            && self.max_height.is_none()
+            && self.max_bitrate_kbps.is_none()
+            && self.low_rungs.is_none()
+            && self.encoder.is_none()
+    }
+
+    /// Options given on the multipart request line: the query string, then
+    /// any `X-VRS-Transcode` headers for fields the query leaves unset.
+    pub(super) fn from_request(query: Option<&str>, headers: &HeaderMap) -> Result<Self, AppError> {
+        let mut options = match query {
+            Some(query) => parse_transcode_form(query)?,
+            None => Self::default(),
+        };
+        for value in headers.get_all(TRANSCODE_HEADER) {
+            let value = value.to_str().map_err(|_| {
+                AppError::validation("X-VRS-Transcode must be visible ASCII")
+                    .with_code("transcode_options_invalid")
+            })?;
+            options = options.or(parse_transcode_form(value)?);
+        }
+        Ok(options)
+    }
+}
+
+pub(super) fn parse_transcode_form(form: &str) -> Result<ClientTranscodeOptions, AppError> {
+    serde_urlencoded::from_str(form).map_err(|err| {
+        AppError::validation(format!("invalid transcode options: {err}"))
+            .with_code("transcode_options_invalid")
+    })
+}
@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+
+use axum::{
+    Extension, Json,
+    body::Body,
+    extract::{Path as AxumPath, RawQuery, Request, State},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::{
+    auth::Claims,
+    callbacks,
+    error::AppError,
+    jobs::JobStage,
+    locks::LockManager,
+    metadata::{self, VideoMetadata},
+    state::AppState,
+    storage::ensure_parent,
+    tags,
+    transcode::EncodeParams,
+    usage,
+};
+
+use super::pipeline::{
+    create_pipeline_job, ingest_transfer, record_source_digest, spawn_local_pipeline,
+};
+use super::transcode_options::{ClientTranscodeOptions, parse_transcode_form};
+use super::upload::{
+    abandon_upload, build_upload_response, still_uploading, upload_too_large, validate_client_data,
+};
+
+/// The only protocol version spoken, see <https://tus.io/protocols/resumable-upload>.
+const TUS_VERSION: &str = "1.0.0";
+const TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+const TUS_VERSION_HEADER: HeaderName = HeaderName::from_static("tus-version");
+const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+const UPLOAD_METADATA: HeaderName = HeaderName::from_static("upload-metadata");
+const OFFSET_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+/// What a tus upload needs once its last byte arrives, kept next to the
+/// partial file in the incoming area.
+#[derive(Debug, Serialize, Deserialize)]
+struct TusUpload {
+    length: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encode: Option<EncodeParams>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    callback_url: Option<String>,
+}
+
+/// Route layer for the tus routes: refuses requests without
+/// `Tus-Resumable: 1.0.0` (`412`) and `PATCH` bodies of another content type
+/// (`415`), and marks every response with the protocol version.
+pub async fn tus_protocol(request: Request, next: Next) -> Response {
+    let headers = request.headers();
+    let mut response = if headers
+        .get(TUS_RESUMABLE)
+        .is_none_or(|value| value != TUS_VERSION)
+    {
+        let mut response = AppError::validation(format!("only tus {TUS_VERSION} is supported"))
+            .with_code("tus_version_unsupported")
+            .into_response();
+        *response.status_mut() = StatusCode::PRECONDITION_FAILED;
+        response
+            .headers_mut()
+            .insert(TUS_VERSION_HEADER, HeaderValue::from_static(TUS_VERSION));
+        response
+    } else if request.method() == Method::PATCH
+        && headers
+            .get(header::CONTENT_TYPE)
+            .is_none_or(|value| value != OFFSET_CONTENT_TYPE)
+    {
+        let mut response = AppError::validation(format!(
+            "upload chunks must be sent as {OFFSET_CONTENT_TYPE}"
+        ))
+        .with_code("tus_content_type_invalid")
+        .into_response();
+        *response.status_mut() = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+        response
+    } else {
+        next.run(request).await
+    };
+    response
+        .headers_mut()
+        .insert(TUS_RESUMABLE, HeaderValue::from_static(TUS_VERSION));
+    response
+}
+
+/// Creates an upload of `Upload-Length` bytes and its job, answering with
+/// the upload URL in `Location`. `Upload-Metadata` may carry `filename`,
+/// `tags` (comma-separated), `callback_url`, `client_data` as JSON, and
+/// `transcode` options in query-string form, which win over the query and
+/// `X-VRS-Transcode`.
+pub async fn tus_create(
+    State(state): State<AppState>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+    claims: Option<Extension<Claims>>,
+) -> Result<Response, AppError> {
+    let length = header_u64(&headers, &UPLOAD_LENGTH)
+        .filter(|&length| length > 0)
+        .ok_or_else(|| {
+            AppError::validation("Upload-Length must be a positive byte count")
+                .with_code("tus_length_invalid")
+        })?;
+    if let Some(max) = state.max_upload_bytes.filter(|&max| length > max) {
+        return Err(upload_too_large(max));
+    }
+    let upload_metadata = upload_metadata(&headers)?;
+    let account = usage::account_key(
+        &headers,
+        claims.as_deref(),
+        state.rate_limits.trusts_forwarded_headers(),
+    );
+
+    let requested = ClientTranscodeOptions::from_request(query.as_deref(), &headers)?;
+    let transcode = match upload_metadata.get("transcode") {
+        Some(form) => parse_transcode_form(form)?.or(requested),
+        None => requested,
+    };
+    let encode = (!transcode.is_empty()).then(|| EncodeParams::from(transcode));
+    let callback_url = upload_metadata
+        .get("callback_url")
+        .map(|url| callbacks::validate_url(url))
+        .transpose()?;
+    let client_data = upload_metadata
+        .get("client_data")
+        .map(|data| {
+            serde_json::from_str(data).map_err(|err| {
+                AppError::validation(format!("client_data is not JSON: {err}"))
+                    .with_code("client_data_invalid")
+            })
+        })
+        .transpose()?;
+    let client_data = validate_client_data(client_data)?;
+    let tag_names: Vec<String> = upload_metadata
+        .get("tags")
+        .into_iter()
+        .flat_map(|tags| tags.split(','))
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_string)
+        .collect();
+    let meta = VideoMetadata {
+        tags: tags::initial_tags(&tag_names)?,
+        account: Some(account.clone()),
+        ..VideoMetadata::default()
+    };
+
+    let id = create_pipeline_job(
+        &state,
+        Some(JobStage::Uploading),
+        upload_metadata.get("filename").map(String::as_str),
+        encode.as_ref(),
+        Some(&account),
+        client_data,
+    )
+    .await?;
+    metadata::save(&state.storage, &id, &meta).await?;
+    state.jobs.update_stage(id, JobStage::Uploading).await?;
+    let incoming = state.storage.incoming_path(&id);
+    ensure_parent(&incoming).await?;
+    File::create(&incoming).await?;
+    let upload = TusUpload {
+        length,
+        encode,
+        callback_url,
+    };
+    fs::write(
+        state.storage.tus_upload_path(&id),
+        serde_json::to_vec(&upload).map_err(std::io::Error::other)?,
+    )
+    .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, format!("/upload/tus/{id}"))],
+        Json(build_upload_response(id)),
+    )
+        .into_response())
+}
+
+/// Reports how many bytes of the upload have arrived, so a client can
+/// resume from there.
+pub async fn tus_offset(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+) -> Result<Response, AppError> {
+    let id = parse_upload_id(&id)?;
+    let upload = load_upload(&state, &id).await?;
+    let offset = received_bytes(&state, &id).await?;
+    Ok((
+        StatusCode::OK,
+        [
+            (UPLOAD_OFFSET, offset.to_string()),
+            (UPLOAD_LENGTH, upload.length.to_string()),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+        ],
+    )
+        .into_response())
+}
+
+/// Appends the body at `Upload-Offset`, which must match the bytes received
+/// so far. Bytes that arrive before the connection drops are kept. The
+/// last chunk starts the transcode.
+pub async fn tus_append(
+    State(state): State<AppState>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, AppError> {
+    let id = parse_upload_id(&id)?;
+    let _guard = state
+        .storage
+        .locks()
+        .acquire(&LockManager::video_key(&id, "tus"))
+        .await?;
+    let upload = load_upload(&state, &id).await?;
+    let mut offset = header_u64(&headers, &UPLOAD_OFFSET).ok_or_else(|| {
+        AppError::validation("Upload-Offset must be a byte count").with_code("tus_offset_invalid")
+    })?;
+    let received = received_bytes(&state, &id).await?;
+    if offset != received {
+        return Err(AppError::conflict(format!(
+            "upload continues at byte {received}, not {offset}"
+        ))
+        .with_code("tus_offset_mismatch")
+        .with_param("offset", received));
+    }
+
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(state.storage.incoming_path(&id))
+        .await?;
+    let transfer = ingest_transfer(&state, id).await?;
+    let mut chunks = body.into_data_stream();
+    let mut failure = None;
+    while let Some(chunk) = chunks.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                failure = Some(
+                    AppError::validation(format!("upload interrupted at byte {offset}: {err}"))
+                        .with_code("tus_upload_interrupted"),
+                );
+                break;
+            }
+        };
+        if offset + chunk.len() as u64 > upload.length {
+            failure = Some(
+                AppError::validation(format!(
+                    "upload exceeds its Upload-Length of {} bytes",
+                    upload.length
+                ))
+                .with_code("tus_length_exceeded")
+                .with_param("length", upload.length),
+            );
+            break;
+        }
+        file.write_all(&chunk).await?;
+        offset += chunk.len() as u64;
+        transfer.consume(chunk.len() as u64).await;
+    }
+    file.flush().await?;
+    drop(file);
+
+    state
+        .jobs
+        .update_progress(id, offset as f32 / upload.length as f32)
+        .await?;
+    if let Some(err) = failure {
+        return Err(err);
+    }
+    if offset == upload.length {
+        finish_upload(&state, id, upload).await?;
+    }
+    Ok((
+        StatusCode::NO_CONTENT,
+        [(UPLOAD_OFFSET, offset.to_string())],
+    )
+        .into_response())
+}
+
+async fn finish_upload(state: &AppState, id: Uuid, upload: TusUpload) -> Result<(), AppError> {
+    match fs::remove_file(state.storage.tus_upload_path(&id)).await {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+    if let Err(err) = record_source_digest(state, id, &state.storage.incoming_path(&id), None).await
+    {
+        return Err(abandon_upload(state, id, err).await);
+    }
+    state.jobs.update_progress(id, 1.0).await?;
+    spawn_local_pipeline(state.clone(), id, upload.encode, upload.callback_url);
+    Ok(())
+}
+
+fn parse_upload_id(id: &str) -> Result<Uuid, AppError> {
+    Uuid::parse_str(id).map_err(|_| AppError::validation("invalid upload identifier"))
+}
+
+/// The upload of `id` while its job is still receiving it.
+async fn load_upload(state: &AppState, id: &Uuid) -> Result<TusUpload, AppError> {
+    let not_found =
+        || AppError::not_found(format!("upload {id}")).with_code("tus_upload_not_found");
+    if !still_uploading(state, id).await? {
+        return Err(not_found());
+    }
+    let raw = match fs::read(state.storage.tus_upload_path(id)).await {
+        Ok(raw) => raw,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Err(not_found()),
+        Err(err) => return Err(err.into()),
+    };
+    serde_json::from_slice(&raw).map_err(|err| std::io::Error::other(err).into())
+}
+
+async fn received_bytes(state: &AppState, id: &Uuid) -> Result<u64, AppError> {
+    match fs::metadata(state.storage.incoming_path(id)).await {
+        Ok(meta) => Ok(meta.len()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn header_u64(headers: &HeaderMap, name: &HeaderName) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// `Upload-Metadata`: comma-separated pairs of a key and its base64 value.
+/// A key may come without a value.
+fn upload_metadata(headers: &HeaderMap) -> Result<HashMap<String, String>, AppError> {
+    let Some(value) = headers.get(UPLOAD_METADATA) else {
+        return Ok(HashMap::new());
+    };
+    let invalid = || {
+        AppError::validation("Upload-Metadata must be comma-separated keys with base64 values")
+            .with_code("tus_metadata_invalid")
+    };
+    value
+        .to_str()
+        .map_err(|_| invalid())?
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, encoded) = pair.split_once(' ').unwrap_or((pair, ""));
+            let value = STANDARD
+                .decode(encoded.trim())
+                .ok()
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .ok_or_else(invalid)?;
+            Ok((key.to_string(), value))
+        })
+        .collect()
+}
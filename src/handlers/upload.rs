@@ -1,136 +1,206 @@
+use std::collections::BTreeMap;
+
 use axum::{
-    Json,
-    extract::{Multipart, State},
+    extract::DefaultBodyLimit,
+    http::{HeaderMap, header},
 };
-use reqwest::Url;
-use serde::Deserialize;
-use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::fs;
 use uuid::Uuid;
 
 use crate::{
-    error::AppError, jobs::JobStage, state::AppState, storage::ensure_parent,
-    transcode::EncodeParams,
+    callbacks, config, error::AppError, jobs::JobStage, metadata::VideoMetadata, state::AppState,
+    tags, transcode::EncodeParams,
 };
 
-use super::pipeline::{spawn_local_pipeline, spawn_remote_pipeline, spawn_ytdlp_pipeline};
+use super::meta::merge_attributes;
+use super::transcode_options::ClientTranscodeOptions;
+
+const DEFAULT_JSON_BODY_LIMIT: usize = 1024 * 1024;
+/// Room over `VIDEO_MAX_UPLOAD_BYTES` for the multipart framing and the
+/// `options` part.
+pub(super) const MULTIPART_OVERHEAD_BYTES: u64 = 64 * 1024;
+/// Largest serialized `client_data`; it is repeated in every job status.
+const MAX_CLIENT_DATA_BYTES: usize = 4 * 1024;
+
+/// Request body limits of the two route classes, read at startup.
+/// `VIDEO_UPLOAD_BODY_LIMIT_BYTES` covers file uploads and is unlimited by
+/// default; `VIDEO_JSON_BODY_LIMIT_BYTES` covers every other route.
+/// `VIDEO_MAX_UPLOAD_BYTES` caps the uploaded file itself, however it is sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodyLimits {
+    pub upload: Option<usize>,
+    pub json: usize,
+    pub max_upload: Option<u64>,
+}
+
+impl BodyLimits {
+    pub fn from_env() -> Self {
+        Self {
+            upload: config::parse_var("VIDEO_UPLOAD_BODY_LIMIT_BYTES").filter(|&bytes| bytes > 0),
+            json: config::parse_var("VIDEO_JSON_BODY_LIMIT_BYTES")
+                .filter(|&bytes| bytes > 0)
+                .unwrap_or(DEFAULT_JSON_BODY_LIMIT),
+            max_upload: config::parse_var("VIDEO_MAX_UPLOAD_BYTES").filter(|&bytes| bytes > 0),
+        }
+    }
+
+    /// Layer for upload routes, which would otherwise inherit the JSON limit.
+    /// With a maximum upload size, bodies may exceed it only by the room
+    /// multipart framing needs.
+    pub fn upload_layer(&self) -> DefaultBodyLimit {
+        let framed = self.max_upload.map(|bytes| {
+            usize::try_from(bytes.saturating_add(MULTIPART_OVERHEAD_BYTES)).unwrap_or(usize::MAX)
+        });
+        match self.upload.into_iter().chain(framed).min() {
+            Some(bytes) => DefaultBodyLimit::max(bytes),
+            None => DefaultBodyLimit::disable(),
+        }
+    }
 
-#[derive(Debug, serde::Serialize)]
+    pub fn json_layer(&self) -> DefaultBodyLimit {
+        DefaultBodyLimit::max(self.json)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UploadResponse {
     pub id: String,
     pub status_url: String,
     pub download_url: String,
     pub hls_master_url: String,
     pub dash_manifest_url: String,
+    /// The upload matched a video the caller already published, whose id is
+    /// returned instead of transcoding the file again.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deduplicated: bool,
 }
 
-#[derive(Debug, Deserialize, Clone, Copy, Default)]
-pub struct ClientTranscodeOptions {
-    pub crf: Option<u8>,
-    #[serde(default, rename = "cpu_used")]
-    pub cpu_used: Option<u8>,
+/// JSON `options` part of a multipart upload. It must come before the file
+/// part, since the file is streamed to disk as it arrives.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UploadOptions {
+    #[serde(default)]
+    pub transcode: Option<ClientTranscodeOptions>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub attributes: BTreeMap<String, Value>,
+    /// Receives the job's final status.
+    #[serde(default)]
+    pub callback_url: Option<String>,
+    /// Any JSON, echoed in the job's status and callbacks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_data: Option<Value>,
 }
 
-impl From<ClientTranscodeOptions> for EncodeParams {
-    fn from(options: ClientTranscodeOptions) -> Self {
-        let mut params = EncodeParams::default();
-        if let Some(crf) = options.crf {
-            params.crf = crf;
-        }
-        if let Some(cpu) = options.cpu_used {
-            params.cpu_used = cpu;
-        }
-        params.sanitized()
-    }
+/// What an [`UploadOptions`] document asks of a new upload.
+pub(super) struct PreparedUpload {
+    pub meta: VideoMetadata,
+    pub encode: Option<EncodeParams>,
+    pub callback_url: Option<String>,
+    pub client_data: Option<Value>,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct RemoteUploadRequest {
-    pub url: String,
-    #[serde(default)]
-    pub transcode: Option<ClientTranscodeOptions>,
+impl UploadOptions {
+    /// Validates the options of an upload charged to `account`. Transcode
+    /// fields left unset fall back to `requested`.
+    pub(super) fn prepare(
+        self,
+        requested: ClientTranscodeOptions,
+        account: &str,
+    ) -> Result<PreparedUpload, AppError> {
+        let mut meta = VideoMetadata {
+            tags: tags::initial_tags(&self.tags)?,
+            account: Some(account.to_string()),
+            ..VideoMetadata::default()
+        };
+        merge_attributes(&mut meta, self.attributes)?;
+        let callback_url = self
+            .callback_url
+            .as_deref()
+            .map(callbacks::validate_url)
+            .transpose()?;
+        let transcode = self.transcode.unwrap_or_default().or(requested);
+        Ok(PreparedUpload {
+            meta,
+            encode: (!transcode.is_empty()).then(|| EncodeParams::from(transcode)),
+            callback_url,
+            client_data: validate_client_data(self.client_data)?,
+        })
+    }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct YtDlpDownloadRequest {
-    pub url: String,
-    #[serde(default)]
-    pub transcode: Option<ClientTranscodeOptions>,
+/// Checks the `client_data` of an ingest request; `null` counts as unset.
+pub(super) fn validate_client_data(data: Option<Value>) -> Result<Option<Value>, AppError> {
+    let Some(data) = data.filter(|data| !data.is_null()) else {
+        return Ok(None);
+    };
+    let size = serde_json::to_vec(&data)
+        .map_err(std::io::Error::from)?
+        .len();
+    if size > MAX_CLIENT_DATA_BYTES {
+        return Err(AppError::validation(format!(
+            "client_data must serialize to at most {MAX_CLIENT_DATA_BYTES} bytes"
+        ))
+        .with_code("client_data_too_large")
+        .with_param("max_bytes", MAX_CLIENT_DATA_BYTES));
+    }
+    Ok(Some(data))
 }
 
-pub async fn upload_multipart(
-    State(state): State<AppState>,
-    mut multipart: Multipart,
-) -> Result<Json<UploadResponse>, AppError> {
-    while let Some(mut field) = multipart.next_field().await? {
-        if field.file_name().is_none() {
-            continue;
-        }
+/// The error for an upload past `VIDEO_MAX_UPLOAD_BYTES`.
+pub(super) fn upload_too_large(max: u64) -> AppError {
+    AppError::too_large(format!("uploads are limited to {max} bytes"))
+        .with_code("upload_too_large")
+        .with_param("max_bytes", max)
+}
 
-        let id = Uuid::new_v4();
-        state.jobs.create_job(id).await?;
-        state
-            .jobs
-            .set_plan(id, vec![JobStage::Uploading, JobStage::Transcoding])
-            .await?;
-        state.jobs.update_stage(id, JobStage::Uploading).await?;
-        let temp_path = state.storage.incoming_path(&id);
-        ensure_parent(&temp_path).await?;
-
-        let mut file = File::create(&temp_path).await?;
-        while let Some(chunk) = field.chunk().await? {
-            file.write_all(&chunk).await?;
+/// Refuses a body whose `Content-Length` shows the upload will not fit,
+/// before any of it is read. `overhead` is what the body may carry besides
+/// the file.
+pub(super) fn check_content_length(
+    headers: &HeaderMap,
+    max: Option<u64>,
+    overhead: u64,
+) -> Result<(), AppError> {
+    let declared = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    match (max, declared) {
+        (Some(max), Some(declared)) if declared > max.saturating_add(overhead) => {
+            Err(upload_too_large(max))
         }
-        file.flush().await?;
-
-        state.jobs.update_progress(id, 1.0).await?;
-        spawn_local_pipeline(state.clone(), id, temp_path);
-        return Ok(Json(build_upload_response(id)));
+        _ => Ok(()),
     }
-
-    Err(AppError::validation("multipart payload missing file field"))
 }
 
-pub async fn upload_remote(
-    State(state): State<AppState>,
-    Json(payload): Json<RemoteUploadRequest>,
-) -> Result<Json<UploadResponse>, AppError> {
-    let encode = payload.transcode.map(EncodeParams::from);
-    let id = Uuid::new_v4();
-    state.jobs.create_job(id).await?;
-    state
-        .jobs
-        .set_plan(id, vec![JobStage::Downloading, JobStage::Transcoding])
-        .await?;
-
-    let raw_url = payload.url.clone();
-    if !raw_url.starts_with("magnet:") {
-        Url::parse(&raw_url).map_err(|err| AppError::validation(format!("invalid url: {err}")))?;
+/// Fails the job of an upload that stopped with `err` and drops what
+/// arrived of it. Returns `err` for the response.
+pub(super) async fn abandon_upload(state: &AppState, id: Uuid, err: AppError) -> AppError {
+    tracing::warn!(%id, error = %err, "upload abandoned");
+    if let Err(store_err) = state.jobs.fail(id, &err).await {
+        tracing::error!(%id, error = %store_err, "failed to mark job as failed");
     }
-
-    spawn_remote_pipeline(state.clone(), id, raw_url, encode);
-
-    Ok(Json(build_upload_response(id)))
+    let incoming = state.storage.incoming_path(&id);
+    match fs::remove_file(&incoming).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            tracing::warn!(path = %incoming.display(), ?e, "cleanup failed");
+        }
+        _ => {}
+    }
+    err
 }
 
-pub async fn download_via_ytdlp(
-    State(state): State<AppState>,
-    Json(payload): Json<YtDlpDownloadRequest>,
-) -> Result<Json<UploadResponse>, AppError> {
-    let url = Url::parse(&payload.url)
-        .map_err(|err| AppError::validation(format!("invalid url: {err}")))?;
-    let encode = payload.transcode.map(EncodeParams::from);
-    let id = Uuid::new_v4();
-    state.jobs.create_job(id).await?;
-    state
+/// Whether the job of `id` is still receiving its upload.
+pub(super) async fn still_uploading(state: &AppState, id: &Uuid) -> Result<bool, AppError> {
+    Ok(state
         .jobs
-        .set_plan(id, vec![JobStage::Downloading, JobStage::Transcoding])
-        .await?;
-
-    let url_string: String = url.into();
-    spawn_ytdlp_pipeline(state.clone(), id, url_string, encode);
-
-    Ok(Json(build_upload_response(id)))
+        .status(id)
+        .await?
+        .is_some_and(|status| status.stage == JobStage::Uploading))
 }
 
 pub(super) fn build_upload_response(id: Uuid) -> UploadResponse {
@@ -141,5 +211,6 @@ pub(super) fn build_upload_response(id: Uuid) -> UploadResponse {
         download_url: format!("/videos/{id_str}/download"),
         hls_master_url: format!("/videos/{id_str}/hls/master.m3u8"),
         dash_manifest_url: format!("/videos/{id_str}/dash/manifest.mpd"),
+        deduplicated: false,
     }
 }
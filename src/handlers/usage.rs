@@ -0,0 +1,23 @@
+use axum::{Extension, Json, extract::State, http::HeaderMap};
+
+use crate::{
+    auth::Claims,
+    error::AppError,
+    state::AppState,
+    usage::{self, UsageReport},
+};
+
+/// The caller's usage today and this month, its stored bytes and the quotas
+/// that apply to it.
+pub async fn get_usage(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    claims: Option<Extension<Claims>>,
+) -> Result<Json<UsageReport>, AppError> {
+    let key = usage::account_key(
+        &headers,
+        claims.as_deref(),
+        state.rate_limits.trusts_forwarded_headers(),
+    );
+    Ok(Json(state.usage.report(&key).await?))
+}
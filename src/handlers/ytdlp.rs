@@ -0,0 +1,468 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use tokio::fs;
+use uuid::Uuid;
+
+use super::approval::await_approval;
+use super::encode::transcode_and_publish;
+use super::pipeline::{record_source_digest, run_hooks, spawn_pipeline};
+use crate::{
+    blocking, captions, cleanup, cookies,
+    error::AppError,
+    hooks::HookPoint,
+    http_client,
+    jobs::{JobOrigin, JobSource, JobStage, YtDlpOptions},
+    metadata::{self, ImportedDetails},
+    process::{DynProcessRunner, map_spawn_error},
+    skip_segments::{self, SkipSegment},
+    state::AppState,
+    storage::{Storage, ensure_parent},
+    transcode::EncodeParams,
+};
+
+const YTDLP_BIN: &str = "yt-dlp";
+/// yt-dlp fields kept in `meta.json` as [`ImportedDetails`].
+const IMPORTED_FIELDS: [&str; 5] = [
+    "title",
+    "description",
+    "uploader",
+    "webpage_url",
+    "upload_date",
+];
+
+pub(super) fn spawn_ytdlp_pipeline(
+    state: AppState,
+    id: Uuid,
+    url: String,
+    options: YtDlpOptions,
+    encode: Option<EncodeParams>,
+    callback_url: Option<String>,
+) {
+    let job = state.running.register(id);
+    let source = JobSource {
+        origin: JobOrigin::YtDlp { url, options },
+        encode,
+        callback_url,
+        credentials_redacted: false,
+    };
+    spawn_pipeline(state, job, id, source);
+}
+
+pub(super) async fn run_ytdlp_pipeline(
+    state: AppState,
+    id: Uuid,
+    url: String,
+    options: &YtDlpOptions,
+    encode: Option<EncodeParams>,
+) -> Result<(), AppError> {
+    cleanup::ensure_capacity(&state.storage, &state.jobs, &state.cleanup.get()).await?;
+    state.jobs.update_stage(id, JobStage::Downloading).await?;
+
+    let temp_path = state.storage.incoming_path(&id);
+    ensure_parent(&temp_path).await?;
+    tracing::debug!(%id, %url, path = %temp_path.display(), "yt-dlp download starting");
+
+    let login = YtDlpLogin::prepare(&state.storage, options, &temp_path).await?;
+    let downloaded =
+        download_with_ytdlp_cli(&state.process_runner, &url, &temp_path, options, &login).await;
+    drop(login);
+    state.breaker.record(&url, downloaded.as_ref().map(|_| ()));
+    state.alerts.record_ytdlp(downloaded.as_ref().map(|_| ()));
+    let downloaded = downloaded?;
+
+    if downloaded.path != temp_path {
+        fs::rename(&downloaded.path, &temp_path).await?;
+    }
+    tracing::debug!(%id, %url, path = %temp_path.display(), "yt-dlp download finished");
+    import_ytdlp_details(&state, id, downloaded.details, downloaded.thumbnail).await?;
+    captions::attach(&state.storage, &id, downloaded.subtitles).await?;
+    if !downloaded.skip_segments.is_empty() {
+        let mut meta = metadata::load(&state.storage, &id).await?;
+        meta.skip_segments = downloaded.skip_segments;
+        metadata::save(&state.storage, &id, &meta).await?;
+    }
+    let digest = record_source_digest(&state, id, &temp_path, None).await?;
+
+    run_hooks(
+        &state,
+        id,
+        HookPoint::PostDownload,
+        Some(&url),
+        Some(&temp_path),
+        Some(&digest),
+        encode.as_ref(),
+        None,
+    )
+    .await?;
+    if await_approval(&state, id, &temp_path, encode.as_ref()).await? {
+        return Ok(());
+    }
+    tracing::debug!(%id, %url, path = %temp_path.display(), "starting transcode for yt-dlp job");
+    transcode_and_publish(&state, id, Some(&url), &temp_path, &digest, encode).await?;
+    tracing::debug!(%id, %url, "yt-dlp pipeline finished");
+
+    Ok(())
+}
+
+/// Stores what yt-dlp reported about the source page in `meta.json`, and
+/// the platform thumbnail as the video's `thumbnail.jpg`.
+async fn import_ytdlp_details(
+    state: &AppState,
+    id: Uuid,
+    details: Option<ImportedDetails>,
+    thumbnail: Option<PathBuf>,
+) -> Result<(), AppError> {
+    let details = details.filter(|details| *details != ImportedDetails::default());
+    if details.is_none() && thumbnail.is_none() {
+        return Ok(());
+    }
+    let mut details = details.unwrap_or_default();
+    if let Some(thumbnail) = thumbnail {
+        let poster = state.storage.thumbnail_path(&id);
+        ensure_parent(&poster).await?;
+        blocking::copy(&thumbnail, &poster).await?;
+        fs::remove_file(&thumbnail).await.ok();
+        details.poster = true;
+    }
+    let mut meta = metadata::load(&state.storage, &id).await?;
+    meta.imported = Some(details);
+    metadata::save(&state.storage, &id, &meta).await
+}
+
+/// Hands a job's yt-dlp login and proxy over in files only the server can
+/// read, next to `base` in the incoming area, so they stay out of the
+/// process list. The files are removed when this is dropped, including when
+/// the job is cancelled mid-download.
+struct YtDlpLogin {
+    args: Vec<OsString>,
+    files: Vec<PathBuf>,
+}
+
+impl YtDlpLogin {
+    async fn prepare(
+        storage: &Storage,
+        options: &YtDlpOptions,
+        base: &Path,
+    ) -> Result<Self, AppError> {
+        let auth = &options.auth;
+        let mut login = Self {
+            args: Vec::new(),
+            files: Vec::new(),
+        };
+        if let Some(name) = &auth.cookies {
+            let jar = cookies::ensure_exists(storage, name).await?;
+            // yt-dlp saves the jar back when it exits, so each run gets a
+            // copy and concurrent jobs cannot clobber the stored file.
+            let copy = base.with_extension("cookies.txt");
+            login.files.push(copy.clone());
+            fs::copy(&jar, &copy).await?;
+            login
+                .args
+                .extend(["--cookies".into(), copy.into_os_string()]);
+        }
+        let mut config = String::new();
+        if let Some(cookie) = &auth.cookie {
+            config.push_str(&format!(
+                "--add-headers {}\n",
+                config_quote(&format!("Cookie:{cookie}"))
+            ));
+        }
+        if let (Some(username), Some(password)) = (&auth.username, &auth.password) {
+            config.push_str(&format!(
+                "--username {}\n--password {}\n",
+                config_quote(username),
+                config_quote(password)
+            ));
+        }
+        // yt-dlp speaks SOCKS too, so any configured proxy applies.
+        let proxy = match &options.proxy {
+            Some(proxy) => Some(http_client::parse_proxy(proxy)?),
+            None => http_client::configured_proxy(),
+        };
+        if let Some(proxy) = proxy {
+            config.push_str(&format!("--proxy {}\n", config_quote(proxy.as_str())));
+        }
+        if !config.is_empty() {
+            let path = base.with_extension("ytdlp.conf");
+            login.files.push(path.clone());
+            cookies::write_private(&path, config.as_bytes()).await?;
+            login
+                .args
+                .extend(["--config-locations".into(), path.into_os_string()]);
+        }
+        Ok(login)
+    }
+}
+
+impl Drop for YtDlpLogin {
+    fn drop(&mut self) {
+        for file in &self.files {
+            std::fs::remove_file(file).ok();
+        }
+    }
+}
+
+/// Quotes `value` for a yt-dlp config file, which is split like a POSIX
+/// shell line.
+fn config_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\"'\"'"))
+}
+
+/// What a yt-dlp run left in the incoming area.
+struct YtDlpDownload {
+    path: PathBuf,
+    details: Option<ImportedDetails>,
+    /// The platform thumbnail, converted to JPEG.
+    thumbnail: Option<PathBuf>,
+    /// `(language, file)` of each subtitle track, converted to WebVTT.
+    subtitles: Vec<(String, PathBuf)>,
+    /// SponsorBlock segments, when they were asked for.
+    skip_segments: Vec<SkipSegment>,
+}
+
+async fn download_with_ytdlp_cli(
+    runner: &DynProcessRunner,
+    url: &str,
+    destination: &Path,
+    options: &YtDlpOptions,
+    login: &YtDlpLogin,
+) -> Result<YtDlpDownload, AppError> {
+    let parent = destination
+        .parent()
+        .ok_or_else(|| AppError::transcode("temporary destination missing parent directory"))?;
+
+    let template_path = destination.with_extension("%(ext)s");
+    let thumbnail_template = destination.with_extension("thumbnail.%(ext)s");
+    let thumbnail_path = destination.with_extension("thumbnail.jpg");
+    let subtitle_template = destination.with_extension("subs.%(ext)s");
+
+    let mut args: Vec<OsString> = vec![
+        "--ignore-config".into(),
+        "--no-warnings".into(),
+        "--quiet".into(),
+        "--no-progress".into(),
+        "--no-playlist".into(),
+        "--no-part".into(),
+        "--no-write-comments".into(),
+        "--no-write-description".into(),
+        "--no-write-info-json".into(),
+        "--write-thumbnail".into(),
+        "--convert-thumbnails".into(),
+        "jpg".into(),
+        "--output".into(),
+        template_path.into_os_string(),
+        "--output".into(),
+        prefixed("thumbnail:", &thumbnail_template),
+        "--print".into(),
+        format!("before_dl:%(.{{{}}})j", IMPORTED_FIELDS.join(",")).into(),
+        "--print".into(),
+        "after_move:filepath".into(),
+    ];
+    args.extend(options.format_args().into_iter().map(OsString::from));
+    let subtitles = &options.subtitles;
+    if subtitles.is_empty() {
+        args.push("--no-write-subs".into());
+    } else {
+        args.extend([
+            "--write-subs".into(),
+            "--sub-langs".into(),
+            subtitles.languages.join(",").into(),
+            "--convert-subs".into(),
+            "vtt".into(),
+            "--output".into(),
+            prefixed("subtitle:", &subtitle_template),
+        ]);
+        if subtitles.auto_captions {
+            args.push("--write-auto-subs".into());
+        }
+    }
+    if options.skip_segments {
+        args.extend([
+            "--sponsorblock-mark".into(),
+            "all".into(),
+            "--print".into(),
+            "before_dl:%(sponsorblock_chapters)j".into(),
+        ]);
+    }
+    args.extend(login.args.iter().cloned());
+    args.push(url.into());
+    let output = runner
+        .output(YTDLP_BIN, &args)
+        .await
+        .map_err(|err| map_spawn_error(err, YTDLP_BIN))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(AppError::dependency(format!(
+            "yt-dlp exited with status {}: {}",
+            output.status,
+            stderr.trim()
+        )));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let reported_path = stdout
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .ok_or_else(|| AppError::dependency("yt-dlp did not report an output file"))?
+        .trim()
+        .to_string();
+
+    let mut resolved = PathBuf::from(&reported_path);
+    if resolved.is_relative() {
+        resolved = parent.join(resolved);
+    }
+
+    if !resolved.exists() {
+        return Err(AppError::dependency(format!(
+            "yt-dlp reported output {}, but file is missing",
+            resolved.display()
+        )));
+    }
+
+    // The details line is printed before the download, so it comes first.
+    let details = stdout
+        .lines()
+        .map(str::trim)
+        .find(|line| line.starts_with('{'))
+        .and_then(|line| match serde_json::from_str::<ImportedDetails>(line) {
+            Ok(details) => Some(details),
+            Err(err) => {
+                tracing::warn!(url, error = %err, "ignoring unparsable yt-dlp details");
+                None
+            }
+        });
+    // Printed as a JSON array, or `NA` when the site has no SponsorBlock data.
+    let skip_segments = stdout
+        .lines()
+        .map(str::trim)
+        .find(|line| line.starts_with('['))
+        .and_then(|line| serde_json::from_str(line).ok())
+        .map(|chapters| skip_segments::from_sponsorblock(&chapters))
+        .unwrap_or_default();
+    Ok(YtDlpDownload {
+        path: resolved,
+        details,
+        thumbnail: thumbnail_path.exists().then_some(thumbnail_path),
+        subtitles: collect_subtitles(&subtitle_template).await?,
+        skip_segments,
+    })
+}
+
+/// A playlist or channel as listed by yt-dlp's flat extraction.
+pub(super) struct ExpandedPlaylist {
+    pub title: Option<String>,
+    /// `(url, title)` of each entry, in playlist order.
+    pub entries: Vec<(String, Option<String>)>,
+}
+
+/// Lists the first `max_entries` entries of a playlist or channel without
+/// extracting them. The URL of a single video lists just that video.
+pub(super) async fn expand_playlist(
+    state: &AppState,
+    url: &str,
+    max_entries: usize,
+    options: &YtDlpOptions,
+) -> Result<ExpandedPlaylist, AppError> {
+    state.breaker.admit(url)?;
+    let base = state.storage.incoming_path(&Uuid::new_v4());
+    ensure_parent(&base).await?;
+    let login = YtDlpLogin::prepare(&state.storage, options, &base).await?;
+    let mut args: Vec<OsString> = vec![
+        "--ignore-config".into(),
+        "--no-warnings".into(),
+        "--flat-playlist".into(),
+        "--dump-single-json".into(),
+        "--playlist-end".into(),
+        max_entries.to_string().into(),
+    ];
+    args.extend(login.args.iter().cloned());
+    args.push(url.into());
+    let listed = list_playlist(&state.process_runner, &args).await;
+    drop(login);
+    state.breaker.record(url, listed.as_ref().map(|_| ()));
+    state.alerts.record_ytdlp(listed.as_ref().map(|_| ()));
+    let listed = listed?;
+
+    let text = |value: &serde_json::Value| value.as_str().map(str::to_string);
+    let title = text(&listed["title"]);
+    if listed["_type"] != "playlist" {
+        let url = text(&listed["webpage_url"]).unwrap_or_else(|| url.to_string());
+        return Ok(ExpandedPlaylist {
+            title: None,
+            entries: vec![(url, title)],
+        });
+    }
+    let entries = listed["entries"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let url = text(&entry["url"]).or_else(|| text(&entry["webpage_url"]))?;
+            Some((url, text(&entry["title"])))
+        })
+        .take(max_entries)
+        .collect();
+    Ok(ExpandedPlaylist { title, entries })
+}
+
+async fn list_playlist(
+    runner: &DynProcessRunner,
+    args: &[OsString],
+) -> Result<serde_json::Value, AppError> {
+    let output = runner
+        .output(YTDLP_BIN, args)
+        .await
+        .map_err(|err| map_spawn_error(err, YTDLP_BIN))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(AppError::dependency(format!(
+            "yt-dlp exited with status {}: {}",
+            output.status,
+            stderr.trim()
+        )));
+    }
+    serde_json::from_slice(&output.stdout)
+        .map_err(|err| AppError::dependency(format!("yt-dlp listed unparsable JSON: {err}")))
+}
+
+/// Finds the `<name>.subs.<language>.vtt` files yt-dlp wrote for
+/// `template`, removing any it could not convert to WebVTT.
+async fn collect_subtitles(template: &Path) -> Result<Vec<(String, PathBuf)>, AppError> {
+    let (Some(parent), Some(prefix)) = (
+        template.parent(),
+        template
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix("%(ext)s")),
+    ) else {
+        return Ok(Vec::new());
+    };
+    let mut found = Vec::new();
+    let mut entries = fs::read_dir(parent).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let Some(rest) = name.to_str().and_then(|name| name.strip_prefix(prefix)) else {
+            continue;
+        };
+        match rest.strip_suffix(".vtt") {
+            Some(language) if captions::is_track_language(language) => {
+                found.push((language.to_string(), entry.path()));
+            }
+            _ => {
+                fs::remove_file(entry.path()).await.ok();
+            }
+        }
+    }
+    Ok(found)
+}
+
+fn prefixed(prefix: &str, path: &Path) -> OsString {
+    let mut value = OsString::from(prefix);
+    value.push(path);
+    value
+}
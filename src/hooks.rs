@@ -0,0 +1,112 @@
+use std::{path::PathBuf, sync::Arc};
+
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::{
+    digest::SourceDigest, error::AppError, jobs::EncodeSummary, storage::Storage,
+    transcode::EncodeParams,
+};
+
+/// Where in the pipeline a hook is invoked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookPoint {
+    /// Before the job is registered. An error rejects the ingest request.
+    PreIngest,
+    /// After the source is on local disk, before transcoding starts.
+    PostDownload,
+    /// After the download and the HLS/DASH ladder have been written.
+    PostEncode,
+    /// Immediately before the job is reported complete.
+    PrePublish,
+}
+
+impl HookPoint {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HookPoint::PreIngest => "pre_ingest",
+            HookPoint::PostDownload => "post_download",
+            HookPoint::PostEncode => "post_encode",
+            HookPoint::PrePublish => "pre_publish",
+        }
+    }
+}
+
+/// What a hook can see about the video being processed.
+#[derive(Clone)]
+pub struct HookContext {
+    pub video_id: Uuid,
+    pub point: HookPoint,
+    /// URL or upload file name supplied by the client, when known.
+    pub source: Option<String>,
+    /// The local input before encoding, the encoded download afterwards.
+    /// `None` during `PreIngest`.
+    pub media_path: Option<PathBuf>,
+    pub encode: Option<EncodeParams>,
+    /// Size, checksum and container of the source; set from `PostDownload`
+    /// on.
+    pub digest: Option<SourceDigest>,
+    /// What the encode produced; set from `PostEncode` on.
+    pub summary: Option<EncodeSummary>,
+    pub storage: Storage,
+}
+
+/// Custom pipeline steps for embedders, e.g. pushing finished videos to a CMS.
+/// Every method defaults to a no-op; an error fails the job (or, for
+/// `pre_ingest`, the request) with that error.
+#[async_trait]
+pub trait PipelineHook: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn pre_ingest(&self, _ctx: &HookContext) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    async fn post_download(&self, _ctx: &HookContext) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    async fn post_encode(&self, _ctx: &HookContext) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    async fn pre_publish(&self, _ctx: &HookContext) -> Result<(), AppError> {
+        Ok(())
+    }
+}
+
+pub type DynPipelineHook = Arc<dyn PipelineHook>;
+
+/// Hooks registered on `AppState`, invoked in registration order.
+#[derive(Clone, Default)]
+pub struct PipelineHooks {
+    hooks: Vec<DynPipelineHook>,
+}
+
+impl PipelineHooks {
+    pub fn push(&mut self, hook: DynPipelineHook) {
+        self.hooks.push(hook);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// Runs every hook for `ctx.point`, stopping at the first error.
+    pub async fn run(&self, ctx: &HookContext) -> Result<(), AppError> {
+        for hook in &self.hooks {
+            tracing::debug!(video_id = %ctx.video_id, hook = hook.name(), point = ctx.point.as_str(), "running pipeline hook");
+            let result = match ctx.point {
+                HookPoint::PreIngest => hook.pre_ingest(ctx).await,
+                HookPoint::PostDownload => hook.post_download(ctx).await,
+                HookPoint::PostEncode => hook.post_encode(ctx).await,
+                HookPoint::PrePublish => hook.pre_publish(ctx).await,
+            };
+            if let Err(err) = result {
+                tracing::warn!(video_id = %ctx.video_id, hook = hook.name(), point = ctx.point.as_str(), error = %err, "pipeline hook failed");
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,254 @@
+use async_trait::async_trait;
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use super::{
+    EncodeSummary, JobGroupStatus, JobSource, JobStage, JobStatusResponse, JobStore, StageTiming,
+    record::{GroupMember, JobRecord, group_of, millis_since_epoch},
+};
+use crate::error::AppError;
+
+#[derive(Clone)]
+pub struct LocalJobStore {
+    inner: Arc<Mutex<HashMap<Uuid, JobRecord>>>,
+    /// When each instance's heartbeat expires.
+    instances: Arc<Mutex<HashMap<String, Instant>>>,
+    cancels: Arc<Mutex<HashSet<Uuid>>>,
+}
+
+impl LocalJobStore {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(HashMap::new())),
+            instances: Arc::new(Mutex::new(HashMap::new())),
+            cancels: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+}
+
+impl Default for LocalJobStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl JobStore for LocalJobStore {
+    async fn create_job(&self, id: Uuid) -> Result<(), AppError> {
+        let mut guard = self.inner.lock().await;
+        guard.insert(id, JobRecord::new());
+        Ok(())
+    }
+
+    async fn set_plan(&self, id: Uuid, plan: Vec<JobStage>) -> Result<(), AppError> {
+        let mut guard = self.inner.lock().await;
+        if let Some(record) = guard.get_mut(&id) {
+            record.set_plan(plan);
+        }
+        Ok(())
+    }
+
+    async fn update_stage(&self, id: Uuid, stage: JobStage) -> Result<(), AppError> {
+        if let Some(record) = self.inner.lock().await.get_mut(&id) {
+            record.set_stage(stage);
+        }
+        Ok(())
+    }
+
+    async fn update_progress(&self, id: Uuid, progress: f32) -> Result<(), AppError> {
+        if let Some(record) = self.inner.lock().await.get_mut(&id) {
+            record.set_stage_progress(progress);
+        }
+        Ok(())
+    }
+
+    async fn update_stage_eta(&self, id: Uuid, eta_seconds: Option<f64>) -> Result<(), AppError> {
+        if let Some(record) = self.inner.lock().await.get_mut(&id) {
+            record.stage_eta_seconds = eta_seconds;
+            record.touch();
+        }
+        Ok(())
+    }
+
+    async fn fail(&self, id: Uuid, error: &AppError) -> Result<(), AppError> {
+        if let Some(record) = self.inner.lock().await.get_mut(&id) {
+            record.fail(error.to_string(), error.class());
+            record.stage_eta_seconds = None;
+        }
+        Ok(())
+    }
+
+    async fn record_retry(&self, id: Uuid, attempt: u32, error: &AppError) -> Result<(), AppError> {
+        if let Some(record) = self.inner.lock().await.get_mut(&id) {
+            record.record_retry(attempt, error.to_string());
+        }
+        Ok(())
+    }
+
+    async fn complete(&self, id: Uuid) -> Result<(), AppError> {
+        if let Some(record) = self.inner.lock().await.get_mut(&id) {
+            record.complete();
+            record.stage_eta_seconds = Some(0.0);
+        }
+        Ok(())
+    }
+
+    async fn set_summary(&self, id: Uuid, summary: EncodeSummary) -> Result<(), AppError> {
+        if let Some(record) = self.inner.lock().await.get_mut(&id) {
+            record.summary = Some(summary);
+            record.touch();
+        }
+        Ok(())
+    }
+
+    async fn status(&self, id: &Uuid) -> Result<Option<JobStatusResponse>, AppError> {
+        let guard = self.inner.lock().await;
+        Ok(guard.get(id).map(|record| record.to_response(*id)))
+    }
+
+    async fn list(&self) -> Result<Vec<JobStatusResponse>, AppError> {
+        let guard = self.inner.lock().await;
+        Ok(guard
+            .iter()
+            .map(|(id, record)| record.to_response(*id))
+            .collect())
+    }
+
+    async fn stage_timings(&self, since: SystemTime) -> Result<Vec<StageTiming>, AppError> {
+        let cutoff = millis_since_epoch(since);
+        let guard = self.inner.lock().await;
+        Ok(guard
+            .values()
+            .flat_map(|record| record.stage_history.iter())
+            .filter(|timing| timing.finished_at_unix_ms >= cutoff)
+            .cloned()
+            .collect())
+    }
+
+    async fn add_child(&self, parent: Uuid, child: Uuid, name: &str) -> Result<(), AppError> {
+        let mut guard = self.inner.lock().await;
+        let record = guard
+            .get_mut(&parent)
+            .ok_or_else(|| AppError::not_found(format!("job {parent} not found")))?;
+        record.children.push(GroupMember {
+            id: child,
+            name: name.to_string(),
+        });
+        record.touch();
+
+        let mut child_record = JobRecord::new();
+        child_record.parent = Some(parent);
+        guard.insert(child, child_record);
+        Ok(())
+    }
+
+    async fn set_source(&self, id: Uuid, source: JobSource) -> Result<(), AppError> {
+        if let Some(record) = self.inner.lock().await.get_mut(&id) {
+            record.source = Some(source);
+        }
+        Ok(())
+    }
+
+    async fn source(&self, id: &Uuid) -> Result<Option<JobSource>, AppError> {
+        Ok(self
+            .inner
+            .lock()
+            .await
+            .get(id)
+            .and_then(|record| record.source.clone()))
+    }
+
+    async fn set_source_file(&self, id: Uuid, name: &str) -> Result<(), AppError> {
+        if let Some(record) = self.inner.lock().await.get_mut(&id) {
+            record.source_file = Some(name.to_string());
+        }
+        Ok(())
+    }
+
+    async fn set_client_data(&self, id: Uuid, data: Value) -> Result<(), AppError> {
+        if let Some(record) = self.inner.lock().await.get_mut(&id) {
+            record.client_data = Some(data);
+        }
+        Ok(())
+    }
+
+    async fn reset(&self, id: Uuid) -> Result<(), AppError> {
+        if let Some(record) = self.inner.lock().await.get_mut(&id) {
+            record.reset();
+        }
+        Ok(())
+    }
+
+    async fn remove(&self, id: Uuid) -> Result<(), AppError> {
+        let mut guard = self.inner.lock().await;
+        if let Some(record) = guard.remove(&id) {
+            for child in record.children {
+                guard.remove(&child.id);
+            }
+        }
+        self.cancels.lock().await.remove(&id);
+        Ok(())
+    }
+
+    async fn group_status(&self, id: &Uuid) -> Result<Option<JobGroupStatus>, AppError> {
+        let guard = self.inner.lock().await;
+        Ok(guard
+            .get(id)
+            .map(|root| group_of(*id, root, |child| guard.get(child))))
+    }
+
+    async fn heartbeat(&self, instance: &str, ttl: Duration) -> Result<(), AppError> {
+        self.instances
+            .lock()
+            .await
+            .insert(instance.to_string(), Instant::now() + ttl);
+        Ok(())
+    }
+
+    async fn live_instances(&self) -> Result<Vec<String>, AppError> {
+        let mut instances = self.instances.lock().await;
+        let now = Instant::now();
+        instances.retain(|_, expires| *expires > now);
+        Ok(instances.keys().cloned().collect())
+    }
+
+    async fn claim(&self, id: Uuid, instance: &str, live: &[String]) -> Result<bool, AppError> {
+        let mut guard = self.inner.lock().await;
+        let Some(record) = guard.get_mut(&id) else {
+            return Ok(false);
+        };
+        if let Some(holder) = &record.instance
+            && holder != instance
+            && live.contains(holder)
+        {
+            return Ok(false);
+        }
+        record.instance = Some(instance.to_string());
+        Ok(true)
+    }
+
+    async fn release(&self, id: Uuid, instance: &str) -> Result<(), AppError> {
+        if let Some(record) = self.inner.lock().await.get_mut(&id)
+            && record.instance.as_deref() == Some(instance)
+        {
+            record.instance = None;
+            self.cancels.lock().await.remove(&id);
+        }
+        Ok(())
+    }
+
+    async fn request_cancel(&self, id: Uuid) -> Result<(), AppError> {
+        self.cancels.lock().await.insert(id);
+        Ok(())
+    }
+
+    async fn cancel_requests(&self) -> Result<Vec<Uuid>, AppError> {
+        Ok(self.cancels.lock().await.iter().copied().collect())
+    }
+}
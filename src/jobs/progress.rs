@@ -0,0 +1,138 @@
+use uuid::Uuid;
+
+use super::{
+    JobStage, JobStatusResponse,
+    record::{JobRecord, millis_since_epoch},
+};
+use crate::clock;
+
+impl JobRecord {
+    pub(super) fn to_response(&self, id: Uuid) -> JobStatusResponse {
+        let elapsed = self
+            .last_update_instant
+            .duration_since(self.started_at_instant);
+        let elapsed_seconds = elapsed.as_secs_f64();
+
+        let (overall_progress, stage_progress, stage_index, total_stages) =
+            self.compute_progress_metrics();
+
+        let estimated_remaining_seconds = self.estimate_remaining_seconds(stage_progress);
+        let started_at = millis_since_epoch(self.started_at_system);
+        let last_update = millis_since_epoch(self.last_update_system);
+
+        JobStatusResponse {
+            id,
+            stage: self.stage,
+            progress: overall_progress,
+            stage_progress,
+            current_stage_index: stage_index,
+            total_stages,
+            elapsed_seconds,
+            estimated_remaining_seconds,
+            error: self.error.clone(),
+            error_class: self.error_class,
+            is_retryable: self.error_class.map(|class| class.is_retryable()),
+            started_at_unix_ms: started_at,
+            last_update_unix_ms: last_update,
+            started_at: clock::rfc3339(started_at),
+            last_update: clock::rfc3339(last_update),
+            parent_id: self.parent,
+            summary: self.summary.clone(),
+            source_file: self.source_file.clone(),
+            client_data: self.client_data.clone(),
+            attempt: self.attempt,
+            retry_error: self.retry_error.clone(),
+            instance: self.instance.clone(),
+            review_ready: false,
+        }
+    }
+
+    fn compute_progress_metrics(&self) -> (f32, f32, Option<u32>, u32) {
+        if self.stage == JobStage::Complete {
+            return (
+                1.0,
+                1.0,
+                Some(self.plan.len() as u32),
+                self.plan.len() as u32,
+            );
+        }
+
+        let total_stages = self.plan.len() as f32;
+
+        if total_stages == 0.0 {
+            let stage_progress = if matches!(self.stage, JobStage::Failed) {
+                self.stage_progress.min(1.0)
+            } else {
+                self.stage_progress
+            };
+            return (stage_progress, stage_progress, None, 0);
+        }
+
+        let stage_index = self.plan.iter().position(|stage| *stage == self.stage);
+        match stage_index {
+            Some(idx) => {
+                let completed = idx as f32;
+                let clamped_stage = self.stage_progress.clamp(0.0, 1.0);
+                let overall = ((completed + clamped_stage) / total_stages).clamp(0.0, 1.0);
+                (
+                    overall,
+                    clamped_stage,
+                    Some((idx + 1) as u32),
+                    self.plan.len() as u32,
+                )
+            }
+            None => {
+                let overall = match self.stage {
+                    JobStage::Failed => self.stage_progress.clamp(0.0, 1.0),
+                    JobStage::Queued => 0.0,
+                    JobStage::Uploading
+                    | JobStage::Downloading
+                    | JobStage::AwaitingApproval
+                    | JobStage::Transcoding => (self.stage_progress / total_stages).clamp(0.0, 1.0),
+                    JobStage::Finalizing => {
+                        ((total_stages - 1.0 + self.stage_progress) / total_stages).clamp(0.0, 1.0)
+                    }
+                    JobStage::Complete => 1.0,
+                };
+                (
+                    overall,
+                    self.stage_progress.clamp(0.0, 1.0),
+                    None,
+                    self.plan.len() as u32,
+                )
+            }
+        }
+    }
+
+    fn estimate_remaining_seconds(&self, stage_progress: f32) -> Option<f64> {
+        const INITIAL_ESTIMATE_SECONDS: f64 = 45.0 * 60.0; // 45 minutes as an upper-bound guess
+        const MIN_STAGE_PROGRESS_FOR_ESTIMATE: f32 = 0.02;
+
+        if matches!(self.stage, JobStage::Complete) {
+            return Some(0.0);
+        }
+        // Nobody can tell when a reviewer will get to it.
+        if matches!(self.stage, JobStage::AwaitingApproval) {
+            return None;
+        }
+
+        if let Some(eta) = self.stage_eta_seconds {
+            return Some(eta.max(0.0));
+        }
+
+        let stage_elapsed = self.stage_elapsed_seconds();
+
+        if stage_progress < MIN_STAGE_PROGRESS_FOR_ESTIMATE {
+            let baseline = INITIAL_ESTIMATE_SECONDS.max(stage_elapsed.max(1.0) * 6.0);
+            return Some(baseline);
+        }
+
+        let divisor = stage_progress.max(MIN_STAGE_PROGRESS_FOR_ESTIMATE) as f64;
+        let total_estimated = stage_elapsed / divisor;
+        Some((total_estimated - stage_elapsed).max(0.0))
+    }
+
+    pub(super) fn stage_elapsed_seconds(&self) -> f64 {
+        self.stage_started_at_instant.elapsed().as_secs_f64()
+    }
+}
@@ -0,0 +1,284 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+#[cfg(feature = "redis")]
+use super::status::first_attempt;
+use super::{
+    EncodeSummary, JobGroupMember, JobGroupStatus, JobSource, JobStage, PRIMARY_MEMBER, StageTiming,
+};
+use crate::error::ErrorClass;
+
+pub(super) struct JobRecord {
+    pub(super) stage: JobStage,
+    pub(super) stage_progress: f32,
+    pub(super) started_at_instant: Instant,
+    pub(super) last_update_instant: Instant,
+    pub(super) started_at_system: SystemTime,
+    pub(super) last_update_system: SystemTime,
+    pub(super) error: Option<String>,
+    pub(super) error_class: Option<ErrorClass>,
+    pub(super) plan: Vec<JobStage>,
+    pub(super) stage_started_at_instant: Instant,
+    pub(super) stage_started_at_system: SystemTime,
+    pub(super) stage_eta_seconds: Option<f64>,
+    pub(super) stage_history: Vec<StageTiming>,
+    pub(super) parent: Option<Uuid>,
+    pub(super) children: Vec<GroupMember>,
+    pub(super) summary: Option<EncodeSummary>,
+    pub(super) source: Option<JobSource>,
+    pub(super) source_file: Option<String>,
+    pub(super) client_data: Option<Value>,
+    pub(super) attempt: u32,
+    pub(super) retry_error: Option<String>,
+    /// The instance running the pipeline; kept apart from the stored job.
+    pub(super) instance: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(super) struct GroupMember {
+    pub(super) id: Uuid,
+    pub(super) name: String,
+}
+
+/// Wall-clock form of a `JobRecord` for stores shared between processes,
+/// where `Instant`s mean nothing.
+#[cfg(feature = "redis")]
+#[derive(Serialize, Deserialize)]
+pub(super) struct StoredJob {
+    stage: JobStage,
+    stage_progress: f32,
+    started_at_unix_ms: u128,
+    last_update_unix_ms: u128,
+    stage_started_at_unix_ms: u128,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error_class: Option<ErrorClass>,
+    plan: Vec<JobStage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stage_eta_seconds: Option<f64>,
+    #[serde(default)]
+    stage_history: Vec<StageTiming>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parent: Option<Uuid>,
+    #[serde(default)]
+    children: Vec<GroupMember>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    summary: Option<EncodeSummary>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<JobSource>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    client_data: Option<Value>,
+    #[serde(default = "first_attempt")]
+    attempt: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retry_error: Option<String>,
+}
+
+impl JobRecord {
+    pub(super) fn new() -> Self {
+        let now_instant = Instant::now();
+        let now_system = SystemTime::now();
+        Self {
+            stage: JobStage::Queued,
+            stage_progress: 0.0,
+            started_at_instant: now_instant,
+            last_update_instant: now_instant,
+            started_at_system: now_system,
+            last_update_system: now_system,
+            error: None,
+            error_class: None,
+            plan: Vec::new(),
+            stage_started_at_instant: now_instant,
+            stage_started_at_system: now_system,
+            stage_eta_seconds: None,
+            stage_history: Vec::new(),
+            parent: None,
+            children: Vec::new(),
+            summary: None,
+            source: None,
+            source_file: None,
+            client_data: None,
+            attempt: 1,
+            retry_error: None,
+            instance: None,
+        }
+    }
+
+    #[cfg(feature = "redis")]
+    pub(super) fn to_stored(&self) -> StoredJob {
+        StoredJob {
+            stage: self.stage,
+            stage_progress: self.stage_progress,
+            started_at_unix_ms: millis_since_epoch(self.started_at_system),
+            last_update_unix_ms: millis_since_epoch(self.last_update_system),
+            stage_started_at_unix_ms: millis_since_epoch(self.stage_started_at_system),
+            error: self.error.clone(),
+            error_class: self.error_class,
+            plan: self.plan.clone(),
+            stage_eta_seconds: self.stage_eta_seconds,
+            stage_history: self.stage_history.clone(),
+            parent: self.parent,
+            children: self
+                .children
+                .iter()
+                .map(|member| GroupMember {
+                    id: member.id,
+                    name: member.name.clone(),
+                })
+                .collect(),
+            summary: self.summary.clone(),
+            source: self.source.clone(),
+            source_file: self.source_file.clone(),
+            client_data: self.client_data.clone(),
+            attempt: self.attempt,
+            retry_error: self.retry_error.clone(),
+        }
+    }
+
+    /// Rebuilds a record written by another process. Instants are placed as
+    /// far in the past as the stored wall-clock times, so elapsed times and
+    /// estimates carry over.
+    #[cfg(feature = "redis")]
+    pub(super) fn from_stored(stored: StoredJob) -> Self {
+        let now_instant = Instant::now();
+        let now_ms = millis_since_epoch(SystemTime::now());
+        let instant_at = |unix_ms: u128| {
+            let ago = Duration::from_millis(now_ms.saturating_sub(unix_ms) as u64);
+            now_instant.checked_sub(ago).unwrap_or(now_instant)
+        };
+        let system_at = |unix_ms: u128| UNIX_EPOCH + Duration::from_millis(unix_ms as u64);
+        Self {
+            stage: stored.stage,
+            stage_progress: stored.stage_progress,
+            started_at_instant: instant_at(stored.started_at_unix_ms),
+            last_update_instant: instant_at(stored.last_update_unix_ms),
+            started_at_system: system_at(stored.started_at_unix_ms),
+            last_update_system: system_at(stored.last_update_unix_ms),
+            error: stored.error,
+            error_class: stored.error_class,
+            plan: stored.plan,
+            stage_started_at_instant: instant_at(stored.stage_started_at_unix_ms),
+            stage_started_at_system: system_at(stored.stage_started_at_unix_ms),
+            stage_eta_seconds: stored.stage_eta_seconds,
+            stage_history: stored.stage_history,
+            parent: stored.parent,
+            children: stored.children,
+            summary: stored.summary,
+            source: stored.source,
+            source_file: stored.source_file,
+            client_data: stored.client_data,
+            attempt: stored.attempt,
+            retry_error: stored.retry_error,
+            instance: None,
+        }
+    }
+
+    pub(super) fn reset(&mut self) {
+        let fresh = JobRecord::new();
+        self.stage = fresh.stage;
+        self.stage_progress = fresh.stage_progress;
+        self.error = None;
+        self.error_class = None;
+        self.stage_started_at_instant = fresh.stage_started_at_instant;
+        self.stage_started_at_system = fresh.stage_started_at_system;
+        self.stage_eta_seconds = None;
+        self.summary = None;
+        self.source_file = None;
+        self.attempt = 1;
+        self.retry_error = None;
+        self.touch();
+    }
+
+    pub(super) fn set_plan(&mut self, plan: Vec<JobStage>) {
+        self.plan = plan;
+        self.touch();
+    }
+
+    pub(super) fn set_stage(&mut self, stage: JobStage) {
+        self.close_stage();
+        self.stage = stage;
+        self.stage_progress = 0.0;
+        self.stage_started_at_instant = Instant::now();
+        self.stage_started_at_system = SystemTime::now();
+        self.stage_eta_seconds = None;
+        self.touch();
+    }
+
+    pub(super) fn set_stage_progress(&mut self, progress: f32) {
+        self.stage_progress = progress.clamp(0.0, 1.0);
+        self.touch();
+    }
+
+    pub(super) fn fail(&mut self, error: String, class: ErrorClass) {
+        self.close_stage();
+        self.stage = JobStage::Failed;
+        self.error = Some(error);
+        self.error_class = Some(class);
+        self.touch();
+    }
+
+    /// Back to `Queued` until the next attempt starts.
+    pub(super) fn record_retry(&mut self, attempt: u32, error: String) {
+        self.set_stage(JobStage::Queued);
+        self.attempt = attempt;
+        self.retry_error = Some(error);
+    }
+
+    pub(super) fn complete(&mut self) {
+        self.close_stage();
+        self.stage = JobStage::Complete;
+        self.stage_progress = 1.0;
+        self.stage_eta_seconds = Some(0.0);
+        self.touch();
+    }
+
+    /// Records how long the current stage ran before it is replaced.
+    fn close_stage(&mut self) {
+        if matches!(self.stage, JobStage::Complete | JobStage::Failed) {
+            return;
+        }
+        self.stage_history.push(StageTiming {
+            stage: self.stage,
+            duration_seconds: self.stage_elapsed_seconds(),
+            finished_at_unix_ms: millis_since_epoch(SystemTime::now()),
+        });
+    }
+
+    pub(super) fn touch(&mut self) {
+        self.last_update_instant = Instant::now();
+        self.last_update_system = SystemTime::now();
+    }
+}
+
+pub(super) fn millis_since_epoch(system_time: SystemTime) -> u128 {
+    system_time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0))
+        .as_millis()
+}
+
+/// Aggregates `root` and whichever of its children `lookup` still finds.
+pub(super) fn group_of<'a>(
+    id: Uuid,
+    root: &JobRecord,
+    lookup: impl Fn(&Uuid) -> Option<&'a JobRecord>,
+) -> JobGroupStatus {
+    let mut members = vec![JobGroupMember {
+        name: PRIMARY_MEMBER.to_string(),
+        weight: root.plan.len().max(1) as f32,
+        status: root.to_response(id),
+    }];
+    members.extend(root.children.iter().filter_map(|member| {
+        lookup(&member.id).map(|record| JobGroupMember {
+            name: member.name.clone(),
+            weight: record.plan.len().max(1) as f32,
+            status: record.to_response(member.id),
+        })
+    }));
+    JobGroupStatus::aggregate(id, members)
+}
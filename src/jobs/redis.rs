@@ -0,0 +1,504 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use redis::{RedisError, aio::ConnectionManager};
+use uuid::Uuid;
+
+use super::{
+    EncodeSummary, GroupMember, JobGroupStatus, JobRecord, JobSource, JobStage, JobStatusResponse,
+    JobStore, StageTiming, StoredJob, group_of, millis_since_epoch,
+};
+use crate::{config, error::AppError};
+use serde_json::Value;
+
+const DEFAULT_KEY_PREFIX: &str = "vrs:";
+const DEFAULT_FINISHED_TTL_SECS: u64 = 24 * 60 * 60;
+/// Sets the claim in `KEYS[1]` on job `ARGV[1]` to instance `ARGV[2]`,
+/// unless it is held by another instance among the live ones that follow.
+const CLAIM_SCRIPT: &str = r#"
+local holder = redis.call('HGET', KEYS[1], ARGV[1])
+if holder and holder ~= ARGV[2] then
+  for i = 3, #ARGV do
+    if ARGV[i] == holder then return 0 end
+  end
+end
+redis.call('HSET', KEYS[1], ARGV[1], ARGV[2])
+return 1
+"#;
+/// Drops the claim in `KEYS[1]` on job `ARGV[1]` if instance `ARGV[2]`
+/// holds it, and the job's cancel request in the set `KEYS[2]`.
+const RELEASE_SCRIPT: &str = r#"
+if redis.call('HGET', KEYS[1], ARGV[1]) == ARGV[2] then
+  redis.call('HDEL', KEYS[1], ARGV[1])
+  redis.call('SREM', KEYS[2], ARGV[1])
+end
+return 1
+"#;
+/// Replaces job `KEYS[1]` with `ARGV[2]` if it still holds `ARGV[1]`,
+/// lists job `ARGV[3]` in the index `KEYS[2]` and, unless `ARGV[4]` is
+/// `0`, expires it after that many milliseconds.
+const SWAP_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) ~= ARGV[1] then return 0 end
+if ARGV[4] == '0' then
+  redis.call('SET', KEYS[1], ARGV[2])
+else
+  redis.call('SET', KEYS[1], ARGV[2], 'PX', ARGV[4])
+end
+redis.call('SADD', KEYS[2], ARGV[3])
+return 1
+"#;
+/// Reads of a job before [`RedisJobStore::modify`] gives up on writing it.
+const MAX_WRITE_ATTEMPTS: usize = 16;
+
+/// Job state kept in Redis so every instance behind a load balancer can
+/// answer status requests for any job.
+///
+/// Each job is one JSON value under `<prefix>job:<id>`, listed in the
+/// `<prefix>jobs` set. Completed and failed jobs expire after
+/// `VIDEO_REDIS_JOB_TTL_SECS`; `0` keeps them forever. Updates are
+/// read-modify-write and only land if the job did not change since it was
+/// read, else they are applied again to the newer value. A job is mostly
+/// written by the instance running its pipeline, but the one accepting it,
+/// one taking it over and one cancelling it while queued write it too.
+/// Which instance runs a job lives in the `<prefix>claims` hash, cancels
+/// asked of it in the `<prefix>cancels` set, and heartbeats in the
+/// `<prefix>instances` sorted set, scored by when they expire.
+pub struct RedisJobStore {
+    connection: ConnectionManager,
+    prefix: String,
+    finished_ttl_ms: u64,
+}
+
+impl RedisJobStore {
+    /// Connects to `url`, e.g. `redis://cache:6379/0`. Keys are prefixed with
+    /// `VIDEO_REDIS_KEY_PREFIX` (default `vrs:`).
+    pub async fn connect(url: &str) -> Result<Self, AppError> {
+        let client = redis::Client::open(url).map_err(unavailable)?;
+        let connection = ConnectionManager::new(client).await.map_err(unavailable)?;
+        let prefix =
+            config::var("VIDEO_REDIS_KEY_PREFIX").unwrap_or_else(|| DEFAULT_KEY_PREFIX.to_string());
+        let finished_ttl_secs =
+            config::parse_var("VIDEO_REDIS_JOB_TTL_SECS").unwrap_or(DEFAULT_FINISHED_TTL_SECS);
+        Ok(Self {
+            connection,
+            prefix,
+            finished_ttl_ms: finished_ttl_secs.saturating_mul(1000),
+        })
+    }
+
+    fn job_key(&self, id: &Uuid) -> String {
+        format!("{}job:{id}", self.prefix)
+    }
+
+    fn index_key(&self) -> String {
+        format!("{}jobs", self.prefix)
+    }
+
+    fn claims_key(&self) -> String {
+        format!("{}claims", self.prefix)
+    }
+
+    fn instances_key(&self) -> String {
+        format!("{}instances", self.prefix)
+    }
+
+    fn cancels_key(&self) -> String {
+        format!("{}cancels", self.prefix)
+    }
+
+    async fn load(&self, id: &Uuid) -> Result<Option<JobRecord>, AppError> {
+        let (value, instance): (Option<Vec<u8>>, Option<String>) = redis::pipe()
+            .cmd("GET")
+            .arg(self.job_key(id))
+            .cmd("HGET")
+            .arg(self.claims_key())
+            .arg(id.to_string())
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(unavailable)?;
+        let Some(bytes) = value else {
+            return Ok(None);
+        };
+        let mut record = decode(&bytes)?;
+        record.instance = instance;
+        Ok(Some(record))
+    }
+
+    async fn load_many(&self, ids: &[Uuid]) -> Result<HashMap<Uuid, JobRecord>, AppError> {
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let keys: Vec<String> = ids.iter().map(|id| self.job_key(id)).collect();
+        let (values, instances): (Vec<Option<Vec<u8>>>, Vec<Option<String>>) = redis::pipe()
+            .cmd("MGET")
+            .arg(keys)
+            .cmd("HMGET")
+            .arg(self.claims_key())
+            .arg(ids.iter().map(Uuid::to_string).collect::<Vec<_>>())
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(unavailable)?;
+        let mut records = HashMap::new();
+        for ((id, value), instance) in ids.iter().zip(values).zip(instances) {
+            if let Some(bytes) = value {
+                let mut record = decode(&bytes)?;
+                record.instance = instance;
+                records.insert(*id, record);
+            }
+        }
+        Ok(records)
+    }
+
+    /// Every job still stored, dropping expired ones from the index.
+    async fn load_all(&self) -> Result<HashMap<Uuid, JobRecord>, AppError> {
+        let members: Vec<String> = redis::cmd("SMEMBERS")
+            .arg(self.index_key())
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(unavailable)?;
+        let ids: Vec<Uuid> = members
+            .iter()
+            .filter_map(|member| Uuid::parse_str(member).ok())
+            .collect();
+        let records = self.load_many(&ids).await?;
+
+        let expired: Vec<String> = ids
+            .iter()
+            .filter(|id| !records.contains_key(id))
+            .map(Uuid::to_string)
+            .collect();
+        if !expired.is_empty() {
+            redis::pipe()
+                .cmd("SREM")
+                .arg(self.index_key())
+                .arg(&expired)
+                .ignore()
+                .cmd("HDEL")
+                .arg(self.claims_key())
+                .arg(&expired)
+                .ignore()
+                .query_async::<()>(&mut self.connection.clone())
+                .await
+                .map_err(unavailable)?;
+        }
+        Ok(records)
+    }
+
+    async fn save(&self, id: &Uuid, record: &JobRecord) -> Result<(), AppError> {
+        let bytes = serde_json::to_vec(&record.to_stored()).map_err(std::io::Error::from)?;
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        let set = pipe.cmd("SET").arg(self.job_key(id)).arg(bytes);
+        if record.stage.is_terminal() && self.finished_ttl_ms > 0 {
+            set.arg("PX").arg(self.finished_ttl_ms);
+        }
+        set.ignore();
+        pipe.cmd("SADD")
+            .arg(self.index_key())
+            .arg(id.to_string())
+            .ignore();
+        pipe.query_async::<()>(&mut self.connection.clone())
+            .await
+            .map_err(unavailable)
+    }
+
+    /// Applies `change` to a stored job; unknown jobs are ignored, as in
+    /// [`LocalJobStore`](super::LocalJobStore).
+    async fn modify(
+        &self,
+        id: Uuid,
+        mut change: impl FnMut(&mut JobRecord),
+    ) -> Result<(), AppError> {
+        for _ in 0..MAX_WRITE_ATTEMPTS {
+            let value: Option<Vec<u8>> = redis::cmd("GET")
+                .arg(self.job_key(&id))
+                .query_async(&mut self.connection.clone())
+                .await
+                .map_err(unavailable)?;
+            let Some(read) = value else {
+                return Ok(());
+            };
+            let mut record = decode(&read)?;
+            change(&mut record);
+            let bytes = serde_json::to_vec(&record.to_stored()).map_err(std::io::Error::from)?;
+            let ttl_ms = if record.stage.is_terminal() {
+                self.finished_ttl_ms
+            } else {
+                0
+            };
+            let swapped: i64 = redis::cmd("EVAL")
+                .arg(SWAP_SCRIPT)
+                .arg(2)
+                .arg(self.job_key(&id))
+                .arg(self.index_key())
+                .arg(read)
+                .arg(bytes)
+                .arg(id.to_string())
+                .arg(ttl_ms)
+                .query_async(&mut self.connection.clone())
+                .await
+                .map_err(unavailable)?;
+            if swapped == 1 {
+                return Ok(());
+            }
+        }
+        Err(AppError::dependency(format!(
+            "redis job store: job {id} kept changing while it was written"
+        ))
+        .with_code("job_store_unavailable"))
+    }
+}
+
+#[async_trait]
+impl JobStore for RedisJobStore {
+    async fn create_job(&self, id: Uuid) -> Result<(), AppError> {
+        self.save(&id, &JobRecord::new()).await
+    }
+
+    async fn set_plan(&self, id: Uuid, plan: Vec<JobStage>) -> Result<(), AppError> {
+        self.modify(id, |record| record.set_plan(plan.clone()))
+            .await
+    }
+
+    async fn update_stage(&self, id: Uuid, stage: JobStage) -> Result<(), AppError> {
+        self.modify(id, |record| record.set_stage(stage)).await
+    }
+
+    async fn update_progress(&self, id: Uuid, progress: f32) -> Result<(), AppError> {
+        self.modify(id, |record| record.set_stage_progress(progress))
+            .await
+    }
+
+    async fn update_stage_eta(&self, id: Uuid, eta_seconds: Option<f64>) -> Result<(), AppError> {
+        self.modify(id, |record| {
+            record.stage_eta_seconds = eta_seconds;
+            record.touch();
+        })
+        .await
+    }
+
+    async fn fail(&self, id: Uuid, error: &AppError) -> Result<(), AppError> {
+        let (message, class) = (error.to_string(), error.class());
+        self.modify(id, |record| {
+            record.fail(message.clone(), class);
+            record.stage_eta_seconds = None;
+        })
+        .await
+    }
+
+    async fn complete(&self, id: Uuid) -> Result<(), AppError> {
+        self.modify(id, |record| {
+            record.complete();
+            record.stage_eta_seconds = Some(0.0);
+        })
+        .await
+    }
+
+    async fn set_summary(&self, id: Uuid, summary: EncodeSummary) -> Result<(), AppError> {
+        self.modify(id, |record| {
+            record.summary = Some(summary.clone());
+            record.touch();
+        })
+        .await
+    }
+
+    async fn set_source(&self, id: Uuid, source: JobSource) -> Result<(), AppError> {
+        self.modify(id, |record| record.source = Some(source.clone()))
+            .await
+    }
+
+    async fn source(&self, id: &Uuid) -> Result<Option<JobSource>, AppError> {
+        Ok(self.load(id).await?.and_then(|record| record.source))
+    }
+
+    async fn record_retry(&self, id: Uuid, attempt: u32, error: &AppError) -> Result<(), AppError> {
+        let error = error.to_string();
+        self.modify(id, |record| record.record_retry(attempt, error.clone()))
+            .await
+    }
+
+    async fn set_source_file(&self, id: Uuid, name: &str) -> Result<(), AppError> {
+        let name = name.to_string();
+        self.modify(id, |record| record.source_file = Some(name.clone()))
+            .await
+    }
+
+    async fn set_client_data(&self, id: Uuid, data: Value) -> Result<(), AppError> {
+        self.modify(id, |record| record.client_data = Some(data.clone()))
+            .await
+    }
+
+    async fn status(&self, id: &Uuid) -> Result<Option<JobStatusResponse>, AppError> {
+        Ok(self.load(id).await?.map(|record| record.to_response(*id)))
+    }
+
+    async fn list(&self) -> Result<Vec<JobStatusResponse>, AppError> {
+        Ok(self
+            .load_all()
+            .await?
+            .iter()
+            .map(|(id, record)| record.to_response(*id))
+            .collect())
+    }
+
+    async fn stage_timings(&self, since: SystemTime) -> Result<Vec<StageTiming>, AppError> {
+        let cutoff = millis_since_epoch(since);
+        Ok(self
+            .load_all()
+            .await?
+            .into_values()
+            .flat_map(|record| record.stage_history)
+            .filter(|timing| timing.finished_at_unix_ms >= cutoff)
+            .collect())
+    }
+
+    async fn add_child(&self, parent: Uuid, child: Uuid, name: &str) -> Result<(), AppError> {
+        if self.load(&parent).await?.is_none() {
+            return Err(AppError::not_found(format!("job {parent} not found")));
+        }
+        self.modify(parent, |record| {
+            record.children.push(GroupMember {
+                id: child,
+                name: name.to_string(),
+            });
+            record.touch();
+        })
+        .await?;
+
+        let mut child_record = JobRecord::new();
+        child_record.parent = Some(parent);
+        self.save(&child, &child_record).await
+    }
+
+    async fn reset(&self, id: Uuid) -> Result<(), AppError> {
+        self.modify(id, JobRecord::reset).await
+    }
+
+    async fn remove(&self, id: Uuid) -> Result<(), AppError> {
+        let Some(record) = self.load(&id).await? else {
+            return Ok(());
+        };
+        let ids: Vec<Uuid> = std::iter::once(id)
+            .chain(record.children.iter().map(|member| member.id))
+            .collect();
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        pipe.cmd("DEL")
+            .arg(ids.iter().map(|id| self.job_key(id)).collect::<Vec<_>>())
+            .ignore();
+        let members: Vec<String> = ids.iter().map(Uuid::to_string).collect();
+        pipe.cmd("SREM")
+            .arg(self.index_key())
+            .arg(&members)
+            .ignore();
+        pipe.cmd("HDEL")
+            .arg(self.claims_key())
+            .arg(&members)
+            .ignore();
+        pipe.cmd("SREM")
+            .arg(self.cancels_key())
+            .arg(&members)
+            .ignore();
+        pipe.query_async::<()>(&mut self.connection.clone())
+            .await
+            .map_err(unavailable)
+    }
+
+    async fn group_status(&self, id: &Uuid) -> Result<Option<JobGroupStatus>, AppError> {
+        let Some(root) = self.load(id).await? else {
+            return Ok(None);
+        };
+        let child_ids: Vec<Uuid> = root.children.iter().map(|member| member.id).collect();
+        let children = self.load_many(&child_ids).await?;
+        Ok(Some(group_of(*id, &root, |child| children.get(child))))
+    }
+
+    async fn heartbeat(&self, instance: &str, ttl: Duration) -> Result<(), AppError> {
+        let expires = millis_since_epoch(SystemTime::now() + ttl) as u64;
+        redis::cmd("ZADD")
+            .arg(self.instances_key())
+            .arg(expires)
+            .arg(instance)
+            .query_async::<()>(&mut self.connection.clone())
+            .await
+            .map_err(unavailable)
+    }
+
+    async fn live_instances(&self) -> Result<Vec<String>, AppError> {
+        let now = millis_since_epoch(SystemTime::now()) as u64;
+        let (live,): (Vec<String>,) = redis::pipe()
+            .cmd("ZREMRANGEBYSCORE")
+            .arg(self.instances_key())
+            .arg("-inf")
+            .arg(now)
+            .ignore()
+            .cmd("ZRANGEBYSCORE")
+            .arg(self.instances_key())
+            .arg(format!("({now}"))
+            .arg("+inf")
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(unavailable)?;
+        Ok(live)
+    }
+
+    async fn claim(&self, id: Uuid, instance: &str, live: &[String]) -> Result<bool, AppError> {
+        let claimed: i64 = redis::cmd("EVAL")
+            .arg(CLAIM_SCRIPT)
+            .arg(1)
+            .arg(self.claims_key())
+            .arg(id.to_string())
+            .arg(instance)
+            .arg(live)
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(unavailable)?;
+        Ok(claimed == 1)
+    }
+
+    async fn release(&self, id: Uuid, instance: &str) -> Result<(), AppError> {
+        redis::cmd("EVAL")
+            .arg(RELEASE_SCRIPT)
+            .arg(2)
+            .arg(self.claims_key())
+            .arg(self.cancels_key())
+            .arg(id.to_string())
+            .arg(instance)
+            .query_async::<()>(&mut self.connection.clone())
+            .await
+            .map_err(unavailable)
+    }
+
+    async fn request_cancel(&self, id: Uuid) -> Result<(), AppError> {
+        redis::cmd("SADD")
+            .arg(self.cancels_key())
+            .arg(id.to_string())
+            .query_async::<()>(&mut self.connection.clone())
+            .await
+            .map_err(unavailable)
+    }
+
+    async fn cancel_requests(&self) -> Result<Vec<Uuid>, AppError> {
+        let ids: Vec<String> = redis::cmd("SMEMBERS")
+            .arg(self.cancels_key())
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(unavailable)?;
+        Ok(ids
+            .iter()
+            .filter_map(|id| Uuid::parse_str(id).ok())
+            .collect())
+    }
+}
+
+fn decode(bytes: &[u8]) -> Result<JobRecord, AppError> {
+    let stored: StoredJob = serde_json::from_slice(bytes).map_err(std::io::Error::from)?;
+    Ok(JobRecord::from_stored(stored))
+}
+
+fn unavailable(err: RedisError) -> AppError {
+    AppError::dependency(format!("redis job store: {err}")).with_code("job_store_unavailable")
+}
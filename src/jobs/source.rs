@@ -0,0 +1,377 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aria2::FileSelection, captions::SubtitleRequest, error::AppError, s3::S3Credentials,
+    transcode::EncodeParams,
+};
+
+/// Where a job's media came from and how it was to be encoded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobSource {
+    pub origin: JobOrigin,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encode: Option<EncodeParams>,
+    /// Receives the job's final status, see [`crate::callbacks`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback_url: Option<String>,
+    /// Per-request credentials were dropped when the source was stored, so
+    /// the job cannot run from it without them.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub credentials_redacted: bool,
+}
+
+impl JobSource {
+    /// The source as kept in the job store and logged, without per-request
+    /// yt-dlp or S3 credentials or the login of its proxy. A stored cookies
+    /// file is still named.
+    pub fn redacted(&self) -> Self {
+        let mut source = self.clone();
+        source.credentials_redacted |= self.has_credentials();
+        let redact_proxy = |proxy: &mut Option<String>| {
+            if let Some(proxy) = proxy {
+                *proxy = crate::http_client::redact_proxy(proxy);
+            }
+        };
+        match &mut source.origin {
+            JobOrigin::YtDlp { options, .. } => {
+                options.auth = options.auth.redacted();
+                redact_proxy(&mut options.proxy);
+            }
+            JobOrigin::Remote {
+                s3_credentials,
+                proxy,
+                ..
+            } => {
+                *s3_credentials = None;
+                redact_proxy(proxy);
+            }
+            JobOrigin::Local => {}
+        }
+        source
+    }
+
+    /// Whether the source carries credentials [`Self::redacted`] drops.
+    pub fn has_credentials(&self) -> bool {
+        let proxy_login = |proxy: &Option<String>| {
+            proxy
+                .as_deref()
+                .is_some_and(|proxy| crate::http_client::redact_proxy(proxy) != proxy)
+        };
+        match &self.origin {
+            JobOrigin::YtDlp { options, .. } => {
+                options.auth != options.auth.redacted() || proxy_login(&options.proxy)
+            }
+            JobOrigin::Remote {
+                s3_credentials,
+                proxy,
+                ..
+            } => s3_credentials.is_some() || proxy_login(proxy),
+            JobOrigin::Local => false,
+        }
+    }
+
+    /// Puts `credentials` into a stored source, e.g. to retry its job.
+    /// Fails with `job_credentials_required` when the source dropped
+    /// credentials and none are given back, and with
+    /// `job_credentials_unexpected` for credentials the job cannot use.
+    pub fn restore_credentials(&mut self, credentials: JobCredentials) -> Result<(), AppError> {
+        let unexpected = |field: &str| {
+            AppError::validation(format!("{field} does not apply to this job"))
+                .with_code("job_credentials_unexpected")
+                .with_param("field", field)
+        };
+        let JobCredentials {
+            s3_credentials: given_s3,
+            auth: given_auth,
+            proxy: given_proxy,
+        } = credentials;
+        if let Some(given) = &given_proxy {
+            crate::http_client::parse_proxy(given)?;
+        }
+        match &mut self.origin {
+            JobOrigin::Remote {
+                url,
+                s3_credentials,
+                proxy,
+                ..
+            } => {
+                if let Some(given) = given_proxy {
+                    *proxy = Some(given);
+                }
+                if given_auth.is_some() {
+                    return Err(unexpected("auth"));
+                }
+                if let Some(given) = given_s3 {
+                    if !crate::s3::is_s3_url(url) {
+                        return Err(unexpected("s3_credentials"));
+                    }
+                    *s3_credentials = Some(given);
+                }
+            }
+            JobOrigin::YtDlp { options, .. } => {
+                if given_s3.is_some() {
+                    return Err(unexpected("s3_credentials"));
+                }
+                if let Some(given) = given_proxy {
+                    options.proxy = Some(given);
+                }
+                if let Some(auth) = given_auth {
+                    auth.validate()?;
+                    options.auth = YtDlpAuth {
+                        cookies: auth.cookies.or(options.auth.cookies.take()),
+                        ..auth
+                    };
+                }
+            }
+            JobOrigin::Local => {
+                if given_s3.is_some() {
+                    return Err(unexpected("s3_credentials"));
+                }
+                if given_auth.is_some() {
+                    return Err(unexpected("auth"));
+                }
+                if given_proxy.is_some() {
+                    return Err(unexpected("proxy"));
+                }
+            }
+        }
+        if self.credentials_redacted && !self.has_credentials() {
+            return Err(AppError::validation(
+                "the job's credentials are not kept; send them again to run it",
+            )
+            .with_code("job_credentials_required"));
+        }
+        self.credentials_redacted = false;
+        Ok(())
+    }
+}
+
+/// Per-request credentials sent again for a job whose stored source dropped
+/// them, as in a retry.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct JobCredentials {
+    /// Keys for the job's `s3://` URL.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s3_credentials: Option<S3Credentials>,
+    /// Cookies or login for the job's yt-dlp download.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<YtDlpAuth>,
+    /// The job's proxy, with the user and password it logs in with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobOrigin {
+    /// An upload or local file, copied to the incoming area.
+    Local,
+    Remote {
+        url: String,
+        /// Proxy the download goes through instead of `VIDEO_HTTP_PROXY`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        proxy: Option<String>,
+        /// Keys for an `s3://` URL, used instead of those in the environment.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        s3_credentials: Option<S3Credentials>,
+        /// Which file of a multi-file torrent is the video.
+        #[serde(flatten)]
+        files: FileSelection,
+    },
+    YtDlp {
+        url: String,
+        #[serde(flatten)]
+        options: YtDlpOptions,
+    },
+}
+
+/// What a yt-dlp job fetches besides the video, kept so a retry fetches the
+/// same.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct YtDlpOptions {
+    #[serde(default, skip_serializing_if = "SubtitleRequest::is_empty")]
+    pub subtitles: SubtitleRequest,
+    /// Import SponsorBlock segments as the video's skip segments.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub skip_segments: bool,
+    /// Skip formats taller than this, unless the site offers nothing else.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_height: Option<u32>,
+    /// Video codec to prefer among formats of the same height.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefer_codec: Option<PreferredCodec>,
+    /// yt-dlp `-f` selector used instead of the default `bv*+ba/b`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    /// Login for sites that need one.
+    #[serde(default, skip_serializing_if = "YtDlpAuth::is_empty")]
+    pub auth: YtDlpAuth,
+    /// Proxy yt-dlp downloads through instead of `VIDEO_HTTP_PROXY`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+}
+
+/// How yt-dlp logs in: with a cookies file stored under `libs/cookies/`, or
+/// with a `Cookie` header or username and password sent with the request.
+#[derive(Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct YtDlpAuth {
+    /// Name of a stored cookies file, see [`crate::cookies`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cookies: Option<String>,
+    /// `Cookie` header value, e.g. `SID=...; HSID=...`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cookie: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+const MAX_CREDENTIAL_LEN: usize = 8 * 1024;
+
+impl YtDlpAuth {
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_none()
+            && self.cookie.is_none()
+            && self.username.is_none()
+            && self.password.is_none()
+    }
+
+    /// Checks the credentials' shape; whether the cookies file exists is
+    /// checked when the job is submitted.
+    pub fn validate(&self) -> Result<(), AppError> {
+        if let Some(name) = &self.cookies {
+            crate::cookies::validate_name(name)?;
+        }
+        if self.username.is_some() != self.password.is_some() {
+            return Err(
+                AppError::validation("username and password must be given together")
+                    .with_code("ytdlp_auth_invalid"),
+            );
+        }
+        for (field, value) in [
+            ("cookie", &self.cookie),
+            ("username", &self.username),
+            ("password", &self.password),
+        ] {
+            if let Some(value) = value
+                && (value.is_empty()
+                    || value.len() > MAX_CREDENTIAL_LEN
+                    || value.chars().any(char::is_control))
+            {
+                return Err(AppError::validation(format!("invalid {field}"))
+                    .with_code("ytdlp_auth_invalid")
+                    .with_param("field", field));
+            }
+        }
+        Ok(())
+    }
+
+    /// Only the name of the stored cookies file.
+    pub fn redacted(&self) -> Self {
+        Self {
+            cookies: self.cookies.clone(),
+            ..Self::default()
+        }
+    }
+}
+
+impl std::fmt::Debug for YtDlpAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hidden = |value: &Option<String>| value.as_ref().map(|_| "<redacted>");
+        f.debug_struct("YtDlpAuth")
+            .field("cookies", &self.cookies)
+            .field("cookie", &hidden(&self.cookie))
+            .field("username", &self.username)
+            .field("password", &hidden(&self.password))
+            .finish()
+    }
+}
+
+/// Codecs `prefer_codec` accepts, named as in encode options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreferredCodec {
+    Av1,
+    Vp9,
+    #[serde(alias = "h265")]
+    Hevc,
+    #[serde(alias = "avc")]
+    H264,
+}
+
+impl PreferredCodec {
+    /// The codec's name in yt-dlp's `vcodec` sort field.
+    fn sort_name(self) -> &'static str {
+        match self {
+            Self::Av1 => "av01",
+            Self::Vp9 => "vp9",
+            Self::Hevc => "h265",
+            Self::H264 => "h264",
+        }
+    }
+}
+
+const MIN_MAX_HEIGHT: u32 = 144;
+const MAX_MAX_HEIGHT: u32 = 4320;
+const MAX_FORMAT_LEN: usize = 256;
+
+impl YtDlpOptions {
+    pub fn validate(&self) -> Result<(), AppError> {
+        self.subtitles.validate()?;
+        self.auth.validate()?;
+        if let Some(proxy) = &self.proxy {
+            crate::http_client::parse_proxy(proxy)?;
+        }
+        if let Some(height) = self.max_height
+            && !(MIN_MAX_HEIGHT..=MAX_MAX_HEIGHT).contains(&height)
+        {
+            return Err(AppError::validation(format!(
+                "max_height must be between {MIN_MAX_HEIGHT} and {MAX_MAX_HEIGHT}"
+            ))
+            .with_code("ytdlp_max_height_invalid")
+            .with_param("min", MIN_MAX_HEIGHT)
+            .with_param("max", MAX_MAX_HEIGHT));
+        }
+        if let Some(format) = &self.format
+            && (format.trim().is_empty()
+                || format.len() > MAX_FORMAT_LEN
+                || format.chars().any(char::is_control))
+        {
+            return Err(
+                AppError::validation(format!("invalid yt-dlp format selector {format:?}"))
+                    .with_code("ytdlp_format_invalid")
+                    .with_param("max_length", MAX_FORMAT_LEN),
+            );
+        }
+        Ok(())
+    }
+
+    /// yt-dlp's `-f` selector and, when a height or codec is preferred, its
+    /// `-S` sort order. Without a raw `format`, `max_height` also filters the
+    /// selector, falling back to any format so the download still succeeds.
+    pub fn format_args(&self) -> Vec<String> {
+        let selector = match (&self.format, self.max_height) {
+            (Some(format), _) => format.clone(),
+            (None, Some(height)) => {
+                format!("bv*[height<={height}]+ba/b[height<={height}]/bv*+ba/b")
+            }
+            (None, None) => "bv*+ba/b".to_string(),
+        };
+        let mut args = vec!["-f".to_string(), selector];
+        if self.max_height.is_some() || self.prefer_codec.is_some() {
+            // Fields given to -S go before yt-dlp's defaults, so resolution
+            // is named first to keep it ahead of the codec.
+            let mut sort = match self.max_height {
+                Some(height) => format!("res:{height}"),
+                None => "res".to_string(),
+            };
+            if let Some(codec) = self.prefer_codec {
+                sort.push_str(",vcodec:");
+                sort.push_str(codec.sort_name());
+            }
+            args.extend(["-S".to_string(), sort]);
+        }
+        args
+    }
+}
@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::error::ErrorClass;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStage {
+    Queued,
+    Uploading,
+    Downloading,
+    /// A review proxy is ready and the full encode waits for approval.
+    AwaitingApproval,
+    Transcoding,
+    Finalizing,
+    Complete,
+    Failed,
+}
+
+impl JobStage {
+    pub const ALL: [JobStage; 8] = [
+        JobStage::Queued,
+        JobStage::Uploading,
+        JobStage::Downloading,
+        JobStage::AwaitingApproval,
+        JobStage::Transcoding,
+        JobStage::Finalizing,
+        JobStage::Complete,
+        JobStage::Failed,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStage::Queued => "queued",
+            JobStage::Uploading => "uploading",
+            JobStage::Downloading => "downloading",
+            JobStage::AwaitingApproval => "awaiting_approval",
+            JobStage::Transcoding => "transcoding",
+            JobStage::Finalizing => "finalizing",
+            JobStage::Complete => "complete",
+            JobStage::Failed => "failed",
+        }
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, JobStage::Complete | JobStage::Failed)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageTiming {
+    pub stage: JobStage,
+    pub duration_seconds: f64,
+    pub finished_at_unix_ms: u128,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobStatusResponse {
+    pub id: Uuid,
+    pub stage: JobStage,
+    pub progress: f32,
+    pub stage_progress: f32,
+    pub current_stage_index: Option<u32>,
+    pub total_stages: u32,
+    pub elapsed_seconds: f64,
+    pub estimated_remaining_seconds: Option<f64>,
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_class: Option<ErrorClass>,
+    /// Set with `error_class`: whether running the job again may succeed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_retryable: Option<bool>,
+    pub started_at_unix_ms: u128,
+    pub last_update_unix_ms: u128,
+    /// `started_at_unix_ms` as an RFC 3339 UTC timestamp.
+    #[serde(default)]
+    pub started_at: String,
+    /// `last_update_unix_ms` as an RFC 3339 UTC timestamp.
+    #[serde(default)]
+    pub last_update: String,
+    /// Group this job belongs to, for child jobs created with `add_child`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<Uuid>,
+    /// What the encode produced; set when the job completes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<EncodeSummary>,
+    /// The file the job ingests from a multi-file torrent, by its path in
+    /// the torrent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_file: Option<String>,
+    /// The `client_data` given at ingest, returned as it was sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_data: Option<Value>,
+    /// Which run of the pipeline this is, counting automatic retries.
+    #[serde(default = "first_attempt")]
+    pub attempt: u32,
+    /// The error the previous run stopped with, once the job was retried
+    /// automatically.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_error: Option<String>,
+    /// The instance running the job's pipeline under sharded dispatch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    /// Set by `GET /jobs/{id}` once a job in `AwaitingApproval` has its
+    /// review proxy and nothing more runs until it is approved or rejected.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub review_ready: bool,
+}
+
+/// Outputs of a finished encode, for clients to log and display.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EncodeSummary {
+    /// ffmpeg encoder that produced the download, e.g. `libaom-av1`.
+    pub encoder: String,
+    pub source_bytes: u64,
+    pub download_bytes: u64,
+    pub hls_bytes: u64,
+    pub dash_bytes: u64,
+    /// Source size divided by download size.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression_ratio: Option<f64>,
+    pub encode_wall_seconds: f64,
+    pub renditions: Vec<RenditionSummary>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RenditionSummary {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    /// ffmpeg encoder of the rung's DASH representation, after any fallback.
+    #[serde(default)]
+    pub encoder: String,
+    /// Average bitrate of the encoded rung, from its DASH segment sizes and
+    /// the source duration. Absent when the duration is unknown.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bitrate_kbps: Option<u32>,
+    /// Average bitrate the ladder asked the encoder for.
+    #[serde(default)]
+    pub target_bitrate_kbps: u32,
+    pub maxrate_kbps: u32,
+}
+
+/// Name of the job that owns a group in `JobGroupStatus::members`.
+pub const PRIMARY_MEMBER: &str = "pipeline";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobGroupMember {
+    pub name: String,
+    /// Share of the aggregate progress, proportional to the member's planned stages.
+    pub weight: f32,
+    pub status: JobStatusResponse,
+}
+
+/// Aggregate view over a job and its child jobs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobGroupStatus {
+    pub id: Uuid,
+    pub stage: JobStage,
+    pub progress: f32,
+    pub estimated_remaining_seconds: Option<f64>,
+    pub members: Vec<JobGroupMember>,
+}
+
+impl JobGroupStatus {
+    /// The group fails as soon as one member fails and completes once all members
+    /// have; otherwise it reports the stage of the first member still running.
+    pub fn aggregate(id: Uuid, members: Vec<JobGroupMember>) -> Self {
+        let stage = if members
+            .iter()
+            .any(|member| member.status.stage == JobStage::Failed)
+        {
+            JobStage::Failed
+        } else {
+            members
+                .iter()
+                .map(|member| member.status.stage)
+                .find(|stage| !stage.is_terminal())
+                .unwrap_or(JobStage::Complete)
+        };
+
+        let total_weight: f32 = members.iter().map(|member| member.weight).sum();
+        let progress = if total_weight > 0.0 {
+            members
+                .iter()
+                .map(|member| member.status.progress * member.weight)
+                .sum::<f32>()
+                / total_weight
+        } else {
+            0.0
+        };
+
+        // Members run concurrently, so the slowest one bounds the group.
+        let estimated_remaining_seconds = members
+            .iter()
+            .filter(|member| !member.status.stage.is_terminal())
+            .filter_map(|member| member.status.estimated_remaining_seconds)
+            .reduce(f64::max)
+            .or((stage == JobStage::Complete).then_some(0.0));
+
+        Self {
+            id,
+            stage,
+            progress: progress.clamp(0.0, 1.0),
+            estimated_remaining_seconds,
+            members,
+        }
+    }
+}
+
+pub(super) fn first_attempt() -> u32 {
+    1
+}
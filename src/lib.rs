@@ -1,11 +1,61 @@
+pub mod alerts;
+pub mod aria2;
+pub mod auth;
+pub mod bandwidth;
+pub mod batches;
+pub mod blocking;
+pub mod breaker;
+pub mod callbacks;
+pub mod cancel;
+pub mod captions;
+pub mod catalog;
 pub mod cleanup;
+pub mod cli;
+#[cfg(feature = "vrs-client")]
+pub mod client;
+pub mod clock;
+pub mod collections;
+pub mod config;
+pub mod cookies;
+pub mod dedup;
+pub mod digest;
 pub mod error;
+pub mod federation;
 pub mod handlers;
+pub mod hooks;
+pub mod http_client;
 pub mod jobs;
+pub mod locks;
+pub mod metadata;
+pub mod metrics;
+pub mod migrations;
+pub mod password;
+pub mod playlist;
+pub mod policy;
+pub mod process;
+pub mod rate_limit;
+pub mod replication;
+pub mod retry;
+pub mod s3;
+pub mod service;
+pub mod shaping;
+pub mod sharding;
+pub mod shares;
+pub mod shedding;
+pub mod signing;
+pub mod skip_segments;
 pub mod state;
 pub mod storage;
+pub mod tags;
 pub mod transcode;
+pub mod usage;
+pub mod video_access;
+pub mod workspace;
 
-pub use jobs::{DynJobStore, JobStage, JobStatusResponse, LocalJobStore};
+pub use hooks::{HookContext, HookPoint, PipelineHook};
+pub use jobs::{
+    DynJobStore, JobGroupStatus, JobOrigin, JobSource, JobStage, JobStatusResponse, LocalJobStore,
+};
+pub use service::VideoService;
 pub use state::AppState;
 pub use storage::Storage;
@@ -0,0 +1,231 @@
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{sync::OwnedMutexGuard, task::JoinHandle};
+use uuid::Uuid;
+
+use crate::{config, error::AppError, storage::ensure_dir};
+
+const DEFAULT_TTL_SECS: u64 = 60;
+const DEFAULT_WAIT_SECS: u64 = 1800;
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Per-key mutual exclusion for work on a video's files: packaging, cleanup
+/// and deletion.
+///
+/// Within one process a keyed mutex always applies. With
+/// `VIDEO_LOCK_BACKEND=file`, a lease file under `<storage>/locks/` extends
+/// the lock to every instance sharing the storage root. Leases carry an
+/// expiry that the holder renews, so a crashed instance blocks others for at
+/// most `VIDEO_LOCK_TTL_SECS`.
+pub struct LockManager {
+    dir: PathBuf,
+    owner: String,
+    local: Mutex<HashMap<String, Weak<tokio::sync::Mutex<()>>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Lease {
+    owner: String,
+    expires_at_unix_ms: u64,
+}
+
+/// Held lock; released on drop.
+pub struct LockGuard {
+    lease: Option<(PathBuf, String)>,
+    renewal: Option<JoinHandle<()>>,
+    _local: OwnedMutexGuard<()>,
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        if let Some(renewal) = self.renewal.take() {
+            renewal.abort();
+        }
+        if let Some((path, owner)) = &self.lease
+            && read_lease_sync(path).is_some_and(|lease| lease.owner == *owner)
+        {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl LockManager {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            dir: root.join("locks"),
+            owner: format!("{}-{}", std::process::id(), Uuid::new_v4().simple()),
+            local: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Lock key for one kind of work on a video, e.g. `video_key(id, "hls")`.
+    pub fn video_key(id: &Uuid, scope: &str) -> String {
+        format!("{}.{scope}", id.hyphenated())
+    }
+
+    /// Waits for `key`, up to `VIDEO_LOCK_WAIT_SECS`.
+    pub async fn acquire(&self, key: &str) -> Result<LockGuard, AppError> {
+        let wait = Duration::from_secs(
+            config::parse_var::<u64>("VIDEO_LOCK_WAIT_SECS").unwrap_or(DEFAULT_WAIT_SECS),
+        );
+        let deadline = Instant::now() + wait;
+
+        let local = self.local_mutex(key);
+        let local = tokio::time::timeout(wait, local.lock_owned())
+            .await
+            .map_err(|_| timed_out(key))?;
+
+        if !file_backend_enabled() {
+            return Ok(LockGuard {
+                lease: None,
+                renewal: None,
+                _local: local,
+            });
+        }
+
+        let ttl = Duration::from_secs(
+            config::parse_var::<u64>("VIDEO_LOCK_TTL_SECS")
+                .filter(|&secs| secs > 0)
+                .unwrap_or(DEFAULT_TTL_SECS),
+        );
+        ensure_dir(&self.dir).await?;
+        let path = self.dir.join(format!("{key}.lock"));
+        loop {
+            if self.try_create_lease(&path, ttl).await? {
+                break;
+            }
+            if Instant::now() >= deadline {
+                return Err(timed_out(key));
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+
+        Ok(LockGuard {
+            renewal: Some(spawn_renewal(path.clone(), self.owner.clone(), ttl)),
+            lease: Some((path, self.owner.clone())),
+            _local: local,
+        })
+    }
+
+    fn local_mutex(&self, key: &str) -> Arc<tokio::sync::Mutex<()>> {
+        let mut local = self
+            .local
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner());
+        local.retain(|_, mutex| mutex.strong_count() > 0);
+        if let Some(mutex) = local.get(key).and_then(Weak::upgrade) {
+            return mutex;
+        }
+        let mutex = Arc::new(tokio::sync::Mutex::new(()));
+        local.insert(key.to_string(), Arc::downgrade(&mutex));
+        mutex
+    }
+
+    /// Creates the lease file if absent, first breaking an expired lease.
+    async fn try_create_lease(&self, path: &Path, ttl: Duration) -> Result<bool, AppError> {
+        let lease = Lease {
+            owner: self.owner.clone(),
+            expires_at_unix_ms: unix_ms() + ttl.as_millis() as u64,
+        };
+        let bytes = serde_json::to_vec(&lease).map_err(std::io::Error::from)?;
+        match tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .await
+        {
+            Ok(mut file) => {
+                use tokio::io::AsyncWriteExt;
+                file.write_all(&bytes).await?;
+                file.flush().await?;
+                return Ok(true);
+            }
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        let seen = tokio::fs::read(path).await.ok();
+        let expired = match seen
+            .as_deref()
+            .and_then(|bytes| serde_json::from_slice::<Lease>(bytes).ok())
+        {
+            Some(current) => current.expires_at_unix_ms <= unix_ms(),
+            // Half-written or unreadable: trust its age instead.
+            None => tokio::fs::metadata(path)
+                .await
+                .and_then(|meta| meta.modified())
+                .is_ok_and(|modified| modified.elapsed().unwrap_or_default() > ttl),
+        };
+        if expired {
+            self.break_lease(path, seen.as_deref()).await;
+        }
+        Ok(false)
+    }
+
+    /// Moves a stale lease aside. If another instance replaced it in the
+    /// meantime, the fresh lease is put back.
+    async fn break_lease(&self, path: &Path, seen: Option<&[u8]>) {
+        let aside = path.with_extension(format!("stale.{}", self.owner));
+        if tokio::fs::rename(path, &aside).await.is_err() {
+            return;
+        }
+        let moved = tokio::fs::read(&aside).await.ok();
+        if moved.as_deref() != seen && tokio::fs::hard_link(&aside, path).await.is_err() {
+            tracing::warn!(path = %path.display(), "lost a lock lease while breaking a stale one");
+        }
+        let _ = tokio::fs::remove_file(&aside).await;
+        tracing::info!(path = %path.display(), "broke expired lock lease");
+    }
+}
+
+fn spawn_renewal(path: PathBuf, owner: String, ttl: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let period = (ttl / 3).max(Duration::from_millis(100));
+        loop {
+            tokio::time::sleep(period).await;
+            if read_lease_sync(&path).is_none_or(|lease| lease.owner != owner) {
+                tracing::warn!(path = %path.display(), "lock lease was taken over; stopping renewal");
+                return;
+            }
+            let lease = Lease {
+                owner: owner.clone(),
+                expires_at_unix_ms: unix_ms() + ttl.as_millis() as u64,
+            };
+            let Ok(bytes) = serde_json::to_vec(&lease) else {
+                return;
+            };
+            let tmp = path.with_extension(format!("renew.{owner}"));
+            if tokio::fs::write(&tmp, bytes).await.is_ok() {
+                let _ = tokio::fs::rename(&tmp, &path).await;
+            }
+        }
+    })
+}
+
+fn file_backend_enabled() -> bool {
+    config::var("VIDEO_LOCK_BACKEND").is_some_and(|value| value.trim() == "file")
+}
+
+fn read_lease_sync(path: &Path) -> Option<Lease> {
+    std::fs::read(path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+}
+
+fn timed_out(key: &str) -> AppError {
+    AppError::dependency(format!("timed out waiting for lock {key}"))
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
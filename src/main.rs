@@ -5,74 +5,504 @@ use std::{
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
+    time::Duration,
 };
 
 use axum::{
     Router,
-    http::Request,
+    http::{HeaderValue, Request, request},
+    middleware,
     response::Response as AxumResponse,
-    routing::{get, post},
+    routing::{delete, get, head, post, put},
 };
 use tower::{Service, layer::Layer};
-use tower_http::cors::CorsLayer;
+use tower_http::cors::{AllowOrigin, CorsLayer};
 use vrs::{
-    cleanup::CleanupConfig,
-    handlers,
-    jobs::{DynJobStore, LocalJobStore},
-    state::AppState,
-    storage::Storage,
+    VideoService,
+    cli::{self, Command, OutputFormat},
+    error::AppError,
+    jobs::JobStage,
+};
+use vrs::{
+    cleanup::CleanupConfig, config, handlers, http_client, jobs, migrations, policy, rate_limit,
+    sharding, state::AppState, storage::Storage, tags, transcode,
 };
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    setup_tracing();
+    let invocation = match cli::parse(env::args().skip(1)) {
+        Ok(invocation) => invocation,
+        Err(err) => {
+            eprint!("{err}\n\n{}", cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    let output = invocation.output;
+    match invocation.command {
+        Command::Help => {
+            print!("{}", cli::USAGE);
+            return Ok(());
+        }
+        Command::Completions(shell) => {
+            print!("{}", cli::completions(shell));
+            return Ok(());
+        }
+        _ => {}
+    }
+    // Commands print their result on stdout, so logs move aside.
+    setup_tracing(invocation.command != Command::Serve);
+    let result = run(invocation.command, output).await;
+    if output == OutputFormat::Json
+        && let Err(err) = &result
+    {
+        let body = match err.downcast_ref::<AppError>() {
+            Some(err) => err.to_json(),
+            None => serde_json::json!({ "error": err.to_string(), "code": "internal_error" }),
+        };
+        println!("{body}");
+        std::process::exit(1);
+    }
+    result
+}
 
-    let addr: SocketAddr = env::var("VIDEO_SERVER_ADDR")
-        .unwrap_or_else(|_| "0.0.0.0:3000".to_string())
+async fn run(command: Command, output: OutputFormat) -> Result<(), Box<dyn std::error::Error>> {
+    config::reload()?;
+
+    let addr: SocketAddr = config::var("VIDEO_SERVER_ADDR")
+        .unwrap_or_else(|| "0.0.0.0:3000".to_string())
         .parse()?;
-    let storage_root = env::var("VIDEO_STORAGE_DIR").unwrap_or_else(|_| "data".to_string());
+    let storage_root = config::var("VIDEO_STORAGE_DIR").unwrap_or_else(|| "data".to_string());
 
     let storage = Storage::initialize(&storage_root).await?;
-    let jobs: DynJobStore = Arc::new(LocalJobStore::new());
-    let http_client = reqwest::Client::builder().build()?;
+    if command == Command::Check {
+        return check_migrations(&storage, output).await;
+    }
+    migrations::run(&storage).await?;
+    let jobs = jobs::job_store_from_env().await?;
+    let http_client = http_client::build_http_client()?;
     let cleanup = CleanupConfig::from_env();
 
-    let state = AppState {
-        storage,
-        http_client,
-        jobs,
-        cleanup,
-    };
+    let body_limits = handlers::BodyLimits::from_env();
+
+    let mut state = AppState::new(storage, http_client, jobs, cleanup)
+        .with_max_upload_bytes(body_limits.max_upload);
+    if transcode::fake_transcode_enabled() {
+        tracing::warn!("VIDEO_FAKE_TRANSCODE is set; ffmpeg and ffprobe are simulated");
+        state = state.with_process_runner(Arc::new(transcode::SimulatedMediaRunner::from_env()));
+    }
+    if let Some(policy) = policy::policy_from_env()? {
+        state = state.with_policy(policy);
+    }
+    if let Command::Transcode { input } = command {
+        return transcode_once(state, &input, output).await;
+    }
+    spawn_reload_on_sighup(state.clone());
+    spawn_tag_retention(state.clone());
+    spawn_bandwidth_flush(state.clone());
+    spawn_alert_checks(state.clone());
+    spawn_sharded_dispatch(state.clone());
 
-    let cors = CorsLayer::permissive();
+    let cors = CorsLayer::permissive().allow_origin(AllowOrigin::predicate(cors_origin_allowed));
     let request_logger = RequestLoggerLayer;
+    let ingest_limit =
+        middleware::from_fn_with_state(state.rate_limits.clone(), rate_limit::limit_ingest);
 
     let app = Router::new()
-        .route("/healthz", get(health))
-        .route("/upload/multipart", post(handlers::upload_multipart))
-        .route("/upload/remote", post(handlers::upload_remote))
-        .route("/download/yt-dlp", post(handlers::download_via_ytdlp))
+        .route("/healthz", get(handlers::health))
+        .route(
+            "/upload/multipart",
+            post(handlers::upload_multipart)
+                .layer(body_limits.upload_layer())
+                .layer(ingest_limit.clone()),
+        )
+        .route(
+            "/upload/remote",
+            post(handlers::upload_remote).layer(ingest_limit.clone()),
+        )
+        .route(
+            "/upload/sessions",
+            post(handlers::create_upload_session).layer(ingest_limit.clone()),
+        )
+        .route(
+            "/upload/sessions/{id}/parts/{part}",
+            put(handlers::put_upload_part),
+        )
+        .route(
+            "/upload/sessions/{id}/complete",
+            post(handlers::complete_upload_session),
+        )
+        .route(
+            "/upload/tus",
+            post(handlers::tus_create)
+                .layer(ingest_limit.clone())
+                .layer(middleware::from_fn(handlers::tus_protocol)),
+        )
+        .route(
+            "/upload/tus/{id}",
+            head(handlers::tus_offset)
+                .patch(handlers::tus_append)
+                .layer(middleware::from_fn(handlers::tus_protocol)),
+        )
+        .route(
+            "/download/yt-dlp",
+            post(handlers::download_via_ytdlp).layer(ingest_limit.clone()),
+        )
+        .route(
+            "/download/yt-dlp/playlist",
+            post(handlers::download_playlist_via_ytdlp).layer(ingest_limit),
+        )
+        .route("/videos", get(handlers::list_videos))
         .route("/videos/{id}/download", get(handlers::download_video))
-        .route("/videos/{id}", get(handlers::download_video))
+        .route(
+            "/videos/{id}",
+            get(handlers::download_video).delete(handlers::delete_video),
+        )
+        .route(
+            "/videos/{id}/partial",
+            get(handlers::download_partial_video),
+        )
+        .route(
+            "/videos/{id}/password",
+            put(handlers::set_video_password).delete(handlers::remove_video_password),
+        )
+        .route("/videos/{id}/access", post(handlers::create_access_token))
+        .route(
+            "/videos/{id}/share",
+            post(handlers::create_share).get(handlers::list_shares),
+        )
+        .route(
+            "/videos/{id}/share/{share_id}",
+            delete(handlers::revoke_share),
+        )
+        .route("/s/{share_id}", get(handlers::share_page))
+        .route("/s/{share_id}/download", get(handlers::share_download))
+        .route("/s/{share_id}/hls/{*asset}", get(handlers::share_hls_asset))
+        .route(
+            "/s/{share_id}/dash/{*asset}",
+            get(handlers::share_dash_asset),
+        )
+        .route("/videos/{id}/info", get(handlers::get_video_info))
+        .route(
+            "/videos/{id}/thumbnail",
+            get(handlers::get_thumbnail).put(handlers::put_thumbnail),
+        )
+        .route("/videos/{id}/preview.webp", get(handlers::get_preview))
+        .route("/videos/{id}/proxy.mp4", get(handlers::get_proxy))
+        .route("/videos/{id}/source", get(handlers::get_source))
+        .route("/videos/{id}/captions", get(handlers::list_caption_tracks))
+        .route(
+            "/videos/{id}/captions/{track}",
+            get(handlers::get_caption_track),
+        )
+        .route(
+            "/videos/{id}/storyboard/{*asset}",
+            get(handlers::get_storyboard_asset),
+        )
+        .route(
+            "/videos/{id}/segments",
+            get(handlers::get_skip_segments).put(handlers::put_skip_segments),
+        )
+        .route(
+            "/videos/{id}/meta",
+            get(handlers::get_video_meta).patch(handlers::patch_video_meta),
+        )
+        .route(
+            "/videos/{id}/tags",
+            get(handlers::get_video_tags).post(handlers::add_video_tags),
+        )
+        .route(
+            "/videos/{id}/tags/{tag}",
+            delete(handlers::remove_video_tag),
+        )
+        .route("/tags", get(handlers::list_tags))
+        .route("/tags/{tag}/videos", get(handlers::list_tagged_videos))
+        .route(
+            "/collections",
+            post(handlers::create_collection).get(handlers::list_collections),
+        )
+        .route(
+            "/collections/{id}",
+            get(handlers::get_collection)
+                .patch(handlers::update_collection)
+                .delete(handlers::delete_collection),
+        )
+        .route(
+            "/collections/{id}/playlist.m3u8",
+            get(handlers::collection_playlist),
+        )
+        .route("/collections/{id}/embed", get(handlers::collection_embed))
         .route("/videos/{id}/hls/{*asset}", get(handlers::get_hls_asset))
+        .route("/videos/{id}/hls.zip", get(handlers::get_hls_archive))
         .route("/videos/{id}/dash/{*asset}", get(handlers::get_dash_asset))
+        .route("/jobs", get(handlers::list_jobs))
         .route("/jobs/{id}", get(handlers::job_status))
+        .route("/jobs/{id}/group", get(handlers::job_group_status))
+        .route("/jobs/{id}/ws", get(handlers::job_socket))
+        .route("/jobs/{id}/diagnostics", get(handlers::job_diagnostics))
+        .route(
+            "/jobs/{id}/diagnostics/frame.jpg",
+            get(handlers::job_diagnostics_frame),
+        )
+        .route("/jobs/{id}/cancel", post(handlers::cancel_job))
+        .route("/jobs/{id}/retry", post(handlers::retry_job))
+        .route("/jobs/{id}/approve", post(handlers::approve_job))
+        .route("/batches/{id}", get(handlers::batch_status))
+        .route("/usage", get(handlers::get_usage))
+        .route("/admin/overview", get(handlers::admin_overview))
+        .route("/admin/alerts", get(handlers::admin_alerts))
+        .route("/admin/reload", post(handlers::reload_config))
+        .route("/admin/bandwidth", get(handlers::bandwidth_rollup))
+        .route(
+            "/admin/tmp",
+            get(handlers::tmp_workspace).delete(handlers::clear_tmp_orphans),
+        )
+        .route("/admin/tmp/{*name}", delete(handlers::delete_tmp_item))
+        .route(
+            "/admin/replicate/{id}",
+            get(handlers::replication_status).post(handlers::replicate_video),
+        )
+        .route(
+            "/admin/import/{id}",
+            put(handlers::import_video).delete(handlers::delete_import),
+        )
+        .route("/admin/export/{id}", get(handlers::export_video))
+        .route("/admin/cookies", get(handlers::list_cookies))
+        .route(
+            "/admin/cookies/{name}",
+            put(handlers::put_cookies).delete(handlers::delete_cookies),
+        )
+        .route("/capabilities", get(handlers::capabilities))
+        .route("/metrics", get(handlers::metrics))
+        .route(
+            "/capabilities/failures",
+            delete(handlers::clear_capability_failures),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            handlers::federate,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            handlers::require_scope,
+        ))
         .with_state(state)
+        .layer(body_limits.json_layer())
         .layer(cors)
         .layer(request_logger);
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
     tracing::info!(%addr, "video server listening");
-    axum::serve(listener, app.into_make_service()).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
 
-async fn health() -> &'static str {
-    "ok"
+/// Checks `VIDEO_CORS_ORIGINS` (comma-separated, unset means any origin) on every
+/// request so reloads apply without rebuilding the router.
+fn cors_origin_allowed(origin: &HeaderValue, _parts: &request::Parts) -> bool {
+    let Some(allowed) = config::var("VIDEO_CORS_ORIGINS") else {
+        return true;
+    };
+    let Ok(origin) = origin.to_str() else {
+        return false;
+    };
+    allowed
+        .split(',')
+        .map(str::trim)
+        .any(|entry| entry == "*" || entry.eq_ignore_ascii_case(origin))
 }
 
-fn setup_tracing() {
+/// `--check`: reports pending storage migrations without applying them and
+/// exits non-zero if there are any, for use in deploy pipelines.
+async fn check_migrations(
+    storage: &Storage,
+    output: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let pending = migrations::pending(storage).await?;
+    if output == OutputFormat::Json {
+        let pending: Vec<_> = pending
+            .iter()
+            .map(|migration| {
+                serde_json::json!({
+                    "version": migration.version,
+                    "description": migration.description,
+                })
+            })
+            .collect();
+        println!(
+            "{}",
+            serde_json::json!({
+                "current_version": migrations::current_version(storage).await?,
+                "latest_version": migrations::latest_version(),
+                "pending": pending,
+            })
+        );
+        if pending.is_empty() {
+            return Ok(());
+        }
+        std::process::exit(1);
+    }
+    if pending.is_empty() {
+        println!(
+            "storage schema is up to date (version {})",
+            migrations::latest_version()
+        );
+        return Ok(());
+    }
+    for migration in pending {
+        println!(
+            "pending migration {}: {}",
+            migration.version, migration.description
+        );
+    }
+    std::process::exit(1);
+}
+
+/// `vrs transcode <file|->`: runs one source through the pipeline without
+/// serving HTTP and prints the outcome, or with `--output json` the final
+/// job status. `-` spools standard input into the incoming area, so other
+/// tools can pipe into it. Exits with status 1 unless the job completes.
+async fn transcode_once(
+    state: AppState,
+    input: &str,
+    output: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let service = VideoService::from_state(state);
+    let id = if input == "-" {
+        service
+            .ingest_reader(tokio::io::stdin(), Some("stdin"), None)
+            .await?
+    } else {
+        service.ingest_file(input, None).await?
+    };
+    let status = service.await_job(id).await?;
+    match output {
+        OutputFormat::Json => println!("{}", serde_json::to_string(&status)?),
+        OutputFormat::Text if status.stage == JobStage::Complete => {
+            println!("{id} complete");
+            println!("download: {}", service.download_path(id).display());
+        }
+        OutputFormat::Text => println!(
+            "{id} {}: {}",
+            serde_json::to_value(status.stage)?
+                .as_str()
+                .unwrap_or("failed"),
+            status.error.as_deref().unwrap_or("no error reported")
+        ),
+    }
+    if status.stage != JobStage::Complete {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Periodically deletes videos past their tag retention. The interval is read
+/// once; the retention rules themselves are re-read on every sweep.
+fn spawn_tag_retention(state: AppState) {
+    let interval = config::parse_var::<u64>("VIDEO_TAG_RETENTION_INTERVAL_SECS")
+        .filter(|&secs| secs > 0)
+        .unwrap_or(3600);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            match tags::enforce_retention(&state).await {
+                Ok(0) => {}
+                Ok(deleted) => tracing::info!(deleted, "tag retention sweep finished"),
+                Err(err) => tracing::warn!(error = %err, "tag retention sweep failed"),
+            }
+        }
+    });
+}
+
+fn spawn_bandwidth_flush(state: AppState) {
+    let interval = config::parse_var::<u64>("VIDEO_BANDWIDTH_FLUSH_SECS")
+        .filter(|&secs| secs > 0)
+        .unwrap_or(60);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            if let Err(err) = state.bandwidth.flush().await {
+                tracing::warn!(error = %err, "bandwidth flush failed");
+            }
+        }
+    });
+}
+
+/// Samples disk space for the `low_disk_space` alert; encode and yt-dlp
+/// alerts are raised as jobs finish.
+fn spawn_alert_checks(state: AppState) {
+    let interval = config::parse_var::<u64>("VIDEO_ALERT_CHECK_SECS")
+        .filter(|&secs| secs > 0)
+        .unwrap_or(60);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            state.alerts.check_disk(&state.storage).await;
+        }
+    });
+}
+
+/// Heartbeats this instance and starts the jobs it owns under
+/// `VIDEO_SHARDING`, including those of instances that went away.
+fn spawn_sharded_dispatch(state: AppState) {
+    let Some(config) = state.dispatcher.config() else {
+        return;
+    };
+    tracing::info!(
+        instance = config.instance_id,
+        "dispatching jobs among instances sharing the job store"
+    );
+    let interval = config.heartbeat_interval;
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match sharding::dispatch(&state).await {
+                Ok(0) => {}
+                Ok(started) => tracing::info!(started, "dispatched jobs to this instance"),
+                Err(err) => tracing::warn!(error = %err, "sharded dispatch failed"),
+            }
+        }
+    });
+}
+
+#[cfg(unix)]
+fn spawn_reload_on_sighup(state: AppState) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(stream) => stream,
+        Err(err) => {
+            tracing::warn!(error = %err, "failed to install SIGHUP handler");
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            match state.reload_config() {
+                Ok(report) => tracing::info!(
+                    applied = ?report.applied,
+                    requires_restart = ?report.requires_restart,
+                    "configuration reloaded on SIGHUP"
+                ),
+                Err(err) => tracing::error!(error = %err, "configuration reload failed"),
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn spawn_reload_on_sighup(_state: AppState) {}
+
+fn setup_tracing(to_stderr: bool) {
     if tracing::dispatcher::has_been_set() {
         return;
     }
@@ -85,6 +515,13 @@ fn setup_tracing() {
         .with_target(false)
         .with_level(true)
         .compact()
+        .with_writer(move || -> Box<dyn std::io::Write> {
+            if to_stderr {
+                Box::new(std::io::stderr())
+            } else {
+                Box::new(std::io::stdout())
+            }
+        })
         .try_init();
 
     if init_result.is_ok() {
@@ -0,0 +1,122 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    digest::SourceDigest,
+    error::AppError,
+    skip_segments::SkipSegment,
+    storage::{Storage, ensure_parent},
+    transcode::{LadderLimits, MezzanineCodec, OutputColor, PosterChoice, TranscodeProfile},
+};
+
+/// Per-video settings persisted next to the download so later packaging runs
+/// (e.g. regenerating HLS after cleanup) reproduce the original choices.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VideoMetadata {
+    #[serde(default)]
+    pub profile: TranscodeProfile,
+    /// Codec and container of the download, fixed when it was encoded.
+    #[serde(default)]
+    pub mezzanine: MezzanineCodec,
+    /// Bounds on the ladder requested at ingest.
+    #[serde(default, skip_serializing_if = "LadderLimits::is_default")]
+    pub ladder: LadderLimits,
+    /// Bit depth and colour tags of the encodes, taken from the source.
+    #[serde(default, skip_serializing_if = "OutputColor::is_default")]
+    pub color: OutputColor,
+    /// The source had no video stream; packaging carries audio only.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub audio_only: bool,
+    /// Argon2 PHC hash; when set, delivery routes require the password.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<String>,
+    /// Normalised labels used for filtering and tag-scoped policies.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+    /// Free-form integrator data such as correlation ids, set via
+    /// `PATCH /videos/{id}/meta`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, serde_json::Value>,
+    /// Caller key the video's ingest, encode and storage are charged to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    /// Extra `x-` headers added to every delivery response of the video.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub response_headers: BTreeMap<String, String>,
+    /// Set by the optional `vmaf` stage.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vmaf_score: Option<f64>,
+    /// Size, checksum and container of the ingested source.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<SourceDigest>,
+    /// What the platform reported for a video ingested with yt-dlp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub imported: Option<ImportedDetails>,
+    /// How `thumbnail.jpg` was picked, when a client chose it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poster: Option<PosterChoice>,
+    /// Ranges players may skip, sorted by start.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skip_segments: Vec<SkipSegment>,
+    /// Set once a job that waited for approval was approved, so retries
+    /// go straight to the full encode.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub approved: bool,
+    /// Set when a corrupt tail was cut off the source to save the encode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub truncated: Option<Truncation>,
+}
+
+/// Where a `salvage` encode cut off a source whose tail would not decode.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Truncation {
+    /// Length of the part that was published.
+    pub duration_seconds: f64,
+    /// Length the source's container claimed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_duration_seconds: Option<f64>,
+    /// Why the encode of the whole source failed.
+    pub error: String,
+}
+
+/// Title, description and other details yt-dlp read from the source page.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ImportedDetails {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uploader: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webpage_url: Option<String>,
+    /// `YYYYMMDD`, as yt-dlp reports it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_date: Option<String>,
+    /// `thumbnail.jpg` is the platform's thumbnail, which the `thumbnails`
+    /// stage leaves in place.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub poster: bool,
+}
+
+/// Loads the stored metadata, falling back to defaults for videos that predate it.
+pub async fn load(storage: &Storage, id: &Uuid) -> Result<VideoMetadata, AppError> {
+    match fs::read(storage.metadata_path(id)).await {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes).map_err(std::io::Error::from)?),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(VideoMetadata::default()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub async fn save(storage: &Storage, id: &Uuid, metadata: &VideoMetadata) -> Result<(), AppError> {
+    let path = storage.metadata_path(id);
+    ensure_parent(&path).await?;
+    let bytes = serde_json::to_vec_pretty(metadata).map_err(std::io::Error::from)?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, bytes).await?;
+    fs::rename(&tmp, &path).await?;
+    Ok(())
+}
@@ -0,0 +1,224 @@
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex};
+
+/// Process-wide counters, gauges and histograms, rendered in the Prometheus text
+/// format by `GET /metrics`.
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry::new());
+
+/// `x realtime` factors for encode speed.
+pub const SPEED_BUCKETS: &[f64] = &[0.1, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0];
+pub const DURATION_BUCKETS: &[f64] = &[1.0, 5.0, 15.0, 60.0, 300.0, 900.0, 3600.0, 14400.0];
+
+/// Help text for every metric that may be recorded, in output order.
+const DESCRIPTIONS: &[(&str, &str)] = &[
+    ("vrs_ffmpeg_spawns_total", "ffmpeg processes started."),
+    (
+        "vrs_ffmpeg_failures_total",
+        "ffmpeg runs that failed to start or exited unsuccessfully.",
+    ),
+    (
+        "vrs_ffmpeg_watchdog_kills_total",
+        "ffmpeg processes killed after producing no output for VIDEO_FFMPEG_STALL_TIMEOUT_SECS.",
+    ),
+    (
+        "vrs_ffmpeg_duration_seconds",
+        "Wall-clock time of successful ffmpeg runs.",
+    ),
+    (
+        "vrs_ffmpeg_encode_speed",
+        "Final speed of successful ffmpeg runs as a multiple of realtime.",
+    ),
+    (
+        "vrs_delivery_bytes_total",
+        "Video bytes sent to clients, by delivery kind.",
+    ),
+    (
+        "vrs_transcode_waiting",
+        "Jobs waiting for a transcode slot, by whether they are past VIDEO_SCHED_BOOST_PROGRESS.",
+    ),
+    (
+        "vrs_transcode_boosted_grants_total",
+        "Transcode slots granted to nearly done jobs ahead of newer ones.",
+    ),
+];
+
+type Labels = Vec<(&'static str, String)>;
+
+struct Registry {
+    counters: BTreeMap<&'static str, BTreeMap<Labels, u64>>,
+    gauges: BTreeMap<&'static str, BTreeMap<Labels, u64>>,
+    histograms: BTreeMap<&'static str, BTreeMap<Labels, Histogram>>,
+}
+
+struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Registry {
+    const fn new() -> Self {
+        Self {
+            counters: BTreeMap::new(),
+            gauges: BTreeMap::new(),
+            histograms: BTreeMap::new(),
+        }
+    }
+}
+
+fn owned(labels: &[(&'static str, &str)]) -> Labels {
+    labels
+        .iter()
+        .map(|(key, value)| (*key, value.to_string()))
+        .collect()
+}
+
+pub fn increment(name: &'static str, labels: &[(&'static str, &str)]) {
+    add(name, labels, 1);
+}
+
+pub fn add(name: &'static str, labels: &[(&'static str, &str)], value: u64) {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|p| p.into_inner());
+    let counter = registry
+        .counters
+        .entry(name)
+        .or_default()
+        .entry(owned(labels))
+        .or_default();
+    *counter = counter.saturating_add(value);
+}
+
+/// Sets the gauge `name` to `value`.
+pub fn set(name: &'static str, labels: &[(&'static str, &str)], value: u64) {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|p| p.into_inner());
+    registry
+        .gauges
+        .entry(name)
+        .or_default()
+        .insert(owned(labels), value);
+}
+
+/// Records `value` in the histogram `name`. `bounds` are the bucket upper
+/// bounds and must be the same on every call for a given name.
+pub fn observe(
+    name: &'static str,
+    labels: &[(&'static str, &str)],
+    bounds: &'static [f64],
+    value: f64,
+) {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|p| p.into_inner());
+    let histogram = registry
+        .histograms
+        .entry(name)
+        .or_default()
+        .entry(owned(labels))
+        .or_insert_with(|| Histogram {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        });
+    for (bound, count) in histogram.bounds.iter().zip(histogram.counts.iter_mut()) {
+        if value <= *bound {
+            *count += 1;
+        }
+    }
+    histogram.sum += value;
+    histogram.count += 1;
+}
+
+/// Current value of a counter, mainly for tests.
+pub fn counter(name: &str, labels: &[(&'static str, &str)]) -> u64 {
+    let registry = REGISTRY.lock().unwrap_or_else(|p| p.into_inner());
+    registry
+        .counters
+        .get(name)
+        .and_then(|series| series.get(&owned(labels)))
+        .copied()
+        .unwrap_or(0)
+}
+
+/// Current value of a gauge, mainly for tests.
+pub fn gauge(name: &str, labels: &[(&'static str, &str)]) -> u64 {
+    let registry = REGISTRY.lock().unwrap_or_else(|p| p.into_inner());
+    registry
+        .gauges
+        .get(name)
+        .and_then(|series| series.get(&owned(labels)))
+        .copied()
+        .unwrap_or(0)
+}
+
+/// Everything recorded so far in the Prometheus text exposition format.
+pub fn render() -> String {
+    let registry = REGISTRY.lock().unwrap_or_else(|p| p.into_inner());
+    let mut out = String::new();
+    for (name, help) in DESCRIPTIONS {
+        if let Some(series) = registry.counters.get(name) {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter");
+            for (labels, value) in series {
+                let _ = writeln!(out, "{name}{} {value}", format_labels(labels, None));
+            }
+        }
+        if let Some(series) = registry.gauges.get(name) {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} gauge");
+            for (labels, value) in series {
+                let _ = writeln!(out, "{name}{} {value}", format_labels(labels, None));
+            }
+        }
+        if let Some(series) = registry.histograms.get(name) {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} histogram");
+            for (labels, histogram) in series {
+                for (bound, count) in histogram.bounds.iter().zip(&histogram.counts) {
+                    let le = bound.to_string();
+                    let _ = writeln!(
+                        out,
+                        "{name}_bucket{} {count}",
+                        format_labels(labels, Some(&le))
+                    );
+                }
+                let _ = writeln!(
+                    out,
+                    "{name}_bucket{} {}",
+                    format_labels(labels, Some("+Inf")),
+                    histogram.count
+                );
+                let _ = writeln!(
+                    out,
+                    "{name}_sum{} {}",
+                    format_labels(labels, None),
+                    histogram.sum
+                );
+                let _ = writeln!(
+                    out,
+                    "{name}_count{} {}",
+                    format_labels(labels, None),
+                    histogram.count
+                );
+            }
+        }
+    }
+    out
+}
+
+fn format_labels(labels: &Labels, le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = labels
+        .iter()
+        .map(|(key, value)| format!("{key}=\"{}\"", escape(value)))
+        .collect();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{le}\""));
+    }
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,113 @@
+use std::{
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::{
+    error::AppError,
+    storage::{Storage, ensure_dir},
+};
+
+type MigrationFuture<'a> = Pin<Box<dyn Future<Output = Result<(), AppError>> + Send + 'a>>;
+
+/// One step of the on-disk layout under the storage root. Steps must be
+/// idempotent: a crash between applying a step and recording it re-runs it.
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    apply: for<'a> fn(&'a Storage) -> MigrationFuture<'a>,
+}
+
+/// Embedded migrations in version order. Append only; never renumber.
+pub const MIGRATIONS: &[Migration] = &[Migration {
+    version: 1,
+    description: "create share and collection stores",
+    apply: create_store_dirs,
+}];
+
+fn create_store_dirs(storage: &Storage) -> MigrationFuture<'_> {
+    Box::pin(async move {
+        ensure_dir(&storage.root_dir().join("shares")).await?;
+        ensure_dir(&storage.root_dir().join("collections")).await
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SchemaVersion {
+    version: u32,
+    updated_at_unix_ms: u64,
+}
+
+/// Highest version this build knows how to produce.
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+/// Version recorded in the storage root; 0 for roots that predate migrations.
+pub async fn current_version(storage: &Storage) -> Result<u32, AppError> {
+    match fs::read(version_path(storage)).await {
+        Ok(bytes) => {
+            let recorded: SchemaVersion =
+                serde_json::from_slice(&bytes).map_err(std::io::Error::from)?;
+            Ok(recorded.version)
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Migrations not yet applied. Fails when the storage root was written by a
+/// newer build, since running an older one against it could corrupt data.
+pub async fn pending(storage: &Storage) -> Result<Vec<&'static Migration>, AppError> {
+    let current = current_version(storage).await?;
+    let latest = latest_version();
+    if current > latest {
+        return Err(AppError::validation(format!(
+            "storage schema version {current} is newer than this build supports ({latest})"
+        )));
+    }
+    Ok(MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > current)
+        .collect())
+}
+
+/// Applies pending migrations in order, recording the version after each.
+pub async fn run(storage: &Storage) -> Result<Vec<&'static Migration>, AppError> {
+    let pending = pending(storage).await?;
+    for migration in &pending {
+        tracing::info!(
+            version = migration.version,
+            description = migration.description,
+            "applying storage migration"
+        );
+        (migration.apply)(storage).await?;
+        record_version(storage, migration.version).await?;
+    }
+    Ok(pending)
+}
+
+fn version_path(storage: &Storage) -> PathBuf {
+    storage.root_dir().join("schema_version.json")
+}
+
+async fn record_version(storage: &Storage, version: u32) -> Result<(), AppError> {
+    let recorded = SchemaVersion {
+        version,
+        updated_at_unix_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64,
+    };
+    let path = version_path(storage);
+    let bytes = serde_json::to_vec_pretty(&recorded).map_err(std::io::Error::from)?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, bytes).await?;
+    fs::rename(&tmp, &path).await?;
+    Ok(())
+}
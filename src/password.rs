@@ -0,0 +1,157 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier, password_hash::SaltString};
+use uuid::Uuid;
+
+use crate::{blocking, config, error::AppError};
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_LOCKOUT: Duration = Duration::from_secs(300);
+const MAX_PASSWORD_BYTES: usize = 1024;
+/// Past this many tracked windows, the ones that have expired are dropped.
+const MAX_TRACKED_WINDOWS: usize = 10_000;
+
+/// Header carrying the password for a protected video. Players that cannot
+/// set headers use an access token from `POST /videos/{id}/access` instead.
+pub const PASSWORD_HEADER: &str = "x-video-password";
+
+/// Hashes `password` into a PHC string (Argon2id) suitable for `meta.json`.
+pub fn hash_password(password: &str) -> Result<String, AppError> {
+    if password.is_empty() {
+        return Err(AppError::validation("password must not be empty"));
+    }
+    if password.len() > MAX_PASSWORD_BYTES {
+        return Err(AppError::validation("password is too long"));
+    }
+    let salt = SaltString::encode_b64(Uuid::new_v4().as_bytes())
+        .map_err(|err| AppError::validation(format!("failed to salt password: {err}")))?;
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|err| AppError::validation(format!("failed to hash password: {err}")))
+}
+
+/// Checks `candidate` against a stored PHC string. Malformed hashes never match.
+pub fn verify_password(hash: &str, candidate: &str) -> bool {
+    let Ok(parsed) = PasswordHash::new(hash) else {
+        tracing::warn!("stored video password hash is malformed");
+        return false;
+    };
+    Argon2::default()
+        .verify_password(candidate.as_bytes(), &parsed)
+        .is_ok()
+}
+
+/// Counts failed password attempts per video and client address. Once
+/// `VIDEO_PASSWORD_MAX_ATTEMPTS` failures land within
+/// `VIDEO_PASSWORD_LOCKOUT_SECS`, further attempts from that address are
+/// refused until that window has passed; other viewers are not affected.
+/// Requests without a known address share one window per video.
+#[derive(Clone, Default)]
+pub struct PasswordAttempts {
+    failures: Arc<Mutex<HashMap<AttemptKey, FailureWindow>>>,
+}
+
+/// A video and the address of the client guessing at it.
+type AttemptKey = (Uuid, Option<IpAddr>);
+
+#[derive(Debug, Clone, Copy)]
+struct FailureWindow {
+    started: Instant,
+    count: u32,
+}
+
+impl PasswordAttempts {
+    /// Refuses the attempt if `client` is currently locked out of `video_id`.
+    pub fn check(&self, video_id: &Uuid, client: Option<IpAddr>) -> Result<(), AppError> {
+        let (max_attempts, lockout) = limits();
+        let key = (*video_id, client);
+        let mut failures = self.failures.lock().unwrap_or_else(|p| p.into_inner());
+        match failures.get(&key) {
+            Some(window) if window.started.elapsed() >= lockout => {
+                failures.remove(&key);
+                Ok(())
+            }
+            Some(window) if window.count >= max_attempts => {
+                let retry_after = lockout.saturating_sub(window.started.elapsed());
+                Err(AppError::rate_limited(format!(
+                    "too many password attempts; retry in {}s",
+                    retry_after.as_secs().max(1)
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn record_failure(&self, video_id: &Uuid, client: Option<IpAddr>) {
+        let (_, lockout) = limits();
+        let mut failures = self.failures.lock().unwrap_or_else(|p| p.into_inner());
+        let now = Instant::now();
+        if failures.len() >= MAX_TRACKED_WINDOWS {
+            failures.retain(|_, window| now.duration_since(window.started) < lockout);
+        }
+        let window = failures
+            .entry((*video_id, client))
+            .or_insert(FailureWindow {
+                started: now,
+                count: 0,
+            });
+        if now.duration_since(window.started) >= lockout {
+            *window = FailureWindow {
+                started: now,
+                count: 0,
+            };
+        }
+        window.count += 1;
+    }
+
+    pub fn reset(&self, video_id: &Uuid, client: Option<IpAddr>) {
+        self.failures
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .remove(&(*video_id, client));
+    }
+
+    /// Verifies `presented` against `hash`, applying the lockout. Argon2 runs
+    /// on the blocking pool.
+    pub async fn verify(
+        &self,
+        video_id: &Uuid,
+        client: Option<IpAddr>,
+        hash: &str,
+        presented: Option<&str>,
+    ) -> Result<(), AppError> {
+        let presented = presented
+            .filter(|value| !value.is_empty())
+            .ok_or_else(|| AppError::forbidden("video password required"))?;
+        self.check(video_id, client)?;
+
+        let hash = hash.to_string();
+        let candidate = presented.to_string();
+        let matches = blocking::run(move || verify_password(&hash, &candidate))
+            .await
+            .map_err(|err| AppError::forbidden(format!("password check failed: {err}")))?;
+        if matches {
+            self.reset(video_id, client);
+            Ok(())
+        } else {
+            self.record_failure(video_id, client);
+            Err(AppError::forbidden("incorrect video password"))
+        }
+    }
+}
+
+fn limits() -> (u32, Duration) {
+    let max_attempts = config::parse_var::<u32>("VIDEO_PASSWORD_MAX_ATTEMPTS")
+        .filter(|value| *value > 0)
+        .unwrap_or(DEFAULT_MAX_ATTEMPTS);
+    let lockout = config::parse_var::<u64>("VIDEO_PASSWORD_LOCKOUT_SECS")
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_LOCKOUT);
+    (max_attempts, lockout)
+}
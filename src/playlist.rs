@@ -0,0 +1,412 @@
+use std::str::FromStr;
+
+use url::Url;
+
+use crate::error::AppError;
+
+/// Video codec families recognised in HLS `CODECS` attributes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodecFamily {
+    H264,
+    Hevc,
+    Av1,
+    Vp9,
+}
+
+impl CodecFamily {
+    fn matches(&self, codec: &str) -> bool {
+        let prefixes: &[&str] = match self {
+            CodecFamily::H264 => &["avc1", "avc3"],
+            CodecFamily::Hevc => &["hvc1", "hev1"],
+            CodecFamily::Av1 => &["av01"],
+            CodecFamily::Vp9 => &["vp09"],
+        };
+        prefixes.iter().any(|prefix| codec.starts_with(prefix))
+    }
+}
+
+impl FromStr for CodecFamily {
+    type Err = AppError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "h264" | "avc" | "avc1" => Ok(CodecFamily::H264),
+            "hevc" | "h265" | "hvc1" => Ok(CodecFamily::Hevc),
+            "av1" | "av01" => Ok(CodecFamily::Av1),
+            "vp9" | "vp09" => Ok(CodecFamily::Vp9),
+            other => Err(AppError::validation(format!(
+                "unknown codec filter: {other}"
+            ))),
+        }
+    }
+}
+
+/// Constraints applied to a master playlist before it is served.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VariantFilter {
+    pub max_height: Option<u32>,
+    pub codecs: Vec<CodecFamily>,
+}
+
+impl VariantFilter {
+    /// Builds a filter from the `max_height` and comma-separated `codecs` query
+    /// values; `codecs=all` keeps every codec.
+    pub fn from_query(max_height: Option<u32>, codecs: Option<&str>) -> Result<Self, AppError> {
+        let codecs = codecs
+            .map(|value| {
+                value
+                    .split(',')
+                    .filter(|entry| {
+                        let entry = entry.trim();
+                        !entry.is_empty() && !entry.eq_ignore_ascii_case("all")
+                    })
+                    .map(CodecFamily::from_str)
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?
+            .unwrap_or_default();
+        Ok(Self { max_height, codecs })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.max_height.is_none() && self.codecs.is_empty()
+    }
+
+    fn accepts(&self, attributes: &str, fallback_codec: Option<CodecFamily>) -> bool {
+        let attributes = parse_attributes(attributes);
+        let lookup = |name: &str| {
+            attributes
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        if let Some(max_height) = self.max_height {
+            let height = lookup("RESOLUTION")
+                .and_then(|resolution| resolution.split_once('x'))
+                .and_then(|(_, height)| height.parse::<u32>().ok());
+            if height.is_some_and(|height| height > max_height) {
+                return false;
+            }
+        }
+
+        if !self.codecs.is_empty() {
+            let accepted = match lookup("CODECS") {
+                Some(codecs) => codecs.split(',').any(|codec| {
+                    self.codecs
+                        .iter()
+                        .any(|family| family.matches(codec.trim()))
+                }),
+                None => fallback_codec.is_some_and(|family| self.codecs.contains(&family)),
+            };
+            if !accepted {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Drops `EXT-X-STREAM-INF` and `EXT-X-I-FRAME-STREAM-INF` entries rejected by
+/// `filter`. `fallback_codec` is assumed for variants without a `CODECS` attribute.
+pub fn filter_master_playlist(
+    playlist: &str,
+    filter: &VariantFilter,
+    fallback_codec: Option<CodecFamily>,
+) -> Result<String, AppError> {
+    let mut output = String::with_capacity(playlist.len());
+    let mut kept = 0usize;
+    let mut skip_uri = false;
+
+    for line in playlist.lines() {
+        let trimmed = line.trim();
+        if let Some(attributes) = trimmed.strip_prefix("#EXT-X-STREAM-INF:") {
+            skip_uri = !filter.accepts(attributes, fallback_codec);
+            if skip_uri {
+                continue;
+            }
+            kept += 1;
+        } else if let Some(attributes) = trimmed.strip_prefix("#EXT-X-I-FRAME-STREAM-INF:") {
+            if !filter.accepts(attributes, fallback_codec) {
+                continue;
+            }
+        } else if skip_uri && !trimmed.is_empty() && !trimmed.starts_with('#') {
+            skip_uri = false;
+            continue;
+        }
+
+        output.push_str(line);
+        output.push('\n');
+    }
+
+    if kept == 0 {
+        return Err(AppError::not_found(
+            "no HLS variants match the requested filter",
+        ));
+    }
+
+    Ok(output)
+}
+
+/// Splits an HLS attribute list, honouring quoted values that contain commas.
+pub fn parse_attributes(list: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = list.trim();
+
+    while !rest.is_empty() {
+        let Some((key, after_key)) = rest.split_once('=') else {
+            break;
+        };
+        let (value, remaining) = if let Some(quoted) = after_key.strip_prefix('"') {
+            match quoted.split_once('"') {
+                Some((value, remaining)) => (value, remaining),
+                None => (quoted, ""),
+            }
+        } else {
+            match after_key.split_once(',') {
+                Some((value, remaining)) => (value, remaining),
+                None => (after_key, ""),
+            }
+        };
+        attributes.push((key.trim().to_string(), value.to_string()));
+        rest = remaining.trim_start_matches(',').trim_start();
+    }
+
+    attributes
+}
+
+/// Appends `query` to every URI in an HLS playlist: plain URI lines as well as
+/// `URI="..."` attributes (`EXT-X-MAP`, `EXT-X-MEDIA`, `EXT-X-I-FRAME-STREAM-INF`).
+pub fn append_query_to_playlist(playlist: &str, query: &str) -> String {
+    map_playlist_uris(playlist, |uri| with_query(uri, query))
+}
+
+/// Resolves every URI in an HLS playlist against `url`, the playlist's own
+/// absolute URL, and appends `query` to each, so the playlist keeps working
+/// once it is saved somewhere else.
+pub fn absolutize_playlist(playlist: &str, url: &Url, query: Option<&str>) -> String {
+    map_playlist_uris(playlist, |uri| {
+        let absolute = url
+            .join(uri)
+            .map(String::from)
+            .unwrap_or_else(|_| uri.to_string());
+        match query {
+            Some(query) => with_query(&absolute, query),
+            None => absolute,
+        }
+    })
+}
+
+/// Adds an `EXT-X-SESSION-DATA` tag carrying `value` under `data_id` to a
+/// master playlist, right after its `#EXTM3U` header.
+pub fn add_session_data(master: &str, data_id: &str, value: &str) -> String {
+    let tag = format!("#EXT-X-SESSION-DATA:DATA-ID=\"{data_id}\",VALUE=\"{value}\"");
+    match master.split_once('\n') {
+        Some((header, rest)) if header.trim() == "#EXTM3U" => format!("{header}\n{tag}\n{rest}"),
+        _ => format!("#EXTM3U\n{tag}\n{master}"),
+    }
+}
+
+/// Applies `map` to every URI line and `URI="..."` attribute of a playlist.
+fn map_playlist_uris(playlist: &str, map: impl Fn(&str) -> String) -> String {
+    let mut output = String::with_capacity(playlist.len() * 2);
+    for line in playlist.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            output.push_str(line);
+        } else if trimmed.starts_with('#') {
+            output.push_str(&rewrite_quoted_attribute(line, "URI", &map));
+        } else {
+            output.push_str(&map(trimmed));
+        }
+        output.push('\n');
+    }
+    output
+}
+
+/// Returns the URI of the tallest variant in a master playlist, skipping
+/// variants taller than `max_height`. Variants without a `RESOLUTION` count
+/// as zero pixels tall.
+pub fn select_variant(master: &str, max_height: Option<u32>) -> Option<&str> {
+    let mut best: Option<(u32, &str)> = None;
+    let mut pending_height: Option<u32> = None;
+    for line in master.lines().map(str::trim) {
+        if let Some(attributes) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+            pending_height = Some(
+                parse_attributes(attributes)
+                    .into_iter()
+                    .find(|(key, _)| key == "RESOLUTION")
+                    .and_then(|(_, value)| {
+                        value
+                            .split_once('x')
+                            .and_then(|(_, height)| height.parse().ok())
+                    })
+                    .unwrap_or(0),
+            );
+        } else if !line.is_empty()
+            && !line.starts_with('#')
+            && let Some(height) = pending_height.take()
+            && max_height.is_none_or(|max| height <= max)
+            && best.is_none_or(|(current, _)| height > current)
+        {
+            best = Some((height, line));
+        }
+    }
+    best.map(|(_, uri)| uri)
+}
+
+/// Joins media playlists into one VOD playlist, with `EXT-X-DISCONTINUITY`
+/// between parts. Relative URIs in each part are resolved against its base,
+/// e.g. `/videos/{id}/hls/`.
+pub fn concat_media_playlists<'a>(parts: impl IntoIterator<Item = (&'a str, &'a str)>) -> String {
+    const PER_PLAYLIST_TAGS: [&str; 7] = [
+        "#EXTM3U",
+        "#EXT-X-VERSION",
+        "#EXT-X-TARGETDURATION",
+        "#EXT-X-MEDIA-SEQUENCE",
+        "#EXT-X-PLAYLIST-TYPE",
+        "#EXT-X-INDEPENDENT-SEGMENTS",
+        "#EXT-X-ENDLIST",
+    ];
+
+    let mut body = String::new();
+    let mut target_duration = 1u32;
+    for (index, (base, playlist)) in parts.into_iter().enumerate() {
+        if index > 0 {
+            body.push_str("#EXT-X-DISCONTINUITY\n");
+        }
+        let resolved = map_playlist_uris(playlist, |uri| {
+            if uri.starts_with('/') || uri.contains("://") {
+                uri.to_string()
+            } else {
+                format!("{base}{uri}")
+            }
+        });
+        for line in resolved.lines() {
+            let trimmed = line.trim();
+            if let Some(value) = trimmed.strip_prefix("#EXT-X-TARGETDURATION:") {
+                target_duration = target_duration.max(value.trim().parse().unwrap_or(0));
+            }
+            if trimmed.is_empty() || PER_PLAYLIST_TAGS.iter().any(|tag| trimmed.starts_with(tag)) {
+                continue;
+            }
+            body.push_str(trimmed);
+            body.push('\n');
+        }
+    }
+
+    format!(
+        "#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-TARGETDURATION:{target_duration}\n\
+         #EXT-X-MEDIA-SEQUENCE:0\n#EXT-X-PLAYLIST-TYPE:VOD\n#EXT-X-INDEPENDENT-SEGMENTS\n\
+         {body}#EXT-X-ENDLIST\n"
+    )
+}
+
+/// Appends `query` to the segment addressing attributes of a DASH manifest.
+pub fn append_query_to_mpd(manifest: &str, query: &str) -> String {
+    ["media", "initialization", "sourceURL"]
+        .iter()
+        .fold(manifest.to_string(), |text, name| {
+            rewrite_quoted_attribute(&text, name, &|uri| with_query(uri, query))
+        })
+}
+
+fn rewrite_quoted_attribute(text: &str, name: &str, map: &dyn Fn(&str) -> String) -> String {
+    let needle = format!("{name}=\"");
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(pos) = rest.find(&needle) {
+        let preceded_by_word = rest[..pos]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '-');
+        let value_start = pos + needle.len();
+        let Some(len) = rest[value_start..].find('"') else {
+            break;
+        };
+        output.push_str(&rest[..value_start]);
+        let value = &rest[value_start..value_start + len];
+        if preceded_by_word {
+            output.push_str(value);
+        } else {
+            output.push_str(&map(value));
+        }
+        rest = &rest[value_start + len..];
+    }
+    output.push_str(rest);
+    output
+}
+
+fn with_query(uri: &str, query: &str) -> String {
+    let separator = if uri.contains('?') { '&' } else { '?' };
+    format!("{uri}{separator}{query}")
+}
+
+/// Guesses which codec families a player decodes from its `Accept` and
+/// `User-Agent` headers. An explicit RFC 6381 `codecs` parameter in `Accept`
+/// wins; otherwise known browsers and native players map to conservative sets.
+/// Returns `None` when the client is unknown and the playlist should not be narrowed.
+pub fn negotiate_codecs(
+    user_agent: Option<&str>,
+    accept: Option<&str>,
+) -> Option<Vec<CodecFamily>> {
+    if let Some(codecs) = accept.and_then(accept_codecs) {
+        return Some(codecs);
+    }
+
+    let ua = user_agent?;
+    let legacy = vec![CodecFamily::H264];
+    let apple = vec![CodecFamily::H264, CodecFamily::Hevc];
+    let modern = vec![CodecFamily::Av1, CodecFamily::Vp9, CodecFamily::H264];
+
+    if ua.starts_with("AppleCoreMedia") {
+        return Some(apple);
+    }
+    if ua.contains("Tizen") || ua.contains("Web0S") || ua.contains("SMART-TV") {
+        return Some(apple);
+    }
+    if let Some(version) = product_major(ua, "Firefox/") {
+        return Some(if version >= 67 { modern } else { legacy });
+    }
+    if let Some(version) = product_major(ua, "Chrome/") {
+        return Some(if version >= 70 { modern } else { legacy });
+    }
+    if ua.contains("AppleWebKit") && ua.contains("Safari/") {
+        // Every browser on iOS and Safari on macOS share WebKit's decoder support.
+        return Some(apple);
+    }
+    if ua.contains("ExoPlayer") {
+        return Some(vec![CodecFamily::H264, CodecFamily::Hevc, CodecFamily::Vp9]);
+    }
+    None
+}
+
+fn accept_codecs(accept: &str) -> Option<Vec<CodecFamily>> {
+    let (_, rest) = accept.split_once("codecs=")?;
+    let value = rest.trim_start_matches('"');
+    let value = value.split(['"', ';']).next().unwrap_or_default();
+    let families: Vec<CodecFamily> = value
+        .split(',')
+        .filter_map(|codec| {
+            let codec = codec.trim();
+            [
+                CodecFamily::H264,
+                CodecFamily::Hevc,
+                CodecFamily::Av1,
+                CodecFamily::Vp9,
+            ]
+            .into_iter()
+            .find(|family| family.matches(codec))
+        })
+        .collect();
+    (!families.is_empty()).then_some(families)
+}
+
+fn product_major(ua: &str, product: &str) -> Option<u32> {
+    let (_, rest) = ua.split_once(product)?;
+    rest.split(|c: char| !c.is_ascii_digit())
+        .next()
+        .and_then(|major| major.parse().ok())
+}
@@ -0,0 +1,208 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    config,
+    error::AppError,
+    handlers::ClientTranscodeOptions,
+    transcode::{EncodeParams, SourceProbe, TranscodeProfile},
+};
+
+/// What an ingest policy is asked to judge, serialized as JSON for plugins.
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyRequest {
+    pub video_id: Uuid,
+    /// URL or upload file name supplied by the client, when known.
+    pub source: Option<String>,
+    pub probe: SourceProbe,
+    pub transcode: PolicyTranscode,
+}
+
+/// The encode settings the job would run with if the policy changes nothing.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct PolicyTranscode {
+    pub crf: u8,
+    pub cpu_used: u8,
+    pub profile: TranscodeProfile,
+}
+
+impl From<EncodeParams> for PolicyTranscode {
+    fn from(params: EncodeParams) -> Self {
+        Self {
+            crf: params.crf,
+            cpu_used: params.cpu_used,
+            profile: params.profile,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Accept,
+    Reject,
+}
+
+/// A policy's answer. `transcode` overrides individual encode settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyDecision {
+    pub decision: Verdict,
+    #[serde(default)]
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub transcode: Option<ClientTranscodeOptions>,
+}
+
+impl PolicyDecision {
+    pub fn accept() -> Self {
+        Self {
+            decision: Verdict::Accept,
+            reason: None,
+            transcode: None,
+        }
+    }
+
+    /// Turns a rejection into a validation error, otherwise merges the
+    /// overrides into `params`.
+    pub fn apply(self, mut params: EncodeParams) -> Result<EncodeParams, AppError> {
+        if self.decision == Verdict::Reject {
+            let reason = self.reason.unwrap_or_else(|| "no reason given".into());
+            return Err(AppError::validation(format!(
+                "rejected by ingest policy: {reason}"
+            )));
+        }
+        if let Some(overrides) = self.transcode {
+            if let Some(crf) = overrides.crf {
+                params.crf = crf;
+            }
+            if let Some(cpu_used) = overrides.cpu_used {
+                params.cpu_used = cpu_used;
+            }
+            if let Some(profile) = overrides.profile {
+                params.profile = profile;
+            }
+        }
+        Ok(params.sanitized())
+    }
+}
+
+/// Decides per ingest whether to accept a source and with which settings.
+/// `evaluate` is synchronous and may be CPU-bound; the pipeline calls it on
+/// the blocking pool.
+pub trait IngestPolicy: Send + Sync {
+    fn evaluate(&self, request: &PolicyRequest) -> Result<PolicyDecision, AppError>;
+}
+
+pub type DynIngestPolicy = Arc<dyn IngestPolicy>;
+
+/// Loads the policy named by `VIDEO_POLICY_WASM`, if any. Builds without the
+/// `wasm-policy` feature ignore the setting with a warning.
+pub fn policy_from_env() -> Result<Option<DynIngestPolicy>, AppError> {
+    let Some(path) = config::var("VIDEO_POLICY_WASM").filter(|path| !path.is_empty()) else {
+        return Ok(None);
+    };
+
+    #[cfg(feature = "wasm-policy")]
+    {
+        let policy = WasmPolicy::from_file(&path)?;
+        tracing::info!(path, "loaded WASM ingest policy");
+        Ok(Some(Arc::new(policy)))
+    }
+
+    #[cfg(not(feature = "wasm-policy"))]
+    {
+        tracing::warn!(
+            path,
+            "VIDEO_POLICY_WASM is set but this build lacks the wasm-policy feature; ignoring"
+        );
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "wasm-policy")]
+pub use wasm::WasmPolicy;
+
+#[cfg(feature = "wasm-policy")]
+mod wasm {
+    use std::path::Path;
+
+    use wasmtime::{Config, Engine, Instance, Module, Store};
+
+    use super::{IngestPolicy, PolicyDecision, PolicyRequest};
+    use crate::{config, error::AppError};
+
+    const DEFAULT_FUEL: u64 = 50_000_000;
+
+    /// Runs a WebAssembly module as an ingest policy. The module must export
+    /// `memory`, `alloc(len: i32) -> i32`, and `validate(ptr: i32, len: i32) -> i64`.
+    /// `validate` receives the JSON `PolicyRequest` and returns the JSON
+    /// `PolicyDecision` location packed as `(ptr << 32) | len`. Modules get no
+    /// imports and a fuel budget (`VIDEO_POLICY_FUEL`) per evaluation.
+    pub struct WasmPolicy {
+        engine: Engine,
+        module: Module,
+    }
+
+    impl WasmPolicy {
+        pub fn from_file(path: impl AsRef<Path>) -> Result<Self, AppError> {
+            let bytes = std::fs::read(path.as_ref())?;
+            Self::from_bytes(&bytes)
+        }
+
+        /// Compiles a binary module, or WAT text.
+        pub fn from_bytes(bytes: &[u8]) -> Result<Self, AppError> {
+            let mut config = Config::new();
+            config.consume_fuel(true);
+            let engine = Engine::new(&config).map_err(policy_error)?;
+            let module = Module::new(&engine, bytes).map_err(policy_error)?;
+            Ok(Self { engine, module })
+        }
+    }
+
+    impl IngestPolicy for WasmPolicy {
+        fn evaluate(&self, request: &PolicyRequest) -> Result<PolicyDecision, AppError> {
+            let input = serde_json::to_vec(request).map_err(std::io::Error::from)?;
+            let input_len = i32::try_from(input.len())
+                .map_err(|_| AppError::validation("policy request too large"))?;
+
+            let mut store = Store::new(&self.engine, ());
+            let fuel = config::parse_var::<u64>("VIDEO_POLICY_FUEL").unwrap_or(DEFAULT_FUEL);
+            store.set_fuel(fuel).map_err(policy_error)?;
+
+            let instance = Instance::new(&mut store, &self.module, &[]).map_err(policy_error)?;
+            let memory = instance
+                .get_memory(&mut store, "memory")
+                .ok_or_else(|| AppError::dependency("policy module does not export memory"))?;
+            let alloc = instance
+                .get_typed_func::<i32, i32>(&mut store, "alloc")
+                .map_err(policy_error)?;
+            let validate = instance
+                .get_typed_func::<(i32, i32), i64>(&mut store, "validate")
+                .map_err(policy_error)?;
+
+            let ptr = alloc.call(&mut store, input_len).map_err(policy_error)?;
+            memory
+                .write(&mut store, ptr as u32 as usize, &input)
+                .map_err(policy_error)?;
+            let packed = validate
+                .call(&mut store, (ptr, input_len))
+                .map_err(policy_error)? as u64;
+
+            let (out_ptr, out_len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+            let mut output = vec![0u8; out_len];
+            memory
+                .read(&store, out_ptr, &mut output)
+                .map_err(policy_error)?;
+
+            serde_json::from_slice(&output).map_err(|err| {
+                AppError::dependency(format!("policy module returned invalid JSON: {err}"))
+            })
+        }
+    }
+
+    fn policy_error(err: impl std::fmt::Display) -> AppError {
+        AppError::dependency(format!("ingest policy failed: {err}"))
+    }
+}
@@ -0,0 +1,295 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    ffi::OsString,
+    fmt,
+    io::{self, Cursor},
+    process::{ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use tokio::{
+    io::AsyncRead,
+    process::{Child, Command},
+};
+
+use crate::error::AppError;
+
+/// Streamed stderr of a spawned process.
+pub type ProcessStderr = Box<dyn AsyncRead + Send + Unpin>;
+
+pub type DynProcessRunner = Arc<dyn ProcessRunner>;
+
+/// Launches external tools (ffmpeg, ffprobe, aria2c, yt-dlp). The pipeline only
+/// talks to media tooling through this trait so it can be scripted in tests.
+#[async_trait]
+pub trait ProcessRunner: Send + Sync {
+    /// Runs the program to completion and captures stdout and stderr.
+    async fn output(&self, program: &str, args: &[OsString]) -> io::Result<ProcessOutput>;
+
+    /// Starts the program with stderr piped so progress can be streamed.
+    ///
+    /// Dropping the returned process, or an unfinished `output` call, must
+    /// stop the program: cancelled jobs rely on it.
+    async fn spawn(&self, program: &str, args: &[OsString]) -> io::Result<Box<dyn RunningProcess>>;
+}
+
+#[async_trait]
+pub trait RunningProcess: Send {
+    fn take_stderr(&mut self) -> Option<ProcessStderr>;
+    async fn wait(&mut self) -> io::Result<ProcessStatus>;
+    async fn kill(&mut self) -> io::Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessStatus {
+    code: Option<i32>,
+}
+
+impl ProcessStatus {
+    pub fn from_code(code: i32) -> Self {
+        Self { code: Some(code) }
+    }
+
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+
+    pub fn code(&self) -> Option<i32> {
+        self.code
+    }
+}
+
+impl From<ExitStatus> for ProcessStatus {
+    fn from(status: ExitStatus) -> Self {
+        Self {
+            code: status.code(),
+        }
+    }
+}
+
+impl fmt::Display for ProcessStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "exit status: {code}"),
+            None => write!(f, "terminated by signal"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProcessOutput {
+    pub status: ProcessStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// Runs real binaries from `$PATH`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemProcessRunner;
+
+#[async_trait]
+impl ProcessRunner for SystemProcessRunner {
+    async fn output(&self, program: &str, args: &[OsString]) -> io::Result<ProcessOutput> {
+        let output = Command::new(program)
+            .args(args)
+            .kill_on_drop(true)
+            .output()
+            .await?;
+        Ok(ProcessOutput {
+            status: output.status.into(),
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+
+    async fn spawn(&self, program: &str, args: &[OsString]) -> io::Result<Box<dyn RunningProcess>> {
+        let child = Command::new(program)
+            .args(args)
+            .stderr(Stdio::piped())
+            .stdout(Stdio::null())
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+        Ok(Box::new(SystemProcess { child }))
+    }
+}
+
+struct SystemProcess {
+    child: Child,
+}
+
+#[async_trait]
+impl RunningProcess for SystemProcess {
+    fn take_stderr(&mut self) -> Option<ProcessStderr> {
+        self.child
+            .stderr
+            .take()
+            .map(|stderr| Box::new(stderr) as ProcessStderr)
+    }
+
+    async fn wait(&mut self) -> io::Result<ProcessStatus> {
+        Ok(self.child.wait().await?.into())
+    }
+
+    async fn kill(&mut self) -> io::Result<()> {
+        self.child.kill().await
+    }
+}
+
+/// The error for a tool that failed to start, naming a missing one.
+pub(crate) fn map_spawn_error(err: io::Error, tool: &str) -> AppError {
+    match err.kind() {
+        io::ErrorKind::NotFound => AppError::dependency(format!("{tool} not found on PATH")),
+        _ => AppError::dependency(format!("failed to spawn {tool}: {err}")),
+    }
+}
+
+type ScriptedEffect = Arc<dyn Fn(&[OsString]) + Send + Sync>;
+
+/// Canned result for one invocation of a scripted program.
+#[derive(Clone)]
+pub struct ScriptedResponse {
+    status: ProcessStatus,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    effect: Option<ScriptedEffect>,
+}
+
+impl ScriptedResponse {
+    pub fn success() -> Self {
+        Self::exit(0)
+    }
+
+    pub fn exit(code: i32) -> Self {
+        Self {
+            status: ProcessStatus::from_code(code),
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            effect: None,
+        }
+    }
+
+    pub fn stdout(mut self, stdout: impl Into<Vec<u8>>) -> Self {
+        self.stdout = stdout.into();
+        self
+    }
+
+    pub fn stderr(mut self, stderr: impl Into<Vec<u8>>) -> Self {
+        self.stderr = stderr.into();
+        self
+    }
+
+    /// Runs `effect` with the invocation arguments, e.g. to create output files.
+    pub fn effect(mut self, effect: impl Fn(&[OsString]) + Send + Sync + 'static) -> Self {
+        self.effect = Some(Arc::new(effect));
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptedCall {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+/// Replays queued responses per program. Invoking a program with no remaining
+/// responses fails with `NotFound`, mimicking a tool missing from `$PATH`.
+#[derive(Default)]
+pub struct ScriptedProcessRunner {
+    responses: Mutex<HashMap<String, VecDeque<ScriptedResponse>>>,
+    calls: Mutex<Vec<ScriptedCall>>,
+}
+
+impl ScriptedProcessRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn expect(&self, program: &str, response: ScriptedResponse) -> &Self {
+        self.responses
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+            .entry(program.to_string())
+            .or_default()
+            .push_back(response);
+        self
+    }
+
+    pub fn calls(&self) -> Vec<ScriptedCall> {
+        self.calls
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+            .clone()
+    }
+
+    fn next_response(&self, program: &str, args: &[OsString]) -> io::Result<ScriptedResponse> {
+        self.calls
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+            .push(ScriptedCall {
+                program: program.to_string(),
+                args: args
+                    .iter()
+                    .map(|arg| arg.to_string_lossy().into_owned())
+                    .collect(),
+            });
+
+        let response = self
+            .responses
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+            .get_mut(program)
+            .and_then(VecDeque::pop_front)
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("{program} not scripted"))
+            })?;
+
+        if let Some(effect) = &response.effect {
+            effect(args);
+        }
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl ProcessRunner for ScriptedProcessRunner {
+    async fn output(&self, program: &str, args: &[OsString]) -> io::Result<ProcessOutput> {
+        let response = self.next_response(program, args)?;
+        Ok(ProcessOutput {
+            status: response.status,
+            stdout: response.stdout,
+            stderr: response.stderr,
+        })
+    }
+
+    async fn spawn(&self, program: &str, args: &[OsString]) -> io::Result<Box<dyn RunningProcess>> {
+        let response = self.next_response(program, args)?;
+        Ok(Box::new(ScriptedProcess {
+            status: response.status,
+            stderr: Some(response.stderr),
+        }))
+    }
+}
+
+struct ScriptedProcess {
+    status: ProcessStatus,
+    stderr: Option<Vec<u8>>,
+}
+
+#[async_trait]
+impl RunningProcess for ScriptedProcess {
+    fn take_stderr(&mut self) -> Option<ProcessStderr> {
+        self.stderr
+            .take()
+            .map(|bytes| Box::new(Cursor::new(bytes)) as ProcessStderr)
+    }
+
+    async fn wait(&mut self) -> io::Result<ProcessStatus> {
+        Ok(self.status)
+    }
+
+    async fn kill(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,290 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Request, State},
+    http::{Extensions, HeaderMap, request::Parts},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{
+    auth::Claims,
+    bandwidth::ANONYMOUS_KEY,
+    config::{self, Reloadable},
+    error::AppError,
+    state::AppState,
+    usage::account_key,
+};
+
+/// Past this many tracked clients, buckets that have refilled completely
+/// are dropped; they behave the same as a fresh bucket.
+const MAX_TRACKED_BUCKETS: usize = 10_000;
+
+/// A token bucket holding `burst` requests and refilling them evenly over
+/// `period`. Written `<requests>/<period>`, e.g. `30/min` or `5/s`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub period: Duration,
+}
+
+impl RateLimit {
+    pub fn parse(spec: &str) -> Option<Self> {
+        let (count, period) = spec.trim().split_once('/')?;
+        let burst = count
+            .trim()
+            .parse::<u32>()
+            .ok()
+            .filter(|&count| count > 0)?;
+        let period = match period.trim().to_ascii_lowercase().as_str() {
+            "s" | "sec" | "second" => Duration::from_secs(1),
+            "m" | "min" | "minute" => Duration::from_secs(60),
+            "h" | "hour" => Duration::from_secs(3600),
+            "d" | "day" => Duration::from_secs(86_400),
+            _ => return None,
+        };
+        Some(Self { burst, period })
+    }
+
+    fn per_second(&self) -> f64 {
+        f64::from(self.burst) / self.period.as_secs_f64()
+    }
+}
+
+/// Limits on the ingestion routes, re-read on reload. Both are off by
+/// default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    /// `VIDEO_RATE_LIMIT_PER_IP`, per client address.
+    pub per_ip: Option<RateLimit>,
+    /// `VIDEO_RATE_LIMIT_PER_KEY`, per token subject. Callers without a
+    /// verified token are only limited by address.
+    pub per_key: Option<RateLimit>,
+    /// `VIDEO_RATE_LIMIT_TRUST_FORWARDED_FOR`: take the client address from
+    /// `X-Forwarded-For`, for servers behind a proxy.
+    pub trust_forwarded_for: bool,
+    /// `VIDEO_RATE_LIMIT_TRUSTED_PROXIES`: how many proxies in front of the
+    /// server append to `X-Forwarded-For`. The client is the entry that many
+    /// hops from the right; entries further left are whatever the client
+    /// sent. `0` counts as one.
+    pub trusted_proxies: usize,
+}
+
+impl RateLimitConfig {
+    pub fn from_env() -> Self {
+        let limit = |key: &str| {
+            let value = config::var(key).filter(|value| !value.trim().is_empty())?;
+            let parsed = RateLimit::parse(&value);
+            if parsed.is_none() {
+                tracing::warn!(key, value, "ignoring invalid rate limit; use e.g. 30/min");
+            }
+            parsed
+        };
+        Self {
+            per_ip: limit("VIDEO_RATE_LIMIT_PER_IP"),
+            per_key: limit("VIDEO_RATE_LIMIT_PER_KEY"),
+            trust_forwarded_for: config::var("VIDEO_RATE_LIMIT_TRUST_FORWARDED_FOR")
+                .is_some_and(|value| matches!(value.trim(), "1" | "true" | "yes" | "on")),
+            trusted_proxies: config::parse_var("VIDEO_RATE_LIMIT_TRUSTED_PROXIES").unwrap_or(1),
+        }
+    }
+}
+
+/// Token buckets per client address and caller key.
+#[derive(Clone)]
+pub struct RateLimiter {
+    config: Reloadable<RateLimitConfig>,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    pub(crate) fn full(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: f64::from(limit.burst),
+            updated: now,
+        }
+    }
+
+    /// Takes one request if the bucket holds one.
+    pub(crate) fn take(&mut self) -> bool {
+        let taken = self.tokens >= 1.0;
+        if taken {
+            self.tokens -= 1.0;
+        }
+        taken
+    }
+
+    pub(crate) fn refilled(self, limit: RateLimit, now: Instant) -> Self {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        Self {
+            tokens: (self.tokens + elapsed * limit.per_second()).min(f64::from(limit.burst)),
+            updated: now,
+        }
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config: Reloadable::new(config),
+            buckets: Arc::default(),
+        }
+    }
+
+    /// Swaps in new limits. Buckets keep their tokens, capped at the new
+    /// burst the next time they are used.
+    pub fn reconfigure(&self, config: RateLimitConfig) {
+        self.config.set(config);
+    }
+
+    /// Takes a request from the buckets of `ip` and `key`, or from neither
+    /// when one of them is empty. Fails with `429` and the seconds until
+    /// the request would be admitted.
+    pub fn check(&self, ip: Option<IpAddr>, key: &str) -> Result<(), AppError> {
+        let config = self.config.get();
+        let limited = [
+            ip.zip(config.per_ip)
+                .map(|(ip, limit)| (format!("ip:{ip}"), limit, "ip")),
+            config
+                .per_key
+                .filter(|_| key != ANONYMOUS_KEY)
+                .map(|limit| (format!("key:{key}"), limit, "key")),
+        ];
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|p| p.into_inner());
+
+        let mut refilled = Vec::with_capacity(limited.len());
+        let mut denied: Option<(f64, &str)> = None;
+        for (name, limit, scope) in limited.into_iter().flatten() {
+            let bucket = buckets
+                .get(&name)
+                .copied()
+                .unwrap_or(Bucket::full(limit, now))
+                .refilled(limit, now);
+            if bucket.tokens < 1.0 {
+                let wait = (1.0 - bucket.tokens) / limit.per_second();
+                if denied.is_none_or(|(longest, _)| wait > longest) {
+                    denied = Some((wait, scope));
+                }
+            }
+            refilled.push((name, bucket));
+        }
+        if let Some((wait, scope)) = denied {
+            let retry_after = (wait.ceil() as u64).max(1);
+            return Err(AppError::rate_limited(format!(
+                "too many ingest requests; retry in {retry_after}s"
+            ))
+            .with_param("limit", scope)
+            .with_param("retry_after_secs", retry_after));
+        }
+
+        if buckets.len() > MAX_TRACKED_BUCKETS {
+            let (per_ip, per_key) = (config.per_ip, config.per_key);
+            buckets.retain(|name, bucket| {
+                let limit = if name.starts_with("ip:") {
+                    per_ip
+                } else {
+                    per_key
+                };
+                limit.is_some_and(|limit| {
+                    bucket.refilled(limit, now).tokens < f64::from(limit.burst)
+                })
+            });
+        }
+        for (name, mut bucket) in refilled {
+            bucket.tokens -= 1.0;
+            buckets.insert(name, bucket);
+        }
+        Ok(())
+    }
+
+    /// Whether requests come through a proxy whose forwarding headers can
+    /// be believed, per `VIDEO_RATE_LIMIT_TRUST_FORWARDED_FOR`.
+    pub fn trusts_forwarded_headers(&self) -> bool {
+        self.config.get().trust_forwarded_for
+    }
+
+    /// The address the limits apply to. Without a trusted `X-Forwarded-For`
+    /// entry, this is the connection's peer.
+    pub fn client_ip(&self, headers: &HeaderMap, extensions: &Extensions) -> Option<IpAddr> {
+        let config = self.config.get();
+        if config.trust_forwarded_for
+            && let Some(forwarded) = forwarded_for(headers, config.trusted_proxies)
+        {
+            return Some(forwarded);
+        }
+        extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())
+    }
+}
+
+/// The address a request came from, taken as the rate limits take it:
+/// from `X-Forwarded-For` when trusted, else from the connection.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientAddr(pub Option<IpAddr>);
+
+impl FromRequestParts<AppState> for ClientAddr {
+    type Rejection = Infallible;
+
+    fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> impl Future<Output = Result<Self, Self::Rejection>> + Send {
+        let ip = state
+            .rate_limits
+            .client_ip(&parts.headers, &parts.extensions);
+        async move { Ok(Self(ip)) }
+    }
+}
+
+/// The `X-Forwarded-For` entry added by the outermost of `trusted_proxies`
+/// proxies. Counting from the right keeps clients from picking their own
+/// address by sending the header themselves. A proxy may add its own header
+/// line rather than extend the client's, so all lines count, in order.
+fn forwarded_for(headers: &HeaderMap, trusted_proxies: usize) -> Option<IpAddr> {
+    let lines = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .map(|value| value.to_str().ok())
+        .collect::<Option<Vec<_>>>()?;
+    lines
+        .join(",")
+        .rsplit(',')
+        .nth(trusted_proxies.saturating_sub(1))?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Route layer applying the ingestion rate limits. It runs after
+/// [`require_scope`](crate::handlers::require_scope), so callers with a
+/// verified token are limited by its subject. Other callers only have
+/// their address: a key they name could be changed on every request.
+pub async fn limit_ingest(
+    State(limiter): State<RateLimiter>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let key = account_key(
+        request.headers(),
+        request.extensions().get::<Claims>(),
+        false,
+    );
+    let ip = limiter.client_ip(request.headers(), request.extensions());
+    limiter.check(ip, &key)?;
+    Ok(next.run(request).await)
+}
@@ -0,0 +1,518 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::{self, Read},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use reqwest::{Client, Url, header};
+use serde::{Deserialize, Serialize};
+use tokio::{fs, sync::broadcast};
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+use crate::{
+    blocking,
+    clock::{rfc3339, unix_ms},
+    config,
+    error::AppError,
+    http_client,
+    storage::Storage,
+    transcode::write_archive,
+};
+
+/// Events buffered per subscriber before the oldest are dropped.
+const CHANNEL_CAPACITY: usize = 64;
+/// Entries of a video's directory a peer rebuilds itself instead of
+/// receiving them.
+const LOCAL_ONLY: &[&str] = &["hls.zip", "frames"];
+const DEFAULT_MAX_IMPORT_BYTES: u64 = 64 * 1024 * 1024 * 1024;
+
+/// Where published videos are pushed to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplicationConfig {
+    /// Base URLs of peer instances, e.g. `https://vrs.eu-west.example.com`.
+    pub peers: Vec<Url>,
+    /// Bearer token sent to peers; it needs the `admin` scope there.
+    pub token: Option<String>,
+    /// Largest replica this instance imports, both as received and once
+    /// unpacked.
+    pub max_import_bytes: u64,
+}
+
+impl Default for ReplicationConfig {
+    fn default() -> Self {
+        Self {
+            peers: Vec::new(),
+            token: None,
+            max_import_bytes: DEFAULT_MAX_IMPORT_BYTES,
+        }
+    }
+}
+
+impl ReplicationConfig {
+    /// From `VIDEO_REPLICATION_PEERS` (comma-separated),
+    /// `VIDEO_REPLICATION_TOKEN` and `VIDEO_REPLICA_MAX_BYTES`.
+    pub fn from_env() -> Self {
+        let peers = config::var("VIDEO_REPLICATION_PEERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|peer| !peer.is_empty())
+            .filter_map(|peer| {
+                parse_peer(peer)
+                    .inspect_err(
+                        |err| tracing::warn!(peer, error = %err, "ignoring replication peer"),
+                    )
+                    .ok()
+            })
+            .collect();
+        Self {
+            peers,
+            token: config::var("VIDEO_REPLICATION_TOKEN").filter(|token| !token.trim().is_empty()),
+            max_import_bytes: max_import_bytes(),
+        }
+    }
+}
+
+/// `VIDEO_REPLICA_MAX_BYTES`, also the cap on replicas fetched by federation.
+pub(crate) fn max_import_bytes() -> u64 {
+    config::parse_var::<u64>("VIDEO_REPLICA_MAX_BYTES")
+        .filter(|&bytes| bytes > 0)
+        .unwrap_or(DEFAULT_MAX_IMPORT_BYTES)
+}
+
+/// Parses a peer's base URL. The path gets a trailing slash so the import
+/// route is joined below it, as for a peer behind a path prefix.
+pub fn parse_peer(peer: &str) -> Result<Url, AppError> {
+    let invalid = |reason: String| {
+        AppError::validation(format!("invalid peer URL {peer:?}: {reason}"))
+            .with_code("replication_peer_invalid")
+    };
+    let mut url = Url::parse(peer).map_err(|err| invalid(err.to_string()))?;
+    if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+        return Err(invalid("expected an http(s) URL with a host".into()));
+    }
+    if !url.path().ends_with('/') {
+        let path = format!("{}/", url.path());
+        url.set_path(&path);
+    }
+    url.set_query(None);
+    url.set_fragment(None);
+    Ok(url)
+}
+
+/// Something that changed a video in a way peers should follow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ReplicationEvent {
+    /// A job finished and the video is ready to serve.
+    Published { video_id: Uuid },
+    /// The video was deleted through the API or by tag retention.
+    Deleted { video_id: Uuid },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplicationState {
+    Running,
+    Complete,
+    Failed,
+}
+
+/// The latest push of a video to one peer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerReplication {
+    pub peer: String,
+    pub state: ReplicationState,
+    /// Size of the archive the peer accepted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub updated_at: String,
+}
+
+/// Publishes [`ReplicationEvent`]s and pushes videos to peers through
+/// their `PUT /admin/import/{id}`, one push per video and peer at a time.
+#[derive(Clone)]
+pub struct Replicator {
+    inner: Arc<ReplicatorInner>,
+}
+
+struct ReplicatorInner {
+    storage: Storage,
+    client: Client,
+    /// Fixed peers; otherwise they are re-read from config for each event.
+    config: Option<ReplicationConfig>,
+    events: broadcast::Sender<ReplicationEvent>,
+    pushes: Mutex<Pushes>,
+}
+
+#[derive(Default)]
+struct Pushes {
+    latest: BTreeMap<(Uuid, String), PeerReplication>,
+    /// Pushes requested while one to the same peer was running. They start
+    /// again once it ends, so the peer gets the latest files.
+    again: HashSet<(Uuid, String)>,
+}
+
+impl Replicator {
+    pub fn new(storage: Storage, client: Client) -> Self {
+        Self::build(storage, client, None)
+    }
+
+    /// Uses `config` instead of reading peers from the environment.
+    pub fn with_config(storage: Storage, client: Client, config: ReplicationConfig) -> Self {
+        Self::build(storage, client, Some(config))
+    }
+
+    fn build(storage: Storage, client: Client, config: Option<ReplicationConfig>) -> Self {
+        Self {
+            inner: Arc::new(ReplicatorInner {
+                storage,
+                client,
+                config,
+                events: broadcast::channel(CHANNEL_CAPACITY).0,
+                pushes: Mutex::default(),
+            }),
+        }
+    }
+
+    pub fn config(&self) -> ReplicationConfig {
+        self.inner
+            .config
+            .clone()
+            .unwrap_or_else(ReplicationConfig::from_env)
+    }
+
+    /// Receives every event from now on, e.g. to replicate to a store of
+    /// your own.
+    pub fn subscribe(&self) -> broadcast::Receiver<ReplicationEvent> {
+        self.inner.events.subscribe()
+    }
+
+    /// Tells subscribers about `event` and applies it to the configured
+    /// peers: a published video is pushed, a deleted one removed.
+    pub fn emit(&self, event: ReplicationEvent) {
+        let _ = self.inner.events.send(event);
+        let config = self.config();
+        match event {
+            ReplicationEvent::Published { video_id } => {
+                for peer in &config.peers {
+                    self.push(video_id, peer, config.token.clone());
+                }
+            }
+            ReplicationEvent::Deleted { video_id } => {
+                self.pushes().latest.retain(|(id, _), _| *id != video_id);
+                for peer in config.peers {
+                    let client = self.inner.client.clone();
+                    let token = config.token.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) =
+                            delete_replica(&client, token.as_deref(), video_id, &peer).await
+                        {
+                            tracing::warn!(%video_id, peer = %peer, error = %err, "failed to delete replica");
+                        }
+                    });
+                }
+            }
+        }
+    }
+
+    /// Pushes the video to `peers`, or to the configured peers, in the
+    /// background and returns where each push stands. The token only goes
+    /// to configured peers, so a caller naming its own host cannot collect it.
+    pub fn replicate(
+        &self,
+        video_id: Uuid,
+        peers: Option<Vec<Url>>,
+    ) -> Result<Vec<PeerReplication>, AppError> {
+        if !self.inner.storage.download_path(&video_id).exists() {
+            return Err(AppError::not_found(format!("video {video_id} not found")));
+        }
+        let config = self.config();
+        let peers = peers.unwrap_or_else(|| config.peers.clone());
+        if peers.is_empty() {
+            return Err(AppError::validation(
+                "no peers given and VIDEO_REPLICATION_PEERS is not set",
+            )
+            .with_code("replication_no_peers"));
+        }
+        Ok(peers
+            .iter()
+            .map(|peer| {
+                let token = config.token.clone().filter(|_| config.peers.contains(peer));
+                self.push(video_id, peer, token)
+            })
+            .collect())
+    }
+
+    /// The latest push of the video to each peer.
+    pub fn status(&self, video_id: Uuid) -> Vec<PeerReplication> {
+        self.pushes()
+            .latest
+            .range((video_id, String::new())..)
+            .take_while(|((id, _), _)| *id == video_id)
+            .map(|(_, push)| push.clone())
+            .collect()
+    }
+
+    fn push(&self, video_id: Uuid, peer: &Url, token: Option<String>) -> PeerReplication {
+        let key = (video_id, peer.to_string());
+        let mut pushes = self.pushes();
+        if let Some(running) = pushes
+            .latest
+            .get(&key)
+            .filter(|push| push.state == ReplicationState::Running)
+        {
+            let running = running.clone();
+            pushes.again.insert(key);
+            return running;
+        }
+        let running = peer_replication(&key.1, ReplicationState::Running, None, None);
+        pushes.latest.insert(key.clone(), running.clone());
+        drop(pushes);
+
+        let replicator = self.clone();
+        let peer = peer.clone();
+        tokio::spawn(async move {
+            loop {
+                let result = push_once(
+                    &replicator.inner.storage,
+                    &replicator.inner.client,
+                    token.as_deref(),
+                    video_id,
+                    &peer,
+                )
+                .await;
+                let mut pushes = replicator.pushes();
+                if pushes.again.remove(&key) {
+                    continue;
+                }
+                let finished = match result {
+                    Ok(size) => {
+                        tracing::info!(%video_id, peer = %peer, size, "replicated video");
+                        peer_replication(&key.1, ReplicationState::Complete, Some(size), None)
+                    }
+                    Err(err) => {
+                        tracing::warn!(%video_id, peer = %peer, error = %err, "replication failed");
+                        peer_replication(
+                            &key.1,
+                            ReplicationState::Failed,
+                            None,
+                            Some(err.to_string()),
+                        )
+                    }
+                };
+                // A deletion in the meantime dropped the entry; keep it dropped.
+                if let Some(latest) = pushes.latest.get_mut(&key) {
+                    *latest = finished;
+                }
+                break;
+            }
+        });
+        running
+    }
+
+    fn pushes(&self) -> std::sync::MutexGuard<'_, Pushes> {
+        self.inner
+            .pushes
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+    }
+}
+
+fn peer_replication(
+    peer: &str,
+    state: ReplicationState,
+    size_bytes: Option<u64>,
+    error: Option<String>,
+) -> PeerReplication {
+    PeerReplication {
+        peer: peer.to_string(),
+        state,
+        size_bytes,
+        error,
+        updated_at: rfc3339(unix_ms(SystemTime::now())),
+    }
+}
+
+/// Zips the video's directory into the tmp area, uploads it, and removes
+/// the zip again. Returns the size of the upload.
+async fn push_once(
+    storage: &Storage,
+    client: &Client,
+    token: Option<&str>,
+    video_id: Uuid,
+    peer: &Url,
+) -> Result<u64, AppError> {
+    let archive = storage.tmp_dir().join(format!(
+        "{}.{}.replica.zip",
+        video_id.simple(),
+        Uuid::new_v4().simple()
+    ));
+    let result = send_archive(storage, client, token, video_id, peer, &archive).await;
+    match fs::remove_file(&archive).await {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => {
+            tracing::warn!(path = %archive.display(), error = %err, "failed to remove replica archive");
+        }
+        _ => {}
+    }
+    result
+}
+
+async fn send_archive(
+    storage: &Storage,
+    client: &Client,
+    token: Option<&str>,
+    video_id: Uuid,
+    peer: &Url,
+    archive: &Path,
+) -> Result<u64, AppError> {
+    write_replica(storage, video_id, archive).await?;
+    let file = fs::File::open(archive).await?;
+    let size = file.metadata().await?.len();
+    let mut request = client
+        .put(import_url(peer, video_id)?)
+        .timeout(http_client::download_timeout())
+        .header(header::CONTENT_TYPE, "application/zip")
+        .header(header::CONTENT_LENGTH, size)
+        .body(reqwest::Body::wrap_stream(ReaderStream::new(file)));
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    request.send().await?.error_for_status()?;
+    Ok(size)
+}
+
+/// Zips the video's directory to `archive` as a peer imports it.
+pub(crate) async fn write_replica(
+    storage: &Storage,
+    video_id: Uuid,
+    archive: &Path,
+) -> Result<(), AppError> {
+    if !storage.download_path(&video_id).exists() {
+        return Err(AppError::not_found(format!("video {video_id} not found")));
+    }
+    let video_dir = storage.video_dir(&video_id);
+    let path = archive.to_path_buf();
+    blocking::run(move || write_archive(&video_dir, &path, LOCAL_ONLY))
+        .await
+        .map_err(io::Error::other)??;
+    Ok(())
+}
+
+async fn delete_replica(
+    client: &Client,
+    token: Option<&str>,
+    video_id: Uuid,
+    peer: &Url,
+) -> Result<(), AppError> {
+    let mut request = client
+        .delete(import_url(peer, video_id)?)
+        .timeout(http_client::download_timeout());
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    request.send().await?.error_for_status()?;
+    tracing::info!(%video_id, peer = %peer, "deleted replica");
+    Ok(())
+}
+
+fn import_url(peer: &Url, video_id: Uuid) -> Result<Url, AppError> {
+    peer.join(&format!("admin/import/{video_id}"))
+        .map_err(|err| AppError::validation(format!("invalid peer URL {peer}: {err}")))
+}
+
+/// Unpacks a replica pushed by a peer and makes it the video's directory,
+/// replacing any older copy. The archive must hold the video's download.
+pub async fn import_archive(
+    storage: &Storage,
+    video_id: Uuid,
+    archive: &Path,
+    max_bytes: u64,
+) -> Result<(), AppError> {
+    let staged = storage.import_dir(&video_id);
+    let unpacked = {
+        let archive = archive.to_path_buf();
+        let staged = staged.clone();
+        blocking::run(move || unpack_archive(&archive, &staged, max_bytes))
+            .await
+            .map_err(io::Error::other)?
+    };
+    let result = match unpacked {
+        Ok(()) if staged.join("download.webm").is_file() => {
+            storage.replace_video(&video_id, &staged).await
+        }
+        Ok(()) => Err(AppError::validation("replica archive has no download.webm")
+            .with_code("replica_invalid")),
+        Err(err) if err.kind() == io::ErrorKind::FileTooLarge => Err(replica_too_large(max_bytes)),
+        Err(err) => Err(
+            AppError::validation(format!("unreadable replica archive: {err}"))
+                .with_code("replica_invalid"),
+        ),
+    };
+    if result.is_err() {
+        match fs::remove_dir_all(&staged).await {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                tracing::warn!(path = %staged.display(), error = %err, "failed to remove staged replica");
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+/// The error for a replica past `VIDEO_REPLICA_MAX_BYTES`.
+pub(crate) fn replica_too_large(max: u64) -> AppError {
+    AppError::too_large(format!("replicas are limited to {max} bytes"))
+        .with_code("replica_too_large")
+        .with_param("max_bytes", max)
+}
+
+/// Extracts the regular files of the zip at `path` into a fresh `dir`.
+/// Entries that would land outside it, and symlinks, are refused, and so
+/// are files adding up to more than `max_bytes`, with `FileTooLarge`.
+fn unpack_archive(path: &Path, dir: &Path, max_bytes: u64) -> io::Result<()> {
+    match std::fs::remove_dir_all(dir) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    std::fs::create_dir_all(dir)?;
+    let mut zip = zip::ZipArchive::new(std::fs::File::open(path)?).map_err(io::Error::other)?;
+    let mut remaining = max_bytes;
+    for index in 0..zip.len() {
+        let mut entry = zip.by_index(index).map_err(io::Error::other)?;
+        let Some(name) = entry.enclosed_name() else {
+            return Err(io::Error::other(format!(
+                "entry {:?} leaves the archive",
+                entry.name()
+            )));
+        };
+        if entry.is_symlink() {
+            return Err(io::Error::other(format!(
+                "entry {:?} is a symlink",
+                entry.name()
+            )));
+        }
+        let target = dir.join(name);
+        if entry.is_dir() {
+            std::fs::create_dir_all(&target)?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // The sizes in the archive are not trusted; what is written counts.
+        let written = io::copy(
+            &mut (&mut entry).take(remaining.saturating_add(1)),
+            &mut std::fs::File::create(&target)?,
+        )?;
+        remaining = remaining
+            .checked_sub(written)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::FileTooLarge))?;
+    }
+    Ok(())
+}
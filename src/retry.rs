@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use crate::{
+    config,
+    error::{AppError, ErrorClass},
+};
+
+const DEFAULT_BACKOFF_SECS: u64 = 30;
+/// Longest wait between two attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(15 * 60);
+const DEFAULT_FFMPEG_CODES: &str = "ffmpeg_stalled,ffmpeg_killed";
+
+/// When a failed pipeline runs again by itself before its job is failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Runs after the first; 0 fails jobs on their first error.
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each one after it.
+    pub backoff: Duration,
+    /// Codes of ffmpeg failures worth another run, such as a stalled or
+    /// killed encoder.
+    pub ffmpeg_codes: Vec<String>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            backoff: Duration::from_secs(DEFAULT_BACKOFF_SECS),
+            ffmpeg_codes: split_codes(DEFAULT_FFMPEG_CODES),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// From `VIDEO_JOB_RETRIES`, `VIDEO_JOB_RETRY_BACKOFF_SECS` and
+    /// `VIDEO_JOB_RETRY_FFMPEG_CODES`.
+    pub fn from_env() -> Self {
+        Self {
+            retries: config::parse_var("VIDEO_JOB_RETRIES").unwrap_or(0),
+            backoff: Duration::from_secs(
+                config::parse_var("VIDEO_JOB_RETRY_BACKOFF_SECS").unwrap_or(DEFAULT_BACKOFF_SECS),
+            ),
+            ffmpeg_codes: split_codes(
+                config::var("VIDEO_JOB_RETRY_FFMPEG_CODES")
+                    .as_deref()
+                    .unwrap_or(DEFAULT_FFMPEG_CODES),
+            ),
+        }
+    }
+
+    /// Whether `err` may go away by itself: a network error, or an ffmpeg
+    /// failure with one of `ffmpeg_codes`.
+    pub fn is_transient(&self, err: &AppError) -> bool {
+        match err.root() {
+            AppError::Transcode(_) => self.ffmpeg_codes.iter().any(|code| code == err.code()),
+            _ => err.class() == ErrorClass::Network,
+        }
+    }
+
+    /// How long to wait before running again after attempt `attempt`,
+    /// counted from 1, stopped with `err`; `None` once the job should fail.
+    pub fn next_delay(&self, err: &AppError, attempt: u32) -> Option<Duration> {
+        if attempt > self.retries || !self.is_transient(err) {
+            return None;
+        }
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        Some(self.backoff.saturating_mul(factor).min(MAX_BACKOFF))
+    }
+}
+
+fn split_codes(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|code| !code.is_empty())
+        .map(str::to_string)
+        .collect()
+}
@@ -0,0 +1,251 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{clock, config, error::AppError};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const DEFAULT_REGION: &str = "us-east-1";
+/// Longest lifetime S3 accepts for a presigned URL.
+const MAX_PRESIGN: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// An object named by an `s3://bucket/key` URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Location {
+    pub bucket: String,
+    pub key: String,
+}
+
+impl S3Location {
+    /// Parses `s3://bucket/key`; both parts are required.
+    pub fn parse(url: &str) -> Result<Self, AppError> {
+        let invalid = || {
+            AppError::validation(format!("expected s3://bucket/key, got {url:?}"))
+                .with_code("s3_url_invalid")
+        };
+        let rest = url.strip_prefix("s3://").ok_or_else(invalid)?;
+        let (bucket, key) = rest.split_once('/').ok_or_else(invalid)?;
+        if bucket.is_empty() || key.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+        })
+    }
+}
+
+/// Whether `url` names an S3 object rather than an HTTP resource.
+pub fn is_s3_url(url: &str) -> bool {
+    url.starts_with("s3://")
+}
+
+/// Access keys for a bucket, from a request or `AWS_ACCESS_KEY_ID`,
+/// `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN`.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct S3Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_token: Option<String>,
+    /// Region of the bucket, overriding `AWS_REGION`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+}
+
+impl std::fmt::Debug for S3Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3Credentials")
+            .field("access_key_id", &self.access_key_id)
+            .field("region", &self.region)
+            .finish_non_exhaustive()
+    }
+}
+
+impl S3Credentials {
+    /// The keys in the environment, if both are set.
+    pub fn from_env() -> Option<Self> {
+        let access_key_id = config::var("AWS_ACCESS_KEY_ID").filter(|key| !key.is_empty())?;
+        let secret_access_key =
+            config::var("AWS_SECRET_ACCESS_KEY").filter(|key| !key.is_empty())?;
+        Some(Self {
+            access_key_id,
+            secret_access_key,
+            session_token: config::var("AWS_SESSION_TOKEN").filter(|token| !token.is_empty()),
+            region: None,
+        })
+    }
+}
+
+/// Where S3 objects are fetched from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Config {
+    pub region: String,
+    /// S3-compatible service such as MinIO; AWS when unset.
+    pub endpoint: Option<Url>,
+    /// Address buckets as `endpoint/bucket/key` instead of `bucket.endpoint/key`.
+    pub path_style: bool,
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        Self {
+            region: DEFAULT_REGION.to_string(),
+            endpoint: None,
+            path_style: false,
+        }
+    }
+}
+
+impl S3Config {
+    /// From `AWS_REGION` (or `AWS_DEFAULT_REGION`), `VIDEO_S3_ENDPOINT` and
+    /// `VIDEO_S3_PATH_STYLE`.
+    pub fn from_env() -> Self {
+        let endpoint = config::var("VIDEO_S3_ENDPOINT").and_then(|endpoint| {
+            Url::parse(&endpoint)
+                .inspect_err(
+                    |err| tracing::warn!(endpoint, error = %err, "ignoring VIDEO_S3_ENDPOINT"),
+                )
+                .ok()
+        });
+        Self {
+            region: config::var("AWS_REGION")
+                .or_else(|| config::var("AWS_DEFAULT_REGION"))
+                .filter(|region| !region.is_empty())
+                .unwrap_or_else(|| DEFAULT_REGION.to_string()),
+            endpoint,
+            path_style: config::var("VIDEO_S3_PATH_STYLE")
+                .map(|value| matches!(value.trim(), "1" | "true" | "yes" | "on"))
+                .unwrap_or(false),
+        }
+    }
+
+    /// The plain HTTPS URL of `location`, without any signature.
+    pub fn object_url(&self, location: &S3Location, region: &str) -> Result<Url, AppError> {
+        let endpoint = match &self.endpoint {
+            Some(endpoint) => endpoint.clone(),
+            None => Url::parse(&format!("https://s3.{region}.amazonaws.com"))
+                .map_err(|err| AppError::validation(format!("invalid S3 region: {err}")))?,
+        };
+        let mut url = endpoint.clone();
+        let key = encode_path(&location.key);
+        if self.path_style {
+            url.set_path(&format!("/{}/{key}", uri_encode(&location.bucket)));
+        } else {
+            let host = endpoint
+                .host_str()
+                .ok_or_else(|| AppError::validation("S3 endpoint has no host"))?;
+            url.set_host(Some(&format!("{}.{host}", location.bucket)))
+                .map_err(|err| {
+                    AppError::validation(format!("invalid bucket name: {err}"))
+                        .with_code("s3_url_invalid")
+                })?;
+            url.set_path(&format!("/{key}"));
+        }
+        Ok(url)
+    }
+
+    /// A URL that fetches `location` for `expires` (at most 7 days) from
+    /// `now`, signed with AWS Signature Version 4 in the query string. Range
+    /// requests work on it like on any other URL. Without `credentials`, the
+    /// plain URL is returned for public objects.
+    pub fn presign_get(
+        &self,
+        location: &S3Location,
+        credentials: Option<&S3Credentials>,
+        expires: Duration,
+        now: SystemTime,
+    ) -> Result<Url, AppError> {
+        let region = credentials
+            .and_then(|credentials| credentials.region.as_deref())
+            .unwrap_or(&self.region);
+        let mut url = self.object_url(location, region)?;
+        let Some(credentials) = credentials else {
+            return Ok(url);
+        };
+
+        let unix = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let timestamp = clock::rfc3339(u128::from(unix) * 1000);
+        let amz_date = format!("{}Z", timestamp[..19].replace(['-', ':'], ""));
+        let date = &amz_date[..8];
+        let scope = format!("{date}/{region}/s3/aws4_request");
+        let host = match url.port() {
+            Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+
+        let mut query = vec![
+            ("X-Amz-Algorithm", "AWS4-HMAC-SHA256".to_string()),
+            (
+                "X-Amz-Credential",
+                format!("{}/{scope}", credentials.access_key_id),
+            ),
+            ("X-Amz-Date", amz_date.clone()),
+            (
+                "X-Amz-Expires",
+                expires.min(MAX_PRESIGN).as_secs().max(1).to_string(),
+            ),
+            ("X-Amz-SignedHeaders", "host".to_string()),
+        ];
+        if let Some(token) = &credentials.session_token {
+            query.push(("X-Amz-Security-Token", token.clone()));
+        }
+        query.sort();
+        let canonical_query = query
+            .iter()
+            .map(|(name, value)| format!("{}={}", uri_encode(name), uri_encode(value)))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let canonical_request = format!(
+            "GET\n{}\n{canonical_query}\nhost:{host}\n\nhost\nUNSIGNED-PAYLOAD",
+            url.path()
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let mut key = hmac(
+            format!("AWS4{}", credentials.secret_access_key).as_bytes(),
+            date,
+        );
+        for part in [region, "s3", "aws4_request"] {
+            key = hmac(&key, part);
+        }
+        let signature = hex::encode(hmac(&key, &string_to_sign));
+
+        url.set_query(Some(&format!(
+            "{canonical_query}&X-Amz-Signature={signature}"
+        )));
+        Ok(url)
+    }
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encodes everything but unreserved characters, as SigV4 requires.
+fn uri_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Encodes each `/`-separated segment of an object key.
+fn encode_path(key: &str) -> String {
+    key.split('/').map(uri_encode).collect::<Vec<_>>().join("/")
+}
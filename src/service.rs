@@ -0,0 +1,227 @@
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncWriteExt},
+};
+use uuid::Uuid;
+
+use crate::{
+    aria2::FileSelection,
+    cleanup::CleanupConfig,
+    digest::{self, DigestWriter, SourceDigest},
+    error::AppError,
+    handlers::{
+        approve_awaiting_job, cancel_running_job, create_pipeline_job, record_source_digest,
+        retry_failed_job, spawn_local_pipeline, submit_remote_job,
+    },
+    http_client,
+    jobs::{
+        DynJobStore, JobCredentials, JobGroupStatus, JobStage, JobStatusResponse, LocalJobStore,
+    },
+    state::AppState,
+    storage::{Storage, ensure_parent},
+    transcode::{EncodeParams, ensure_hls_ready},
+};
+
+const AWAIT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Embeds the ingest and transcode pipeline without running the HTTP server.
+#[derive(Clone)]
+pub struct VideoService {
+    state: AppState,
+}
+
+impl VideoService {
+    /// Builds a service with an in-memory job store rooted at `storage_root`.
+    pub async fn new(storage_root: impl AsRef<Path>) -> Result<Self, AppError> {
+        let storage = Storage::initialize(storage_root).await?;
+        let jobs: DynJobStore = Arc::new(LocalJobStore::new());
+        let http_client = http_client::build_http_client()?;
+        Ok(Self::from_state(AppState::new(
+            storage,
+            http_client,
+            jobs,
+            CleanupConfig::from_env(),
+        )))
+    }
+
+    pub fn from_state(state: AppState) -> Self {
+        Self { state }
+    }
+
+    pub fn state(&self) -> &AppState {
+        &self.state
+    }
+
+    /// Copies a local media file into the incoming area and starts a transcode job.
+    /// The original file is left untouched.
+    pub async fn ingest_file(
+        &self,
+        path: impl AsRef<Path>,
+        encode: Option<EncodeParams>,
+    ) -> Result<Uuid, AppError> {
+        let source = path.as_ref();
+        if !source.is_file() {
+            return Err(AppError::not_found(format!(
+                "input file {}",
+                source.display()
+            )));
+        }
+
+        let file_name = source.file_name().map(|name| name.to_string_lossy());
+        self.ingest_with(file_name.as_deref(), encode, |temp_path| async move {
+            digest::copy_file(source, &temp_path).await
+        })
+        .await
+    }
+
+    /// Spools `reader`, such as standard input, into the incoming area and
+    /// starts a transcode job once it ends. `name` is recorded as the job's
+    /// source.
+    pub async fn ingest_reader<R>(
+        &self,
+        mut reader: R,
+        name: Option<&str>,
+        encode: Option<EncodeParams>,
+    ) -> Result<Uuid, AppError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        self.ingest_with(name, encode, |temp_path| async move {
+            let mut file = DigestWriter::new(File::create(&temp_path).await?);
+            tokio::io::copy(&mut reader, &mut file).await?;
+            file.flush().await?;
+            Ok(file.digest())
+        })
+        .await
+    }
+
+    /// Registers a job, has `spool` write its source to the incoming path
+    /// it is given, and starts the pipeline. A job whose source could not
+    /// be written or is not media is failed.
+    async fn ingest_with<F, Fut>(
+        &self,
+        name: Option<&str>,
+        encode: Option<EncodeParams>,
+        spool: F,
+    ) -> Result<Uuid, AppError>
+    where
+        F: FnOnce(PathBuf) -> Fut,
+        Fut: Future<Output = std::io::Result<SourceDigest>>,
+    {
+        let id = create_pipeline_job(&self.state, None, name, encode.as_ref(), None, None).await?;
+
+        let temp_path = self.state.storage.incoming_path(&id);
+        ensure_parent(&temp_path).await?;
+        let digest = match spool(temp_path.clone()).await {
+            Ok(digest) => digest,
+            Err(err) => {
+                let err = AppError::from(err);
+                self.state.jobs.fail(id, &err).await?;
+                let _ = tokio::fs::remove_file(&temp_path).await;
+                return Err(err);
+            }
+        };
+        if let Err(err) = record_source_digest(&self.state, id, &temp_path, Some(digest)).await {
+            self.state.jobs.fail(id, &err).await?;
+            let _ = tokio::fs::remove_file(&temp_path).await;
+            return Err(err);
+        }
+
+        spawn_local_pipeline(self.state.clone(), id, encode, None);
+        Ok(id)
+    }
+
+    /// Starts a download-and-transcode job for an HTTP(S), FTP, torrent, or magnet source.
+    pub async fn ingest_url(
+        &self,
+        url: impl Into<String>,
+        encode: Option<EncodeParams>,
+    ) -> Result<Uuid, AppError> {
+        submit_remote_job(
+            &self.state,
+            url.into(),
+            None,
+            None,
+            FileSelection::default(),
+            encode,
+            None,
+            None,
+            None,
+        )
+        .await
+    }
+
+    pub async fn job_status(&self, id: Uuid) -> Result<JobStatusResponse, AppError> {
+        self.state
+            .jobs
+            .status(&id)
+            .await?
+            .ok_or_else(|| AppError::not_found(format!("job {id} not found")))
+    }
+
+    pub async fn job_group(&self, id: Uuid) -> Result<JobGroupStatus, AppError> {
+        self.state
+            .jobs
+            .group_status(&id)
+            .await?
+            .ok_or_else(|| AppError::not_found(format!("job {id} not found")))
+    }
+
+    /// Cancels a job whose pipeline runs in this process, stopping any
+    /// external tool it is running, and returns its updated snapshot.
+    pub async fn cancel_job(&self, id: Uuid) -> Result<JobStatusResponse, AppError> {
+        cancel_running_job(&self.state, id).await
+    }
+
+    /// Re-runs a failed job under the same id and returns its reset snapshot.
+    pub async fn retry_job(&self, id: Uuid) -> Result<JobStatusResponse, AppError> {
+        retry_failed_job(&self.state, id, JobCredentials::default()).await
+    }
+
+    /// Re-runs a failed job that was submitted with per-request
+    /// credentials, which are not kept, with `credentials` given again.
+    pub async fn retry_job_with_credentials(
+        &self,
+        id: Uuid,
+        credentials: JobCredentials,
+    ) -> Result<JobStatusResponse, AppError> {
+        retry_failed_job(&self.state, id, credentials).await
+    }
+
+    /// Starts the full encode of a job waiting for approval and returns its
+    /// snapshot.
+    pub async fn approve_job(&self, id: Uuid) -> Result<JobStatusResponse, AppError> {
+        approve_awaiting_job(&self.state, id).await
+    }
+
+    /// Waits until the job completes, fails or has its review proxy ready for
+    /// approval, and returns that snapshot.
+    pub async fn await_job(&self, id: Uuid) -> Result<JobStatusResponse, AppError> {
+        loop {
+            let status = self.job_status(id).await?;
+            let awaiting =
+                status.stage == JobStage::AwaitingApproval && !self.state.running.is_running(&id);
+            if status.stage.is_terminal() || awaiting {
+                return Ok(status);
+            }
+            tokio::time::sleep(AWAIT_POLL_INTERVAL).await;
+        }
+    }
+
+    pub fn download_path(&self, id: Uuid) -> PathBuf {
+        self.state.storage.download_path(&id)
+    }
+
+    /// Makes sure the HLS ladder exists on disk and returns its directory.
+    pub async fn open_hls_dir(&self, id: Uuid) -> Result<PathBuf, AppError> {
+        ensure_hls_ready(&self.state.storage, &self.state.process_runner, &id).await?;
+        Ok(self.state.storage.hls_dir(&id))
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::{config, usage::QuotaLimit};
+
+/// Seconds `bytes_per_sec` is averaged over in [`TenantIngest`].
+const RATE_WINDOW_SECS: u64 = 10;
+
+/// Ingest bandwidth budgets per caller key.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShapingConfig {
+    /// `VIDEO_INGEST_BYTES_PER_SEC`: a default for every key plus per-key
+    /// overrides, in the format of the `VIDEO_QUOTA_*` variables.
+    pub bytes_per_sec: QuotaLimit<u64>,
+}
+
+impl ShapingConfig {
+    pub fn from_env() -> Self {
+        Self {
+            bytes_per_sec: config::var("VIDEO_INGEST_BYTES_PER_SEC")
+                .map(|spec| QuotaLimit::parse(&spec))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// The budget of `key`; 0 means unlimited.
+    pub fn for_key(&self, key: &str) -> Option<u64> {
+        self.bytes_per_sec.for_key(key).filter(|&rate| rate > 0)
+    }
+}
+
+/// Ingest traffic of one caller key since startup.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TenantIngest {
+    pub key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit_bytes_per_sec: Option<u64>,
+    pub active_transfers: usize,
+    /// Average over the last ten seconds.
+    pub bytes_per_sec: u64,
+    pub total_bytes: u64,
+    /// Time transfers spent waiting for the key's budget.
+    pub throttled_seconds: f64,
+}
+
+/// Shares each caller key's ingest budget between its uploads and downloads,
+/// so one key's bulk import cannot take all of the network and disk.
+///
+/// Transfers of a key draw from one token bucket holding a second of its
+/// budget. A transfer that overdraws it waits until the debt is paid, which
+/// slows the socket it reads from.
+#[derive(Clone, Default)]
+pub struct IngestShaper {
+    inner: Arc<ShaperInner>,
+}
+
+#[derive(Default)]
+struct ShaperInner {
+    /// Fixed budgets; otherwise they are re-read from config when a transfer
+    /// starts.
+    config: Option<ShapingConfig>,
+    tenants: Mutex<BTreeMap<String, Tenant>>,
+}
+
+struct Tenant {
+    /// Budget left; negative while transfers owe time.
+    tokens: f64,
+    updated: Instant,
+    active: usize,
+    total_bytes: u64,
+    throttled: Duration,
+    started: Instant,
+    /// Bytes per whole second since `started`, for the last
+    /// `RATE_WINDOW_SECS` seconds.
+    recent: VecDeque<(u64, u64)>,
+}
+
+impl Tenant {
+    fn new(limit: Option<u64>) -> Self {
+        let now = Instant::now();
+        Self {
+            tokens: limit.unwrap_or(0) as f64,
+            updated: now,
+            active: 0,
+            total_bytes: 0,
+            throttled: Duration::ZERO,
+            started: now,
+            recent: VecDeque::new(),
+        }
+    }
+
+    fn count(&mut self, bytes: u64, now: Instant) {
+        self.total_bytes = self.total_bytes.saturating_add(bytes);
+        let second = now.duration_since(self.started).as_secs();
+        match self.recent.back_mut() {
+            Some((last, sum)) if *last == second => *sum += bytes,
+            _ => self.recent.push_back((second, bytes)),
+        }
+        self.prune(now);
+    }
+
+    fn prune(&mut self, now: Instant) {
+        let second = now.duration_since(self.started).as_secs();
+        while self
+            .recent
+            .front()
+            .is_some_and(|(at, _)| at + RATE_WINDOW_SECS <= second)
+        {
+            self.recent.pop_front();
+        }
+    }
+
+    /// Takes `bytes` from the bucket and returns how long the caller must
+    /// wait to pay off what it overdrew.
+    fn draw(&mut self, limit: u64, bytes: u64, now: Instant) -> Duration {
+        let rate = limit as f64;
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate) - bytes as f64;
+        self.updated = now;
+        if self.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+        let wait = Duration::from_secs_f64(-self.tokens / rate);
+        self.throttled += wait;
+        wait
+    }
+}
+
+impl IngestShaper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses `config` instead of reading budgets from the environment.
+    pub fn with_config(config: ShapingConfig) -> Self {
+        Self {
+            inner: Arc::new(ShaperInner {
+                config: Some(config),
+                tenants: Mutex::default(),
+            }),
+        }
+    }
+
+    fn config(&self) -> ShapingConfig {
+        self.inner
+            .config
+            .clone()
+            .unwrap_or_else(ShapingConfig::from_env)
+    }
+
+    /// Starts a transfer for `key`. It keeps the budget in force now until
+    /// it ends, and counts as running until the last clone is dropped.
+    pub fn begin(&self, key: &str) -> IngestTransfer {
+        let limit = self.config().for_key(key);
+        self.inner
+            .tenants()
+            .entry(key.to_string())
+            .or_insert_with(|| Tenant::new(limit))
+            .active += 1;
+        IngestTransfer {
+            inner: Arc::new(TransferInner {
+                shaper: self.inner.clone(),
+                key: key.to_string(),
+                limit,
+            }),
+        }
+    }
+
+    /// Every key that ingested since startup, busiest first.
+    pub fn report(&self) -> Vec<TenantIngest> {
+        let config = self.config();
+        let now = Instant::now();
+        let mut report: Vec<TenantIngest> = self
+            .inner
+            .tenants()
+            .iter_mut()
+            .map(|(key, tenant)| {
+                tenant.prune(now);
+                let recent: u64 = tenant.recent.iter().map(|(_, bytes)| bytes).sum();
+                TenantIngest {
+                    key: key.clone(),
+                    limit_bytes_per_sec: config.for_key(key),
+                    active_transfers: tenant.active,
+                    bytes_per_sec: recent / RATE_WINDOW_SECS,
+                    total_bytes: tenant.total_bytes,
+                    throttled_seconds: tenant.throttled.as_secs_f64(),
+                }
+            })
+            .collect();
+        report.sort_by(|a, b| {
+            b.bytes_per_sec
+                .cmp(&a.bytes_per_sec)
+                .then_with(|| b.active_transfers.cmp(&a.active_transfers))
+                .then_with(|| a.key.cmp(&b.key))
+        });
+        report
+    }
+}
+
+impl ShaperInner {
+    fn tenants(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, Tenant>> {
+        self.tenants.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+/// An upload or download counted against its key's budget.
+#[derive(Clone)]
+pub struct IngestTransfer {
+    inner: Arc<TransferInner>,
+}
+
+struct TransferInner {
+    shaper: Arc<ShaperInner>,
+    key: String,
+    limit: Option<u64>,
+}
+
+impl Drop for TransferInner {
+    fn drop(&mut self) {
+        if let Some(tenant) = self.shaper.tenants().get_mut(&self.key) {
+            tenant.active = tenant.active.saturating_sub(1);
+        }
+    }
+}
+
+impl IngestTransfer {
+    pub fn key(&self) -> &str {
+        &self.inner.key
+    }
+
+    pub fn limit(&self) -> Option<u64> {
+        self.inner.limit
+    }
+
+    /// The key's budget split evenly between its running transfers, for
+    /// downloaders such as aria2 that enforce a fixed rate themselves.
+    pub fn share(&self) -> Option<u64> {
+        let limit = self.inner.limit?;
+        let active = self
+            .inner
+            .shaper
+            .tenants()
+            .get(&self.inner.key)
+            .map_or(1, |tenant| tenant.active.max(1));
+        Some((limit / active as u64).max(1))
+    }
+
+    /// Counts `bytes` that arrived and, while the key is over its budget,
+    /// waits until the budget covers them.
+    pub async fn consume(&self, bytes: u64) {
+        let wait = {
+            let mut tenants = self.inner.shaper.tenants();
+            let now = Instant::now();
+            let tenant = tenants
+                .entry(self.inner.key.clone())
+                .or_insert_with(|| Tenant::new(self.inner.limit));
+            tenant.count(bytes, now);
+            match self.inner.limit {
+                Some(limit) => tenant.draw(limit, bytes, now),
+                None => Duration::ZERO,
+            }
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Counts `bytes` that arrived outside the server's own loops, without
+    /// waiting.
+    pub fn record(&self, bytes: u64) {
+        let mut tenants = self.inner.shaper.tenants();
+        let tenant = tenants
+            .entry(self.inner.key.clone())
+            .or_insert_with(|| Tenant::new(self.inner.limit));
+        tenant.count(bytes, Instant::now());
+    }
+}
@@ -0,0 +1,240 @@
+//! Sharded job dispatch for instances sharing a job store and storage root.
+//!
+//! Each instance heartbeats into the job store. Job ids are hashed onto a
+//! ring of the live instances, and a job's pipeline runs on the instance
+//! owning its id. An instance receiving a job it does not own records it
+//! and leaves it queued for the owner to pick up. Whichever instance starts
+//! a pipeline claims the job in the store first, so it runs once. When an
+//! instance stops heartbeating, its jobs hash to the remaining instances,
+//! which run them again from their sources.
+
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    config,
+    error::AppError,
+    handlers,
+    jobs::{DynJobStore, JobStage},
+    state::AppState,
+};
+
+const DEFAULT_HEARTBEAT_SECS: u64 = 5;
+/// Points each instance takes on the ring, so ids spread evenly.
+const VIRTUAL_NODES: u32 = 128;
+
+/// A consistent-hash ring: adding or removing an instance only moves the
+/// ids of the ring segments it gains or loses.
+#[derive(Debug, Clone, Default)]
+pub struct HashRing {
+    points: BTreeMap<u64, String>,
+}
+
+impl HashRing {
+    pub fn new<I, S>(instances: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut points = BTreeMap::new();
+        for instance in instances {
+            let instance = instance.as_ref();
+            for node in 0..VIRTUAL_NODES {
+                points.insert(
+                    hash(format!("{instance}#{node}").as_bytes()),
+                    instance.to_string(),
+                );
+            }
+        }
+        Self { points }
+    }
+
+    /// The instance job `id` belongs to; `None` on an empty ring.
+    pub fn owner(&self, id: &Uuid) -> Option<&str> {
+        let key = hash(id.as_bytes());
+        self.points
+            .range(key..)
+            .next()
+            .or_else(|| self.points.iter().next())
+            .map(|(_, instance)| instance.as_str())
+    }
+}
+
+/// First 8 bytes of SHA-256, so every instance places ids alike.
+fn hash(bytes: &[u8]) -> u64 {
+    let digest = Sha256::digest(bytes);
+    u64::from_be_bytes(digest[..8].try_into().expect("digest has 8 bytes"))
+}
+
+/// This instance's place among those dispatching jobs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShardConfig {
+    /// Unique among the instances; also shown as the `instance` of the jobs
+    /// it runs.
+    pub instance_id: String,
+    /// How often the instance heartbeats and looks for jobs it owns.
+    pub heartbeat_interval: Duration,
+    /// How long after its last heartbeat an instance counts as gone.
+    pub node_ttl: Duration,
+}
+
+impl ShardConfig {
+    pub fn new(instance_id: impl Into<String>) -> Self {
+        let heartbeat_interval = Duration::from_secs(DEFAULT_HEARTBEAT_SECS);
+        Self {
+            instance_id: instance_id.into(),
+            heartbeat_interval,
+            node_ttl: heartbeat_interval * 3,
+        }
+    }
+
+    /// From `VIDEO_SHARDING`, when it is on: `VIDEO_INSTANCE_ID` (else
+    /// `HOSTNAME`, else a random id), `VIDEO_SHARD_HEARTBEAT_SECS` and
+    /// `VIDEO_SHARD_NODE_TTL_SECS`.
+    pub fn from_env() -> Option<Self> {
+        let enabled = config::var("VIDEO_SHARDING")
+            .is_some_and(|value| matches!(value.trim(), "1" | "true" | "yes" | "on"));
+        if !enabled {
+            return None;
+        }
+        let instance_id = config::var("VIDEO_INSTANCE_ID")
+            .or_else(|| config::var("HOSTNAME"))
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        let heartbeat_interval = Duration::from_secs(
+            config::parse_var::<u64>("VIDEO_SHARD_HEARTBEAT_SECS")
+                .filter(|&secs| secs > 0)
+                .unwrap_or(DEFAULT_HEARTBEAT_SECS),
+        );
+        let node_ttl = config::parse_var::<u64>("VIDEO_SHARD_NODE_TTL_SECS")
+            .map(Duration::from_secs)
+            .filter(|ttl| *ttl > heartbeat_interval)
+            .unwrap_or(heartbeat_interval * 3);
+        Some(Self {
+            instance_id,
+            heartbeat_interval,
+            node_ttl,
+        })
+    }
+}
+
+/// Decides which instance runs each job. Without a [`ShardConfig`], every
+/// job runs where it was submitted.
+#[derive(Clone, Default)]
+pub struct Dispatcher {
+    config: Option<Arc<ShardConfig>>,
+}
+
+impl Dispatcher {
+    pub fn new(config: Option<ShardConfig>) -> Self {
+        Self {
+            config: config.map(Arc::new),
+        }
+    }
+
+    pub fn config(&self) -> Option<&ShardConfig> {
+        self.config.as_deref()
+    }
+
+    /// Whether this instance should run the pipeline of job `id`, which it
+    /// then holds the claim on. A job store failure runs the job here.
+    pub async fn admit(&self, jobs: &DynJobStore, id: Uuid) -> bool {
+        let Some(config) = self.config() else {
+            return true;
+        };
+        let admitted = async {
+            let live = live_ring(jobs, config).await?;
+            if live.1.owner(&id) != Some(config.instance_id.as_str()) {
+                return Ok(false);
+            }
+            jobs.claim(id, &config.instance_id, &live.0).await
+        };
+        match admitted.await {
+            Ok(admitted) => admitted,
+            Err(err) => {
+                tracing::warn!(%id, error = %err, "sharded dispatch unavailable; running job here");
+                true
+            }
+        }
+    }
+
+    /// Takes job `id` for this instance whichever instance held it, as when
+    /// it is approved here.
+    pub async fn take(&self, jobs: &DynJobStore, id: Uuid) -> Result<(), AppError> {
+        if let Some(config) = self.config() {
+            jobs.claim(id, &config.instance_id, &[]).await?;
+        }
+        Ok(())
+    }
+
+    /// Gives up the claim on job `id` once its pipeline stopped.
+    pub async fn release(&self, jobs: &DynJobStore, id: Uuid) {
+        let Some(config) = self.config() else {
+            return;
+        };
+        if let Err(err) = jobs.release(id, &config.instance_id).await {
+            tracing::warn!(%id, error = %err, "failed to release job claim");
+        }
+    }
+}
+
+/// The live instances, this one included, and their ring.
+async fn live_ring(
+    jobs: &DynJobStore,
+    config: &ShardConfig,
+) -> Result<(Vec<String>, HashRing), AppError> {
+    let mut live = jobs.live_instances().await?;
+    if !live.contains(&config.instance_id) {
+        live.push(config.instance_id.clone());
+    }
+    let ring = HashRing::new(&live);
+    Ok((live, ring))
+}
+
+/// Heartbeats this instance, cancels the jobs it runs that other instances
+/// were asked to cancel, and starts the pipelines of the jobs it owns that
+/// no live instance runs: jobs other instances left waiting, and jobs whose
+/// instance is gone, which run again from their sources. Returns how many
+/// pipelines were started. Does nothing without sharding.
+pub async fn dispatch(state: &AppState) -> Result<usize, AppError> {
+    let Some(config) = state.dispatcher.config() else {
+        return Ok(0);
+    };
+    state
+        .jobs
+        .heartbeat(&config.instance_id, config.node_ttl)
+        .await?;
+    let (live, ring) = live_ring(&state.jobs, config).await?;
+    let cancels = state.jobs.cancel_requests().await?;
+    let mut started = 0;
+    for status in state.jobs.list().await? {
+        // Approval waits without a pipeline; the approving instance runs it.
+        if status.parent_id.is_some()
+            || status.stage.is_terminal()
+            || status.stage == JobStage::AwaitingApproval
+        {
+            continue;
+        }
+        let cancelled = cancels.contains(&status.id);
+        if cancelled && state.running.cancel(&status.id) {
+            tracing::info!(id = %status.id, "cancelling job as asked by another instance");
+            continue;
+        }
+        let stranded = match &status.instance {
+            // Uploads wait in `Uploading` once received.
+            None => matches!(status.stage, JobStage::Queued | JobStage::Uploading),
+            Some(holder) if *holder == config.instance_id => !state.running.is_running(&status.id),
+            Some(holder) => !live.contains(holder),
+        };
+        if !stranded || ring.owner(&status.id) != Some(config.instance_id.as_str()) {
+            continue;
+        }
+        if handlers::take_over_job(state, status.id, &live, cancelled).await? {
+            started += 1;
+        }
+    }
+    Ok(started)
+}
@@ -0,0 +1,217 @@
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{fs, sync::Mutex};
+use uuid::Uuid;
+
+use crate::{
+    error::AppError,
+    storage::{Storage, ensure_dir},
+    video_access::AccessSigner,
+};
+
+const SHARE_ID_LEN: usize = 12;
+const ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// A public link to one video, valid until it expires, runs out of uses, or
+/// is revoked.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShareLink {
+    pub share_id: String,
+    pub video_id: Uuid,
+    pub created_at_unix_ms: u64,
+    pub expires_at_unix_ms: u64,
+    /// `None` means unlimited.
+    pub max_uses: Option<u32>,
+    pub uses: u32,
+}
+
+impl ShareLink {
+    fn is_expired(&self, now_ms: u64) -> bool {
+        now_ms >= self.expires_at_unix_ms
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.max_uses.is_some_and(|max| self.uses >= max)
+    }
+}
+
+/// Share links persisted as one JSON file per link under `<storage>/shares/`,
+/// so lookups by share id need no index.
+#[derive(Clone)]
+pub struct ShareStore {
+    storage: Storage,
+    // Serialises read-modify-write of use counters.
+    lock: Arc<Mutex<()>>,
+}
+
+impl ShareStore {
+    pub fn new(storage: Storage) -> Self {
+        Self {
+            storage,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    fn dir(&self) -> PathBuf {
+        self.storage.root_dir().join("shares")
+    }
+
+    fn path(&self, share_id: &str) -> PathBuf {
+        self.dir().join(format!("{share_id}.json"))
+    }
+
+    pub async fn create(
+        &self,
+        video_id: Uuid,
+        ttl: Duration,
+        max_uses: Option<u32>,
+    ) -> Result<ShareLink, AppError> {
+        let now = unix_ms();
+        let link = ShareLink {
+            share_id: generate_share_id(),
+            video_id,
+            created_at_unix_ms: now,
+            expires_at_unix_ms: now.saturating_add(ttl.as_millis() as u64),
+            max_uses,
+            uses: 0,
+        };
+        let _guard = self.lock.lock().await;
+        ensure_dir(&self.dir()).await?;
+        self.write(&link).await?;
+        Ok(link)
+    }
+
+    /// Looks up a live link for one request. A request carrying the `play`
+    /// grant of a playback the link already counted continues that playback.
+    /// Any other request starts a new one, which counts one use and is
+    /// refused once none are left. Returns the link with the grant to forward
+    /// to the rest of the playback. Unknown, expired, and used-up links are
+    /// all reported as not found.
+    pub async fn resolve(
+        &self,
+        share_id: &str,
+        play: Option<&str>,
+    ) -> Result<(ShareLink, String), AppError> {
+        if !is_valid_share_id(share_id) {
+            return Err(AppError::not_found("share link"));
+        }
+        let _guard = self.lock.lock().await;
+        let Some(mut link) = self.read(share_id).await? else {
+            return Err(AppError::not_found("share link"));
+        };
+        if link.is_expired(unix_ms()) {
+            fs::remove_file(self.path(share_id)).await.ok();
+            return Err(AppError::not_found("share link has expired"));
+        }
+        let signer = AccessSigner::from_env();
+        let scope = format!("share:{share_id}");
+        if let Some(play) = play
+            && signer.verify(&link.video_id, &scope, play).is_ok()
+        {
+            return Ok((link, play.to_string()));
+        }
+        if link.is_exhausted() {
+            return Err(AppError::not_found("share link has no uses left"));
+        }
+        link.uses += 1;
+        self.write(&link).await?;
+        // A grant lasts one playback session, never past the link itself.
+        let expires = (unix_ms() / 1000)
+            .saturating_add(signer.ttl().as_secs())
+            .min(link.expires_at_unix_ms.div_ceil(1000));
+        let grant = signer.sign(&link.video_id, &scope, expires);
+        Ok((link, grant))
+    }
+
+    /// Links for `video_id` that have not expired, oldest first.
+    pub async fn list(&self, video_id: &Uuid) -> Result<Vec<ShareLink>, AppError> {
+        let mut entries = match fs::read_dir(self.dir()).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let now = unix_ms();
+        let mut links = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.path().extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let Ok(bytes) = fs::read(entry.path()).await else {
+                continue;
+            };
+            match serde_json::from_slice::<ShareLink>(&bytes) {
+                Ok(link) if link.video_id == *video_id && !link.is_expired(now) => links.push(link),
+                Ok(_) => {}
+                Err(err) => {
+                    tracing::warn!(path = %entry.path().display(), %err, "skipping unreadable share link");
+                }
+            }
+        }
+        links.sort_by_key(|link| link.created_at_unix_ms);
+        Ok(links)
+    }
+
+    /// Deletes a link of `video_id`. Returns false if there was none.
+    pub async fn revoke(&self, video_id: &Uuid, share_id: &str) -> Result<bool, AppError> {
+        if !is_valid_share_id(share_id) {
+            return Ok(false);
+        }
+        let _guard = self.lock.lock().await;
+        match self.read(share_id).await? {
+            Some(link) if link.video_id == *video_id => {
+                fs::remove_file(self.path(share_id)).await?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn read(&self, share_id: &str) -> Result<Option<ShareLink>, AppError> {
+        match fs::read(self.path(share_id)).await {
+            Ok(bytes) => Ok(Some(
+                serde_json::from_slice(&bytes).map_err(std::io::Error::from)?,
+            )),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn write(&self, link: &ShareLink) -> Result<(), AppError> {
+        let path = self.path(&link.share_id);
+        let bytes = serde_json::to_vec_pretty(link).map_err(std::io::Error::from)?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, bytes).await?;
+        fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+}
+
+/// Twelve base62 characters, just over 71 bits, taken from the 122 random
+/// bits of a v4 UUID. The version and variant bits are dropped first, so every
+/// character is random; the bias of the final modulo is below 2^-50.
+fn generate_share_id() -> String {
+    let raw = Uuid::new_v4().as_u128();
+    let mut value = (raw >> 80) << 74 | ((raw >> 64) & 0xfff) << 62 | (raw & ((1 << 62) - 1));
+    let mut id = String::with_capacity(SHARE_ID_LEN);
+    for _ in 0..SHARE_ID_LEN {
+        id.push(ALPHABET[(value % ALPHABET.len() as u128) as usize] as char);
+        value /= ALPHABET.len() as u128;
+    }
+    id
+}
+
+fn is_valid_share_id(share_id: &str) -> bool {
+    share_id.len() == SHARE_ID_LEN && share_id.bytes().all(|byte| byte.is_ascii_alphanumeric())
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
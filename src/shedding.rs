@@ -0,0 +1,322 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tokio::sync::Notify;
+
+use crate::{cleanup::storage_disk_status, config, error::AppError, metrics, storage::Storage};
+
+const DEFAULT_SHED_MIN_FREE_BYTES: u64 = 1024 * 1024 * 1024; // 1 GiB
+const DEFAULT_RETRY_AFTER_SECS: u64 = 30;
+const DEFAULT_SHED_TRANSCODES: usize = 1;
+const DEFAULT_BOOST_PROGRESS: f32 = 0.9;
+/// How long a health reading is reused before disk and load are sampled again.
+const SAMPLE_TTL: Duration = Duration::from_secs(5);
+/// Waiting transcodes re-check their limit at least this often, so a
+/// recovered host raises concurrency without a permit being released.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Thresholds past which the host counts as overloaded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SheddingConfig {
+    pub min_free_bytes: u64,
+    pub min_free_ratio: f32,
+    /// One-minute load average per CPU; `None` disables the check.
+    pub max_load_per_cpu: Option<f32>,
+    pub retry_after_secs: u64,
+    /// Transcode concurrency while healthy; `None` means unlimited.
+    pub max_transcodes: Option<usize>,
+    pub shed_transcodes: usize,
+    /// Jobs at or past this transcode progress get free transcode slots
+    /// before jobs that would start fresh.
+    pub boost_progress: f32,
+}
+
+impl SheddingConfig {
+    pub fn from_env() -> Self {
+        Self {
+            min_free_bytes: config::parse_var("VIDEO_SHED_MIN_FREE_BYTES")
+                .unwrap_or(DEFAULT_SHED_MIN_FREE_BYTES),
+            min_free_ratio: config::parse_var::<f32>("VIDEO_SHED_MIN_FREE_RATIO")
+                .map(|ratio| ratio.clamp(0.0, 0.9))
+                .unwrap_or(0.0),
+            max_load_per_cpu: config::parse_var::<f32>("VIDEO_SHED_MAX_LOAD")
+                .filter(|load| *load > 0.0),
+            retry_after_secs: config::parse_var("VIDEO_SHED_RETRY_AFTER_SECS")
+                .unwrap_or(DEFAULT_RETRY_AFTER_SECS),
+            max_transcodes: config::parse_var::<usize>("VIDEO_MAX_CONCURRENT_TRANSCODES")
+                .filter(|&limit| limit > 0),
+            shed_transcodes: config::parse_var::<usize>("VIDEO_SHED_TRANSCODE_CONCURRENCY")
+                .filter(|&limit| limit > 0)
+                .unwrap_or(DEFAULT_SHED_TRANSCODES),
+            boost_progress: config::parse_var::<f32>("VIDEO_SCHED_BOOST_PROGRESS")
+                .map(|progress| progress.clamp(0.0, 1.0))
+                .unwrap_or(DEFAULT_BOOST_PROGRESS),
+        }
+    }
+}
+
+/// Why the host is shedding load, if it is.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LoadReport {
+    pub overloaded: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub active_transcodes: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transcode_limit: Option<usize>,
+    pub waiting_transcodes: usize,
+    /// Waiting jobs past the boost threshold, served first.
+    pub boosted_waiting: usize,
+    /// Slots granted ahead of newer jobs since startup.
+    pub boosted_grants: u64,
+}
+
+/// Rejects new ingest and narrows transcode concurrency while disk space or
+/// CPU load is past its threshold, returning to normal once it recovers.
+#[derive(Clone, Default)]
+pub struct LoadShedder {
+    inner: Arc<ShedderInner>,
+}
+
+#[derive(Default)]
+struct ShedderInner {
+    /// Fixed thresholds; otherwise they are re-read from config on each check.
+    config: Option<SheddingConfig>,
+    sample: Mutex<Option<(Instant, Option<String>)>>,
+    active: Mutex<usize>,
+    queue: Mutex<WaitQueue>,
+    released: Notify,
+}
+
+#[derive(Default)]
+struct WaitQueue {
+    waiting: usize,
+    boosted: usize,
+    boosted_grants: u64,
+}
+
+impl WaitQueue {
+    /// Exports the queue length to `/metrics`.
+    fn publish(&self) {
+        let normal = (self.waiting - self.boosted) as u64;
+        metrics::set("vrs_transcode_waiting", &[("priority", "normal")], normal);
+        metrics::set(
+            "vrs_transcode_waiting",
+            &[("priority", "boosted")],
+            self.boosted as u64,
+        );
+    }
+}
+
+/// Counts a caller as waiting until it gets a slot or gives up.
+struct Waiting<'a> {
+    queue: &'a Mutex<WaitQueue>,
+    boosted: bool,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        let mut queue = self.queue.lock().unwrap_or_else(|p| p.into_inner());
+        queue.waiting -= 1;
+        if self.boosted {
+            queue.boosted -= 1;
+        }
+        queue.publish();
+    }
+}
+
+/// A running transcode's slot; frees it on drop.
+pub struct TranscodePermit {
+    inner: Arc<ShedderInner>,
+}
+
+impl Drop for TranscodePermit {
+    fn drop(&mut self) {
+        let mut active = self.inner.active.lock().unwrap_or_else(|p| p.into_inner());
+        *active = active.saturating_sub(1);
+        self.inner.released.notify_waiters();
+    }
+}
+
+impl LoadShedder {
+    /// Uses `config` instead of reading thresholds from the environment.
+    pub fn with_config(config: SheddingConfig) -> Self {
+        Self {
+            inner: Arc::new(ShedderInner {
+                config: Some(config),
+                ..ShedderInner::default()
+            }),
+        }
+    }
+
+    fn config(&self) -> SheddingConfig {
+        self.inner
+            .config
+            .clone()
+            .unwrap_or_else(SheddingConfig::from_env)
+    }
+
+    /// Fails with `503` and `Retry-After` while the host is overloaded.
+    pub async fn admit(&self, storage: &Storage) -> Result<(), AppError> {
+        let config = self.config();
+        match self.overload_reason(storage, &config).await {
+            Some(reason) => Err(AppError::overloaded(
+                format!("not accepting new videos: {reason}"),
+                config.retry_after_secs,
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Waits for a transcode slot. The limit is `VIDEO_MAX_CONCURRENT_TRANSCODES`,
+    /// lowered to `VIDEO_SHED_TRANSCODE_CONCURRENCY` while overloaded.
+    ///
+    /// `progress` is how far the job's transcode got. While slots are contended,
+    /// jobs at or past `VIDEO_SCHED_BOOST_PROGRESS` go first, so nearly
+    /// finished work completes before new work starts.
+    pub async fn transcode_slot(&self, storage: &Storage, progress: f32) -> TranscodePermit {
+        let boosted = progress >= self.config().boost_progress;
+        let _waiting = {
+            let mut queue = self.inner.queue.lock().unwrap_or_else(|p| p.into_inner());
+            queue.waiting += 1;
+            if boosted {
+                queue.boosted += 1;
+            }
+            queue.publish();
+            Waiting {
+                queue: &self.inner.queue,
+                boosted,
+            }
+        };
+        let mut deferred = false;
+        loop {
+            let config = self.config();
+            let limit = self.transcode_limit(storage, &config).await;
+            // Register before checking so a release between the check and
+            // the wait is not missed.
+            let released = self.inner.released.notified();
+            {
+                let mut queue = self.inner.queue.lock().unwrap_or_else(|p| p.into_inner());
+                let mut active = self.inner.active.lock().unwrap_or_else(|p| p.into_inner());
+                let free = limit.is_none_or(|limit| *active < limit);
+                let yields = !boosted && queue.boosted > 0;
+                if free && !yields {
+                    *active += 1;
+                    let passed = queue.waiting - queue.boosted;
+                    if boosted && limit.is_some() && passed > 0 {
+                        queue.boosted_grants += 1;
+                        metrics::increment("vrs_transcode_boosted_grants_total", &[]);
+                    }
+                    tracing::debug!(
+                        progress,
+                        boosted,
+                        active = *active,
+                        limit = ?limit,
+                        "granted transcode slot"
+                    );
+                    return TranscodePermit {
+                        inner: self.inner.clone(),
+                    };
+                }
+                if free && !deferred {
+                    deferred = true;
+                    tracing::debug!(
+                        progress,
+                        boosted_waiting = queue.boosted,
+                        "deferring new transcode to finish nearly done jobs first"
+                    );
+                }
+            }
+            let _ = tokio::time::timeout(RECHECK_INTERVAL, released).await;
+        }
+    }
+
+    pub async fn report(&self, storage: &Storage) -> LoadReport {
+        let config = self.config();
+        let reason = self.overload_reason(storage, &config).await;
+        let queue = self.inner.queue.lock().unwrap_or_else(|p| p.into_inner());
+        LoadReport {
+            waiting_transcodes: queue.waiting,
+            boosted_waiting: queue.boosted,
+            boosted_grants: queue.boosted_grants,
+            overloaded: reason.is_some(),
+            transcode_limit: limit_for(&config, reason.is_some()),
+            reason,
+            active_transcodes: *self.inner.active.lock().unwrap_or_else(|p| p.into_inner()),
+        }
+    }
+
+    async fn transcode_limit(&self, storage: &Storage, config: &SheddingConfig) -> Option<usize> {
+        let overloaded = self.overload_reason(storage, config).await.is_some();
+        limit_for(config, overloaded)
+    }
+
+    async fn overload_reason(&self, storage: &Storage, config: &SheddingConfig) -> Option<String> {
+        if let Some((sampled_at, reason)) = self
+            .inner
+            .sample
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .clone()
+            && sampled_at.elapsed() < SAMPLE_TTL
+        {
+            return reason;
+        }
+
+        let reason = sample(storage, config).await;
+        let mut cached = self.inner.sample.lock().unwrap_or_else(|p| p.into_inner());
+        let was_overloaded = cached.as_ref().is_some_and(|(_, prev)| prev.is_some());
+        match (&reason, was_overloaded) {
+            (Some(reason), false) => tracing::warn!(%reason, "shedding load"),
+            (None, true) => tracing::info!("load recovered; accepting new videos"),
+            _ => {}
+        }
+        *cached = Some((Instant::now(), reason.clone()));
+        reason
+    }
+}
+
+fn limit_for(config: &SheddingConfig, overloaded: bool) -> Option<usize> {
+    if overloaded {
+        Some(config.max_transcodes.map_or(config.shed_transcodes, |max| {
+            max.min(config.shed_transcodes)
+        }))
+    } else {
+        config.max_transcodes
+    }
+}
+
+async fn sample(storage: &Storage, config: &SheddingConfig) -> Option<String> {
+    match storage_disk_status(storage).await {
+        Ok(disk) if disk.free_bytes < config.min_free_bytes => {
+            return Some(format!(
+                "{} bytes free on the storage volume, below {}",
+                disk.free_bytes, config.min_free_bytes
+            ));
+        }
+        Ok(disk) if disk.free_ratio() < config.min_free_ratio => {
+            return Some(format!(
+                "{:.1}% free on the storage volume, below {:.1}%",
+                disk.free_ratio() * 100.0,
+                config.min_free_ratio * 100.0
+            ));
+        }
+        Ok(_) => {}
+        Err(err) => tracing::debug!(error = %err, "disk status unavailable for load shedding"),
+    }
+
+    let max_load = config.max_load_per_cpu?;
+    let load = load_per_cpu()?;
+    (load > max_load).then(|| format!("load average {load:.2} per CPU, above {max_load:.2}"))
+}
+
+/// One-minute load average divided by the CPU count. Only available on Linux.
+fn load_per_cpu() -> Option<f32> {
+    let loadavg = std::fs::read_to_string("/proc/loadavg").ok()?;
+    let one_minute: f32 = loadavg.split_whitespace().next()?.parse().ok()?;
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    Some(one_minute / cpus as f32)
+}
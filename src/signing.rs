@@ -0,0 +1,105 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::{config, error::AppError};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const DEFAULT_TTL: Duration = Duration::from_secs(3600);
+
+/// Issues and verifies playback session tokens of the form `{expires}.{signature}`,
+/// where `signature` is the hex HMAC-SHA256 of `{video_id}:{expires}` and
+/// `expires` is a unix timestamp in seconds. Enabled by `VIDEO_SIGNING_SECRET`.
+#[derive(Clone)]
+pub struct PlaybackSigner {
+    secret: Vec<u8>,
+    ttl: Duration,
+}
+
+impl PlaybackSigner {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+            ttl: DEFAULT_TTL,
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Returns a signer when signed URLs are enabled; read per request so reloads apply.
+    pub fn from_env() -> Option<Self> {
+        let secret = config::var("VIDEO_SIGNING_SECRET").filter(|secret| !secret.is_empty())?;
+        let ttl = config::parse_var::<u64>("VIDEO_SIGNED_URL_TTL_SECS")
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_TTL);
+        Some(Self::new(secret).with_ttl(ttl))
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Issues a token for `video_id` valid for the configured TTL.
+    pub fn issue(&self, video_id: &Uuid) -> String {
+        self.issue_for(video_id, self.ttl).0
+    }
+
+    /// Issues a token for `video_id` valid for `ttl`, along with the unix
+    /// timestamp it expires at.
+    pub fn issue_for(&self, video_id: &Uuid, ttl: Duration) -> (String, u64) {
+        let expires = unix_now().saturating_add(ttl.as_secs());
+        (self.sign(video_id, expires), expires)
+    }
+
+    pub fn sign(&self, video_id: &Uuid, expires_unix: u64) -> String {
+        let signature = hex::encode(self.mac(video_id, expires_unix).finalize().into_bytes());
+        format!("{expires_unix}.{signature}")
+    }
+
+    pub fn verify(&self, video_id: &Uuid, token: Option<&str>) -> Result<(), AppError> {
+        let token = token.ok_or_else(|| AppError::forbidden("playback token required"))?;
+        let (expires, signature) = token
+            .split_once('.')
+            .ok_or_else(|| AppError::forbidden("malformed playback token"))?;
+        let expires = expires
+            .parse::<u64>()
+            .map_err(|_| AppError::forbidden("malformed playback token"))?;
+        let signature =
+            hex::decode(signature).map_err(|_| AppError::forbidden("malformed playback token"))?;
+
+        self.mac(video_id, expires)
+            .verify_slice(&signature)
+            .map_err(|_| AppError::forbidden("invalid playback token"))?;
+
+        if expires < unix_now() {
+            return Err(AppError::forbidden("playback token expired"));
+        }
+        Ok(())
+    }
+
+    fn mac(&self, video_id: &Uuid, expires_unix: u64) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(format!("{}:{expires_unix}", video_id.hyphenated()).as_bytes());
+        mac
+    }
+}
+
+/// The expiry of a `{expires}.{signature}` token, whether a playback or an
+/// access token. Only meaningful once the token has been verified.
+pub(crate) fn token_expiry(token: &str) -> Option<u64> {
+    token.split_once('.')?.0.parse().ok()
+}
+
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{clock, error::AppError};
+
+const MAX_SEGMENTS: usize = 100;
+/// `CLASS` of the `EXT-X-DATERANGE` tags marking skip segments.
+pub const DATERANGE_CLASS: &str = "com.newspicel.vrs.skip";
+/// Media playlists carry no wall-clock time, so skip segments are dated
+/// relative to this `EXT-X-PROGRAM-DATE-TIME` at the start of the video.
+const PLAYLIST_EPOCH: &str = "1970-01-01T00:00:00.000Z";
+
+/// Kind of content a skip segment covers, named as in SponsorBlock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipCategory {
+    /// Paid promotion or ad read.
+    Sponsor,
+    Intro,
+    Outro,
+    /// Unpaid promotion of the creator's own products or channels.
+    Selfpromo,
+    /// Reminders to like, subscribe or follow.
+    Interaction,
+    /// Recap of earlier content or preview of what is coming.
+    Preview,
+    Filler,
+    /// Non-music section of a music video.
+    MusicOfftopic,
+}
+
+impl SkipCategory {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Sponsor => "sponsor",
+            Self::Intro => "intro",
+            Self::Outro => "outro",
+            Self::Selfpromo => "selfpromo",
+            Self::Interaction => "interaction",
+            Self::Preview => "preview",
+            Self::Filler => "filler",
+            Self::MusicOfftopic => "music_offtopic",
+        }
+    }
+}
+
+/// A range of the video, in seconds, that players may skip.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SkipSegment {
+    pub start: f64,
+    pub end: f64,
+    pub category: SkipCategory,
+}
+
+/// Checks `segments` and sorts them by start.
+pub fn validate(segments: &mut [SkipSegment]) -> Result<(), AppError> {
+    if segments.len() > MAX_SEGMENTS {
+        return Err(AppError::validation(format!(
+            "a video carries at most {MAX_SEGMENTS} skip segments"
+        ))
+        .with_code("skip_segments_invalid")
+        .with_param("max", MAX_SEGMENTS));
+    }
+    for segment in segments.iter() {
+        if !(segment.start.is_finite() && segment.end.is_finite())
+            || segment.start < 0.0
+            || segment.end <= segment.start
+        {
+            return Err(AppError::validation(format!(
+                "skip segment {}..{} must start at 0 or later and end after it starts",
+                segment.start, segment.end
+            ))
+            .with_code("skip_segments_invalid")
+            .with_param("start", segment.start)
+            .with_param("end", segment.end));
+        }
+    }
+    segments.sort_by(|a, b| a.start.total_cmp(&b.start));
+    Ok(())
+}
+
+/// Skip segments from the `sponsorblock_chapters` yt-dlp reports with
+/// `--sponsorblock-mark`. Highlights, chapters and unknown categories are
+/// left out.
+pub fn from_sponsorblock(chapters: &Value) -> Vec<SkipSegment> {
+    let mut segments: Vec<SkipSegment> = chapters
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|chapter| chapter["type"].as_str().is_none_or(|kind| kind == "skip"))
+        .filter_map(|chapter| {
+            Some(SkipSegment {
+                start: chapter["start_time"].as_f64()?,
+                end: chapter["end_time"].as_f64()?,
+                category: SkipCategory::deserialize(&chapter["category"]).ok()?,
+            })
+        })
+        .filter(|segment| segment.start >= 0.0 && segment.end > segment.start)
+        .take(MAX_SEGMENTS)
+        .collect();
+    segments.sort_by(|a, b| a.start.total_cmp(&b.start));
+    segments
+}
+
+/// Adds an `EXT-X-DATERANGE` tag per skip segment to a media playlist,
+/// anchored at an `EXT-X-PROGRAM-DATE-TIME` of the Unix epoch. Playlists
+/// that already carry a program date are returned unchanged.
+pub fn annotate_media_playlist(playlist: &str, segments: &[SkipSegment]) -> String {
+    if segments.is_empty() || playlist.contains("#EXT-X-PROGRAM-DATE-TIME") {
+        return playlist.to_string();
+    }
+    let mut tags = format!("#EXT-X-PROGRAM-DATE-TIME:{PLAYLIST_EPOCH}\n");
+    for (index, segment) in segments.iter().enumerate() {
+        let start_ms = (segment.start * 1000.0).round() as u128;
+        tags.push_str(&format!(
+            "#EXT-X-DATERANGE:ID=\"skip-{index}\",CLASS=\"{DATERANGE_CLASS}\",\
+             START-DATE=\"{}\",DURATION={:.3},X-CATEGORY=\"{}\"\n",
+            clock::rfc3339(start_ms),
+            segment.end - segment.start,
+            segment.category.as_str()
+        ));
+    }
+
+    let mut output = String::with_capacity(playlist.len() + tags.len());
+    let mut inserted = false;
+    for line in playlist.lines() {
+        let trimmed = line.trim();
+        let starts_media = trimmed.starts_with("#EXTINF")
+            || trimmed.starts_with("#EXT-X-MAP")
+            || (!trimmed.is_empty() && !trimmed.starts_with('#'));
+        if !inserted && starts_media {
+            output.push_str(&tags);
+            inserted = true;
+        }
+        output.push_str(line);
+        output.push('\n');
+    }
+    if !inserted {
+        output.push_str(&tags);
+    }
+    output
+}
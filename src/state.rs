@@ -1,11 +1,212 @@
+use std::sync::Arc;
+
 use reqwest::Client;
 
-use crate::{cleanup::CleanupConfig, jobs::DynJobStore, storage::Storage};
+use crate::{
+    alerts::{AlertCenter, AlertConfig},
+    auth::JwtAuth,
+    bandwidth::BandwidthLedger,
+    breaker::HostBreaker,
+    callbacks::ProgressCallbacks,
+    cancel::RunningJobs,
+    cleanup::CleanupConfig,
+    collections::CollectionStore,
+    config::{self, ReloadReport, Reloadable},
+    error::AppError,
+    federation::{Federation, FederationConfig},
+    hooks::{DynPipelineHook, PipelineHooks},
+    jobs::DynJobStore,
+    password::PasswordAttempts,
+    policy::DynIngestPolicy,
+    process::{DynProcessRunner, SystemProcessRunner},
+    rate_limit::{RateLimitConfig, RateLimiter},
+    replication::{ReplicationConfig, Replicator},
+    retry::RetryPolicy,
+    shaping::{IngestShaper, ShapingConfig},
+    sharding::{Dispatcher, ShardConfig},
+    shares::ShareStore,
+    shedding::LoadShedder,
+    storage::Storage,
+    transcode::HlsArchives,
+    usage::{QuotaConfig, UsageLedger},
+};
 
 #[derive(Clone)]
 pub struct AppState {
     pub storage: Storage,
     pub http_client: Client,
     pub jobs: DynJobStore,
-    pub cleanup: CleanupConfig,
+    pub cleanup: Reloadable<CleanupConfig>,
+    /// When failed pipelines run again by themselves.
+    pub retry: Reloadable<RetryPolicy>,
+    /// Whether callback URLs also receive progress.
+    pub progress_callbacks: Reloadable<ProgressCallbacks>,
+    pub process_runner: DynProcessRunner,
+    pub hooks: PipelineHooks,
+    pub policy: Option<DynIngestPolicy>,
+    pub password_attempts: PasswordAttempts,
+    pub shares: ShareStore,
+    pub collections: CollectionStore,
+    pub load: LoadShedder,
+    pub breaker: HostBreaker,
+    pub bandwidth: BandwidthLedger,
+    pub running: RunningJobs,
+    pub auth: JwtAuth,
+    pub usage: UsageLedger,
+    pub alerts: AlertCenter,
+    pub shaper: IngestShaper,
+    /// `hls.zip` builds running in the background.
+    pub hls_archives: HlsArchives,
+    /// Replication events and pushes to peer instances.
+    pub replication: Replicator,
+    /// Peers consulted for videos missing here.
+    pub federation: Federation,
+    /// Which instance runs each job when instances share the job store.
+    pub dispatcher: Dispatcher,
+    /// `VIDEO_MAX_UPLOAD_BYTES`: the largest file a client may upload.
+    pub max_upload_bytes: Option<u64>,
+    /// Token buckets on the ingestion routes.
+    pub rate_limits: RateLimiter,
+}
+
+impl AppState {
+    pub fn new(
+        storage: Storage,
+        http_client: Client,
+        jobs: DynJobStore,
+        cleanup: CleanupConfig,
+    ) -> Self {
+        Self {
+            shares: ShareStore::new(storage.clone()),
+            collections: CollectionStore::new(storage.clone()),
+            bandwidth: BandwidthLedger::new(storage.clone()),
+            usage: UsageLedger::new(storage.clone()),
+            alerts: AlertCenter::new(http_client.clone()),
+            replication: Replicator::new(storage.clone(), http_client.clone()),
+            federation: Federation::new(storage.clone(), http_client.clone()),
+            storage,
+            http_client,
+            jobs,
+            cleanup: Reloadable::new(cleanup),
+            retry: Reloadable::new(RetryPolicy::from_env()),
+            progress_callbacks: Reloadable::new(ProgressCallbacks::from_env()),
+            process_runner: Arc::new(SystemProcessRunner),
+            hooks: PipelineHooks::default(),
+            policy: None,
+            password_attempts: PasswordAttempts::default(),
+            load: LoadShedder::default(),
+            breaker: HostBreaker::default(),
+            running: RunningJobs::default(),
+            auth: JwtAuth::default(),
+            shaper: IngestShaper::new(),
+            hls_archives: HlsArchives::default(),
+            dispatcher: Dispatcher::new(ShardConfig::from_env()),
+            max_upload_bytes: None,
+            rate_limits: RateLimiter::new(RateLimitConfig::from_env()),
+        }
+    }
+
+    /// Replaces how external tools are launched, e.g. with a scripted runner in tests.
+    pub fn with_process_runner(mut self, runner: DynProcessRunner) -> Self {
+        self.process_runner = runner;
+        self
+    }
+
+    /// Registers a hook invoked at each pipeline stage boundary, after any
+    /// hooks registered earlier.
+    pub fn with_hook(mut self, hook: DynPipelineHook) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// Consults `policy` for every ingest once the source has been probed.
+    pub fn with_policy(mut self, policy: DynIngestPolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Replaces the load-shedding thresholds read from the environment.
+    pub fn with_load_shedder(mut self, load: LoadShedder) -> Self {
+        self.load = load;
+        self
+    }
+
+    /// Replaces the per-host circuit breaker thresholds read from the environment.
+    pub fn with_host_breaker(mut self, breaker: HostBreaker) -> Self {
+        self.breaker = breaker;
+        self
+    }
+
+    /// Replaces the token settings read from the environment.
+    pub fn with_jwt_auth(mut self, auth: JwtAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Replaces the automatic retry settings read from the environment.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Reloadable::new(policy);
+        self
+    }
+
+    /// Replaces the progress callback step read from the environment.
+    pub fn with_progress_callbacks(mut self, config: ProgressCallbacks) -> Self {
+        self.progress_callbacks = Reloadable::new(config);
+        self
+    }
+
+    /// Replaces the per-key quotas read from the environment.
+    pub fn with_quotas(mut self, quotas: QuotaConfig) -> Self {
+        self.usage = self.usage.with_quotas(quotas);
+        self
+    }
+
+    /// Replaces the alert thresholds and webhook read from the environment.
+    pub fn with_alerts(mut self, config: AlertConfig) -> Self {
+        self.alerts = AlertCenter::with_config(self.http_client.clone(), config);
+        self
+    }
+
+    /// Replaces the per-key ingest bandwidth budgets read from the environment.
+    pub fn with_ingest_shaping(mut self, config: ShapingConfig) -> Self {
+        self.shaper = IngestShaper::with_config(config);
+        self
+    }
+
+    /// Replaces the replication peers read from the environment.
+    pub fn with_replication(mut self, config: ReplicationConfig) -> Self {
+        self.replication =
+            Replicator::with_config(self.storage.clone(), self.http_client.clone(), config);
+        self
+    }
+
+    /// Replaces the federation peers read from the environment.
+    pub fn with_federation(mut self, config: FederationConfig) -> Self {
+        self.federation =
+            Federation::with_config(self.storage.clone(), self.http_client.clone(), config);
+        self
+    }
+
+    /// Dispatches jobs among the instances sharing the job store as this
+    /// `config`'s instance, instead of as `VIDEO_SHARDING` says.
+    pub fn with_sharding(mut self, config: ShardConfig) -> Self {
+        self.dispatcher = Dispatcher::new(Some(config));
+        self
+    }
+
+    /// Caps the size of uploaded files; larger uploads fail with `413`.
+    pub fn with_max_upload_bytes(mut self, bytes: Option<u64>) -> Self {
+        self.max_upload_bytes = bytes;
+        self
+    }
+
+    /// Re-reads the config file and swaps in settings that can change at runtime.
+    pub fn reload_config(&self) -> Result<ReloadReport, AppError> {
+        let changed = config::reload()?;
+        self.cleanup.set(CleanupConfig::from_env());
+        self.retry.set(RetryPolicy::from_env());
+        self.progress_callbacks.set(ProgressCallbacks::from_env());
+        self.rate_limits.reconfigure(RateLimitConfig::from_env());
+        Ok(ReloadReport::from_changed(changed))
+    }
 }
@@ -6,7 +6,7 @@ use std::{
 
 use tokio::fs;
 
-use crate::error::AppError;
+use crate::{error::AppError, locks::LockManager};
 
 #[derive(Clone)]
 pub struct Storage {
@@ -19,6 +19,7 @@ struct StorageInner {
     tmp_incoming_dir: PathBuf,
     tmp_hls_dir: PathBuf,
     tmp_dash_dir: PathBuf,
+    locks: LockManager,
 }
 
 impl Storage {
@@ -37,6 +38,7 @@ impl Storage {
 
         Ok(Self {
             inner: Arc::new(StorageInner {
+                locks: LockManager::new(&root),
                 root_dir: root,
                 tmp_root,
                 tmp_incoming_dir,
@@ -52,6 +54,28 @@ impl Storage {
             .join(format!("{}.incoming", id.simple()))
     }
 
+    /// Length and options of a tus upload still being received into the
+    /// incoming path.
+    pub fn tus_upload_path(&self, id: &uuid::Uuid) -> PathBuf {
+        self.inner
+            .tmp_incoming_dir
+            .join(format!("{}.tus.json", id.simple()))
+    }
+
+    /// Options of a chunked upload session, kept until it is completed.
+    pub fn upload_session_path(&self, id: &uuid::Uuid) -> PathBuf {
+        self.inner
+            .tmp_incoming_dir
+            .join(format!("{}.session.json", id.simple()))
+    }
+
+    /// Parts received by a chunked upload session, one `<n>.part` each.
+    pub fn upload_parts_dir(&self, id: &uuid::Uuid) -> PathBuf {
+        self.inner
+            .tmp_incoming_dir
+            .join(format!("{}.parts", id.simple()))
+    }
+
     pub fn video_dir(&self, id: &uuid::Uuid) -> PathBuf {
         self.inner.root_dir.join(id.hyphenated().to_string())
     }
@@ -60,14 +84,93 @@ impl Storage {
         self.video_dir(id).join("download.webm")
     }
 
+    /// Temporary output of an encode that is still running.
+    pub fn partial_encode_path(&self, id: &uuid::Uuid) -> PathBuf {
+        self.inner
+            .tmp_root
+            .join(format!("{}.encode.webm", id.simple()))
+    }
+
+    pub fn metadata_path(&self, id: &uuid::Uuid) -> PathBuf {
+        self.video_dir(id).join("meta.json")
+    }
+
+    /// Cached ffprobe report of the download, see `GET /videos/{id}/info`.
+    pub fn info_path(&self, id: &uuid::Uuid) -> PathBuf {
+        self.video_dir(id).join("info.json")
+    }
+
+    pub fn thumbnail_path(&self, id: &uuid::Uuid) -> PathBuf {
+        self.video_dir(id).join("thumbnail.jpg")
+    }
+
+    /// Frames extracted on request by `GET /videos/{id}/thumbnail`.
+    pub fn frames_dir(&self, id: &uuid::Uuid) -> PathBuf {
+        self.video_dir(id).join("frames")
+    }
+
+    pub fn sprite_path(&self, id: &uuid::Uuid) -> PathBuf {
+        self.video_dir(id).join("sprites.jpg")
+    }
+
+    pub fn storyboard_path(&self, id: &uuid::Uuid) -> PathBuf {
+        self.video_dir(id).join("thumbnails.vtt")
+    }
+
+    pub fn preview_path(&self, id: &uuid::Uuid) -> PathBuf {
+        self.video_dir(id).join("preview.webp")
+    }
+
+    pub fn proxy_path(&self, id: &uuid::Uuid) -> PathBuf {
+        self.video_dir(id).join("proxy.mp4")
+    }
+
+    /// The original upload, kept as received when `keep_source` is set.
+    pub fn source_path(&self, id: &uuid::Uuid) -> PathBuf {
+        self.video_dir(id).join("source")
+    }
+
+    /// What was captured when the video's last encode failed.
+    pub fn diagnostics_dir(&self, id: &uuid::Uuid) -> PathBuf {
+        self.video_dir(id).join("diagnostics")
+    }
+
+    pub fn captions_path(&self, id: &uuid::Uuid) -> PathBuf {
+        self.video_dir(id).join("captions.vtt")
+    }
+
+    /// One `<language>.vtt` per track of the caption set.
+    pub fn captions_dir(&self, id: &uuid::Uuid) -> PathBuf {
+        self.video_dir(id).join("captions")
+    }
+
+    /// H.264/AAC MP4 for clients that cannot play the WebM download.
+    pub fn fallback_path(&self, id: &uuid::Uuid) -> PathBuf {
+        self.video_dir(id).join("fallback.mp4")
+    }
+
     pub fn hls_dir(&self, id: &uuid::Uuid) -> PathBuf {
         self.inner.tmp_hls_dir.join(id.hyphenated().to_string())
     }
 
+    /// Zip of the video's HLS directory, see `GET /videos/{id}/hls.zip`.
+    pub fn hls_archive_path(&self, id: &uuid::Uuid) -> PathBuf {
+        self.video_dir(id).join("hls.zip")
+    }
+
     pub fn dash_dir(&self, id: &uuid::Uuid) -> PathBuf {
         self.inner.tmp_dash_dir.join(id.hyphenated().to_string())
     }
 
+    /// Where a replica pushed by a peer is unpacked before it replaces the
+    /// video's directory; on the same volume, so the swap is a rename.
+    pub fn import_dir(&self, id: &uuid::Uuid) -> PathBuf {
+        self.inner
+            .root_dir
+            .join("imports")
+            .join(id.hyphenated().to_string())
+    }
+
     pub fn tmp_dir(&self) -> PathBuf {
         self.inner.tmp_root.clone()
     }
@@ -76,7 +179,83 @@ impl Storage {
         self.inner.root_dir.clone()
     }
 
+    /// Locks that serialise packaging, cleanup and deletion of a video.
+    pub fn locks(&self) -> &LockManager {
+        &self.inner.locks
+    }
+
     pub async fn prune_transcodes(&self, id: &uuid::Uuid) -> Result<bool, AppError> {
+        let _hls = self
+            .locks()
+            .acquire(&LockManager::video_key(id, "hls"))
+            .await?;
+        let _dash = self
+            .locks()
+            .acquire(&LockManager::video_key(id, "dash"))
+            .await?;
+        self.remove_transcodes(id).await
+    }
+
+    /// Removes the stored video together with its derived renditions and
+    /// any upload still in the incoming area. Returns whether anything
+    /// existed.
+    pub async fn delete_video(&self, id: &uuid::Uuid) -> Result<bool, AppError> {
+        let _hls = self
+            .locks()
+            .acquire(&LockManager::video_key(id, "hls"))
+            .await?;
+        let _dash = self
+            .locks()
+            .acquire(&LockManager::video_key(id, "dash"))
+            .await?;
+        let mut removed = self.remove_transcodes(id).await?;
+        match fs::remove_dir_all(self.video_dir(id)).await {
+            Ok(()) => removed = true,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        for incoming in [
+            self.incoming_path(id),
+            self.tus_upload_path(id),
+            self.upload_session_path(id),
+        ] {
+            match fs::remove_file(incoming).await {
+                Ok(()) => removed = true,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        match fs::remove_dir_all(self.upload_parts_dir(id)).await {
+            Ok(()) => removed = true,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        Ok(removed)
+    }
+
+    /// Makes `staged` the video's directory, dropping the old one and any
+    /// HLS/DASH packaged from it.
+    pub async fn replace_video(&self, id: &uuid::Uuid, staged: &Path) -> Result<(), AppError> {
+        let _hls = self
+            .locks()
+            .acquire(&LockManager::video_key(id, "hls"))
+            .await?;
+        let _dash = self
+            .locks()
+            .acquire(&LockManager::video_key(id, "dash"))
+            .await?;
+        self.remove_transcodes(id).await?;
+        let video_dir = self.video_dir(id);
+        match fs::remove_dir_all(&video_dir).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        fs::rename(staged, &video_dir).await?;
+        Ok(())
+    }
+
+    async fn remove_transcodes(&self, id: &uuid::Uuid) -> Result<bool, AppError> {
         let mut pruned = false;
         let hls_dir = self.hls_dir(id);
         if hls_dir.exists() {
@@ -87,6 +266,12 @@ impl Storage {
             }
         }
 
+        match fs::remove_file(self.hls_archive_path(id)).await {
+            Ok(()) => pruned = true,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+
         let dash_dir = self.dash_dir(id);
         if dash_dir.exists() {
             match fs::remove_dir_all(&dash_dir).await {
@@ -124,3 +309,22 @@ pub async fn ensure_parent(path: &Path) -> Result<(), AppError> {
     }
     Ok(())
 }
+
+/// Total size of the files under `dir`; 0 when it does not exist.
+pub async fn dir_size(dir: &Path) -> u64 {
+    let mut total = 0;
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(mut entries) = fs::read_dir(&dir).await else {
+            continue;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            match entry.metadata().await {
+                Ok(meta) if meta.is_dir() => pending.push(entry.path()),
+                Ok(meta) => total += meta.len(),
+                Err(_) => {}
+            }
+        }
+    }
+    total
+}
@@ -0,0 +1,288 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    catalog, config, error::AppError, handlers, metadata, replication::ReplicationEvent,
+    state::AppState, storage::Storage,
+};
+
+const MAX_TAG_LEN: usize = 64;
+const MAX_TAGS_PER_VIDEO: usize = 50;
+
+/// Lowercases and validates a tag. Tags are limited to ASCII letters, digits
+/// and `-_.:` so they can appear in paths and config lists unescaped.
+pub fn normalize_tag(tag: &str) -> Result<String, AppError> {
+    let tag = tag.trim().to_ascii_lowercase();
+    let valid = !tag.is_empty()
+        && tag.len() <= MAX_TAG_LEN
+        && tag
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"-_.:".contains(&byte));
+    if !valid {
+        return Err(AppError::validation(format!(
+            "tags must be 1 to {MAX_TAG_LEN} characters of a-z, 0-9, '-', '_', '.' or ':'"
+        ))
+        .with_code("tag_invalid")
+        .with_param("max_len", MAX_TAG_LEN));
+    }
+    Ok(tag)
+}
+
+/// Per-tag limits, read from `VIDEO_TAG_RETENTION_DAYS` and
+/// `VIDEO_TAG_QUOTA_BYTES` as comma-separated `tag=value` lists.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagPolicies {
+    pub retention: BTreeMap<String, Duration>,
+    pub quota_bytes: BTreeMap<String, u64>,
+}
+
+impl TagPolicies {
+    pub fn from_env() -> Self {
+        let retention = parse_tag_values::<u64>("VIDEO_TAG_RETENTION_DAYS")
+            .into_iter()
+            .filter(|(_, days)| *days > 0)
+            .map(|(tag, days)| (tag, Duration::from_secs(days * 24 * 3600)))
+            .collect();
+        Self {
+            retention,
+            quota_bytes: parse_tag_values("VIDEO_TAG_QUOTA_BYTES"),
+        }
+    }
+}
+
+fn parse_tag_values<T: std::str::FromStr>(key: &str) -> BTreeMap<String, T> {
+    let Some(value) = config::var(key) else {
+        return BTreeMap::new();
+    };
+    value
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .filter_map(|entry| {
+            let parsed = entry.split_once('=').and_then(|(tag, value)| {
+                Some((normalize_tag(tag).ok()?, value.trim().parse::<T>().ok()?))
+            });
+            if parsed.is_none() {
+                tracing::warn!(%key, entry = entry.trim(), "ignoring malformed tag policy entry");
+            }
+            parsed
+        })
+        .collect()
+}
+
+/// A stored video as seen by tag listings.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TaggedVideo {
+    pub id: Uuid,
+    pub tags: BTreeSet<String>,
+    /// See [`catalog::listed_attributes`].
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, serde_json::Value>,
+    /// Size of the download, or 0 while it is still being produced.
+    pub size_bytes: u64,
+    pub created_at_unix_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TagSummary {
+    pub tag: String,
+    pub videos: usize,
+    pub size_bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retention_days: Option<u64>,
+}
+
+/// Videos in the storage root, keeping those tagged `tag` if given.
+pub async fn list_videos(
+    storage: &Storage,
+    tag: Option<&str>,
+) -> Result<Vec<TaggedVideo>, AppError> {
+    let mut videos = Vec::new();
+    for entry in catalog::scan(storage, tag).await? {
+        let (size_bytes, created_at_unix_ms) =
+            match fs::metadata(storage.download_path(&entry.id)).await {
+                Ok(meta) => (meta.len(), meta.modified().map(unix_ms).unwrap_or(0)),
+                Err(_) => (0, 0),
+            };
+        videos.push(TaggedVideo {
+            id: entry.id,
+            attributes: catalog::listed_attributes(&entry.meta),
+            tags: entry.meta.tags,
+            size_bytes,
+            created_at_unix_ms,
+        });
+    }
+    videos.sort_by_key(|video| (video.created_at_unix_ms, video.id));
+    Ok(videos)
+}
+
+/// Video counts and sizes for every tag in use or with a policy.
+pub async fn summarize(storage: &Storage) -> Result<Vec<TagSummary>, AppError> {
+    let policies = TagPolicies::from_env();
+    let mut totals: BTreeMap<String, (usize, u64)> = policies
+        .retention
+        .keys()
+        .chain(policies.quota_bytes.keys())
+        .map(|tag| (tag.clone(), (0, 0)))
+        .collect();
+    for video in list_videos(storage, None).await? {
+        for tag in video.tags {
+            let total = totals.entry(tag).or_default();
+            total.0 += 1;
+            total.1 += video.size_bytes;
+        }
+    }
+    Ok(totals
+        .into_iter()
+        .map(|(tag, (videos, size_bytes))| TagSummary {
+            quota_bytes: policies.quota_bytes.get(&tag).copied(),
+            retention_days: policies
+                .retention
+                .get(&tag)
+                .map(|ttl| ttl.as_secs() / (24 * 3600)),
+            tag,
+            videos,
+            size_bytes,
+        })
+        .collect())
+}
+
+/// Adds tags to a video, refusing any that would push a tag past its quota.
+/// Returns the video's full tag set.
+pub async fn add_tags(
+    storage: &Storage,
+    video_id: &Uuid,
+    tags: &[String],
+) -> Result<BTreeSet<String>, AppError> {
+    let tags = tags
+        .iter()
+        .map(|tag| normalize_tag(tag))
+        .collect::<Result<BTreeSet<_>, _>>()?;
+    let mut meta = metadata::load(storage, video_id).await?;
+    let added: Vec<&String> = tags.difference(&meta.tags).collect();
+    if added.is_empty() {
+        return Ok(meta.tags);
+    }
+    if meta.tags.len() + added.len() > MAX_TAGS_PER_VIDEO {
+        return Err(too_many_tags());
+    }
+
+    let policies = TagPolicies::from_env();
+    if added
+        .iter()
+        .any(|tag| policies.quota_bytes.contains_key(*tag))
+    {
+        let size = fs::metadata(storage.download_path(video_id))
+            .await
+            .map(|meta| meta.len())
+            .unwrap_or(0);
+        for tag in &added {
+            let Some(quota) = policies.quota_bytes.get(*tag) else {
+                continue;
+            };
+            let used: u64 = list_videos(storage, Some(tag))
+                .await?
+                .iter()
+                .map(|video| video.size_bytes)
+                .sum();
+            if used + size > *quota {
+                return Err(AppError::forbidden(format!(
+                    "tag {tag} would exceed its quota of {quota} bytes ({used} used)"
+                ))
+                .with_code("tag_quota_exceeded")
+                .with_param("tag", tag.as_str())
+                .with_param("quota_bytes", *quota)
+                .with_param("used_bytes", used));
+            }
+        }
+    }
+
+    meta.tags.extend(tags);
+    metadata::save(storage, video_id, &meta).await?;
+    Ok(meta.tags)
+}
+
+/// Normalizes the tags given for a video that has none yet. Quotas are not
+/// checked: the video has no download to count.
+pub fn initial_tags(tags: &[String]) -> Result<BTreeSet<String>, AppError> {
+    let tags = tags
+        .iter()
+        .map(|tag| normalize_tag(tag))
+        .collect::<Result<BTreeSet<_>, _>>()?;
+    if tags.len() > MAX_TAGS_PER_VIDEO {
+        return Err(too_many_tags());
+    }
+    Ok(tags)
+}
+
+fn too_many_tags() -> AppError {
+    AppError::validation(format!("a video holds at most {MAX_TAGS_PER_VIDEO} tags"))
+        .with_code("too_many_tags")
+        .with_param("max", MAX_TAGS_PER_VIDEO)
+}
+
+/// Removes one tag. Returns the remaining tags.
+pub async fn remove_tag(
+    storage: &Storage,
+    video_id: &Uuid,
+    tag: &str,
+) -> Result<BTreeSet<String>, AppError> {
+    let tag = normalize_tag(tag)?;
+    let mut meta = metadata::load(storage, video_id).await?;
+    if meta.tags.remove(&tag) {
+        metadata::save(storage, video_id, &meta).await?;
+    }
+    Ok(meta.tags)
+}
+
+/// Deletes videos carrying a tag whose retention has elapsed, measured from
+/// when the download was written, like `DELETE /videos/{id}` does. Videos
+/// with unfinished jobs are skipped. With several tags, the shortest
+/// retention wins.
+pub async fn enforce_retention(state: &AppState) -> Result<usize, AppError> {
+    let policies = TagPolicies::from_env();
+    if policies.retention.is_empty() {
+        return Ok(0);
+    }
+
+    let now = unix_ms(SystemTime::now());
+    let mut deleted = 0;
+    for video in list_videos(&state.storage, None).await? {
+        let Some(ttl) = video
+            .tags
+            .iter()
+            .filter_map(|tag| policies.retention.get(tag))
+            .min()
+        else {
+            continue;
+        };
+        if video.created_at_unix_ms == 0 || now < video.created_at_unix_ms + ttl.as_millis() as u64
+        {
+            continue;
+        }
+        match handlers::remove_video(state, video.id).await {
+            Ok(_) => {}
+            Err(err) if err.code() == "video_in_use" => continue,
+            Err(err) => return Err(err),
+        }
+        state
+            .replication
+            .emit(ReplicationEvent::Deleted { video_id: video.id });
+        tracing::info!(video_id = %video.id, tags = ?video.tags, "deleted video past tag retention");
+        deleted += 1;
+    }
+    Ok(deleted)
+}
+
+fn unix_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
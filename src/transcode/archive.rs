@@ -0,0 +1,210 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use tokio::fs;
+use uuid::Uuid;
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+use crate::{
+    blocking, error::AppError, locks::LockManager, process::DynProcessRunner, storage::Storage,
+};
+
+use super::pipeline::ensure_hls_ready;
+
+/// What a request for a video's HLS archive finds.
+#[derive(Debug)]
+pub enum HlsArchive {
+    /// An archive of the current HLS renditions.
+    Ready(PathBuf),
+    /// The archive is being built in the background.
+    Building,
+}
+
+/// Builds `hls.zip` archives of whole HLS directories in the background, one
+/// build per video at a time.
+#[derive(Clone, Default)]
+pub struct HlsArchives {
+    inner: Arc<Mutex<Builds>>,
+}
+
+#[derive(Default)]
+struct Builds {
+    running: HashSet<Uuid>,
+    /// Why the last build of a video failed, reported to the next request.
+    failed: HashMap<Uuid, String>,
+}
+
+impl HlsArchives {
+    /// Returns the video's archive when it is at least as new as every file
+    /// of its HLS renditions. Otherwise starts building it, packaging HLS
+    /// first if needed, and returns [`HlsArchive::Building`]. A failed build
+    /// is reported once; the request after that starts a new one.
+    pub async fn request(
+        &self,
+        storage: &Storage,
+        runner: &DynProcessRunner,
+        id: Uuid,
+    ) -> Result<HlsArchive, AppError> {
+        if !storage.download_path(&id).exists() {
+            return Err(AppError::not_found(format!("video {id} not found")));
+        }
+        if let Some(reason) = self.take_failure(&id) {
+            return Err(
+                AppError::transcode(format!("building the HLS archive failed: {reason}"))
+                    .with_code("hls_archive_failed")
+                    .with_param("id", id.to_string()),
+            );
+        }
+        if self.lock().running.contains(&id) {
+            return Ok(HlsArchive::Building);
+        }
+        let path = storage.hls_archive_path(&id);
+        if is_current(&path, &storage.hls_dir(&id)).await {
+            return Ok(HlsArchive::Ready(path));
+        }
+        if !self.lock().running.insert(id) {
+            return Ok(HlsArchive::Building);
+        }
+
+        let archives = self.clone();
+        let storage = storage.clone();
+        let runner = runner.clone();
+        tokio::spawn(async move {
+            let result = build(&storage, &runner, &id).await;
+            let mut builds = archives.lock();
+            builds.running.remove(&id);
+            if let Err(err) = result {
+                tracing::warn!(video_id = %id, error = %err, "failed to build HLS archive");
+                builds.failed.insert(id, err.to_string());
+            }
+        });
+        Ok(HlsArchive::Building)
+    }
+
+    fn take_failure(&self, id: &Uuid) -> Option<String> {
+        self.lock().failed.remove(id)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Builds> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+    }
+}
+
+/// Whether the archive at `path` exists and no file under `hls_dir` changed
+/// after it was written. An archive outlives a pruned HLS directory, since
+/// the renditions it holds have not changed.
+async fn is_current(path: &Path, hls_dir: &Path) -> bool {
+    let Ok(written) = fs::metadata(path).await.and_then(|meta| meta.modified()) else {
+        return false;
+    };
+    match newest_modification(hls_dir).await {
+        Ok(Some(newest)) => newest <= written,
+        Ok(None) => true,
+        Err(_) => false,
+    }
+}
+
+async fn newest_modification(dir: &Path) -> io::Result<Option<SystemTime>> {
+    let mut newest = None;
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let meta = entry.metadata().await?;
+            if meta.is_dir() {
+                pending.push(entry.path());
+            } else {
+                let modified = meta.modified()?;
+                newest = newest.max(Some(modified));
+            }
+        }
+    }
+    Ok(newest)
+}
+
+/// Packages HLS if needed and zips the directory next to the download. The
+/// HLS lock keeps cleanup and packaging away while the files are read.
+async fn build(storage: &Storage, runner: &DynProcessRunner, id: &Uuid) -> Result<(), AppError> {
+    ensure_hls_ready(storage, runner, id).await?;
+    let _lock = storage
+        .locks()
+        .acquire(&LockManager::video_key(id, "hls"))
+        .await?;
+    let hls_dir = storage.hls_dir(id);
+    let path = storage.hls_archive_path(id);
+    let partial = path.with_extension("zip.part");
+    blocking::run(move || {
+        write_archive(&hls_dir, &partial, &[])?;
+        std::fs::rename(&partial, &path)
+    })
+    .await
+    .map_err(io::Error::other)??;
+    tracing::info!(video_id = %id, "built HLS archive");
+    Ok(())
+}
+
+/// Writes every file under `dir` to a zip at `path`, under its path relative
+/// to `dir`, leaving out the top-level entries named in `skip`. Playlists,
+/// manifests and other text are deflated; media is already compressed and
+/// stored as it is.
+pub(crate) fn write_archive(dir: &Path, path: &Path, skip: &[&str]) -> io::Result<()> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in std::fs::read_dir(&current)? {
+            let entry = entry?;
+            if current == dir
+                && skip
+                    .iter()
+                    .any(|name| entry.file_name().to_str() == Some(name))
+            {
+                continue;
+            }
+            if entry.file_type()?.is_dir() {
+                pending.push(entry.path());
+            } else {
+                files.push(entry.path());
+            }
+        }
+    }
+    files.sort();
+
+    let mut zip = ZipWriter::new(std::fs::File::create(path)?);
+    for file in files {
+        let Ok(name) = file.strip_prefix(dir) else {
+            continue;
+        };
+        let name = name
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let size = std::fs::metadata(&file)?.len();
+        let method = if [".m3u8", ".mpd", ".vtt", ".json"]
+            .iter()
+            .any(|extension| name.ends_with(extension))
+        {
+            CompressionMethod::Deflated
+        } else {
+            CompressionMethod::Stored
+        };
+        let options = SimpleFileOptions::default()
+            .compression_method(method)
+            .large_file(size > u64::from(u32::MAX));
+        zip.start_file(name, options).map_err(io::Error::other)?;
+        io::copy(&mut std::fs::File::open(&file)?, &mut zip)?;
+    }
+    zip.finish().map_err(io::Error::other)?;
+    Ok(())
+}
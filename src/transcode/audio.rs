@@ -0,0 +1,95 @@
+use std::path::Path;
+
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{error::AppError, jobs::DynJobStore, process::DynProcessRunner, storage::Storage};
+
+use super::{
+    config::{AudioPresentation, EncodeParams},
+    ffmpeg::{FfmpegProgressConfig, run_ffmpeg, run_ffmpeg_with_progress},
+    probe::{probe_duration, probe_has_audio, probe_has_video},
+    util::{finalize_encoded_file, os, os_path},
+};
+
+const VISUAL_SIZE: &str = "1280x720";
+const VISUAL_FPS: u32 = 30;
+
+/// Replaces an audio-only `input` with a waveform or spectrogram video, in
+/// place, when the upload asked for one. Returns false when nothing was
+/// rendered: the presentation is `AudioOnly` or the input has a video stream.
+pub async fn render_audio_visual(
+    storage: &Storage,
+    jobs: &DynJobStore,
+    runner: &DynProcessRunner,
+    id: &Uuid,
+    input: &Path,
+    encode: Option<&EncodeParams>,
+) -> Result<bool, AppError> {
+    let presentation = encode
+        .map(|params| params.audio_presentation)
+        .unwrap_or_default();
+    let visual = match presentation {
+        AudioPresentation::AudioOnly => return Ok(false),
+        AudioPresentation::Waveform => {
+            format!("showwaves=s={VISUAL_SIZE}:mode=cline:rate={VISUAL_FPS}:colors=white")
+        }
+        AudioPresentation::Spectrogram => format!(
+            "showspectrum=s={VISUAL_SIZE}:slide=scroll:mode=combined:color=intensity:scale=log,fps={VISUAL_FPS}"
+        ),
+    };
+    if !probe_has_audio(runner, input).await? || probe_has_video(runner, input).await? {
+        return Ok(false);
+    }
+
+    let output = storage
+        .tmp_dir()
+        .join(format!("{}.visual.mkv", id.simple()));
+    let args = vec![
+        os("-y"),
+        os("-i"),
+        os_path(input),
+        os("-filter_complex"),
+        os(format!("[0:a]{visual},format=yuv420p[v]")),
+        os("-map"),
+        os("[v]"),
+        os("-map"),
+        os("0:a:0"),
+        os("-c:v"),
+        os("libx264"),
+        os("-preset"),
+        os("veryfast"),
+        os("-crf"),
+        os("20"),
+        os("-c:a"),
+        os("aac"),
+        os("-b:a"),
+        os("192k"),
+        os("-shortest"),
+        os_path(&output),
+    ];
+
+    let result = match probe_duration(runner, input).await.unwrap_or(None) {
+        Some(total) => {
+            run_ffmpeg_with_progress(
+                runner,
+                args,
+                FfmpegProgressConfig {
+                    total_duration: total,
+                    jobs: jobs.clone(),
+                    job_id: *id,
+                    operation: "render_audio_visual",
+                },
+            )
+            .await
+        }
+        None => run_ffmpeg(runner, args).await,
+    };
+    if let Err(err) = result {
+        fs::remove_file(&output).await.ok();
+        return Err(err);
+    }
+
+    finalize_encoded_file(&output, input).await?;
+    Ok(true)
+}
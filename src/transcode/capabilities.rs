@@ -0,0 +1,266 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::{Mutex, MutexGuard},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use tokio::sync::OnceCell;
+
+use crate::{error::AppError, process::DynProcessRunner};
+
+use super::{config::EncoderKind, util::os};
+
+const FFMPEG_BIN: &str = "ffmpeg";
+
+const MAX_SIGNATURE_LEN: usize = 160;
+
+static ENCODER_CAPABILITIES: OnceCell<EncoderCapabilities> = OnceCell::const_new();
+static ENCODER_FAILURES: FailureRegistry = FailureRegistry::new();
+
+/// Encoder failures by encoder and error signature.
+struct FailureRegistry {
+    failures: Mutex<BTreeMap<(EncoderKind, String), EncoderFailure>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EncoderCapabilities {
+    pub ffmpeg_available: bool,
+    pub encoders: Vec<EncoderAvailability>,
+    /// Encoders that failed while a fallback succeeded on the same input.
+    pub failures: Vec<EncoderFailure>,
+}
+
+impl EncoderCapabilities {
+    /// Rejects an encoder a request asked for by name when the local ffmpeg
+    /// lacks it or it is blacklisted.
+    pub fn ensure_usable(&self, encoder: EncoderKind) -> Result<(), AppError> {
+        let availability = self
+            .encoders
+            .iter()
+            .find(|availability| availability.name == encoder.label());
+        let reason = match availability {
+            Some(availability) if availability.blacklisted => "is blacklisted after failing",
+            Some(availability) if availability.available => return Ok(()),
+            _ => "is not available in the local ffmpeg",
+        };
+        Err(
+            AppError::validation(format!("encoder {} {reason}", encoder.label()))
+                .with_code("encoder_unavailable")
+                .with_param("encoder", encoder.label()),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EncoderAvailability {
+    pub name: &'static str,
+    pub codec: &'static str,
+    pub available: bool,
+    /// Skipped by new encodes until the failures are cleared.
+    pub blacklisted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EncoderFailure {
+    pub encoder: &'static str,
+    /// The error with numbers masked, so repeats group together.
+    pub signature: String,
+    pub count: u32,
+    pub first_failed_at_unix_ms: u64,
+    pub last_failed_at_unix_ms: u64,
+}
+
+/// Lists which AV1 encoders the local ffmpeg build ships with, and which
+/// have been blacklisted after failing at runtime. The probe runs once per
+/// process; hardware presence is only confirmed when an encode runs.
+pub async fn encoder_capabilities(runner: &DynProcessRunner) -> EncoderCapabilities {
+    let mut capabilities = ENCODER_CAPABILITIES
+        .get_or_init(|| probe_encoders(runner))
+        .await
+        .clone();
+    let failures = ENCODER_FAILURES.lock();
+    for availability in &mut capabilities.encoders {
+        availability.blacklisted = failures
+            .values()
+            .any(|failure| failure.encoder == availability.name);
+    }
+    capabilities.failures = failures.values().cloned().collect();
+    capabilities
+}
+
+/// Remembers, for the rest of the process lifetime, that `encoder` failed
+/// where a fallback encoder then succeeded. Such failures point at the
+/// encoder path (driver, device, missing hardware) rather than the input.
+pub(crate) fn record_encoder_failure(encoder: EncoderKind, error: &str) {
+    ENCODER_FAILURES.record(encoder, error);
+}
+
+/// Whether new encodes should skip `encoder`. Software encoding is the last
+/// resort and is never blacklisted.
+pub(crate) fn is_blacklisted(encoder: EncoderKind) -> bool {
+    ENCODER_FAILURES.is_blacklisted(encoder)
+}
+
+/// Forgets all recorded failures, e.g. after a driver fix. Returns how many
+/// were cleared.
+pub fn clear_encoder_failures() -> usize {
+    ENCODER_FAILURES.clear()
+}
+
+impl FailureRegistry {
+    const fn new() -> Self {
+        Self {
+            failures: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<(EncoderKind, String), EncoderFailure>> {
+        self.failures
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+    }
+
+    fn record(&self, encoder: EncoderKind, error: &str) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let signature = error_signature(error);
+        tracing::warn!(encoder = encoder.label(), %signature, "blacklisting encoder");
+        self.lock()
+            .entry((encoder, signature.clone()))
+            .and_modify(|failure| {
+                failure.count += 1;
+                failure.last_failed_at_unix_ms = now;
+            })
+            .or_insert(EncoderFailure {
+                encoder: encoder.label(),
+                signature,
+                count: 1,
+                first_failed_at_unix_ms: now,
+                last_failed_at_unix_ms: now,
+            });
+    }
+
+    fn is_blacklisted(&self, encoder: EncoderKind) -> bool {
+        encoder != EncoderKind::SoftwareAv1 && self.lock().keys().any(|(kind, _)| *kind == encoder)
+    }
+
+    fn clear(&self) -> usize {
+        let mut failures = self.lock();
+        let cleared = failures.len();
+        failures.clear();
+        cleared
+    }
+}
+
+fn error_signature(error: &str) -> String {
+    let line = error
+        .lines()
+        .rev()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or("unknown error");
+    let mut signature = String::with_capacity(line.len().min(MAX_SIGNATURE_LEN));
+    for ch in line.chars() {
+        if signature.len() >= MAX_SIGNATURE_LEN {
+            break;
+        }
+        if ch.is_ascii_digit() {
+            if !signature.ends_with('#') {
+                signature.push('#');
+            }
+        } else {
+            signature.push(ch);
+        }
+    }
+    signature
+}
+
+async fn probe_encoders(runner: &DynProcessRunner) -> EncoderCapabilities {
+    let output = runner
+        .output(FFMPEG_BIN, &[os("-hide_banner"), os("-encoders")])
+        .await;
+
+    let compiled: Option<HashSet<String>> = match output {
+        Ok(output) if output.status.success() => Some(parse_encoder_names(
+            &String::from_utf8_lossy(&output.stdout),
+        )),
+        Ok(output) => {
+            tracing::warn!(status = %output.status, "ffmpeg encoder probe failed");
+            None
+        }
+        Err(err) => {
+            tracing::warn!(error = %err, "ffmpeg unavailable for encoder probe");
+            None
+        }
+    };
+
+    let encoders = EncoderKind::ALL
+        .iter()
+        .map(|kind| EncoderAvailability {
+            name: kind.label(),
+            codec: kind.ffmpeg_codec(),
+            available: compiled
+                .as_ref()
+                .is_some_and(|names| names.contains(kind.ffmpeg_codec())),
+            blacklisted: false,
+        })
+        .collect();
+
+    EncoderCapabilities {
+        ffmpeg_available: compiled.is_some(),
+        encoders,
+        failures: Vec::new(),
+    }
+}
+
+fn parse_encoder_names(listing: &str) -> HashSet<String> {
+    listing
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let flags = parts.next()?;
+            let name = parts.next()?;
+            (flags.len() == 6 && !flags.contains('=')).then(|| name.to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_masks_numbers_and_keeps_last_line() {
+        assert_eq!(
+            error_signature("ffmpeg failed\nCannot load libcuda.so.1 (status 127)\n"),
+            "Cannot load libcuda.so.# (status #)"
+        );
+        assert_eq!(error_signature(""), "unknown error");
+    }
+
+    #[test]
+    fn failures_blacklist_hardware_encoders_until_cleared() {
+        // A registry of its own, so failures other tests record on the
+        // process-wide one cannot change the counts.
+        let registry = FailureRegistry::new();
+        registry.record(EncoderKind::NvencAv1, "ffmpeg exited with status 1");
+        registry.record(EncoderKind::NvencAv1, "ffmpeg exited with status 234");
+        registry.record(EncoderKind::SoftwareAv1, "ffmpeg exited with status 1");
+        assert!(registry.is_blacklisted(EncoderKind::NvencAv1));
+        assert!(!registry.is_blacklisted(EncoderKind::SoftwareAv1));
+
+        let failures = registry.lock();
+        let nvenc = &failures[&(
+            EncoderKind::NvencAv1,
+            "ffmpeg exited with status #".to_string(),
+        )];
+        assert_eq!(nvenc.count, 2);
+        drop(failures);
+
+        assert_eq!(registry.clear(), 2);
+        assert!(!registry.is_blacklisted(EncoderKind::NvencAv1));
+    }
+}
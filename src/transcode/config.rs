@@ -1,10 +1,212 @@
-use std::env;
+use std::ffi::OsString;
 
-#[derive(Clone, Copy, Debug)]
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+
+use super::{
+    capabilities::is_blacklisted, probe::SourceColor, profile::TranscodeProfile, util::os,
+};
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct EncodeParams {
     pub crf: u8,
     pub cpu_used: u8,
-    pub(crate) encoder: Option<EncoderKind>,
+    pub profile: TranscodeProfile,
+    pub slideshow: SlideshowParams,
+    pub audio_presentation: AudioPresentation,
+    /// Render `preview.webp`, a short looping clip, while finalizing.
+    #[serde(default)]
+    pub preview: bool,
+    /// Render `proxy.mp4`, a quick 480p H.264 copy, alongside the encode.
+    #[serde(default)]
+    pub proxy: bool,
+    /// Stop after a review proxy until the job is approved.
+    #[serde(default)]
+    pub approval: bool,
+    /// When the encode fails partway, publish the part of the source before
+    /// the failure instead of failing the job.
+    #[serde(default)]
+    pub salvage: bool,
+    /// Keep the original upload next to the encodes. Unset follows
+    /// `VIDEO_KEEP_SOURCE`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_source: Option<bool>,
+    /// Cut the ladder from the source while the download encodes. Unset
+    /// follows `VIDEO_LADDER_FROM_SOURCE`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ladder_from_source: Option<bool>,
+    #[serde(default)]
+    pub ladder: LadderLimits,
+    /// Encoder tried first, ahead of `VIDEO_SERVER_ENCODER` and the platform
+    /// defaults. Software AV1 stays the fallback.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoder: Option<EncoderKind>,
+}
+
+/// Bounds on the HLS/DASH ladder, kept with the video so later packaging
+/// runs shape it the same way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LadderLimits {
+    /// Rungs taller than this are left out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_height: Option<u32>,
+    /// Rungs with a higher average bitrate are left out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bitrate_kbps: Option<u32>,
+    /// Add 240p and 144p rungs below the ladder. Unset follows the profile.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub low_rungs: Option<bool>,
+}
+
+impl LadderLimits {
+    pub fn is_default(&self) -> bool {
+        self.max_height.is_none() && self.max_bitrate_kbps.is_none() && self.low_rungs.is_none()
+    }
+}
+
+/// How still-image uploads are turned into video.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SlideshowParams {
+    /// How long each image stays on screen.
+    pub image_seconds: f32,
+    pub fps: u32,
+}
+
+/// Codec of the intermediate download that the HLS/DASH ladder is cut from,
+/// chosen independently of the delivery codecs. AV1 keeps the download
+/// small; H.264 and HEVC encode many times faster and suit hosts that mostly
+/// serve the ladder.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MezzanineCodec {
+    #[default]
+    Av1,
+    H264,
+    Hevc,
+}
+
+impl MezzanineCodec {
+    /// Reads `VIDEO_MEZZANINE_CODEC`; unknown values fall back to AV1.
+    pub fn from_env() -> Self {
+        match config::var("VIDEO_MEZZANINE_CODEC")
+            .map(|value| value.trim().to_ascii_lowercase())
+            .as_deref()
+        {
+            None | Some("") | Some("av1") => Self::Av1,
+            Some("h264" | "avc") => Self::H264,
+            Some("hevc" | "h265") => Self::Hevc,
+            Some(other) => {
+                tracing::warn!(value = %other, "unknown VIDEO_MEZZANINE_CODEC; using av1");
+                Self::Av1
+            }
+        }
+    }
+
+    /// AV1 downloads are WebM; H.264 and HEVC need full Matroska.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Av1 => "video/webm",
+            Self::H264 | Self::Hevc => "video/x-matroska",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Av1 => "webm",
+            Self::H264 | Self::Hevc => "mkv",
+        }
+    }
+}
+
+/// Bit depth and colour tags of a video's encodes, decided from the source
+/// at ingest and kept in `meta.json` so regenerated streams match.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputColor {
+    /// Encode 10-bit 4:2:0 instead of 8-bit. H.264 output stays 8-bit.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ten_bit: bool,
+    /// ffmpeg names of the source's tags, e.g. `bt709` or `bt2020`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub primaries: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transfer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matrix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<String>,
+}
+
+impl OutputColor {
+    /// Carries the source's colour tags over and picks the bit depth from
+    /// `VIDEO_OUTPUT_BIT_DEPTH`: `8` (default), `10`, or `source` for
+    /// 10-bit output from sources deeper than 8 bits.
+    pub(crate) fn for_source(source: &SourceColor) -> Self {
+        let ten_bit = match config::var("VIDEO_OUTPUT_BIT_DEPTH")
+            .map(|value| value.trim().to_ascii_lowercase())
+            .as_deref()
+        {
+            None | Some("" | "8") => false,
+            Some("10") => true,
+            Some("source") => source.bit_depth > 8,
+            Some(other) => {
+                tracing::warn!(value = %other, "unknown VIDEO_OUTPUT_BIT_DEPTH; using 8");
+                false
+            }
+        };
+        Self {
+            ten_bit,
+            primaries: source.primaries.clone(),
+            transfer: source.transfer.clone(),
+            matrix: source.matrix.clone(),
+            range: source.range.clone(),
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// `eight_bit` or `ten_bit`, whichever pixel format this output uses.
+    pub(crate) fn pix_fmt(&self, eight_bit: &'static str, ten_bit: &'static str) -> &'static str {
+        if self.ten_bit { ten_bit } else { eight_bit }
+    }
+
+    /// Tags the encoded stream with the source's colour description.
+    pub(crate) fn tag_args(&self) -> Vec<OsString> {
+        let tags = [
+            ("-color_primaries", &self.primaries),
+            ("-color_trc", &self.transfer),
+            ("-colorspace", &self.matrix),
+            ("-color_range", &self.range),
+        ];
+        tags.into_iter()
+            .filter_map(|(flag, value)| value.as_ref().map(|value| [os(flag), os(value)]))
+            .flatten()
+            .collect()
+    }
+}
+
+/// How uploads without a video stream are published.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioPresentation {
+    /// Audio-only download plus audio-only HLS and DASH.
+    #[default]
+    AudioOnly,
+    /// Render an animated waveform video and run the normal ladder.
+    Waveform,
+    /// Render a scrolling spectrogram video and run the normal ladder.
+    Spectrogram,
+}
+
+impl Default for SlideshowParams {
+    fn default() -> Self {
+        Self {
+            image_seconds: 5.0,
+            fps: 30,
+        }
+    }
 }
 
 impl EncodeParams {
@@ -12,6 +214,23 @@ impl EncodeParams {
         Self {
             crf: self.crf.clamp(0, 63),
             cpu_used: self.cpu_used.clamp(0, 8),
+            profile: self.profile,
+            slideshow: SlideshowParams {
+                image_seconds: self.slideshow.image_seconds.clamp(0.1, 600.0),
+                fps: self.slideshow.fps.clamp(1, 120),
+            },
+            audio_presentation: self.audio_presentation,
+            preview: self.preview,
+            proxy: self.proxy,
+            approval: self.approval,
+            salvage: self.salvage,
+            keep_source: self.keep_source,
+            ladder_from_source: self.ladder_from_source,
+            ladder: LadderLimits {
+                max_height: self.ladder.max_height.filter(|height| *height > 0),
+                max_bitrate_kbps: self.ladder.max_bitrate_kbps.filter(|kbps| *kbps > 0),
+                low_rungs: self.ladder.low_rungs,
+            },
             encoder: self.encoder,
         }
     }
@@ -19,6 +238,26 @@ impl EncodeParams {
     pub(crate) fn preferred_encoder(&self) -> Option<EncoderKind> {
         self.encoder
     }
+
+    /// Whether the original upload is kept, falling back to
+    /// `VIDEO_KEEP_SOURCE`.
+    pub fn keeps_source(&self) -> bool {
+        self.keep_source.unwrap_or_else(|| {
+            config::var("VIDEO_KEEP_SOURCE")
+                .map(|value| matches!(value.trim(), "1" | "true" | "yes" | "on"))
+                .unwrap_or(false)
+        })
+    }
+
+    /// Whether the ladder is cut from the source, falling back to
+    /// `VIDEO_LADDER_FROM_SOURCE`.
+    pub fn ladders_from_source(&self) -> bool {
+        self.ladder_from_source.unwrap_or_else(|| {
+            config::var("VIDEO_LADDER_FROM_SOURCE")
+                .map(|value| matches!(value.trim(), "1" | "true" | "yes" | "on"))
+                .unwrap_or(false)
+        })
+    }
 }
 
 impl Default for EncodeParams {
@@ -26,23 +265,69 @@ impl Default for EncodeParams {
         Self {
             crf: 24,
             cpu_used: 4,
+            profile: TranscodeProfile::default(),
+            slideshow: SlideshowParams::default(),
+            audio_presentation: AudioPresentation::default(),
+            preview: false,
+            proxy: false,
+            approval: false,
+            salvage: false,
+            keep_source: None,
+            ladder_from_source: None,
+            ladder: LadderLimits::default(),
             encoder: None,
         }
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub(crate) enum EncoderKind {
+/// AV1 encoder backends, named as in `VIDEO_SERVER_ENCODER`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum EncoderKind {
+    #[serde(rename = "videotoolbox")]
     VideoToolboxAv1,
+    #[serde(rename = "nvenc")]
     NvencAv1,
+    #[serde(rename = "qsv")]
     QsvAv1,
+    #[serde(rename = "vaapi")]
     VaapiAv1,
+    /// libaom.
+    #[serde(rename = "software")]
     SoftwareAv1,
 }
 
-fn encoder_from_env() -> Option<EncoderKind> {
-    env::var("VIDEO_SERVER_ENCODER").ok().and_then(|value| {
-        match value.to_ascii_lowercase().as_str() {
+impl EncoderKind {
+    pub(crate) const ALL: [EncoderKind; 5] = [
+        EncoderKind::VideoToolboxAv1,
+        EncoderKind::NvencAv1,
+        EncoderKind::QsvAv1,
+        EncoderKind::VaapiAv1,
+        EncoderKind::SoftwareAv1,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            EncoderKind::VideoToolboxAv1 => "videotoolbox",
+            EncoderKind::NvencAv1 => "nvenc",
+            EncoderKind::QsvAv1 => "qsv",
+            EncoderKind::VaapiAv1 => "vaapi",
+            EncoderKind::SoftwareAv1 => "software",
+        }
+    }
+
+    pub fn ffmpeg_codec(&self) -> &'static str {
+        match self {
+            EncoderKind::VideoToolboxAv1 => "av1_videotoolbox",
+            EncoderKind::NvencAv1 => "av1_nvenc",
+            EncoderKind::QsvAv1 => "av1_qsv",
+            EncoderKind::VaapiAv1 => "av1_vaapi",
+            EncoderKind::SoftwareAv1 => "libaom-av1",
+        }
+    }
+
+    /// Parses a name as accepted by `VIDEO_SERVER_ENCODER`, ignoring case.
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
             "videotoolbox" | "vt" => Some(EncoderKind::VideoToolboxAv1),
             "nvenc" | "cuda" => Some(EncoderKind::NvencAv1),
             "qsv" | "quicksync" => Some(EncoderKind::QsvAv1),
@@ -50,7 +335,11 @@ fn encoder_from_env() -> Option<EncoderKind> {
             "software" | "cpu" => Some(EncoderKind::SoftwareAv1),
             _ => None,
         }
-    })
+    }
+}
+
+fn encoder_from_env() -> Option<EncoderKind> {
+    config::var("VIDEO_SERVER_ENCODER").and_then(|value| EncoderKind::from_name(&value))
 }
 
 pub(crate) fn encoder_candidates(explicit: Option<EncoderKind>) -> Vec<EncoderKind> {
@@ -76,5 +365,12 @@ pub(crate) fn encoder_candidates(explicit: Option<EncoderKind>) -> Vec<EncoderKi
     order.push(EncoderKind::SoftwareAv1);
     order.sort_unstable();
     order.dedup();
+    order.retain(|kind| {
+        let skip = is_blacklisted(*kind);
+        if skip {
+            tracing::debug!(encoder = kind.label(), "skipping blacklisted encoder");
+        }
+        !skip
+    });
     order
 }
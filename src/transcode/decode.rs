@@ -0,0 +1,128 @@
+use std::{collections::BTreeSet, ffi::OsString, future::Future, sync::Mutex};
+
+use crate::{config, error::AppError};
+
+use super::util::os;
+
+/// Hardware decoders that failed where another decoder then succeeded on the
+/// same input; skipped for the rest of the process lifetime.
+static FAILED_DECODERS: Mutex<BTreeSet<DecoderKind>> = Mutex::new(BTreeSet::new());
+
+/// How the mezzanine is decoded while packaging the rendition ladder. Frames
+/// are copied back to system memory, so the software scaler and ladder
+/// encoders work unchanged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum DecoderKind {
+    VideoToolbox,
+    Cuda,
+    Vaapi,
+    Software,
+}
+
+impl DecoderKind {
+    pub(crate) fn label(&self) -> &'static str {
+        match self {
+            DecoderKind::VideoToolbox => "videotoolbox",
+            DecoderKind::Cuda => "cuda",
+            DecoderKind::Vaapi => "vaapi",
+            DecoderKind::Software => "software",
+        }
+    }
+
+    /// Input options that must precede `-i`.
+    pub(crate) fn input_args(&self) -> Vec<OsString> {
+        match self {
+            DecoderKind::VideoToolbox => vec![os("-hwaccel"), os("videotoolbox")],
+            DecoderKind::Cuda => vec![os("-hwaccel"), os("cuda")],
+            DecoderKind::Vaapi => {
+                let device = config::var("VIDEO_VAAPI_DEVICE")
+                    .unwrap_or_else(|| "/dev/dri/renderD128".into());
+                vec![
+                    os("-hwaccel"),
+                    os("vaapi"),
+                    os("-hwaccel_device"),
+                    os(device),
+                ]
+            }
+            DecoderKind::Software => Vec::new(),
+        }
+    }
+}
+
+/// Reads `VIDEO_HWACCEL_DECODE`: `auto` (default) tries the platform's
+/// hardware decoders, a decoder name pins one, and `off` decodes in software.
+pub(crate) fn decoder_candidates() -> Vec<DecoderKind> {
+    let setting = config::var("VIDEO_HWACCEL_DECODE")
+        .map(|value| value.trim().to_ascii_lowercase())
+        .unwrap_or_else(|| "auto".into());
+    let mut order = Vec::new();
+    match setting.as_str() {
+        "videotoolbox" | "vt" => order.push(DecoderKind::VideoToolbox),
+        "cuda" | "nvdec" => order.push(DecoderKind::Cuda),
+        "vaapi" => order.push(DecoderKind::Vaapi),
+        "off" | "software" | "cpu" | "none" => {}
+        other => {
+            if other != "auto" {
+                tracing::warn!(value = %other, "unknown VIDEO_HWACCEL_DECODE; using auto");
+            }
+            #[cfg(target_os = "macos")]
+            {
+                order.push(DecoderKind::VideoToolbox);
+            }
+            #[cfg(target_os = "windows")]
+            {
+                order.push(DecoderKind::Cuda);
+            }
+            #[cfg(target_os = "linux")]
+            {
+                order.push(DecoderKind::Vaapi);
+                order.push(DecoderKind::Cuda);
+            }
+        }
+    }
+    let failed = FAILED_DECODERS
+        .lock()
+        .unwrap_or_else(|poison| poison.into_inner());
+    order.retain(|kind| !failed.contains(kind));
+    order.push(DecoderKind::Software);
+    order
+}
+
+/// Runs `attempt` with each decoder candidate until one succeeds. Hardware
+/// decoders that failed before another one succeeded are remembered and
+/// skipped by later runs; if every decoder fails, the input is at fault and
+/// nothing is remembered.
+pub(crate) async fn with_decoder_fallback<F, Fut>(mut attempt: F) -> Result<(), AppError>
+where
+    F: FnMut(DecoderKind) -> Fut,
+    Fut: Future<Output = Result<(), AppError>>,
+{
+    let mut failed: Vec<DecoderKind> = Vec::new();
+    let mut last_error = None;
+    for decoder in decoder_candidates() {
+        match attempt(decoder).await {
+            Ok(()) => {
+                let mut remembered = FAILED_DECODERS
+                    .lock()
+                    .unwrap_or_else(|poison| poison.into_inner());
+                for kind in failed {
+                    tracing::warn!(decoder = kind.label(), "disabling hardware decoder");
+                    remembered.insert(kind);
+                }
+                return Ok(());
+            }
+            Err(err) => {
+                if decoder != DecoderKind::Software {
+                    tracing::warn!(
+                        decoder = decoder.label(),
+                        error = %err,
+                        "hardware decode failed, falling back"
+                    );
+                    failed.push(decoder);
+                }
+                last_error = Some(err);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| AppError::transcode("no decoder available")))
+}
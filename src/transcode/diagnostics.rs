@@ -0,0 +1,158 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    clock,
+    error::AppError,
+    process::DynProcessRunner,
+    storage::{Storage, ensure_dir},
+};
+
+use super::{
+    ffmpeg::{POSITION_PARAM, run_ffmpeg},
+    probe::{MediaInfo, probe_media_info},
+    util::{os, os_path},
+};
+
+/// Height the failure frame is scaled down to.
+const FRAME_HEIGHT: u32 = 720;
+
+/// What was known about a source when its encode failed, kept as
+/// `diagnostics/report.json` in the video directory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FailureReport {
+    pub error: String,
+    pub code: String,
+    pub failed_at: String,
+    /// Input time ffmpeg last reported before it failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position_seconds: Option<f64>,
+    /// `position_seconds` as `HH:MM:SS.mmm`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<String>,
+    /// The source's container and streams, when ffprobe could still read
+    /// them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media: Option<MediaInfo>,
+    /// Why ffprobe could not.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probe_error: Option<String>,
+    /// Whether `diagnostics/frame.jpg` holds the frame at `position_seconds`.
+    pub frame: bool,
+}
+
+/// Probes `input` and extracts the frame where the encode of video `id`
+/// stopped with `err`, replacing any earlier report. Either may fail on a
+/// broken source; the report then says so.
+pub async fn capture_failure(
+    storage: &Storage,
+    runner: &DynProcessRunner,
+    id: &Uuid,
+    input: &Path,
+    err: &AppError,
+) -> Result<FailureReport, AppError> {
+    let dir = storage.diagnostics_dir(id);
+    ensure_dir(&dir).await?;
+    let position_seconds = err.params().get(POSITION_PARAM).and_then(|v| v.as_f64());
+    let (media, probe_error) = match probe_media_info(runner, input).await {
+        Ok(media) => (Some(media), None),
+        Err(probe_err) => (None, Some(probe_err.to_string())),
+    };
+    let frame_path = dir.join("frame.jpg");
+    fs::remove_file(&frame_path).await.ok();
+    let frame = match position_seconds {
+        Some(at) => match extract_frame(runner, input, at, &frame_path).await {
+            Ok(()) => true,
+            Err(frame_err) => {
+                tracing::debug!(video_id = %id, error = %frame_err, "no frame at the failure");
+                false
+            }
+        },
+        None => false,
+    };
+
+    let report = FailureReport {
+        error: err.to_string(),
+        code: err.code().to_string(),
+        failed_at: clock::rfc3339(clock::unix_ms(std::time::SystemTime::now())),
+        position_seconds,
+        position: position_seconds.map(timecode),
+        media,
+        probe_error,
+        frame,
+    };
+    let bytes = serde_json::to_vec_pretty(&report).map_err(std::io::Error::from)?;
+    let path = dir.join("report.json");
+    let temp = path.with_extension("json.tmp");
+    fs::write(&temp, bytes).await?;
+    fs::rename(&temp, &path).await?;
+    Ok(report)
+}
+
+/// The failure report of video `id`, if its last encode failed.
+pub async fn load_failure(storage: &Storage, id: &Uuid) -> Result<Option<FailureReport>, AppError> {
+    match fs::read(storage.diagnostics_dir(id).join("report.json")).await {
+        Ok(bytes) => Ok(Some(
+            serde_json::from_slice(&bytes).map_err(std::io::Error::from)?,
+        )),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Forgets the failure of video `id`, before it is encoded again.
+pub async fn clear_failure(storage: &Storage, id: &Uuid) -> Result<(), AppError> {
+    match fs::remove_dir_all(storage.diagnostics_dir(id)).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+async fn extract_frame(
+    runner: &DynProcessRunner,
+    input: &Path,
+    at: f64,
+    target: &Path,
+) -> Result<(), AppError> {
+    let temp = target.with_extension("part");
+    let args = vec![
+        os("-y"),
+        os("-ss"),
+        os(format!("{at:.3}")),
+        os("-i"),
+        os_path(input),
+        os("-frames:v"),
+        os("1"),
+        os("-vf"),
+        os(format!("scale=-2:'min({FRAME_HEIGHT},ih)'")),
+        os("-c:v"),
+        os("mjpeg"),
+        os("-q:v"),
+        os("3"),
+        os("-f"),
+        os("image2"),
+        os("-update"),
+        os("1"),
+        os_path(&temp),
+    ];
+    if let Err(err) = run_ffmpeg(runner, args).await {
+        fs::remove_file(&temp).await.ok();
+        return Err(err);
+    }
+    fs::rename(&temp, target).await?;
+    Ok(())
+}
+
+fn timecode(seconds: f64) -> String {
+    let millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
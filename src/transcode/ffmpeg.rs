@@ -1,38 +1,92 @@
 use std::{
+    collections::BTreeSet,
     ffi::OsString,
-    process::Stdio,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
     time::{Duration, Instant},
 };
 
-use tokio::{
-    io::AsyncReadExt,
-    process::{ChildStderr, Command},
-};
+use tokio::io::AsyncReadExt;
 use uuid::Uuid;
 
-use crate::{error::AppError, jobs::DynJobStore};
+use crate::{
+    config,
+    error::AppError,
+    jobs::DynJobStore,
+    metrics,
+    process::{DynProcessRunner, ProcessStderr},
+};
 
-use super::util::map_io_error;
+use super::{logs, util::map_io_error};
 
 const FFMPEG_BIN: &str = "ffmpeg";
 const PROGRESS_EPSILON: f32 = 0.005;
 const MAX_PROGRESS_UPDATE_INTERVAL: Duration = Duration::from_secs(3);
 const PROGRESS_LOG_INTERVAL: Duration = Duration::from_secs(10);
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+/// Param of a failed run's error: the input time, in seconds, ffmpeg last
+/// reported before it failed.
+pub(crate) const POSITION_PARAM: &str = "position_seconds";
 
-pub(crate) async fn run_ffmpeg(args: Vec<OsString>) -> Result<(), AppError> {
-    run_ffmpeg_inner(args, None).await
+pub(crate) async fn run_ffmpeg(
+    runner: &DynProcessRunner,
+    args: Vec<OsString>,
+) -> Result<(), AppError> {
+    run_ffmpeg_inner(runner, args, None).await
 }
 
 pub(crate) async fn run_ffmpeg_with_progress(
+    runner: &DynProcessRunner,
     args: Vec<OsString>,
     config: FfmpegProgressConfig,
 ) -> Result<(), AppError> {
-    run_ffmpeg_inner(args, Some(config)).await
+    run_ffmpeg_inner(runner, args, Some(config)).await
 }
 
 async fn run_ffmpeg_inner(
+    runner: &DynProcessRunner,
+    args: Vec<OsString>,
+    progress: Option<FfmpegProgressConfig>,
+) -> Result<(), AppError> {
+    let labels = CommandLabels::from_args(&args);
+    let activity = Arc::new(FfmpegActivity::new());
+    let result = supervise_ffmpeg(runner, args, progress, &labels, activity.clone())
+        .await
+        .map_err(|err| activity.annotate(err));
+    let encoder = [("encoder", labels.encoder.as_str())];
+    match &result {
+        Ok(()) => {
+            metrics::observe(
+                "vrs_ffmpeg_duration_seconds",
+                &encoder,
+                metrics::DURATION_BUCKETS,
+                activity.started.elapsed().as_secs_f64(),
+            );
+            if let Some(speed) = activity.speed() {
+                metrics::observe(
+                    "vrs_ffmpeg_encode_speed",
+                    &[
+                        ("encoder", labels.encoder.as_str()),
+                        ("resolution", labels.resolution.as_str()),
+                    ],
+                    metrics::SPEED_BUCKETS,
+                    speed,
+                );
+            }
+        }
+        Err(_) => metrics::increment("vrs_ffmpeg_failures_total", &encoder),
+    }
+    result
+}
+
+async fn supervise_ffmpeg(
+    runner: &DynProcessRunner,
     args: Vec<OsString>,
     progress: Option<FfmpegProgressConfig>,
+    labels: &CommandLabels,
+    activity: Arc<FfmpegActivity>,
 ) -> Result<(), AppError> {
     let printable_args: Vec<String> = args
         .iter()
@@ -40,29 +94,61 @@ async fn run_ffmpeg_inner(
         .collect();
     tracing::debug!(command = %printable_args.join(" "), "spawning ffmpeg");
 
-    let mut command = Command::new(FFMPEG_BIN);
-    command.args(&args);
-    command.stderr(Stdio::piped());
-    command.stdout(Stdio::null());
-    command.stdin(Stdio::null());
-
-    let mut child = command.spawn().map_err(map_io_error)?;
+    let mut child = runner
+        .spawn(FFMPEG_BIN, &args)
+        .await
+        .map_err(map_io_error)?;
+    metrics::increment(
+        "vrs_ffmpeg_spawns_total",
+        &[("encoder", labels.encoder.as_str())],
+    );
 
     let mut progress_opt = progress;
-    let stderr = child.stderr.take();
+    let stderr = child.take_stderr();
     let monitor_handle = if let Some(stderr) = stderr {
+        let activity = activity.clone();
         if let Some(config) = progress_opt.take() {
-            Some(tokio::spawn(monitor_ffmpeg(stderr, config)))
+            Some(tokio::spawn(monitor_ffmpeg(stderr, config, activity)))
         } else {
-            Some(tokio::spawn(
-                async move { drain_ffmpeg(stderr, "ffmpeg").await },
-            ))
+            Some(tokio::spawn(async move {
+                drain_ffmpeg(stderr, "ffmpeg", &activity).await
+            }))
         }
     } else {
         None
     };
 
-    let status = child.wait().await.map_err(map_io_error)?;
+    let status = match stall_timeout() {
+        None => child.wait().await.map_err(map_io_error)?,
+        Some(limit) => loop {
+            tokio::select! {
+                status = child.wait() => break status.map_err(map_io_error)?,
+                _ = tokio::time::sleep(WATCHDOG_INTERVAL) => {
+                    if activity.idle() < limit {
+                        continue;
+                    }
+                    let _ = child.kill().await;
+                    if let Some(handle) = &monitor_handle {
+                        handle.abort();
+                    }
+                    metrics::increment(
+                        "vrs_ffmpeg_watchdog_kills_total",
+                        &[("encoder", labels.encoder.as_str())],
+                    );
+                    tracing::warn!(
+                        command = %printable_args.join(" "),
+                        idle_secs = limit.as_secs(),
+                        "killing stalled ffmpeg"
+                    );
+                    return Err(AppError::transcode(format!(
+                        "ffmpeg produced no output for {} seconds and was killed",
+                        limit.as_secs()
+                    ))
+                    .with_code("ffmpeg_stalled"));
+                }
+            }
+        },
+    };
 
     if let Some(handle) = monitor_handle {
         match handle.await {
@@ -77,9 +163,12 @@ async fn run_ffmpeg_inner(
     }
 
     if !status.success() {
-        return Err(AppError::transcode(format!(
-            "ffmpeg exited with status {status}"
-        )));
+        let err = AppError::transcode(format!("ffmpeg exited with status {status}"));
+        // No exit code: killed by a signal, e.g. the OOM killer.
+        return Err(match status.code() {
+            Some(_) => err,
+            None => err.with_code("ffmpeg_killed"),
+        });
     }
 
     tracing::debug!(command = %printable_args.join(" "), "ffmpeg finished successfully");
@@ -87,9 +176,121 @@ async fn run_ffmpeg_inner(
     Ok(())
 }
 
+/// `VIDEO_FFMPEG_STALL_TIMEOUT_SECS`: how long ffmpeg may go without writing
+/// to stderr before it is killed. Unset or 0 disables the watchdog.
+fn stall_timeout() -> Option<Duration> {
+    config::parse_var::<u64>("VIDEO_FFMPEG_STALL_TIMEOUT_SECS")
+        .filter(|&secs| secs > 0)
+        .map(Duration::from_secs)
+}
+
+/// Metric labels for one ffmpeg invocation.
+struct CommandLabels {
+    /// First video encoder, e.g. `libsvtav1`, or `none`.
+    encoder: String,
+    /// Output height such as `720p`, `ladder` for several, or `source`.
+    resolution: String,
+}
+
+impl CommandLabels {
+    fn from_args(args: &[OsString]) -> Self {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let encoder = args
+            .windows(2)
+            .find(|pair| pair[0].starts_with("-c:v") || pair[0] == "-vcodec")
+            .map_or_else(|| "none".to_string(), |pair| pair[1].clone());
+
+        let mut heights = BTreeSet::new();
+        for arg in &args {
+            for (_, rest) in arg
+                .match_indices("scale=")
+                .map(|(at, _)| arg.split_at(at + 6))
+            {
+                let height = rest
+                    .split(':')
+                    .nth(1)
+                    .map(|value| value.split([',', '[', ';', ':']).next().unwrap_or(""))
+                    .and_then(|value| value.parse::<i64>().ok())
+                    .filter(|&height| height > 0);
+                heights.extend(height);
+            }
+        }
+        let resolution = match heights.len() {
+            0 => "source".to_string(),
+            1 => format!("{}p", heights.first().unwrap_or(&0)),
+            _ => "ladder".to_string(),
+        };
+        Self {
+            encoder,
+            resolution,
+        }
+    }
+}
+
+/// What the stderr reader has seen, shared with the watchdog.
+struct FfmpegActivity {
+    started: Instant,
+    last_output_ms: AtomicU64,
+    /// Last reported `speed=`, as `f64` bits; NaN until one is seen.
+    speed_bits: AtomicU64,
+    /// Last reported `time=`, as `f64` bits; NaN until one is seen.
+    position_bits: AtomicU64,
+}
+
+impl FfmpegActivity {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            last_output_ms: AtomicU64::new(0),
+            speed_bits: AtomicU64::new(f64::NAN.to_bits()),
+            position_bits: AtomicU64::new(f64::NAN.to_bits()),
+        }
+    }
+
+    fn touch(&self) {
+        self.last_output_ms
+            .store(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    fn idle(&self) -> Duration {
+        self.started.elapsed().saturating_sub(Duration::from_millis(
+            self.last_output_ms.load(Ordering::Relaxed),
+        ))
+    }
+
+    fn note_line(&self, line: &str) {
+        let Some(metrics) = parse_ffmpeg_metrics(line) else {
+            return;
+        };
+        self.position_bits
+            .store(metrics.time_seconds.to_bits(), Ordering::Relaxed);
+        if let Some(speed) = metrics.speed {
+            self.speed_bits.store(speed.to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    /// Tags a failure of the run with where in the input ffmpeg got to.
+    fn annotate(&self, err: AppError) -> AppError {
+        let position = f64::from_bits(self.position_bits.load(Ordering::Relaxed));
+        if position.is_nan() || !matches!(err.root(), AppError::Transcode(_)) {
+            return err;
+        }
+        err.with_param(POSITION_PARAM, position)
+    }
+
+    fn speed(&self) -> Option<f64> {
+        Some(f64::from_bits(self.speed_bits.load(Ordering::Relaxed)))
+            .filter(|speed| !speed.is_nan())
+    }
+}
+
 async fn monitor_ffmpeg(
-    mut stderr: ChildStderr,
+    mut stderr: ProcessStderr,
     config: FfmpegProgressConfig,
+    activity: Arc<FfmpegActivity>,
 ) -> Result<(), AppError> {
     let FfmpegProgressConfig {
         total_duration,
@@ -100,18 +301,7 @@ async fn monitor_ffmpeg(
 
     let total_seconds = total_duration.as_secs_f64();
     if total_seconds <= f64::EPSILON {
-        let mut drain = Vec::new();
-        stderr.read_to_end(&mut drain).await.map_err(map_io_error)?;
-        if !drain.is_empty() {
-            let text = String::from_utf8_lossy(&drain);
-            for line in text.split('\n') {
-                let trimmed = line.trim();
-                if !trimmed.is_empty() {
-                    log_ffmpeg_line(operation, trimmed);
-                }
-            }
-        }
-        return Ok(());
+        return drain_ffmpeg(stderr, operation, &activity).await;
     }
 
     let mut buffer = Vec::with_capacity(8192);
@@ -125,6 +315,7 @@ async fn monitor_ffmpeg(
         if read == 0 {
             break;
         }
+        activity.touch();
         buffer.extend_from_slice(&chunk[..read]);
 
         while let Some(idx) = buffer
@@ -149,6 +340,8 @@ async fn monitor_ffmpeg(
             }
 
             log_ffmpeg_line(operation, trimmed);
+            activity.note_line(trimmed);
+            logs::publish(job_id, operation, trimmed);
             process_ffmpeg_line(
                 trimmed,
                 ProgressContext {
@@ -170,6 +363,8 @@ async fn monitor_ffmpeg(
         let trimmed = line.trim();
         if !trimmed.is_empty() {
             log_ffmpeg_line(operation, trimmed);
+            activity.note_line(trimmed);
+            logs::publish(job_id, operation, trimmed);
             process_ffmpeg_line(
                 trimmed,
                 ProgressContext {
@@ -194,7 +389,11 @@ async fn monitor_ffmpeg(
     Ok(())
 }
 
-async fn drain_ffmpeg(mut stderr: ChildStderr, operation: &'static str) -> Result<(), AppError> {
+async fn drain_ffmpeg(
+    mut stderr: ProcessStderr,
+    operation: &'static str,
+    activity: &FfmpegActivity,
+) -> Result<(), AppError> {
     let mut buffer = Vec::with_capacity(8192);
     let mut chunk = [0u8; 4096];
 
@@ -203,6 +402,7 @@ async fn drain_ffmpeg(mut stderr: ChildStderr, operation: &'static str) -> Resul
         if read == 0 {
             break;
         }
+        activity.touch();
         buffer.extend_from_slice(&chunk[..read]);
 
         while let Some(idx) = buffer
@@ -226,6 +426,7 @@ async fn drain_ffmpeg(mut stderr: ChildStderr, operation: &'static str) -> Resul
                 continue;
             }
             log_ffmpeg_line(operation, trimmed);
+            activity.note_line(trimmed);
         }
     }
 
@@ -234,6 +435,7 @@ async fn drain_ffmpeg(mut stderr: ChildStderr, operation: &'static str) -> Resul
         let trimmed = line.trim();
         if !trimmed.is_empty() {
             log_ffmpeg_line(operation, trimmed);
+            activity.note_line(trimmed);
         }
     }
 
@@ -0,0 +1,298 @@
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    error::AppError,
+    locks::LockManager,
+    metadata,
+    process::DynProcessRunner,
+    storage::{Storage, ensure_dir},
+};
+
+use super::{
+    ffmpeg::run_ffmpeg,
+    probe::probe_duration,
+    util::{os, os_path},
+};
+
+const MIN_FRAME_WIDTH: u32 = 16;
+const MAX_FRAME_WIDTH: u32 = 3840;
+/// Where in the video a frame is taken when no timestamp is given, matching
+/// the `thumbnails` stage.
+const DEFAULT_POSITION: f64 = 0.1;
+/// Height of posters, matching the `thumbnails` stage.
+const POSTER_HEIGHT: u32 = 720;
+
+/// How the poster `thumbnail.jpg` was picked through
+/// `PUT /videos/{id}/thumbnail`. The `thumbnails` stage keeps a picked
+/// poster, and frames requested without a time are taken from it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum PosterChoice {
+    /// The frame `t` seconds into the video.
+    Frame { t: f64 },
+    /// An image uploaded by the client.
+    Image,
+}
+
+/// Image format of an extracted frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameFormat {
+    #[default]
+    Jpeg,
+    Webp,
+}
+
+impl FrameFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Webp => "webp",
+        }
+    }
+
+    fn codec_args(self) -> [&'static str; 4] {
+        match self {
+            Self::Jpeg => ["-c:v", "mjpeg", "-q:v", "3"],
+            Self::Webp => ["-c:v", "libwebp", "-quality", "80"],
+        }
+    }
+}
+
+/// A frame of the download to extract.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameRequest {
+    /// Seconds from the start; 10% into the video when unset.
+    pub at_seconds: Option<f64>,
+    /// Output width, keeping the aspect ratio; the source width when unset.
+    pub width: Option<u32>,
+    pub format: FrameFormat,
+}
+
+/// Extracts one frame of the download into `frames/` in the video directory
+/// and returns its path. Without a time, the frame is the picked poster, if
+/// any. Frames are cached there until the download or poster is rewritten.
+pub async fn ensure_frame(
+    storage: &Storage,
+    runner: &DynProcessRunner,
+    id: &Uuid,
+    request: FrameRequest,
+) -> Result<PathBuf, AppError> {
+    if let Some(width) = request.width
+        && !(MIN_FRAME_WIDTH..=MAX_FRAME_WIDTH).contains(&width)
+    {
+        return Err(AppError::validation(format!(
+            "width must be between {MIN_FRAME_WIDTH} and {MAX_FRAME_WIDTH}"
+        ))
+        .with_code("width_invalid")
+        .with_param("min", MIN_FRAME_WIDTH)
+        .with_param("max", MAX_FRAME_WIDTH));
+    }
+    if let Some(at) = request.at_seconds {
+        validate_timestamp(at)?;
+    }
+
+    let download = storage.download_path(id);
+    let mut written = download_written(&download, id).await?;
+    let poster = match request.at_seconds {
+        Some(_) => None,
+        None => metadata::load(storage, id).await?.poster,
+    };
+    if poster.is_some()
+        && let Ok(meta) = fs::metadata(storage.thumbnail_path(id)).await
+    {
+        written = written.max(meta.modified()?);
+    }
+    let source = match poster {
+        Some(PosterChoice::Image) => storage.thumbnail_path(id),
+        _ => download,
+    };
+
+    let position = match request.at_seconds {
+        Some(at) => format!("{}ms", (at * 1000.0).round() as u64),
+        None => "default".to_string(),
+    };
+    let width = request
+        .width
+        .map_or_else(|| "source".to_string(), |width| width.to_string());
+    let frames_dir = storage.frames_dir(id);
+    let target = frames_dir.join(format!("{position}_{width}.{}", request.format.extension()));
+
+    let _guard = storage
+        .locks()
+        .acquire(&LockManager::video_key(id, "frames"))
+        .await?;
+    if let Ok(cached) = fs::metadata(&target).await
+        && cached.modified().is_ok_and(|rendered| rendered >= written)
+    {
+        return Ok(target);
+    }
+
+    let at = match (request.at_seconds.or(poster_time(poster)), poster) {
+        (_, Some(PosterChoice::Image)) => 0.0,
+        (Some(at), _) => checked_position(runner, &source, at).await?,
+        (None, _) => probe_duration(runner, &source)
+            .await
+            .unwrap_or(None)
+            .map_or(0.0, |total| total.as_secs_f64() * DEFAULT_POSITION),
+    };
+
+    ensure_dir(&frames_dir).await?;
+    let temp = target.with_extension("part");
+    let mut args = vec![
+        os("-y"),
+        os("-ss"),
+        os(format!("{at:.3}")),
+        os("-i"),
+        os_path(&source),
+        os("-frames:v"),
+        os("1"),
+    ];
+    if let Some(width) = request.width {
+        args.extend([os("-vf"), os(format!("scale={width}:-2"))]);
+    }
+    args.extend(request.format.codec_args().map(os));
+    args.extend([
+        os("-f"),
+        os("image2"),
+        os("-update"),
+        os("1"),
+        os_path(&temp),
+    ]);
+    if let Err(err) = run_ffmpeg(runner, args).await {
+        fs::remove_file(&temp).await.ok();
+        return Err(err);
+    }
+    fs::rename(&temp, &target).await?;
+    tracing::debug!(video_id = %id, path = %target.display(), "frame extracted");
+    Ok(target)
+}
+
+/// Writes the frame `at` seconds into the download as the poster.
+pub async fn poster_from_frame(
+    storage: &Storage,
+    runner: &DynProcessRunner,
+    id: &Uuid,
+    at: f64,
+) -> Result<(), AppError> {
+    validate_timestamp(at)?;
+    let download = storage.download_path(id);
+    download_written(&download, id).await?;
+    let at = checked_position(runner, &download, at).await?;
+    let args = vec![
+        os("-y"),
+        os("-ss"),
+        os(format!("{at:.3}")),
+        os("-i"),
+        os_path(&download),
+        os("-frames:v"),
+        os("1"),
+        os("-vf"),
+        os(format!("scale=-2:{POSTER_HEIGHT}")),
+    ];
+    write_poster(storage, runner, id, args).await
+}
+
+/// Converts an uploaded image to JPEG and writes it as the poster, scaled
+/// down to 720 lines when taller. Files ffmpeg cannot decode are rejected.
+pub async fn poster_from_image(
+    storage: &Storage,
+    runner: &DynProcessRunner,
+    id: &Uuid,
+    image: &Path,
+) -> Result<(), AppError> {
+    let args = vec![
+        os("-y"),
+        os("-i"),
+        os_path(image),
+        os("-frames:v"),
+        os("1"),
+        os("-vf"),
+        os(format!("scale=-2:'min({POSTER_HEIGHT},ih)'")),
+    ];
+    write_poster(storage, runner, id, args)
+        .await
+        .map_err(|err| match err.root() {
+            AppError::Transcode(_) => AppError::validation("the poster image could not be decoded")
+                .with_code("poster_image_invalid"),
+            _ => err,
+        })
+}
+
+async fn write_poster(
+    storage: &Storage,
+    runner: &DynProcessRunner,
+    id: &Uuid,
+    mut args: Vec<std::ffi::OsString>,
+) -> Result<(), AppError> {
+    let target = storage.thumbnail_path(id);
+    ensure_dir(&storage.video_dir(id)).await?;
+    let temp = target.with_extension("part");
+    args.extend([
+        os("-f"),
+        os("image2"),
+        os("-update"),
+        os("1"),
+        os_path(&temp),
+    ]);
+    if let Err(err) = run_ffmpeg(runner, args).await {
+        fs::remove_file(&temp).await.ok();
+        return Err(err);
+    }
+    fs::rename(&temp, &target).await?;
+    tracing::debug!(video_id = %id, "poster written");
+    Ok(())
+}
+
+fn poster_time(poster: Option<PosterChoice>) -> Option<f64> {
+    match poster {
+        Some(PosterChoice::Frame { t }) => Some(t),
+        _ => None,
+    }
+}
+
+fn validate_timestamp(at: f64) -> Result<(), AppError> {
+    if at.is_finite() && at >= 0.0 {
+        return Ok(());
+    }
+    Err(
+        AppError::validation("t must be a non-negative number of seconds")
+            .with_code("timestamp_invalid"),
+    )
+}
+
+/// `at`, refused when the download is known to be shorter.
+async fn checked_position(
+    runner: &DynProcessRunner,
+    source: &Path,
+    at: f64,
+) -> Result<f64, AppError> {
+    match probe_duration(runner, source).await.unwrap_or(None) {
+        Some(total) if at >= total.as_secs_f64() => Err(AppError::validation(format!(
+            "t must be less than the duration of {:.3}s",
+            total.as_secs_f64()
+        ))
+        .with_code("timestamp_out_of_range")
+        .with_param("duration_seconds", total.as_secs_f64())),
+        _ => Ok(at),
+    }
+}
+
+async fn download_written(download: &Path, id: &Uuid) -> Result<SystemTime, AppError> {
+    match fs::metadata(download).await {
+        Ok(meta) => Ok(meta.modified()?),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Err(AppError::not_found(
+            format!("video {id} has no download yet"),
+        )
+        .with_code("download_missing")
+        .with_param("id", id.to_string())),
+        Err(err) => Err(err.into()),
+    }
+}
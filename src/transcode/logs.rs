@@ -0,0 +1,46 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// Lines buffered per subscriber before the oldest are dropped.
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Log channels of jobs someone is watching. Lines of jobs nobody watches
+/// are not kept.
+static CHANNELS: Mutex<BTreeMap<Uuid, broadcast::Sender<FfmpegLogLine>>> =
+    Mutex::new(BTreeMap::new());
+
+/// A line ffmpeg wrote to stderr while encoding for a job.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FfmpegLogLine {
+    /// The encode that wrote it, e.g. `encode_download`.
+    pub operation: &'static str,
+    pub line: String,
+}
+
+/// Receives the stderr lines of the job's progress-tracked ffmpeg runs from
+/// now on.
+pub fn subscribe_ffmpeg_log(job_id: Uuid) -> broadcast::Receiver<FfmpegLogLine> {
+    let mut channels = CHANNELS.lock().unwrap_or_else(|p| p.into_inner());
+    channels.retain(|_, sender| sender.receiver_count() > 0);
+    channels
+        .entry(job_id)
+        .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+        .subscribe()
+}
+
+pub(crate) fn publish(job_id: Uuid, operation: &'static str, line: &str) {
+    let mut channels = CHANNELS.lock().unwrap_or_else(|p| p.into_inner());
+    let Some(sender) = channels.get(&job_id) else {
+        return;
+    };
+    let line = FfmpegLogLine {
+        operation,
+        line: line.to_string(),
+    };
+    if sender.send(line).is_err() {
+        channels.remove(&job_id);
+    }
+}
@@ -1,9 +1,52 @@
+mod archive;
+mod audio;
+mod capabilities;
 mod config;
+mod decode;
+mod diagnostics;
 mod ffmpeg;
+mod frames;
+mod logs;
+mod mpd;
+mod packaging;
 mod pipeline;
+mod preview;
 mod probe;
+mod profile;
+mod proxy;
+mod salvage;
+mod simulate;
+mod stages;
+mod stills;
+mod storyboard;
 mod streams;
 mod util;
 
-pub use config::EncodeParams;
+pub(crate) use archive::write_archive;
+pub use archive::{HlsArchive, HlsArchives};
+pub use audio::render_audio_visual;
+pub use capabilities::{
+    EncoderAvailability, EncoderCapabilities, EncoderFailure, clear_encoder_failures,
+    encoder_capabilities,
+};
+pub use config::{
+    AudioPresentation, EncodeParams, EncoderKind, LadderLimits, MezzanineCodec, OutputColor,
+    SlideshowParams,
+};
+pub use diagnostics::{FailureReport, capture_failure, clear_failure, load_failure};
+pub use frames::{
+    FrameFormat, FrameRequest, PosterChoice, ensure_frame, poster_from_frame, poster_from_image,
+};
+pub use logs::{FfmpegLogLine, subscribe_ffmpeg_log};
 pub use pipeline::{ensure_dash_ready, ensure_hls_ready, process_video};
+pub use probe::{
+    AudioStreamInfo, MediaInfo, SourceProbe, VideoStreamInfo, ensure_media, probe_media_info,
+    probe_source,
+};
+pub use profile::{Av1Tune, Av1Tuning, TranscodeProfile};
+pub use proxy::render_proxy;
+pub use salvage::{salvage_point, truncate_source};
+pub use simulate::{SimulatedMediaRunner, fake_transcode_enabled};
+pub use stages::{OptionalStage, run_optional_stages};
+pub use stills::render_stills;
+pub use storyboard::{SPRITE_FILE, STORYBOARD_FILE, StoryboardLayout, append_query_to_storyboard};
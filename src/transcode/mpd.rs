@@ -0,0 +1,319 @@
+use std::fmt::Write;
+
+use super::{probe::pixel_format_depth, streams::Rendition};
+
+const AUDIO_CODEC: &str = "mp4a.40.2";
+const UTC_TIMING_SCHEME: &str = "urn:mpeg:dash:utc:http-iso:2014";
+
+/// Facts about the packaged streams that ffmpeg's DASH muxer omits or leaves vague.
+#[derive(Debug, Clone)]
+pub(crate) struct MpdMetadata<'a> {
+    pub renditions: &'a [Rendition],
+    pub has_audio: bool,
+    pub frame_rate: Option<String>,
+    /// Pixel format of the video rungs, e.g. `yuv420p10le` for 10-bit output.
+    pub pixel_format: &'a str,
+    pub segment_seconds: u32,
+    pub utc_timing_url: Option<String>,
+}
+
+/// Rewrites an ffmpeg-generated MPD so every Representation carries a precise
+/// `@codecs`, video `@frameRate` and a `<Label>`, sets `@minBufferTime` from the
+/// segment length and optionally appends a `<UTCTiming>` element.
+pub(crate) fn postprocess_mpd(manifest: &str, metadata: &MpdMetadata<'_>) -> String {
+    let mut out = String::with_capacity(manifest.len() + 512);
+    let mut rest = manifest;
+
+    loop {
+        let next = [find_tag(rest, "MPD"), find_tag(rest, "Representation")]
+            .into_iter()
+            .flatten()
+            .min();
+        let Some(start) = next else {
+            break;
+        };
+        let Some(end) = rest[start..].find('>').map(|offset| start + offset + 1) else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        let tag = &rest[start..end];
+        rest = &rest[end..];
+
+        if tag.starts_with("<MPD") {
+            let min_buffer = format!("PT{}S", metadata.segment_seconds.max(1) * 2);
+            out.push_str(&set_attr(tag, "minBufferTime", &min_buffer));
+            continue;
+        }
+
+        let (tag, label) = rewrite_representation(tag, metadata);
+        out.push_str(&tag);
+        if let Some(label) = label.filter(|_| !tag.ends_with("/>")) {
+            // Label precedes segment addressing in RepresentationBaseType.
+            let insert_at = label_position(rest);
+            let indent = rest[..insert_at]
+                .rsplit('\n')
+                .next()
+                .filter(|line| line.chars().all(char::is_whitespace))
+                .unwrap_or("");
+            out.push_str(&rest[..insert_at]);
+            let _ = write!(out, "<Label>{label}</Label>\n{indent}");
+            rest = &rest[insert_at..];
+        }
+    }
+    out.push_str(rest);
+
+    match &metadata.utc_timing_url {
+        Some(url) => insert_utc_timing(&out, url),
+        None => out,
+    }
+}
+
+/// Joins the manifests of packaging passes that each encoded part of the
+/// ladder. Each manifest comes with the ladder position of each of its video
+/// representations; only the first carries audio. Representations are
+/// renumbered to their ladder position, audio after the ladder, and their
+/// segment templates are pinned to the files their pass wrote.
+pub(crate) fn merge_pass_manifests(
+    passes: &[(String, Vec<usize>)],
+    renditions: &[Rendition],
+) -> String {
+    let Some((base, _)) = passes.first() else {
+        return String::new();
+    };
+    let mut video: Vec<(usize, String)> = Vec::new();
+    for (manifest, indices) in passes {
+        for (start, end) in representation_spans(manifest) {
+            let block = &manifest[start..end];
+            let position = attr(block, "id")
+                .and_then(|id| id.parse::<usize>().ok())
+                .and_then(|id| indices.get(id));
+            if let (false, Some(&index)) = (is_audio(block), position) {
+                video.push((index, renumber_representation(block, index)));
+            }
+        }
+    }
+    video.sort_by_key(|(index, _)| *index);
+
+    let mut out = String::with_capacity(passes.iter().map(|(m, _)| m.len()).sum());
+    let mut cursor = 0;
+    let mut video = Some(video);
+    let mut audio_id = renditions.len();
+    for (start, end) in representation_spans(base) {
+        out.push_str(&base[cursor..start]);
+        let block = &base[start..end];
+        if is_audio(block) {
+            out.push_str(&renumber_representation(block, audio_id));
+            audio_id += 1;
+        } else if let Some(video) = video.take() {
+            for (_, block) in video {
+                out.push_str(&block);
+            }
+        }
+        cursor = end;
+    }
+    out.push_str(&base[cursor..]);
+    widen_video_adaptation_set(&out, renditions)
+}
+
+/// Byte ranges of the `<Representation>` elements, each from the start of
+/// its line through the line break after it.
+fn representation_spans(manifest: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut offset = 0;
+    while let Some(pos) = find_tag(&manifest[offset..], "Representation") {
+        let start = offset + pos;
+        let Some(tag_end) = manifest[start..].find('>').map(|end| start + end + 1) else {
+            break;
+        };
+        let mut end = if manifest[..tag_end].ends_with("/>") {
+            tag_end
+        } else {
+            let Some(close) = manifest[tag_end..].find("</Representation>") else {
+                break;
+            };
+            tag_end + close + "</Representation>".len()
+        };
+        if manifest[end..].starts_with('\n') {
+            end += 1;
+        }
+        let line_start = manifest[..start]
+            .rfind('\n')
+            .map_or(0, |newline| newline + 1);
+        let indented = manifest[line_start..start].trim().is_empty();
+        spans.push((if indented { line_start } else { start }, end));
+        offset = end;
+    }
+    spans
+}
+
+fn is_audio(representation: &str) -> bool {
+    attr(representation, "mimeType").is_some_and(|mime| mime.starts_with("audio/"))
+}
+
+/// Gives a representation a new `id`, replacing `$RepresentationID$` in its
+/// segment template with the id its files were written under.
+fn renumber_representation(block: &str, id: usize) -> String {
+    let written_as = attr(block, "id").unwrap_or_default();
+    let tag_end = block.find('>').map_or(block.len(), |end| end + 1);
+    format!(
+        "{}{}",
+        set_attr(&block[..tag_end], "id", &id.to_string()),
+        block[tag_end..].replace("$RepresentationID$", written_as)
+    )
+}
+
+/// The merged video adaptation set spans every pass, so its size bounds
+/// must cover the whole ladder, and bitstream switching no longer holds
+/// across encoders.
+fn widen_video_adaptation_set(manifest: &str, renditions: &[Rendition]) -> String {
+    let max_width = renditions.iter().map(|rung| rung.width).max().unwrap_or(0);
+    let max_height = renditions.iter().map(|rung| rung.height).max().unwrap_or(0);
+    let mut out = String::with_capacity(manifest.len());
+    let mut rest = manifest;
+    while let Some(start) = find_tag(rest, "AdaptationSet") {
+        let Some(end) = rest[start..].find('>').map(|offset| start + offset + 1) else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        let mut tag = rest[start..end].to_string();
+        if attr(&tag, "contentType") == Some("video") {
+            for (name, value) in [
+                ("maxWidth", max_width.to_string()),
+                ("maxHeight", max_height.to_string()),
+                ("bitstreamSwitching", "false".to_string()),
+            ] {
+                if attr(&tag, name).is_some() {
+                    tag = set_attr(&tag, name, &value);
+                }
+            }
+        }
+        out.push_str(&tag);
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn rewrite_representation(tag: &str, metadata: &MpdMetadata<'_>) -> (String, Option<String>) {
+    let is_audio = attr(tag, "mimeType").is_some_and(|mime| mime.starts_with("audio/"));
+    let rendition = attr(tag, "id")
+        .and_then(|id| id.parse::<usize>().ok())
+        .and_then(|index| metadata.renditions.get(index))
+        .filter(|_| !is_audio);
+
+    if is_audio || (metadata.has_audio && rendition.is_none()) {
+        let tag = set_attr(tag, "codecs", AUDIO_CODEC);
+        return (tag, Some("audio".to_string()));
+    }
+
+    let Some(rendition) = rendition else {
+        return (tag.to_string(), None);
+    };
+
+    let mut tag = tag.to_string();
+    let existing_codec = attr(&tag, "codecs").filter(|codec| codec.contains('.'));
+    if existing_codec.is_none() {
+        tag = set_attr(
+            &tag,
+            "codecs",
+            &av1_codec_string(
+                rendition.width,
+                rendition.height,
+                pixel_format_depth(metadata.pixel_format),
+            ),
+        );
+    }
+    if let Some(rate) = &metadata.frame_rate {
+        tag = set_attr(&tag, "frameRate", rate);
+    }
+    (tag, Some(rendition.name.clone()))
+}
+
+/// Builds an RFC 6381 AV1 codec string (Main profile) for `bit_depth`
+/// whose level fits the frame size.
+pub(crate) fn av1_codec_string(width: u32, height: u32, bit_depth: u8) -> String {
+    let pixels = u64::from(width) * u64::from(height);
+    let level = match pixels {
+        0..=147_456 => 0,
+        147_457..=278_784 => 1,
+        278_785..=665_856 => 4,
+        665_857..=1_065_024 => 5,
+        1_065_025..=2_359_296 => 8,
+        2_359_297..=8_912_896 => 12,
+        _ => 16,
+    };
+    format!("av01.0.{level:02}M.{bit_depth:02}")
+}
+
+fn label_position(body: &str) -> usize {
+    let closing = body.find("</Representation>").unwrap_or(body.len());
+    [
+        "<BaseURL",
+        "<SegmentBase",
+        "<SegmentList",
+        "<SegmentTemplate",
+    ]
+    .iter()
+    .filter_map(|needle| body[..closing].find(needle))
+    .min()
+    .unwrap_or(closing)
+}
+
+fn find_tag(text: &str, name: &str) -> Option<usize> {
+    let needle = format!("<{name}");
+    let mut offset = 0;
+    while let Some(pos) = text[offset..].find(&needle) {
+        let start = offset + pos;
+        let next = text[start + needle.len()..].chars().next();
+        if matches!(next, Some(' ' | '>' | '/' | '\n' | '\t' | '\r')) {
+            return Some(start);
+        }
+        offset = start + needle.len();
+    }
+    None
+}
+
+fn attr<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let needle = format!(" {name}=\"");
+    let start = tag.find(&needle)? + needle.len();
+    let end = tag[start..].find('"')?;
+    Some(&tag[start..start + end])
+}
+
+fn set_attr(tag: &str, name: &str, value: &str) -> String {
+    let needle = format!(" {name}=\"");
+    if let Some(pos) = tag.find(&needle) {
+        let value_start = pos + needle.len();
+        if let Some(len) = tag[value_start..].find('"') {
+            return format!(
+                "{}{}{}",
+                &tag[..value_start],
+                value,
+                &tag[value_start + len..]
+            );
+        }
+    }
+
+    let close = if tag.ends_with("/>") {
+        tag.len() - 2
+    } else {
+        tag.len() - 1
+    };
+    let head = tag[..close].trim_end();
+    format!("{head} {name}=\"{value}\"{}", &tag[close..])
+}
+
+fn insert_utc_timing(manifest: &str, url: &str) -> String {
+    let Some(pos) = manifest.rfind("</MPD>") else {
+        return manifest.to_string();
+    };
+    let url = url
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;");
+    format!(
+        "{}\t<UTCTiming schemeIdUri=\"{UTC_TIMING_SCHEME}\" value=\"{url}\"/>\n{}",
+        &manifest[..pos],
+        &manifest[pos..]
+    )
+}
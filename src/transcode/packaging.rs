@@ -0,0 +1,290 @@
+//! Ladders whose rungs use different encoders are packaged in several ffmpeg
+//! runs, one per encoder, whose outputs are merged back into one ladder.
+
+use std::{future::Future, path::Path, time::Duration};
+
+use tokio::fs;
+
+use crate::error::AppError;
+
+use super::{
+    capabilities::{is_blacklisted, record_encoder_failure},
+    config::EncoderKind,
+    profile::LadderCodec,
+    streams::Rendition,
+};
+
+/// Where a rung of the DASH ladder ended up after packaging.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PackagedRung {
+    /// The encoder that produced the rung, after any fallback.
+    pub encoder: EncoderKind,
+    /// Prefix of the pass's segment file names.
+    pub segment_prefix: String,
+    /// `$RepresentationID$` of the rung within its pass.
+    pub representation: usize,
+}
+
+impl PackagedRung {
+    /// Average bitrate of the rung's init and media segments in `dash_dir`
+    /// over `duration`.
+    pub(crate) async fn realized_kbps(&self, dash_dir: &Path, duration: Duration) -> Option<u32> {
+        let seconds = duration.as_secs_f64();
+        if seconds <= 0.0 {
+            return None;
+        }
+        let (prefix, representation) = (&self.segment_prefix, self.representation);
+        let init = format!("{prefix}init_{representation}.m4s");
+        let chunks = format!("{prefix}chunk_{representation}_");
+        let mut entries = fs::read_dir(dash_dir).await.ok()?;
+        let mut bytes = 0u64;
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name == init || name.starts_with(&chunks) {
+                bytes += entry.metadata().await.map_or(0, |meta| meta.len());
+            }
+        }
+        (bytes > 0).then(|| (bytes as f64 * 8.0 / 1000.0 / seconds).round() as u32)
+    }
+}
+
+/// Rungs packaged together by one ffmpeg run, all with the same encoder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct PackagingPass {
+    pub encoder: EncoderKind,
+    /// Positions of the rungs in the ladder.
+    pub indices: Vec<usize>,
+}
+
+impl PackagingPass {
+    pub(super) fn renditions(&self, ladder: &[Rendition]) -> Vec<Rendition> {
+        self.indices
+            .iter()
+            .map(|&index| ladder[index].clone())
+            .collect()
+    }
+}
+
+/// Groups the ladder by encoder, in the order the encoders first appear, so
+/// a failing hardware encoder only sends its own rungs back to software.
+/// Blacklisted encoders are replaced by software up front, and H.264 ladders
+/// always run as one pass.
+pub(super) fn packaging_passes(renditions: &[Rendition], codec: LadderCodec) -> Vec<PackagingPass> {
+    let mut passes: Vec<PackagingPass> = Vec::new();
+    for (index, rung) in renditions.iter().enumerate() {
+        let encoder = match codec {
+            LadderCodec::Av1 if !is_blacklisted(rung.encoder) => rung.encoder,
+            _ => EncoderKind::SoftwareAv1,
+        };
+        match passes.iter_mut().find(|pass| pass.encoder == encoder) {
+            Some(pass) => pass.indices.push(index),
+            None => passes.push(PackagingPass {
+                encoder,
+                indices: vec![index],
+            }),
+        }
+    }
+    passes
+}
+
+/// Runs a packaging pass with `encoder`, then once more with software AV1 if
+/// a hardware encoder fails, and returns the encoder that succeeded. As with
+/// the download encode, the hardware encoder is only blacklisted once the
+/// software run succeeds.
+pub(super) async fn with_encoder_fallback<F, Fut>(
+    encoder: EncoderKind,
+    mut attempt: F,
+) -> Result<EncoderKind, AppError>
+where
+    F: FnMut(EncoderKind) -> Fut,
+    Fut: Future<Output = Result<(), AppError>>,
+{
+    let Err(err) = attempt(encoder).await else {
+        return Ok(encoder);
+    };
+    if encoder == EncoderKind::SoftwareAv1 {
+        return Err(err);
+    }
+    tracing::warn!(
+        encoder = encoder.label(),
+        error = %err,
+        "ladder encode failed, falling back to software"
+    );
+    attempt(EncoderKind::SoftwareAv1).await?;
+    record_encoder_failure(encoder, &err.to_string());
+    Ok(EncoderKind::SoftwareAv1)
+}
+
+/// Moves what a pass wrote in `staging` up into `output_dir`, except the
+/// playlist or manifest named `skip`, which is merged separately.
+pub(super) async fn move_pass_outputs(
+    staging: &Path,
+    output_dir: &Path,
+    skip: &str,
+) -> Result<(), AppError> {
+    let mut entries = fs::read_dir(staging).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_name() != skip {
+            fs::rename(entry.path(), output_dir.join(entry.file_name())).await?;
+        }
+    }
+    fs::remove_dir_all(staging).await?;
+    Ok(())
+}
+
+/// Joins the master playlists of several passes into one. Header tags come
+/// from the first; variants are put back in ladder order.
+pub(super) fn merge_master_playlists(passes: &[(String, Vec<usize>)]) -> String {
+    let mut header = String::new();
+    let mut variants: Vec<(usize, String)> = Vec::new();
+    for (pass, (playlist, indices)) in passes.iter().enumerate() {
+        let mut variant = String::new();
+        let mut seen = 0;
+        for line in playlist
+            .lines()
+            .map(str::trim_end)
+            .filter(|line| !line.is_empty())
+        {
+            if line.starts_with("#EXT-X-STREAM-INF") || !variant.is_empty() {
+                variant.push_str(line);
+                variant.push('\n');
+                if !line.starts_with('#') {
+                    let index = indices.get(seen).copied().unwrap_or(usize::MAX);
+                    variants.push((index, std::mem::take(&mut variant)));
+                    seen += 1;
+                }
+            } else if pass == 0 {
+                header.push_str(line);
+                header.push('\n');
+            }
+        }
+    }
+    variants.sort_by_key(|(index, _)| *index);
+    for (_, variant) in variants {
+        header.push_str(&variant);
+    }
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcode::mpd::merge_pass_manifests;
+
+    fn sample_renditions() -> Vec<Rendition> {
+        let rung = |name: &str, width, height, bitrate| Rendition {
+            name: name.into(),
+            width,
+            height,
+            bitrate,
+            maxrate: bitrate + 500,
+            bufsize: bitrate + 2000,
+            low_bandwidth: false,
+            encoder: EncoderKind::SoftwareAv1,
+        };
+        vec![
+            rung("1080p", 1920, 1080, 6000),
+            rung("720p", 1280, 720, 3000),
+        ]
+    }
+
+    #[test]
+    fn packaging_passes_group_rungs_by_encoder() {
+        let mut renditions = sample_renditions();
+        renditions[1].encoder = EncoderKind::QsvAv1;
+        renditions.push(Rendition {
+            name: "480p".into(),
+            width: 854,
+            height: 480,
+            encoder: EncoderKind::SoftwareAv1,
+            ..renditions[1].clone()
+        });
+
+        let passes = packaging_passes(&renditions, LadderCodec::Av1);
+        let layout: Vec<_> = passes
+            .iter()
+            .map(|pass| (pass.encoder, pass.indices.clone()))
+            .collect();
+        assert_eq!(
+            layout,
+            vec![
+                (EncoderKind::SoftwareAv1, vec![0, 2]),
+                (EncoderKind::QsvAv1, vec![1]),
+            ]
+        );
+        assert_eq!(packaging_passes(&renditions, LadderCodec::H264).len(), 1);
+    }
+
+    #[test]
+    fn pass_master_playlists_merge_in_ladder_order() {
+        let merged = merge_master_playlists(&[
+            (
+                "#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-STREAM-INF:BANDWIDTH=6000\n1080p/index.m3u8\n\
+                 #EXT-X-STREAM-INF:BANDWIDTH=900\n480p/index.m3u8\n"
+                    .into(),
+                vec![0, 2],
+            ),
+            (
+                "#EXTM3U\n#EXT-X-VERSION:6\n#EXT-X-STREAM-INF:BANDWIDTH=3000\n720p/index.m3u8\n"
+                    .into(),
+                vec![1],
+            ),
+        ]);
+        assert_eq!(
+            merged,
+            "#EXTM3U\n#EXT-X-VERSION:7\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=6000\n1080p/index.m3u8\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=3000\n720p/index.m3u8\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=900\n480p/index.m3u8\n"
+        );
+    }
+
+    #[test]
+    fn pass_manifests_merge_into_one_ladder() {
+        let pass = |prefix: &str, video: &str, audio: &str| {
+            format!(
+                concat!(
+                    "<MPD>\n",
+                    "\t<Period id=\"0\">\n",
+                    "\t\t<AdaptationSet id=\"0\" contentType=\"video\" maxWidth=\"{w}\" maxHeight=\"{h}\">\n",
+                    "\t\t\t<SegmentTemplate media=\"{p}chunk_$RepresentationID$_$Number$.m4s\"/>\n",
+                    "\t\t\t<Representation id=\"0\" mimeType=\"video/mp4\" width=\"{w}\" height=\"{h}\">\n",
+                    "\t\t\t\t<SegmentTemplate media=\"{p}chunk_$RepresentationID$_$Number$.m4s\"/>\n",
+                    "\t\t\t</Representation>\n",
+                    "\t\t</AdaptationSet>\n",
+                    "{a}",
+                    "\t</Period>\n",
+                    "</MPD>\n",
+                ),
+                p = prefix,
+                w = video.split('x').next().unwrap(),
+                h = video.split('x').nth(1).unwrap(),
+                a = audio,
+            )
+        };
+        let audio = concat!(
+            "\t\t<AdaptationSet id=\"1\" contentType=\"audio\">\n",
+            "\t\t\t<Representation id=\"1\" mimeType=\"audio/mp4\"/>\n",
+            "\t\t</AdaptationSet>\n",
+        );
+        let renditions = sample_renditions();
+
+        let merged = merge_pass_manifests(
+            &[
+                (pass("p0_", "1280x720", audio), vec![1]),
+                (pass("p1_", "1920x1080", ""), vec![0]),
+            ],
+            &renditions,
+        );
+
+        let full = merged.find("id=\"0\" mimeType=\"video/mp4\" width=\"1920\"");
+        let reduced = merged.find("id=\"1\" mimeType=\"video/mp4\" width=\"1280\"");
+        assert!(full.is_some() && reduced.is_some() && full < reduced);
+        assert!(merged.contains("media=\"p1_chunk_0_$Number$.m4s\""));
+        assert!(merged.contains("media=\"p0_chunk_0_$Number$.m4s\""));
+        assert!(merged.contains("<Representation id=\"2\" mimeType=\"audio/mp4\"/>"));
+        assert!(merged.contains("maxWidth=\"1920\" maxHeight=\"1080\""));
+        assert_eq!(merged.matches("<Representation").count(), 3);
+    }
+}
@@ -1,37 +1,68 @@
-use std::{env, ffi::OsString, path::Path, time::Duration};
+use std::{
+    ffi::OsString,
+    path::Path,
+    time::{Duration, Instant},
+};
 
 use tokio::fs;
 use uuid::Uuid;
 
 use crate::{
+    config,
     error::AppError,
-    jobs::{DynJobStore, JobStage},
-    storage::{Storage, ensure_parent},
+    jobs::{DynJobStore, EncodeSummary, JobStage, RenditionSummary},
+    locks::LockManager,
+    metadata::{self, VideoMetadata},
+    process::DynProcessRunner,
+    storage::{Storage, dir_size, ensure_parent},
 };
 
 use super::{
-    config::{EncodeParams, EncoderKind, encoder_candidates},
+    capabilities::record_encoder_failure,
+    config::{EncodeParams, EncoderKind, MezzanineCodec, OutputColor, encoder_candidates},
     ffmpeg::{FfmpegProgressConfig, run_ffmpeg, run_ffmpeg_with_progress},
-    probe::{probe_duration, probe_has_audio, probe_video_geometry},
-    streams::{generate_dash_stream, generate_hls_stream, select_renditions},
+    packaging::PackagedRung,
+    preview::render_preview,
+    probe::{probe_color, probe_duration, probe_has_audio, probe_has_video, probe_video_geometry},
+    streams::{
+        LadderConfig, LadderEncoding, Rendition, generate_dash_stream, generate_hls_stream,
+        select_renditions,
+    },
     util::{finalize_encoded_file, os, os_path},
 };
 
 pub async fn process_video(
     storage: &Storage,
     jobs: &DynJobStore,
+    runner: &DynProcessRunner,
     id: &Uuid,
     input: &Path,
     encode: Option<EncodeParams>,
-) -> Result<(), AppError> {
+) -> Result<EncodeSummary, AppError> {
+    let started = Instant::now();
+    let source_bytes = file_size(input).await;
     storage.prepare_video_dirs(id, &[]).await?;
 
     let download_path = storage.download_path(id);
     ensure_parent(&download_path).await?;
 
     let params = encode.unwrap_or_default().sanitized();
-    let has_audio = probe_has_audio(input).await?;
-    let duration = match probe_duration(input).await {
+    let has_audio = probe_has_audio(runner, input).await?;
+    // Cover art counts as an attached picture, not a video stream, so
+    // podcasts with embedded artwork still package as audio only.
+    let audio_only = has_audio && !probe_has_video(runner, input).await?;
+    // Keep settings made through the API (e.g. a password) while the job ran.
+    let mut meta = metadata::load(storage, id).await?;
+    meta.profile = params.profile;
+    meta.ladder = params.ladder;
+    meta.audio_only = audio_only;
+    // Audio-only downloads are always Opus in WebM.
+    meta.mezzanine = if audio_only {
+        MezzanineCodec::Av1
+    } else {
+        MezzanineCodec::from_env()
+    };
+    let duration = match probe_duration(runner, input).await {
         Ok(value) => value,
         Err(err) => {
             tracing::warn!(
@@ -42,21 +73,189 @@ pub async fn process_video(
             None
         }
     };
+    if !audio_only {
+        meta.color = match probe_color(runner, input).await {
+            Ok(source) => OutputColor::for_source(&source),
+            Err(err) => {
+                tracing::warn!(
+                    path = %input.display(),
+                    ?err,
+                    "failed to probe source colour; encoding untagged 8-bit"
+                );
+                OutputColor::default()
+            }
+        };
+    }
+    metadata::save(storage, id, &meta).await?;
+    let encoding = LadderEncoding::new(params.profile, meta.color.clone());
 
-    let tmp_output = storage
-        .tmp_dir()
-        .join(format!("{}.encode.webm", id.simple()));
+    let tmp_output = storage.partial_encode_path(id);
     ensure_parent(&tmp_output).await?;
     if tmp_output.exists() {
         fs::remove_file(&tmp_output).await.ok();
     }
 
-    encode_download(jobs, id, &tmp_output, input, has_audio, duration, params).await?;
+    let encode = async {
+        let encoder = encode_mezzanine(
+            jobs,
+            runner,
+            id,
+            &tmp_output,
+            input,
+            has_audio,
+            duration,
+            params,
+            &meta,
+        )
+        .await?;
+        finalize_encoded_file(&tmp_output, &download_path).await?;
+        Ok::<_, AppError>(encoder)
+    };
+
+    let (encoder, renditions, packaged) = if !audio_only && params.ladders_from_source() {
+        // Cut the ladder from the original while the mezzanine encodes, so the
+        // two heaviest steps overlap instead of running back to back.
+        let renditions = plan_ladder(runner, id, input, &params).await?;
+        let (encoder, packaged) = tokio::try_join!(
+            encode,
+            package_ladder(
+                storage,
+                runner,
+                id,
+                input,
+                has_audio,
+                renditions.clone(),
+                &encoding
+            ),
+        )?;
+        remove_input(input).await;
+        jobs.update_progress(*id, 0.95).await?;
+        jobs.update_stage(*id, JobStage::Finalizing).await?;
+        (encoder, renditions, packaged)
+    } else {
+        let encoder = encode.await?;
+        let renditions = if audio_only {
+            tracing::debug!(video_id = %id, "packaging audio-only source");
+            Vec::new()
+        } else {
+            plan_ladder(runner, id, &download_path, &params).await?
+        };
+        remove_input(input).await;
+        jobs.update_progress(*id, 0.95).await?;
+        jobs.update_stage(*id, JobStage::Finalizing).await?;
+        let packaged = package_ladder(
+            storage,
+            runner,
+            id,
+            &download_path,
+            has_audio,
+            renditions.clone(),
+            &encoding,
+        )
+        .await?;
+        (encoder, renditions, packaged)
+    };
+
+    tracing::debug!(video_id = %id, "segment generation finished");
+
+    if params.preview && !audio_only {
+        let target = storage.preview_path(id);
+        // Like the optional stages, a broken preview leaves the video playable.
+        if let Err(err) = render_preview(runner, &download_path, &target, duration).await {
+            tracing::warn!(video_id = %id, error = %err, "animated preview failed");
+        }
+    }
+
+    jobs.update_progress(*id, 1.0).await?;
+    jobs.update_stage_eta(*id, Some(0.0)).await?;
 
-    finalize_encoded_file(&tmp_output, &download_path).await?;
+    let download_bytes = file_size(&download_path).await;
+    let dash_dir = storage.dash_dir(id);
+    let mut summaries = Vec::with_capacity(renditions.len());
+    for (rung, packaged) in renditions.into_iter().zip(packaged) {
+        let bitrate_kbps = match duration {
+            Some(duration) => packaged.realized_kbps(&dash_dir, duration).await,
+            None => None,
+        };
+        summaries.push(RenditionSummary {
+            name: rung.name,
+            width: rung.width,
+            height: rung.height,
+            encoder: packaged.encoder.ffmpeg_codec().to_string(),
+            bitrate_kbps,
+            target_bitrate_kbps: rung.bitrate,
+            maxrate_kbps: rung.maxrate,
+        });
+    }
+    Ok(EncodeSummary {
+        encoder: encoder.to_string(),
+        source_bytes,
+        download_bytes,
+        hls_bytes: dir_size(&storage.hls_dir(id)).await,
+        dash_bytes: dir_size(&dash_dir).await,
+        compression_ratio: (source_bytes > 0 && download_bytes > 0)
+            .then(|| source_bytes as f64 / download_bytes as f64),
+        encode_wall_seconds: started.elapsed().as_secs_f64(),
+        renditions: summaries,
+    })
+}
+
+async fn file_size(path: &Path) -> u64 {
+    fs::metadata(path).await.map_or(0, |meta| meta.len())
+}
 
-    let geometry = probe_video_geometry(&download_path).await?;
-    let renditions = select_renditions(geometry);
+#[allow(clippy::too_many_arguments)]
+async fn encode_mezzanine(
+    jobs: &DynJobStore,
+    runner: &DynProcessRunner,
+    id: &Uuid,
+    output: &Path,
+    input: &Path,
+    has_audio: bool,
+    duration: Option<Duration>,
+    params: EncodeParams,
+    meta: &VideoMetadata,
+) -> Result<&'static str, AppError> {
+    if meta.audio_only {
+        encode_audio_download(jobs, runner, id, output, input, duration).await
+    } else if meta.mezzanine == MezzanineCodec::Av1 {
+        encode_download(
+            jobs,
+            runner,
+            id,
+            output,
+            input,
+            has_audio,
+            duration,
+            params,
+            &meta.color,
+        )
+        .await
+    } else {
+        encode_fast_mezzanine(
+            jobs,
+            runner,
+            id,
+            output,
+            input,
+            has_audio,
+            duration,
+            meta.mezzanine,
+            &meta.color,
+        )
+        .await
+    }
+}
+
+async fn plan_ladder(
+    runner: &DynProcessRunner,
+    id: &Uuid,
+    source: &Path,
+    params: &EncodeParams,
+) -> Result<Vec<Rendition>, AppError> {
+    let geometry = probe_video_geometry(runner, source).await?;
+    let ladder = LadderConfig::for_video(params.profile, params.ladder);
+    let renditions = select_renditions(geometry, &ladder);
     let rendition_summary: Vec<String> = renditions
         .iter()
         .map(|r| format!("{}x{}@{}k", r.width, r.height, r.bitrate))
@@ -68,62 +267,71 @@ pub async fn process_video(
         renditions = %rendition_summary.join(", "),
         "selected rendition ladder"
     );
+    Ok(renditions)
+}
 
+/// Generates HLS and DASH from `source` concurrently, each under its lock.
+/// Returns where each rung of the DASH ladder ended up.
+async fn package_ladder(
+    storage: &Storage,
+    runner: &DynProcessRunner,
+    id: &Uuid,
+    source: &Path,
+    has_audio: bool,
+    renditions: Vec<Rendition>,
+    encoding: &LadderEncoding,
+) -> Result<Vec<PackagedRung>, AppError> {
+    let ((), packaged) = tokio::try_join!(
+        async {
+            let _lock = storage
+                .locks()
+                .acquire(&LockManager::video_key(id, "hls"))
+                .await?;
+            generate_hls_stream(
+                storage,
+                runner,
+                id,
+                source,
+                has_audio,
+                renditions.clone(),
+                encoding,
+            )
+            .await
+        },
+        async {
+            let _lock = storage
+                .locks()
+                .acquire(&LockManager::video_key(id, "dash"))
+                .await?;
+            generate_dash_stream(
+                storage,
+                runner,
+                id,
+                source,
+                has_audio,
+                renditions.clone(),
+                encoding,
+            )
+            .await
+        },
+    )?;
+    Ok(packaged)
+}
+
+async fn remove_input(input: &Path) {
     match fs::remove_file(input).await {
         Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
             tracing::warn!(path = %input.display(), ?err, "failed to remove temporary input file");
         }
         _ => {}
     }
-
-    jobs.update_progress(*id, 0.95).await?;
-    jobs.update_stage(*id, JobStage::Finalizing).await?;
-
-    let storage_for_hls = storage.clone();
-    let storage_for_dash = storage.clone();
-    let id_for_hls = *id;
-    let id_for_dash = *id;
-    let download_for_hls = download_path.clone();
-    let download_for_dash = download_path.clone();
-
-    tokio::try_join!(
-        {
-            let renditions = renditions.clone();
-            async move {
-                generate_hls_stream(
-                    &storage_for_hls,
-                    &id_for_hls,
-                    &download_for_hls,
-                    has_audio,
-                    renditions,
-                )
-                .await
-            }
-        },
-        {
-            let renditions = renditions.clone();
-            async move {
-                generate_dash_stream(
-                    &storage_for_dash,
-                    &id_for_dash,
-                    &download_for_dash,
-                    has_audio,
-                    renditions,
-                )
-                .await
-            }
-        },
-    )?;
-
-    tracing::debug!(video_id = %id, "segment generation finished");
-
-    jobs.update_progress(*id, 1.0).await?;
-    jobs.update_stage_eta(*id, Some(0.0)).await?;
-
-    Ok(())
 }
 
-pub async fn ensure_hls_ready(storage: &Storage, id: &Uuid) -> Result<(), AppError> {
+pub async fn ensure_hls_ready(
+    storage: &Storage,
+    runner: &DynProcessRunner,
+    id: &Uuid,
+) -> Result<(), AppError> {
     let source = storage.download_path(id);
     if !source.exists() {
         return Err(AppError::not_found(format!(
@@ -135,20 +343,36 @@ pub async fn ensure_hls_ready(storage: &Storage, id: &Uuid) -> Result<(), AppErr
     let hls_dir = storage.hls_dir(id);
     let index = hls_dir.join("index.m3u8");
     if index.exists() {
-        let master = hls_dir.join("master.m3u8");
-        if !master.exists() {
-            fs::copy(&index, &master).await?;
-        }
-        return Ok(());
+        return ensure_master_copy(&hls_dir).await;
+    }
+    let _lock = storage
+        .locks()
+        .acquire(&LockManager::video_key(id, "hls"))
+        .await?;
+    // Another request may have packaged it while we waited.
+    if index.exists() {
+        return ensure_master_copy(&hls_dir).await;
     }
 
-    let has_audio = probe_has_audio(&source).await.unwrap_or(false);
-    let geometry = probe_video_geometry(&source).await?;
-    let renditions = select_renditions(geometry);
-    generate_hls_stream(storage, id, &source, has_audio, renditions).await
+    let meta = metadata::load(storage, id).await?;
+    let (has_audio, renditions) = source_ladder(runner, &source, &meta).await?;
+    generate_hls_stream(
+        storage,
+        runner,
+        id,
+        &source,
+        has_audio,
+        renditions,
+        &LadderEncoding::new(meta.profile, meta.color),
+    )
+    .await
 }
 
-pub async fn ensure_dash_ready(storage: &Storage, id: &Uuid) -> Result<(), AppError> {
+pub async fn ensure_dash_ready(
+    storage: &Storage,
+    runner: &DynProcessRunner,
+    id: &Uuid,
+) -> Result<(), AppError> {
     let source = storage.download_path(id);
     if !source.exists() {
         return Err(AppError::not_found(format!(
@@ -161,30 +385,114 @@ pub async fn ensure_dash_ready(storage: &Storage, id: &Uuid) -> Result<(), AppEr
     if manifest.exists() {
         return Ok(());
     }
+    let _lock = storage
+        .locks()
+        .acquire(&LockManager::video_key(id, "dash"))
+        .await?;
+    if manifest.exists() {
+        return Ok(());
+    }
+
+    let meta = metadata::load(storage, id).await?;
+    let (has_audio, renditions) = source_ladder(runner, &source, &meta).await?;
+    generate_dash_stream(
+        storage,
+        runner,
+        id,
+        &source,
+        has_audio,
+        renditions,
+        &LadderEncoding::new(meta.profile, meta.color),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn ensure_master_copy(hls_dir: &Path) -> Result<(), AppError> {
+    let master = hls_dir.join("master.m3u8");
+    if !master.exists() {
+        fs::copy(hls_dir.join("index.m3u8"), &master).await?;
+    }
+    Ok(())
+}
 
-    let has_audio = probe_has_audio(&source).await.unwrap_or(false);
-    let geometry = probe_video_geometry(&source).await?;
-    let renditions = select_renditions(geometry);
-    generate_dash_stream(storage, id, &source, has_audio, renditions).await
+/// Audio presence and rendition ladder for regenerating streams from a
+/// stored download. Audio-only videos package without video renditions.
+async fn source_ladder(
+    runner: &DynProcessRunner,
+    source: &Path,
+    meta: &VideoMetadata,
+) -> Result<(bool, Vec<Rendition>), AppError> {
+    if meta.audio_only {
+        return Ok((true, Vec::new()));
+    }
+    let has_audio = probe_has_audio(runner, source).await.unwrap_or(false);
+    let geometry = probe_video_geometry(runner, source).await?;
+    Ok((
+        has_audio,
+        select_renditions(
+            geometry,
+            &LadderConfig::for_video(meta.profile, meta.ladder),
+        ),
+    ))
 }
 
+async fn encode_audio_download(
+    jobs: &DynJobStore,
+    runner: &DynProcessRunner,
+    id: &Uuid,
+    output: &Path,
+    input: &Path,
+    duration: Option<Duration>,
+) -> Result<&'static str, AppError> {
+    let mut args = base_encode_args(input);
+    args.extend([os("-map"), os("0:a:0"), os("-vn")]);
+    apply_audio_args(&mut args, true);
+    args.push(os_path(output));
+
+    tracing::info!(path = %output.display(), "starting audio-only encode");
+
+    match duration {
+        Some(total) => {
+            run_ffmpeg_with_progress(
+                runner,
+                args,
+                FfmpegProgressConfig {
+                    total_duration: total,
+                    jobs: jobs.clone(),
+                    job_id: *id,
+                    operation: "encode_download",
+                },
+            )
+            .await?
+        }
+        None => run_ffmpeg(runner, args).await?,
+    }
+    jobs.update_stage_eta(*id, Some(0.0)).await?;
+    Ok("libopus")
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn encode_download(
     jobs: &DynJobStore,
+    runner: &DynProcessRunner,
     id: &Uuid,
     output: &Path,
     input: &Path,
     has_audio: bool,
     duration: Option<Duration>,
     params: EncodeParams,
-) -> Result<(), AppError> {
+    color: &OutputColor,
+) -> Result<&'static str, AppError> {
     ensure_parent(output).await?;
 
     let candidates = encoder_candidates(params.preferred_encoder());
     let mut last_error: Option<AppError> = None;
+    let mut failed: Vec<(EncoderKind, String)> = Vec::new();
 
     for encoder in candidates {
         let mut args = base_encode_args(input);
-        apply_encoder_args(&mut args, encoder, params);
+        apply_encoder_args(&mut args, encoder, params, color);
         apply_audio_args(&mut args, has_audio);
         args.push(os_path(output));
 
@@ -192,6 +500,7 @@ async fn encode_download(
 
         let result = if let Some(total) = duration {
             run_ffmpeg_with_progress(
+                runner,
                 args,
                 FfmpegProgressConfig {
                     total_duration: total,
@@ -202,13 +511,18 @@ async fn encode_download(
             )
             .await
         } else {
-            run_ffmpeg(args).await
+            run_ffmpeg(runner, args).await
         };
 
         match result {
             Ok(()) => {
+                // A fallback succeeding on the same input means the earlier
+                // encoders are broken on this host, not the input.
+                for (kind, error) in &failed {
+                    record_encoder_failure(*kind, error);
+                }
                 jobs.update_stage_eta(*id, Some(0.0)).await?;
-                return Ok(());
+                return Ok(encoder.ffmpeg_codec());
             }
             Err(err) => {
                 tracing::warn!(
@@ -216,6 +530,7 @@ async fn encode_download(
                     error = %err,
                     "ffmpeg encode failed, attempting fallback"
                 );
+                failed.push((encoder, err.to_string()));
                 last_error = Some(err);
                 continue;
             }
@@ -225,11 +540,74 @@ async fn encode_download(
     Err(last_error.unwrap_or_else(|| AppError::transcode("encode pipeline failed")))
 }
 
+/// High-quality H.264 or HEVC download in Matroska. Only the ladder is
+/// delivered as AV1, so the download is encoded once with a fast software
+/// encoder instead of twice with an AV1 one.
+#[allow(clippy::too_many_arguments)]
+async fn encode_fast_mezzanine(
+    jobs: &DynJobStore,
+    runner: &DynProcessRunner,
+    id: &Uuid,
+    output: &Path,
+    input: &Path,
+    has_audio: bool,
+    duration: Option<Duration>,
+    codec: MezzanineCodec,
+    color: &OutputColor,
+) -> Result<&'static str, AppError> {
+    ensure_parent(output).await?;
+
+    // Like the H.264 ladder, an H.264 download stays 8-bit.
+    let (encoder, crf, pix_fmt) = match codec {
+        MezzanineCodec::H264 => ("libx264", "16", "yuv420p"),
+        _ => ("libx265", "18", color.pix_fmt("yuv420p", "yuv420p10le")),
+    };
+    let mut args = base_encode_args(input);
+    args.extend([
+        os("-c:v"),
+        os(encoder),
+        os("-preset"),
+        os("medium"),
+        os("-crf"),
+        os(crf),
+        os("-pix_fmt"),
+        os(pix_fmt),
+    ]);
+    args.extend(color.tag_args());
+    apply_audio_args(&mut args, has_audio);
+    args.extend([os("-f"), os("matroska"), os_path(output)]);
+
+    tracing::info!(?codec, path = %output.display(), "starting mezzanine encode");
+    match duration {
+        Some(total) => {
+            run_ffmpeg_with_progress(
+                runner,
+                args,
+                FfmpegProgressConfig {
+                    total_duration: total,
+                    jobs: jobs.clone(),
+                    job_id: *id,
+                    operation: "encode_download",
+                },
+            )
+            .await?
+        }
+        None => run_ffmpeg(runner, args).await?,
+    }
+    jobs.update_stage_eta(*id, Some(0.0)).await?;
+    Ok(encoder)
+}
+
 fn base_encode_args(input: &Path) -> Vec<OsString> {
     vec![os("-y"), os("-i"), os_path(input)]
 }
 
-fn apply_encoder_args(args: &mut Vec<OsString>, encoder: EncoderKind, params: EncodeParams) {
+fn apply_encoder_args(
+    args: &mut Vec<OsString>,
+    encoder: EncoderKind,
+    params: EncodeParams,
+    color: &OutputColor,
+) {
     match encoder {
         EncoderKind::VideoToolboxAv1 => {
             args.extend([
@@ -238,7 +616,7 @@ fn apply_encoder_args(args: &mut Vec<OsString>, encoder: EncoderKind, params: En
                 os("-q:v"),
                 os(params.crf.to_string()),
                 os("-pix_fmt"),
-                os("yuv420p"),
+                os(color.pix_fmt("yuv420p", "p010le")),
             ]);
         }
         EncoderKind::NvencAv1 => {
@@ -255,7 +633,7 @@ fn apply_encoder_args(args: &mut Vec<OsString>, encoder: EncoderKind, params: En
                 os("-cq"),
                 os(cq.to_string()),
                 os("-pix_fmt"),
-                os("yuv420p"),
+                os(color.pix_fmt("yuv420p", "p010le")),
             ]);
         }
         EncoderKind::QsvAv1 => {
@@ -267,12 +645,12 @@ fn apply_encoder_args(args: &mut Vec<OsString>, encoder: EncoderKind, params: En
                 os("-global_quality"),
                 os(params.crf.to_string()),
                 os("-pix_fmt"),
-                os("yuv420p"),
+                os(color.pix_fmt("yuv420p", "p010le")),
             ]);
         }
         EncoderKind::VaapiAv1 => {
             let device =
-                env::var("VIDEO_VAAPI_DEVICE").unwrap_or_else(|_| "/dev/dri/renderD128".into());
+                config::var("VIDEO_VAAPI_DEVICE").unwrap_or_else(|| "/dev/dri/renderD128".into());
             args.extend([
                 os("-hwaccel"),
                 os("vaapi"),
@@ -281,7 +659,7 @@ fn apply_encoder_args(args: &mut Vec<OsString>, encoder: EncoderKind, params: En
                 os("-hwaccel_output_format"),
                 os("vaapi"),
                 os("-vf"),
-                os("format=nv12,hwupload"),
+                os(format!("format={},hwupload", color.pix_fmt("nv12", "p010"))),
                 os("-c:v"),
                 os("av1_vaapi"),
                 os("-qp"),
@@ -301,10 +679,12 @@ fn apply_encoder_args(args: &mut Vec<OsString>, encoder: EncoderKind, params: En
                 os("-cpu-used"),
                 os(params.cpu_used.to_string()),
                 os("-pix_fmt"),
-                os("yuv420p"),
+                os(color.pix_fmt("yuv420p", "yuv420p10le")),
             ]);
+            args.extend(params.profile.av1_tuning().libaom_args());
         }
     }
+    args.extend(color.tag_args());
 }
 
 fn apply_audio_args(args: &mut Vec<OsString>, has_audio: bool) {
@@ -0,0 +1,100 @@
+use std::{path::Path, time::Duration};
+
+use tokio::fs;
+
+use crate::{config, error::AppError, process::DynProcessRunner};
+
+use super::{
+    ffmpeg::run_ffmpeg,
+    util::{os, os_path},
+};
+
+const DEFAULT_SAMPLES: u32 = 3;
+const DEFAULT_SAMPLE_SECS: f64 = 2.0;
+const PREVIEW_WIDTH: u32 = 320;
+const PREVIEW_FPS: u32 = 12;
+
+/// Renders a looping animated WebP at `target` from evenly spaced samples of
+/// `source`. `VIDEO_PREVIEW_SAMPLES` and `VIDEO_PREVIEW_SAMPLE_SECS` set how
+/// many clips are taken and how long each is.
+pub(crate) async fn render_preview(
+    runner: &DynProcessRunner,
+    source: &Path,
+    target: &Path,
+    duration: Option<Duration>,
+) -> Result<(), AppError> {
+    let samples = config::parse_var::<u32>("VIDEO_PREVIEW_SAMPLES")
+        .filter(|samples| (1..=10).contains(samples))
+        .unwrap_or(DEFAULT_SAMPLES);
+    let length = config::parse_var::<f64>("VIDEO_PREVIEW_SAMPLE_SECS")
+        .filter(|secs| *secs > 0.0 && *secs <= 10.0)
+        .unwrap_or(DEFAULT_SAMPLE_SECS);
+    let clips = sample_clips(duration, samples, length);
+
+    let mut args = vec![os("-y")];
+    for (start, length) in &clips {
+        args.extend([
+            os("-ss"),
+            os(format!("{start:.3}")),
+            os("-t"),
+            os(format!("{length:.3}")),
+            os("-i"),
+            os_path(source),
+        ]);
+    }
+    let mut graph = String::new();
+    for index in 0..clips.len() {
+        graph.push_str(&format!(
+            "[{index}:v]fps={PREVIEW_FPS},scale={PREVIEW_WIDTH}:-2,setsar=1[v{index}];"
+        ));
+    }
+    for index in 0..clips.len() {
+        graph.push_str(&format!("[v{index}]"));
+    }
+    graph.push_str(&format!("concat=n={}:v=1:a=0[preview]", clips.len()));
+
+    let temp = target.with_extension("webp.part");
+    args.extend([
+        os("-filter_complex"),
+        os(graph),
+        os("-map"),
+        os("[preview]"),
+        os("-an"),
+        os("-c:v"),
+        os("libwebp_anim"),
+        os("-quality"),
+        os("70"),
+        os("-loop"),
+        os("0"),
+        os("-f"),
+        os("webp"),
+        os_path(&temp),
+    ]);
+    if let Err(err) = run_ffmpeg(runner, args).await {
+        fs::remove_file(&temp).await.ok();
+        return Err(err);
+    }
+    fs::rename(&temp, target).await?;
+    Ok(())
+}
+
+/// Start and length of each clip. Clips are centred in equal slices of the
+/// video; a video too short to hold them all becomes a single clip.
+fn sample_clips(duration: Option<Duration>, samples: u32, length: f64) -> Vec<(f64, f64)> {
+    let Some(total) = duration
+        .map(|total| total.as_secs_f64())
+        .filter(|total| *total > 0.0)
+    else {
+        return vec![(0.0, length * f64::from(samples))];
+    };
+    if total <= length * f64::from(samples) {
+        return vec![(0.0, total)];
+    }
+    let slice = total / f64::from(samples);
+    (0..samples)
+        .map(|index| {
+            let start = slice * f64::from(index) + (slice - length) / 2.0;
+            (start, length)
+        })
+        .collect()
+}
@@ -1,27 +1,69 @@
-use std::{path::Path, time::Duration};
+use std::{ffi::OsString, path::Path, time::Duration};
 
-use tokio::process::Command;
+use serde::{Deserialize, Serialize};
 
-use crate::error::AppError;
+use crate::{
+    digest::Container,
+    error::AppError,
+    process::{DynProcessRunner, ProcessOutput},
+};
 
-use super::util::map_io_error;
+use super::util::{map_io_error, os, os_path};
 
 const FFPROBE_BIN: &str = "ffprobe";
 
-pub(crate) async fn probe_has_audio(input: &Path) -> Result<bool, AppError> {
-    let output = Command::new(FFPROBE_BIN)
-        .arg("-v")
-        .arg("error")
-        .arg("-select_streams")
-        .arg("a")
-        .arg("-show_entries")
-        .arg("stream=index")
-        .arg("-of")
-        .arg("csv=p=0")
-        .arg(input)
-        .output()
+async fn run_ffprobe(
+    runner: &DynProcessRunner,
+    args: Vec<OsString>,
+) -> Result<ProcessOutput, AppError> {
+    runner
+        .output(FFPROBE_BIN, &args)
         .await
-        .map_err(map_io_error)?;
+        .map_err(map_io_error)
+}
+
+pub(crate) async fn probe_has_audio(
+    runner: &DynProcessRunner,
+    input: &Path,
+) -> Result<bool, AppError> {
+    probe_has_stream(runner, input, "a").await
+}
+
+/// Ignores attached pictures such as MP3 cover art (the `V` stream specifier).
+pub(crate) async fn probe_has_video(
+    runner: &DynProcessRunner,
+    input: &Path,
+) -> Result<bool, AppError> {
+    probe_has_stream(runner, input, "V").await
+}
+
+pub(crate) async fn probe_has_subtitles(
+    runner: &DynProcessRunner,
+    input: &Path,
+) -> Result<bool, AppError> {
+    probe_has_stream(runner, input, "s").await
+}
+
+async fn probe_has_stream(
+    runner: &DynProcessRunner,
+    input: &Path,
+    selector: &str,
+) -> Result<bool, AppError> {
+    let output = run_ffprobe(
+        runner,
+        vec![
+            os("-v"),
+            os("error"),
+            os("-select_streams"),
+            os(selector),
+            os("-show_entries"),
+            os("stream=index"),
+            os("-of"),
+            os("csv=p=0"),
+            os_path(input),
+        ],
+    )
+    .await?;
 
     if !output.status.success() {
         return Err(AppError::transcode(format!(
@@ -33,18 +75,23 @@ pub(crate) async fn probe_has_audio(input: &Path) -> Result<bool, AppError> {
     Ok(!output.stdout.is_empty())
 }
 
-pub(crate) async fn probe_duration(input: &Path) -> Result<Option<Duration>, AppError> {
-    let output = Command::new(FFPROBE_BIN)
-        .arg("-v")
-        .arg("error")
-        .arg("-show_entries")
-        .arg("format=duration")
-        .arg("-of")
-        .arg("default=noprint_wrappers=1:nokey=1")
-        .arg(input)
-        .output()
-        .await
-        .map_err(map_io_error)?;
+pub(crate) async fn probe_duration(
+    runner: &DynProcessRunner,
+    input: &Path,
+) -> Result<Option<Duration>, AppError> {
+    let output = run_ffprobe(
+        runner,
+        vec![
+            os("-v"),
+            os("error"),
+            os("-show_entries"),
+            os("format=duration"),
+            os("-of"),
+            os("default=noprint_wrappers=1:nokey=1"),
+            os_path(input),
+        ],
+    )
+    .await?;
 
     if !output.status.success() {
         tracing::warn!(status = %output.status, "ffprobe did not report duration");
@@ -69,26 +116,159 @@ pub(crate) async fn probe_duration(input: &Path) -> Result<Option<Duration>, App
     Ok(None)
 }
 
+/// Returns the first video stream's frame rate as a reduced `num/den` string,
+/// the form DASH expects for `@frameRate`.
+pub(crate) async fn probe_frame_rate(
+    runner: &DynProcessRunner,
+    input: &Path,
+) -> Result<Option<String>, AppError> {
+    let output = run_ffprobe(
+        runner,
+        vec![
+            os("-v"),
+            os("error"),
+            os("-select_streams"),
+            os("v:0"),
+            os("-show_entries"),
+            os("stream=r_frame_rate"),
+            os("-of"),
+            os("default=noprint_wrappers=1:nokey=1"),
+            os_path(input),
+        ],
+    )
+    .await?;
+
+    if !output.status.success() {
+        tracing::warn!(status = %output.status, "ffprobe did not report frame rate");
+        return Ok(None);
+    }
+
+    let text = String::from_utf8_lossy(&output.stdout);
+    Ok(text.lines().next().and_then(normalize_frame_rate))
+}
+
+/// Bit depth and colour description of the first video stream.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct SourceColor {
+    pub bit_depth: u8,
+    pub primaries: Option<String>,
+    pub transfer: Option<String>,
+    pub matrix: Option<String>,
+    pub range: Option<String>,
+}
+
+/// Reads the pixel format and colour tags of the first video stream.
+/// Values ffprobe reports as `unknown` are left out.
+pub(crate) async fn probe_color(
+    runner: &DynProcessRunner,
+    input: &Path,
+) -> Result<SourceColor, AppError> {
+    let output = run_ffprobe(
+        runner,
+        vec![
+            os("-v"),
+            os("error"),
+            os("-select_streams"),
+            os("v:0"),
+            os("-show_entries"),
+            os("stream=pix_fmt,color_range,color_space,color_transfer,color_primaries"),
+            os("-of"),
+            os("default=noprint_wrappers=1"),
+            os_path(input),
+        ],
+    )
+    .await?;
+
+    if !output.status.success() {
+        return Err(AppError::transcode(format!(
+            "ffprobe exited with status {} while probing colour",
+            output.status
+        )));
+    }
+
+    let mut color = SourceColor {
+        bit_depth: 8,
+        ..SourceColor::default()
+    };
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        if value.is_empty() || matches!(value, "unknown" | "unspecified" | "reserved") {
+            continue;
+        }
+        match key {
+            "pix_fmt" => color.bit_depth = pixel_format_depth(value),
+            "color_primaries" => color.primaries = Some(value.to_string()),
+            "color_transfer" => color.transfer = Some(value.to_string()),
+            "color_space" => color.matrix = Some(value.to_string()),
+            "color_range" => color.range = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    Ok(color)
+}
+
+/// Bits per component of an ffmpeg pixel format, e.g. 10 for `yuv420p10le`
+/// and for the semi-planar `p010le`.
+pub(crate) fn pixel_format_depth(pix_fmt: &str) -> u8 {
+    let semi_planar = pix_fmt
+        .strip_prefix('p')
+        .filter(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))
+        .and_then(|rest| rest.get(1..));
+    semi_planar
+        .or_else(|| pix_fmt.rsplit_once('p').map(|(_, depth)| depth))
+        .map(|depth| depth.trim_end_matches(|c: char| c.is_ascii_alphabetic()))
+        .and_then(|depth| depth.parse::<u8>().ok())
+        .filter(|depth| (9..=16).contains(depth))
+        .unwrap_or(8)
+}
+
+pub(crate) fn normalize_frame_rate(raw: &str) -> Option<String> {
+    let (num, den) = raw.trim().split_once('/').unwrap_or((raw.trim(), "1"));
+    let num = num.trim().parse::<u64>().ok().filter(|value| *value > 0)?;
+    let den = den.trim().parse::<u64>().ok().filter(|value| *value > 0)?;
+    let divisor = gcd(num, den);
+    let (num, den) = (num / divisor, den / divisor);
+    Some(if den == 1 {
+        num.to_string()
+    } else {
+        format!("{num}/{den}")
+    })
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) struct VideoGeometry {
     pub width: u32,
     pub height: u32,
 }
 
-pub(crate) async fn probe_video_geometry(input: &Path) -> Result<VideoGeometry, AppError> {
-    let output = Command::new(FFPROBE_BIN)
-        .arg("-v")
-        .arg("error")
-        .arg("-select_streams")
-        .arg("v:0")
-        .arg("-show_entries")
-        .arg("stream=width,height")
-        .arg("-of")
-        .arg("csv=p=0:s=x")
-        .arg(input)
-        .output()
-        .await
-        .map_err(map_io_error)?;
+pub(crate) async fn probe_video_geometry(
+    runner: &DynProcessRunner,
+    input: &Path,
+) -> Result<VideoGeometry, AppError> {
+    let output = run_ffprobe(
+        runner,
+        vec![
+            os("-v"),
+            os("error"),
+            os("-select_streams"),
+            os("v:0"),
+            os("-show_entries"),
+            os("stream=width,height"),
+            os("-of"),
+            os("csv=p=0:s=x"),
+            os_path(input),
+        ],
+    )
+    .await?;
 
     if !output.status.success() {
         return Err(AppError::transcode(format!(
@@ -113,3 +293,229 @@ pub(crate) async fn probe_video_geometry(input: &Path) -> Result<VideoGeometry,
         )),
     }
 }
+
+/// Summary of an ingested file, as handed to ingest policies.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SourceProbe {
+    pub width: u32,
+    pub height: u32,
+    pub duration_seconds: Option<f64>,
+    pub frame_rate: Option<String>,
+    pub has_audio: bool,
+}
+
+/// Probes `input` once per property. Fails when no video stream is found.
+pub async fn probe_source(
+    runner: &DynProcessRunner,
+    input: &Path,
+) -> Result<SourceProbe, AppError> {
+    let geometry = probe_video_geometry(runner, input).await?;
+    let duration = probe_duration(runner, input).await.unwrap_or(None);
+    let frame_rate = probe_frame_rate(runner, input).await.unwrap_or(None);
+    let has_audio = probe_has_audio(runner, input).await.unwrap_or(false);
+    Ok(SourceProbe {
+        width: geometry.width,
+        height: geometry.height,
+        duration_seconds: duration.map(|value| value.as_secs_f64()),
+        frame_rate,
+        has_audio,
+    })
+}
+
+/// Container and stream details of a finished file, as reported by
+/// `GET /videos/{id}/info`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MediaInfo {
+    /// ffprobe's format name, e.g. `matroska,webm`.
+    pub format: String,
+    pub duration_seconds: Option<f64>,
+    /// Overall bitrate in bits per second.
+    pub bit_rate: Option<u64>,
+    pub video: Option<VideoStreamInfo>,
+    pub audio: Vec<AudioStreamInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VideoStreamInfo {
+    pub codec: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    /// Reduced `num/den`, or a whole number.
+    pub frame_rate: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pixel_format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bit_rate: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AudioStreamInfo {
+    pub codec: String,
+    pub channels: Option<u32>,
+    /// e.g. `stereo` or `5.1(side)`.
+    pub channel_layout: Option<String>,
+    pub sample_rate: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bit_rate: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProbeReport {
+    #[serde(default)]
+    streams: Vec<ProbeStream>,
+    format: Option<ProbeFormat>,
+}
+
+#[derive(Deserialize)]
+struct ProbeFormat {
+    format_name: Option<String>,
+    duration: Option<String>,
+    bit_rate: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProbeStream {
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    profile: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    r_frame_rate: Option<String>,
+    pix_fmt: Option<String>,
+    bit_rate: Option<String>,
+    channels: Option<u32>,
+    channel_layout: Option<String>,
+    sample_rate: Option<String>,
+    #[serde(default)]
+    disposition: ProbeDisposition,
+    #[serde(default)]
+    tags: ProbeTags,
+}
+
+#[derive(Default, Deserialize)]
+struct ProbeDisposition {
+    #[serde(default)]
+    attached_pic: u8,
+}
+
+#[derive(Default, Deserialize)]
+struct ProbeTags {
+    language: Option<String>,
+}
+
+/// Probes container and streams of `input` in one ffprobe run. Cover art is
+/// not reported as the video stream.
+pub async fn probe_media_info(
+    runner: &DynProcessRunner,
+    input: &Path,
+) -> Result<MediaInfo, AppError> {
+    let output = run_ffprobe(
+        runner,
+        vec![
+            os("-v"),
+            os("error"),
+            os("-show_format"),
+            os("-show_streams"),
+            os("-of"),
+            os("json"),
+            os_path(input),
+        ],
+    )
+    .await?;
+
+    if !output.status.success() {
+        return Err(AppError::transcode(format!(
+            "ffprobe exited with status {} while probing media info",
+            output.status
+        )));
+    }
+
+    let report: ProbeReport = serde_json::from_slice(&output.stdout)
+        .map_err(|err| AppError::transcode(format!("unreadable ffprobe output: {err}")))?;
+    Ok(media_info_from(report))
+}
+
+/// Refuses sources that are not audio, video or an image. A file whose first
+/// bytes match a known `container` passes as is; anything else is probed and
+/// needs at least one audio or video stream.
+pub async fn ensure_media(
+    runner: &DynProcessRunner,
+    input: &Path,
+    container: Option<Container>,
+) -> Result<(), AppError> {
+    if container.is_some() {
+        return Ok(());
+    }
+    let info = match probe_media_info(runner, input).await {
+        Ok(info) => Some(info),
+        Err(err) if matches!(err.root(), AppError::Transcode(_)) => None,
+        Err(err) => return Err(err),
+    };
+    if info.is_some_and(|info| info.video.is_some() || !info.audio.is_empty()) {
+        return Ok(());
+    }
+    Err(
+        AppError::validation("the source is not a video, audio or image file")
+            .with_code("source_not_media"),
+    )
+}
+
+fn media_info_from(report: ProbeReport) -> MediaInfo {
+    let parse_u64 = |value: Option<&String>| value.and_then(|raw| raw.trim().parse::<u64>().ok());
+    let format = report.format;
+    let video = report
+        .streams
+        .iter()
+        .filter(|stream| stream.codec_type.as_deref() == Some("video"))
+        .find(|stream| stream.disposition.attached_pic == 0)
+        .and_then(|stream| {
+            Some(VideoStreamInfo {
+                codec: stream.codec_name.clone()?,
+                profile: stream.profile.clone(),
+                width: stream.width.filter(|width| *width > 0)?,
+                height: stream.height.filter(|height| *height > 0)?,
+                frame_rate: stream
+                    .r_frame_rate
+                    .as_deref()
+                    .and_then(normalize_frame_rate),
+                pixel_format: stream.pix_fmt.clone(),
+                bit_rate: parse_u64(stream.bit_rate.as_ref()),
+            })
+        });
+    let audio = report
+        .streams
+        .iter()
+        .filter(|stream| stream.codec_type.as_deref() == Some("audio"))
+        .filter_map(|stream| {
+            Some(AudioStreamInfo {
+                codec: stream.codec_name.clone()?,
+                channels: stream.channels,
+                channel_layout: stream.channel_layout.clone(),
+                sample_rate: stream
+                    .sample_rate
+                    .as_deref()
+                    .and_then(|raw| raw.trim().parse().ok()),
+                bit_rate: parse_u64(stream.bit_rate.as_ref()),
+                language: stream.tags.language.clone(),
+            })
+        })
+        .collect();
+    MediaInfo {
+        format: format
+            .as_ref()
+            .and_then(|format| format.format_name.clone())
+            .unwrap_or_default(),
+        duration_seconds: format
+            .as_ref()
+            .and_then(|format| format.duration.as_deref())
+            .and_then(|raw| raw.trim().parse::<f64>().ok())
+            .filter(|seconds| seconds.is_finite() && *seconds > 0.0),
+        bit_rate: parse_u64(format.as_ref().and_then(|format| format.bit_rate.as_ref())),
+        video,
+        audio,
+    }
+}
@@ -0,0 +1,196 @@
+use std::ffi::OsString;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config, playlist::CodecFamily};
+
+use super::{
+    stages::{OptionalStage, parse_stage_list},
+    util::os,
+};
+
+/// Named packaging presets selectable per upload.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscodeProfile {
+    /// AV1 renditions in fragmented MP4 segments.
+    #[default]
+    Standard,
+    /// H.264 renditions in MPEG-TS segments for players without fMP4 HLS support.
+    Compat,
+}
+
+impl TranscodeProfile {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TranscodeProfile::Standard => "standard",
+            TranscodeProfile::Compat => "compat",
+        }
+    }
+
+    /// Optional stages run after packaging. `VIDEO_PROFILE_<NAME>_STAGES` (a
+    /// comma-separated list, empty for none) replaces the built-in defaults.
+    pub fn optional_stages(&self) -> Vec<OptionalStage> {
+        let key = format!("VIDEO_PROFILE_{}_STAGES", self.name().to_ascii_uppercase());
+        if let Some(value) = config::var(&key) {
+            return parse_stage_list(&value);
+        }
+        match self {
+            TranscodeProfile::Standard => {
+                vec![OptionalStage::Thumbnails, OptionalStage::Sprites]
+            }
+            TranscodeProfile::Compat => vec![
+                OptionalStage::Thumbnails,
+                OptionalStage::Sprites,
+                OptionalStage::Mp4Fallback,
+            ],
+        }
+    }
+
+    /// Whether the ladder gets extra 240p and 144p rungs for slow mobile
+    /// networks. `VIDEO_PROFILE_<NAME>_LOW_RUNGS` turns them on; they are
+    /// off by default.
+    pub fn low_rungs(&self) -> bool {
+        let key = format!(
+            "VIDEO_PROFILE_{}_LOW_RUNGS",
+            self.name().to_ascii_uppercase()
+        );
+        config::var(&key).is_some_and(|value| matches!(value.trim(), "1" | "true" | "yes" | "on"))
+    }
+
+    /// libaom tuning from `VIDEO_PROFILE_<NAME>_FILM_GRAIN`, `_TUNE`,
+    /// `_ARNR_STRENGTH` and `_ARNR_MAX_FRAMES`. Values out of range are
+    /// ignored with a warning.
+    pub fn av1_tuning(&self) -> Av1Tuning {
+        let prefix = format!("VIDEO_PROFILE_{}", self.name().to_ascii_uppercase());
+        let bounded = |suffix: &str, max: u8| {
+            let key = format!("{prefix}_{suffix}");
+            let value = config::var(&key)?;
+            match value.trim().parse::<u8>() {
+                Ok(level) if level <= max => Some(level),
+                _ => {
+                    tracing::warn!(key, value, max, "ignoring out-of-range AV1 tuning value");
+                    None
+                }
+            }
+        };
+        let tune = config::var(&format!("{prefix}_TUNE")).and_then(|value| {
+            match value.trim().to_ascii_lowercase().as_str() {
+                "psnr" => Some(Av1Tune::Psnr),
+                "ssim" => Some(Av1Tune::Ssim),
+                _ => {
+                    tracing::warn!(value, "ignoring unknown AV1 tune; use psnr or ssim");
+                    None
+                }
+            }
+        });
+        Av1Tuning {
+            film_grain: bounded("FILM_GRAIN", 50).filter(|level| *level > 0),
+            tune,
+            arnr_strength: bounded("ARNR_STRENGTH", 6),
+            arnr_max_frames: bounded("ARNR_MAX_FRAMES", 15),
+        }
+    }
+
+    pub(crate) fn hls_packaging(&self) -> HlsPackaging {
+        match self {
+            TranscodeProfile::Standard => HlsPackaging {
+                segments: HlsSegmentFormat::Fmp4,
+                codec: LadderCodec::Av1,
+            },
+            TranscodeProfile::Compat => HlsPackaging {
+                segments: HlsSegmentFormat::MpegTs,
+                codec: LadderCodec::H264,
+            },
+        }
+    }
+}
+
+/// libaom settings for grainy or detail-heavy sources, set per profile.
+/// Unset fields keep libaom's defaults; hardware encoders ignore them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Av1Tuning {
+    /// Film-grain synthesis level, 1 to 50. The encoder denoises the grain
+    /// away and signals a grain model that the decoder adds back, instead of
+    /// blurring it or spending bitrate on it.
+    pub film_grain: Option<u8>,
+    pub tune: Option<Av1Tune>,
+    /// Strength of the temporal filter on alt-ref frames, 0 to 6.
+    pub arnr_strength: Option<u8>,
+    /// Frames the temporal filter averages over, 0 to 15.
+    pub arnr_max_frames: Option<u8>,
+}
+
+/// Metric libaom optimises for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Av1Tune {
+    Psnr,
+    Ssim,
+}
+
+impl Av1Tuning {
+    pub(crate) fn libaom_args(&self) -> Vec<OsString> {
+        let mut args = Vec::new();
+        if let Some(level) = self.film_grain {
+            args.extend([os("-denoise-noise-level"), os(level.to_string())]);
+        }
+        if let Some(tune) = self.tune {
+            let name = match tune {
+                Av1Tune::Psnr => "psnr",
+                Av1Tune::Ssim => "ssim",
+            };
+            args.extend([os("-tune"), os(name)]);
+        }
+        if let Some(strength) = self.arnr_strength {
+            args.extend([os("-arnr-strength"), os(strength.to_string())]);
+        }
+        if let Some(frames) = self.arnr_max_frames {
+            args.extend([os("-arnr-max-frames"), os(frames.to_string())]);
+        }
+        args
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct HlsPackaging {
+    pub segments: HlsSegmentFormat,
+    pub codec: LadderCodec,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum HlsSegmentFormat {
+    Fmp4,
+    MpegTs,
+}
+
+impl HlsSegmentFormat {
+    pub(crate) fn ffmpeg_name(&self) -> &'static str {
+        match self {
+            HlsSegmentFormat::Fmp4 => "fmp4",
+            HlsSegmentFormat::MpegTs => "mpegts",
+        }
+    }
+
+    pub(crate) fn extension(&self) -> &'static str {
+        match self {
+            HlsSegmentFormat::Fmp4 => "m4s",
+            HlsSegmentFormat::MpegTs => "ts",
+        }
+    }
+}
+
+/// Video codec used for the adaptive ladder renditions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum LadderCodec {
+    Av1,
+    H264,
+}
+
+impl LadderCodec {
+    pub(crate) fn family(&self) -> CodecFamily {
+        match self {
+            LadderCodec::Av1 => CodecFamily::Av1,
+            LadderCodec::H264 => CodecFamily::H264,
+        }
+    }
+}
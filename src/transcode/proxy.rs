@@ -0,0 +1,98 @@
+use std::path::Path;
+
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    error::AppError,
+    jobs::DynJobStore,
+    process::DynProcessRunner,
+    storage::{Storage, ensure_parent},
+};
+
+use super::{
+    ffmpeg::{FfmpegProgressConfig, run_ffmpeg, run_ffmpeg_with_progress},
+    probe::{probe_duration, probe_has_video},
+    util::{os, os_path},
+};
+
+const PROXY_HEIGHT: u32 = 480;
+
+/// Renders `proxy.mp4`, a quick 480p H.264 copy of `input` that plays in any
+/// browser, for reviewing a video before its full encode. Audio-only sources
+/// get an audio-only proxy. Progress is reported on the job when `jobs` is
+/// given; a proxy rendered alongside the encode leaves it to the encode.
+pub async fn render_proxy(
+    storage: &Storage,
+    jobs: Option<&DynJobStore>,
+    runner: &DynProcessRunner,
+    id: &Uuid,
+    input: &Path,
+) -> Result<(), AppError> {
+    let target = storage.proxy_path(id);
+    ensure_parent(&target).await?;
+    let temp = target.with_extension("mp4.part");
+
+    let mut args = vec![os("-y"), os("-i"), os_path(input)];
+    if probe_has_video(runner, input).await? {
+        args.extend([
+            os("-map"),
+            os("0:v:0"),
+            os("-vf"),
+            os(format!("scale=-2:'min({PROXY_HEIGHT},ih)'")),
+            os("-c:v"),
+            os("libx264"),
+            os("-preset"),
+            os("veryfast"),
+            os("-crf"),
+            os("30"),
+            os("-pix_fmt"),
+            os("yuv420p"),
+        ]);
+    }
+    args.extend([
+        os("-map"),
+        os("0:a:0?"),
+        os("-c:a"),
+        os("aac"),
+        os("-b:a"),
+        os("96k"),
+        os("-ac"),
+        os("2"),
+        os("-movflags"),
+        os("+faststart"),
+        os("-f"),
+        os("mp4"),
+        os_path(&temp),
+    ]);
+
+    let progress = match jobs {
+        Some(jobs) => probe_duration(runner, input)
+            .await
+            .unwrap_or(None)
+            .map(|total| (jobs, total)),
+        None => None,
+    };
+    let result = match progress {
+        Some((jobs, total)) => {
+            run_ffmpeg_with_progress(
+                runner,
+                args,
+                FfmpegProgressConfig {
+                    total_duration: total,
+                    jobs: jobs.clone(),
+                    job_id: *id,
+                    operation: "render_proxy",
+                },
+            )
+            .await
+        }
+        None => run_ffmpeg(runner, args).await,
+    };
+    if let Err(err) = result {
+        fs::remove_file(&temp).await.ok();
+        return Err(err);
+    }
+    fs::rename(&temp, &target).await?;
+    Ok(())
+}
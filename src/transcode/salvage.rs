@@ -0,0 +1,74 @@
+use std::path::Path;
+
+use tokio::fs;
+
+use crate::{error::AppError, metadata::Truncation, process::DynProcessRunner};
+
+use super::{
+    ffmpeg::{POSITION_PARAM, run_ffmpeg},
+    probe::probe_duration,
+    util::{os, os_path},
+};
+
+/// Shortest salvaged part worth publishing.
+const MIN_SALVAGED_SECONDS: f64 = 1.0;
+
+/// Where `err`, a failed encode, got to in its source, when enough of the
+/// source decoded to be worth publishing on its own.
+pub fn salvage_point(err: &AppError) -> Option<f64> {
+    if !matches!(err.root(), AppError::Transcode(_)) {
+        return None;
+    }
+    err.params()
+        .get(POSITION_PARAM)
+        .and_then(|value| value.as_f64())
+        .filter(|&seconds| seconds >= MIN_SALVAGED_SECONDS)
+}
+
+/// Cuts `input` in place to its first `seconds`, the part that decoded
+/// before the encode failed. Streams are copied with decoding errors
+/// ignored and corrupt packets dropped, so the cut itself gets past the
+/// damage it stops short of.
+pub async fn truncate_source(
+    runner: &DynProcessRunner,
+    input: &Path,
+    seconds: f64,
+    error: &AppError,
+) -> Result<Truncation, AppError> {
+    let source_duration = probe_duration(runner, input)
+        .await
+        .ok()
+        .flatten()
+        .map(|duration| duration.as_secs_f64());
+    let temp = input.with_extension("salvage.mkv");
+    let args = vec![
+        os("-y"),
+        os("-err_detect"),
+        os("ignore_err"),
+        os("-fflags"),
+        os("+discardcorrupt"),
+        os("-i"),
+        os_path(input),
+        os("-t"),
+        os(format!("{seconds:.3}")),
+        os("-map"),
+        os("0:v?"),
+        os("-map"),
+        os("0:a?"),
+        os("-c"),
+        os("copy"),
+        os("-f"),
+        os("matroska"),
+        os_path(&temp),
+    ];
+    if let Err(err) = run_ffmpeg(runner, args).await {
+        fs::remove_file(&temp).await.ok();
+        return Err(err);
+    }
+    fs::rename(&temp, input).await?;
+    Ok(Truncation {
+        duration_seconds: seconds,
+        source_duration_seconds: source_duration,
+        error: error.to_string(),
+    })
+}
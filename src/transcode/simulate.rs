@@ -0,0 +1,265 @@
+use std::{
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio::{io::AsyncWriteExt, task::JoinHandle};
+
+use crate::{
+    config,
+    process::{
+        ProcessOutput, ProcessRunner, ProcessStatus, ProcessStderr, RunningProcess,
+        SystemProcessRunner,
+    },
+};
+
+const SIMULATED_MEDIA_SECONDS: f64 = 60.0;
+const SIMULATED_GEOMETRY: &str = "1920x1080";
+const TICK: Duration = Duration::from_millis(250);
+const DEFAULT_ENCODE_SECONDS: u64 = 20;
+
+/// Returns true when `VIDEO_FAKE_TRANSCODE` asks for simulated media tooling.
+pub fn fake_transcode_enabled() -> bool {
+    config::var("VIDEO_FAKE_TRANSCODE")
+        .map(|value| matches!(value.trim(), "1" | "true" | "yes" | "on"))
+        .unwrap_or(false)
+}
+
+/// Stands in for ffmpeg/ffprobe so the real pipeline runs against fabricated
+/// progress and stub outputs. Other tools (aria2c, yt-dlp) still run for real.
+#[derive(Debug, Clone)]
+pub struct SimulatedMediaRunner {
+    encode_wall_time: Duration,
+    fallback: SystemProcessRunner,
+}
+
+impl SimulatedMediaRunner {
+    pub fn new(encode_wall_time: Duration) -> Self {
+        Self {
+            encode_wall_time,
+            fallback: SystemProcessRunner,
+        }
+    }
+
+    /// Reads the simulated encode duration from `VIDEO_FAKE_TRANSCODE_SECONDS`.
+    pub fn from_env() -> Self {
+        let seconds = config::parse_var::<u64>("VIDEO_FAKE_TRANSCODE_SECONDS")
+            .unwrap_or(DEFAULT_ENCODE_SECONDS);
+        Self::new(Duration::from_secs(seconds))
+    }
+}
+
+#[async_trait]
+impl ProcessRunner for SimulatedMediaRunner {
+    async fn output(&self, program: &str, args: &[OsString]) -> io::Result<ProcessOutput> {
+        let mut stderr = String::new();
+        let stdout = match program {
+            "ffprobe" => probe_answer(args),
+            "ffmpeg"
+                if args
+                    .iter()
+                    .any(|arg| arg.to_string_lossy().contains("libvmaf")) =>
+            {
+                stderr.push_str("[libvmaf @ 0x0] VMAF score: 95.000000\n");
+                String::new()
+            }
+            "ffmpeg" => " V....D libaom-av1           libaom AV1 (codec av1)\n".to_string(),
+            _ => return self.fallback.output(program, args).await,
+        };
+        Ok(ProcessOutput {
+            status: ProcessStatus::from_code(0),
+            stdout: stdout.into_bytes(),
+            stderr: stderr.into_bytes(),
+        })
+    }
+
+    async fn spawn(&self, program: &str, args: &[OsString]) -> io::Result<Box<dyn RunningProcess>> {
+        if program != "ffmpeg" {
+            return self.fallback.spawn(program, args).await;
+        }
+
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let output = args.last().map(PathBuf::from).unwrap_or_default();
+        let is_packaging = args.iter().any(|arg| arg == "hls" || arg == "dash");
+        let wall_time = if is_packaging {
+            self.encode_wall_time / 2
+        } else {
+            self.encode_wall_time
+        };
+
+        let (mut writer, reader) = tokio::io::duplex(8192);
+        let task = tokio::spawn(async move {
+            let ticks = (wall_time.as_millis() / TICK.as_millis()).max(1) as u32;
+            for tick in 1..=ticks {
+                tokio::time::sleep(TICK).await;
+                let line = progress_line(tick, ticks, wall_time);
+                if writer.write_all(line.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+            if let Err(err) = write_outputs(&args, &output).await {
+                tracing::warn!(error = %err, "simulated ffmpeg failed to write outputs");
+            }
+        });
+
+        Ok(Box::new(SimulatedProcess {
+            stderr: Some(Box::new(reader)),
+            task: Some(task),
+        }))
+    }
+}
+
+struct SimulatedProcess {
+    stderr: Option<ProcessStderr>,
+    task: Option<JoinHandle<()>>,
+}
+
+#[async_trait]
+impl RunningProcess for SimulatedProcess {
+    fn take_stderr(&mut self) -> Option<ProcessStderr> {
+        self.stderr.take()
+    }
+
+    async fn wait(&mut self) -> io::Result<ProcessStatus> {
+        if let Some(task) = self.task.take() {
+            task.await.map_err(io::Error::other)?;
+        }
+        Ok(ProcessStatus::from_code(0))
+    }
+
+    async fn kill(&mut self) -> io::Result<()> {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        Ok(())
+    }
+}
+
+fn probe_answer(args: &[OsString]) -> String {
+    let joined = args
+        .iter()
+        .map(|arg| arg.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ");
+    if joined.contains("-show_streams") {
+        let (width, height) = SIMULATED_GEOMETRY.split_once('x').unwrap_or_default();
+        format!(
+            r#"{{"streams":[{{"codec_type":"video","codec_name":"av1","width":{width},"height":{height},"r_frame_rate":"30/1","pix_fmt":"yuv420p"}},{{"codec_type":"audio","codec_name":"opus","channels":2,"channel_layout":"stereo","sample_rate":"48000"}}],"format":{{"format_name":"matroska,webm","duration":"{SIMULATED_MEDIA_SECONDS:.6}","bit_rate":"2000000"}}}}"#
+        )
+    } else if joined.contains("stream=width,height") {
+        format!("{SIMULATED_GEOMETRY}\n")
+    } else if joined.contains("color_primaries") {
+        "pix_fmt=yuv420p\ncolor_range=tv\ncolor_space=bt709\ncolor_transfer=bt709\ncolor_primaries=bt709\n"
+            .to_string()
+    } else if joined.contains("stream=r_frame_rate") {
+        "30/1\n".to_string()
+    } else if joined.contains("format=duration") {
+        format!("{SIMULATED_MEDIA_SECONDS:.6}\n")
+    } else if joined.contains("stream=index") {
+        "1\n".to_string()
+    } else {
+        String::new()
+    }
+}
+
+/// Emits an ffmpeg-style status line. Speed ramps up over the first quarter to
+/// mimic encoder warm-up, so ETAs start pessimistic and settle like real runs.
+fn progress_line(tick: u32, ticks: u32, wall_time: Duration) -> String {
+    let fraction = tick as f64 / ticks as f64;
+    let eased = if fraction < 0.25 {
+        fraction * fraction * 2.0
+    } else {
+        0.125 + (fraction - 0.25) * (0.875 / 0.75)
+    };
+    let media_seconds = SIMULATED_MEDIA_SECONDS * eased.min(1.0);
+    let elapsed = wall_time.as_secs_f64() * fraction;
+    let speed = if elapsed > 0.0 {
+        media_seconds / elapsed
+    } else {
+        0.0
+    };
+    let frame = (media_seconds * 30.0) as u64;
+    let total = media_seconds as u64;
+    format!(
+        "frame={frame} fps=30 q=28.0 size={}kB time={:02}:{:02}:{:05.2} bitrate=2000.0kbits/s speed={speed:.2}x\r",
+        frame * 8,
+        total / 3600,
+        (total % 3600) / 60,
+        media_seconds % 60.0,
+    )
+}
+
+async fn write_outputs(args: &[String], output: &Path) -> io::Result<()> {
+    let Some(dir) = output.parent() else {
+        return Ok(());
+    };
+    tokio::fs::create_dir_all(dir).await?;
+
+    let file_name = output
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    if file_name == "stream_%v.m3u8" {
+        let extension = if args.iter().any(|arg| arg == "mpegts") {
+            "ts"
+        } else {
+            "m4s"
+        };
+        let mut master = String::from("#EXTM3U\n#EXT-X-VERSION:7\n");
+        for name in variant_names(args) {
+            master.push_str(&format!(
+                "#EXT-X-STREAM-INF:BANDWIDTH=2000000,NAME=\"{name}\"\nstream_{name}.m3u8\n"
+            ));
+            let mut playlist = String::from(
+                "#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-TARGETDURATION:4\n#EXT-X-PLAYLIST-TYPE:VOD\n",
+            );
+            for segment in 0..(SIMULATED_MEDIA_SECONDS as u32 / 4) {
+                let segment_name = format!("segment_{name}_{segment:05}.{extension}");
+                tokio::fs::write(dir.join(&segment_name), b"simulated").await?;
+                playlist.push_str(&format!("#EXTINF:4.0,\n{segment_name}\n"));
+            }
+            playlist.push_str("#EXT-X-ENDLIST\n");
+            tokio::fs::write(dir.join(format!("stream_{name}.m3u8")), playlist).await?;
+        }
+        tokio::fs::write(dir.join("index.m3u8"), master).await
+    } else if file_name.ends_with(".mpd") {
+        let manifest = format!(
+            "<?xml version=\"1.0\"?>\n<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" type=\"static\" mediaPresentationDuration=\"PT{SIMULATED_MEDIA_SECONDS}S\" minBufferTime=\"PT4S\">\n</MPD>\n"
+        );
+        tokio::fs::write(output, manifest).await
+    } else {
+        tokio::fs::write(output, b"simulated transcode output").await
+    }
+}
+
+fn variant_names(args: &[String]) -> Vec<String> {
+    let map = args
+        .iter()
+        .position(|arg| arg == "-var_stream_map")
+        .and_then(|idx| args.get(idx + 1));
+    let names: Vec<String> = map
+        .map(|value| {
+            value
+                .split_whitespace()
+                .filter_map(|entry| {
+                    entry
+                        .split(',')
+                        .find_map(|part| part.strip_prefix("name:"))
+                        .map(str::to_string)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    if names.is_empty() {
+        vec!["0".to_string()]
+    } else {
+        names
+    }
+}
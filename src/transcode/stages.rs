@@ -0,0 +1,312 @@
+use std::{ffi::OsString, path::Path, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    config,
+    error::AppError,
+    jobs::{DynJobStore, JobStage},
+    metadata,
+    playlist::select_variant,
+    process::DynProcessRunner,
+    storage::Storage,
+};
+
+use super::{
+    ffmpeg::{FfmpegProgressConfig, run_ffmpeg, run_ffmpeg_with_progress},
+    probe::{probe_duration, probe_has_audio, probe_has_subtitles},
+    storyboard::StoryboardLayout,
+    util::{finalize_encoded_file, map_io_error, os, os_path},
+};
+
+const FFMPEG_BIN: &str = "ffmpeg";
+
+/// Extra work a profile can request after the ladder has been packaged. Each
+/// stage runs as a child job of the upload, so `/jobs/{id}/group` reports it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OptionalStage {
+    /// Poster frame written to `thumbnail.jpg`.
+    Thumbnails,
+    /// Tiled preview frames written to `sprites.jpg`, indexed by the
+    /// `thumbnails.vtt` storyboard.
+    Sprites,
+    /// First embedded subtitle track converted to `captions.vtt`.
+    Captions,
+    /// Progressive H.264/AAC `fallback.mp4`.
+    Mp4Fallback,
+    /// VMAF of the top HLS rendition against the download, stored in `meta.json`.
+    Vmaf,
+}
+
+impl OptionalStage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OptionalStage::Thumbnails => "thumbnails",
+            OptionalStage::Sprites => "sprites",
+            OptionalStage::Captions => "captions",
+            OptionalStage::Mp4Fallback => "mp4_fallback",
+            OptionalStage::Vmaf => "vmaf",
+        }
+    }
+}
+
+impl FromStr for OptionalStage {
+    type Err = AppError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "thumbnails" | "thumbnail" => Ok(OptionalStage::Thumbnails),
+            "sprites" | "sprite" => Ok(OptionalStage::Sprites),
+            "captions" | "subtitles" => Ok(OptionalStage::Captions),
+            "mp4_fallback" | "mp4" => Ok(OptionalStage::Mp4Fallback),
+            "vmaf" => Ok(OptionalStage::Vmaf),
+            other => Err(AppError::validation(format!(
+                "unknown pipeline stage: {other}"
+            ))),
+        }
+    }
+}
+
+/// Parses a comma-separated stage list, skipping unknown entries with a warning.
+pub(crate) fn parse_stage_list(value: &str) -> Vec<OptionalStage> {
+    let mut stages = Vec::new();
+    for entry in value.split(',').filter(|entry| !entry.trim().is_empty()) {
+        match entry.parse::<OptionalStage>() {
+            Ok(stage) if !stages.contains(&stage) => stages.push(stage),
+            Ok(_) => {}
+            Err(err) => tracing::warn!(error = %err, "ignoring optional stage"),
+        }
+    }
+    stages
+}
+
+/// Runs the optional stages registered as children of `id`, in registration
+/// order. A failing stage fails only its child job; the video stays playable.
+pub async fn run_optional_stages(
+    storage: &Storage,
+    jobs: &DynJobStore,
+    runner: &DynProcessRunner,
+    id: &Uuid,
+) -> Result<(), AppError> {
+    let Some(group) = jobs.group_status(id).await? else {
+        return Ok(());
+    };
+
+    for member in group.members {
+        let Ok(stage) = member.name.parse::<OptionalStage>() else {
+            continue;
+        };
+        let child = member.status.id;
+        jobs.update_stage(child, JobStage::Transcoding).await?;
+        match run_stage(storage, jobs, runner, id, &child, stage).await {
+            Ok(()) => jobs.complete(child).await?,
+            Err(err) => {
+                tracing::warn!(video_id = %id, stage = stage.name(), error = %err, "optional stage failed");
+                jobs.fail(child, &err).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_stage(
+    storage: &Storage,
+    jobs: &DynJobStore,
+    runner: &DynProcessRunner,
+    id: &Uuid,
+    child: &Uuid,
+    stage: OptionalStage,
+) -> Result<(), AppError> {
+    let source = storage.download_path(id);
+    if !source.exists() {
+        return Err(AppError::not_found(format!(
+            "source video missing for {}: {}",
+            stage.name(),
+            source.display()
+        )));
+    }
+    let duration = probe_duration(runner, &source).await.unwrap_or(None);
+
+    match stage {
+        OptionalStage::Thumbnails => {
+            let meta = metadata::load(storage, id).await?;
+            let imported = meta.imported.is_some_and(|details| details.poster);
+            if (imported || meta.poster.is_some()) && storage.thumbnail_path(id).exists() {
+                tracing::debug!(video_id = %id, "keeping imported or picked poster");
+                return Ok(());
+            }
+            let offset = duration
+                .map(|total| total.as_secs_f64() * 0.1)
+                .unwrap_or(0.0);
+            let args = vec![
+                os("-y"),
+                os("-ss"),
+                os(format!("{offset:.3}")),
+                os("-i"),
+                os_path(&source),
+                os("-frames:v"),
+                os("1"),
+                os("-vf"),
+                os("scale=-2:720"),
+            ];
+            render_to(runner, args, &storage.thumbnail_path(id)).await
+        }
+        OptionalStage::Sprites => {
+            let layout = StoryboardLayout::from_env(duration);
+            let args = vec![
+                os("-y"),
+                os("-i"),
+                os_path(&source),
+                os("-vf"),
+                os(layout.filter()),
+                os("-frames:v"),
+                os("1"),
+            ];
+            render_to(runner, args, &storage.sprite_path(id)).await?;
+
+            let target = storage.storyboard_path(id);
+            let temp = target.with_extension("vtt.tmp");
+            fs::write(&temp, layout.vtt(duration)).await?;
+            fs::rename(&temp, &target).await?;
+            Ok(())
+        }
+        OptionalStage::Captions => {
+            if !probe_has_subtitles(runner, &source).await? {
+                tracing::debug!(video_id = %id, "no subtitle track; skipping captions");
+                return Ok(());
+            }
+            let args = vec![
+                os("-y"),
+                os("-i"),
+                os_path(&source),
+                os("-map"),
+                os("0:s:0"),
+                os("-c:s"),
+                os("webvtt"),
+            ];
+            render_to(runner, args, &storage.captions_path(id)).await
+        }
+        OptionalStage::Mp4Fallback => {
+            let has_audio = probe_has_audio(runner, &source).await.unwrap_or(false);
+            let target = storage.fallback_path(id);
+            let temp = storage
+                .tmp_dir()
+                .join(format!("{}.fallback.mp4", id.simple()));
+            let mut args = vec![
+                os("-y"),
+                os("-i"),
+                os_path(&source),
+                os("-c:v"),
+                os("libx264"),
+                os("-preset"),
+                os("veryfast"),
+                os("-crf"),
+                os("23"),
+                os("-pix_fmt"),
+                os("yuv420p"),
+                os("-movflags"),
+                os("+faststart"),
+            ];
+            if has_audio {
+                args.extend([os("-c:a"), os("aac"), os("-b:a"), os("128k")]);
+            } else {
+                args.push(os("-an"));
+            }
+            args.push(os_path(&temp));
+
+            let result = match duration {
+                Some(total) => {
+                    run_ffmpeg_with_progress(
+                        runner,
+                        args,
+                        FfmpegProgressConfig {
+                            total_duration: total,
+                            jobs: jobs.clone(),
+                            job_id: *child,
+                            operation: "mp4_fallback",
+                        },
+                    )
+                    .await
+                }
+                None => run_ffmpeg(runner, args).await,
+            };
+            if let Err(err) = result {
+                fs::remove_file(&temp).await.ok();
+                return Err(err);
+            }
+            finalize_encoded_file(&temp, &target).await
+        }
+        OptionalStage::Vmaf => {
+            let score = measure_vmaf(storage, runner, id, &source).await?;
+            let mut meta = metadata::load(storage, id).await?;
+            meta.vmaf_score = Some(score);
+            metadata::save(storage, id, &meta).await?;
+
+            if let Some(minimum) = config::parse_var::<f64>("VIDEO_VMAF_MIN_SCORE")
+                && score < minimum
+            {
+                return Err(AppError::transcode(format!(
+                    "VMAF {score:.2} is below the required {minimum:.2}"
+                )));
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Renders a single artifact next to the download, replacing any previous copy.
+async fn render_to(
+    runner: &DynProcessRunner,
+    mut args: Vec<OsString>,
+    target: &Path,
+) -> Result<(), AppError> {
+    args.push(os_path(target));
+    run_ffmpeg(runner, args).await
+}
+
+async fn measure_vmaf(
+    storage: &Storage,
+    runner: &DynProcessRunner,
+    id: &Uuid,
+    reference: &Path,
+) -> Result<f64, AppError> {
+    let hls_dir = storage.hls_dir(id);
+    let master = fs::read_to_string(hls_dir.join("index.m3u8"))
+        .await
+        .map_err(|_| AppError::not_found(format!("HLS ladder for {id}")))?;
+    let variant = select_variant(&master, None)
+        .ok_or_else(|| AppError::transcode("HLS master playlist has no variants"))?;
+
+    let args = vec![
+        os("-i"),
+        os_path(&hls_dir.join(variant)),
+        os("-i"),
+        os_path(reference),
+        os("-lavfi"),
+        os("[0:v][1:v]scale2ref=flags=bicubic[dist][ref];[dist][ref]libvmaf"),
+        os("-f"),
+        os("null"),
+        os("-"),
+    ];
+    let output = runner
+        .output(FFMPEG_BIN, &args)
+        .await
+        .map_err(map_io_error)?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        return Err(AppError::transcode(format!(
+            "ffmpeg VMAF run exited with status {}",
+            output.status
+        )));
+    }
+
+    stderr
+        .lines()
+        .find_map(|line| line.split_once("VMAF score:"))
+        .and_then(|(_, score)| score.trim().parse::<f64>().ok())
+        .ok_or_else(|| AppError::transcode("ffmpeg did not report a VMAF score"))
+}
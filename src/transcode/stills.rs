@@ -0,0 +1,279 @@
+use std::{
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tokio::{fs, io::AsyncReadExt};
+use uuid::Uuid;
+
+use crate::{
+    blocking, config, error::AppError, jobs::DynJobStore, process::DynProcessRunner,
+    storage::Storage,
+};
+
+use super::{
+    config::EncodeParams,
+    ffmpeg::{FfmpegProgressConfig, run_ffmpeg_with_progress},
+    util::{finalize_encoded_file, os, os_path},
+};
+
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp", "bmp", "tif", "tiff"];
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "m4a", "aac", "wav", "ogg", "opus", "flac"];
+const DEFAULT_MAX_IMAGES: usize = 500;
+const MAX_EXTRACTED_BYTES: u64 = 1024 * 1024 * 1024;
+const DEFAULT_RESOLUTION: (u32, u32) = (1920, 1080);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StillSource {
+    Image,
+    Archive,
+}
+
+/// Replaces a still image or zip of images at `input` with a rendered video,
+/// in place, so the rest of the pipeline treats it like any other upload.
+/// Each image is shown for `slideshow.image_seconds`; the first audio file in
+/// a zip becomes the soundtrack. Returns false when `input` is not a still.
+pub async fn render_stills(
+    storage: &Storage,
+    jobs: &DynJobStore,
+    runner: &DynProcessRunner,
+    id: &Uuid,
+    input: &Path,
+    encode: Option<&EncodeParams>,
+) -> Result<bool, AppError> {
+    let Some(kind) = detect_still_source(input).await? else {
+        return Ok(false);
+    };
+    let params = encode.copied().unwrap_or_default().sanitized().slideshow;
+
+    let work_dir = storage.tmp_dir().join(format!("{}.stills", id.simple()));
+    fs::create_dir_all(&work_dir).await?;
+    let result = async {
+        let (images, audio) = match kind {
+            StillSource::Image => (vec![input.to_path_buf()], None),
+            StillSource::Archive => {
+                let archive = input.to_path_buf();
+                let dir = work_dir.clone();
+                blocking::run(move || extract_archive(&archive, &dir))
+                    .await
+                    .map_err(|err| AppError::transcode(format!("archive extraction panicked: {err}")))??
+            }
+        };
+        tracing::debug!(video_id = %id, images = images.len(), soundtrack = audio.is_some(), "rendering still images");
+
+        let list = work_dir.join("images.ffconcat");
+        fs::write(&list, concat_list(&images, params.image_seconds)).await?;
+
+        let output = work_dir.join("slideshow.mkv");
+        let (width, height) = slideshow_resolution();
+        let mut args = vec![
+            os("-y"),
+            os("-f"),
+            os("concat"),
+            os("-safe"),
+            os("0"),
+            os("-i"),
+            os_path(&list),
+        ];
+        if let Some(audio) = &audio {
+            args.extend([os("-i"), os_path(audio)]);
+        }
+        args.extend([
+            os("-vf"),
+            os(format!(
+                "scale={width}:{height}:force_original_aspect_ratio=decrease,\
+                 pad={width}:{height}:(ow-iw)/2:(oh-ih)/2,setsar=1,fps={},format=yuv420p",
+                params.fps
+            )),
+            os("-c:v"),
+            os("libx264"),
+            os("-preset"),
+            os("veryfast"),
+            os("-crf"),
+            os("18"),
+        ]);
+        let total = Duration::from_secs_f64(f64::from(params.image_seconds) * images.len() as f64);
+        if audio.is_some() {
+            args.extend([
+                os("-af"),
+                os("apad"),
+                os("-c:a"),
+                os("aac"),
+                os("-b:a"),
+                os("192k"),
+            ]);
+        }
+        args.extend([os("-t"), os(format!("{:.3}", total.as_secs_f64()))]);
+        args.push(os_path(&output));
+
+        run_ffmpeg_with_progress(
+            runner,
+            args,
+            FfmpegProgressConfig {
+                total_duration: total,
+                jobs: jobs.clone(),
+                job_id: *id,
+                operation: "render_stills",
+            },
+        )
+        .await?;
+        finalize_encoded_file(&output, input).await
+    }
+    .await;
+
+    if let Err(err) = fs::remove_dir_all(&work_dir).await {
+        tracing::warn!(path = %work_dir.display(), ?err, "failed to remove still-image workspace");
+    }
+    result.map(|()| true)
+}
+
+async fn detect_still_source(input: &Path) -> Result<Option<StillSource>, AppError> {
+    let mut header = [0u8; 12];
+    let mut file = fs::File::open(input).await?;
+    let mut read = 0;
+    while read < header.len() {
+        let count = file.read(&mut header[read..]).await?;
+        if count == 0 {
+            break;
+        }
+        read += count;
+    }
+    let header = &header[..read];
+
+    let image = header.starts_with(&[0xFF, 0xD8, 0xFF])
+        || header.starts_with(b"\x89PNG\r\n\x1a\n")
+        || (header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WEBP"))
+        || (header.starts_with(b"BM") && header.get(6..10) == Some(&[0u8; 4][..]))
+        || header.starts_with(b"II*\0")
+        || header.starts_with(b"MM\0*");
+    if image {
+        Ok(Some(StillSource::Image))
+    } else if header.starts_with(b"PK\x03\x04") {
+        Ok(Some(StillSource::Archive))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Extracts images (sorted by path) and the first audio file from a zip into
+/// `dir`, under generated names so entry paths never reach the filesystem.
+fn extract_archive(
+    archive: &Path,
+    dir: &Path,
+) -> Result<(Vec<PathBuf>, Option<PathBuf>), AppError> {
+    let file = std::fs::File::open(archive)?;
+    let mut zip = zip::ZipArchive::new(file).map_err(invalid_archive)?;
+
+    let mut images: Vec<(String, usize)> = Vec::new();
+    let mut audio: Option<(String, usize)> = None;
+    for index in 0..zip.len() {
+        let entry = zip.by_index(index).map_err(invalid_archive)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let Some(path) = entry.enclosed_name() else {
+            continue;
+        };
+        let hidden = path.components().any(|component| {
+            let part = component.as_os_str().to_string_lossy();
+            part.starts_with('.') || part == "__MACOSX"
+        });
+        if hidden {
+            continue;
+        }
+        let Some(extension) = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        else {
+            continue;
+        };
+        let name = path.to_string_lossy().into_owned();
+        if IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+            images.push((name, index));
+        } else if AUDIO_EXTENSIONS.contains(&extension.as_str())
+            && audio.as_ref().is_none_or(|(current, _)| name < *current)
+        {
+            audio = Some((name, index));
+        }
+    }
+
+    if images.is_empty() {
+        return Err(AppError::validation("zip archive contains no images"));
+    }
+    let max_images =
+        config::parse_var::<usize>("VIDEO_SLIDESHOW_MAX_IMAGES").unwrap_or(DEFAULT_MAX_IMAGES);
+    if images.len() > max_images {
+        return Err(AppError::validation(format!(
+            "zip archive contains {} images; the limit is {max_images}",
+            images.len()
+        )));
+    }
+    images.sort();
+
+    let mut budget = MAX_EXTRACTED_BYTES;
+    let mut extract =
+        |index: usize, name: &str, target_stem: String| -> Result<PathBuf, AppError> {
+            let extension = Path::new(name)
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+                .unwrap_or_default();
+            let target = dir.join(format!("{target_stem}.{extension}"));
+            let entry = zip.by_index(index).map_err(invalid_archive)?;
+            let mut writer = std::fs::File::create(&target)?;
+            let written = io::copy(&mut entry.take(budget + 1), &mut writer)?;
+            if written > budget {
+                return Err(AppError::validation(
+                    "zip archive expands beyond the extraction limit",
+                ));
+            }
+            budget -= written;
+            Ok(target)
+        };
+
+    let mut extracted = Vec::with_capacity(images.len());
+    for (position, (name, index)) in images.iter().enumerate() {
+        extracted.push(extract(*index, name, format!("{position:05}"))?);
+    }
+    let soundtrack = audio
+        .map(|(name, index)| extract(index, &name, "soundtrack".to_string()))
+        .transpose()?;
+
+    Ok((extracted, soundtrack))
+}
+
+/// Builds an ffconcat script. The last image is listed twice because the
+/// concat demuxer ignores the duration of the final entry.
+fn concat_list(images: &[PathBuf], seconds: f32) -> String {
+    let mut list = String::from("ffconcat version 1.0\n");
+    for image in images {
+        list.push_str(&format!(
+            "file '{}'\nduration {seconds:.3}\n",
+            escape_concat_path(image)
+        ));
+    }
+    if let Some(last) = images.last() {
+        list.push_str(&format!("file '{}'\n", escape_concat_path(last)));
+    }
+    list
+}
+
+fn escape_concat_path(path: &Path) -> String {
+    path.to_string_lossy().replace('\'', "'\\''")
+}
+
+fn slideshow_resolution() -> (u32, u32) {
+    config::var("VIDEO_SLIDESHOW_RESOLUTION")
+        .and_then(|value| {
+            let (width, height) = value.trim().split_once('x')?;
+            let width = width.parse::<u32>().ok()?;
+            let height = height.parse::<u32>().ok()?;
+            // Keep both dimensions even so yuv420p encoders accept them.
+            (width >= 2 && height >= 2).then_some((width & !1, height & !1))
+        })
+        .unwrap_or(DEFAULT_RESOLUTION)
+}
+
+fn invalid_archive(err: zip::result::ZipError) -> AppError {
+    AppError::validation(format!("invalid zip archive: {err}"))
+}
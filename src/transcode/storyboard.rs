@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+use crate::config;
+
+/// File name of the sprite sheet as referenced from the storyboard VTT.
+pub const SPRITE_FILE: &str = "sprites.jpg";
+/// File name of the storyboard cues, served next to the sprite sheet.
+pub const STORYBOARD_FILE: &str = "thumbnails.vtt";
+
+const MAX_TILES: u32 = 100;
+const DEFAULT_COLUMNS: u32 = 10;
+const DEFAULT_INTERVAL_SECS: f64 = 10.0;
+const DEFAULT_TILE_SIZE: (u32, u32) = (160, 90);
+
+/// How frames are sampled and laid out on the sprite sheet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StoryboardLayout {
+    /// Seconds between sampled frames.
+    pub interval: f64,
+    pub tiles: u32,
+    pub columns: u32,
+    pub tile_width: u32,
+    pub tile_height: u32,
+}
+
+impl StoryboardLayout {
+    /// Reads `VIDEO_SPRITE_INTERVAL_SECS`, `VIDEO_SPRITE_TILE_SIZE` and
+    /// `VIDEO_SPRITE_COLUMNS`. The interval grows for long videos so a sheet
+    /// never holds more than 100 tiles.
+    pub fn from_env(duration: Option<Duration>) -> Self {
+        let interval = config::parse_var::<f64>("VIDEO_SPRITE_INTERVAL_SECS")
+            .filter(|value| *value > 0.0)
+            .unwrap_or(DEFAULT_INTERVAL_SECS);
+        let (tile_width, tile_height) = config::var("VIDEO_SPRITE_TILE_SIZE")
+            .and_then(|value| parse_tile_size(&value))
+            .unwrap_or(DEFAULT_TILE_SIZE);
+        let columns = config::parse_var::<u32>("VIDEO_SPRITE_COLUMNS")
+            .filter(|columns| *columns > 0)
+            .unwrap_or(DEFAULT_COLUMNS);
+        Self::new(duration, interval, columns, (tile_width, tile_height))
+    }
+
+    pub fn new(
+        duration: Option<Duration>,
+        interval: f64,
+        columns: u32,
+        (tile_width, tile_height): (u32, u32),
+    ) -> Self {
+        let (interval, tiles) = match duration.map(|total| total.as_secs_f64()) {
+            Some(total) => {
+                let interval = interval.max(total / MAX_TILES as f64);
+                let tiles = ((total / interval).ceil() as u32).clamp(1, MAX_TILES);
+                (interval, tiles)
+            }
+            None => (interval, MAX_TILES),
+        };
+        Self {
+            interval,
+            tiles,
+            columns: columns.min(tiles),
+            tile_width,
+            tile_height,
+        }
+    }
+
+    pub fn rows(&self) -> u32 {
+        self.tiles.div_ceil(self.columns).max(1)
+    }
+
+    /// ffmpeg filter that samples the frames, letterboxes each into a tile
+    /// and packs them into one image.
+    pub fn filter(&self) -> String {
+        let (width, height) = (self.tile_width, self.tile_height);
+        format!(
+            "fps=1/{:.3},scale={width}:{height}:force_original_aspect_ratio=decrease,\
+             pad={width}:{height}:(ow-iw)/2:(oh-ih)/2,setsar=1,tile={}x{}",
+            self.interval,
+            self.columns,
+            self.rows()
+        )
+    }
+
+    /// WebVTT cues pointing each interval at its tile of `sprites.jpg` with a
+    /// `#xywh=` media fragment. The last cue ends at `duration` when known.
+    pub fn vtt(&self, duration: Option<Duration>) -> String {
+        let end_of_video = duration.map(|total| total.as_secs_f64());
+        let mut vtt = String::from("WEBVTT\n");
+        for index in 0..self.tiles {
+            let start = f64::from(index) * self.interval;
+            let mut end = start + self.interval;
+            if let Some(total) = end_of_video {
+                if start >= total {
+                    break;
+                }
+                end = end.min(total);
+            }
+            let x = (index % self.columns) * self.tile_width;
+            let y = (index / self.columns) * self.tile_height;
+            vtt.push_str(&format!(
+                "\n{} --> {}\n{SPRITE_FILE}#xywh={x},{y},{},{}\n",
+                cue_time(start),
+                cue_time(end),
+                self.tile_width,
+                self.tile_height
+            ));
+        }
+        vtt
+    }
+}
+
+/// Appends `query` to the image URI of every cue, ahead of its fragment.
+pub fn append_query_to_storyboard(vtt: &str, query: &str) -> String {
+    let mut output = String::with_capacity(vtt.len() * 2);
+    for line in vtt.lines() {
+        match line.split_once("#xywh=") {
+            Some((uri, fragment)) if !line.contains("-->") => {
+                let separator = if uri.contains('?') { '&' } else { '?' };
+                output.push_str(&format!("{uri}{separator}{query}#xywh={fragment}"));
+            }
+            _ => output.push_str(line),
+        }
+        output.push('\n');
+    }
+    output
+}
+
+fn parse_tile_size(value: &str) -> Option<(u32, u32)> {
+    let (width, height) = value.trim().split_once(['x', 'X'])?;
+    let width = width.trim().parse::<u32>().ok().filter(|px| *px >= 16)?;
+    let height = height.trim().parse::<u32>().ok().filter(|px| *px >= 16)?;
+    Some((width, height))
+}
+
+fn cue_time(seconds: f64) -> String {
+    let millis = (seconds * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
@@ -0,0 +1,187 @@
+use std::{ffi::OsString, fmt::Write};
+
+use crate::{
+    config,
+    transcode::{
+        config::{EncoderKind, OutputColor},
+        util::os,
+    },
+};
+
+use super::{LadderEncoding, Rendition};
+
+const AUDIO_BITRATE: &str = "192k";
+const AUDIO_CHANNELS: &str = "2";
+/// Mono AAC shared by the low rungs, small enough for 2G/3G links.
+const LOW_RUNG_AUDIO_BITRATE: &str = "48k";
+const LOW_RUNG_AUDIO_CHANNELS: &str = "1";
+
+/// AAC for the ladder. With low rungs, a second, mono low-rate copy of the
+/// audio is encoded for them.
+pub(super) fn apply_audio_args(args: &mut Vec<OsString>, has_audio: bool, low_rate_audio: bool) {
+    if !has_audio {
+        args.push(os("-an"));
+        return;
+    }
+    args.extend([os("-c:a"), os("aac")]);
+    if low_rate_audio {
+        args.extend([
+            os("-b:a:0"),
+            os(AUDIO_BITRATE),
+            os("-ac:a:0"),
+            os(AUDIO_CHANNELS),
+            os("-b:a:1"),
+            os(LOW_RUNG_AUDIO_BITRATE),
+            os("-ac:a:1"),
+            os(LOW_RUNG_AUDIO_CHANNELS),
+        ]);
+    } else {
+        args.extend([os("-b:a"), os(AUDIO_BITRATE), os("-ac"), os(AUDIO_CHANNELS)]);
+    }
+}
+
+/// Video codec options shared by every rung of a pass. `encoder` is the AV1
+/// encoder, or `None` for an H.264 ladder, which stays 8-bit.
+pub(super) fn apply_ladder_codec_args(
+    args: &mut Vec<OsString>,
+    encoder: Option<EncoderKind>,
+    encoding: &LadderEncoding,
+) {
+    let color = &encoding.color;
+    match encoder {
+        Some(EncoderKind::SoftwareAv1) => {
+            args.extend([
+                os("-c:v"),
+                os("libaom-av1"),
+                os("-pix_fmt"),
+                os(color.pix_fmt("yuv420p", "yuv420p10le")),
+                os("-row-mt"),
+                os("1"),
+                os("-cpu-used"),
+                os("6"),
+            ]);
+            args.extend(encoding.tuning.libaom_args());
+        }
+        Some(EncoderKind::NvencAv1) => args.extend([
+            os("-c:v"),
+            os("av1_nvenc"),
+            os("-preset"),
+            os("p5"),
+            os("-rc"),
+            os("vbr"),
+            os("-pix_fmt"),
+            os(color.pix_fmt("yuv420p", "p010le")),
+        ]),
+        Some(EncoderKind::QsvAv1) => args.extend([
+            os("-c:v"),
+            os("av1_qsv"),
+            os("-preset"),
+            os("medium"),
+            os("-pix_fmt"),
+            os(color.pix_fmt("nv12", "p010le")),
+        ]),
+        // Frames arrive in GPU memory through `hwupload`.
+        Some(EncoderKind::VaapiAv1) => {
+            args.extend([os("-c:v"), os("av1_vaapi"), os("-rc_mode"), os("VBR")])
+        }
+        Some(EncoderKind::VideoToolboxAv1) => args.extend([
+            os("-c:v"),
+            os("av1_videotoolbox"),
+            os("-pix_fmt"),
+            os(color.pix_fmt("yuv420p", "p010le")),
+        ]),
+        None => args.extend([
+            os("-c:v"),
+            os("libx264"),
+            os("-preset"),
+            os("veryfast"),
+            os("-profile:v"),
+            os("high"),
+            os("-pix_fmt"),
+            os("yuv420p"),
+        ]),
+    }
+    args.extend([
+        os("-g"),
+        os("120"),
+        os("-keyint_min"),
+        os("120"),
+        os("-sc_threshold"),
+        os("0"),
+    ]);
+    args.extend(color.tag_args());
+}
+
+/// Per-rung rate control, indexed by the rung's position in the pass.
+pub(super) fn apply_rendition_args(args: &mut Vec<OsString>, renditions: &[Rendition]) {
+    for (idx, rendition) in renditions.iter().enumerate() {
+        args.extend([
+            os(format!("-b:v:{idx}")),
+            os(format!("{}k", rendition.bitrate)),
+            os(format!("-maxrate:v:{idx}")),
+            os(format!("{}k", rendition.maxrate)),
+            os(format!("-bufsize:v:{idx}")),
+            os(format!("{}k", rendition.bufsize)),
+            os(format!("-metadata:s:v:{idx}")),
+            os(format!("variant={}", rendition.name)),
+        ]);
+    }
+}
+
+/// Global options opening the VAAPI device that scaled frames are uploaded to.
+pub(super) fn encoder_device_args(encoder: Option<EncoderKind>) -> Vec<OsString> {
+    if encoder != Some(EncoderKind::VaapiAv1) {
+        return Vec::new();
+    }
+    let device = config::var("VIDEO_VAAPI_DEVICE").unwrap_or_else(|| "/dev/dri/renderD128".into());
+    vec![
+        os("-init_hw_device"),
+        os(format!("vaapi=ladder:{device}")),
+        os("-filter_hw_device"),
+        os("ladder"),
+    ]
+}
+
+/// Scales the source once per rung. VAAPI rungs are uploaded to the GPU
+/// after scaling.
+pub(super) fn build_filter_complex(
+    renditions: &[Rendition],
+    encoder: Option<EncoderKind>,
+    color: &OutputColor,
+) -> String {
+    let upload = if encoder == Some(EncoderKind::VaapiAv1) {
+        format!(",format={},hwupload", color.pix_fmt("nv12", "p010"))
+    } else {
+        String::new()
+    };
+    let mut filter = String::new();
+    for (idx, rendition) in renditions.iter().enumerate() {
+        if idx > 0 {
+            filter.push(';');
+        }
+        let _ = write!(
+            &mut filter,
+            "[0:v]scale=-2:{}:flags=lanczos{upload}[v{}]",
+            rendition.height, idx
+        );
+    }
+    filter
+}
+
+/// An empty ladder with audio yields a single audio-only variant. Low rungs
+/// pair with the second, low-rate audio stream.
+pub(super) fn build_var_stream_map(renditions: &[Rendition], has_audio: bool) -> String {
+    if renditions.is_empty() && has_audio {
+        return "a:0,name:audio".to_string();
+    }
+    let mut entries = Vec::with_capacity(renditions.len());
+    for (idx, rendition) in renditions.iter().enumerate() {
+        if has_audio {
+            let audio = usize::from(rendition.low_bandwidth);
+            entries.push(format!("v:{idx},a:{audio},name:{}", rendition.name));
+        } else {
+            entries.push(format!("v:{idx},name:{}", rendition.name));
+        }
+    }
+    entries.join(" ")
+}
@@ -0,0 +1,190 @@
+use std::path::Path;
+
+use tokio::fs;
+
+use crate::{
+    config,
+    error::AppError,
+    process::DynProcessRunner,
+    storage::Storage,
+    transcode::{
+        config::EncoderKind,
+        mpd::{MpdMetadata, merge_pass_manifests, postprocess_mpd},
+        packaging::{PackagedRung, move_pass_outputs, packaging_passes, with_encoder_fallback},
+        probe::probe_frame_rate,
+        profile::LadderCodec,
+        util::{os, os_path},
+    },
+};
+
+use super::{
+    LadderEncoding, Rendition, SEGMENT_SECONDS,
+    args::{
+        apply_audio_args, apply_ladder_codec_args, apply_rendition_args, build_filter_complex,
+        encoder_device_args,
+    },
+    reset_dir, run_packaging,
+};
+
+pub(crate) async fn generate_dash_stream(
+    storage: &Storage,
+    runner: &DynProcessRunner,
+    id: &uuid::Uuid,
+    source: &Path,
+    has_audio: bool,
+    renditions: Vec<Rendition>,
+    encoding: &LadderEncoding,
+) -> Result<Vec<PackagedRung>, AppError> {
+    let dash_dir = storage.dash_dir(id);
+    let manifest = dash_dir.join("manifest.mpd");
+    let low_rate_audio = has_audio && renditions.iter().any(|rung| rung.low_bandwidth);
+    let passes = packaging_passes(&renditions, LadderCodec::Av1);
+    let mut packaged = vec![None; renditions.len()];
+
+    if passes.len() > 1 {
+        reset_dir(&dash_dir).await?;
+        let mut manifests = Vec::with_capacity(passes.len());
+        for (index, pass) in passes.iter().enumerate() {
+            let staging = dash_dir.join(format!("pass{index}"));
+            let rungs = pass.renditions(&renditions);
+            // The first pass carries the audio for the whole ladder.
+            let audio = (has_audio && index == 0, low_rate_audio && index == 0);
+            let prefix = format!("p{index}_");
+            let encoder = with_encoder_fallback(pass.encoder, |encoder| {
+                dash_pass(
+                    runner, source, &staging, audio, &rungs, encoding, encoder, &prefix,
+                )
+            })
+            .await?;
+            for (representation, &rung) in pass.indices.iter().enumerate() {
+                packaged[rung] = Some(PackagedRung {
+                    encoder,
+                    segment_prefix: prefix.clone(),
+                    representation,
+                });
+            }
+            let pass_manifest = fs::read_to_string(staging.join("manifest.mpd")).await?;
+            manifests.push((pass_manifest, pass.indices.clone()));
+            move_pass_outputs(&staging, &dash_dir, "manifest.mpd").await?;
+        }
+        fs::write(&manifest, merge_pass_manifests(&manifests, &renditions)).await?;
+    } else {
+        let encoder = passes
+            .first()
+            .map_or(EncoderKind::SoftwareAv1, |pass| pass.encoder);
+        let encoder = with_encoder_fallback(encoder, |encoder| {
+            dash_pass(
+                runner,
+                source,
+                &dash_dir,
+                (has_audio, low_rate_audio),
+                &renditions,
+                encoding,
+                encoder,
+                "",
+            )
+        })
+        .await?;
+        for (representation, rung) in packaged.iter_mut().enumerate() {
+            *rung = Some(PackagedRung {
+                encoder,
+                segment_prefix: String::new(),
+                representation,
+            });
+        }
+    }
+
+    let frame_rate = if renditions.is_empty() {
+        None
+    } else {
+        match probe_frame_rate(runner, source).await {
+            Ok(rate) => rate,
+            Err(err) => {
+                tracing::warn!(error = %err, "failed to probe frame rate for DASH manifest");
+                None
+            }
+        }
+    };
+    let metadata = MpdMetadata {
+        renditions: &renditions,
+        has_audio,
+        frame_rate,
+        pixel_format: encoding.color.pix_fmt("yuv420p", "yuv420p10le"),
+        segment_seconds: SEGMENT_SECONDS,
+        utc_timing_url: config::var("VIDEO_DASH_UTC_TIMING_URL").filter(|url| !url.is_empty()),
+    };
+    let generated = fs::read_to_string(&manifest).await?;
+    fs::write(&manifest, postprocess_mpd(&generated, &metadata)).await?;
+
+    Ok(packaged.into_iter().flatten().collect())
+}
+
+/// Packages `renditions` as DASH into `output_dir/manifest.mpd` with one
+/// ffmpeg run. `audio` says whether to add the audio and its low-rate copy;
+/// segment file names start with `prefix`.
+#[allow(clippy::too_many_arguments)]
+async fn dash_pass(
+    runner: &DynProcessRunner,
+    source: &Path,
+    output_dir: &Path,
+    audio: (bool, bool),
+    renditions: &[Rendition],
+    encoding: &LadderEncoding,
+    encoder: EncoderKind,
+    prefix: &str,
+) -> Result<(), AppError> {
+    let (has_audio, low_rate_audio) = audio;
+    let filter_complex = build_filter_complex(renditions, Some(encoder), &encoding.color);
+
+    let mut args = encoder_device_args(Some(encoder));
+    if !filter_complex.is_empty() {
+        args.extend([os("-filter_complex"), os(filter_complex)]);
+    }
+
+    for (index, _) in renditions.iter().enumerate() {
+        args.extend([os("-map"), os(format!("[v{index}]"))]);
+    }
+
+    if has_audio {
+        args.extend([os("-map"), os("0:a:0")]);
+    }
+    if low_rate_audio {
+        args.extend([os("-map"), os("0:a:0")]);
+    }
+
+    if !renditions.is_empty() {
+        apply_ladder_codec_args(&mut args, Some(encoder), encoding);
+    }
+    apply_rendition_args(&mut args, renditions);
+    apply_audio_args(&mut args, has_audio, low_rate_audio);
+
+    let adaptation_sets = match (renditions.is_empty(), has_audio) {
+        (true, _) => "id=0,streams=a",
+        (false, true) => "id=0,streams=v id=1,streams=a",
+        (false, false) => "id=0,streams=v",
+    };
+
+    args.extend([
+        os("-f"),
+        os("dash"),
+        os("-seg_duration"),
+        os(SEGMENT_SECONDS.to_string()),
+        os("-use_template"),
+        os("1"),
+        os("-use_timeline"),
+        os("1"),
+        os("-streaming"),
+        os("1"),
+        os("-remove_at_exit"),
+        os("0"),
+        os("-adaptation_sets"),
+        os(adaptation_sets),
+        os("-init_seg_name"),
+        os(format!("{prefix}init_$RepresentationID$.m4s")),
+        os("-media_seg_name"),
+        os(format!("{prefix}chunk_$RepresentationID$_$Number$.m4s")),
+        os_path(&output_dir.join("manifest.mpd")),
+    ]);
+
+    run_packaging(runner, source, output_dir, !renditions.is_empty(), &args).await
+}
@@ -0,0 +1,160 @@
+use std::path::Path;
+
+use tokio::fs;
+
+use crate::{
+    error::AppError,
+    process::DynProcessRunner,
+    storage::Storage,
+    transcode::{
+        config::EncoderKind,
+        packaging::{
+            merge_master_playlists, move_pass_outputs, packaging_passes, with_encoder_fallback,
+        },
+        profile::{HlsSegmentFormat, LadderCodec},
+        util::{os, os_path},
+    },
+};
+
+use super::{
+    LadderEncoding, Rendition, SEGMENT_SECONDS,
+    args::{
+        apply_audio_args, apply_ladder_codec_args, apply_rendition_args, build_filter_complex,
+        build_var_stream_map, encoder_device_args,
+    },
+    reset_dir, run_packaging,
+};
+
+pub(crate) async fn generate_hls_stream(
+    storage: &Storage,
+    runner: &DynProcessRunner,
+    id: &uuid::Uuid,
+    source: &Path,
+    has_audio: bool,
+    renditions: Vec<Rendition>,
+    encoding: &LadderEncoding,
+) -> Result<(), AppError> {
+    let hls_dir = storage.hls_dir(id);
+    let packaging = encoding.profile.hls_packaging();
+    let passes = packaging_passes(&renditions, packaging.codec);
+
+    if passes.len() > 1 {
+        reset_dir(&hls_dir).await?;
+        let mut masters = Vec::with_capacity(passes.len());
+        for (index, pass) in passes.iter().enumerate() {
+            let staging = hls_dir.join(format!("pass{index}"));
+            let rungs = pass.renditions(&renditions);
+            with_encoder_fallback(pass.encoder, |encoder| {
+                hls_pass(
+                    runner, source, &staging, has_audio, &rungs, encoding, encoder,
+                )
+            })
+            .await?;
+            let master = fs::read_to_string(staging.join("index.m3u8")).await?;
+            masters.push((master, pass.indices.clone()));
+            move_pass_outputs(&staging, &hls_dir, "index.m3u8").await?;
+        }
+        fs::write(hls_dir.join("index.m3u8"), merge_master_playlists(&masters)).await?;
+    } else {
+        let encoder = passes
+            .first()
+            .map_or(EncoderKind::SoftwareAv1, |pass| pass.encoder);
+        with_encoder_fallback(encoder, |encoder| {
+            hls_pass(
+                runner,
+                source,
+                &hls_dir,
+                has_audio,
+                &renditions,
+                encoding,
+                encoder,
+            )
+        })
+        .await?;
+    }
+
+    let index_playlist = hls_dir.join("index.m3u8");
+    if !index_playlist.exists() {
+        return Err(AppError::transcode(
+            "ffmpeg did not produce an HLS master playlist",
+        ));
+    }
+
+    let master_playlist = hls_dir.join("master.m3u8");
+    fs::copy(&index_playlist, &master_playlist).await?;
+
+    Ok(())
+}
+
+/// Packages `renditions` as HLS variants into `output_dir` with one ffmpeg
+/// run, each variant carrying its own copy of the audio.
+async fn hls_pass(
+    runner: &DynProcessRunner,
+    source: &Path,
+    output_dir: &Path,
+    has_audio: bool,
+    renditions: &[Rendition],
+    encoding: &LadderEncoding,
+    encoder: EncoderKind,
+) -> Result<(), AppError> {
+    let packaging = encoding.profile.hls_packaging();
+    let encoder = (packaging.codec == LadderCodec::Av1).then_some(encoder);
+    let filter_complex = build_filter_complex(renditions, encoder, &encoding.color);
+    let var_stream_map = build_var_stream_map(renditions, has_audio);
+
+    let mut args = encoder_device_args(encoder);
+    if !filter_complex.is_empty() {
+        args.extend([os("-filter_complex"), os(filter_complex)]);
+    }
+
+    for (index, _) in renditions.iter().enumerate() {
+        args.extend([os("-map"), os(format!("[v{index}]"))]);
+    }
+
+    let low_rate_audio = has_audio && renditions.iter().any(|rung| rung.low_bandwidth);
+    if has_audio {
+        args.extend([os("-map"), os("0:a:0")]);
+    }
+    if low_rate_audio {
+        args.extend([os("-map"), os("0:a:0")]);
+    }
+
+    if !renditions.is_empty() {
+        apply_ladder_codec_args(&mut args, encoder, encoding);
+    }
+    apply_rendition_args(&mut args, renditions);
+    apply_audio_args(&mut args, has_audio, low_rate_audio);
+
+    let segment_pattern = output_dir.join(format!(
+        "segment_%v_%05d.{}",
+        packaging.segments.extension()
+    ));
+    let variant_index = output_dir.join("stream_%v.m3u8");
+
+    args.extend([
+        os("-f"),
+        os("hls"),
+        os("-hls_time"),
+        os(SEGMENT_SECONDS.to_string()),
+        os("-hls_playlist_type"),
+        os("event"),
+        os("-hls_flags"),
+        os("independent_segments+append_list+omit_endlist"),
+        os("-hls_segment_type"),
+        os(packaging.segments.ffmpeg_name()),
+    ]);
+    if packaging.segments == HlsSegmentFormat::Fmp4 {
+        args.extend([os("-hls_fmp4_init_filename"), os("init_%v.m4s")]);
+    }
+    args.extend([
+        os("-hls_segment_filename"),
+        os_path(&segment_pattern),
+        os("-master_pl_name"),
+        os("index.m3u8"),
+        os("-var_stream_map"),
+        os(var_stream_map),
+        os_path(&variant_index),
+    ]);
+
+    run_packaging(runner, source, output_dir, !renditions.is_empty(), &args).await
+}
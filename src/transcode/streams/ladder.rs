@@ -0,0 +1,202 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, HashSet},
+};
+
+use crate::{
+    config,
+    transcode::{
+        config::{EncoderKind, LadderLimits},
+        probe::VideoGeometry,
+        profile::TranscodeProfile,
+    },
+};
+
+use super::{
+    Rendition,
+    rungs::{add_low_rungs, apply_limits, base_height_candidates, estimate_bitrates, fit_rung},
+};
+
+pub(super) const DEFAULT_MAX_RENDITIONS: usize = 5;
+const DEFAULT_BASE_BITRATE_1080P_KBPS: f64 = 4_500.0;
+
+/// Ladder shape knobs, read at packaging time so config reloads apply to the next run.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct LadderConfig {
+    pub max_renditions: usize,
+    pub base_bitrate_1080p_kbps: f64,
+    pub limits: LadderLimits,
+    /// Add the low rungs below the regular ladder.
+    pub low_rungs: bool,
+    pub encoders: LadderEncoders,
+}
+
+impl LadderConfig {
+    pub(crate) fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            max_renditions: config::parse_var::<usize>("VIDEO_LADDER_MAX_RENDITIONS")
+                .filter(|&value| value > 0)
+                .unwrap_or(defaults.max_renditions),
+            base_bitrate_1080p_kbps: config::parse_var::<f64>("VIDEO_LADDER_BASE_BITRATE_KBPS")
+                .filter(|value| value.is_finite() && *value > 0.0)
+                .unwrap_or(defaults.base_bitrate_1080p_kbps),
+            limits: LadderLimits::default(),
+            low_rungs: false,
+            encoders: LadderEncoders::from_env(),
+        }
+    }
+
+    /// The environment's ladder shaped by a video's profile and limits.
+    pub(crate) fn for_video(profile: TranscodeProfile, limits: LadderLimits) -> Self {
+        Self {
+            low_rungs: limits.low_rungs.unwrap_or_else(|| profile.low_rungs()),
+            ..Self::from_env().with_limits(limits)
+        }
+    }
+
+    pub(crate) fn with_limits(self, limits: LadderLimits) -> Self {
+        Self { limits, ..self }
+    }
+}
+
+impl Default for LadderConfig {
+    fn default() -> Self {
+        Self {
+            max_renditions: DEFAULT_MAX_RENDITIONS,
+            base_bitrate_1080p_kbps: DEFAULT_BASE_BITRATE_1080P_KBPS,
+            limits: LadderLimits::default(),
+            low_rungs: false,
+            encoders: LadderEncoders::default(),
+        }
+    }
+}
+
+/// Which AV1 encoder each rung uses, from `VIDEO_LADDER_ENCODERS`, e.g.
+/// `2160=software,720=nvenc`. A rung takes the encoder of the largest size
+/// its shorter side reaches; smaller rungs take the `*` entry, or software.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct LadderEncoders {
+    /// Minimum size and encoder, largest first.
+    thresholds: Vec<(u32, EncoderKind)>,
+    fallback: Option<EncoderKind>,
+}
+
+impl LadderEncoders {
+    pub(crate) fn from_env() -> Self {
+        config::var("VIDEO_LADDER_ENCODERS")
+            .map(|spec| Self::parse(&spec))
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn parse(spec: &str) -> Self {
+        let mut encoders = Self::default();
+        for entry in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            // `None` stands for the `*` entry.
+            let parsed = entry.split_once('=').and_then(|(size, name)| {
+                let encoder = EncoderKind::from_name(name)?;
+                match size.trim() {
+                    "*" => Some((None, encoder)),
+                    size => Some((Some(size.trim_end_matches('p').parse().ok()?), encoder)),
+                }
+            });
+            match parsed {
+                Some((Some(size), encoder)) => encoders.thresholds.push((size, encoder)),
+                Some((None, encoder)) => encoders.fallback = Some(encoder),
+                None => tracing::warn!(entry, "ignoring invalid VIDEO_LADDER_ENCODERS entry"),
+            }
+        }
+        encoders.thresholds.sort_by_key(|(size, _)| Reverse(*size));
+        encoders
+    }
+
+    pub(crate) fn for_rung(&self, width: u32, height: u32) -> EncoderKind {
+        let size = width.min(height);
+        self.thresholds
+            .iter()
+            .find(|(min, _)| size >= *min)
+            .map(|(_, encoder)| *encoder)
+            .or(self.fallback)
+            .unwrap_or(EncoderKind::SoftwareAv1)
+    }
+}
+
+pub(crate) fn select_renditions(geometry: VideoGeometry, ladder: &LadderConfig) -> Vec<Rendition> {
+    let mut height_candidates = BTreeSet::new();
+    if geometry.height > 0 {
+        height_candidates.insert(geometry.height);
+    }
+    for value in base_height_candidates(geometry) {
+        if *value > 0 {
+            height_candidates.insert(*value);
+        }
+    }
+
+    let mut renditions = Vec::new();
+    let mut seen = HashSet::new();
+
+    let mut sorted_candidates: Vec<u32> = height_candidates.into_iter().collect();
+    sorted_candidates.sort_unstable();
+    sorted_candidates.reverse();
+
+    for raw_height in sorted_candidates {
+        let Some((width, height)) = fit_rung(geometry, raw_height) else {
+            continue;
+        };
+        if !seen.insert((width, height)) {
+            continue;
+        }
+
+        let (bitrate, maxrate, bufsize) = estimate_bitrates(width, height, ladder);
+        renditions.push(Rendition {
+            name: format!("{}p", height),
+            width,
+            height,
+            bitrate,
+            maxrate,
+            bufsize,
+            low_bandwidth: false,
+            encoder: ladder.encoders.for_rung(width, height),
+        });
+    }
+    apply_limits(&mut renditions, ladder);
+    renditions.truncate(ladder.max_renditions);
+    if ladder.low_rungs {
+        add_low_rungs(&mut renditions, geometry, ladder);
+    }
+
+    if renditions.is_empty() {
+        let mut width = if geometry.width.is_multiple_of(2) {
+            geometry.width
+        } else {
+            geometry.width.saturating_sub(1)
+        };
+        let mut height = if geometry.height.is_multiple_of(2) {
+            geometry.height
+        } else {
+            geometry.height.saturating_sub(1)
+        };
+
+        width = width.max(2);
+        height = height.max(2);
+
+        let (bitrate, maxrate, bufsize) = estimate_bitrates(width, height, ladder);
+        renditions.push(Rendition {
+            name: format!("{}p", height),
+            width,
+            height,
+            bitrate,
+            maxrate,
+            bufsize,
+            low_bandwidth: false,
+            encoder: ladder.encoders.for_rung(width, height),
+        });
+    }
+
+    renditions.sort_by_key(|rung| std::cmp::Reverse(rung.height));
+    renditions
+}
@@ -0,0 +1,93 @@
+mod args;
+mod dash;
+mod hls;
+mod ladder;
+mod rungs;
+#[cfg(test)]
+mod tests;
+
+use std::{ffi::OsString, path::Path};
+
+use tokio::fs;
+
+use crate::{error::AppError, process::DynProcessRunner, storage::ensure_dir};
+
+use super::{
+    config::{EncoderKind, OutputColor},
+    decode::{DecoderKind, with_decoder_fallback},
+    ffmpeg::run_ffmpeg,
+    profile::{Av1Tuning, TranscodeProfile},
+    util::{os, os_path},
+};
+
+pub(crate) use dash::generate_dash_stream;
+pub(crate) use hls::generate_hls_stream;
+pub(crate) use ladder::{LadderConfig, select_renditions};
+
+const SEGMENT_SECONDS: u32 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Rendition {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub bitrate: u32,
+    pub maxrate: u32,
+    pub bufsize: u32,
+    /// One of the extra 240p/144p rungs, paired with the low-rate audio.
+    pub low_bandwidth: bool,
+    /// Encoder of the rung in AV1 ladders; H.264 ladders ignore it.
+    pub encoder: EncoderKind,
+}
+
+/// How every rung is encoded, whichever encoder its pass uses.
+#[derive(Clone, Debug)]
+pub(crate) struct LadderEncoding {
+    pub profile: TranscodeProfile,
+    pub tuning: Av1Tuning,
+    pub color: OutputColor,
+}
+
+impl LadderEncoding {
+    pub(crate) fn new(profile: TranscodeProfile, color: OutputColor) -> Self {
+        Self {
+            profile,
+            tuning: profile.av1_tuning(),
+            color,
+        }
+    }
+}
+
+/// Runs one packaging pass over `source` into a fresh `output_dir`. Video
+/// ladders try hardware decoding first and fall back to software.
+async fn run_packaging(
+    runner: &DynProcessRunner,
+    source: &Path,
+    output_dir: &Path,
+    decodes_video: bool,
+    output_args: &[OsString],
+) -> Result<(), AppError> {
+    let attempt = |decoder: DecoderKind| async move {
+        reset_dir(output_dir).await?;
+
+        let mut args = vec![os("-y")];
+        args.extend(decoder.input_args());
+        args.extend([os("-i"), os_path(source)]);
+        args.extend(output_args.iter().cloned());
+        run_ffmpeg(runner, args).await
+    };
+    if decodes_video {
+        with_decoder_fallback(attempt).await
+    } else {
+        attempt(DecoderKind::Software).await
+    }
+}
+
+async fn reset_dir(dir: &Path) -> Result<(), AppError> {
+    match fs::remove_dir_all(dir).await {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+    ensure_dir(dir).await
+}
@@ -0,0 +1,158 @@
+use crate::transcode::probe::VideoGeometry;
+
+use super::{Rendition, ladder::LadderConfig};
+
+const MIN_BITRATE_KBPS: f64 = 320.0;
+const LOW_RUNG_MIN_BITRATE_KBPS: f64 = 80.0;
+const MAX_BITRATE_KBPS: f64 = 22_000.0;
+
+pub(super) fn base_height_candidates(geometry: VideoGeometry) -> &'static [u32] {
+    match classify_aspect(geometry) {
+        AspectClass::Ultrawide => &[
+            4320, 3200, 2560, 2160, 2000, 1600, 1440, 1080, 864, 720, 540, 432, 360,
+        ],
+        AspectClass::SixteenNine => &[
+            4320, 2880, 2160, 1800, 1440, 1200, 1080, 900, 720, 540, 480, 360, 240,
+        ],
+        AspectClass::FourThree => &[
+            2880, 2160, 1600, 1440, 1280, 1080, 960, 720, 540, 480, 360, 240,
+        ],
+        AspectClass::Tall => &[
+            2160, 1920, 1600, 1440, 1200, 1080, 900, 720, 540, 480, 360, 240,
+        ],
+    }
+}
+
+/// Extra rungs for slow mobile networks. Tall videos are sized by their
+/// short side, so a 240p rung is 240 pixels wide.
+fn low_height_candidates(geometry: VideoGeometry) -> &'static [u32] {
+    match classify_aspect(geometry) {
+        AspectClass::Ultrawide | AspectClass::SixteenNine | AspectClass::FourThree => &[240, 144],
+        AspectClass::Tall => &[426, 256],
+    }
+}
+
+fn classify_aspect(geometry: VideoGeometry) -> AspectClass {
+    if geometry.width == 0 || geometry.height == 0 {
+        return AspectClass::SixteenNine;
+    }
+
+    let ratio = geometry.width as f64 / geometry.height as f64;
+
+    if ratio >= 2.1 {
+        AspectClass::Ultrawide
+    } else if ratio >= 1.55 {
+        AspectClass::SixteenNine
+    } else if ratio >= 1.3 {
+        AspectClass::FourThree
+    } else {
+        AspectClass::Tall
+    }
+}
+
+enum AspectClass {
+    Ultrawide,
+    SixteenNine,
+    FourThree,
+    Tall,
+}
+
+/// Even frame size of a rung `raw_height` pixels tall in the source's aspect
+/// ratio, or `None` when the source is too small for it.
+pub(super) fn fit_rung(geometry: VideoGeometry, raw_height: u32) -> Option<(u32, u32)> {
+    if raw_height == 0 || raw_height > geometry.height {
+        return None;
+    }
+    let height = raw_height - raw_height % 2;
+    if height < 2 {
+        return None;
+    }
+    let aspect_ratio = geometry.width as f64 / geometry.height as f64;
+    let mut width = ((aspect_ratio * height as f64).round() as u32).min(geometry.width);
+    width -= width % 2;
+    (width >= 2).then_some((width, height))
+}
+
+/// Appends the low rungs shorter than every regular rung. They fall outside
+/// `max_renditions` but still honor the ladder limits.
+pub(super) fn add_low_rungs(
+    renditions: &mut Vec<Rendition>,
+    geometry: VideoGeometry,
+    ladder: &LadderConfig,
+) {
+    let limits = ladder.limits;
+    let shortest = renditions.iter().map(|rung| rung.height).min();
+    for &raw_height in low_height_candidates(geometry) {
+        let Some((width, height)) = fit_rung(geometry, raw_height) else {
+            continue;
+        };
+        if shortest.is_some_and(|shortest| height >= shortest)
+            || limits.max_height.is_some_and(|max| height > max)
+        {
+            continue;
+        }
+        let bitrate = scaled_bitrate(width, height, ladder)
+            .clamp(LOW_RUNG_MIN_BITRATE_KBPS, MIN_BITRATE_KBPS);
+        let (bitrate, maxrate, bufsize) = rate_control(bitrate);
+        if limits.max_bitrate_kbps.is_some_and(|max| bitrate > max) {
+            continue;
+        }
+        renditions.push(Rendition {
+            name: format!("{}p", height),
+            width,
+            height,
+            bitrate,
+            maxrate,
+            bufsize,
+            low_bandwidth: true,
+            encoder: ladder.encoders.for_rung(width, height),
+        });
+    }
+}
+
+/// Drops rungs above the requested height or bitrate, largest first. When
+/// none fit, the smallest rung is kept at the bitrate limit so the video
+/// still plays.
+pub(super) fn apply_limits(renditions: &mut Vec<Rendition>, ladder: &LadderConfig) {
+    let limits = ladder.limits;
+    let Some(smallest) = renditions.last().cloned() else {
+        return;
+    };
+    renditions.retain(|rung| {
+        limits.max_height.is_none_or(|max| rung.height <= max)
+            && limits
+                .max_bitrate_kbps
+                .is_none_or(|max| rung.bitrate <= max)
+    });
+    if renditions.is_empty() {
+        let mut rung = smallest;
+        if let Some(max) = limits.max_bitrate_kbps {
+            (rung.bitrate, rung.maxrate, rung.bufsize) =
+                rate_control(f64::from(max).clamp(MIN_BITRATE_KBPS, f64::from(rung.bitrate)));
+        }
+        renditions.push(rung);
+    }
+}
+
+pub(super) fn estimate_bitrates(width: u32, height: u32, ladder: &LadderConfig) -> (u32, u32, u32) {
+    rate_control(scaled_bitrate(width, height, ladder).clamp(MIN_BITRATE_KBPS, MAX_BITRATE_KBPS))
+}
+
+/// The 1080p base bitrate scaled by pixel count.
+fn scaled_bitrate(width: u32, height: u32, ladder: &LadderConfig) -> f64 {
+    let pixels = (width as f64) * (height as f64);
+    let reference = 1920.0 * 1080.0;
+    let bitrate = ladder.base_bitrate_1080p_kbps * (pixels / reference);
+    if bitrate.is_finite() {
+        bitrate
+    } else {
+        ladder.base_bitrate_1080p_kbps
+    }
+}
+
+/// Average bitrate, maxrate and buffer size for a target bitrate.
+fn rate_control(bitrate: f64) -> (u32, u32, u32) {
+    let maxrate = (bitrate * 1.3).ceil();
+    let bufsize = (bitrate * 2.5).ceil();
+    (bitrate.round() as u32, maxrate as u32, bufsize as u32)
+}
@@ -0,0 +1,307 @@
+use crate::transcode::{
+    config::{EncoderKind, LadderLimits, OutputColor},
+    mpd::{MpdMetadata, postprocess_mpd},
+    probe::VideoGeometry,
+};
+
+use super::{
+    Rendition,
+    args::{build_filter_complex, build_var_stream_map},
+    ladder::{DEFAULT_MAX_RENDITIONS, LadderConfig, LadderEncoders, select_renditions},
+    rungs::estimate_bitrates,
+};
+
+fn ladder_heights(renditions: &[Rendition]) -> Vec<u32> {
+    renditions.iter().map(|rung| rung.height).collect()
+}
+
+fn sample_renditions() -> Vec<Rendition> {
+    vec![
+        Rendition {
+            name: "1080p".into(),
+            width: 1920,
+            height: 1080,
+            bitrate: 6000,
+            maxrate: 6500,
+            bufsize: 8000,
+            low_bandwidth: false,
+            encoder: EncoderKind::SoftwareAv1,
+        },
+        Rendition {
+            name: "720p".into(),
+            width: 1280,
+            height: 720,
+            bitrate: 3000,
+            maxrate: 3500,
+            bufsize: 4000,
+            low_bandwidth: false,
+            encoder: EncoderKind::SoftwareAv1,
+        },
+    ]
+}
+
+#[test]
+fn ultrawide_source_produces_descending_unique_even_rungs() {
+    let geometry = VideoGeometry {
+        width: 5120,
+        height: 2160,
+    };
+
+    let renditions = select_renditions(geometry, &LadderConfig::default());
+    assert!(!renditions.is_empty());
+    assert!(renditions.len() <= DEFAULT_MAX_RENDITIONS);
+    assert_eq!(renditions[0].width, 5120);
+    assert_eq!(renditions[0].height, 2160);
+
+    let mut last_height = u32::MAX;
+    let mut seen = std::collections::HashSet::new();
+    for rung in renditions {
+        assert!(rung.width <= 5120);
+        assert!(rung.height <= 2160);
+        assert!(rung.width.is_multiple_of(2));
+        assert!(rung.height.is_multiple_of(2));
+        assert!(rung.height <= last_height);
+        assert!(seen.insert((rung.width, rung.height)));
+        last_height = rung.height;
+    }
+}
+
+#[test]
+fn sixteen_nine_source_matches_expected_ladder() {
+    let geometry = VideoGeometry {
+        width: 1920,
+        height: 1080,
+    };
+
+    let renditions = select_renditions(geometry, &LadderConfig::default());
+    assert_eq!(ladder_heights(&renditions), vec![1080, 900, 720, 540, 480]);
+    for rung in renditions {
+        assert!(rung.width <= 1920);
+        assert!(rung.width.is_multiple_of(2));
+    }
+}
+
+#[test]
+fn tall_video_keeps_vertical_ladder() {
+    let geometry = VideoGeometry {
+        width: 1080,
+        height: 1920,
+    };
+
+    let renditions = select_renditions(geometry, &LadderConfig::default());
+    assert_eq!(
+        ladder_heights(&renditions),
+        vec![1920, 1600, 1440, 1200, 1080]
+    );
+    for rung in renditions {
+        assert!(rung.width <= 1080);
+    }
+}
+
+#[test]
+fn filter_complex_matches_expected_layout() {
+    let filter = build_filter_complex(
+        &sample_renditions(),
+        Some(EncoderKind::SoftwareAv1),
+        &OutputColor::default(),
+    );
+    assert_eq!(
+        filter,
+        "[0:v]scale=-2:1080:flags=lanczos[v0];[0:v]scale=-2:720:flags=lanczos[v1]"
+    );
+}
+
+#[test]
+fn var_stream_map_handles_audio_and_video() {
+    let renditions = sample_renditions();
+    let with_audio = build_var_stream_map(&renditions, true);
+    assert_eq!(with_audio, "v:0,a:0,name:1080p v:1,a:0,name:720p");
+
+    let without_audio = build_var_stream_map(&renditions, false);
+    assert_eq!(without_audio, "v:0,name:1080p v:1,name:720p");
+
+    assert_eq!(build_var_stream_map(&[], true), "a:0,name:audio");
+}
+
+#[test]
+fn bitrate_estimates_scale_with_resolution() {
+    let ladder = LadderConfig::default();
+    let high = estimate_bitrates(1920, 1080, &ladder);
+    let mid = estimate_bitrates(1280, 720, &ladder);
+    let low = estimate_bitrates(640, 360, &ladder);
+
+    assert!(high.0 > mid.0);
+    assert!(high.1 > mid.1);
+    assert!(high.2 > mid.2);
+    assert!(mid.0 > low.0);
+}
+
+#[test]
+fn ladder_config_caps_rung_count() {
+    let geometry = VideoGeometry {
+        width: 1920,
+        height: 1080,
+    };
+    let ladder = LadderConfig {
+        max_renditions: 2,
+        ..LadderConfig::default()
+    };
+
+    let renditions = select_renditions(geometry, &ladder);
+    assert_eq!(ladder_heights(&renditions), vec![1080, 900]);
+}
+
+#[test]
+fn ladder_limits_trim_tall_and_costly_rungs() {
+    let geometry = VideoGeometry {
+        width: 3840,
+        height: 2160,
+    };
+    let capped = |limits| select_renditions(geometry, &LadderConfig::default().with_limits(limits));
+
+    let by_height = capped(LadderLimits {
+        max_height: Some(720),
+        ..LadderLimits::default()
+    });
+    assert_eq!(ladder_heights(&by_height), vec![720, 540, 480, 360, 240]);
+
+    let by_bitrate = capped(LadderLimits {
+        max_bitrate_kbps: Some(2_500),
+        ..LadderLimits::default()
+    });
+    assert_eq!(ladder_heights(&by_bitrate), vec![720, 540, 480, 360, 240]);
+    assert!(by_bitrate.iter().all(|rung| rung.bitrate <= 2_500));
+
+    let below_every_rung = capped(LadderLimits {
+        max_height: Some(100),
+        max_bitrate_kbps: Some(200),
+        ..LadderLimits::default()
+    });
+    assert_eq!(ladder_heights(&below_every_rung), vec![240]);
+    assert_eq!(below_every_rung[0].bitrate, 320);
+}
+
+#[test]
+fn low_rungs_extend_the_ladder_with_low_rate_audio() {
+    let ladder = LadderConfig {
+        low_rungs: true,
+        ..LadderConfig::default()
+    };
+
+    let wide = select_renditions(
+        VideoGeometry {
+            width: 1920,
+            height: 1080,
+        },
+        &ladder,
+    );
+    assert_eq!(
+        ladder_heights(&wide),
+        vec![1080, 900, 720, 540, 480, 240, 144]
+    );
+    assert_eq!((wide[6].width, wide[6].bitrate), (256, 80));
+    assert!(wide[5].low_bandwidth && !wide[4].low_bandwidth);
+    assert!(build_var_stream_map(&wide, true).ends_with("v:5,a:1,name:240p v:6,a:1,name:144p"));
+
+    let tall = select_renditions(
+        VideoGeometry {
+            width: 1080,
+            height: 1920,
+        },
+        &ladder,
+    );
+    let low: Vec<_> = tall
+        .iter()
+        .filter(|rung| rung.low_bandwidth)
+        .map(|rung| (rung.width, rung.height))
+        .collect();
+    assert_eq!(low, vec![(240, 426), (144, 256)]);
+
+    let small = select_renditions(
+        VideoGeometry {
+            width: 426,
+            height: 240,
+        },
+        &ladder,
+    );
+    assert_eq!(ladder_heights(&small), vec![240, 144]);
+    assert!(!small[0].low_bandwidth);
+}
+
+#[test]
+fn mpd_postprocessing_fills_codecs_labels_and_timing() {
+    let manifest = concat!(
+        "<?xml version=\"1.0\"?>\n",
+        "<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" minBufferTime=\"PT2.0S\">\n",
+        "\t<Period id=\"0\">\n",
+        "\t\t<AdaptationSet id=\"0\" contentType=\"video\">\n",
+        "\t\t\t<Representation id=\"0\" mimeType=\"video/mp4\" codecs=\"av01\" width=\"1920\" height=\"1080\">\n",
+        "\t\t\t\t<SegmentTemplate media=\"chunk.m4s\"/>\n",
+        "\t\t\t</Representation>\n",
+        "\t\t</AdaptationSet>\n",
+        "\t\t<AdaptationSet id=\"1\" contentType=\"audio\">\n",
+        "\t\t\t<Representation id=\"2\" mimeType=\"audio/mp4\" bandwidth=\"192000\">\n",
+        "\t\t\t\t<AudioChannelConfiguration value=\"2\"/>\n",
+        "\t\t\t\t<SegmentTemplate media=\"chunk.m4s\"/>\n",
+        "\t\t\t</Representation>\n",
+        "\t\t</AdaptationSet>\n",
+        "\t</Period>\n",
+        "</MPD>\n",
+    );
+    let renditions = sample_renditions();
+    let metadata = MpdMetadata {
+        renditions: &renditions,
+        has_audio: true,
+        frame_rate: Some("30000/1001".into()),
+        pixel_format: "yuv420p",
+        segment_seconds: 4,
+        utc_timing_url: Some("https://time.example/?iso&ms".into()),
+    };
+
+    let output = postprocess_mpd(manifest, &metadata);
+
+    assert!(output.contains("minBufferTime=\"PT8S\""));
+    assert!(output.contains("codecs=\"av01.0.08M.08\""));
+    assert!(output.contains("frameRate=\"30000/1001\""));
+    assert!(output.contains("codecs=\"mp4a.40.2\""));
+    assert!(output.contains("<Label>1080p</Label>\n\t\t\t\t<SegmentTemplate"));
+    assert!(output.contains("<Label>audio</Label>\n\t\t\t\t<SegmentTemplate"));
+    assert!(output.contains("value=\"https://time.example/?iso&amp;ms\"/>\n</MPD>"));
+}
+
+#[test]
+fn mpd_postprocessing_advertises_ten_bit_renditions() {
+    let manifest = concat!(
+        "<MPD minBufferTime=\"PT2.0S\">\n",
+        "<Representation id=\"0\" mimeType=\"video/mp4\" codecs=\"av01\" width=\"1920\" height=\"1080\">\n",
+        "<SegmentTemplate media=\"chunk.m4s\"/>\n",
+        "</Representation>\n",
+        "</MPD>\n",
+    );
+    let renditions = sample_renditions();
+    let metadata = MpdMetadata {
+        renditions: &renditions,
+        has_audio: false,
+        frame_rate: None,
+        pixel_format: "yuv420p10le",
+        segment_seconds: 4,
+        utc_timing_url: None,
+    };
+
+    let output = postprocess_mpd(manifest, &metadata);
+
+    assert!(output.contains("codecs=\"av01.0.08M.10\""));
+}
+
+#[test]
+fn ladder_encoders_pick_the_largest_matching_threshold() {
+    let encoders = LadderEncoders::parse("720p=nvenc, 2160=software, bogus, *=qsv");
+    assert_eq!(encoders.for_rung(3840, 2160), EncoderKind::SoftwareAv1);
+    assert_eq!(encoders.for_rung(1920, 1080), EncoderKind::NvencAv1);
+    assert_eq!(encoders.for_rung(720, 1280), EncoderKind::NvencAv1);
+    assert_eq!(encoders.for_rung(854, 480), EncoderKind::QsvAv1);
+    assert_eq!(
+        LadderEncoders::default().for_rung(1920, 1080),
+        EncoderKind::SoftwareAv1
+    );
+}
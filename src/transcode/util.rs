@@ -2,7 +2,7 @@ use std::{ffi::OsString, path::Path};
 
 use tokio::fs;
 
-use crate::{error::AppError, storage::ensure_parent};
+use crate::{blocking, error::AppError, storage::ensure_parent};
 
 pub(crate) async fn finalize_encoded_file(temp: &Path, final_path: &Path) -> Result<(), AppError> {
     ensure_parent(final_path).await?;
@@ -14,7 +14,7 @@ pub(crate) async fn finalize_encoded_file(temp: &Path, final_path: &Path) -> Res
     match fs::rename(temp, final_path).await {
         Ok(_) => Ok(()),
         Err(err) if err.kind() == std::io::ErrorKind::CrossesDevices => {
-            fs::copy(temp, final_path).await.map_err(AppError::from)?;
+            blocking::copy(temp, final_path).await?;
             fs::remove_file(temp).await.ok();
             Ok(())
         }
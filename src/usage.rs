@@ -0,0 +1,383 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::SystemTime,
+};
+
+use axum::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    auth::Claims,
+    bandwidth, clock, config,
+    error::AppError,
+    metadata,
+    storage::{Storage, dir_size, ensure_dir},
+};
+
+const MAX_KEY_LEN: usize = 128;
+
+/// Work charged to a caller key.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct UsageCounters {
+    /// Bytes of source media written to storage by uploads and downloads.
+    pub ingested_bytes: u64,
+    /// Wall-clock minutes spent encoding.
+    pub encode_minutes: f64,
+}
+
+impl UsageCounters {
+    fn add(&mut self, ingested_bytes: u64, encode_minutes: f64) {
+        self.ingested_bytes = self.ingested_bytes.saturating_add(ingested_bytes);
+        self.encode_minutes += encode_minutes;
+    }
+}
+
+/// A key's usage in one month, with a breakdown by UTC day (`YYYY-MM-DD`).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct KeyUsage {
+    pub month: UsageCounters,
+    pub days: BTreeMap<String, UsageCounters>,
+}
+
+/// One calendar month (UTC) of usage, as stored under
+/// `<storage>/analytics/usage/<YYYY-MM>.json`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MonthlyUsage {
+    pub keys: BTreeMap<String, KeyUsage>,
+}
+
+/// One quota: a default for every key plus per-key overrides.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuotaLimit<T> {
+    pub default: Option<T>,
+    pub keys: BTreeMap<String, T>,
+}
+
+impl<T> Default for QuotaLimit<T> {
+    fn default() -> Self {
+        Self {
+            default: None,
+            keys: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: FromStr + Copy> QuotaLimit<T> {
+    /// Parses `500000000` or `alice=2000000000,*=500000000`. A bare value
+    /// and `*` both set the default; entries that do not parse are ignored.
+    pub fn parse(spec: &str) -> Self {
+        let mut limit = Self::default();
+        for entry in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            match entry.split_once('=') {
+                Some((key, value)) => {
+                    let Ok(value) = value.trim().parse() else {
+                        continue;
+                    };
+                    match key.trim() {
+                        "*" => limit.default = Some(value),
+                        key => {
+                            limit.keys.insert(key.to_string(), value);
+                        }
+                    }
+                }
+                None => limit.default = entry.parse().ok().or(limit.default),
+            }
+        }
+        limit
+    }
+
+    pub fn for_key(&self, key: &str) -> Option<T> {
+        self.keys.get(key).copied().or(self.default)
+    }
+
+    fn from_var(name: &str) -> Self {
+        config::var(name)
+            .map(|spec| Self::parse(&spec))
+            .unwrap_or_default()
+    }
+}
+
+/// Quotas enforced when a job is created. Every quota is unlimited unless
+/// its variable is set.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QuotaConfig {
+    pub daily_ingest_bytes: QuotaLimit<u64>,
+    pub monthly_ingest_bytes: QuotaLimit<u64>,
+    pub daily_encode_minutes: QuotaLimit<f64>,
+    pub monthly_encode_minutes: QuotaLimit<f64>,
+    pub storage_bytes: QuotaLimit<u64>,
+}
+
+impl QuotaConfig {
+    pub fn from_env() -> Self {
+        Self {
+            daily_ingest_bytes: QuotaLimit::from_var("VIDEO_QUOTA_DAILY_INGEST_BYTES"),
+            monthly_ingest_bytes: QuotaLimit::from_var("VIDEO_QUOTA_MONTHLY_INGEST_BYTES"),
+            daily_encode_minutes: QuotaLimit::from_var("VIDEO_QUOTA_DAILY_ENCODE_MINUTES"),
+            monthly_encode_minutes: QuotaLimit::from_var("VIDEO_QUOTA_MONTHLY_ENCODE_MINUTES"),
+            storage_bytes: QuotaLimit::from_var("VIDEO_QUOTA_STORAGE_BYTES"),
+        }
+    }
+
+    pub fn for_key(&self, key: &str) -> KeyQuotas {
+        KeyQuotas {
+            daily_ingest_bytes: self.daily_ingest_bytes.for_key(key),
+            monthly_ingest_bytes: self.monthly_ingest_bytes.for_key(key),
+            daily_encode_minutes: self.daily_encode_minutes.for_key(key),
+            monthly_encode_minutes: self.monthly_encode_minutes.for_key(key),
+            storage_bytes: self.storage_bytes.for_key(key),
+        }
+    }
+}
+
+/// The quotas that apply to one key; absent ones are unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct KeyQuotas {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily_ingest_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monthly_ingest_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily_encode_minutes: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monthly_encode_minutes: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage_bytes: Option<u64>,
+}
+
+/// A key's usage today and this month, its stored bytes and its quotas.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UsageReport {
+    pub key: String,
+    pub day: String,
+    pub month: String,
+    pub daily: UsageCounters,
+    pub monthly: UsageCounters,
+    /// Size of the video directories ingested under the key.
+    pub stored_bytes: u64,
+    pub quotas: KeyQuotas,
+}
+
+/// Counts ingest and encode work per caller key and enforces quotas on it.
+///
+/// Every charge is written straight into the month's file under the
+/// `usage.<YYYY-MM>` lock, so instances sharing a storage root see each
+/// other's usage when admitting jobs.
+#[derive(Clone)]
+pub struct UsageLedger {
+    storage: Storage,
+    /// Fixed quotas; otherwise they are re-read from config on each check.
+    quotas: Option<Arc<QuotaConfig>>,
+}
+
+impl UsageLedger {
+    pub fn new(storage: Storage) -> Self {
+        Self {
+            storage,
+            quotas: None,
+        }
+    }
+
+    /// Uses `quotas` instead of reading them from the environment.
+    pub fn with_quotas(mut self, quotas: QuotaConfig) -> Self {
+        self.quotas = Some(Arc::new(quotas));
+        self
+    }
+
+    fn quotas(&self) -> QuotaConfig {
+        self.quotas
+            .as_deref()
+            .cloned()
+            .unwrap_or_else(QuotaConfig::from_env)
+    }
+
+    /// Charges work to `key` for the current day and month.
+    pub async fn record(
+        &self,
+        key: &str,
+        ingested_bytes: u64,
+        encode_minutes: f64,
+    ) -> Result<(), AppError> {
+        if ingested_bytes == 0 && encode_minutes <= 0.0 {
+            return Ok(());
+        }
+        let day = current_day();
+        let month = &day[..7];
+        let _guard = self
+            .storage
+            .locks()
+            .acquire(&format!("usage.{month}"))
+            .await?;
+        let path = self.path(month);
+        let mut stored = read_month(&path).await?;
+        let usage = stored.keys.entry(key.to_string()).or_default();
+        usage.month.add(ingested_bytes, encode_minutes);
+        usage
+            .days
+            .entry(day.clone())
+            .or_default()
+            .add(ingested_bytes, encode_minutes);
+        ensure_dir(&self.dir()).await?;
+        let bytes = serde_json::to_vec_pretty(&stored).map_err(std::io::Error::from)?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, bytes).await?;
+        fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    pub async fn report(&self, key: &str) -> Result<UsageReport, AppError> {
+        let day = current_day();
+        let month = day[..7].to_string();
+        let (daily, monthly) = self.counters(key, &day).await?;
+        Ok(UsageReport {
+            key: key.to_string(),
+            stored_bytes: stored_bytes(&self.storage, key).await?,
+            quotas: self.quotas().for_key(key),
+            day,
+            month,
+            daily,
+            monthly,
+        })
+    }
+
+    /// Refuses new work with `429` once `key` has reached any of its quotas.
+    pub async fn admit(&self, key: &str) -> Result<(), AppError> {
+        let quotas = self.quotas().for_key(key);
+        if quotas == KeyQuotas::default() {
+            return Ok(());
+        }
+        let (daily, monthly) = self.counters(key, &current_day()).await?;
+        check(
+            key,
+            "daily_ingest_bytes",
+            quotas.daily_ingest_bytes,
+            daily.ingested_bytes,
+        )?;
+        check(
+            key,
+            "monthly_ingest_bytes",
+            quotas.monthly_ingest_bytes,
+            monthly.ingested_bytes,
+        )?;
+        check(
+            key,
+            "daily_encode_minutes",
+            quotas.daily_encode_minutes,
+            daily.encode_minutes,
+        )?;
+        check(
+            key,
+            "monthly_encode_minutes",
+            quotas.monthly_encode_minutes,
+            monthly.encode_minutes,
+        )?;
+        if quotas.storage_bytes.is_some() {
+            let stored = stored_bytes(&self.storage, key).await?;
+            check(key, "storage_bytes", quotas.storage_bytes, stored)?;
+        }
+        Ok(())
+    }
+
+    async fn counters(
+        &self,
+        key: &str,
+        day: &str,
+    ) -> Result<(UsageCounters, UsageCounters), AppError> {
+        let stored = read_month(&self.path(&day[..7])).await?;
+        let Some(usage) = stored.keys.get(key) else {
+            return Ok(Default::default());
+        };
+        let daily = usage.days.get(day).copied().unwrap_or_default();
+        Ok((daily, usage.month))
+    }
+
+    fn dir(&self) -> PathBuf {
+        self.storage.root_dir().join("analytics").join("usage")
+    }
+
+    fn path(&self, month: &str) -> PathBuf {
+        self.dir().join(format!("{month}.json"))
+    }
+}
+
+fn check<T>(key: &str, quota: &'static str, limit: Option<T>, used: T) -> Result<(), AppError>
+where
+    T: PartialOrd + Display + Into<serde_json::Value> + Copy,
+{
+    match limit {
+        Some(limit) if used >= limit => Err(AppError::rate_limited(format!(
+            "{key} has reached its {quota} quota of {limit} ({used} used)"
+        ))
+        .with_code("quota_exceeded")
+        .with_param("key", key)
+        .with_param("quota", quota)
+        .with_param("limit", limit)
+        .with_param("used", used)),
+        _ => Ok(()),
+    }
+}
+
+/// Size of the video directories whose metadata names `key` as their account.
+pub async fn stored_bytes(storage: &Storage, key: &str) -> Result<u64, AppError> {
+    let mut entries = match fs::read_dir(storage.root_dir()).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+    let mut total = 0u64;
+    while let Some(entry) = entries.next_entry().await? {
+        let Some(id) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| Uuid::parse_str(name).ok())
+        else {
+            continue;
+        };
+        if metadata::load(storage, &id).await?.account.as_deref() == Some(key) {
+            total = total.saturating_add(dir_size(&entry.path()).await);
+        }
+    }
+    Ok(total)
+}
+
+/// The key usage is charged to: the subject of a verified token, else the
+/// caller key of [`bandwidth::request_key`] when `trusted_proxy` says the
+/// request came through a proxy that sets it, else `anonymous`. A client
+/// talking to the server directly cannot pick its own key.
+pub fn account_key(headers: &HeaderMap, claims: Option<&Claims>, trusted_proxy: bool) -> String {
+    claims
+        .and_then(|claims| claims.sub.as_deref())
+        .map(str::trim)
+        .filter(|sub| !sub.is_empty())
+        .map(|sub| sub.chars().take(MAX_KEY_LEN).collect())
+        .unwrap_or_else(|| {
+            if trusted_proxy {
+                bandwidth::request_key(headers)
+            } else {
+                bandwidth::ANONYMOUS_KEY.to_string()
+            }
+        })
+}
+
+/// The current UTC day as `YYYY-MM-DD`.
+fn current_day() -> String {
+    clock::rfc3339(clock::unix_ms(SystemTime::now()))[..10].to_string()
+}
+
+async fn read_month(path: &Path) -> Result<MonthlyUsage, AppError> {
+    match fs::read(path).await {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes).map_err(std::io::Error::from)?),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(MonthlyUsage::default()),
+        Err(err) => Err(err.into()),
+    }
+}
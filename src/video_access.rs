@@ -0,0 +1,192 @@
+use std::{net::IpAddr, sync::OnceLock, time::Duration};
+
+use axum::http::HeaderMap;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::{
+    config,
+    error::AppError,
+    metadata::VideoMetadata,
+    password::PASSWORD_HEADER,
+    signing::{PlaybackSigner, unix_now},
+    state::AppState,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const DEFAULT_ACCESS_TTL: Duration = Duration::from_secs(3600);
+
+static PROCESS_SECRET: OnceLock<Vec<u8>> = OnceLock::new();
+
+/// Issues and verifies access tokens for password-protected videos, of the
+/// form `{expires}.{signature}`, where `signature` is the hex HMAC-SHA256 of
+/// `access:{video_id}:{expires}:{password_hash}`. Binding the hash means a
+/// replaced or removed password revokes every token issued for it.
+#[derive(Clone)]
+pub struct AccessSigner {
+    secret: Vec<u8>,
+    ttl: Duration,
+}
+
+impl AccessSigner {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+            ttl: DEFAULT_ACCESS_TTL,
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Keyed by `VIDEO_ACCESS_TOKEN_SECRET`, else `VIDEO_SIGNING_SECRET`, else
+    /// a key generated at startup that only this process accepts. Read per
+    /// request so reloads apply.
+    pub fn from_env() -> Self {
+        let secret = config::var("VIDEO_ACCESS_TOKEN_SECRET")
+            .filter(|secret| !secret.is_empty())
+            .or_else(|| config::var("VIDEO_SIGNING_SECRET").filter(|secret| !secret.is_empty()))
+            .map(String::into_bytes)
+            .unwrap_or_else(|| {
+                PROCESS_SECRET
+                    .get_or_init(|| {
+                        [Uuid::new_v4(), Uuid::new_v4()]
+                            .iter()
+                            .flat_map(|id| id.into_bytes())
+                            .collect()
+                    })
+                    .clone()
+            });
+        let ttl = config::parse_var::<u64>("VIDEO_PASSWORD_ACCESS_TTL_SECS")
+            .filter(|ttl| *ttl > 0)
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_ACCESS_TTL);
+        Self::new(secret).with_ttl(ttl)
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Issues a token for `video_id` valid for `ttl`, along with the unix
+    /// timestamp it expires at.
+    pub fn issue_for(&self, video_id: &Uuid, password_hash: &str, ttl: Duration) -> (String, u64) {
+        let expires = unix_now().saturating_add(ttl.as_secs());
+        (self.sign(video_id, password_hash, expires), expires)
+    }
+
+    pub fn sign(&self, video_id: &Uuid, password_hash: &str, expires_unix: u64) -> String {
+        let signature = hex::encode(
+            self.mac(video_id, expires_unix, password_hash)
+                .finalize()
+                .into_bytes(),
+        );
+        format!("{expires_unix}.{signature}")
+    }
+
+    pub fn verify(
+        &self,
+        video_id: &Uuid,
+        password_hash: &str,
+        token: &str,
+    ) -> Result<(), AppError> {
+        let malformed = || AppError::forbidden("malformed video access token");
+        let (expires, signature) = token.split_once('.').ok_or_else(malformed)?;
+        let expires = expires.parse::<u64>().map_err(|_| malformed())?;
+        let signature = hex::decode(signature).map_err(|_| malformed())?;
+
+        self.mac(video_id, expires, password_hash)
+            .verify_slice(&signature)
+            .map_err(|_| AppError::forbidden("invalid video access token"))?;
+
+        if expires < unix_now() {
+            return Err(AppError::forbidden("video access token expired"));
+        }
+        Ok(())
+    }
+
+    fn mac(&self, video_id: &Uuid, expires_unix: u64, password_hash: &str) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(
+            format!(
+                "access:{}:{expires_unix}:{password_hash}",
+                video_id.hyphenated()
+            )
+            .as_bytes(),
+        );
+        mac
+    }
+}
+
+/// Checks the session token when signed URLs are enabled and returns the active signer.
+pub fn verify_playback(
+    video_id: &Uuid,
+    token: Option<&str>,
+) -> Result<Option<PlaybackSigner>, AppError> {
+    let Some(signer) = PlaybackSigner::from_env() else {
+        return Ok(None);
+    };
+    signer.verify(video_id, token)?;
+    Ok(Some(signer))
+}
+
+/// Admits a viewer to a protected video with a valid `access` token or the
+/// password in `X-Video-Password`. Returns the access token to forward to
+/// the video's other assets, or `None` when the video has no password. A
+/// password that checks out gets a fresh token, so Argon2 runs once and the
+/// requests that follow carry the token instead.
+pub async fn unlock(
+    state: &AppState,
+    video_id: &Uuid,
+    meta: &VideoMetadata,
+    headers: &HeaderMap,
+    client: Option<IpAddr>,
+    access: Option<&str>,
+) -> Result<Option<String>, AppError> {
+    let Some(hash) = &meta.password_hash else {
+        return Ok(None);
+    };
+    let signer = AccessSigner::from_env();
+    let presented = headers
+        .get(PASSWORD_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty());
+    if let Some(access) = access.filter(|access| !access.is_empty()) {
+        match signer.verify(video_id, hash, access) {
+            Ok(()) => return Ok(Some(access.to_string())),
+            Err(err) if presented.is_none() => return Err(err),
+            Err(_) => {}
+        }
+    }
+    state
+        .password_attempts
+        .verify(video_id, client, hash, presented)
+        .await?;
+    Ok(Some(signer.issue_for(video_id, hash, signer.ttl()).0))
+}
+
+/// Query string appended to manifest URIs so players that cannot set headers
+/// carry the session and access tokens on to every segment request. The
+/// password itself is never forwarded.
+pub fn forwarded_query(
+    signer: &Option<PlaybackSigner>,
+    token: Option<&str>,
+    access: Option<&str>,
+) -> Option<String> {
+    let mut pairs = url::form_urlencoded::Serializer::new(String::new());
+    let mut any = false;
+    if let (Some(_), Some(token)) = (signer, token) {
+        pairs.append_pair("token", token);
+        any = true;
+    }
+    if let Some(access) = access {
+        pairs.append_pair("access", access);
+        any = true;
+    }
+    any.then(|| pairs.finish())
+}
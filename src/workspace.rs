@@ -0,0 +1,258 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use serde::Serialize;
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    clock,
+    error::AppError,
+    jobs::{DynJobStore, JobStage},
+    locks::LockManager,
+    storage::{Storage, dir_size},
+};
+
+/// What a tmp workspace item is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TmpKind {
+    /// An upload or remote download waiting to be transcoded.
+    Incoming,
+    /// Generated HLS renditions of a stored video.
+    Hls,
+    /// Generated DASH renditions of a stored video.
+    Dash,
+    /// Intermediate output of a running encode or optional stage.
+    Work,
+}
+
+/// One file or directory in the tmp workspace.
+#[derive(Debug, Clone, Serialize)]
+pub struct TmpEntry {
+    /// Path relative to the workspace root, e.g. `incoming/<id>.incoming`.
+    pub name: String,
+    pub kind: TmpKind,
+    pub size_bytes: u64,
+    pub modified_at: String,
+    pub age_seconds: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<Uuid>,
+    /// Stage of the owning job, when the job store still knows it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_stage: Option<JobStage>,
+    /// Left behind with nothing that will use or clean it up: its job is
+    /// unknown or finished, or for HLS/DASH, its video is gone.
+    pub orphaned: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TmpListing {
+    pub root: String,
+    pub total_bytes: u64,
+    pub orphaned_bytes: u64,
+    /// Oldest first.
+    pub entries: Vec<TmpEntry>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct TmpClearReport {
+    pub removed: Vec<String>,
+    pub freed_bytes: u64,
+}
+
+const SUBDIRS: [(&str, TmpKind); 3] = [
+    ("incoming", TmpKind::Incoming),
+    ("hls", TmpKind::Hls),
+    ("dash", TmpKind::Dash),
+];
+
+/// Everything under the tmp workspace with its size, age and owning job.
+pub async fn list(storage: &Storage, jobs: &DynJobStore) -> Result<TmpListing, AppError> {
+    let entries = scan(storage, jobs).await?;
+    Ok(TmpListing {
+        root: storage.tmp_dir().display().to_string(),
+        total_bytes: entries.iter().map(|entry| entry.size_bytes).sum(),
+        orphaned_bytes: entries
+            .iter()
+            .filter(|entry| entry.orphaned)
+            .map(|entry| entry.size_bytes)
+            .sum(),
+        entries,
+    })
+}
+
+/// Deletes the item listed as `name`. Items of a job that is still running
+/// are refused unless `force` is set.
+pub async fn remove(
+    storage: &Storage,
+    jobs: &DynJobStore,
+    name: &str,
+    force: bool,
+) -> Result<TmpEntry, AppError> {
+    let entry = scan(storage, jobs)
+        .await?
+        .into_iter()
+        .find(|entry| entry.name == name)
+        .ok_or_else(|| AppError::not_found(format!("tmp item {name}")))?;
+    if let (Some(job_id), Some(stage)) = (entry.job_id, entry.job_stage)
+        && !stage.is_terminal()
+        && !force
+    {
+        return Err(AppError::validation(format!(
+            "{name} belongs to job {job_id}, which is still {}; pass force=true to delete it anyway",
+            stage.as_str()
+        ))
+        .with_code("tmp_item_in_use")
+        .with_param("job_id", job_id.to_string()));
+    }
+    delete_entry(storage, &entry).await?;
+    tracing::info!(
+        name,
+        size_bytes = entry.size_bytes,
+        "removed tmp workspace item"
+    );
+    Ok(entry)
+}
+
+/// Deletes every orphaned item.
+pub async fn clear_orphans(
+    storage: &Storage,
+    jobs: &DynJobStore,
+) -> Result<TmpClearReport, AppError> {
+    let mut report = TmpClearReport::default();
+    for entry in scan(storage, jobs).await? {
+        if !entry.orphaned {
+            continue;
+        }
+        delete_entry(storage, &entry).await?;
+        report.freed_bytes += entry.size_bytes;
+        report.removed.push(entry.name);
+    }
+    if !report.removed.is_empty() {
+        tracing::info!(
+            removed = report.removed.len(),
+            freed_bytes = report.freed_bytes,
+            "cleared orphaned tmp workspace items"
+        );
+    }
+    Ok(report)
+}
+
+async fn scan(storage: &Storage, jobs: &DynJobStore) -> Result<Vec<TmpEntry>, AppError> {
+    let stages: HashMap<Uuid, JobStage> = jobs
+        .list()
+        .await?
+        .into_iter()
+        .map(|status| (status.id, status.stage))
+        .collect();
+    let root = storage.tmp_dir();
+    let now = SystemTime::now();
+
+    let mut found = Vec::new();
+    for (name, path) in read_names(&root).await? {
+        match SUBDIRS.iter().find(|(subdir, _)| *subdir == name) {
+            Some((subdir, kind)) if path.is_dir() => {
+                for (child, path) in read_names(&path).await? {
+                    found.push((format!("{subdir}/{child}"), child, path, *kind));
+                }
+            }
+            _ => found.push((name.clone(), name, path, TmpKind::Work)),
+        }
+    }
+
+    let mut entries = Vec::with_capacity(found.len());
+    for (name, file_name, path, kind) in found {
+        let Ok(meta) = fs::symlink_metadata(&path).await else {
+            continue;
+        };
+        let modified = meta.modified().unwrap_or(now);
+        let size_bytes = if meta.is_dir() {
+            dir_size(&path).await
+        } else {
+            meta.len()
+        };
+        let job_id = owner_of(&file_name);
+        let job_stage = job_id.and_then(|id| stages.get(&id).copied());
+        let orphaned = match (kind, job_id) {
+            (_, None) => true,
+            (_, Some(_)) if job_stage.is_some_and(|stage| !stage.is_terminal()) => false,
+            (TmpKind::Hls | TmpKind::Dash, Some(id)) => !storage.download_path(&id).exists(),
+            (TmpKind::Incoming | TmpKind::Work, Some(_)) => true,
+        };
+        entries.push(TmpEntry {
+            name,
+            kind,
+            size_bytes,
+            modified_at: clock::rfc3339(clock::unix_ms(modified)),
+            age_seconds: now
+                .duration_since(modified)
+                .unwrap_or(Duration::ZERO)
+                .as_secs(),
+            job_id,
+            job_stage,
+            orphaned,
+        });
+    }
+    entries.sort_by(|a, b| {
+        b.age_seconds
+            .cmp(&a.age_seconds)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    Ok(entries)
+}
+
+async fn read_names(dir: &Path) -> Result<Vec<(String, PathBuf)>, AppError> {
+    let mut names = Vec::new();
+    let mut entries = match fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(names),
+        Err(err) => return Err(err.into()),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(name) = entry.file_name().to_str() {
+            names.push((name.to_string(), entry.path()));
+        }
+    }
+    Ok(names)
+}
+
+/// Tmp items are named after their video, e.g. `<id>.incoming`,
+/// `<id>.encode.webm` or `hls/<uuid>`.
+fn owner_of(file_name: &str) -> Option<Uuid> {
+    let stem = file_name.split('.').next()?;
+    Uuid::parse_str(stem).ok()
+}
+
+async fn delete_entry(storage: &Storage, entry: &TmpEntry) -> Result<(), AppError> {
+    // HLS/DASH directories are also written by on-demand packaging.
+    let _guard = match (entry.kind, entry.job_id) {
+        (TmpKind::Hls, Some(id)) => Some(
+            storage
+                .locks()
+                .acquire(&LockManager::video_key(&id, "hls"))
+                .await?,
+        ),
+        (TmpKind::Dash, Some(id)) => Some(
+            storage
+                .locks()
+                .acquire(&LockManager::video_key(&id, "dash"))
+                .await?,
+        ),
+        _ => None,
+    };
+    let path = storage.tmp_dir().join(&entry.name);
+    let result = match fs::symlink_metadata(&path).await {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(&path).await,
+        Ok(_) => fs::remove_file(&path).await,
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
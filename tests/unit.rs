@@ -1,12 +1,74 @@
+#[path = "unit/alerts.rs"]
+mod alerts;
+#[path = "unit/aria2.rs"]
+mod aria2;
+#[path = "unit/auth.rs"]
+mod auth;
+#[path = "unit/bandwidth.rs"]
+mod bandwidth;
+#[path = "unit/blocking.rs"]
+mod blocking;
+#[path = "unit/breaker.rs"]
+mod breaker;
+#[path = "unit/callbacks.rs"]
+mod callbacks;
 #[path = "unit/cleanup.rs"]
 mod cleanup;
+#[path = "unit/cli.rs"]
+mod cli;
+#[path = "unit/clock.rs"]
+mod clock;
+#[path = "unit/config.rs"]
+mod config;
+#[path = "unit/digest.rs"]
+mod digest;
 #[path = "unit/error.rs"]
 mod error;
+#[path = "unit/federation.rs"]
+mod federation;
 #[path = "unit/handlers.rs"]
 mod handlers;
+#[path = "unit/http_client.rs"]
+mod http_client;
 #[path = "unit/jobs.rs"]
 mod jobs;
+#[path = "unit/locks.rs"]
+mod locks;
+#[path = "unit/migrations.rs"]
+mod migrations;
+#[path = "unit/password.rs"]
+mod password;
+#[path = "unit/playlist.rs"]
+mod playlist;
+#[path = "unit/policy.rs"]
+mod policy;
+#[path = "unit/rate_limit.rs"]
+mod rate_limit;
+#[path = "unit/replication.rs"]
+mod replication;
+#[path = "unit/retry.rs"]
+mod retry;
+#[path = "unit/s3.rs"]
+mod s3;
+#[path = "unit/service.rs"]
+mod service;
+#[path = "unit/shaping.rs"]
+mod shaping;
+#[path = "unit/sharding.rs"]
+mod sharding;
+#[path = "unit/shedding.rs"]
+mod shedding;
+#[path = "unit/signing.rs"]
+mod signing;
+#[path = "unit/skip_segments.rs"]
+mod skip_segments;
 #[path = "unit/storage.rs"]
 mod storage;
+#[path = "unit/tags.rs"]
+mod tags;
 #[path = "unit/transcode.rs"]
 mod transcode;
+#[path = "unit/usage.rs"]
+mod usage;
+#[path = "unit/video_access.rs"]
+mod video_access;
@@ -0,0 +1,99 @@
+use axum::{Json, Router, routing::post};
+use reqwest::Client;
+use serde_json::Value;
+use tempfile::tempdir;
+use vrs::alerts::{AlertCenter, AlertConfig, AlertKind, AlertStatus};
+use vrs::error::AppError;
+use vrs::storage::Storage;
+
+fn config(webhook_url: Option<String>) -> AlertConfig {
+    AlertConfig {
+        webhook_url,
+        min_free_bytes: 0,
+        min_free_ratio: 0.0,
+        ffmpeg_failures: 3,
+        ytdlp_error_rate: 0.5,
+        ytdlp_window: 10,
+    }
+}
+
+#[test]
+fn consecutive_encode_failures_raise_until_an_encode_succeeds() {
+    let alerts = AlertCenter::with_config(Client::new(), config(None));
+    let failed = AppError::transcode("ffmpeg exited with status 1");
+    for _ in 0..2 {
+        alerts.record_ffmpeg(Err(&failed));
+    }
+    // Cancelled jobs neither count nor reset the run.
+    alerts.record_ffmpeg(Err(&AppError::cancelled("job cancelled")));
+    assert!(alerts.report().active.is_empty());
+
+    alerts.record_ffmpeg(Err(&failed));
+    let report = alerts.report();
+    assert_eq!(report.active.len(), 1);
+    assert_eq!(report.active[0].kind, AlertKind::FfmpegFailing);
+    assert!(report.active[0].message.contains("3 encodes in a row"));
+
+    alerts.record_ffmpeg(Ok(()));
+    let report = alerts.report();
+    assert!(report.active.is_empty());
+    let statuses: Vec<AlertStatus> = report.recent.iter().map(|event| event.status).collect();
+    assert_eq!(statuses, [AlertStatus::Resolved, AlertStatus::Raised]);
+}
+
+#[test]
+fn ytdlp_error_rate_is_judged_over_recent_downloads() {
+    let alerts = AlertCenter::with_config(Client::new(), config(None));
+    let failed = AppError::dependency("yt-dlp exited with status 1");
+    // Too few downloads to judge, however many failed.
+    for _ in 0..4 {
+        alerts.record_ytdlp(Err(&failed));
+    }
+    assert!(alerts.report().active.is_empty());
+
+    alerts.record_ytdlp(Ok(()));
+    let report = alerts.report();
+    assert_eq!(report.active[0].kind, AlertKind::YtDlpFailing);
+    assert!(report.active[0].message.starts_with("4 of the last 5"));
+
+    for _ in 0..4 {
+        alerts.record_ytdlp(Ok(()));
+    }
+    assert!(alerts.report().active.is_empty());
+}
+
+#[tokio::test]
+async fn low_disk_space_is_posted_to_the_webhook() {
+    let temp = tempdir().unwrap();
+    let storage = Storage::initialize(temp.path()).await.unwrap();
+    let (sender, mut events) = tokio::sync::mpsc::unbounded_channel::<Value>();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+    let receiver = Router::new().route(
+        "/alerts",
+        post(move |Json(event): Json<Value>| async move {
+            sender.send(event).unwrap();
+        }),
+    );
+    tokio::spawn(async move { axum::serve(listener, receiver).await.unwrap() });
+
+    let alerts = AlertCenter::with_config(
+        Client::new(),
+        AlertConfig {
+            min_free_bytes: u64::MAX,
+            ..config(Some(url))
+        },
+    );
+    alerts.check_disk(&storage).await;
+    // Still low: no second event.
+    alerts.check_disk(&storage).await;
+
+    let event = tokio::time::timeout(std::time::Duration::from_secs(5), events.recv())
+        .await
+        .expect("alert webhook")
+        .unwrap();
+    assert_eq!(event["kind"], "low_disk_space");
+    assert_eq!(event["status"], "raised");
+    assert!(event["message"].as_str().unwrap().contains("bytes free"));
+    assert_eq!(alerts.report().recent.len(), 1);
+}
@@ -0,0 +1,145 @@
+use std::time::Duration;
+
+use vrs::aria2::{Aria2File, Aria2Status, FileSelection, new_gid};
+
+#[test]
+fn tell_status_numbers_are_read_from_strings() {
+    let status: Aria2Status = serde_json::from_value(serde_json::json!({
+        "status": "active",
+        "totalLength": "1048576",
+        "completedLength": "262144",
+        "downloadSpeed": "65536",
+        "followedBy": [],
+    }))
+    .unwrap();
+    assert_eq!(status.progress(), Some(0.25));
+    assert_eq!(status.eta(), Some(Duration::from_secs(12)));
+
+    // A magnet's metadata before its length is known.
+    let status: Aria2Status = serde_json::from_value(serde_json::json!({
+        "status": "active",
+        "totalLength": "0",
+        "completedLength": "0",
+        "downloadSpeed": "0",
+    }))
+    .unwrap();
+    assert_eq!(status.progress(), None);
+    assert_eq!(status.eta(), None);
+
+    let failed: Aria2Status = serde_json::from_value(serde_json::json!({
+        "status": "error",
+        "errorCode": "3",
+        "errorMessage": "Resource not found",
+    }))
+    .unwrap();
+    assert_eq!(failed.error_message.as_deref(), Some("Resource not found"));
+}
+
+#[test]
+fn gids_are_sixteen_hex_digits() {
+    let gid = new_gid();
+    assert_eq!(gid.len(), 16);
+    assert!(gid.chars().all(|c| c.is_ascii_hexdigit()));
+    assert_ne!(gid, new_gid());
+}
+
+fn torrent_files(files: &[(&str, u64)]) -> Vec<Aria2File> {
+    serde_json::from_value(serde_json::Value::Array(
+        files
+            .iter()
+            .enumerate()
+            .map(|(index, (path, length))| {
+                serde_json::json!({
+                    "index": (index + 1).to_string(),
+                    "path": format!("/srv/incoming/{path}"),
+                    "length": length.to_string(),
+                    "selected": "true",
+                })
+            })
+            .collect(),
+    ))
+    .unwrap()
+}
+
+#[test]
+fn torrent_files_are_chosen_by_size_index_or_glob() {
+    let files = torrent_files(&[
+        ("Film/Extras/trailer.MP4", 50),
+        ("Film/film.mkv", 4_000),
+        ("Film/film.nfo", 1),
+        ("Film/Sample/sample.mkv", 20),
+        ("Film/poster.png", 9_000),
+    ]);
+    let choose = |selection: FileSelection| {
+        selection
+            .choose(&files, "/srv/incoming")
+            .map(|file| file.index)
+    };
+
+    assert_eq!(choose(FileSelection::default()).unwrap(), 2);
+    let by_index = FileSelection {
+        file_index: Some(5),
+        ..Default::default()
+    };
+    assert_eq!(choose(by_index).unwrap(), 5);
+    let by_glob = |glob: &str| FileSelection {
+        file_glob: Some(glob.to_string()),
+        ..Default::default()
+    };
+    assert_eq!(choose(by_glob("film/*/*.mp4")).unwrap(), 1);
+    assert_eq!(choose(by_glob("*sample?mkv")).unwrap(), 4);
+    // Several matches: the largest wins.
+    assert_eq!(choose(by_glob("*.mkv")).unwrap(), 2);
+
+    let missing = [
+        FileSelection {
+            file_index: Some(6),
+            ..Default::default()
+        },
+        by_glob("*.avi"),
+    ];
+    for selection in missing {
+        let err = selection.choose(&files, "/srv/incoming").unwrap_err();
+        assert_eq!(err.code(), "torrent_file_not_found");
+    }
+
+    let no_video = torrent_files(&[("Album/01.flac", 30), ("Album/cover.jpg", 2)]);
+    let err = FileSelection::default()
+        .choose(&no_video, "/srv/incoming")
+        .unwrap_err();
+    assert_eq!(err.code(), "torrent_file_not_found");
+    // A single-file torrent is taken whatever it holds.
+    let single = torrent_files(&[("clip.bin", 30)]);
+    assert_eq!(
+        FileSelection::default()
+            .choose(&single, "/srv/incoming")
+            .unwrap()
+            .index,
+        1
+    );
+}
+
+#[test]
+fn file_selections_take_one_valid_criterion() {
+    assert!(FileSelection::default().validate().is_ok());
+    for invalid in [
+        FileSelection {
+            file_index: Some(1),
+            file_glob: Some("*.mkv".into()),
+        },
+        FileSelection {
+            file_index: Some(0),
+            file_glob: None,
+        },
+        FileSelection {
+            file_index: None,
+            file_glob: Some(" ".into()),
+        },
+    ] {
+        assert_eq!(
+            invalid.validate().unwrap_err().code(),
+            "file_selection_invalid",
+            "{invalid:?}"
+        );
+    }
+}
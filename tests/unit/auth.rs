@@ -0,0 +1,151 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode, header};
+use axum::response::IntoResponse;
+use jsonwebtoken::{EncodingKey, Header};
+use serde_json::{Value, json};
+use vrs::auth::{JwtAuth, JwtConfig, Scope};
+
+const SECRET: &str = "test-secret";
+
+fn auth() -> JwtAuth {
+    JwtAuth::with_config(JwtConfig {
+        secret: Some(SECRET.into()),
+        jwks_url: None,
+        issuer: Some("https://idp.example".into()),
+        audience: None,
+        scope_prefix: "vrs:".into(),
+        jwks_ttl: Duration::from_secs(300),
+    })
+}
+
+fn bearer(claims: Value) -> HeaderMap {
+    let exp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + 600;
+    let mut claims = claims;
+    claims["exp"] = exp.into();
+    claims["iss"] = "https://idp.example".into();
+    let token = jsonwebtoken::encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(SECRET.as_bytes()),
+    )
+    .unwrap();
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::AUTHORIZATION,
+        HeaderValue::from_str(&format!("Bearer {token}")).unwrap(),
+    );
+    headers
+}
+
+#[test]
+fn routes_map_to_scopes() {
+    let cases = [
+        (Method::GET, "/videos/abc/hls/master.m3u8", None),
+        (Method::GET, "/jobs/abc", None),
+        (Method::GET, "/usage", Some(Scope::Upload)),
+        (Method::GET, "/jobs/abc/diagnostics", Some(Scope::Upload)),
+        (
+            Method::GET,
+            "/jobs/abc/diagnostics/frame.jpg",
+            Some(Scope::Upload),
+        ),
+        (Method::GET, "/jobs/abc/ws", Some(Scope::Upload)),
+        (Method::POST, "/upload/multipart", Some(Scope::Upload)),
+        (Method::POST, "/download/yt-dlp", Some(Scope::Upload)),
+        (
+            Method::POST,
+            "/download/yt-dlp/playlist",
+            Some(Scope::Upload),
+        ),
+        (Method::GET, "/batches/abc", None),
+        (Method::PATCH, "/videos/abc/meta", Some(Scope::Upload)),
+        (Method::POST, "/videos/abc/access", None),
+        (Method::GET, "/videos/abc/share", Some(Scope::Upload)),
+        (Method::GET, "/s/abc/hls/master.m3u8", None),
+        (Method::PUT, "/videos/abc/password", Some(Scope::Upload)),
+        (Method::DELETE, "/videos/abc/tags/news", Some(Scope::Upload)),
+        (Method::DELETE, "/videos/abc", Some(Scope::Delete)),
+        (Method::DELETE, "/collections/abc", Some(Scope::Delete)),
+        (Method::GET, "/admin/overview", Some(Scope::Admin)),
+        (Method::GET, "/metrics", Some(Scope::Admin)),
+        (Method::DELETE, "/capabilities/failures", Some(Scope::Admin)),
+    ];
+    for (method, path, scope) in cases {
+        assert_eq!(Scope::for_route(&method, path), scope, "{method} {path}");
+    }
+}
+
+#[tokio::test]
+async fn tokens_need_the_route_scope() {
+    let client = reqwest::Client::new();
+    let auth = auth();
+
+    let err = auth
+        .authorize(&client, &HeaderMap::new(), Scope::Upload)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), "token_missing");
+    let response = err.into_response();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+
+    let uploader = bearer(json!({ "sub": "batch", "scope": "openid vrs:upload" }));
+    let claims = auth
+        .authorize(&client, &uploader, Scope::Upload)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(claims.sub.as_deref(), Some("batch"));
+    let err = auth
+        .authorize(&client, &uploader, Scope::Delete)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), "scope_missing");
+
+    let unprefixed = bearer(json!({ "scope": "upload" }));
+    assert!(
+        auth.authorize(&client, &unprefixed, Scope::Upload)
+            .await
+            .is_err()
+    );
+
+    let admin = bearer(json!({ "scp": ["vrs:admin"] }));
+    assert!(auth.authorize(&client, &admin, Scope::Delete).await.is_ok());
+
+    let mut tampered = uploader.clone();
+    let value = tampered[header::AUTHORIZATION]
+        .to_str()
+        .unwrap()
+        .to_string();
+    tampered.insert(
+        header::AUTHORIZATION,
+        HeaderValue::from_str(&format!("{value}x")).unwrap(),
+    );
+    let err = auth
+        .authorize(&client, &tampered, Scope::Upload)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), "token_invalid");
+}
+
+#[tokio::test]
+async fn auth_is_off_without_a_secret_or_key_set() {
+    let auth = JwtAuth::with_config(JwtConfig {
+        secret: None,
+        jwks_url: None,
+        issuer: None,
+        audience: None,
+        scope_prefix: String::new(),
+        jwks_ttl: Duration::from_secs(300),
+    });
+    let authorized = auth
+        .authorize(&reqwest::Client::new(), &HeaderMap::new(), Scope::Admin)
+        .await
+        .unwrap();
+    assert!(authorized.is_none());
+}
@@ -0,0 +1,83 @@
+use axum::{
+    body::{Body, to_bytes},
+    http::{HeaderMap, HeaderValue},
+    response::Response,
+};
+use tempfile::tempdir;
+use uuid::Uuid;
+use vrs::bandwidth::{self, BandwidthLedger, DeliveryKind};
+use vrs::error::AppError;
+use vrs::storage::Storage;
+
+#[tokio::test]
+async fn instances_sharing_storage_add_up() {
+    let temp = tempdir().unwrap();
+    let storage = Storage::initialize(temp.path()).await.unwrap();
+    let first = BandwidthLedger::new(storage.clone());
+    let second = BandwidthLedger::new(storage);
+    let video_id = Uuid::new_v4();
+
+    first.record(video_id, "acme", DeliveryKind::Hls, 1000);
+    first.record(video_id, "acme", DeliveryKind::Download, 24);
+    first.flush().await.unwrap();
+    second.record(video_id, "globex", DeliveryKind::Dash, 500);
+    second.record(Uuid::new_v4(), "acme", DeliveryKind::Hls, 0);
+
+    let month = second.month(&bandwidth::current_month()).await.unwrap();
+    assert_eq!(month.total.total(), 1524);
+    assert_eq!(month.videos.len(), 1);
+    let video = month.videos[&video_id];
+    assert_eq!(
+        (video.download_bytes, video.hls_bytes, video.dash_bytes),
+        (24, 1000, 500)
+    );
+    assert_eq!(month.keys["acme"].total(), 1024);
+    assert_eq!(month.keys["globex"].dash_bytes, 500);
+
+    // Flushed counts are not written twice.
+    first.flush().await.unwrap();
+    let again = first.month(&bandwidth::current_month()).await.unwrap();
+    assert_eq!(again, month);
+}
+
+#[tokio::test]
+async fn multi_part_bodies_are_charged_to_each_video_in_turn() {
+    let temp = tempdir().unwrap();
+    let ledger = BandwidthLedger::new(Storage::initialize(temp.path()).await.unwrap());
+    let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+
+    let response = ledger.meter_parts(
+        Response::new(Body::from(vec![0u8; 100])),
+        vec![(first, 30), (second, 50)],
+        &HeaderMap::new(),
+        DeliveryKind::Hls,
+    );
+    to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+    let month = ledger.month(&bandwidth::current_month()).await.unwrap();
+    assert_eq!(month.videos[&first].hls_bytes, 30);
+    // The last part takes the bytes the shares did not cover.
+    assert_eq!(month.videos[&second].hls_bytes, 70);
+    assert_eq!(month.keys["anonymous"].hls_bytes, 100);
+}
+
+#[tokio::test]
+async fn month_must_be_year_and_month() {
+    let temp = tempdir().unwrap();
+    let ledger = BandwidthLedger::new(Storage::initialize(temp.path()).await.unwrap());
+
+    assert!(ledger.month("2024-02").await.unwrap().videos.is_empty());
+    for month in ["2024-13", "2024-1", "../x", "2024_02"] {
+        let err = ledger.month(month).await.unwrap_err();
+        assert!(matches!(err.root(), AppError::Validation(_)));
+        assert_eq!(err.code(), "month_invalid");
+    }
+}
+
+#[test]
+fn requests_without_a_key_are_anonymous() {
+    let mut headers = HeaderMap::new();
+    assert_eq!(bandwidth::request_key(&headers), "anonymous");
+    headers.insert("x-tenant-id", HeaderValue::from_static(" acme "));
+    assert_eq!(bandwidth::request_key(&headers), "acme");
+}
@@ -0,0 +1,31 @@
+use std::thread;
+
+use tempfile::tempdir;
+use vrs::blocking;
+
+#[tokio::test]
+async fn tasks_run_on_dedicated_threads() {
+    let name = blocking::run(|| thread::current().name().map(str::to_string))
+        .await
+        .unwrap();
+    assert!(name.unwrap().starts_with("vrs-blocking-"));
+    assert!(blocking::stats().threads > 0);
+}
+
+#[tokio::test]
+async fn panics_are_reported_and_the_pool_keeps_working() {
+    let panicked = blocking::run(|| -> u32 { panic!("boom") }).await;
+    assert!(panicked.is_err());
+    assert_eq!(blocking::run(|| 42).await.unwrap(), 42);
+}
+
+#[tokio::test]
+async fn copy_writes_the_whole_file() {
+    let temp = tempdir().unwrap();
+    let from = temp.path().join("from.bin");
+    let to = temp.path().join("to.bin");
+    tokio::fs::write(&from, vec![7u8; 64 * 1024]).await.unwrap();
+
+    assert_eq!(blocking::copy(&from, &to).await.unwrap(), 64 * 1024);
+    assert_eq!(tokio::fs::read(&to).await.unwrap(), vec![7u8; 64 * 1024]);
+}
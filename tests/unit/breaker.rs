@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use axum::{http::StatusCode, response::IntoResponse};
+use vrs::breaker::{BreakerConfig, HostBreaker};
+use vrs::error::AppError;
+
+fn breaker(cooldown: Duration) -> HostBreaker {
+    HostBreaker::with_config(BreakerConfig {
+        failure_threshold: 3,
+        cooldown,
+    })
+}
+
+fn connection_refused() -> AppError {
+    std::io::Error::from(std::io::ErrorKind::ConnectionRefused).into()
+}
+
+#[test]
+fn repeated_failures_pause_the_host() {
+    let breaker = breaker(Duration::from_secs(120));
+    let url = "https://Mirror.example.com/video.mp4";
+    for _ in 0..2 {
+        breaker.record(url, Err(&connection_refused()));
+        breaker.admit(url).unwrap();
+    }
+    breaker.record(url, Err(&connection_refused()));
+
+    let err = breaker
+        .admit("https://mirror.example.com/other.mp4")
+        .unwrap_err();
+    assert!(err.to_string().contains("mirror.example.com"));
+    let response = err.into_response();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(response.headers().contains_key("retry-after"));
+
+    breaker
+        .admit("https://healthy.example.com/video.mp4")
+        .unwrap();
+    let report = breaker.report();
+    assert_eq!(report.len(), 1);
+    assert!(report[0].open);
+    assert_eq!(report[0].trips, 1);
+}
+
+#[test]
+fn cooldown_admits_a_probe_and_success_closes() {
+    let breaker = breaker(Duration::ZERO);
+    let url = "https://mirror.example.com/video.mp4";
+    for _ in 0..3 {
+        breaker.record(url, Err(&connection_refused()));
+    }
+    breaker.admit(url).unwrap();
+
+    breaker.record(url, Ok(()));
+    assert!(breaker.report().is_empty());
+}
+
+#[test]
+fn local_failures_do_not_count_against_the_host() {
+    let breaker = breaker(Duration::from_secs(120));
+    let url = "https://mirror.example.com/video.mp4";
+    let disk_full: AppError = std::io::Error::from(std::io::ErrorKind::StorageFull).into();
+    for _ in 0..5 {
+        breaker.record(url, Err(&disk_full));
+        breaker.record(url, Err(&AppError::cancelled("stopped")));
+    }
+    breaker.admit(url).unwrap();
+    assert!(breaker.report().is_empty());
+
+    breaker.record("magnet:?xt=urn:btih:abc", Err(&connection_refused()));
+    breaker.admit("magnet:?xt=urn:btih:abc").unwrap();
+}
@@ -0,0 +1,31 @@
+use std::time::Duration;
+
+use vrs::callbacks::ProgressCallbacks;
+
+#[test]
+fn progress_reports_are_stepped_and_back_off_from_slow_receivers() {
+    let config = ProgressCallbacks { step_percent: 10 };
+    assert!(config.enabled());
+    assert!(!ProgressCallbacks::default().enabled());
+    assert_eq!(config.steps(0.0), 0);
+    assert_eq!(config.steps(0.099), 0);
+    assert_eq!(config.steps(0.1), 1);
+    assert_eq!(config.steps(0.73), 7);
+    assert_eq!(config.steps(1.5), 10);
+
+    let fast = Duration::from_millis(50);
+    let slow = Duration::from_secs(3);
+    assert_eq!(
+        config.next_pause(Duration::ZERO, true, fast),
+        Duration::ZERO
+    );
+    let pause = config.next_pause(Duration::ZERO, true, slow);
+    assert_eq!(pause, Duration::from_secs(1));
+    let pause = config.next_pause(pause, false, fast);
+    assert_eq!(pause, Duration::from_secs(2));
+    assert_eq!(
+        config.next_pause(Duration::from_secs(50), false, fast),
+        Duration::from_secs(60)
+    );
+    assert_eq!(config.next_pause(pause, true, fast), Duration::ZERO);
+}
@@ -0,0 +1,57 @@
+use vrs::cli::{Command, Invocation, OutputFormat, Shell, completions, parse};
+
+fn args(line: &str) -> Vec<String> {
+    line.split_whitespace().map(str::to_string).collect()
+}
+
+#[test]
+fn output_format_applies_to_any_command() {
+    assert_eq!(
+        parse(args("")).unwrap(),
+        Invocation {
+            command: Command::Serve,
+            output: OutputFormat::Text,
+        }
+    );
+    assert_eq!(
+        parse(args("--output json --check")).unwrap(),
+        Invocation {
+            command: Command::Check,
+            output: OutputFormat::Json,
+        }
+    );
+    assert_eq!(
+        parse(args("transcode - --output=json")).unwrap(),
+        Invocation {
+            command: Command::Transcode { input: "-".into() },
+            output: OutputFormat::Json,
+        }
+    );
+    assert_eq!(
+        parse(args("completions zsh")).unwrap().command,
+        Command::Completions(Shell::Zsh)
+    );
+
+    for invalid in [
+        "--output yaml",
+        "transcode",
+        "completions tcsh",
+        "serve now",
+    ] {
+        let err = parse(args(invalid)).unwrap_err();
+        assert_eq!(err.code(), "cli_usage", "{invalid}");
+    }
+}
+
+#[test]
+fn completions_offer_every_command() {
+    for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+        let script = completions(shell);
+        for word in ["transcode", "completions", "--check", "json"] {
+            assert!(
+                script.contains(word.trim_start_matches('-')),
+                "{shell:?}: {word}"
+            );
+        }
+    }
+}
@@ -0,0 +1,9 @@
+use vrs::clock::rfc3339;
+
+#[test]
+fn rfc3339_formats_utc_with_milliseconds() {
+    assert_eq!(rfc3339(0), "1970-01-01T00:00:00.000Z");
+    assert_eq!(rfc3339(951_782_400_000), "2000-02-29T00:00:00.000Z");
+    assert_eq!(rfc3339(1_709_210_096_789), "2024-02-29T12:34:56.789Z");
+    assert_eq!(rfc3339(4_102_444_799_999), "2099-12-31T23:59:59.999Z");
+}
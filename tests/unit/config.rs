@@ -0,0 +1,47 @@
+use vrs::config::{ReloadReport, Reloadable, parse_config};
+
+#[test]
+fn parse_config_reads_key_value_lines() {
+    let values = parse_config(
+        "# thresholds\nVIDEO_STORAGE_MIN_FREE_BYTES = 1024\n\nVIDEO_CORS_ORIGINS=\"https://a.example\"\nmalformed\n",
+    );
+
+    assert_eq!(values.len(), 2);
+    assert_eq!(values["VIDEO_STORAGE_MIN_FREE_BYTES"], "1024");
+    assert_eq!(values["VIDEO_CORS_ORIGINS"], "https://a.example");
+}
+
+#[test]
+fn reload_report_separates_restart_only_settings() {
+    let report = ReloadReport::from_changed(vec![
+        "VIDEO_SERVER_ADDR".into(),
+        "VIDEO_STORAGE_CLEANUP_BATCH".into(),
+    ]);
+
+    assert_eq!(report.applied, vec!["VIDEO_STORAGE_CLEANUP_BATCH"]);
+    assert_eq!(report.requires_restart, vec!["VIDEO_SERVER_ADDR"]);
+}
+
+#[test]
+fn reload_report_lists_sharding_settings_as_restart_only() {
+    let keys = [
+        "VIDEO_SHARDING",
+        "VIDEO_INSTANCE_ID",
+        "VIDEO_SHARD_HEARTBEAT_SECS",
+        "VIDEO_SHARD_NODE_TTL_SECS",
+    ];
+    let report = ReloadReport::from_changed(keys.iter().map(|key| key.to_string()).collect());
+
+    assert!(report.applied.is_empty());
+    assert_eq!(report.requires_restart, keys);
+}
+
+#[test]
+fn reloadable_shares_updates_across_clones() {
+    let original = Reloadable::new(1u32);
+    let clone = original.clone();
+
+    clone.set(5);
+
+    assert_eq!(original.get(), 5);
+}
@@ -0,0 +1,63 @@
+use std::io::Write;
+
+use tempfile::tempdir;
+use tokio::io::AsyncWriteExt;
+use vrs::digest::{self, Container, DigestWriter};
+
+const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+#[test]
+fn sniffs_common_containers() {
+    let mut mp4 = vec![0, 0, 0, 0x20];
+    mp4.extend_from_slice(b"ftypisom");
+    assert_eq!(Container::sniff(&mp4), Some(Container::Mp4));
+    assert_eq!(
+        Container::sniff(&[0x1a, 0x45, 0xdf, 0xa3, 0x01]),
+        Some(Container::Matroska)
+    );
+    assert_eq!(
+        Container::sniff(b"RIFF\0\0\0\0WAVEfmt "),
+        Some(Container::Wav)
+    );
+    let mut ts = vec![0u8; 189];
+    ts[0] = 0x47;
+    ts[188] = 0x47;
+    assert_eq!(Container::sniff(&ts), Some(Container::MpegTs));
+    assert_eq!(Container::sniff(b"\x89PNG\r\n"), Some(Container::Png));
+    assert_eq!(Container::sniff(b"not a video"), None);
+    assert_eq!(Container::sniff(b""), None);
+}
+
+#[test]
+fn blocking_writes_are_counted_and_hashed() {
+    let mut writer = DigestWriter::new(Vec::new());
+    Write::write_all(&mut writer, b"a").unwrap();
+    Write::write_all(&mut writer, b"bc").unwrap();
+    let (inner, digest) = writer.into_inner();
+    assert_eq!(inner, b"abc");
+    assert_eq!(digest.bytes, 3);
+    assert_eq!(digest.sha256, ABC_SHA256);
+    assert_eq!(digest.container, None);
+}
+
+#[tokio::test]
+async fn async_writes_match_a_file_digest() {
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("source.mkv");
+    let mut writer = DigestWriter::new(tokio::fs::File::create(&path).await.unwrap());
+    writer.write_all(&[0x1a, 0x45, 0xdf, 0xa3]).await.unwrap();
+    writer.write_all(&vec![9u8; 100_000]).await.unwrap();
+    writer.flush().await.unwrap();
+    let streamed = writer.digest();
+
+    assert_eq!(streamed.bytes, 100_004);
+    assert_eq!(streamed.container, Some(Container::Matroska));
+    assert_eq!(digest::digest_file(&path).await.unwrap(), streamed);
+
+    let copy = temp.path().join("copy.mkv");
+    assert_eq!(digest::copy_file(&path, &copy).await.unwrap(), streamed);
+    assert_eq!(
+        tokio::fs::read(&copy).await.unwrap().len() as u64,
+        streamed.bytes
+    );
+}
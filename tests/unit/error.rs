@@ -1,6 +1,6 @@
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
-use vrs::error::AppError;
+use vrs::error::{AppError, ErrorClass};
 
 #[test]
 fn into_response_sets_http_status() {
@@ -13,3 +13,70 @@ fn validation_helper_formats_message() {
     let err = AppError::validation("bad value");
     assert_eq!(err.to_string(), "validation failed: bad value");
 }
+
+#[test]
+fn errors_are_classified_for_retry() {
+    let cases = [
+        (
+            AppError::transcode("bad frame"),
+            ErrorClass::SourceInvalid,
+            false,
+        ),
+        (
+            AppError::dependency("ffmpeg not found"),
+            ErrorClass::EncoderMissing,
+            false,
+        ),
+        (
+            AppError::Io(std::io::ErrorKind::StorageFull.into()),
+            ErrorClass::DiskFull,
+            true,
+        ),
+        (
+            AppError::Io(std::io::ErrorKind::TimedOut.into()),
+            ErrorClass::Network,
+            true,
+        ),
+        (AppError::cancelled("by user"), ErrorClass::Cancelled, false),
+        (
+            AppError::overloaded("busy", 5),
+            ErrorClass::Overloaded,
+            true,
+        ),
+    ];
+    for (err, class, retryable) in cases {
+        assert_eq!(err.class(), class, "{err}");
+        assert_eq!(err.is_retryable(), retryable, "{err}");
+    }
+    assert_eq!(
+        AppError::cancelled("by user").into_response().status(),
+        StatusCode::CONFLICT
+    );
+}
+
+#[tokio::test]
+async fn error_body_carries_code_and_params() {
+    let err = AppError::validation("invalid range bounds")
+        .with_code("range_invalid")
+        .with_param("max", 1234);
+    assert!(matches!(err.root(), AppError::Validation(_)));
+    assert_eq!(err.class(), ErrorClass::SourceInvalid);
+
+    let response = err.into_response();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = axum::body::to_bytes(response.into_body(), 1024)
+        .await
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["error"], "validation failed: invalid range bounds");
+    assert_eq!(json["code"], "range_invalid");
+    assert_eq!(json["params"]["max"], 1234);
+
+    let overloaded = AppError::overloaded("busy", 30).into_response();
+    let body = axum::body::to_bytes(overloaded.into_body(), 1024)
+        .await
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["code"], "overloaded");
+    assert_eq!(json["params"]["retry_after_secs"], 30);
+}
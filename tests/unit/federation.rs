@@ -0,0 +1,101 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use tempfile::tempdir;
+use uuid::Uuid;
+use vrs::{
+    federation::{Federation, FederationConfig},
+    rate_limit::RateLimit,
+    replication::parse_peer,
+    storage::Storage,
+};
+
+#[tokio::test]
+async fn lookups_skip_unreachable_peers_and_are_remembered() {
+    let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let closed_url = format!("http://{}/", closed.local_addr().unwrap());
+    drop(closed);
+
+    let video_id = Uuid::new_v4();
+    let probes = Arc::new(AtomicUsize::new(0));
+    let peer = axum::Router::new().route(
+        &format!("/media/videos/{video_id}/download"),
+        axum::routing::get({
+            let probes = probes.clone();
+            move || async move {
+                probes.fetch_add(1, Ordering::SeqCst);
+                "av1"
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let peer_url = format!("http://{}/media", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, peer).await.unwrap() });
+
+    let temp = tempdir().unwrap();
+    let storage = Storage::initialize(temp.path()).await.unwrap();
+    let client = reqwest::Client::builder().no_proxy().build().unwrap();
+    let federation = Federation::with_config(
+        storage,
+        client,
+        FederationConfig {
+            peers: vec![
+                parse_peer(&closed_url).unwrap(),
+                parse_peer(&peer_url).unwrap(),
+            ],
+            ..Default::default()
+        },
+    );
+
+    let path = format!("/videos/{video_id}/download?token=t");
+    for _ in 0..2 {
+        assert_eq!(
+            federation.locate(video_id, &path).await.unwrap().as_str(),
+            format!("{peer_url}/videos/{video_id}/download?token=t")
+        );
+    }
+    assert_eq!(probes.load(Ordering::SeqCst), 1);
+    assert_eq!(
+        federation
+            .locate(Uuid::new_v4(), "/videos/missing/download")
+            .await,
+        None
+    );
+}
+
+#[tokio::test]
+async fn probes_past_the_limit_are_not_sent() {
+    let probes = Arc::new(AtomicUsize::new(0));
+    let peer = axum::Router::new().fallback({
+        let probes = probes.clone();
+        move || async move {
+            probes.fetch_add(1, Ordering::SeqCst);
+            axum::http::StatusCode::NOT_FOUND
+        }
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let peer_url = format!("http://{}/", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, peer).await.unwrap() });
+
+    let temp = tempdir().unwrap();
+    let storage = Storage::initialize(temp.path()).await.unwrap();
+    let client = reqwest::Client::builder().no_proxy().build().unwrap();
+    let federation = Federation::with_config(
+        storage,
+        client,
+        FederationConfig {
+            peers: vec![parse_peer(&peer_url).unwrap()],
+            probe_limit: RateLimit::parse("2/hour").unwrap(),
+            ..Default::default()
+        },
+    );
+
+    for _ in 0..5 {
+        let video_id = Uuid::new_v4();
+        let path = format!("/videos/{video_id}/download");
+        assert_eq!(federation.locate(video_id, &path).await, None);
+    }
+    assert_eq!(probes.load(Ordering::SeqCst), 2);
+}
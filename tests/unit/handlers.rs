@@ -1,15 +1,20 @@
 use axum::body;
-use axum::extract::{Path as AxumPath, State};
-use axum::http::StatusCode;
+use axum::extract::{Path as AxumPath, Query, State};
+use axum::http::{HeaderMap, StatusCode};
 use std::sync::Arc;
 use tempfile::tempdir;
 use uuid::Uuid;
 use vrs::cleanup::CleanupConfig;
 use vrs::error::AppError;
-use vrs::handlers::{ClientTranscodeOptions, RangeHeader, download_video, job_status};
+use vrs::handlers::{
+    ClientTranscodeOptions, PlaybackQuery, RangeHeader, download_video, job_status,
+};
+use vrs::rate_limit::ClientAddr;
 use vrs::state::AppState;
 use vrs::storage::{Storage, ensure_parent};
-use vrs::transcode::EncodeParams;
+use vrs::transcode::{
+    EncodeParams, EncoderAvailability, EncoderCapabilities, EncoderKind, TranscodeProfile,
+};
 use vrs::{DynJobStore, JobStage, LocalJobStore};
 
 const BODY_LIMIT: usize = 1024 * 1024;
@@ -28,12 +33,7 @@ async fn build_state(root: &std::path::Path) -> AppState {
         .expect("client");
     let cleanup = CleanupConfig::from_env();
 
-    AppState {
-        storage,
-        http_client,
-        jobs,
-        cleanup,
-    }
+    AppState::new(storage, http_client, jobs, cleanup)
 }
 
 #[test]
@@ -41,16 +41,65 @@ fn client_options_override_defaults() {
     let params = encode_params_from(ClientTranscodeOptions {
         crf: Some(12),
         cpu_used: Some(2),
+        profile: Some(TranscodeProfile::Compat),
+        ..ClientTranscodeOptions::default()
     });
     assert_eq!(params.crf, 12);
     assert_eq!(params.cpu_used, 2);
+    assert_eq!(params.profile, TranscodeProfile::Compat);
 
     let sanitized = encode_params_from(ClientTranscodeOptions {
         crf: Some(80),
         cpu_used: Some(99),
+        profile: None,
+        ..ClientTranscodeOptions::default()
     });
     assert_eq!(sanitized.crf, 63);
     assert_eq!(sanitized.cpu_used, 8);
+
+    let capped = encode_params_from(ClientTranscodeOptions {
+        max_height: Some(720),
+        max_bitrate_kbps: Some(0),
+        low_rungs: Some(true),
+        ..ClientTranscodeOptions::default()
+    });
+    assert_eq!(capped.ladder.max_height, Some(720));
+    assert_eq!(capped.ladder.max_bitrate_kbps, None);
+    assert_eq!(capped.ladder.low_rungs, Some(true));
+}
+
+#[test]
+fn requested_encoder_must_be_usable() {
+    let options: ClientTranscodeOptions = serde_json::from_str(r#"{"encoder":"nvenc"}"#).unwrap();
+    assert_eq!(
+        encode_params_from(options).encoder,
+        Some(EncoderKind::NvencAv1)
+    );
+
+    let encoder = |kind: EncoderKind, available, blacklisted| EncoderAvailability {
+        name: kind.label(),
+        codec: kind.ffmpeg_codec(),
+        available,
+        blacklisted,
+    };
+    let capabilities = EncoderCapabilities {
+        ffmpeg_available: true,
+        encoders: vec![
+            encoder(EncoderKind::NvencAv1, true, true),
+            encoder(EncoderKind::VaapiAv1, false, false),
+            encoder(EncoderKind::SoftwareAv1, true, false),
+        ],
+        failures: Vec::new(),
+    };
+    assert!(capabilities.ensure_usable(EncoderKind::SoftwareAv1).is_ok());
+    for kind in [
+        EncoderKind::NvencAv1,
+        EncoderKind::VaapiAv1,
+        EncoderKind::QsvAv1,
+    ] {
+        let err = capabilities.ensure_usable(kind).unwrap_err();
+        assert_eq!(err.code(), "encoder_unavailable");
+    }
 }
 
 #[tokio::test]
@@ -67,6 +116,9 @@ async fn download_video_supports_range_requests() -> Result<(), AppError> {
         State(state.clone()),
         AxumPath(id.to_string()),
         RangeHeader::new(Some("bytes=0-4".to_string())),
+        HeaderMap::new(),
+        ClientAddr(None),
+        Query(PlaybackQuery::default()),
     )
     .await?;
 
@@ -96,6 +148,9 @@ async fn download_video_rejects_invalid_ids() {
         State(state),
         AxumPath("not-a-uuid".to_string()),
         RangeHeader::new(None),
+        HeaderMap::new(),
+        ClientAddr(None),
+        Query(PlaybackQuery::default()),
     )
     .await;
 
@@ -109,7 +164,9 @@ async fn job_status_returns_not_found() {
 
     let response = job_status(State(state.clone()), AxumPath(Uuid::new_v4().to_string())).await;
 
-    assert!(matches!(response, Err(AppError::NotFound(_))));
+    let err = response.unwrap_err();
+    assert!(matches!(err.root(), AppError::NotFound(_)));
+    assert_eq!(err.code(), "job_not_found");
 }
 
 #[tokio::test]
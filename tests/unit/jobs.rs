@@ -1,5 +1,6 @@
+use std::time::{Duration, SystemTime};
 use uuid::Uuid;
-use vrs::error::AppError;
+use vrs::error::{AppError, ErrorClass};
 use vrs::jobs::JobStore;
 use vrs::{JobStage, LocalJobStore};
 
@@ -19,6 +20,10 @@ async fn local_job_store_lifecycle() -> Result<(), AppError> {
     assert_eq!(initial.stage_progress, 0.0);
     assert_eq!(initial.current_stage_index, None);
     assert_eq!(initial.total_stages, 2);
+    assert_eq!(
+        initial.started_at,
+        vrs::clock::rfc3339(initial.started_at_unix_ms)
+    );
 
     store.update_stage(id, JobStage::Downloading).await?;
     store.update_progress(id, 0.5).await?;
@@ -49,10 +54,13 @@ async fn local_job_store_lifecycle() -> Result<(), AppError> {
     assert!((transcoding.progress - 0.7).abs() < f32::EPSILON);
     assert_eq!(transcoding.current_stage_index, Some(2));
 
-    store.fail(id, "network".into()).await?;
+    let network = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+    store.fail(id, &AppError::Io(network)).await?;
     let failed = store.status(&id).await?.expect("job missing after fail");
     assert_eq!(failed.stage, JobStage::Failed);
-    assert_eq!(failed.error.as_deref(), Some("network"));
+    assert_eq!(failed.error.as_deref(), Some("connection reset"));
+    assert_eq!(failed.error_class, Some(ErrorClass::Network));
+    assert_eq!(failed.is_retryable, Some(true));
     assert!((failed.progress - failed.stage_progress).abs() < f32::EPSILON);
 
     store.complete(id).await?;
@@ -95,6 +103,33 @@ async fn update_stage_resets_progress() -> Result<(), AppError> {
     Ok(())
 }
 
+#[tokio::test]
+async fn client_data_survives_a_reset() -> Result<(), AppError> {
+    let store = LocalJobStore::new();
+    let id = Uuid::new_v4();
+    let data = serde_json::json!({ "ticket": 42 });
+
+    store.create_job(id).await?;
+    assert!(
+        store
+            .status(&id)
+            .await?
+            .expect("missing job")
+            .client_data
+            .is_none()
+    );
+    store.set_client_data(id, data.clone()).await?;
+    store.fail(id, &AppError::validation("bad input")).await?;
+    store.reset(id).await?;
+
+    let status = store.status(&id).await?.expect("missing job");
+    assert_eq!(status.client_data, Some(data));
+    let json = serde_json::to_value(&status).unwrap();
+    assert_eq!(json["client_data"]["ticket"], 42);
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn progress_handles_stage_missing_from_plan() -> Result<(), AppError> {
     let store = LocalJobStore::new();
@@ -133,3 +168,193 @@ async fn stage_eta_reflected_in_response() -> Result<(), AppError> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn stage_timings_record_finished_stages() -> Result<(), AppError> {
+    let store = LocalJobStore::new();
+    let id = Uuid::new_v4();
+
+    store.create_job(id).await?;
+    store.update_stage(id, JobStage::Downloading).await?;
+    store.update_stage(id, JobStage::Transcoding).await?;
+    store.complete(id).await?;
+
+    let since = SystemTime::now() - Duration::from_secs(60);
+    let stages: Vec<JobStage> = store
+        .stage_timings(since)
+        .await?
+        .into_iter()
+        .map(|timing| timing.stage)
+        .collect();
+    assert_eq!(
+        stages,
+        vec![
+            JobStage::Queued,
+            JobStage::Downloading,
+            JobStage::Transcoding
+        ]
+    );
+
+    let future = SystemTime::now() + Duration::from_secs(60);
+    assert!(store.stage_timings(future).await?.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn job_groups_aggregate_child_progress() -> Result<(), AppError> {
+    let store = LocalJobStore::new();
+    let root = Uuid::new_v4();
+    let captions = Uuid::new_v4();
+
+    store.create_job(root).await?;
+    store
+        .set_plan(root, vec![JobStage::Downloading, JobStage::Transcoding])
+        .await?;
+    store.add_child(root, captions, "captions").await?;
+    store.complete(root).await?;
+    store.update_stage(captions, JobStage::Transcoding).await?;
+    store.update_progress(captions, 0.5).await?;
+
+    let group = store.group_status(&root).await?.expect("group status");
+    assert_eq!(group.stage, JobStage::Transcoding);
+    assert_eq!(group.members.len(), 2);
+    assert_eq!(group.members[1].name, "captions");
+    assert_eq!(group.members[1].status.parent_id, Some(root));
+    // Pipeline weighs two planned stages at 100%, captions one at 50%.
+    assert!((group.progress - 2.5 / 3.0).abs() < 1e-4);
+
+    store
+        .fail(captions, &AppError::transcode("no speech"))
+        .await?;
+    let group = store.group_status(&root).await?.expect("group status");
+    assert_eq!(group.stage, JobStage::Failed);
+
+    store.reset(captions).await?;
+    store.complete(captions).await?;
+    let group = store.group_status(&root).await?.expect("group status");
+    assert_eq!(group.stage, JobStage::Complete);
+    assert_eq!(group.progress, 1.0);
+
+    store.remove(root).await?;
+    assert!(store.group_status(&root).await?.is_none());
+    assert!(store.status(&captions).await?.is_none());
+
+    Ok(())
+}
+
+/// Runs against the server in `VIDEO_TEST_REDIS_URL`; skipped when unset.
+#[cfg(feature = "redis")]
+#[tokio::test]
+async fn redis_job_store_shares_state_between_instances() -> Result<(), AppError> {
+    use vrs::jobs::RedisJobStore;
+
+    let Ok(url) = std::env::var("VIDEO_TEST_REDIS_URL") else {
+        return Ok(());
+    };
+    let writer = RedisJobStore::connect(&url).await?;
+    let reader = RedisJobStore::connect(&url).await?;
+    let id = Uuid::new_v4();
+    let child = Uuid::new_v4();
+
+    writer.create_job(id).await?;
+    writer
+        .set_plan(id, vec![JobStage::Downloading, JobStage::Transcoding])
+        .await?;
+    writer.update_stage(id, JobStage::Transcoding).await?;
+    writer.update_progress(id, 0.5).await?;
+    writer.update_stage_eta(id, Some(30.0)).await?;
+    writer.add_child(id, child, "rendition").await?;
+
+    let status = reader.status(&id).await?.expect("job missing on reader");
+    assert_eq!(status.stage, JobStage::Transcoding);
+    assert!((status.progress - 0.75).abs() < f32::EPSILON);
+    assert_eq!(status.estimated_remaining_seconds, Some(30.0));
+    let group = reader.group_status(&id).await?.expect("group missing");
+    assert_eq!(group.members.len(), 2);
+    assert_eq!(group.members[1].status.parent_id, Some(id));
+
+    // Writes from two instances at once both land.
+    let (first, second) = tokio::join!(
+        writer.set_source_file(id, "clip.mp4"),
+        reader.update_stage_eta(id, Some(10.0)),
+    );
+    first?;
+    second?;
+    let status = reader.status(&id).await?.expect("job missing");
+    assert_eq!(status.source_file.as_deref(), Some("clip.mp4"));
+    assert_eq!(status.estimated_remaining_seconds, Some(10.0));
+
+    // A cancel asked of the instance holding the job lasts until it releases it.
+    assert!(writer.claim(id, "a", &[]).await?);
+    reader.request_cancel(id).await?;
+    assert!(writer.cancel_requests().await?.contains(&id));
+    writer.release(id, "a").await?;
+    assert!(!reader.cancel_requests().await?.contains(&id));
+
+    writer.complete(id).await?;
+    let complete = reader.status(&id).await?.expect("job missing");
+    assert_eq!(complete.stage, JobStage::Complete);
+    assert!(reader.list().await?.iter().any(|job| job.id == id));
+    let since = SystemTime::now() - Duration::from_secs(60);
+    assert!(
+        reader
+            .stage_timings(since)
+            .await?
+            .iter()
+            .any(|timing| timing.stage == JobStage::Transcoding)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn ytdlp_format_options_build_selector_and_sort() {
+    use vrs::jobs::{PreferredCodec, YtDlpOptions};
+
+    let defaults = YtDlpOptions::default();
+    assert_eq!(defaults.format_args(), ["-f", "bv*+ba/b"]);
+
+    let capped = YtDlpOptions {
+        max_height: Some(1080),
+        prefer_codec: Some(PreferredCodec::Av1),
+        ..YtDlpOptions::default()
+    };
+    capped.validate().unwrap();
+    assert_eq!(
+        capped.format_args(),
+        [
+            "-f",
+            "bv*[height<=1080]+ba/b[height<=1080]/bv*+ba/b",
+            "-S",
+            "res:1080,vcodec:av01",
+        ]
+    );
+
+    let raw = YtDlpOptions {
+        format: Some("bv*[vcodec^=avc1]+ba".to_string()),
+        prefer_codec: Some(PreferredCodec::H264),
+        ..YtDlpOptions::default()
+    };
+    assert_eq!(
+        raw.format_args(),
+        ["-f", "bv*[vcodec^=avc1]+ba", "-S", "res,vcodec:h264"]
+    );
+
+    let too_small = YtDlpOptions {
+        max_height: Some(10),
+        ..YtDlpOptions::default()
+    };
+    assert_eq!(
+        too_small.validate().unwrap_err().code(),
+        "ytdlp_max_height_invalid"
+    );
+    let multiline = YtDlpOptions {
+        format: Some("best\n--exec rm".to_string()),
+        ..YtDlpOptions::default()
+    };
+    assert_eq!(
+        multiline.validate().unwrap_err().code(),
+        "ytdlp_format_invalid"
+    );
+}
@@ -0,0 +1,65 @@
+use std::env;
+
+use tempfile::tempdir;
+use uuid::Uuid;
+use vrs::error::AppError;
+use vrs::locks::LockManager;
+
+static ENV_MUTEX: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+#[tokio::test]
+async fn file_leases_exclude_other_instances_until_released() {
+    let temp = tempdir().unwrap();
+    let first = LockManager::new(temp.path());
+    let second = LockManager::new(temp.path());
+    let key = LockManager::video_key(&Uuid::new_v4(), "hls");
+
+    let _lock = ENV_MUTEX.lock().await;
+    unsafe {
+        env::set_var("VIDEO_LOCK_BACKEND", "file");
+        env::set_var("VIDEO_LOCK_WAIT_SECS", "1");
+    }
+    let held = first.acquire(&key).await.unwrap();
+    let contended = second.acquire(&key).await;
+    drop(held);
+    let reacquired = second.acquire(&key).await;
+    unsafe {
+        env::remove_var("VIDEO_LOCK_BACKEND");
+        env::remove_var("VIDEO_LOCK_WAIT_SECS");
+    }
+
+    assert!(matches!(contended, Err(AppError::Dependency(_))));
+    assert!(reacquired.is_ok());
+}
+
+#[tokio::test]
+async fn expired_leases_are_broken() {
+    let temp = tempdir().unwrap();
+    let manager = LockManager::new(temp.path());
+    let key = LockManager::video_key(&Uuid::new_v4(), "dash");
+    let locks_dir = temp.path().join("locks");
+    tokio::fs::create_dir_all(&locks_dir).await.unwrap();
+    tokio::fs::write(
+        locks_dir.join(format!("{key}.lock")),
+        r#"{"owner":"crashed-instance","expires_at_unix_ms":0}"#,
+    )
+    .await
+    .unwrap();
+
+    let _lock = ENV_MUTEX.lock().await;
+    unsafe {
+        env::set_var("VIDEO_LOCK_BACKEND", "file");
+        env::set_var("VIDEO_LOCK_WAIT_SECS", "2");
+    }
+    let acquired = manager.acquire(&key).await;
+    unsafe {
+        env::remove_var("VIDEO_LOCK_BACKEND");
+        env::remove_var("VIDEO_LOCK_WAIT_SECS");
+    }
+
+    assert!(acquired.is_ok());
+    let lease = tokio::fs::read_to_string(locks_dir.join(format!("{key}.lock")))
+        .await
+        .unwrap();
+    assert!(!lease.contains("crashed-instance"));
+}
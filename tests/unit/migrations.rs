@@ -0,0 +1,37 @@
+use tempfile::tempdir;
+use vrs::error::AppError;
+use vrs::migrations::{current_version, latest_version, pending, run};
+use vrs::storage::Storage;
+
+#[tokio::test]
+async fn migrations_apply_once_and_record_the_version() {
+    let temp = tempdir().unwrap();
+    let storage = Storage::initialize(temp.path()).await.unwrap();
+    assert_eq!(current_version(&storage).await.unwrap(), 0);
+    assert!(!pending(&storage).await.unwrap().is_empty());
+
+    let applied = run(&storage).await.unwrap();
+    assert_eq!(applied.last().unwrap().version, latest_version());
+    assert_eq!(current_version(&storage).await.unwrap(), latest_version());
+    assert!(storage.root_dir().join("shares").is_dir());
+
+    assert!(run(&storage).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn newer_storage_schema_is_refused() {
+    let temp = tempdir().unwrap();
+    let storage = Storage::initialize(temp.path()).await.unwrap();
+    let newer = latest_version() + 1;
+    tokio::fs::write(
+        storage.root_dir().join("schema_version.json"),
+        format!(r#"{{"version":{newer},"updated_at_unix_ms":0}}"#),
+    )
+    .await
+    .unwrap();
+
+    assert!(matches!(
+        run(&storage).await,
+        Err(AppError::Validation(message)) if message.contains("newer")
+    ));
+}
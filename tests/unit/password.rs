@@ -0,0 +1,23 @@
+use std::net::IpAddr;
+
+use uuid::Uuid;
+use vrs::password::PasswordAttempts;
+
+#[test]
+fn one_clients_lockout_does_not_block_another() {
+    let attempts = PasswordAttempts::default();
+    let video_id = Uuid::new_v4();
+    let guesser: IpAddr = "192.0.2.1".parse().unwrap();
+    let viewer: IpAddr = "192.0.2.2".parse().unwrap();
+
+    for _ in 0..5 {
+        attempts.check(&video_id, Some(guesser)).unwrap();
+        attempts.record_failure(&video_id, Some(guesser));
+    }
+    let err = attempts.check(&video_id, Some(guesser)).unwrap_err();
+    assert_eq!(err.code(), "rate_limited");
+
+    attempts.check(&video_id, Some(viewer)).unwrap();
+    attempts.check(&video_id, None).unwrap();
+    attempts.check(&Uuid::new_v4(), Some(guesser)).unwrap();
+}
@@ -0,0 +1,149 @@
+use vrs::playlist::{
+    CodecFamily, VariantFilter, absolutize_playlist, add_session_data, append_query_to_mpd,
+    append_query_to_playlist, concat_media_playlists, filter_master_playlist, negotiate_codecs,
+    parse_attributes, select_variant,
+};
+
+const MASTER: &str = "#EXTM3U
+#EXT-X-VERSION:7
+#EXT-X-STREAM-INF:BANDWIDTH=6000000,RESOLUTION=1920x1080,CODECS=\"av01.0.08M.08,mp4a.40.2\"
+stream_1080p.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=3000000,RESOLUTION=1280x720,CODECS=\"avc1.64001f,mp4a.40.2\"
+stream_720p.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=1000000,RESOLUTION=640x360
+stream_360p.m3u8
+";
+
+#[test]
+fn attributes_keep_quoted_commas() {
+    let attributes =
+        parse_attributes("BANDWIDTH=1,CODECS=\"avc1.64001f,mp4a.40.2\",RESOLUTION=1x2");
+    assert_eq!(
+        attributes,
+        vec![
+            ("BANDWIDTH".to_string(), "1".to_string()),
+            ("CODECS".to_string(), "avc1.64001f,mp4a.40.2".to_string()),
+            ("RESOLUTION".to_string(), "1x2".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn max_height_drops_taller_variants() {
+    let filter = VariantFilter::from_query(Some(720), None).unwrap();
+    let filtered = filter_master_playlist(MASTER, &filter, None).unwrap();
+
+    assert!(!filtered.contains("stream_1080p.m3u8"));
+    assert!(filtered.contains("stream_720p.m3u8"));
+    assert!(filtered.contains("stream_360p.m3u8"));
+    assert!(filtered.starts_with("#EXTM3U\n#EXT-X-VERSION:7\n"));
+}
+
+#[test]
+fn codec_filter_uses_fallback_for_variants_without_codecs() {
+    let filter = VariantFilter::from_query(None, Some("h264")).unwrap();
+
+    let filtered = filter_master_playlist(MASTER, &filter, Some(CodecFamily::Av1)).unwrap();
+    assert_eq!(filtered.matches("#EXT-X-STREAM-INF").count(), 1);
+    assert!(filtered.contains("stream_720p.m3u8"));
+
+    let filtered = filter_master_playlist(MASTER, &filter, Some(CodecFamily::H264)).unwrap();
+    assert!(filtered.contains("stream_360p.m3u8"));
+}
+
+#[test]
+fn unmatched_filters_are_rejected() {
+    assert!(VariantFilter::from_query(None, Some("theora")).is_err());
+
+    let filter = VariantFilter::from_query(Some(144), Some("vp9")).unwrap();
+    assert!(filter_master_playlist(MASTER, &filter, None).is_err());
+}
+
+#[test]
+fn session_token_is_appended_to_every_uri() {
+    let variant = "#EXTM3U\n#EXT-X-MAP:URI=\"init_0.m4s\"\n#EXTINF:4.0,\nsegment_0_00000.m4s\n#EXT-X-ENDLIST\n";
+    let rewritten = append_query_to_playlist(variant, "token=1.ab");
+    assert!(rewritten.contains("#EXT-X-MAP:URI=\"init_0.m4s?token=1.ab\""));
+    assert!(rewritten.contains("\nsegment_0_00000.m4s?token=1.ab\n"));
+    assert!(rewritten.ends_with("#EXT-X-ENDLIST\n"));
+
+    let master = append_query_to_playlist(MASTER, "token=1.ab");
+    assert!(master.contains("stream_720p.m3u8?token=1.ab"));
+
+    let mpd = "<SegmentTemplate timescale=\"1\" initialization=\"init_$RepresentationID$.m4s\" media=\"chunk_$Number$.m4s\"/>";
+    let rewritten = append_query_to_mpd(mpd, "token=1.ab");
+    assert!(rewritten.contains("initialization=\"init_$RepresentationID$.m4s?token=1.ab\""));
+    assert!(rewritten.contains(" media=\"chunk_$Number$.m4s?token=1.ab\""));
+}
+
+#[test]
+fn offline_playlists_resolve_every_uri() {
+    let url = url::Url::parse("https://cdn.example.com/videos/v/hls/720p/index.m3u8").unwrap();
+    let variant = "#EXTM3U\n#EXT-X-MAP:URI=\"init.m4s\"\n#EXTINF:4.0,\nseg_0.m4s\n#EXT-X-ENDLIST\n";
+    let rewritten = absolutize_playlist(variant, &url, Some("token=1.ab"));
+    assert!(rewritten.contains(
+        "#EXT-X-MAP:URI=\"https://cdn.example.com/videos/v/hls/720p/init.m4s?token=1.ab\""
+    ));
+    assert!(
+        rewritten.contains("\nhttps://cdn.example.com/videos/v/hls/720p/seg_0.m4s?token=1.ab\n")
+    );
+    let bare = absolutize_playlist(variant, &url, None);
+    assert!(bare.contains("\nhttps://cdn.example.com/videos/v/hls/720p/seg_0.m4s\n"));
+
+    let master = add_session_data(MASTER, "com.vrs.expires", "1736965234");
+    assert!(master.starts_with(
+        "#EXTM3U\n#EXT-X-SESSION-DATA:DATA-ID=\"com.vrs.expires\",VALUE=\"1736965234\"\n#EXT-X-VERSION:7\n"
+    ));
+}
+
+#[test]
+fn negotiation_maps_clients_to_codec_sets() {
+    let safari = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/16.6 Safari/605.1.15";
+    let chrome = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36";
+    let old_chrome = "Mozilla/5.0 (Windows NT 6.1) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/49.0.2623.112 Safari/537.36";
+
+    assert_eq!(
+        negotiate_codecs(Some(safari), None),
+        Some(vec![CodecFamily::H264, CodecFamily::Hevc])
+    );
+    assert!(
+        negotiate_codecs(Some(chrome), None)
+            .unwrap()
+            .contains(&CodecFamily::Av1)
+    );
+    assert_eq!(
+        negotiate_codecs(Some(old_chrome), None),
+        Some(vec![CodecFamily::H264])
+    );
+    assert_eq!(negotiate_codecs(Some("curl/8.0"), None), None);
+    assert_eq!(
+        negotiate_codecs(
+            Some(safari),
+            Some("application/vnd.apple.mpegurl; codecs=\"av01.0.08M.08\"")
+        ),
+        Some(vec![CodecFamily::Av1])
+    );
+}
+
+#[test]
+fn variant_selection_respects_max_height() {
+    assert_eq!(select_variant(MASTER, None), Some("stream_1080p.m3u8"));
+    assert_eq!(select_variant(MASTER, Some(720)), Some("stream_720p.m3u8"));
+    assert_eq!(select_variant(MASTER, Some(240)), None);
+}
+
+#[test]
+fn media_playlists_concatenate_with_discontinuities() {
+    let first = "#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXT-X-MAP:URI=\"init.mp4\"\n#EXTINF:4.0,\nseg_0.m4s\n#EXT-X-ENDLIST\n";
+    let second =
+        "#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXTINF:6.0,\nseg_0.ts?token=t\n#EXT-X-ENDLIST\n";
+    let joined = concat_media_playlists([("a/", first), ("b/", second)]);
+
+    assert!(joined.starts_with("#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-TARGETDURATION:6\n"));
+    assert!(joined.contains(
+        "#EXT-X-MAP:URI=\"a/init.mp4\"\n#EXTINF:4.0,\na/seg_0.m4s\n#EXT-X-DISCONTINUITY\n"
+    ));
+    assert!(joined.contains("b/seg_0.ts?token=t\n"));
+    assert_eq!(joined.matches("#EXT-X-ENDLIST").count(), 1);
+    assert!(joined.ends_with("#EXT-X-ENDLIST\n"));
+}
@@ -0,0 +1,130 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tempfile::tempdir;
+use vrs::cleanup::CleanupConfig;
+use vrs::error::AppError;
+use vrs::policy::{IngestPolicy, PolicyDecision, PolicyRequest, Verdict};
+use vrs::transcode::SimulatedMediaRunner;
+use vrs::{AppState, DynJobStore, JobStage, LocalJobStore, Storage, VideoService};
+
+struct MaxHeightPolicy(u32);
+
+impl IngestPolicy for MaxHeightPolicy {
+    fn evaluate(&self, request: &PolicyRequest) -> Result<PolicyDecision, AppError> {
+        if request.probe.height > self.0 {
+            return Ok(PolicyDecision {
+                decision: Verdict::Reject,
+                reason: Some(format!("taller than {}p", self.0)),
+                transcode: None,
+            });
+        }
+        Ok(PolicyDecision::accept())
+    }
+}
+
+#[tokio::test]
+async fn rejecting_policy_fails_the_job_with_its_reason() -> Result<(), AppError> {
+    let temp = tempdir().expect("tempdir");
+    let source = temp.path().join("input.mp4");
+    tokio::fs::write(&source, b"source").await?;
+
+    let storage = Storage::initialize(temp.path().join("store")).await?;
+    let jobs: DynJobStore = Arc::new(LocalJobStore::new());
+    let state = AppState::new(
+        storage,
+        reqwest::Client::new(),
+        jobs,
+        CleanupConfig::from_env(),
+    )
+    .with_process_runner(Arc::new(SimulatedMediaRunner::new(Duration::from_millis(
+        100,
+    ))))
+    .with_policy(Arc::new(MaxHeightPolicy(720)));
+    let service = VideoService::from_state(state);
+
+    let id = service.ingest_file(&source, None).await?;
+    let status = service.await_job(id).await?;
+
+    assert_eq!(status.stage, JobStage::Failed);
+    let error = status.error.unwrap_or_default();
+    assert!(
+        error.contains("rejected by ingest policy: taller than 720p"),
+        "{error}"
+    );
+    assert!(!service.download_path(id).exists());
+
+    Ok(())
+}
+
+#[cfg(feature = "wasm-policy")]
+mod wasm {
+    use vrs::policy::{IngestPolicy, PolicyRequest, PolicyTranscode, Verdict, WasmPolicy};
+    use vrs::transcode::{SourceProbe, TranscodeProfile};
+
+    use super::*;
+
+    const DECISION: &str = r#"{"decision":"accept","transcode":{"crf":40,"profile":"compat"}}"#;
+
+    fn module(validate_body: &str) -> String {
+        format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (data (i32.const 0) "{}")
+                (func (export "alloc") (param i32) (result i32) i32.const 1024)
+                (func (export "validate") (param i32 i32) (result i64) {validate_body}))"#,
+            DECISION.replace('"', "\\22")
+        )
+    }
+
+    fn request() -> PolicyRequest {
+        PolicyRequest {
+            video_id: uuid::Uuid::new_v4(),
+            source: Some("https://example.com/clip.mp4".into()),
+            probe: SourceProbe {
+                width: 1920,
+                height: 1080,
+                duration_seconds: Some(12.5),
+                frame_rate: Some("30".into()),
+                has_audio: true,
+            },
+            transcode: PolicyTranscode {
+                crf: 24,
+                cpu_used: 4,
+                profile: TranscodeProfile::Standard,
+            },
+        }
+    }
+
+    #[test]
+    fn wasm_policy_returns_decision_and_overrides() -> Result<(), AppError> {
+        let policy =
+            WasmPolicy::from_bytes(module(&format!("i64.const {}", DECISION.len())).as_bytes())?;
+
+        let decision = policy.evaluate(&request())?;
+        assert_eq!(decision.decision, Verdict::Accept);
+        let params = decision.apply(vrs::transcode::EncodeParams::default())?;
+        assert_eq!(params.crf, 40);
+        assert_eq!(params.cpu_used, 4);
+        assert_eq!(params.profile, TranscodeProfile::Compat);
+
+        Ok(())
+    }
+
+    #[test]
+    fn wasm_policy_traps_and_runaway_loops_are_errors() -> Result<(), AppError> {
+        let trapping = WasmPolicy::from_bytes(module("unreachable").as_bytes())?;
+        assert!(matches!(
+            trapping.evaluate(&request()),
+            Err(AppError::Dependency(_))
+        ));
+
+        let looping = WasmPolicy::from_bytes(module("(loop (br 0)) i64.const 0").as_bytes())?;
+        assert!(matches!(
+            looping.evaluate(&request()),
+            Err(AppError::Dependency(_))
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+use std::{net::IpAddr, time::Duration};
+
+use axum::{
+    extract::ConnectInfo,
+    http::{Extensions, HeaderMap},
+};
+use vrs::rate_limit::{RateLimit, RateLimitConfig, RateLimiter};
+
+#[test]
+fn rate_limits_parse_count_and_period() {
+    assert_eq!(
+        RateLimit::parse("30/min"),
+        Some(RateLimit {
+            burst: 30,
+            period: Duration::from_secs(60),
+        })
+    );
+    assert_eq!(
+        RateLimit::parse(" 5 / s ").map(|limit| limit.period),
+        Some(Duration::from_secs(1))
+    );
+    assert_eq!(RateLimit::parse("0/min"), None);
+    assert_eq!(RateLimit::parse("30/fortnight"), None);
+    assert_eq!(RateLimit::parse("30"), None);
+}
+
+#[test]
+fn buckets_limit_addresses_and_keys_separately() {
+    let limiter = RateLimiter::new(RateLimitConfig {
+        per_ip: RateLimit::parse("2/hour"),
+        per_key: RateLimit::parse("3/hour"),
+        trust_forwarded_for: false,
+        trusted_proxies: 1,
+    });
+    let first: IpAddr = "192.0.2.1".parse().unwrap();
+    let second: IpAddr = "192.0.2.2".parse().unwrap();
+
+    limiter.check(Some(first), "acme").unwrap();
+    limiter.check(Some(first), "acme").unwrap();
+    let err = limiter.check(Some(first), "acme").unwrap_err();
+    assert_eq!(err.code(), "rate_limited");
+    assert_eq!(err.params()["limit"], "ip");
+    assert_eq!(err.params()["retry_after_secs"], 1800);
+
+    // The refused request took nothing from the key's bucket.
+    limiter.check(Some(second), "acme").unwrap();
+    let err = limiter.check(Some(second), "acme").unwrap_err();
+    assert_eq!(err.params()["limit"], "key");
+
+    // Callers without a key are only limited by address.
+    limiter.check(Some(second), "anonymous").unwrap();
+    limiter.check(None, "anonymous").unwrap();
+}
+
+#[test]
+fn reconfigured_limits_apply_to_existing_buckets() {
+    let limiter = RateLimiter::new(RateLimitConfig {
+        per_ip: RateLimit::parse("5/hour"),
+        ..RateLimitConfig::default()
+    });
+    let ip: IpAddr = "192.0.2.1".parse().unwrap();
+    limiter.check(Some(ip), "anonymous").unwrap();
+
+    limiter.reconfigure(RateLimitConfig {
+        per_ip: RateLimit::parse("1/hour"),
+        ..RateLimitConfig::default()
+    });
+    limiter.check(Some(ip), "anonymous").unwrap();
+    let err = limiter.check(Some(ip), "anonymous").unwrap_err();
+    assert_eq!(err.params()["limit"], "ip");
+
+    limiter.reconfigure(RateLimitConfig::default());
+    limiter.check(Some(ip), "anonymous").unwrap();
+}
+
+#[test]
+fn forwarded_addresses_are_counted_from_the_nearest_proxy() {
+    let config = |trusted_proxies| RateLimitConfig {
+        trust_forwarded_for: true,
+        trusted_proxies,
+        ..RateLimitConfig::default()
+    };
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "x-forwarded-for",
+        "203.0.113.9, 192.0.2.1, 10.0.0.2".parse().unwrap(),
+    );
+    let mut extensions = Extensions::new();
+    extensions.insert(ConnectInfo(
+        "10.0.0.1:4000".parse::<std::net::SocketAddr>().unwrap(),
+    ));
+    let ip = |limiter: &RateLimiter| limiter.client_ip(&headers, &extensions);
+
+    // The leftmost entry is whatever the client sent.
+    assert_eq!(
+        ip(&RateLimiter::new(config(1))),
+        Some("10.0.0.2".parse().unwrap())
+    );
+    assert_eq!(
+        ip(&RateLimiter::new(config(2))),
+        Some("192.0.2.1".parse().unwrap())
+    );
+    // Fewer hops than proxies means the header did not come through them.
+    assert_eq!(
+        ip(&RateLimiter::new(config(4))),
+        Some("10.0.0.1".parse().unwrap())
+    );
+    assert_eq!(
+        ip(&RateLimiter::new(RateLimitConfig::default())),
+        Some("10.0.0.1".parse().unwrap())
+    );
+
+    // A proxy that adds a header line of its own still counts as a hop.
+    let mut headers = HeaderMap::new();
+    headers.append("x-forwarded-for", "198.51.100.7".parse().unwrap());
+    headers.append("x-forwarded-for", "203.0.113.9".parse().unwrap());
+    assert_eq!(
+        RateLimiter::new(config(1)).client_ip(&headers, &extensions),
+        Some("203.0.113.9".parse().unwrap())
+    );
+    assert_eq!(
+        RateLimiter::new(config(2)).client_ip(&headers, &extensions),
+        Some("198.51.100.7".parse().unwrap())
+    );
+}
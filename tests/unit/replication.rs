@@ -0,0 +1,19 @@
+use vrs::replication::parse_peer;
+
+#[test]
+fn peer_urls_keep_their_path_prefix() {
+    let peer = parse_peer("https://vrs.eu.example.com/media?x=1").unwrap();
+    assert_eq!(peer.as_str(), "https://vrs.eu.example.com/media/");
+    assert_eq!(
+        peer.join("admin/import/x").unwrap().as_str(),
+        "https://vrs.eu.example.com/media/admin/import/x"
+    );
+    assert_eq!(
+        parse_peer("http://10.0.0.2:3000").unwrap().as_str(),
+        "http://10.0.0.2:3000/"
+    );
+    for invalid in ["ftp://peer/", "peer.example.com", "file:///srv"] {
+        let err = parse_peer(invalid).unwrap_err();
+        assert_eq!(err.code(), "replication_peer_invalid", "{invalid}");
+    }
+}
@@ -0,0 +1,25 @@
+use std::time::Duration;
+
+use vrs::{error::AppError, retry::RetryPolicy};
+
+#[test]
+fn only_transient_failures_are_retried_with_backoff() {
+    let policy = RetryPolicy {
+        retries: 3,
+        backoff: Duration::from_secs(10),
+        ..RetryPolicy::default()
+    };
+    let reset: AppError = std::io::Error::from(std::io::ErrorKind::ConnectionReset).into();
+    assert_eq!(policy.next_delay(&reset, 1), Some(Duration::from_secs(10)));
+    assert_eq!(policy.next_delay(&reset, 3), Some(Duration::from_secs(40)));
+    assert_eq!(policy.next_delay(&reset, 4), None);
+
+    let stalled = AppError::transcode("ffmpeg produced no output").with_code("ffmpeg_stalled");
+    assert!(policy.is_transient(&stalled));
+    assert!(policy.is_transient(&stalled.with_param("position_seconds", 12.5)));
+    assert!(!policy.is_transient(&AppError::transcode("ffmpeg exited with status 1")));
+    assert!(!policy.is_transient(&AppError::validation("not media")));
+
+    let disabled = RetryPolicy::default();
+    assert_eq!(disabled.next_delay(&reset, 1), None);
+}
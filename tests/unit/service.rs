@@ -0,0 +1,271 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tempfile::tempdir;
+use uuid::Uuid;
+use vrs::cleanup::CleanupConfig;
+use vrs::error::{AppError, ErrorClass};
+use vrs::metadata;
+use vrs::process::{ScriptedProcessRunner, ScriptedResponse};
+use vrs::transcode::SimulatedMediaRunner;
+use vrs::{
+    AppState, DynJobStore, HookContext, HookPoint, JobStage, LocalJobStore, PipelineHook, Storage,
+    VideoService,
+};
+
+#[tokio::test]
+async fn ingest_file_keeps_original_and_reports_failure() -> Result<(), AppError> {
+    let temp = tempdir().expect("tempdir");
+    let scripted = Arc::new(ScriptedProcessRunner::new());
+    scripted.expect("ffprobe", ScriptedResponse::exit(1));
+    let storage = Storage::initialize(temp.path().join("store")).await?;
+    let jobs: DynJobStore = Arc::new(LocalJobStore::new());
+    let state = AppState::new(
+        storage,
+        reqwest::Client::new(),
+        jobs.clone(),
+        CleanupConfig::from_env(),
+    )
+    .with_process_runner(scripted);
+    let service = VideoService::from_state(state);
+    let source = temp.path().join("input.bin");
+    tokio::fs::write(&source, b"not a video").await?;
+
+    let rejected = service.ingest_file(&source, None).await.unwrap_err();
+    assert_eq!(rejected.code(), "source_not_media");
+    let failed: Vec<_> = jobs
+        .list()
+        .await?
+        .into_iter()
+        .filter(|job| job.parent_id.is_none())
+        .collect();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].stage, JobStage::Failed);
+    assert!(failed[0].error.is_some());
+    assert!(source.exists());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn ingest_rejects_missing_input_and_invalid_urls() -> Result<(), AppError> {
+    let temp = tempdir().expect("tempdir");
+    let service = VideoService::new(temp.path()).await?;
+
+    let missing = service
+        .ingest_file(temp.path().join("missing.mp4"), None)
+        .await;
+    assert!(matches!(missing, Err(AppError::NotFound(_))));
+
+    let invalid = service.ingest_url("not a url", None).await;
+    assert!(matches!(invalid, Err(AppError::Validation(_))));
+
+    let unknown = service.open_hls_dir(Uuid::new_v4()).await;
+    assert!(matches!(unknown, Err(AppError::NotFound(_))));
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct RecordingHook {
+    points: Mutex<Vec<HookPoint>>,
+    reject_ingest: bool,
+}
+
+#[async_trait]
+impl PipelineHook for RecordingHook {
+    fn name(&self) -> &str {
+        "recording"
+    }
+
+    async fn pre_ingest(&self, ctx: &HookContext) -> Result<(), AppError> {
+        self.points.lock().unwrap().push(ctx.point);
+        if self.reject_ingest {
+            return Err(AppError::validation("rejected by policy"));
+        }
+        Ok(())
+    }
+
+    async fn post_download(&self, ctx: &HookContext) -> Result<(), AppError> {
+        assert!(ctx.media_path.as_ref().is_some_and(|path| path.exists()));
+        self.points.lock().unwrap().push(ctx.point);
+        Ok(())
+    }
+
+    async fn post_encode(&self, ctx: &HookContext) -> Result<(), AppError> {
+        self.points.lock().unwrap().push(ctx.point);
+        Ok(())
+    }
+
+    async fn pre_publish(&self, ctx: &HookContext) -> Result<(), AppError> {
+        self.points.lock().unwrap().push(ctx.point);
+        Ok(())
+    }
+}
+
+async fn hooked_service(root: &Path, hook: Arc<RecordingHook>) -> Result<VideoService, AppError> {
+    let storage = Storage::initialize(root).await?;
+    let jobs: DynJobStore = Arc::new(LocalJobStore::new());
+    let state = AppState::new(
+        storage,
+        reqwest::Client::new(),
+        jobs,
+        CleanupConfig::from_env(),
+    )
+    .with_process_runner(Arc::new(SimulatedMediaRunner::new(Duration::from_millis(
+        300,
+    ))))
+    .with_hook(hook);
+    Ok(VideoService::from_state(state))
+}
+
+#[tokio::test]
+async fn pipeline_hooks_run_at_each_stage_boundary() -> Result<(), AppError> {
+    let temp = tempdir().expect("tempdir");
+    let source = temp.path().join("input.mp4");
+    tokio::fs::write(&source, b"source").await?;
+
+    let hook = Arc::new(RecordingHook::default());
+    let service = hooked_service(&temp.path().join("store"), hook.clone()).await?;
+    let id = service.ingest_file(&source, None).await?;
+    let status = service.await_job(id).await?;
+
+    assert_eq!(status.stage, JobStage::Complete);
+    assert_eq!(
+        *hook.points.lock().unwrap(),
+        vec![
+            HookPoint::PreIngest,
+            HookPoint::PostDownload,
+            HookPoint::PostEncode,
+            HookPoint::PrePublish,
+        ]
+    );
+
+    let rejecting = Arc::new(RecordingHook {
+        reject_ingest: true,
+        ..RecordingHook::default()
+    });
+    let service = hooked_service(&temp.path().join("rejecting"), rejecting).await?;
+    let rejected = service.ingest_file(&source, None).await;
+    assert!(matches!(rejected, Err(AppError::Validation(_))));
+
+    Ok(())
+}
+
+fn simulated_service(storage: Storage, encode: Duration) -> VideoService {
+    let jobs: DynJobStore = Arc::new(LocalJobStore::new());
+    let state = AppState::new(
+        storage,
+        reqwest::Client::new(),
+        jobs,
+        CleanupConfig::from_env(),
+    )
+    .with_process_runner(Arc::new(SimulatedMediaRunner::new(encode)));
+    VideoService::from_state(state)
+}
+
+async fn wait_for_stage(service: &VideoService, id: Uuid, stage: JobStage) -> Result<(), AppError> {
+    while service.job_status(id).await?.stage != stage {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn cancel_job_stops_the_running_pipeline() -> Result<(), AppError> {
+    let temp = tempdir().expect("tempdir");
+    let source = temp.path().join("input.mp4");
+    tokio::fs::write(&source, b"source").await?;
+
+    let storage = Storage::initialize(temp.path().join("store")).await?;
+    let service = simulated_service(storage, Duration::from_secs(60));
+    let id = service.ingest_file(&source, None).await?;
+    wait_for_stage(&service, id, JobStage::Transcoding).await?;
+
+    let status = tokio::time::timeout(Duration::from_secs(5), service.cancel_job(id))
+        .await
+        .expect("cancel returns before the encode would finish")?;
+    assert_eq!(status.stage, JobStage::Failed);
+    assert_eq!(status.error_class, Some(ErrorClass::Cancelled));
+    assert!(!service.download_path(id).exists());
+
+    let again = service.cancel_job(id).await.unwrap_err();
+    assert_eq!(again.code(), "job_finished");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn ingest_records_the_source_digest() -> Result<(), AppError> {
+    let temp = tempdir().expect("tempdir");
+    let source = temp.path().join("input.mp4");
+    tokio::fs::write(&source, b"abc").await?;
+
+    let storage = Storage::initialize(temp.path().join("store")).await?;
+    let service = simulated_service(storage.clone(), Duration::from_millis(100));
+    let id = service.ingest_file(&source, None).await?;
+    service.await_job(id).await?;
+
+    let digest = metadata::load(&storage, &id)
+        .await?
+        .source
+        .expect("source digest stored");
+    assert_eq!(digest.bytes, 3);
+    assert_eq!(
+        digest.sha256,
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn ingest_reader_spools_a_stream_into_the_pipeline() -> Result<(), AppError> {
+    let temp = tempdir().expect("tempdir");
+    let storage = Storage::initialize(temp.path().join("store")).await?;
+    let service = simulated_service(storage.clone(), Duration::from_millis(100));
+
+    let (mut writer, reader) = tokio::io::duplex(4);
+    tokio::spawn(async move {
+        use tokio::io::AsyncWriteExt;
+        writer.write_all(b"abc").await.unwrap();
+    });
+    let id = service.ingest_reader(reader, Some("stdin"), None).await?;
+    let status = service.await_job(id).await?;
+    assert_eq!(status.stage, JobStage::Complete);
+    let digest = metadata::load(&storage, &id)
+        .await?
+        .source
+        .expect("source digest stored");
+    assert_eq!(digest.bytes, 3);
+    assert!(service.download_path(id).exists());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn retry_job_reruns_a_failed_upload_under_the_same_id() -> Result<(), AppError> {
+    let temp = tempdir().expect("tempdir");
+    let source = temp.path().join("input.mp4");
+    tokio::fs::write(&source, b"source").await?;
+
+    let storage = Storage::initialize(temp.path().join("store")).await?;
+    let service = simulated_service(storage, Duration::from_secs(1));
+    let id = service.ingest_file(&source, None).await?;
+    let running = service.retry_job(id).await.unwrap_err();
+    assert_eq!(running.code(), "job_not_failed");
+
+    wait_for_stage(&service, id, JobStage::Transcoding).await?;
+    service.cancel_job(id).await?;
+
+    let status = service.retry_job(id).await?;
+    assert_eq!(status.id, id);
+    assert!(status.error.is_none());
+    let status = service.await_job(id).await?;
+    assert_eq!(status.stage, JobStage::Complete);
+    assert!(service.download_path(id).exists());
+
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use std::time::{Duration, Instant};
+
+use vrs::shaping::{IngestShaper, ShapingConfig};
+use vrs::usage::QuotaLimit;
+
+fn shaper(spec: &str) -> IngestShaper {
+    IngestShaper::with_config(ShapingConfig {
+        bytes_per_sec: QuotaLimit::parse(spec),
+    })
+}
+
+#[tokio::test]
+async fn transfers_of_a_key_share_its_budget() {
+    let shaper = shaper("bulk=1000000,*=0");
+    let first = shaper.begin("bulk");
+    let second = shaper.begin("bulk");
+    assert_eq!(first.limit(), Some(1_000_000));
+    assert_eq!(first.share(), Some(500_000));
+
+    // A second of budget is available at once; the rest is paid in time,
+    // whichever transfer draws it.
+    let started = Instant::now();
+    first.consume(1_000_000).await;
+    assert!(started.elapsed() < Duration::from_millis(150));
+    second.consume(300_000).await;
+    assert!(started.elapsed() >= Duration::from_millis(250));
+
+    // Keys without a budget are only counted.
+    let other = shaper.begin("interactive");
+    assert_eq!(other.limit(), None);
+    let started = Instant::now();
+    other.consume(50_000_000).await;
+    assert!(started.elapsed() < Duration::from_millis(150));
+}
+
+#[tokio::test]
+async fn report_shows_running_transfers_and_totals() {
+    let shaper = shaper("2000000");
+    let upload = shaper.begin("acme");
+    let download = upload.clone();
+    upload.consume(1_000).await;
+    download.record(4_000);
+    let other = shaper.begin("globex");
+    drop(other);
+
+    let report = shaper.report();
+    assert_eq!(report.len(), 2);
+    let acme = &report[0];
+    assert_eq!(acme.key, "acme");
+    assert_eq!(acme.limit_bytes_per_sec, Some(2_000_000));
+    assert_eq!(acme.active_transfers, 1);
+    assert_eq!(acme.total_bytes, 5_000);
+    assert_eq!(acme.bytes_per_sec, 500);
+    assert_eq!(report[1].key, "globex");
+    assert_eq!(report[1].active_transfers, 0);
+
+    drop((upload, download));
+    assert_eq!(shaper.report()[0].active_transfers, 0);
+}
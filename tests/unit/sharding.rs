@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use uuid::Uuid;
+use vrs::{
+    jobs::{JobStore, LocalJobStore},
+    sharding::HashRing,
+};
+
+#[test]
+fn ring_owners_ignore_instance_order_and_move_only_from_removed_instances() {
+    let ids: Vec<Uuid> = (0..1000).map(|_| Uuid::new_v4()).collect();
+    let ring = HashRing::new(["a", "b", "c"]);
+    let reordered = HashRing::new(["c", "a", "b"]);
+    let shrunk = HashRing::new(["a", "c"]);
+
+    let mut owned = std::collections::HashMap::<&str, usize>::new();
+    for id in &ids {
+        let owner = ring.owner(id).unwrap();
+        assert_eq!(reordered.owner(id), Some(owner));
+        if owner != "b" {
+            assert_eq!(shrunk.owner(id), Some(owner));
+        }
+        *owned.entry(owner).or_default() += 1;
+    }
+    for instance in ["a", "b", "c"] {
+        assert!(owned[instance] > 200, "{instance} owns {owned:?}");
+    }
+    assert_eq!(HashRing::new(Vec::<String>::new()).owner(&ids[0]), None);
+}
+
+#[tokio::test]
+async fn claims_hold_until_released_or_the_instance_is_gone() {
+    let jobs = LocalJobStore::new();
+    let id = Uuid::new_v4();
+    jobs.create_job(id).await.unwrap();
+    jobs.heartbeat("a", Duration::from_secs(60)).await.unwrap();
+    jobs.heartbeat("b", Duration::from_millis(50))
+        .await
+        .unwrap();
+    let mut live = jobs.live_instances().await.unwrap();
+    live.sort();
+    assert_eq!(live, ["a", "b"]);
+
+    assert!(jobs.claim(id, "b", &live).await.unwrap());
+    assert!(jobs.claim(id, "b", &live).await.unwrap());
+    assert!(!jobs.claim(id, "a", &live).await.unwrap());
+    jobs.release(id, "a").await.unwrap();
+    let status = jobs.status(&id).await.unwrap().unwrap();
+    assert_eq!(status.instance.as_deref(), Some("b"));
+
+    tokio::time::sleep(Duration::from_millis(80)).await;
+    let live = jobs.live_instances().await.unwrap();
+    assert_eq!(live, ["a"]);
+    assert!(jobs.claim(id, "a", &live).await.unwrap());
+    jobs.release(id, "a").await.unwrap();
+    assert_eq!(jobs.status(&id).await.unwrap().unwrap().instance, None);
+    assert!(!jobs.claim(Uuid::new_v4(), "a", &live).await.unwrap());
+}
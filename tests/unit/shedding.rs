@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use axum::{http::StatusCode, response::IntoResponse};
+use tempfile::tempdir;
+use vrs::error::AppError;
+use vrs::shedding::{LoadShedder, SheddingConfig};
+use vrs::storage::Storage;
+
+fn config(min_free_bytes: u64) -> SheddingConfig {
+    SheddingConfig {
+        min_free_bytes,
+        min_free_ratio: 0.0,
+        max_load_per_cpu: None,
+        retry_after_secs: 42,
+        max_transcodes: Some(3),
+        shed_transcodes: 1,
+        boost_progress: 0.9,
+    }
+}
+
+#[tokio::test]
+async fn low_disk_rejects_ingest_with_retry_after() {
+    let temp = tempdir().unwrap();
+    let storage = Storage::initialize(temp.path()).await.unwrap();
+    let shedder = LoadShedder::with_config(config(u64::MAX));
+
+    let err = shedder.admit(&storage).await.unwrap_err();
+    assert!(matches!(err, AppError::Overloaded { .. }));
+    let response = err.into_response();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()["retry-after"], "42");
+
+    let report = shedder.report(&storage).await;
+    assert!(report.overloaded);
+    assert_eq!(report.transcode_limit, Some(1));
+}
+
+#[tokio::test]
+async fn overload_narrows_transcode_concurrency() {
+    let temp = tempdir().unwrap();
+    let storage = Storage::initialize(temp.path()).await.unwrap();
+
+    let healthy = LoadShedder::with_config(config(0));
+    healthy.admit(&storage).await.unwrap();
+    let _first = healthy.transcode_slot(&storage, 0.0).await;
+    let _second = healthy.transcode_slot(&storage, 0.0).await;
+    assert_eq!(healthy.report(&storage).await.active_transcodes, 2);
+
+    let shedding = LoadShedder::with_config(config(u64::MAX));
+    let first = shedding.transcode_slot(&storage, 0.0).await;
+    let waiting = tokio::time::timeout(
+        Duration::from_millis(200),
+        shedding.transcode_slot(&storage, 0.0),
+    )
+    .await;
+    assert!(waiting.is_err());
+
+    drop(first);
+    let next = tokio::time::timeout(
+        Duration::from_secs(2),
+        shedding.transcode_slot(&storage, 0.0),
+    )
+    .await;
+    assert!(next.is_ok());
+}
+
+#[tokio::test]
+async fn nearly_done_jobs_get_contended_slots_first() {
+    let temp = tempdir().unwrap();
+    let storage = Storage::initialize(temp.path()).await.unwrap();
+    let shedder = LoadShedder::with_config(config(u64::MAX));
+
+    let held = shedder.transcode_slot(&storage, 0.0).await;
+    let fresh = tokio::spawn({
+        let shedder = shedder.clone();
+        let storage = storage.clone();
+        async move { shedder.transcode_slot(&storage, 0.1).await }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let nearly_done = tokio::spawn({
+        let shedder = shedder.clone();
+        let storage = storage.clone();
+        async move { shedder.transcode_slot(&storage, 0.95).await }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let report = shedder.report(&storage).await;
+    assert_eq!(report.waiting_transcodes, 2);
+    assert_eq!(report.boosted_waiting, 1);
+
+    drop(held);
+    let boosted = tokio::time::timeout(Duration::from_secs(2), nearly_done)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!fresh.is_finished());
+    assert_eq!(shedder.report(&storage).await.boosted_grants, 1);
+
+    drop(boosted);
+    tokio::time::timeout(Duration::from_secs(2), fresh)
+        .await
+        .unwrap()
+        .unwrap();
+}
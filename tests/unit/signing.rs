@@ -0,0 +1,56 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use uuid::Uuid;
+use vrs::error::AppError;
+use vrs::signing::PlaybackSigner;
+
+#[test]
+fn issued_tokens_verify_for_their_video_only() {
+    let signer = PlaybackSigner::new("secret").with_ttl(Duration::from_secs(60));
+    let video_id = Uuid::new_v4();
+    let token = signer.issue(&video_id);
+
+    assert!(signer.verify(&video_id, Some(&token)).is_ok());
+    assert!(matches!(
+        signer.verify(&Uuid::new_v4(), Some(&token)),
+        Err(AppError::Forbidden(_))
+    ));
+    assert!(matches!(
+        PlaybackSigner::new("other").verify(&video_id, Some(&token)),
+        Err(AppError::Forbidden(_))
+    ));
+    assert!(matches!(
+        signer.verify(&video_id, None),
+        Err(AppError::Forbidden(_))
+    ));
+
+    let (token, expires) = signer.issue_for(&video_id, Duration::from_secs(7 * 24 * 60 * 60));
+    assert!(token.starts_with(&format!("{expires}.")));
+    assert!(
+        expires
+            > signer
+                .issue(&video_id)
+                .split_once('.')
+                .unwrap()
+                .0
+                .parse()
+                .unwrap()
+    );
+    assert!(signer.verify(&video_id, Some(&token)).is_ok());
+}
+
+#[test]
+fn expired_or_malformed_tokens_are_rejected() {
+    let signer = PlaybackSigner::new("secret");
+    let video_id = Uuid::new_v4();
+    let past = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        - 10;
+
+    let expired = signer.sign(&video_id, past);
+    assert!(signer.verify(&video_id, Some(&expired)).is_err());
+    assert!(signer.verify(&video_id, Some("not-a-token")).is_err());
+    assert!(signer.verify(&video_id, Some("123.zz")).is_err());
+}
@@ -0,0 +1,48 @@
+use vrs::skip_segments::{SkipCategory, SkipSegment, annotate_media_playlist, from_sponsorblock};
+
+#[test]
+fn sponsorblock_chapters_become_skip_segments() {
+    let chapters = serde_json::json!([
+        { "start_time": 300.0, "end_time": 320.5, "category": "outro", "type": "skip" },
+        { "start_time": 12.0, "end_time": 12.0, "category": "poi_highlight", "type": "poi" },
+        { "start_time": 40.0, "end_time": 75.25, "category": "sponsor", "type": "skip" },
+        { "start_time": 80.0, "end_time": 90.0, "category": "chapter", "type": "chapter" },
+    ]);
+    assert_eq!(
+        from_sponsorblock(&chapters),
+        vec![
+            SkipSegment {
+                start: 40.0,
+                end: 75.25,
+                category: SkipCategory::Sponsor,
+            },
+            SkipSegment {
+                start: 300.0,
+                end: 320.5,
+                category: SkipCategory::Outro,
+            },
+        ]
+    );
+    assert!(from_sponsorblock(&serde_json::Value::Null).is_empty());
+}
+
+#[test]
+fn media_playlists_get_dated_skip_ranges() {
+    let playlist = "#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXT-X-MAP:URI=\"init_0.m4s\"\n#EXTINF:4.0,\nsegment_0_00000.m4s\n";
+    let segments = [SkipSegment {
+        start: 3725.0,
+        end: 3730.0,
+        category: SkipCategory::Intro,
+    }];
+    assert_eq!(
+        annotate_media_playlist(playlist, &segments),
+        "#EXTM3U\n#EXT-X-TARGETDURATION:4\n\
+         #EXT-X-PROGRAM-DATE-TIME:1970-01-01T00:00:00.000Z\n\
+         #EXT-X-DATERANGE:ID=\"skip-0\",CLASS=\"com.newspicel.vrs.skip\",\
+         START-DATE=\"1970-01-01T01:02:05.000Z\",DURATION=5.000,X-CATEGORY=\"intro\"\n\
+         #EXT-X-MAP:URI=\"init_0.m4s\"\n#EXTINF:4.0,\nsegment_0_00000.m4s\n"
+    );
+
+    let dated = "#EXTM3U\n#EXT-X-PROGRAM-DATE-TIME:2026-01-01T00:00:00.000Z\n#EXTINF:4.0,\na.ts\n";
+    assert_eq!(annotate_media_playlist(dated, &segments), dated);
+}
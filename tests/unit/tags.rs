@@ -0,0 +1,149 @@
+use std::{
+    env,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use tempfile::tempdir;
+use uuid::Uuid;
+use vrs::cleanup::CleanupConfig;
+use vrs::error::AppError;
+use vrs::jobs::{DynJobStore, LocalJobStore};
+use vrs::metadata;
+use vrs::replication::ReplicationEvent;
+use vrs::state::AppState;
+use vrs::storage::{Storage, ensure_parent};
+use vrs::tags::{add_tags, enforce_retention, list_videos, normalize_tag, remove_tag};
+
+static ENV_MUTEX: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+async fn store_video(storage: &Storage, bytes: usize, age: Duration) -> Uuid {
+    let id = Uuid::new_v4();
+    let path = storage.download_path(&id);
+    ensure_parent(&path).await.unwrap();
+    tokio::fs::write(&path, vec![0u8; bytes]).await.unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(SystemTime::now() - age)
+        .unwrap();
+    id
+}
+
+#[test]
+fn tags_are_normalized() {
+    assert_eq!(normalize_tag("  Season:1 ").unwrap(), "season:1");
+    assert!(normalize_tag("two words").is_err());
+    assert!(normalize_tag("").is_err());
+}
+
+#[tokio::test]
+async fn tag_quota_limits_total_tagged_bytes() {
+    let temp = tempdir().unwrap();
+    let storage = Storage::initialize(temp.path()).await.unwrap();
+    let first = store_video(&storage, 600, Duration::ZERO).await;
+    let second = store_video(&storage, 600, Duration::ZERO).await;
+
+    let _lock = ENV_MUTEX.lock().await;
+    unsafe { env::set_var("VIDEO_TAG_QUOTA_BYTES", "client-a=1000") };
+    let added = add_tags(&storage, &first, &["Client-A".to_string()]).await;
+    let refused = add_tags(&storage, &second, &["client-a".to_string()]).await;
+    unsafe { env::remove_var("VIDEO_TAG_QUOTA_BYTES") };
+
+    assert!(added.unwrap().contains("client-a"));
+    let refused = refused.unwrap_err();
+    assert!(matches!(refused.root(), AppError::Forbidden(_)));
+    assert_eq!(refused.code(), "tag_quota_exceeded");
+    assert_eq!(refused.params()["quota_bytes"], 1000);
+    let tagged = list_videos(&storage, Some("client-a")).await.unwrap();
+    assert_eq!(tagged.len(), 1);
+    assert_eq!(tagged[0].id, first);
+
+    assert!(
+        remove_tag(&storage, &first, "client-a")
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(
+        list_videos(&storage, Some("client-a"))
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn listings_leave_out_attributes_of_protected_videos() {
+    let temp = tempdir().unwrap();
+    let storage = Storage::initialize(temp.path()).await.unwrap();
+    let open = store_video(&storage, 10, Duration::ZERO).await;
+    let protected = store_video(&storage, 10, Duration::ZERO).await;
+    for (id, password_hash) in [(open, None), (protected, Some("hash".to_string()))] {
+        let meta = metadata::VideoMetadata {
+            tags: ["client-a".to_string()].into(),
+            attributes: [("order".to_string(), serde_json::json!(42))].into(),
+            password_hash,
+            ..Default::default()
+        };
+        metadata::save(&storage, &id, &meta).await.unwrap();
+    }
+
+    let tagged = list_videos(&storage, Some("client-a")).await.unwrap();
+    let attributes = |id| {
+        &tagged
+            .iter()
+            .find(|video| video.id == id)
+            .unwrap()
+            .attributes
+    };
+    assert_eq!(attributes(open)["order"], 42);
+    assert!(attributes(protected).is_empty());
+}
+
+#[tokio::test]
+async fn retention_deletes_expired_tagged_videos() {
+    let temp = tempdir().unwrap();
+    let storage = Storage::initialize(temp.path()).await.unwrap();
+    let jobs: DynJobStore = Arc::new(LocalJobStore::new());
+    let state = AppState::new(
+        storage.clone(),
+        reqwest::Client::new(),
+        jobs.clone(),
+        CleanupConfig::from_env(),
+    );
+    let mut events = state.replication.subscribe();
+    let day = Duration::from_secs(24 * 3600);
+    let expired = store_video(&storage, 10, day * 3).await;
+    let busy = store_video(&storage, 10, day * 3).await;
+    let fresh = store_video(&storage, 10, Duration::ZERO).await;
+    let untagged = store_video(&storage, 10, day * 3).await;
+    for id in [expired, busy, fresh] {
+        add_tags(&storage, &id, &["scratch".to_string()])
+            .await
+            .unwrap();
+    }
+    let link = state
+        .shares
+        .create(expired, Duration::from_secs(3600), None)
+        .await
+        .unwrap();
+    jobs.create_job(busy).await.unwrap();
+
+    let _lock = ENV_MUTEX.lock().await;
+    unsafe { env::set_var("VIDEO_TAG_RETENTION_DAYS", "scratch=2") };
+    let deleted = enforce_retention(&state).await;
+    unsafe { env::remove_var("VIDEO_TAG_RETENTION_DAYS") };
+
+    assert_eq!(deleted.unwrap(), 1);
+    assert!(!storage.video_dir(&expired).exists());
+    assert!(storage.video_dir(&busy).exists());
+    assert!(storage.video_dir(&fresh).exists());
+    assert!(storage.video_dir(&untagged).exists());
+    assert!(state.shares.resolve(&link.share_id, None).await.is_err());
+    assert_eq!(
+        events.try_recv().unwrap(),
+        ReplicationEvent::Deleted { video_id: expired }
+    );
+}
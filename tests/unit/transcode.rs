@@ -1,13 +1,58 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
 use tempfile::tempdir;
 use uuid::Uuid;
 use vrs::error::AppError;
+use vrs::jobs::{DynJobStore, JobStage, LocalJobStore};
+use vrs::metadata::{self, VideoMetadata};
+use vrs::metrics;
+use vrs::process::{
+    DynProcessRunner, ScriptedProcessRunner, ScriptedResponse, SystemProcessRunner,
+};
 use vrs::storage::{self, Storage};
-use vrs::transcode::ensure_hls_ready;
+use vrs::transcode::{
+    Av1Tune, Av1Tuning, EncodeParams, MezzanineCodec, SimulatedMediaRunner, StoryboardLayout,
+    TranscodeProfile, append_query_to_storyboard, ensure_hls_ready, probe_media_info,
+    process_video, render_stills, run_optional_stages,
+};
+
+/// Serialises every test that runs the pipeline, since some of them set
+/// `VIDEO_*` variables it reads.
+static ENV_MUTEX: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+fn last_arg(args: &[OsString]) -> PathBuf {
+    PathBuf::from(args.last().expect("ffmpeg args"))
+}
+
+/// Mimics ffmpeg writing its packaging outputs next to the last argument.
+fn write_packaging_outputs(args: &[OsString]) {
+    let target = last_arg(args);
+    let name = target.file_name().unwrap().to_string_lossy().into_owned();
+    if name == "stream_%v.m3u8" {
+        std::fs::write(target.with_file_name("index.m3u8"), b"#EXTM3U\n").unwrap();
+    } else {
+        std::fs::write(&target, b"<MPD/>").unwrap();
+        // 12.5 kB over the scripted 10 s duration.
+        std::fs::write(target.with_file_name("chunk_0_00001.m4s"), [0; 12_500]).unwrap();
+    }
+}
+
+fn video_codec(args: &[String]) -> Option<&str> {
+    args.iter()
+        .position(|arg| arg == "-c:v")
+        .and_then(|idx| args.get(idx + 1))
+        .map(String::as_str)
+}
 
 #[tokio::test]
 async fn ensure_hls_ready_backfills_master_playlist() -> Result<(), AppError> {
+    let _env = ENV_MUTEX.lock().await;
     let temp = tempdir().expect("tempdir");
     let storage = Storage::initialize(temp.path()).await?;
+    let runner: DynProcessRunner = Arc::new(SystemProcessRunner);
     let video_id = Uuid::new_v4();
 
     // Pretend the download already completed so ensure_hls_ready will skip re-encoding.
@@ -22,7 +67,7 @@ async fn ensure_hls_ready_backfills_master_playlist() -> Result<(), AppError> {
     let master = hls_dir.join("master.m3u8");
     assert!(!master.exists());
 
-    ensure_hls_ready(&storage, &video_id).await?;
+    ensure_hls_ready(&storage, &runner, &video_id).await?;
 
     assert!(master.exists());
     let master_contents = tokio::fs::read(&master).await?;
@@ -31,3 +76,897 @@ async fn ensure_hls_ready_backfills_master_playlist() -> Result<(), AppError> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn process_video_falls_back_after_encoder_failure() -> Result<(), AppError> {
+    let _env = ENV_MUTEX.lock().await;
+    let temp = tempdir().expect("tempdir");
+    let storage = Storage::initialize(temp.path()).await?;
+    let jobs: DynJobStore = Arc::new(LocalJobStore::new());
+    let id = Uuid::new_v4();
+    jobs.create_job(id).await?;
+    jobs.update_stage(id, JobStage::Transcoding).await?;
+
+    let input = temp.path().join("input.mp4");
+    tokio::fs::write(&input, b"source").await?;
+
+    let scripted = Arc::new(ScriptedProcessRunner::new());
+    scripted
+        .expect("ffprobe", ScriptedResponse::success())
+        .expect("ffprobe", ScriptedResponse::success().stdout("10.0\n"))
+        .expect("ffprobe", ScriptedResponse::success())
+        .expect("ffprobe", ScriptedResponse::success().stdout("1280x720\n"))
+        .expect(
+            "ffmpeg",
+            ScriptedResponse::exit(1).stderr("Error: no device\n"),
+        )
+        .expect(
+            "ffmpeg",
+            ScriptedResponse::success()
+                .stderr("frame=10 time=00:00:05.00 bitrate=1k speed=2.0x\n")
+                .effect(|args| std::fs::write(last_arg(args), b"webm").unwrap()),
+        )
+        .expect(
+            "ffmpeg",
+            ScriptedResponse::success().effect(write_packaging_outputs),
+        )
+        .expect(
+            "ffmpeg",
+            ScriptedResponse::success().effect(write_packaging_outputs),
+        );
+    let runner: DynProcessRunner = scripted.clone();
+
+    let summary = process_video(&storage, &jobs, &runner, &id, &input, None).await?;
+
+    assert_eq!(tokio::fs::read(storage.download_path(&id)).await?, b"webm");
+    assert!(storage.hls_dir(&id).join("master.m3u8").exists());
+    assert!(storage.dash_dir(&id).join("manifest.mpd").exists());
+    assert!(!input.exists());
+
+    let encodes: Vec<_> = scripted
+        .calls()
+        .into_iter()
+        .filter(|call| call.program == "ffmpeg")
+        .take(2)
+        .collect();
+    assert_ne!(video_codec(&encodes[0].args), video_codec(&encodes[1].args));
+    assert_eq!(
+        Some(summary.encoder.as_str()),
+        video_codec(&encodes[1].args)
+    );
+    assert_eq!(summary.source_bytes, 6);
+    assert_eq!(summary.download_bytes, 4);
+    assert_eq!(summary.compression_ratio, Some(1.5));
+    assert_eq!(summary.renditions[0].name, "720p");
+    assert_eq!(summary.renditions[0].encoder, "libaom-av1");
+    assert_eq!(summary.renditions[0].bitrate_kbps, Some(10));
+    assert!(summary.renditions[0].target_bitrate_kbps > 10);
+    assert!(summary.hls_bytes > 0);
+
+    let failed = video_codec(&encodes[0].args).unwrap();
+    assert!(metrics::counter("vrs_ffmpeg_failures_total", &[("encoder", failed)]) >= 1);
+    assert!(metrics::counter("vrs_ffmpeg_spawns_total", &[("encoder", &summary.encoder)]) >= 1);
+    let exported = metrics::render();
+    assert!(exported.contains(&format!(
+        "vrs_ffmpeg_encode_speed_bucket{{encoder=\"{}\",resolution=\"source\",le=\"2\"}}",
+        summary.encoder
+    )));
+
+    let status = jobs.status(&id).await?.expect("job status");
+    assert_eq!(status.stage, JobStage::Finalizing);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn process_video_encodes_configured_mezzanine_codec() -> Result<(), AppError> {
+    let _env = ENV_MUTEX.lock().await;
+    let temp = tempdir().expect("tempdir");
+    let storage = Storage::initialize(temp.path()).await?;
+    let jobs: DynJobStore = Arc::new(LocalJobStore::new());
+    let id = Uuid::new_v4();
+    jobs.create_job(id).await?;
+    jobs.update_stage(id, JobStage::Transcoding).await?;
+
+    let input = temp.path().join("input.mp4");
+    tokio::fs::write(&input, b"source").await?;
+
+    let scripted = Arc::new(ScriptedProcessRunner::new());
+    scripted
+        .expect("ffprobe", ScriptedResponse::success())
+        .expect("ffprobe", ScriptedResponse::success().stdout("10.0\n"))
+        .expect("ffprobe", ScriptedResponse::success())
+        .expect(
+            "ffmpeg",
+            ScriptedResponse::success()
+                .effect(|args| std::fs::write(last_arg(args), b"mkv").unwrap()),
+        )
+        .expect("ffprobe", ScriptedResponse::success().stdout("1280x720\n"))
+        .expect(
+            "ffmpeg",
+            ScriptedResponse::success().effect(write_packaging_outputs),
+        )
+        .expect(
+            "ffmpeg",
+            ScriptedResponse::success().effect(write_packaging_outputs),
+        );
+    let runner: DynProcessRunner = scripted.clone();
+
+    unsafe { std::env::set_var("VIDEO_MEZZANINE_CODEC", "h264") };
+    let result = process_video(&storage, &jobs, &runner, &id, &input, None).await;
+    unsafe { std::env::remove_var("VIDEO_MEZZANINE_CODEC") };
+    result?;
+
+    let encode = scripted
+        .calls()
+        .into_iter()
+        .find(|call| call.program == "ffmpeg")
+        .expect("encode call");
+    assert_eq!(video_codec(&encode.args), Some("libx264"));
+    assert!(encode.args.iter().any(|arg| arg == "matroska"));
+    assert_eq!(
+        metadata::load(&storage, &id).await?.mezzanine,
+        MezzanineCodec::H264
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn process_video_keeps_ten_bit_depth_and_colour_tags() -> Result<(), AppError> {
+    let _env = ENV_MUTEX.lock().await;
+    let temp = tempdir().expect("tempdir");
+    let storage = Storage::initialize(temp.path()).await?;
+    let jobs: DynJobStore = Arc::new(LocalJobStore::new());
+    let id = Uuid::new_v4();
+    jobs.create_job(id).await?;
+    jobs.update_stage(id, JobStage::Transcoding).await?;
+
+    let input = temp.path().join("input.mkv");
+    tokio::fs::write(&input, b"source").await?;
+
+    let scripted = Arc::new(ScriptedProcessRunner::new());
+    scripted
+        .expect("ffprobe", ScriptedResponse::success())
+        .expect("ffprobe", ScriptedResponse::success().stdout("10.0\n"))
+        .expect(
+            "ffprobe",
+            ScriptedResponse::success().stdout(
+                "pix_fmt=yuv420p10le\ncolor_range=tv\ncolor_space=bt2020nc\n\
+                 color_transfer=smpte2084\ncolor_primaries=bt2020\n",
+            ),
+        )
+        .expect(
+            "ffmpeg",
+            ScriptedResponse::success()
+                .effect(|args| std::fs::write(last_arg(args), b"webm").unwrap()),
+        )
+        .expect("ffprobe", ScriptedResponse::success().stdout("1280x720\n"))
+        .expect(
+            "ffmpeg",
+            ScriptedResponse::success().effect(write_packaging_outputs),
+        )
+        .expect(
+            "ffmpeg",
+            ScriptedResponse::success().effect(write_packaging_outputs),
+        );
+    let runner: DynProcessRunner = scripted.clone();
+
+    unsafe {
+        std::env::set_var("VIDEO_OUTPUT_BIT_DEPTH", "source");
+        std::env::set_var("VIDEO_SERVER_ENCODER", "software");
+    }
+    let result = process_video(&storage, &jobs, &runner, &id, &input, None).await;
+    unsafe {
+        std::env::remove_var("VIDEO_OUTPUT_BIT_DEPTH");
+        std::env::remove_var("VIDEO_SERVER_ENCODER");
+    }
+    result?;
+
+    let ffmpeg: Vec<_> = scripted
+        .calls()
+        .into_iter()
+        .filter(|call| call.program == "ffmpeg")
+        .collect();
+    assert_eq!(ffmpeg.len(), 3);
+    for call in &ffmpeg {
+        assert!(call.args.iter().any(|arg| arg == "yuv420p10le"));
+        let transfer = call.args.iter().position(|arg| arg == "-color_trc");
+        assert_eq!(
+            transfer.map(|index| call.args[index + 1].as_str()),
+            Some("smpte2084")
+        );
+    }
+    let color = metadata::load(&storage, &id).await?.color;
+    assert!(color.ten_bit);
+    assert_eq!(color.primaries.as_deref(), Some("bt2020"));
+    assert_eq!(color.matrix.as_deref(), Some("bt2020nc"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn process_video_packages_ladder_from_source_alongside_encode() -> Result<(), AppError> {
+    let _env = ENV_MUTEX.lock().await;
+    let temp = tempdir().expect("tempdir");
+    let storage = Storage::initialize(temp.path()).await?;
+    let jobs: DynJobStore = Arc::new(LocalJobStore::new());
+    let id = Uuid::new_v4();
+    jobs.create_job(id).await?;
+    jobs.update_stage(id, JobStage::Transcoding).await?;
+
+    let input = temp.path().join("input.mp4");
+    tokio::fs::write(&input, b"source").await?;
+
+    // The encode and both packaging runs start in any order.
+    let write_outputs = |args: &[OsString]| {
+        let target = last_arg(args);
+        if target.extension().is_some_and(|ext| ext == "webm") {
+            std::fs::write(target, b"webm").unwrap();
+        } else {
+            write_packaging_outputs(args);
+        }
+    };
+    let scripted = Arc::new(ScriptedProcessRunner::new());
+    scripted
+        .expect("ffprobe", ScriptedResponse::success())
+        .expect("ffprobe", ScriptedResponse::success().stdout("10.0\n"))
+        .expect("ffprobe", ScriptedResponse::success())
+        .expect("ffprobe", ScriptedResponse::success().stdout("1920x1080\n"));
+    for _ in 0..3 {
+        scripted.expect("ffmpeg", ScriptedResponse::success().effect(write_outputs));
+    }
+    let runner: DynProcessRunner = scripted.clone();
+
+    let encode = EncodeParams {
+        ladder_from_source: Some(true),
+        ..EncodeParams::default()
+    };
+    process_video(&storage, &jobs, &runner, &id, &input, Some(encode)).await?;
+
+    let input_arg = input.to_string_lossy().into_owned();
+    let ffmpeg: Vec<_> = scripted
+        .calls()
+        .into_iter()
+        .filter(|call| call.program == "ffmpeg")
+        .collect();
+    assert_eq!(ffmpeg.len(), 3);
+    for call in &ffmpeg {
+        let source = call.args.iter().position(|arg| arg == "-i").unwrap() + 1;
+        assert_eq!(call.args[source], input_arg);
+    }
+    assert_eq!(tokio::fs::read(storage.download_path(&id)).await?, b"webm");
+    assert!(storage.hls_dir(&id).join("master.m3u8").exists());
+    assert!(storage.dash_dir(&id).join("manifest.mpd").exists());
+    assert!(!input.exists());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn process_video_packages_audio_only_sources() -> Result<(), AppError> {
+    let _env = ENV_MUTEX.lock().await;
+    let temp = tempdir().expect("tempdir");
+    let storage = Storage::initialize(temp.path()).await?;
+    let jobs: DynJobStore = Arc::new(LocalJobStore::new());
+    let id = Uuid::new_v4();
+    jobs.create_job(id).await?;
+    jobs.update_stage(id, JobStage::Transcoding).await?;
+
+    let input = temp.path().join("episode.mp3");
+    tokio::fs::write(&input, b"ID3").await?;
+
+    let scripted = Arc::new(ScriptedProcessRunner::new());
+    scripted
+        .expect("ffprobe", ScriptedResponse::success().stdout("1\n"))
+        .expect("ffprobe", ScriptedResponse::success())
+        .expect("ffprobe", ScriptedResponse::success().stdout("10.0\n"))
+        .expect(
+            "ffmpeg",
+            ScriptedResponse::success()
+                .effect(|args| std::fs::write(last_arg(args), b"opus").unwrap()),
+        )
+        .expect(
+            "ffmpeg",
+            ScriptedResponse::success().effect(write_packaging_outputs),
+        )
+        .expect(
+            "ffmpeg",
+            ScriptedResponse::success().effect(write_packaging_outputs),
+        );
+    let runner: DynProcessRunner = scripted.clone();
+
+    process_video(&storage, &jobs, &runner, &id, &input, None).await?;
+
+    assert!(metadata::load(&storage, &id).await?.audio_only);
+    let ffmpeg: Vec<_> = scripted
+        .calls()
+        .into_iter()
+        .filter(|call| call.program == "ffmpeg")
+        .collect();
+    assert!(ffmpeg[0].args.iter().any(|arg| arg == "-vn"));
+    for call in &ffmpeg {
+        assert_eq!(video_codec(&call.args), None);
+    }
+    // HLS and DASH are packaged concurrently, in either order.
+    let packaged_with = |expected: &str| {
+        ffmpeg
+            .iter()
+            .filter(|call| call.args.iter().any(|arg| arg == expected))
+            .count()
+    };
+    assert_eq!(packaged_with("a:0,name:audio"), 1);
+    assert_eq!(packaged_with("id=0,streams=a"), 1);
+    assert!(storage.hls_dir(&id).join("master.m3u8").exists());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn process_video_reports_missing_tooling() -> Result<(), AppError> {
+    let _env = ENV_MUTEX.lock().await;
+    let temp = tempdir().expect("tempdir");
+    let storage = Storage::initialize(temp.path()).await?;
+    let jobs: DynJobStore = Arc::new(LocalJobStore::new());
+    let runner: DynProcessRunner = Arc::new(ScriptedProcessRunner::new());
+    let id = Uuid::new_v4();
+    let input = temp.path().join("input.mp4");
+    tokio::fs::write(&input, b"source").await?;
+
+    let result = process_video(&storage, &jobs, &runner, &id, &input, None).await;
+
+    assert!(matches!(result, Err(AppError::Dependency(_))));
+    Ok(())
+}
+
+#[tokio::test]
+async fn simulated_runner_produces_stub_outputs() -> Result<(), AppError> {
+    let _env = ENV_MUTEX.lock().await;
+    let temp = tempdir().expect("tempdir");
+    let storage = Storage::initialize(temp.path()).await?;
+    let jobs: DynJobStore = Arc::new(LocalJobStore::new());
+    let runner: DynProcessRunner = Arc::new(SimulatedMediaRunner::new(Duration::from_millis(500)));
+    let id = Uuid::new_v4();
+    jobs.create_job(id).await?;
+    jobs.update_stage(id, JobStage::Transcoding).await?;
+
+    let input = temp.path().join("input.mp4");
+    tokio::fs::write(&input, b"source").await?;
+
+    process_video(&storage, &jobs, &runner, &id, &input, None).await?;
+
+    assert!(storage.download_path(&id).exists());
+    let master = tokio::fs::read_to_string(storage.hls_dir(&id).join("master.m3u8")).await?;
+    assert!(master.contains("#EXT-X-STREAM-INF"));
+    assert!(storage.dash_dir(&id).join("manifest.mpd").exists());
+
+    let status = jobs.status(&id).await?.expect("job status");
+    assert_eq!(status.stage, JobStage::Finalizing);
+    assert!(status.progress > 0.0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn ensure_hls_ready_uses_stored_compat_profile() -> Result<(), AppError> {
+    let _env = ENV_MUTEX.lock().await;
+    let temp = tempdir().expect("tempdir");
+    let storage = Storage::initialize(temp.path()).await?;
+    let video_id = Uuid::new_v4();
+
+    let download = storage.download_path(&video_id);
+    storage::ensure_parent(&download).await?;
+    tokio::fs::write(&download, b"stub").await?;
+    metadata::save(
+        &storage,
+        &video_id,
+        &VideoMetadata {
+            profile: TranscodeProfile::Compat,
+            ..VideoMetadata::default()
+        },
+    )
+    .await?;
+
+    let scripted = Arc::new(ScriptedProcessRunner::new());
+    scripted
+        .expect("ffprobe", ScriptedResponse::success())
+        .expect("ffprobe", ScriptedResponse::success().stdout("1280x720\n"))
+        .expect(
+            "ffmpeg",
+            ScriptedResponse::success().effect(write_packaging_outputs),
+        );
+    let runner: DynProcessRunner = scripted.clone();
+
+    ensure_hls_ready(&storage, &runner, &video_id).await?;
+
+    let packaging = scripted
+        .calls()
+        .into_iter()
+        .find(|call| call.program == "ffmpeg")
+        .expect("ffmpeg call");
+    assert_eq!(video_codec(&packaging.args), Some("libx264"));
+    assert!(packaging.args.iter().any(|arg| arg == "mpegts"));
+    assert!(
+        !packaging
+            .args
+            .iter()
+            .any(|arg| arg == "-hls_fmp4_init_filename")
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn ladder_packaging_falls_back_to_software_decode() -> Result<(), AppError> {
+    let _env = ENV_MUTEX.lock().await;
+    let temp = tempdir().expect("tempdir");
+    let storage = Storage::initialize(temp.path()).await?;
+    let video_id = Uuid::new_v4();
+
+    let download = storage.download_path(&video_id);
+    storage::ensure_parent(&download).await?;
+    tokio::fs::write(&download, b"stub").await?;
+
+    let scripted = Arc::new(ScriptedProcessRunner::new());
+    scripted
+        .expect("ffprobe", ScriptedResponse::success())
+        .expect("ffprobe", ScriptedResponse::success().stdout("1280x720\n"))
+        .expect(
+            "ffmpeg",
+            ScriptedResponse::exit(1).stderr("Failed setup for format cuda\n"),
+        )
+        .expect(
+            "ffmpeg",
+            ScriptedResponse::success().effect(write_packaging_outputs),
+        );
+    let runner: DynProcessRunner = scripted.clone();
+
+    unsafe { std::env::set_var("VIDEO_HWACCEL_DECODE", "cuda") };
+    let result = ensure_hls_ready(&storage, &runner, &video_id).await;
+    unsafe { std::env::remove_var("VIDEO_HWACCEL_DECODE") };
+    result?;
+
+    let packaging: Vec<_> = scripted
+        .calls()
+        .into_iter()
+        .filter(|call| call.program == "ffmpeg")
+        .collect();
+    assert_eq!(packaging.len(), 2);
+    let hwaccel = |args: &[String]| {
+        args.iter()
+            .position(|arg| arg == "-hwaccel")
+            .map(|idx| args[idx + 1].clone())
+    };
+    assert_eq!(hwaccel(&packaging[0].args).as_deref(), Some("cuda"));
+    assert_eq!(hwaccel(&packaging[1].args), None);
+    assert!(storage.hls_dir(&video_id).join("master.m3u8").exists());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn profile_tuning_reaches_libaom_ladder() -> Result<(), AppError> {
+    let _env = ENV_MUTEX.lock().await;
+    let temp = tempdir().expect("tempdir");
+    let storage = Storage::initialize(temp.path()).await?;
+    let video_id = Uuid::new_v4();
+
+    let download = storage.download_path(&video_id);
+    storage::ensure_parent(&download).await?;
+    tokio::fs::write(&download, b"stub").await?;
+
+    let scripted = Arc::new(ScriptedProcessRunner::new());
+    scripted
+        .expect("ffprobe", ScriptedResponse::success())
+        .expect("ffprobe", ScriptedResponse::success().stdout("1280x720\n"))
+        .expect(
+            "ffmpeg",
+            ScriptedResponse::success().effect(write_packaging_outputs),
+        );
+    let runner: DynProcessRunner = scripted.clone();
+
+    let vars = [
+        ("VIDEO_PROFILE_STANDARD_FILM_GRAIN", "12"),
+        ("VIDEO_PROFILE_STANDARD_TUNE", "SSIM"),
+        ("VIDEO_PROFILE_STANDARD_ARNR_STRENGTH", "9"),
+        ("VIDEO_PROFILE_STANDARD_ARNR_MAX_FRAMES", "7"),
+    ];
+    for (key, value) in vars {
+        unsafe { std::env::set_var(key, value) };
+    }
+    let tuning = TranscodeProfile::Standard.av1_tuning();
+    let result = ensure_hls_ready(&storage, &runner, &video_id).await;
+    for (key, _) in vars {
+        unsafe { std::env::remove_var(key) };
+    }
+    result?;
+
+    assert_eq!(
+        tuning,
+        Av1Tuning {
+            film_grain: Some(12),
+            tune: Some(Av1Tune::Ssim),
+            arnr_strength: None,
+            arnr_max_frames: Some(7),
+        }
+    );
+    let packaging = scripted
+        .calls()
+        .into_iter()
+        .find(|call| call.program == "ffmpeg")
+        .expect("ffmpeg call");
+    let value = |flag: &str| {
+        packaging
+            .args
+            .iter()
+            .position(|arg| arg == flag)
+            .map(|idx| packaging.args[idx + 1].as_str())
+    };
+    assert_eq!(value("-denoise-noise-level"), Some("12"));
+    assert_eq!(value("-tune"), Some("ssim"));
+    assert_eq!(value("-arnr-strength"), None);
+    assert_eq!(value("-arnr-max-frames"), Some("7"));
+    assert_eq!(
+        TranscodeProfile::Standard.av1_tuning(),
+        Av1Tuning::default()
+    );
+
+    Ok(())
+}
+
+/// Writes a master playlist listing the variants of the pass's stream map.
+fn write_pass_master(args: &[OsString]) {
+    let map = args
+        .iter()
+        .position(|arg| arg == "-var_stream_map")
+        .map(|idx| args[idx + 1].to_string_lossy().into_owned())
+        .expect("var_stream_map");
+    let mut playlist = String::from("#EXTM3U\n#EXT-X-VERSION:7\n");
+    for name in map
+        .split(' ')
+        .filter_map(|entry| entry.split("name:").nth(1))
+    {
+        playlist.push_str(&format!(
+            "#EXT-X-STREAM-INF:BANDWIDTH=1\n{name}/index.m3u8\n"
+        ));
+    }
+    std::fs::write(last_arg(args).with_file_name("index.m3u8"), playlist).unwrap();
+}
+
+#[tokio::test]
+async fn ladder_encoders_split_packaging_into_passes() -> Result<(), AppError> {
+    let _env = ENV_MUTEX.lock().await;
+    let temp = tempdir().expect("tempdir");
+    let storage = Storage::initialize(temp.path()).await?;
+    let video_id = Uuid::new_v4();
+
+    let download = storage.download_path(&video_id);
+    storage::ensure_parent(&download).await?;
+    tokio::fs::write(&download, b"stub").await?;
+
+    let scripted = Arc::new(ScriptedProcessRunner::new());
+    scripted
+        .expect("ffprobe", ScriptedResponse::success())
+        .expect("ffprobe", ScriptedResponse::success().stdout("1920x1080\n"))
+        .expect(
+            "ffmpeg",
+            ScriptedResponse::success().effect(write_pass_master),
+        )
+        .expect(
+            "ffmpeg",
+            ScriptedResponse::success().effect(write_pass_master),
+        );
+    let runner: DynProcessRunner = scripted.clone();
+
+    unsafe { std::env::set_var("VIDEO_LADDER_ENCODERS", "1080=qsv") };
+    let result = ensure_hls_ready(&storage, &runner, &video_id).await;
+    unsafe { std::env::remove_var("VIDEO_LADDER_ENCODERS") };
+    result?;
+
+    let packaging: Vec<_> = scripted
+        .calls()
+        .into_iter()
+        .filter(|call| call.program == "ffmpeg")
+        .collect();
+    assert_eq!(packaging.len(), 2);
+    assert_eq!(video_codec(&packaging[0].args), Some("av1_qsv"));
+    assert_eq!(video_codec(&packaging[1].args), Some("libaom-av1"));
+
+    let hls_dir = storage.hls_dir(&video_id);
+    let master = tokio::fs::read_to_string(hls_dir.join("master.m3u8")).await?;
+    let variants: Vec<&str> = master
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect();
+    assert_eq!(
+        variants,
+        [
+            "1080p/index.m3u8",
+            "900p/index.m3u8",
+            "720p/index.m3u8",
+            "540p/index.m3u8",
+            "480p/index.m3u8",
+        ]
+    );
+    assert!(!hls_dir.join("pass0").exists());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn optional_stages_run_as_child_jobs() -> Result<(), AppError> {
+    let _env = ENV_MUTEX.lock().await;
+    let temp = tempdir().expect("tempdir");
+    let storage = Storage::initialize(temp.path()).await?;
+    let jobs: DynJobStore = Arc::new(LocalJobStore::new());
+    let id = Uuid::new_v4();
+    jobs.create_job(id).await?;
+    for name in ["thumbnails", "captions", "vmaf"] {
+        jobs.add_child(id, Uuid::new_v4(), name).await?;
+    }
+
+    let download = storage.download_path(&id);
+    storage::ensure_parent(&download).await?;
+    tokio::fs::write(&download, b"webm").await?;
+    let hls_dir = storage.hls_dir(&id);
+    storage::ensure_dir(&hls_dir).await?;
+    tokio::fs::write(
+        hls_dir.join("index.m3u8"),
+        "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=800000,RESOLUTION=640x360\nstream_360p.m3u8\n\
+         #EXT-X-STREAM-INF:BANDWIDTH=4000000,RESOLUTION=1920x1080\nstream_1080p.m3u8\n",
+    )
+    .await?;
+
+    let scripted = Arc::new(ScriptedProcessRunner::new());
+    scripted
+        .expect("ffprobe", ScriptedResponse::success().stdout("20.0\n"))
+        .expect(
+            "ffmpeg",
+            ScriptedResponse::success()
+                .effect(|args| std::fs::write(last_arg(args), b"jpg").unwrap()),
+        )
+        .expect("ffprobe", ScriptedResponse::success().stdout("20.0\n"))
+        .expect("ffprobe", ScriptedResponse::success())
+        .expect("ffprobe", ScriptedResponse::success().stdout("20.0\n"))
+        .expect(
+            "ffmpeg",
+            ScriptedResponse::success().stderr("[libvmaf @ 0x1] VMAF score: 91.500000\n"),
+        );
+    let runner: DynProcessRunner = scripted.clone();
+
+    run_optional_stages(&storage, &jobs, &runner, &id).await?;
+
+    assert!(storage.thumbnail_path(&id).exists());
+    assert!(!storage.captions_path(&id).exists());
+    let ffmpeg: Vec<_> = scripted
+        .calls()
+        .into_iter()
+        .filter(|call| call.program == "ffmpeg")
+        .collect();
+    assert!(
+        ffmpeg[0]
+            .args
+            .windows(2)
+            .any(|pair| pair == ["-ss", "2.000"])
+    );
+    assert!(
+        ffmpeg[1]
+            .args
+            .iter()
+            .any(|arg| arg.ends_with("stream_1080p.m3u8"))
+    );
+
+    let meta = metadata::load(&storage, &id).await?;
+    assert_eq!(meta.vmaf_score, Some(91.5));
+
+    let group = jobs.group_status(&id).await?.expect("group");
+    assert!(
+        group.members[1..]
+            .iter()
+            .all(|member| member.status.stage == JobStage::Complete)
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn process_video_renders_preview_on_request() -> Result<(), AppError> {
+    let _env = ENV_MUTEX.lock().await;
+    let temp = tempdir().expect("tempdir");
+    let storage = Storage::initialize(temp.path()).await?;
+    let jobs: DynJobStore = Arc::new(LocalJobStore::new());
+    let runner: DynProcessRunner = Arc::new(SimulatedMediaRunner::new(Duration::from_millis(50)));
+
+    for preview in [false, true] {
+        let id = Uuid::new_v4();
+        jobs.create_job(id).await?;
+        let input = temp.path().join(format!("{id}.mp4"));
+        tokio::fs::write(&input, b"source").await?;
+        let encode = EncodeParams {
+            preview,
+            ..EncodeParams::default()
+        };
+
+        process_video(&storage, &jobs, &runner, &id, &input, Some(encode)).await?;
+
+        assert_eq!(storage.preview_path(&id).exists(), preview);
+        assert!(
+            !storage
+                .preview_path(&id)
+                .with_extension("webp.part")
+                .exists()
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn storyboard_layout_maps_cues_to_tiles() {
+    let short = StoryboardLayout::new(Some(Duration::from_secs(25)), 10.0, 10, (160, 90));
+    assert_eq!((short.tiles, short.columns, short.rows()), (3, 3, 1));
+    assert!(short.filter().ends_with("tile=3x1"));
+    let vtt = short.vtt(Some(Duration::from_secs(25)));
+    assert!(vtt.starts_with("WEBVTT\n"));
+    assert!(vtt.contains("00:00:00.000 --> 00:00:10.000\nsprites.jpg#xywh=0,0,160,90\n"));
+    assert!(vtt.contains("00:00:20.000 --> 00:00:25.000\nsprites.jpg#xywh=320,0,160,90\n"));
+
+    let long = StoryboardLayout::new(Some(Duration::from_secs(2000)), 10.0, 10, (160, 90));
+    assert_eq!((long.interval, long.tiles, long.rows()), (20.0, 100, 10));
+    let vtt = long.vtt(Some(Duration::from_secs(2000)));
+    assert!(vtt.contains("00:33:00.000 --> 00:33:20.000\nsprites.jpg#xywh=1440,810,160,90\n"));
+
+    let signed = append_query_to_storyboard(&vtt, "token=1.abc");
+    assert!(signed.contains("\nsprites.jpg?token=1.abc#xywh=0,0,160,90\n"));
+    assert!(signed.contains("00:00:00.000 --> 00:00:20.000\n"));
+}
+
+#[tokio::test]
+async fn sprites_stage_writes_storyboard() -> Result<(), AppError> {
+    let _env = ENV_MUTEX.lock().await;
+    let temp = tempdir().expect("tempdir");
+    let storage = Storage::initialize(temp.path()).await?;
+    let jobs: DynJobStore = Arc::new(LocalJobStore::new());
+    let id = Uuid::new_v4();
+    jobs.create_job(id).await?;
+    jobs.add_child(id, Uuid::new_v4(), "sprites").await?;
+    let download = storage.download_path(&id);
+    storage::ensure_parent(&download).await?;
+    tokio::fs::write(&download, b"webm").await?;
+
+    let scripted = Arc::new(ScriptedProcessRunner::new());
+    scripted
+        .expect("ffprobe", ScriptedResponse::success().stdout("35.0\n"))
+        .expect(
+            "ffmpeg",
+            ScriptedResponse::success()
+                .effect(|args| std::fs::write(last_arg(args), b"jpg").unwrap()),
+        );
+    let runner: DynProcessRunner = scripted.clone();
+
+    run_optional_stages(&storage, &jobs, &runner, &id).await?;
+
+    assert!(storage.sprite_path(&id).exists());
+    let vtt = tokio::fs::read_to_string(storage.storyboard_path(&id)).await?;
+    assert_eq!(vtt.matches(" --> ").count(), 4);
+    assert!(vtt.contains("00:00:30.000 --> 00:00:35.000\nsprites.jpg#xywh=480,0,160,90"));
+    let ffmpeg = &scripted.calls()[1];
+    assert!(ffmpeg.args.iter().any(|arg| arg.ends_with("tile=4x1")));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn render_stills_turns_zip_into_slideshow() -> Result<(), AppError> {
+    use std::io::Write;
+    use std::sync::Mutex;
+
+    let _env = ENV_MUTEX.lock().await;
+    let temp = tempdir().expect("tempdir");
+    let storage = Storage::initialize(temp.path()).await?;
+    let jobs: DynJobStore = Arc::new(LocalJobStore::new());
+    let id = Uuid::new_v4();
+    jobs.create_job(id).await?;
+
+    let input = storage.incoming_path(&id);
+    storage::ensure_parent(&input).await?;
+    {
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&input)?);
+        let options = zip::write::SimpleFileOptions::default();
+        for (name, bytes) in [
+            ("b.png", &b"\x89PNG\r\n\x1a\n"[..]),
+            ("a.jpg", &b"\xff\xd8\xff\xe0"[..]),
+            ("__MACOSX/a.jpg", &b"resource fork"[..]),
+            ("music/theme.mp3", &b"ID3"[..]),
+        ] {
+            zip.start_file(name, options).expect("zip entry");
+            zip.write_all(bytes)?;
+        }
+        zip.finish().expect("zip finish");
+    }
+
+    let list = Arc::new(Mutex::new(String::new()));
+    let captured = list.clone();
+    let scripted = Arc::new(ScriptedProcessRunner::new());
+    scripted.expect(
+        "ffmpeg",
+        ScriptedResponse::success().effect(move |args| {
+            let args: Vec<String> = args
+                .iter()
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect();
+            let concat = args
+                .iter()
+                .position(|arg| arg == "-i")
+                .map(|idx| &args[idx + 1]);
+            *captured.lock().unwrap() = std::fs::read_to_string(concat.unwrap()).unwrap();
+            std::fs::write(args.last().unwrap(), b"slideshow").unwrap();
+        }),
+    );
+    let runner: DynProcessRunner = scripted.clone();
+
+    let mut params = EncodeParams::default();
+    params.slideshow.image_seconds = 2.5;
+    let rendered = render_stills(&storage, &jobs, &runner, &id, &input, Some(&params)).await?;
+
+    assert!(rendered);
+    assert_eq!(tokio::fs::read(&input).await?, b"slideshow");
+    let list = list.lock().unwrap().clone();
+    let files: Vec<&str> = list
+        .lines()
+        .filter(|line| line.starts_with("file"))
+        .collect();
+    assert_eq!(files.len(), 3);
+    assert!(files[0].ends_with(".jpg'") && files[1].ends_with(".png'"));
+    assert_eq!(list.matches("duration 2.500").count(), 2);
+
+    let args = &scripted.calls()[0].args;
+    assert!(args.iter().any(|arg| arg.ends_with("soundtrack.mp3")));
+    assert!(args.windows(2).any(|pair| pair == ["-t", "5.000"]));
+
+    let plain = temp.path().join("clip.mp4");
+    tokio::fs::write(&plain, b"\0\0\0\x18ftypmp42").await?;
+    assert!(!render_stills(&storage, &jobs, &runner, &id, &plain, None).await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn probe_media_info_skips_cover_art_and_reads_audio_layout() -> Result<(), AppError> {
+    let scripted = Arc::new(ScriptedProcessRunner::new());
+    scripted.expect(
+        "ffprobe",
+        ScriptedResponse::success().stdout(
+            r#"{
+                "streams": [
+                    {"codec_type": "video", "codec_name": "mjpeg", "width": 600, "height": 600,
+                     "disposition": {"attached_pic": 1}},
+                    {"codec_type": "video", "codec_name": "av1", "profile": "Main",
+                     "width": 1280, "height": 720, "r_frame_rate": "60000/2002",
+                     "pix_fmt": "yuv420p10le"},
+                    {"codec_type": "audio", "codec_name": "opus", "channels": 6,
+                     "channel_layout": "5.1(side)", "sample_rate": "48000",
+                     "tags": {"language": "eng"}}
+                ],
+                "format": {"format_name": "matroska,webm", "duration": "12.500000",
+                           "bit_rate": "1843200"}
+            }"#,
+        ),
+    );
+    let runner: DynProcessRunner = scripted.clone();
+
+    let info = probe_media_info(&runner, std::path::Path::new("download.webm")).await?;
+
+    assert_eq!(info.format, "matroska,webm");
+    assert_eq!(info.duration_seconds, Some(12.5));
+    assert_eq!(info.bit_rate, Some(1_843_200));
+    let video = info.video.expect("video stream");
+    assert_eq!(video.codec, "av1");
+    assert_eq!((video.width, video.height), (1280, 720));
+    assert_eq!(video.frame_rate.as_deref(), Some("30000/1001"));
+    assert_eq!(info.audio.len(), 1);
+    assert_eq!(info.audio[0].channel_layout.as_deref(), Some("5.1(side)"));
+    assert_eq!(info.audio[0].sample_rate, Some(48_000));
+    assert_eq!(info.audio[0].language.as_deref(), Some("eng"));
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use tempfile::tempdir;
+use vrs::{
+    storage::Storage,
+    usage::{QuotaConfig, QuotaLimit, UsageLedger},
+};
+
+#[test]
+fn quota_limits_parse_defaults_and_overrides() {
+    let limit = QuotaLimit::<u64>::parse("acme=2000, *=500, broken=x");
+    assert_eq!(limit.for_key("acme"), Some(2000));
+    assert_eq!(limit.for_key("other"), Some(500));
+    assert_eq!(limit.for_key("broken"), Some(500));
+
+    let limit = QuotaLimit::<f64>::parse("90.5");
+    assert_eq!(limit.for_key("anyone"), Some(90.5));
+    assert_eq!(QuotaLimit::<u64>::parse("acme=10").for_key("other"), None);
+}
+
+#[tokio::test]
+async fn ledger_adds_up_charges_and_admits_until_the_quota() {
+    let temp = tempdir().unwrap();
+    let storage = Storage::initialize(temp.path()).await.unwrap();
+    let ledger = UsageLedger::new(storage).with_quotas(QuotaConfig {
+        monthly_encode_minutes: QuotaLimit::parse("acme=3"),
+        ..QuotaConfig::default()
+    });
+
+    ledger.record("acme", 100, 0.0).await.unwrap();
+    ledger.record("acme", 50, 2.5).await.unwrap();
+    ledger.record("other", 7, 10.0).await.unwrap();
+    ledger.admit("acme").await.unwrap();
+    ledger.admit("other").await.unwrap();
+
+    let report = ledger.report("acme").await.unwrap();
+    assert_eq!(report.daily.ingested_bytes, 150);
+    assert_eq!(report.monthly.encode_minutes, 2.5);
+    assert_eq!(report.month, report.day[..7]);
+    assert_eq!(report.quotas.monthly_encode_minutes, Some(3.0));
+
+    ledger.record("acme", 0, 0.5).await.unwrap();
+    let err = ledger.admit("acme").await.unwrap_err();
+    assert_eq!(err.code(), "quota_exceeded");
+}
@@ -0,0 +1,45 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use uuid::Uuid;
+use vrs::video_access::{AccessSigner, forwarded_query};
+
+#[test]
+fn access_tokens_are_bound_to_video_and_password() {
+    let signer = AccessSigner::new("secret");
+    let video_id = Uuid::new_v4();
+    let (token, _) = signer.issue_for(&video_id, "hash-a", Duration::from_secs(60));
+
+    signer.verify(&video_id, "hash-a", &token).unwrap();
+    assert!(signer.verify(&Uuid::new_v4(), "hash-a", &token).is_err());
+    // A replaced password revokes the tokens issued for the old one.
+    assert!(signer.verify(&video_id, "hash-b", &token).is_err());
+    assert!(
+        AccessSigner::new("other")
+            .verify(&video_id, "hash-a", &token)
+            .is_err()
+    );
+    assert!(signer.verify(&video_id, "hash-a", "garbage").is_err());
+}
+
+#[test]
+fn expired_access_tokens_are_refused() {
+    let signer = AccessSigner::new("secret");
+    let video_id = Uuid::new_v4();
+    let past = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        - 10;
+
+    let expired = signer.sign(&video_id, "hash", past);
+    assert!(signer.verify(&video_id, "hash", &expired).is_err());
+}
+
+#[test]
+fn forwarded_query_carries_tokens_only() {
+    assert_eq!(forwarded_query(&None, Some("t"), None), None);
+    assert_eq!(
+        forwarded_query(&None, Some("t"), Some("a.b")),
+        Some("access=a.b".to_string())
+    );
+}